@@ -0,0 +1,428 @@
+//! Boot Counter Component
+//!
+//! Records each boot attempt in the [`BootCounters`], resets the consecutive failure count when ReadyToBoot is
+//! signaled, and applies the configured [`FallbackAction`]s once the consecutive failure count reaches the configured
+//! threshold.
+//!
+//! The counters are stored in a UEFI variable, which can only be written once the variable driver installs the
+//! Variable Write Architectural Protocol. The component is usually dispatched before then, so it waits for the protocol
+//! to be installed before it records the boot attempt.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this component using the `boot_counter` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{cell::Cell, ffi::c_void};
+
+use patina::{
+    boot_services::{BootServices, StandardBootServices, event::EventType, tpl::Tpl},
+    component::{IntoComponent, params::Config},
+    error::EfiError,
+    runtime_services::{RuntimeServices, StandardRuntimeServices},
+    uefi_protocol::fv_loader::FvLoaderProtocol,
+};
+use r_efi::{efi, system::EVENT_GROUP_READY_TO_BOOT};
+
+use crate::{
+    config::{BootCounterConfig, FallbackAction},
+    counter::BootCounters,
+};
+
+/// The event group signaled when the fallback actions are applied.
+///
+/// Components that own settings which may prevent a successful boot can create an event in this group to restore their
+/// defaults. [`BootCounters::read`] returns the counters, including the current boot attempt, when it is signaled.
+pub const BOOT_FALLBACK_EVENT_GROUP: efi::Guid =
+    efi::Guid::from_fields(0x3b9e6a41, 0x1f27, 0x4c5d, 0x8e, 0x02, &[0x6d, 0xa1, 0x94, 0x57, 0xc3, 0x2e]);
+
+/// The PI Variable Write Architectural Protocol, installed once UEFI variables can be written.
+const VARIABLE_WRITE_ARCH_PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0x6441f818, 0x6362, 0x4e44, 0xb5, 0x70, &[0x7d, 0xba, 0x31, 0xdd, 0x24, 0x53]);
+
+/// A component that counts boot attempts and applies the fallback actions after repeated boot failures.
+///
+/// A failure threshold of zero disables the fallback actions, but boot attempts are still counted.
+#[derive(Debug, Default, IntoComponent)]
+pub struct BootCounter;
+
+/// The state of the boot attempt, held until the variable services are available.
+struct BootAttempt<BB, RR> {
+    config: BootCounterConfig,
+    boot_services: BB,
+    runtime_services: RR,
+    recorded: Cell<bool>,
+}
+
+impl BootCounter {
+    /// Entry point of [`BootCounter`]
+    #[coverage(off)] // This is tested via the generic version, see _entry_point.
+    fn entry_point(
+        self,
+        config: Config<BootCounterConfig>,
+        boot_services: StandardBootServices,
+        runtime_services: StandardRuntimeServices,
+    ) -> patina::error::Result<()> {
+        self._entry_point(&config, boot_services, runtime_services)
+    }
+
+    /// Entry point that has generic parameters.
+    fn _entry_point<BB, B, RR, R>(
+        self,
+        config: &BootCounterConfig,
+        boot_services: BB,
+        runtime_services: RR,
+    ) -> patina::error::Result<()>
+    where
+        BB: AsRef<B> + Clone + 'static,
+        B: BootServices + 'static,
+        RR: AsRef<R> + Clone + 'static,
+        R: RuntimeServices + 'static,
+    {
+        let attempt: &'static BootAttempt<BB, RR> = Box::leak(Box::new(BootAttempt {
+            config: config.clone(),
+            boot_services: BB::clone(&boot_services),
+            runtime_services,
+            recorded: Cell::new(false),
+        }));
+
+        let event = boot_services.as_ref().create_event(
+            EventType::NOTIFY_SIGNAL,
+            Tpl::CALLBACK,
+            Some(record_boot_attempt::<BB, B, RR, R>),
+            attempt,
+        )?;
+        boot_services.as_ref().register_protocol_notify(&VARIABLE_WRITE_ARCH_PROTOCOL_GUID, event)?;
+        // The protocol may already be installed, in which case the notify function records the attempt immediately.
+        boot_services.as_ref().signal_event(event)?;
+        Ok(())
+    }
+}
+
+/// Records the boot attempt, and applies the fallback actions, once UEFI variables can be written.
+extern "efiapi" fn record_boot_attempt<BB, B, RR, R>(event: efi::Event, attempt: &'static BootAttempt<BB, RR>)
+where
+    BB: AsRef<B>,
+    B: BootServices + 'static,
+    RR: AsRef<R> + Clone + 'static,
+    R: RuntimeServices + 'static,
+{
+    let boot_services = attempt.boot_services.as_ref();
+    if attempt.recorded.get() || boot_services.locate_protocol_marker(&VARIABLE_WRITE_ARCH_PROTOCOL_GUID, None).is_err()
+    {
+        return;
+    }
+    attempt.recorded.set(true);
+    let _ = boot_services.close_event(event);
+
+    let runtime_services = attempt.runtime_services.as_ref();
+    let counters = match BootCounters::record_attempt(runtime_services) {
+        Ok(counters) => counters,
+        Err(err) => {
+            log::error!(target: "boot_counter", "Failed to record the boot attempt: {err:?}");
+            return;
+        }
+    };
+    log::info!(
+        target: "boot_counter",
+        "Boot attempt {} after {} consecutive failed boots.",
+        counters.boot_attempts.saturating_add(1),
+        counters.consecutive_failures
+    );
+
+    let runtime_services_ref: &'static RR = Box::leak(Box::new(RR::clone(&attempt.runtime_services)));
+    if let Err(status) = boot_services.create_event_ex(
+        EventType::NOTIFY_SIGNAL,
+        Tpl::CALLBACK,
+        Some(record_boot_success::<RR, R>),
+        runtime_services_ref,
+        &EVENT_GROUP_READY_TO_BOOT,
+    ) {
+        log::error!(target: "boot_counter", "Failed to register for ReadyToBoot: {status:?}");
+    }
+
+    let config = &attempt.config;
+    if config.failure_threshold == 0 || counters.consecutive_failures < config.failure_threshold {
+        return;
+    }
+
+    log::warn!(
+        target: "boot_counter",
+        "{} consecutive failed boots reached the threshold of {}, applying fallback actions.",
+        counters.consecutive_failures,
+        config.failure_threshold
+    );
+    for action in &config.actions {
+        apply_action(action, boot_services, runtime_services);
+    }
+    signal_fallback_event_group(boot_services);
+}
+
+/// Signals [`BOOT_FALLBACK_EVENT_GROUP`].
+fn signal_fallback_event_group(boot_services: &impl BootServices) {
+    extern "efiapi" fn noop(_event: efi::Event, _context: *mut c_void) {}
+
+    match boot_services.create_event_ex(
+        EventType::NOTIFY_SIGNAL,
+        Tpl::CALLBACK,
+        Some(noop),
+        core::ptr::null_mut(),
+        &BOOT_FALLBACK_EVENT_GROUP,
+    ) {
+        Ok(event) => {
+            let _ = boot_services.signal_event(event);
+            let _ = boot_services.close_event(event);
+        }
+        Err(status) => {
+            log::error!(target: "boot_counter", "Failed to signal the boot fallback event group: {status:?}")
+        }
+    }
+}
+
+/// Applies a fallback action. Failures are logged so that the remaining actions are still applied.
+fn apply_action(action: &FallbackAction, boot_services: &impl BootServices, runtime_services: &impl RuntimeServices) {
+    match action {
+        FallbackAction::VerboseLogging => {
+            log::set_max_level(log::LevelFilter::Trace);
+            log::info!(target: "boot_counter", "Enabled verbose logging.");
+        }
+        FallbackAction::RecoveryFv { base_address, size } => {
+            // SAFETY: The protocol interface is produced by the core and is valid for the remainder of boot.
+            let Ok(fv_loader) = (unsafe { boot_services.locate_protocol::<FvLoaderProtocol>(None) }) else {
+                log::error!(target: "boot_counter", "Firmware volume loader protocol not found, cannot boot recovery FV.");
+                return;
+            };
+            let mut handle: efi::Handle = core::ptr::null_mut();
+            let status = (fv_loader.install_fv_from_buffer)(
+                fv_loader as *mut FvLoaderProtocol,
+                *base_address as usize as *const c_void,
+                *size,
+                &mut handle,
+            );
+            match EfiError::status_to_result(status) {
+                Ok(()) => log::info!(target: "boot_counter", "Installed recovery FV at {base_address:#x}."),
+                Err(err) => {
+                    log::error!(target: "boot_counter", "Failed to install recovery FV at {base_address:#x}: {err:?}")
+                }
+            }
+        }
+        FallbackAction::ClearVariables(variables) => {
+            for (name, namespace) in variables {
+                let name: Vec<u16> = name.encode_utf16().chain(core::iter::once(0)).collect();
+                match runtime_services.set_variable(&name, namespace, 0, &[0u8; 0]) {
+                    Ok(()) | Err(efi::Status::NOT_FOUND) => {}
+                    Err(status) => log::error!(
+                        target: "boot_counter",
+                        "Failed to clear variable {}: {status:?}",
+                        alloc::string::String::from_utf16_lossy(&name[..name.len() - 1])
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Resets the consecutive failure count once the boot reaches ReadyToBoot.
+///
+/// ReadyToBoot is signaled again for each boot option that is attempted, so the context is never freed.
+extern "efiapi" fn record_boot_success<RR, R>(_event: efi::Event, runtime_services: &'static RR)
+where
+    RR: AsRef<R>,
+    R: RuntimeServices,
+{
+    if let Err(err) = BootCounters::record_success(runtime_services.as_ref()) {
+        log::error!(target: "boot_counter", "Failed to reset the consecutive boot failure count: {err:?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use patina::{boot_services::MockBootServices, runtime_services::MockRuntimeServices};
+    use std::rc::Rc;
+
+    const TEST_GUID: efi::Guid = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+    const TEST_EVENT: efi::Event = 1_usize as efi::Event;
+    const FALLBACK_EVENT: efi::Event = 3_usize as efi::Event;
+
+    type TestAttempt = BootAttempt<Rc<MockBootServices>, Rc<MockRuntimeServices>>;
+
+    fn runtime_services_with(counters: BootCounters) -> MockRuntimeServices {
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_get_variable::<Vec<u8>>().once().returning(move |_, _, _| {
+            let bytes = [counters.boot_attempts.to_le_bytes(), counters.consecutive_failures.to_le_bytes()];
+            Ok((bytes.concat(), 0))
+        });
+        runtime_services.expect_set_variable::<[u8; 8]>().once().returning(|_, _, _, _| Ok(()));
+        runtime_services
+    }
+
+    fn boot_services_with_variable_write() -> MockBootServices {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_locate_protocol_marker()
+            .withf(|protocol, _| protocol == &VARIABLE_WRITE_ARCH_PROTOCOL_GUID)
+            .returning(|_, _| Ok(()));
+        boot_services.expect_close_event().once().withf(|event| *event == TEST_EVENT).returning(|_| Ok(()));
+        boot_services
+            .expect_create_event_ex::<&'static Rc<MockRuntimeServices>>()
+            .once()
+            .withf_st(|event_type, notify_tpl, notify_function, _, event_group| {
+                assert_eq!(&EventType::NOTIFY_SIGNAL, event_type);
+                assert_eq!(&Tpl::CALLBACK, notify_tpl);
+                assert_eq!(
+                    record_boot_success::<Rc<MockRuntimeServices>, MockRuntimeServices> as usize,
+                    notify_function.unwrap() as usize
+                );
+                assert_eq!(&EVENT_GROUP_READY_TO_BOOT, event_group);
+                true
+            })
+            .return_const_st(Ok(2_usize as efi::Event));
+        boot_services
+    }
+
+    fn attempt(
+        config: BootCounterConfig,
+        boot_services: MockBootServices,
+        runtime_services: MockRuntimeServices,
+    ) -> &'static TestAttempt {
+        Box::leak(Box::new(BootAttempt {
+            config,
+            boot_services: Rc::new(boot_services),
+            runtime_services: Rc::new(runtime_services),
+            recorded: Cell::new(false),
+        }))
+    }
+
+    #[test]
+    fn test_entry_point_waits_for_variable_write() {
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_get_variable::<Vec<u8>>().never();
+
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event::<&'static TestAttempt>()
+            .once()
+            .withf_st(|event_type, notify_tpl, notify_function, _| {
+                assert_eq!(&EventType::NOTIFY_SIGNAL, event_type);
+                assert_eq!(&Tpl::CALLBACK, notify_tpl);
+                assert_eq!(
+                    record_boot_attempt::<_, MockBootServices, _, MockRuntimeServices>
+                        as extern "efiapi" fn(_, &'static TestAttempt) as usize,
+                    notify_function.unwrap() as usize
+                );
+                true
+            })
+            .return_const_st(Ok(TEST_EVENT));
+        boot_services
+            .expect_register_protocol_notify()
+            .once()
+            .withf(|protocol, event| protocol == &VARIABLE_WRITE_ARCH_PROTOCOL_GUID && *event == TEST_EVENT)
+            .returning(|_, _| Ok(core::ptr::NonNull::dangling()));
+        boot_services.expect_signal_event().once().withf(|event| *event == TEST_EVENT).returning(|_| Ok(()));
+
+        assert!(
+            BootCounter
+                ._entry_point(&BootCounterConfig::default(), Rc::new(boot_services), Rc::new(runtime_services))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_attempt_is_not_recorded_before_variable_write() {
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_get_variable::<Vec<u8>>().never();
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_locate_protocol_marker().once().returning(|_, _| Err(efi::Status::NOT_FOUND));
+        boot_services.expect_close_event().never();
+
+        let attempt = attempt(BootCounterConfig::default(), boot_services, runtime_services);
+        record_boot_attempt::<_, MockBootServices, _, MockRuntimeServices>(TEST_EVENT, attempt);
+        assert!(!attempt.recorded.get());
+    }
+
+    #[test]
+    fn test_no_fallback_below_threshold() {
+        let runtime_services = runtime_services_with(BootCounters { boot_attempts: 10, consecutive_failures: 2 });
+        let mut boot_services = boot_services_with_variable_write();
+        boot_services.expect_locate_protocol::<FvLoaderProtocol>().never();
+        boot_services.expect_create_event_ex::<*mut c_void>().never();
+
+        let config = BootCounterConfig::default()
+            .with_threshold(3)
+            .with_action(FallbackAction::RecoveryFv { base_address: 0x1000, size: 0x1000 });
+        let attempt = attempt(config, boot_services, runtime_services);
+        record_boot_attempt::<_, MockBootServices, _, MockRuntimeServices>(TEST_EVENT, attempt);
+        assert!(attempt.recorded.get());
+
+        // the attempt is only recorded once, even if the protocol is installed again.
+        record_boot_attempt::<_, MockBootServices, _, MockRuntimeServices>(TEST_EVENT, attempt);
+    }
+
+    #[test]
+    fn test_fallback_actions_are_applied_at_threshold() {
+        static INSTALLED: AtomicUsize = AtomicUsize::new(0);
+        extern "efiapi" fn install_fv_from_buffer(
+            _: *mut FvLoaderProtocol,
+            buffer: *const c_void,
+            size: usize,
+            _: *mut efi::Handle,
+        ) -> efi::Status {
+            assert_eq!((buffer as usize, size), (0x8000, 0x2000));
+            INSTALLED.fetch_add(1, Ordering::SeqCst);
+            efi::Status::SUCCESS
+        }
+        let fv_loader: &'static mut FvLoaderProtocol =
+            Box::leak(Box::new(FvLoaderProtocol::new(install_fv_from_buffer)));
+        let fv_loader_ptr = fv_loader as *mut FvLoaderProtocol;
+
+        let mut runtime_services = runtime_services_with(BootCounters { boot_attempts: 10, consecutive_failures: 3 });
+        runtime_services
+            .expect_set_variable::<[u8; 0]>()
+            .times(2)
+            .withf(|_, namespace, attributes, data| namespace == &TEST_GUID && *attributes == 0 && data.is_empty())
+            .returning(|name, _, _, _| if name[0] == 'A' as u16 { Ok(()) } else { Err(efi::Status::NOT_FOUND) });
+
+        let mut boot_services = boot_services_with_variable_write();
+        boot_services
+            .expect_locate_protocol::<FvLoaderProtocol>()
+            .once()
+            .returning_st(move |_| Ok(unsafe { &mut *fv_loader_ptr }));
+        boot_services
+            .expect_create_event_ex::<*mut c_void>()
+            .once()
+            .withf_st(|_, _, _, _, event_group| event_group == &BOOT_FALLBACK_EVENT_GROUP)
+            .return_const_st(Ok(FALLBACK_EVENT));
+        boot_services.expect_signal_event().once().withf(|event| *event == FALLBACK_EVENT).returning(|_| Ok(()));
+        boot_services.expect_close_event().once().withf(|event| *event == FALLBACK_EVENT).returning(|_| Ok(()));
+
+        let config = BootCounterConfig::default()
+            .with_threshold(3)
+            .with_action(FallbackAction::RecoveryFv { base_address: 0x8000, size: 0x2000 })
+            .with_action(FallbackAction::ClearVariables(vec![("A", TEST_GUID), ("B", TEST_GUID)]));
+        record_boot_attempt::<_, MockBootServices, _, MockRuntimeServices>(
+            TEST_EVENT,
+            attempt(config, boot_services, runtime_services),
+        );
+        assert_eq!(INSTALLED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_ready_to_boot_resets_consecutive_failures() {
+        let runtime_services = runtime_services_with(BootCounters { boot_attempts: 5, consecutive_failures: 1 });
+        record_boot_success::<Rc<MockRuntimeServices>, MockRuntimeServices>(
+            TEST_EVENT,
+            Box::leak(Box::new(Rc::new(runtime_services))),
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! Boot Counter Configuration
+//!
+//! Defines the [`BootCounterConfig`] used to configure the [`BootCounter`](crate::component::BootCounter) component.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+
+use r_efi::efi;
+
+/// The default number of consecutive failed boots that activates the fallback actions.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// An action applied when the number of consecutive failed boots reaches the configured threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackAction {
+    /// Raises the maximum log level to `Trace` so that the failing boot can be diagnosed.
+    VerboseLogging,
+    /// Installs, and dispatches the drivers in, a recovery firmware volume that is memory mapped at `base_address`.
+    RecoveryFv {
+        /// The address of the recovery firmware volume.
+        base_address: u64,
+        /// The size of the recovery firmware volume in bytes.
+        size: usize,
+    },
+    /// Deletes the UEFI variables holding settings that may be preventing a successful boot.
+    ClearVariables(Vec<(&'static str, efi::Guid)>),
+}
+
+/// The configuration of the [`BootCounter`](crate::component::BootCounter) component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootCounterConfig {
+    /// The number of consecutive failed boots that activates the fallback actions.
+    pub failure_threshold: u32,
+    /// The actions applied, in order, once the failure threshold is reached.
+    pub actions: Vec<FallbackAction>,
+}
+
+impl Default for BootCounterConfig {
+    fn default() -> Self {
+        Self { failure_threshold: DEFAULT_FAILURE_THRESHOLD, actions: Vec::new() }
+    }
+}
+
+impl BootCounterConfig {
+    /// Sets the number of consecutive failed boots that activates the fallback actions.
+    pub fn with_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold;
+        self
+    }
+
+    /// Adds an action to apply once the failure threshold is reached.
+    pub fn with_action(mut self, action: FallbackAction) -> Self {
+        self.actions.push(action);
+        self
+    }
+}
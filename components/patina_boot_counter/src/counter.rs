@@ -0,0 +1,180 @@
+//! Boot Counters
+//!
+//! Persists the [`BootCounters`] in a non-volatile UEFI variable named `BootCounters` in the
+//! [`BOOT_COUNTER_VARIABLE_GUID`] namespace. The variable holds the monotonic boot attempt count followed by the
+//! consecutive failure count, each as a little endian `u32`.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use patina::{error::EfiError, runtime_services::RuntimeServices};
+use r_efi::efi;
+
+/// The namespace of the boot counter variable.
+pub const BOOT_COUNTER_VARIABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0x6c2d54d4, 0x8f0e, 0x4a8b, 0x9d, 0x61, &[0x3a, 0x0f, 0xe2, 0x7b, 0x19, 0xc4]);
+
+/// The attributes of the boot counter variable.
+pub const BOOT_COUNTER_VARIABLE_ATTRIBUTES: u32 =
+    efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+
+/// The UTF-16, NUL terminated, name of the boot counter variable.
+fn variable_name() -> Vec<u16> {
+    "BootCounters".encode_utf16().chain(core::iter::once(0)).collect()
+}
+
+/// The persisted boot counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BootCounters {
+    /// The number of boot attempts. This count is never reset.
+    pub boot_attempts: u32,
+    /// The number of consecutive boot attempts that did not reach ReadyToBoot.
+    pub consecutive_failures: u32,
+}
+
+impl BootCounters {
+    const SIZE: usize = 2 * size_of::<u32>();
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..4].copy_from_slice(&self.boot_attempts.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.consecutive_failures.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; Self::SIZE] = bytes.try_into().ok()?;
+        Some(Self {
+            boot_attempts: u32::from_le_bytes(bytes[..4].try_into().ok()?),
+            consecutive_failures: u32::from_le_bytes(bytes[4..].try_into().ok()?),
+        })
+    }
+
+    /// Reads the counters, returning the default counters if the variable does not exist or is malformed.
+    pub fn read(runtime_services: &impl RuntimeServices) -> Result<Self, EfiError> {
+        match runtime_services.get_variable::<Vec<u8>>(&variable_name(), &BOOT_COUNTER_VARIABLE_GUID, Some(Self::SIZE))
+        {
+            Ok((bytes, _)) => Ok(Self::from_bytes(&bytes).unwrap_or_else(|| {
+                log::warn!(target: "boot_counter", "Boot counter variable is malformed, resetting it.");
+                Self::default()
+            })),
+            Err(efi::Status::NOT_FOUND) => Ok(Self::default()),
+            Err(status) => Err(EfiError::from(status)),
+        }
+    }
+
+    /// Writes the counters.
+    pub fn write(self, runtime_services: &impl RuntimeServices) -> Result<(), EfiError> {
+        runtime_services
+            .set_variable(
+                &variable_name(),
+                &BOOT_COUNTER_VARIABLE_GUID,
+                BOOT_COUNTER_VARIABLE_ATTRIBUTES,
+                &self.to_bytes(),
+            )
+            .map_err(EfiError::from)
+    }
+
+    /// Records the start of a boot attempt, returning the counters as they were before this attempt.
+    ///
+    /// The previous attempt is counted as a failure until [`record_success`](Self::record_success) is called.
+    pub fn record_attempt(runtime_services: &impl RuntimeServices) -> Result<Self, EfiError> {
+        let previous = Self::read(runtime_services)?;
+        Self {
+            boot_attempts: previous.boot_attempts.saturating_add(1),
+            consecutive_failures: previous.consecutive_failures.saturating_add(1),
+        }
+        .write(runtime_services)?;
+        Ok(previous)
+    }
+
+    /// Records that the current boot attempt reached ReadyToBoot, resetting the consecutive failure count.
+    pub fn record_success(runtime_services: &impl RuntimeServices) -> Result<(), EfiError> {
+        let counters = Self::read(runtime_services)?;
+        if counters.consecutive_failures == 0 {
+            return Ok(());
+        }
+        Self { consecutive_failures: 0, ..counters }.write(runtime_services)
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use patina::runtime_services::MockRuntimeServices;
+
+    fn expect_read(runtime_services: &mut MockRuntimeServices, result: Result<Vec<u8>, efi::Status>) {
+        runtime_services
+            .expect_get_variable::<Vec<u8>>()
+            .once()
+            .withf(|name, namespace, _| name == variable_name().as_slice() && namespace == &BOOT_COUNTER_VARIABLE_GUID)
+            .return_once(move |_, _, _| result.map(|bytes| (bytes, BOOT_COUNTER_VARIABLE_ATTRIBUTES)));
+    }
+
+    #[test]
+    fn test_record_attempt_increments_counters() {
+        let mut runtime_services = MockRuntimeServices::new();
+        expect_read(
+            &mut runtime_services,
+            Ok(BootCounters { boot_attempts: 7, consecutive_failures: 2 }.to_bytes().to_vec()),
+        );
+        runtime_services
+            .expect_set_variable::<[u8; BootCounters::SIZE]>()
+            .once()
+            .withf(|_, _, attributes, data| {
+                *attributes == BOOT_COUNTER_VARIABLE_ATTRIBUTES
+                    && BootCounters::from_bytes(data)
+                        == Some(BootCounters { boot_attempts: 8, consecutive_failures: 3 })
+            })
+            .returning(|_, _, _, _| Ok(()));
+
+        assert_eq!(
+            BootCounters::record_attempt(&runtime_services),
+            Ok(BootCounters { boot_attempts: 7, consecutive_failures: 2 })
+        );
+    }
+
+    #[test]
+    fn test_missing_or_malformed_variable_reads_as_default() {
+        let mut runtime_services = MockRuntimeServices::new();
+        expect_read(&mut runtime_services, Err(efi::Status::NOT_FOUND));
+        assert_eq!(BootCounters::read(&runtime_services), Ok(BootCounters::default()));
+
+        let mut runtime_services = MockRuntimeServices::new();
+        expect_read(&mut runtime_services, Ok(vec![1, 2, 3]));
+        assert_eq!(BootCounters::read(&runtime_services), Ok(BootCounters::default()));
+
+        let mut runtime_services = MockRuntimeServices::new();
+        expect_read(&mut runtime_services, Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(BootCounters::read(&runtime_services), Err(EfiError::DeviceError));
+    }
+
+    #[test]
+    fn test_record_success_resets_consecutive_failures() {
+        let mut runtime_services = MockRuntimeServices::new();
+        expect_read(
+            &mut runtime_services,
+            Ok(BootCounters { boot_attempts: 4, consecutive_failures: 4 }.to_bytes().to_vec()),
+        );
+        runtime_services
+            .expect_set_variable::<[u8; BootCounters::SIZE]>()
+            .once()
+            .withf(|_, _, _, data| {
+                BootCounters::from_bytes(data) == Some(BootCounters { boot_attempts: 4, consecutive_failures: 0 })
+            })
+            .returning(|_, _, _, _| Ok(()));
+        assert_eq!(BootCounters::record_success(&runtime_services), Ok(()));
+
+        let mut runtime_services = MockRuntimeServices::new();
+        expect_read(&mut runtime_services, Err(efi::Status::NOT_FOUND));
+        runtime_services.expect_set_variable::<[u8; BootCounters::SIZE]>().never();
+        assert_eq!(BootCounters::record_success(&runtime_services), Ok(()));
+    }
+}
@@ -0,0 +1,48 @@
+//! Boot Attempt Counting and Boot Failure Fallback
+//!
+//! This crate provides a resilience mechanism for platforms that fail to boot, for example due to a bad configuration
+//! setting or a corrupted driver.
+//!
+//! The [`component::BootCounter`] component persists a pair of [`counter::BootCounters`] in a non-volatile UEFI
+//! variable: a monotonic count of boot attempts, and the number of consecutive boot attempts that failed to reach
+//! ReadyToBoot. Each boot increments both counters once the variable services are available, and the consecutive
+//! failure count is reset when ReadyToBoot is signaled. Once the consecutive failure count reaches the threshold in
+//! [`config::BootCounterConfig`], the component applies the configured [`config::FallbackAction`]s, such as enabling
+//! verbose logging, booting a recovery firmware volume or clearing problematic settings, and signals the
+//! [`component::BOOT_FALLBACK_EVENT_GROUP`] event group.
+//!
+//! ## Examples
+//!
+//! ```rust,ignore
+//! use patina_boot_counter::{
+//!     component::BootCounter,
+//!     config::{BootCounterConfig, FallbackAction},
+//! };
+//!
+//! patina_dxe_core::Core::default()
+//!   .init_memory(physical_hob_list)
+//!   .with_config(
+//!       BootCounterConfig::default()
+//!           .with_threshold(3)
+//!           .with_action(FallbackAction::VerboseLogging)
+//!           .with_action(FallbackAction::RecoveryFv { base_address: RECOVERY_FV_BASE, size: RECOVERY_FV_SIZE }),
+//!   )
+//!   .with_component(BootCounter)
+//!   .start()
+//!   .unwrap();
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![feature(coverage_attribute)]
+
+extern crate alloc;
+
+pub mod component;
+pub mod config;
+pub mod counter;
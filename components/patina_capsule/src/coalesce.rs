@@ -0,0 +1,199 @@
+//! Capsule Coalescing
+//!
+//! Reassembles capsules from the scatter-gather list passed to `UpdateCapsule`.
+//!
+//! A scatter-gather list is an array of `EFI_CAPSULE_BLOCK_DESCRIPTOR` entries. A descriptor with a non-zero length
+//! describes a fragment of capsule data, a descriptor with a zero length and a non-zero address continues the list
+//! at that address, and a descriptor with a zero length and address terminates the list. The fragments, in order,
+//! form one or more capsules placed back to back, each starting with an `EFI_CAPSULE_HEADER`.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::{mem::size_of, slice};
+
+use patina::error::{EfiError, Result};
+use r_efi::efi;
+
+/// The maximum number of descriptors walked in a single scatter-gather list, which bounds a list that loops.
+const MAX_DESCRIPTORS: usize = 0x10000;
+
+/// A capsule coalesced into contiguous memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Capsule<'a> {
+    /// The capsule header.
+    pub header: efi::CapsuleHeader,
+    /// The complete capsule image, including the header.
+    pub image: &'a [u8],
+}
+
+impl<'a> Capsule<'a> {
+    /// Returns the GUID identifying the capsule type.
+    pub fn guid(&self) -> efi::Guid {
+        self.header.capsule_guid
+    }
+
+    /// Returns the capsule payload following the header.
+    pub fn body(&self) -> &'a [u8] {
+        &self.image[self.header.header_size as usize..]
+    }
+}
+
+/// Copies the fragments described by the scatter-gather list at `sg_list` into a contiguous buffer.
+///
+/// Returns [`EfiError::BadBufferSize`] if the fragments exceed `max_size` bytes, and [`EfiError::VolumeCorrupted`] if
+/// the list does not terminate.
+///
+/// ## Safety
+///
+/// `sg_list` must be the physical address of a valid scatter-gather list, and all memory it references must be
+/// readable.
+pub unsafe fn coalesce(sg_list: u64, max_size: usize) -> Result<Vec<u8>> {
+    if sg_list == 0 {
+        return Err(EfiError::InvalidParameter);
+    }
+
+    let mut buffer = Vec::new();
+    let mut descriptor = sg_list as *const efi::CapsuleBlockDescriptor;
+    for _ in 0..MAX_DESCRIPTORS {
+        // SAFETY: The caller guarantees the scatter-gather list is valid.
+        let entry = unsafe { descriptor.read_unaligned() };
+        // SAFETY: Both union members are physical addresses.
+        let address = unsafe { entry.data.data_block };
+        match (entry.length, address) {
+            (0, 0) => return Ok(buffer),
+            (0, next) => descriptor = next as *const efi::CapsuleBlockDescriptor,
+            (length, address) => {
+                let length = usize::try_from(length).map_err(|_| EfiError::BadBufferSize)?;
+                if address == 0 || length > max_size - buffer.len() {
+                    return Err(EfiError::BadBufferSize);
+                }
+                // SAFETY: The caller guarantees the fragments described by the list are readable.
+                buffer.extend_from_slice(unsafe { slice::from_raw_parts(address as *const u8, length) });
+                // SAFETY: Data descriptors are followed by another descriptor in the same array.
+                descriptor = unsafe { descriptor.add(1) };
+            }
+        }
+    }
+
+    log::error!("Capsule scatter-gather list at {sg_list:#x} does not terminate.");
+    Err(EfiError::VolumeCorrupted)
+}
+
+/// Splits a coalesced buffer into the capsules it contains.
+///
+/// Returns [`EfiError::VolumeCorrupted`] if a capsule header is malformed or a capsule extends past the end of the
+/// buffer.
+pub fn split_capsules(buffer: &[u8]) -> Result<Vec<Capsule<'_>>> {
+    let mut capsules = Vec::new();
+    let mut remaining = buffer;
+    while !remaining.is_empty() {
+        if remaining.len() < size_of::<efi::CapsuleHeader>() {
+            return Err(EfiError::VolumeCorrupted);
+        }
+        // SAFETY: The buffer holds at least a capsule header.
+        let header = unsafe { (remaining.as_ptr() as *const efi::CapsuleHeader).read_unaligned() };
+        let image_size = header.capsule_image_size as usize;
+        let header_size = header.header_size as usize;
+        if header_size < size_of::<efi::CapsuleHeader>() || header_size > image_size || image_size > remaining.len() {
+            log::error!("Malformed capsule header: {header:?}");
+            return Err(EfiError::VolumeCorrupted);
+        }
+        let (image, rest) = remaining.split_at(image_size);
+        capsules.push(Capsule { header, image });
+        remaining = rest;
+    }
+    Ok(capsules)
+}
+
+#[cfg(test)]
+#[coverage(off)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::{boxed::Box, vec};
+
+    pub(crate) const TEST_GUID: efi::Guid =
+        efi::Guid::from_fields(0x6dcbd5ed, 0xe82d, 0x4c44, 0xbd, 0xa1, &[0x71, 0x94, 0x19, 0x9a, 0xd9, 0x2a]);
+
+    /// Builds a capsule image with the given GUID and body.
+    pub(crate) fn capsule_image(guid: efi::Guid, body: &[u8]) -> Vec<u8> {
+        let header_size = size_of::<efi::CapsuleHeader>();
+        let header = efi::CapsuleHeader {
+            capsule_guid: guid,
+            header_size: header_size as u32,
+            flags: efi::CAPSULE_FLAGS_PERSIST_ACROSS_RESET,
+            capsule_image_size: (header_size + body.len()) as u32,
+        };
+        // SAFETY: The header is a plain old data structure.
+        let bytes = unsafe { slice::from_raw_parts(&header as *const _ as *const u8, header_size) };
+        [bytes, body].concat()
+    }
+
+    fn descriptor(length: u64, address: u64) -> efi::CapsuleBlockDescriptor {
+        efi::CapsuleBlockDescriptor { length, data: efi::CapsuleBlockDescriptorUnion { data_block: address } }
+    }
+
+    /// Scatters `data` into fragments of `fragment_size` bytes across two descriptor arrays joined by a continuation,
+    /// leaking the memory so the returned list address remains valid.
+    pub(crate) fn scatter(data: &[u8], fragment_size: usize) -> u64 {
+        let fragments: Vec<&'static [u8]> =
+            data.chunks(fragment_size).map(|c| &*Box::leak(c.to_vec().into_boxed_slice())).collect();
+        let (first, second) = fragments.split_at(fragments.len() / 2);
+
+        let mut tail: Vec<_> = second.iter().map(|f| descriptor(f.len() as u64, f.as_ptr() as u64)).collect();
+        tail.push(descriptor(0, 0));
+        let tail = Box::leak(tail.into_boxed_slice());
+
+        let mut head: Vec<_> = first.iter().map(|f| descriptor(f.len() as u64, f.as_ptr() as u64)).collect();
+        head.push(descriptor(0, tail.as_ptr() as u64));
+        Box::leak(head.into_boxed_slice()).as_ptr() as u64
+    }
+
+    #[test]
+    fn test_coalesce_reassembles_fragments() {
+        let data: Vec<u8> = (0..=255).collect();
+        let sg_list = scatter(&data, 7);
+        assert_eq!(unsafe { coalesce(sg_list, data.len()) }.unwrap(), data);
+        assert_eq!(unsafe { coalesce(sg_list, data.len() - 1) }, Err(EfiError::BadBufferSize));
+        assert_eq!(unsafe { coalesce(0, data.len()) }, Err(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn test_coalesce_rejects_looping_list() {
+        let list = Box::leak(Box::new([descriptor(0, 0)]));
+        list[0] = descriptor(0, list.as_ptr() as u64);
+        assert_eq!(unsafe { coalesce(list.as_ptr() as u64, 0x1000) }, Err(EfiError::VolumeCorrupted));
+    }
+
+    #[test]
+    fn test_split_capsules() {
+        let other_guid = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+        let buffer = [capsule_image(TEST_GUID, b"first"), capsule_image(other_guid, b"second!")].concat();
+
+        let capsules = split_capsules(&buffer).unwrap();
+        assert_eq!(capsules.len(), 2);
+        assert_eq!(capsules[0].guid(), TEST_GUID);
+        assert_eq!(capsules[0].body(), b"first");
+        assert_eq!(capsules[1].guid(), other_guid);
+        assert_eq!(capsules[1].body(), b"second!");
+        assert!(split_capsules(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_split_capsules_rejects_malformed_headers() {
+        let image = capsule_image(TEST_GUID, b"payload");
+        assert_eq!(split_capsules(&image[..image.len() - 1]).err(), Some(EfiError::VolumeCorrupted));
+        assert_eq!(split_capsules(&image[..8]).err(), Some(EfiError::VolumeCorrupted));
+
+        let mut bad_header_size = image.clone();
+        bad_header_size[16..20].copy_from_slice(&4u32.to_le_bytes());
+        assert_eq!(split_capsules(&bad_header_size).err(), Some(EfiError::VolumeCorrupted));
+        assert_eq!(split_capsules(&[image, vec![0; 4]].concat()).err(), Some(EfiError::VolumeCorrupted));
+    }
+}
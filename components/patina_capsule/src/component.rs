@@ -0,0 +1,143 @@
+//! Capsule Coalescing Component
+//!
+//! Discovers capsules persisted across a reset, coalesces them and hands them to the registered capsule processors.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this component using the `capsule` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina::component::{IntoComponent, hob::Hob, params::Config};
+
+use crate::{
+    coalesce::{coalesce, split_capsules},
+    hob::CapsuleMailboxHob,
+    processor::CapsuleProcessors,
+};
+
+/// The default limit on the size of the capsules described by a single scatter-gather list.
+const DEFAULT_MAX_SIZE: usize = 0x1000_0000;
+
+/// A component that processes capsules described by [`CapsuleMailboxHob`]s.
+///
+/// The component only runs if the platform produced at least one [`CapsuleMailboxHob`], and runs once configuration
+/// is locked so that all [`CapsuleProcessors`] have been registered.
+#[derive(Debug, IntoComponent)]
+pub struct CapsuleCoalescer {
+    max_size: usize,
+}
+
+impl Default for CapsuleCoalescer {
+    fn default() -> Self {
+        Self { max_size: DEFAULT_MAX_SIZE }
+    }
+}
+
+impl CapsuleCoalescer {
+    /// Limits the size of the capsules described by a single scatter-gather list to `max_size` bytes.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Entry point for the capsule coalescer.
+    ///
+    /// A scatter-gather list that cannot be coalesced, a capsule without a registered processor and a capsule that
+    /// fails to process are logged and skipped so that the remaining capsules are still processed.
+    fn entry_point(
+        self,
+        mailboxes: Hob<CapsuleMailboxHob>,
+        processors: Config<CapsuleProcessors>,
+    ) -> patina::error::Result<()> {
+        for mailbox in mailboxes.iter() {
+            log::info!(target: "capsule", "Coalescing capsules from scatter-gather list at {:#x}.", mailbox.scatter_gather_list);
+            // SAFETY: The platform guarantees the scatter-gather list described by the HOB was preserved.
+            let buffer = match unsafe { coalesce(mailbox.scatter_gather_list, self.max_size) } {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    log::error!(target: "capsule", "Failed to coalesce capsules: {err:?}");
+                    continue;
+                }
+            };
+            let capsules = match split_capsules(&buffer) {
+                Ok(capsules) => capsules,
+                Err(err) => {
+                    log::error!(target: "capsule", "Failed to parse coalesced capsules: {err:?}");
+                    continue;
+                }
+            };
+
+            for capsule in capsules {
+                let guid = capsule.guid();
+                let Some(processor) = processors.find(&guid) else {
+                    log::warn!(target: "capsule", "No processor registered for capsule {guid:?}.");
+                    continue;
+                };
+                match processor.process(&capsule) {
+                    Ok(()) => log::info!(target: "capsule", "Processed capsule {guid:?}."),
+                    Err(err) => log::error!(target: "capsule", "Failed to process capsule {guid:?}: {err:?}"),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::{
+        coalesce::tests::{TEST_GUID, capsule_image, scatter},
+        processor::MockCapsuleProcessor,
+    };
+    use patina::error::EfiError;
+    use r_efi::efi;
+
+    #[test]
+    fn test_capsules_are_dispatched_to_processors() {
+        let other_guid = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+        let unknown_guid = efi::Guid::from_fields(7, 8, 9, 10, 11, &[12; 6]);
+        let first = [capsule_image(TEST_GUID, b"one"), capsule_image(unknown_guid, b"ignored")].concat();
+        let second = [capsule_image(other_guid, b"two"), capsule_image(TEST_GUID, b"three")].concat();
+
+        let mut processor = MockCapsuleProcessor::new();
+        let mut seq = mockall::Sequence::new();
+        processor.expect_process().once().in_sequence(&mut seq).withf(|c| c.body() == b"one").returning(|_| Ok(()));
+        processor
+            .expect_process()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|c| c.body() == b"three")
+            .returning(|_| Err(EfiError::Unsupported));
+        let mut other = MockCapsuleProcessor::new();
+        other.expect_process().once().withf(|c| c.body() == b"two").returning(|_| Ok(()));
+
+        let mailboxes = Hob::mock(vec![
+            CapsuleMailboxHob { scatter_gather_list: scatter(&first, 5) },
+            CapsuleMailboxHob { scatter_gather_list: 0 },
+            CapsuleMailboxHob { scatter_gather_list: scatter(&second, 64) },
+        ]);
+        let processors = Config::mock(
+            CapsuleProcessors::default().with_processor(TEST_GUID, processor).with_processor(other_guid, other),
+        );
+
+        assert!(CapsuleCoalescer::default().entry_point(mailboxes, processors).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_capsules_are_skipped() {
+        let image = capsule_image(TEST_GUID, &[0xA5; 0x100]);
+        let mut processor = MockCapsuleProcessor::new();
+        processor.expect_process().never();
+
+        let mailboxes = Hob::mock(vec![CapsuleMailboxHob { scatter_gather_list: scatter(&image, 0x40) }]);
+        let processors = Config::mock(CapsuleProcessors::default().with_processor(TEST_GUID, processor));
+        assert!(CapsuleCoalescer::default().with_max_size(0x80).entry_point(mailboxes, processors).is_ok());
+    }
+}
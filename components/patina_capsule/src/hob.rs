@@ -0,0 +1,27 @@
+//! Capsule Mailbox HOB
+//!
+//! Defines the HOB used by the platform to describe capsules that were persisted across a reset.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina::component::hob::FromHob;
+
+/// A HOB that describes one scatter-gather list of capsules persisted across a reset.
+///
+/// The platform produces one HOB for each scatter-gather list passed to `UpdateCapsule` before the reset, and must
+/// ensure that the list and all memory it references are preserved until the capsules are processed.
+///
+/// HOB GUID values for reference:
+/// - `{0x4ad0e0a5, 0x1b7e, 0x4f39, {0x9d, 0x6c, 0x3e, 0x5a, 0x27, 0x91, 0xc0, 0x4b}}`
+/// - `{4ad0e0a5-1b7e-4f39-9d6c-3e5a2791c04b}`
+#[derive(FromHob, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[hob = "4ad0e0a5-1b7e-4f39-9d6c-3e5a2791c04b"]
+#[repr(C)]
+pub struct CapsuleMailboxHob {
+    /// The physical address of the first `EFI_CAPSULE_BLOCK_DESCRIPTOR` of the scatter-gather list.
+    pub scatter_gather_list: u64,
+}
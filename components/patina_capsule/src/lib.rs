@@ -0,0 +1,42 @@
+//! UEFI Capsule Support
+//!
+//! This crate provides support for processing capsules that were submitted with `UpdateCapsule` and persisted across a
+//! reset (`CAPSULE_FLAGS_PERSIST_ACROSS_RESET`).
+//!
+//! Before the reset, the caller of `UpdateCapsule` hands the firmware a scatter-gather list describing the capsule
+//! fragments in memory. The platform preserves that memory across the reset and describes each scatter-gather list in
+//! a [`hob::CapsuleMailboxHob`]. At the start of DXE, the [`component::CapsuleCoalescer`] component discovers these
+//! HOBs, coalesces the fragments into contiguous capsules and hands each capsule to the
+//! [`processor::CapsuleProcessor`] registered for its GUID.
+//!
+//! ## Examples
+//!
+//! ```rust,ignore
+//! use patina_capsule::{component::CapsuleCoalescer, processor::CapsuleProcessors};
+//!
+//! patina_dxe_core::Core::default()
+//!   .init_memory(physical_hob_list)
+//!   .with_config(CapsuleProcessors::default().with_processor(MY_CAPSULE_GUID, MyCapsuleProcessor))
+//!   .with_component(CapsuleCoalescer::default())
+//!   .start()
+//!   .unwrap();
+//! ```
+//!
+//! Components may also register processors before configuration is locked through
+//! `ConfigMut<CapsuleProcessors>`.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![feature(coverage_attribute)]
+
+extern crate alloc;
+
+pub mod coalesce;
+pub mod component;
+pub mod hob;
+pub mod processor;
@@ -0,0 +1,63 @@
+//! Capsule Processors
+//!
+//! Defines the [`CapsuleProcessor`] trait implemented by handlers of specific capsule types, and the
+//! [`CapsuleProcessors`] configuration used to register them.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec::Vec};
+
+use r_efi::efi;
+
+use crate::coalesce::Capsule;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// A handler for capsules of a specific type.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+#[allow(clippy::needless_lifetimes)] //https://github.com/rust-lang/rust-clippy/issues/6622
+pub trait CapsuleProcessor {
+    /// Processes a capsule that was persisted across a reset.
+    ///
+    /// The capsule memory is only valid for the duration of the call.
+    fn process<'a>(&self, capsule: &Capsule<'a>) -> patina::error::Result<()>;
+}
+
+/// The capsule processors available to the [`CapsuleCoalescer`](crate::component::CapsuleCoalescer) component.
+///
+/// Processors are registered by capsule GUID, either by the platform when configuring the core or by components
+/// through `ConfigMut<CapsuleProcessors>` before configuration is locked. If more than one processor is registered for
+/// a GUID, the first one registered is used.
+#[derive(Default)]
+pub struct CapsuleProcessors {
+    processors: Vec<(efi::Guid, Box<dyn CapsuleProcessor>)>,
+}
+
+impl CapsuleProcessors {
+    /// Registers `processor` for capsules identified by `guid`.
+    pub fn register(&mut self, guid: efi::Guid, processor: impl CapsuleProcessor + 'static) {
+        self.processors.push((guid, Box::new(processor)));
+    }
+
+    /// Registers `processor` for capsules identified by `guid`, returning the updated configuration.
+    pub fn with_processor(mut self, guid: efi::Guid, processor: impl CapsuleProcessor + 'static) -> Self {
+        self.register(guid, processor);
+        self
+    }
+
+    /// Returns the processor registered for capsules identified by `guid`, if any.
+    pub fn find(&self, guid: &efi::Guid) -> Option<&dyn CapsuleProcessor> {
+        self.processors.iter().find(|(g, _)| g == guid).map(|(_, p)| p.as_ref())
+    }
+}
+
+impl core::fmt::Debug for CapsuleProcessors {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.processors.iter().map(|(guid, _)| guid)).finish()
+    }
+}
@@ -0,0 +1,219 @@
+//! Memory Scrubber Component
+//!
+//! Snapshots the conventional memory in the memory map when the component is dispatched, then scrubs it a budget of
+//! pages at a time from a periodic timer event at `TPL_CALLBACK`, so that scrubbing is interleaved with the dispatch of
+//! the remaining drivers. The timer is closed once every page was scrubbed or skipped.
+//!
+//! Scrubbing runs on the boot processor only. Memory that is freed after the snapshot is not scrubbed, and pages that
+//! were allocated since the snapshot are skipped.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this component using the `memory_scrub` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec::Vec};
+
+use patina::{
+    boot_services::{
+        BootServices, StandardBootServices,
+        event::{EventTimerType, EventType},
+        tpl::Tpl,
+    },
+    component::{
+        IntoComponent,
+        params::{Commands, Config},
+    },
+};
+use r_efi::efi;
+use spin::Mutex;
+
+use crate::{
+    config::MemoryScrubConfig,
+    scrubber::{Scrubber, scrub_pages},
+    service::ScrubProgressReporter,
+};
+
+/// The timer period unit, 100 ns, per millisecond.
+const TIMER_UNITS_PER_MS: u64 = 10_000;
+
+/// A component that scrubs the free memory in the background and produces the
+/// [`MemoryScrubProgress`](crate::service::MemoryScrubProgress) service.
+#[derive(Debug, Default, IntoComponent)]
+pub struct MemoryScrubber;
+
+/// The state shared with the timer event for the rest of boot.
+struct ScrubState<B> {
+    boot_services: B,
+    scrubber: Mutex<Scrubber>,
+    config: MemoryScrubConfig,
+}
+
+impl MemoryScrubber {
+    /// Entry point of [`MemoryScrubber`]
+    #[coverage(off)] // This is tested via the generic version, see _entry_point.
+    fn entry_point(
+        self,
+        config: Config<MemoryScrubConfig>,
+        boot_services: StandardBootServices,
+        mut commands: Commands,
+    ) -> patina::error::Result<()> {
+        let regions = match boot_services.get_memory_map() {
+            Ok(memory_map) => free_regions(&memory_map.descriptors),
+            Err((status, _)) => return Err(status.into()),
+        };
+        self._entry_point(*config, regions, boot_services, &mut commands)
+    }
+
+    /// Entry point that has generic parameters.
+    fn _entry_point<B>(
+        self,
+        config: MemoryScrubConfig,
+        regions: Vec<(u64, usize)>,
+        boot_services: B,
+        commands: &mut Commands,
+    ) -> patina::error::Result<()>
+    where
+        B: BootServices + 'static,
+    {
+        let scrubber = Scrubber::new(regions);
+        log::info!(
+            target: "memory_scrub",
+            "Scrubbing {:#x} free pages ({:?}), {} pages every {} ms.",
+            scrubber.progress().total_pages,
+            config.mode,
+            config.pages_per_tick,
+            config.tick_period_ms
+        );
+        let state: &'static ScrubState<B> =
+            Box::leak(Box::new(ScrubState { boot_services, scrubber: Mutex::new(scrubber), config }));
+
+        let event = state.boot_services.create_event(
+            EventType::TIMER | EventType::NOTIFY_SIGNAL,
+            Tpl::CALLBACK,
+            Some(scrub_tick::<B>),
+            state,
+        )?;
+        state.boot_services.set_timer(
+            event,
+            EventTimerType::Periodic,
+            config.tick_period_ms.max(1) * TIMER_UNITS_PER_MS,
+        )?;
+
+        commands.add_service(ScrubProgressReporter(&state.scrubber));
+        Ok(())
+    }
+}
+
+/// Returns the base address and page count of each conventional memory region in the memory map.
+fn free_regions(descriptors: &[efi::MemoryDescriptor]) -> Vec<(u64, usize)> {
+    descriptors
+        .iter()
+        .filter(|descriptor| descriptor.r#type == efi::CONVENTIONAL_MEMORY && descriptor.number_of_pages > 0)
+        .map(|descriptor| (descriptor.physical_start, descriptor.number_of_pages as usize))
+        .collect()
+}
+
+/// Scrubs the next budget of pages, and closes the timer once scrubbing is complete.
+extern "efiapi" fn scrub_tick<B: BootServices>(event: efi::Event, state: &'static ScrubState<B>) {
+    let mut scrubber = state.scrubber.lock();
+    scrubber.step(state.config.pages_per_tick, |base, pages| {
+        scrub_pages(&state.boot_services, base, pages, state.config.mode)
+    });
+
+    let progress = scrubber.progress();
+    if progress.is_complete() {
+        log::info!(
+            target: "memory_scrub",
+            "Scrubbing complete: {:#x} pages scrubbed, {:#x} pages skipped.",
+            progress.scrubbed_pages,
+            progress.skipped_pages
+        );
+        let _ = state.boot_services.set_timer(event, EventTimerType::Cancel, 0);
+        let _ = state.boot_services.close_event(event);
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::vec;
+    use patina::{
+        base::UEFI_PAGE_SIZE,
+        boot_services::{MockBootServices, allocation::AllocType},
+    };
+
+    fn descriptor(r#type: efi::MemoryType, physical_start: u64, number_of_pages: u64) -> efi::MemoryDescriptor {
+        efi::MemoryDescriptor { r#type, physical_start, virtual_start: 0, number_of_pages, attribute: 0 }
+    }
+
+    #[test]
+    fn test_only_conventional_memory_is_scrubbed() {
+        let descriptors = [
+            descriptor(efi::BOOT_SERVICES_DATA, 0x1000, 4),
+            descriptor(efi::CONVENTIONAL_MEMORY, 0x5000, 2),
+            descriptor(efi::CONVENTIONAL_MEMORY, 0x7000, 0),
+            descriptor(efi::CONVENTIONAL_MEMORY, 0x10000, 8),
+        ];
+        assert_eq!(free_regions(&descriptors), [(0x5000, 2), (0x10000, 8)]);
+    }
+
+    #[test]
+    fn test_entry_point_starts_a_periodic_timer() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event::<&'static ScrubState<MockBootServices>>()
+            .once()
+            .withf_st(|event_type, notify_tpl, notify_function, _| {
+                *event_type == EventType::TIMER | EventType::NOTIFY_SIGNAL
+                    && *notify_tpl == Tpl::CALLBACK
+                    && notify_function.unwrap() as usize == scrub_tick::<MockBootServices> as usize
+            })
+            .return_const_st(Ok(1_usize as efi::Event));
+        boot_services
+            .expect_set_timer()
+            .once()
+            .withf(|_, timer_type, trigger_time| {
+                matches!(timer_type, EventTimerType::Periodic) && *trigger_time == 20 * TIMER_UNITS_PER_MS
+            })
+            .return_const(Ok(()));
+
+        let config = MemoryScrubConfig { tick_period_ms: 20, ..Default::default() };
+        assert!(MemoryScrubber._entry_point(config, vec![(0x1000, 4)], boot_services, &mut Commands::mock()).is_ok());
+    }
+
+    #[test]
+    fn test_timer_is_closed_once_scrubbing_is_complete() {
+        let memory: &'static mut [u64] = vec![0u64; 3 * UEFI_PAGE_SIZE / size_of::<u64>()].leak();
+        let base = memory.as_mut_ptr() as u64;
+
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pages().times(2).returning(|alloc_type, _, _| match alloc_type {
+            AllocType::Address(address) => Ok(address),
+            _ => panic!("pages must be scrubbed in place"),
+        });
+        boot_services.expect_free_pages().times(2).returning(|_, _| Ok(()));
+        boot_services.expect_set_timer().once().return_const(Ok(()));
+        boot_services.expect_close_event().once().return_const(Ok(()));
+
+        let config = MemoryScrubConfig { pages_per_tick: 2, ..Default::default() };
+        let state: &'static ScrubState<MockBootServices> = Box::leak(Box::new(ScrubState {
+            boot_services,
+            scrubber: Mutex::new(Scrubber::new(vec![(base, 3)])),
+            config,
+        }));
+
+        scrub_tick(1_usize as efi::Event, state);
+        assert!(!state.scrubber.lock().progress().is_complete());
+        scrub_tick(1_usize as efi::Event, state);
+        assert_eq!(state.scrubber.lock().progress().scrubbed_pages, 3);
+    }
+}
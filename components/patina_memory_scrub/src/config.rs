@@ -0,0 +1,41 @@
+//! Memory Scrub Configuration
+//!
+//! Defines the [`MemoryScrubConfig`] used to configure the [`MemoryScrubber`](crate::component::MemoryScrubber)
+//! component.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+/// How each free page is scrubbed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScrubMode {
+    /// Every word of the page is read, which surfaces ECC errors without changing the memory.
+    #[default]
+    Touch,
+    /// Every word of the page is written with zero, which also initializes the ECC check bits.
+    Zero,
+}
+
+/// The configuration of the [`MemoryScrubber`](crate::component::MemoryScrubber) component.
+///
+/// The budget is the number of pages scrubbed each time the timer fires: the default of 256 pages every 10 ms
+/// scrubs about 100 MiB per second, which keeps each tick short enough not to delay the drivers being dispatched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryScrubConfig {
+    /// How each free page is scrubbed.
+    pub mode: ScrubMode,
+    /// The maximum number of pages scrubbed each time the timer fires.
+    pub pages_per_tick: usize,
+    /// The period of the timer, in milliseconds.
+    pub tick_period_ms: u64,
+}
+
+impl Default for MemoryScrubConfig {
+    fn default() -> Self {
+        Self { mode: ScrubMode::Touch, pages_per_tick: 256, tick_period_ms: 10 }
+    }
+}
@@ -0,0 +1,39 @@
+//! Background Memory Scrubbing
+//!
+//! This crate provides an optional component that touches all free memory in the background while drivers are
+//! dispatched. Reading every page surfaces latent ECC errors while the firmware can still report them, rather than
+//! when the OS first uses the memory, and leaves the page tables for free memory populated. Platforms whose memory
+//! controller does not initialize the ECC check bits can have the component zero the memory instead.
+//!
+//! - [`component::MemoryScrubber`]: Scrubs the free memory a few pages at a time from a periodic timer event, within
+//!   the budget set in [`config::MemoryScrubConfig`], and produces the [`service::ScrubProgressReporter`] service.
+//! - [`scrubber::Scrubber`]: Tracks the progress through the free memory that was present when scrubbing started.
+//!
+//! ## Examples
+//!
+//! ```rust,ignore
+//! use patina_memory_scrub::{component::MemoryScrubber, config::{MemoryScrubConfig, ScrubMode}};
+//!
+//! patina_dxe_core::Core::default()
+//!   .init_memory(physical_hob_list)
+//!   .with_config(MemoryScrubConfig { mode: ScrubMode::Zero, pages_per_tick: 512, ..Default::default() })
+//!   .with_component(MemoryScrubber)
+//!   .start()
+//!   .unwrap();
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![feature(coverage_attribute)]
+
+extern crate alloc;
+
+pub mod component;
+pub mod config;
+pub mod scrubber;
+pub mod service;
@@ -0,0 +1,162 @@
+//! Free Memory Scrubber
+//!
+//! [`Scrubber`] walks the free memory regions that were present when scrubbing started, a budget of pages at a time.
+//! Each chunk is allocated at its address for the time it is scrubbed, so memory that was allocated since scrubbing
+//! started is skipped rather than overwritten; memory freed since is not scrubbed.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::{ptr, slice};
+
+use patina::{
+    base::UEFI_PAGE_SIZE,
+    boot_services::{
+        BootServices,
+        allocation::{AllocType, MemoryType},
+    },
+};
+
+use crate::config::ScrubMode;
+
+/// The progress of a [`Scrubber`], in pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrubProgress {
+    /// The pages that were scrubbed.
+    pub scrubbed_pages: usize,
+    /// The pages that were skipped because they were allocated since scrubbing started.
+    pub skipped_pages: usize,
+    /// The free pages when scrubbing started.
+    pub total_pages: usize,
+}
+
+impl ScrubProgress {
+    /// Returns true once every page was either scrubbed or skipped.
+    pub fn is_complete(&self) -> bool {
+        self.scrubbed_pages + self.skipped_pages >= self.total_pages
+    }
+}
+
+/// Tracks the progress through a list of free memory regions.
+#[derive(Debug, Default)]
+pub struct Scrubber {
+    /// The base address and page count of each free region.
+    regions: Vec<(u64, usize)>,
+    /// The region being scrubbed, and the pages of it already scrubbed or skipped.
+    cursor: (usize, usize),
+    progress: ScrubProgress,
+}
+
+impl Scrubber {
+    /// Creates a scrubber for the free regions, given as base address and page count.
+    pub fn new(regions: Vec<(u64, usize)>) -> Self {
+        let total_pages = regions.iter().map(|&(_, pages)| pages).sum();
+        Self { regions, cursor: (0, 0), progress: ScrubProgress { total_pages, ..Default::default() } }
+    }
+
+    /// Returns the progress so far.
+    pub fn progress(&self) -> ScrubProgress {
+        self.progress
+    }
+
+    /// Scrubs up to `budget` pages with `scrub`, which is given the address and page count of a chunk and returns false
+    /// if the chunk was skipped.
+    pub fn step(&mut self, budget: usize, mut scrub: impl FnMut(u64, usize) -> bool) {
+        let mut budget = budget;
+        while budget > 0 {
+            let (region, done) = self.cursor;
+            let Some(&(base, pages)) = self.regions.get(region) else {
+                return;
+            };
+            let chunk = (pages - done).min(budget);
+            match scrub(base + (done * UEFI_PAGE_SIZE) as u64, chunk) {
+                true => self.progress.scrubbed_pages += chunk,
+                false => self.progress.skipped_pages += chunk,
+            }
+            budget -= chunk;
+            self.cursor = if done + chunk == pages { (region + 1, 0) } else { (region, done + chunk) };
+        }
+    }
+}
+
+/// Scrubs `pages` pages at `base` per `mode`, returning false if they are no longer free.
+pub fn scrub_pages(boot_services: &impl BootServices, base: u64, pages: usize, mode: ScrubMode) -> bool {
+    if boot_services.allocate_pages(AllocType::Address(base as usize), MemoryType::BOOT_SERVICES_DATA, pages).is_err() {
+        return false;
+    }
+    // SAFETY: The pages were just allocated at `base`, so they are owned by the scrubber until they are freed below.
+    let words = unsafe { slice::from_raw_parts_mut(base as *mut u64, pages * UEFI_PAGE_SIZE / size_of::<u64>()) };
+    for word in words {
+        match mode {
+            // SAFETY: The word is within the allocated pages; volatile accesses keep the compiler from eliding them.
+            ScrubMode::Touch => _ = unsafe { ptr::read_volatile(word) },
+            // SAFETY: As above.
+            ScrubMode::Zero => unsafe { ptr::write_volatile(word, 0) },
+        }
+    }
+    if let Err(status) = boot_services.free_pages(base as usize, pages) {
+        log::error!(target: "memory_scrub", "Failed to free scrubbed pages at {base:#x}: {status:?}");
+    }
+    true
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::vec;
+    use patina::boot_services::MockBootServices;
+    use r_efi::efi;
+
+    #[test]
+    fn test_step_walks_regions_within_the_budget() {
+        let mut scrubber = Scrubber::new(vec![(0x1000, 3), (0x10000, 2)]);
+        let mut chunks = vec![];
+
+        scrubber.step(2, |base, pages| {
+            chunks.push((base, pages));
+            true
+        });
+        // The second chunk was allocated since scrubbing started.
+        scrubber.step(2, |base, pages| {
+            chunks.push((base, pages));
+            base != 0x10000
+        });
+        assert_eq!(chunks, [(0x1000, 2), (0x3000, 1), (0x10000, 1)]);
+        assert_eq!(scrubber.progress(), ScrubProgress { scrubbed_pages: 3, skipped_pages: 1, total_pages: 5 });
+        assert!(!scrubber.progress().is_complete());
+
+        scrubber.step(8, |_, _| true);
+        assert!(scrubber.progress().is_complete());
+        scrubber.step(8, |_, _| panic!("all regions were scrubbed"));
+    }
+
+    #[test]
+    fn test_scrub_pages_allocates_the_chunk_while_it_is_scrubbed() {
+        let mut memory = vec![0xA5u64; 2 * UEFI_PAGE_SIZE / size_of::<u64>()];
+        let base = memory.as_mut_ptr() as u64;
+
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_allocate_pages()
+            .withf(move |alloc_type, _, pages| *alloc_type == AllocType::Address(base as usize) && *pages == 2)
+            .times(2)
+            .returning(move |_, _, _| Ok(base as usize));
+        boot_services.expect_free_pages().times(2).returning(|_, _| Ok(()));
+
+        assert!(scrub_pages(&boot_services, base, 2, ScrubMode::Touch));
+        assert!(memory.iter().all(|&word| word == 0xA5));
+        assert!(scrub_pages(&boot_services, base, 2, ScrubMode::Zero));
+        assert!(memory.iter().all(|&word| word == 0));
+
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pages().returning(|_, _, _| Err(efi::Status::NOT_FOUND));
+        assert!(!scrub_pages(&boot_services, base, 2, ScrubMode::Zero));
+    }
+}
@@ -0,0 +1,34 @@
+//! Memory Scrub Progress Service
+//!
+//! Lets other components, such as a boot manager deciding whether to wait, see how far scrubbing has progressed.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+use patina::component::service::IntoService;
+use spin::Mutex;
+
+use crate::scrubber::{ScrubProgress, Scrubber};
+
+/// Memory Scrub Progress Service
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MemoryScrubProgress {
+    /// Returns the progress of scrubbing the free memory.
+    fn progress(&self) -> ScrubProgress;
+}
+
+/// The [`MemoryScrubProgress`] service produced by the [`MemoryScrubber`](crate::component::MemoryScrubber) component.
+#[derive(IntoService)]
+#[service(dyn MemoryScrubProgress)]
+pub struct ScrubProgressReporter(pub(crate) &'static Mutex<Scrubber>);
+
+impl MemoryScrubProgress for ScrubProgressReporter {
+    fn progress(&self) -> ScrubProgress {
+        self.0.lock().progress()
+    }
+}
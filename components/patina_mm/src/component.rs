@@ -2,7 +2,8 @@
 //!
 //! This module provides components for interacting with MM from the DXE environment. These components ultimately do
 //! so through the `SwmMmiTrigger` service which is installed by the `SwMmiManager` component. The `Communicator`
-//! component leverages the `SwmMmiTrigger` service to exchange messages with MM.
+//! component leverages the `SwmMmiTrigger` service to exchange messages with MM. The `tseg` components produce the
+//! `MmAccess` and `MmControl` services for chipsets that place MMRAM in TSEG.
 //!
 //! ## License
 //!
@@ -12,3 +13,4 @@
 //!
 pub mod communicator;
 pub mod sw_mmi_manager;
+pub mod tseg;
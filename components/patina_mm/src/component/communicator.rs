@@ -1,6 +1,8 @@
 //! Management Mode (MM) Communicator Service
 //!
-//! Provides a MM communication service that can be used to send and receive messages to MM handlers.
+//! Provides a MM communication service that can be used to send and receive messages to MM handlers. The number of
+//! calls and the round-trip latency of the communications are recorded by recipient GUID and available through the
+//! `MmHandlerProfile` service.
 //!
 //! ## Logging
 //!
@@ -14,6 +16,8 @@
 //!
 use crate::config::{CommunicateBuffer, EfiMmCommunicateHeader, MmCommunicationConfiguration};
 use crate::service::SwMmiTrigger;
+use crate::service::mm_handler_profile::{MmHandlerProfile, MmHandlerProfileEntry, MmHandlerProfileReport};
+use mu_rust_helpers::perf_timer::{Arch, ArchFunctionality};
 use patina::Guid;
 use patina::component::{
     IntoComponent, Storage,
@@ -24,6 +28,7 @@ use alloc::{boxed::Box, vec::Vec};
 
 use core::cell::RefCell;
 use core::fmt::{self, Debug};
+use r_efi::efi;
 
 #[cfg(any(test, feature = "mockall"))]
 use mockall::automock;
@@ -85,6 +90,8 @@ pub enum Status {
     CommBufferNotFound,
     /// The specified communication buffer is too small for the operation.
     CommBufferTooSmall,
+    /// The communication buffer is not in runtime memory, so it cannot be used after ExitBootServices.
+    CommBufferNotRuntime,
     /// An error occurred while initializing the communication buffer contents.
     CommBufferInitError,
     /// The given data buffer is empty or invalid.
@@ -144,21 +151,22 @@ pub trait MmCommunication {
 /// Allows sending messages via a communication ("comm") buffer and receiving responses from the MM handler where
 /// the response is stored in the same buffer.
 #[derive(IntoComponent, IntoService)]
-#[service(dyn MmCommunication)]
+#[service(dyn MmCommunication, dyn MmHandlerProfile)]
 pub struct MmCommunicator {
     comm_buffers: RefCell<Vec<CommunicateBuffer>>,
     mm_executor: Option<Box<dyn MmExecutor>>,
+    profile: RefCell<Vec<MmHandlerProfileEntry>>,
 }
 
 impl MmCommunicator {
     /// Create a new `MmCommunicator` instance.
     pub fn new() -> Self {
-        Self { comm_buffers: RefCell::new(Vec::new()), mm_executor: None }
+        Self { comm_buffers: RefCell::new(Vec::new()), mm_executor: None, profile: RefCell::new(Vec::new()) }
     }
 
     /// Create a new `MmCommunicator` instance with a custom MM executor (for testing).
     pub fn with_executor(executor: Box<dyn MmExecutor>) -> Self {
-        Self { comm_buffers: RefCell::new(Vec::new()), mm_executor: Some(executor) }
+        Self { comm_buffers: RefCell::new(Vec::new()), mm_executor: Some(executor), profile: RefCell::new(Vec::new()) }
     }
 
     /// Set communication buffers for testing purposes.
@@ -192,6 +200,19 @@ impl MmCommunicator {
 
         Ok(())
     }
+
+    fn record_profile(&self, recipient: efi::Guid, ticks: u64, success: bool) {
+        let mut profile = self.profile.borrow_mut();
+        let index = match profile.iter().position(|entry| entry.recipient == recipient) {
+            Some(index) => index,
+            None => {
+                profile.push(MmHandlerProfileEntry::new(recipient));
+                profile.len() - 1
+            }
+        };
+        profile[index].record(ticks, success);
+        log::trace!(target: "mm_comm", "MM communication to {:?} took {} ticks (call {})", recipient, ticks, profile[index].count);
+    }
 }
 
 impl Debug for MmCommunicator {
@@ -256,7 +277,10 @@ impl MmCommunication for MmCommunicator {
         log::trace!(target: "mm_comm", "Comm buffer before request: {:?}", comm_buffer);
 
         log::debug!(target: "mm_comm", "Executing MM communication");
-        mm_executor.execute_mm(comm_buffer)?;
+        let start = Arch::cpu_count();
+        let result = mm_executor.execute_mm(comm_buffer);
+        self.record_profile(recipient.to_efi_guid(), Arch::cpu_count().wrapping_sub(start), result.is_ok());
+        result?;
 
         log::trace!(target: "mm_comm", "MM communication completed successfully, retrieving response");
         let response = comm_buffer.get_message().map_err(|_| {
@@ -269,6 +293,16 @@ impl MmCommunication for MmCommunicator {
     }
 }
 
+impl MmHandlerProfile for MmCommunicator {
+    fn profile(&self) -> MmHandlerProfileReport {
+        MmHandlerProfileReport { entries: self.profile.borrow().clone(), frequency: Arch::perf_frequency() }
+    }
+
+    fn reset(&self) {
+        self.profile.borrow_mut().clear();
+    }
+}
+
 impl Default for MmCommunicator {
     fn default() -> Self {
         Self::new()
@@ -362,6 +396,7 @@ mod tests {
             MmCommunicator {
                 comm_buffers: RefCell::new(vec![CommunicateBuffer::new(Pin::new(buffer), 0)]),
                 mm_executor: Some(Box::new($mock_executor)),
+                profile: RefCell::new(Vec::new()),
             }
         }};
     }
@@ -370,7 +405,11 @@ mod tests {
         buffers: Vec<CommunicateBuffer>,
         executor: Box<dyn MmExecutor>,
     ) -> MmCommunicator {
-        MmCommunicator { comm_buffers: RefCell::new(buffers), mm_executor: Some(executor) }
+        MmCommunicator {
+            comm_buffers: RefCell::new(buffers),
+            mm_executor: Some(executor),
+            profile: RefCell::new(Vec::new()),
+        }
     }
 
     #[test]
@@ -390,8 +429,11 @@ mod tests {
         let mut mock_executor = MockMmExecutor::new();
         mock_executor.expect_execute_mm().never();
 
-        let communicator =
-            MmCommunicator { comm_buffers: RefCell::new(vec![]), mm_executor: Some(Box::new(mock_executor)) };
+        let communicator = MmCommunicator {
+            comm_buffers: RefCell::new(vec![]),
+            mm_executor: Some(Box::new(mock_executor)),
+            profile: RefCell::new(Vec::new()),
+        };
         let result = communicator.communicate(0, &TEST_DATA, test_recipient());
         assert_eq!(result, Err(Status::NoCommBuffer));
     }
@@ -411,6 +453,7 @@ mod tests {
         let communicator = MmCommunicator {
             comm_buffers: RefCell::new(vec![CommunicateBuffer::new(Pin::new(Box::leak(Box::new([0u8; 1024]))), 0)]),
             mm_executor: None,
+            profile: RefCell::new(Vec::new()),
         };
         let result = communicator.communicate(0, &TEST_DATA, test_recipient());
         assert_eq!(result, Err(Status::SwMmiServiceNotAvailable));
@@ -476,6 +519,31 @@ mod tests {
         assert_eq!(result, Err(Status::SwMmiFailed));
     }
 
+    #[test]
+    fn test_communicate_records_profile() {
+        let mut mock_executor = MockMmExecutor::new();
+        let mut calls = 0;
+        mock_executor.expect_execute_mm().times(3).returning(move |_| {
+            calls += 1;
+            if calls == 3 { Err(Status::SwMmiFailed) } else { Ok(()) }
+        });
+
+        let communicator = get_test_communicator!(1024, mock_executor);
+        for _ in 0..3 {
+            let _ = communicator.communicate(0, &TEST_DATA, test_recipient());
+        }
+        // Communications that never reach MM are not recorded.
+        let _ = communicator.communicate(0, &[], test_recipient());
+
+        let report = MmHandlerProfile::profile(&communicator);
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[0].recipient, TEST_RECIPIENT);
+        assert_eq!((report.entries[0].count, report.entries[0].failures), (3, 1));
+
+        MmHandlerProfile::reset(&communicator);
+        assert!(MmHandlerProfile::profile(&communicator).entries.is_empty());
+    }
+
     #[test]
     fn test_communicate_with_multiple_buffers() {
         // Create multiple buffers with different IDs
@@ -0,0 +1,551 @@
+//! TSEG Management Mode (MM) Components
+//!
+//! Reference implementations of the `MmAccess` and `MmControl` services for x86 chipsets that place MMRAM in the Top
+//! of Memory Segment (TSEG), so that DXE-side MM infrastructure does not need the C `PiSmmAccess` and `PiSmmControl`
+//! drivers. The register layout is that of the Intel Q35 memory controller hub (MCH) and the ICH9 I/O controller hub,
+//! as emulated by QEMU:
+//!
+//! - TSEG spans from the TSEG memory base (`TSEGMB`) to the top of low usable DRAM (`TOLUD`) of the MCH.
+//! - TSEG is closed while `T_EN` is set in `ESMRAMC`, and `D_LCK` in `SMRAM` locks it.
+//! - Software MMIs are generated by writing the APM control port, and are enabled and reported by the `SMI_EN` and
+//!   `SMI_STS` registers at the ACPI base of the ICH9.
+//!
+//! Platforms with a different chipset can use these components as a template for their own implementations.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for these components using the `mm_tseg` log target.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use crate::config::{AcpiBase, MmCommunicationConfiguration, MmiPort};
+use crate::service::mm_access::{MMRAM_CACHEABLE, MMRAM_CLOSED, MMRAM_LOCKED, MMRAM_OPEN, MmAccess, MmramDescriptor};
+use crate::service::mm_control::MmControl;
+use patina::component::{
+    IntoComponent,
+    params::{Commands, Config},
+    service::IntoService,
+};
+use patina::error::EfiError;
+
+#[cfg(any(feature = "doc", all(target_os = "uefi", target_arch = "x86_64")))]
+use x86_64::instructions::port::Port;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+// MCH registers in the PCI configuration space of the host bridge.
+const MCH_SMRAM: u8 = 0x9D;
+const MCH_SMRAM_D_LCK: u8 = 1 << 4;
+const MCH_ESMRAMC: u8 = 0x9E;
+const MCH_ESMRAMC_T_EN: u8 = 1 << 0;
+const MCH_TSEGMB: u8 = 0xAC;
+const MCH_TSEGMB_MASK: u32 = 0xFFF0_0000;
+const MCH_TOLUD: u8 = 0xB0;
+const MCH_TOLUD_MASK: u32 = 0xFFF0;
+
+// ICH9 power management registers, relative to the ACPI base.
+const ICH9_SMI_EN: u16 = 0x30;
+const ICH9_SMI_EN_GBL_SMI_EN: u32 = 1 << 0;
+const ICH9_SMI_EN_EOS: u32 = 1 << 1;
+const ICH9_SMI_EN_APMC_EN: u32 = 1 << 5;
+const ICH9_SMI_STS: u16 = 0x34;
+const ICH9_SMI_STS_APM_STS: u32 = 1 << 5;
+
+/// Chipset Register Access
+///
+/// Abstracts the register accesses of the TSEG components so testing can be performed without touching the
+/// hardware.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait ChipsetRegisters {
+    /// Reads the 8-bit register at `offset` in the PCI configuration space of the host bridge.
+    fn read_config8(&self, offset: u8) -> u8;
+    /// Writes the 8-bit register at `offset` in the PCI configuration space of the host bridge.
+    fn write_config8(&self, offset: u8, value: u8);
+    /// Reads the 32-bit register at `offset` in the PCI configuration space of the host bridge.
+    fn read_config32(&self, offset: u8) -> u32;
+    /// Writes an 8-bit value to an I/O port.
+    fn write_io8(&self, port: u16, value: u8);
+    /// Reads a 32-bit value from an I/O port.
+    fn read_io32(&self, port: u16) -> u32;
+    /// Writes a 32-bit value to an I/O port.
+    fn write_io32(&self, port: u16, value: u32);
+    /// Reads a 32-bit memory-mapped register.
+    fn read_mmio32(&self, address: usize) -> u32;
+    /// Writes a 32-bit memory-mapped register.
+    fn write_mmio32(&self, address: usize, value: u32);
+}
+
+/// Chipset register access through x86 I/O ports and memory-mapped IO.
+///
+/// The PCI configuration space of the host bridge (bus 0, device 0, function 0) is accessed through the legacy
+/// `0xCF8`/`0xCFC` configuration mechanism. Register accesses are skipped, and reads return 0, when not built for an
+/// x86_64 UEFI target.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoPortChipsetRegisters;
+
+#[cfg(any(feature = "doc", all(target_os = "uefi", target_arch = "x86_64")))]
+impl IoPortChipsetRegisters {
+    const CONFIG_ADDRESS: u16 = 0xCF8;
+    const CONFIG_DATA: u16 = 0xCFC;
+
+    fn select(offset: u8) {
+        // Enable bit, bus 0, device 0, function 0, and the dword containing the register.
+        unsafe { Port::<u32>::new(Self::CONFIG_ADDRESS).write(0x8000_0000 | (offset as u32 & 0xFC)) };
+    }
+}
+
+#[cfg(any(feature = "doc", all(target_os = "uefi", target_arch = "x86_64")))]
+impl ChipsetRegisters for IoPortChipsetRegisters {
+    fn read_config8(&self, offset: u8) -> u8 {
+        Self::select(offset);
+        unsafe { Port::<u8>::new(Self::CONFIG_DATA + (offset as u16 & 0x3)).read() }
+    }
+
+    fn write_config8(&self, offset: u8, value: u8) {
+        Self::select(offset);
+        unsafe { Port::<u8>::new(Self::CONFIG_DATA + (offset as u16 & 0x3)).write(value) }
+    }
+
+    fn read_config32(&self, offset: u8) -> u32 {
+        Self::select(offset);
+        unsafe { Port::<u32>::new(Self::CONFIG_DATA).read() }
+    }
+
+    fn write_io8(&self, port: u16, value: u8) {
+        unsafe { Port::<u8>::new(port).write(value) }
+    }
+
+    fn read_io32(&self, port: u16) -> u32 {
+        unsafe { Port::<u32>::new(port).read() }
+    }
+
+    fn write_io32(&self, port: u16, value: u32) {
+        unsafe { Port::<u32>::new(port).write(value) }
+    }
+
+    fn read_mmio32(&self, address: usize) -> u32 {
+        unsafe { core::ptr::read_volatile(address as *const u32) }
+    }
+
+    fn write_mmio32(&self, address: usize, value: u32) {
+        unsafe { core::ptr::write_volatile(address as *mut u32, value) }
+    }
+}
+
+#[cfg(not(any(feature = "doc", all(target_os = "uefi", target_arch = "x86_64"))))]
+#[coverage(off)]
+impl ChipsetRegisters for IoPortChipsetRegisters {
+    fn read_config8(&self, _offset: u8) -> u8 {
+        0
+    }
+
+    fn write_config8(&self, _offset: u8, _value: u8) {}
+
+    fn read_config32(&self, _offset: u8) -> u32 {
+        0
+    }
+
+    fn write_io8(&self, _port: u16, _value: u8) {}
+
+    fn read_io32(&self, _port: u16) -> u32 {
+        0
+    }
+
+    fn write_io32(&self, _port: u16, _value: u32) {}
+
+    fn read_mmio32(&self, _address: usize) -> u32 {
+        0
+    }
+
+    fn write_mmio32(&self, _address: usize, _value: u32) {}
+}
+
+/// A component that provides the `MmAccess` service for MMRAM in TSEG.
+#[derive(IntoComponent, IntoService)]
+#[service(dyn MmAccess)]
+pub struct TsegMmAccess {
+    registers: Box<dyn ChipsetRegisters>,
+}
+
+impl TsegMmAccess {
+    /// Create a new `TsegMmAccess` instance that accesses the chipset registers through I/O ports.
+    pub fn new() -> Self {
+        Self::with_registers(Box::new(IoPortChipsetRegisters))
+    }
+
+    /// Create a new `TsegMmAccess` instance with custom register access (for testing).
+    pub fn with_registers(registers: Box<dyn ChipsetRegisters>) -> Self {
+        Self { registers }
+    }
+
+    fn entry_point(self, mut commands: Commands) -> patina::error::Result<()> {
+        log::info!(target: "mm_tseg", "Initializing TsegMmAccess...");
+        for region in self.regions() {
+            log::debug!(target: "mm_tseg", "MMRAM region: {:#X}-{:#X}, state={:#X}",
+                region.physical_start, region.physical_start + region.physical_size, region.region_state);
+        }
+
+        commands.add_service(self);
+        log::info!(target: "mm_tseg", "TsegMmAccess service registered and ready");
+
+        Ok(())
+    }
+}
+
+impl Default for TsegMmAccess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MmAccess for TsegMmAccess {
+    fn open(&self) -> patina::error::Result<()> {
+        if self.is_locked() {
+            log::error!(target: "mm_tseg", "Cannot open TSEG, it is locked");
+            return Err(EfiError::AccessDenied);
+        }
+
+        let esmramc = self.registers.read_config8(MCH_ESMRAMC);
+        self.registers.write_config8(MCH_ESMRAMC, esmramc & !MCH_ESMRAMC_T_EN);
+        if !self.is_open() {
+            log::error!(target: "mm_tseg", "Failed to open TSEG");
+            return Err(EfiError::DeviceError);
+        }
+        log::trace!(target: "mm_tseg", "TSEG opened");
+        Ok(())
+    }
+
+    fn close(&self) -> patina::error::Result<()> {
+        if !self.is_open() {
+            return Ok(());
+        }
+
+        let esmramc = self.registers.read_config8(MCH_ESMRAMC);
+        self.registers.write_config8(MCH_ESMRAMC, esmramc | MCH_ESMRAMC_T_EN);
+        if self.is_open() {
+            log::error!(target: "mm_tseg", "Failed to close TSEG");
+            return Err(EfiError::DeviceError);
+        }
+        log::trace!(target: "mm_tseg", "TSEG closed");
+        Ok(())
+    }
+
+    fn lock(&self) -> patina::error::Result<()> {
+        if self.is_open() {
+            log::error!(target: "mm_tseg", "Cannot lock TSEG while it is open");
+            return Err(EfiError::DeviceError);
+        }
+
+        let smram = self.registers.read_config8(MCH_SMRAM);
+        self.registers.write_config8(MCH_SMRAM, smram | MCH_SMRAM_D_LCK);
+        if !self.is_locked() {
+            log::error!(target: "mm_tseg", "Failed to lock TSEG");
+            return Err(EfiError::DeviceError);
+        }
+        log::trace!(target: "mm_tseg", "TSEG locked");
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.registers.read_config8(MCH_ESMRAMC) & MCH_ESMRAMC_T_EN == 0
+    }
+
+    fn is_locked(&self) -> bool {
+        self.registers.read_config8(MCH_SMRAM) & MCH_SMRAM_D_LCK != 0
+    }
+
+    fn regions(&self) -> Vec<MmramDescriptor> {
+        let base = (self.registers.read_config32(MCH_TSEGMB) & MCH_TSEGMB_MASK) as u64;
+        let top = ((self.registers.read_config32(MCH_TOLUD) & MCH_TOLUD_MASK) as u64) << 16;
+        if base == 0 || base >= top {
+            log::warn!(target: "mm_tseg", "TSEG is not configured: TSEGMB={:#X}, TOLUD={:#X}", base, top);
+            return Vec::new();
+        }
+
+        let mut region_state = MMRAM_CACHEABLE;
+        region_state |= if self.is_open() { MMRAM_OPEN } else { MMRAM_CLOSED };
+        if self.is_locked() {
+            region_state |= MMRAM_LOCKED;
+        }
+        vec![MmramDescriptor { physical_start: base, cpu_start: base, physical_size: top - base, region_state }]
+    }
+}
+
+/// A component that provides the `MmControl` service for the APM software MMI source of the chipset.
+///
+/// Software MMIs are enabled when the component is dispatched, using the ACPI base and the MMI ports from the
+/// `MmCommunicationConfiguration`.
+#[derive(IntoComponent, IntoService)]
+#[service(dyn MmControl)]
+pub struct TsegMmControl {
+    registers: Box<dyn ChipsetRegisters>,
+    acpi_base: AcpiBase,
+    cmd_port: u16,
+    data_port: u16,
+}
+
+impl TsegMmControl {
+    /// Create a new `TsegMmControl` instance that accesses the chipset registers through I/O ports.
+    pub fn new() -> Self {
+        Self::with_registers(Box::new(IoPortChipsetRegisters))
+    }
+
+    /// Create a new `TsegMmControl` instance with custom register access (for testing).
+    pub fn with_registers(registers: Box<dyn ChipsetRegisters>) -> Self {
+        Self { registers, acpi_base: AcpiBase::Io(0), cmd_port: 0, data_port: 0 }
+    }
+
+    fn entry_point(
+        mut self,
+        config: Config<MmCommunicationConfiguration>,
+        mut commands: Commands,
+    ) -> patina::error::Result<()> {
+        log::info!(target: "mm_tseg", "Initializing TsegMmControl...");
+
+        if config.acpi_base.get_io_value() == 0 && config.acpi_base.get_mmio_value() == 0 {
+            log::error!(target: "mm_tseg", "The ACPI base is not set in the MM configuration");
+            return Err(EfiError::InvalidParameter);
+        }
+        let (MmiPort::Smi(cmd_port), MmiPort::Smi(data_port)) = (config.cmd_port, config.data_port) else {
+            log::error!(target: "mm_tseg", "SMC MMI ports are not supported: cmd_port: {:?}, data_port: {:?}",
+                config.cmd_port, config.data_port);
+            return Err(EfiError::Unsupported);
+        };
+        self.acpi_base = config.acpi_base;
+        self.cmd_port = cmd_port;
+        self.data_port = data_port;
+
+        self.enable()?;
+
+        commands.add_service(self);
+        log::info!(target: "mm_tseg", "TsegMmControl service registered and ready");
+
+        Ok(())
+    }
+
+    fn enable(&self) -> patina::error::Result<()> {
+        let smi_en = self.read_acpi32(ICH9_SMI_EN);
+        self.write_acpi32(ICH9_SMI_EN, smi_en | ICH9_SMI_EN_GBL_SMI_EN | ICH9_SMI_EN_APMC_EN);
+
+        let enabled = ICH9_SMI_EN_GBL_SMI_EN | ICH9_SMI_EN_APMC_EN;
+        if self.read_acpi32(ICH9_SMI_EN) & enabled != enabled {
+            log::error!(target: "mm_tseg", "Failed to enable software MMIs");
+            return Err(EfiError::DeviceError);
+        }
+        log::debug!(target: "mm_tseg", "Software MMIs enabled");
+        Ok(())
+    }
+
+    fn read_acpi32(&self, offset: u16) -> u32 {
+        match self.acpi_base {
+            AcpiBase::Io(port) => self.registers.read_io32(port + offset),
+            AcpiBase::Mmio(address) => self.registers.read_mmio32(address + offset as usize),
+        }
+    }
+
+    fn write_acpi32(&self, offset: u16, value: u32) {
+        match self.acpi_base {
+            AcpiBase::Io(port) => self.registers.write_io32(port + offset, value),
+            AcpiBase::Mmio(address) => self.registers.write_mmio32(address + offset as usize, value),
+        }
+    }
+}
+
+impl Default for TsegMmControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl MmControl for TsegMmControl {
+    unsafe fn trigger(&self, command: u8, data: u8) -> patina::error::Result<()> {
+        log::debug!(target: "mm_tseg", "Triggering SW MMI with command=0x{:02X}, data=0x{:02X}", command, data);
+
+        // The MMI is generated by the write to the command port, so the data must be in place first.
+        self.registers.write_io8(self.data_port, data);
+        self.registers.write_io8(self.cmd_port, command);
+        Ok(())
+    }
+
+    fn is_pending(&self) -> bool {
+        self.read_acpi32(ICH9_SMI_STS) & ICH9_SMI_STS_APM_STS != 0
+    }
+
+    fn clear(&self) -> patina::error::Result<()> {
+        // APM_STS is cleared by writing 1, and setting EOS allows the next MMI to be generated.
+        self.write_acpi32(ICH9_SMI_STS, ICH9_SMI_STS_APM_STS);
+        let smi_en = self.read_acpi32(ICH9_SMI_EN);
+        self.write_acpi32(ICH9_SMI_EN, smi_en | ICH9_SMI_EN_EOS);
+
+        if self.is_pending() {
+            log::error!(target: "mm_tseg", "Failed to clear the pending software MMI");
+            return Err(EfiError::DeviceError);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use patina::component::params::Commands;
+
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    const ACPI_BASE: u16 = 0x600;
+
+    /// Chipset registers for unit tests that emulate the TSEG and software MMI behavior of a Q35 chipset.
+    #[derive(Clone, Default)]
+    struct FakeChipset {
+        config: Rc<RefCell<HashMap<u8, u32>>>,
+        io: Rc<RefCell<HashMap<u16, u32>>>,
+        io8_writes: Rc<RefCell<Vec<(u16, u8)>>>,
+    }
+
+    impl FakeChipset {
+        fn with_tseg(tsegmb: u32, tolud: u32) -> Self {
+            let chipset = Self::default();
+            chipset.config.borrow_mut().insert(MCH_TSEGMB, tsegmb);
+            chipset.config.borrow_mut().insert(MCH_TOLUD, tolud);
+            chipset.write_config8(MCH_ESMRAMC, MCH_ESMRAMC_T_EN);
+            chipset
+        }
+    }
+
+    impl ChipsetRegisters for FakeChipset {
+        fn read_config8(&self, offset: u8) -> u8 {
+            (self.read_config32(offset & 0xFC) >> ((offset & 0x3) * 8)) as u8
+        }
+
+        fn write_config8(&self, offset: u8, value: u8) {
+            // T_EN can no longer be changed once D_LCK is set.
+            if offset == MCH_ESMRAMC && self.read_config8(MCH_SMRAM) & MCH_SMRAM_D_LCK != 0 {
+                return;
+            }
+            let shift = (offset & 0x3) * 8;
+            let mut config = self.config.borrow_mut();
+            let dword = config.entry(offset & 0xFC).or_default();
+            *dword = (*dword & !(0xFF << shift)) | ((value as u32) << shift);
+        }
+
+        fn read_config32(&self, offset: u8) -> u32 {
+            self.config.borrow().get(&offset).copied().unwrap_or_default()
+        }
+
+        fn write_io8(&self, port: u16, value: u8) {
+            self.io8_writes.borrow_mut().push((port, value));
+            if port == 0xB2 {
+                *self.io.borrow_mut().entry(ACPI_BASE + ICH9_SMI_STS).or_default() |= ICH9_SMI_STS_APM_STS;
+            }
+        }
+
+        fn read_io32(&self, port: u16) -> u32 {
+            self.io.borrow().get(&port).copied().unwrap_or_default()
+        }
+
+        fn write_io32(&self, port: u16, value: u32) {
+            let mut io = self.io.borrow_mut();
+            let register = io.entry(port).or_default();
+            // SMI_STS bits are cleared by writing 1.
+            *register = if port == ACPI_BASE + ICH9_SMI_STS { *register & !value } else { value };
+        }
+
+        fn read_mmio32(&self, _address: usize) -> u32 {
+            unimplemented!()
+        }
+
+        fn write_mmio32(&self, _address: usize, _value: u32) {
+            unimplemented!()
+        }
+    }
+
+    fn config(cmd_port: MmiPort) -> MmCommunicationConfiguration {
+        MmCommunicationConfiguration {
+            acpi_base: AcpiBase::Io(ACPI_BASE),
+            cmd_port,
+            data_port: MmiPort::Smi(0xB3),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tseg_mm_access_open_close_lock() {
+        let chipset = FakeChipset::with_tseg(0x7F00_0000, 0x8000);
+        let mm_access = TsegMmAccess::with_registers(Box::new(chipset.clone()));
+
+        assert!(!mm_access.is_open());
+        assert_eq!(
+            mm_access.regions(),
+            [MmramDescriptor {
+                physical_start: 0x7F00_0000,
+                cpu_start: 0x7F00_0000,
+                physical_size: 0x100_0000,
+                region_state: MMRAM_CLOSED | MMRAM_CACHEABLE
+            }]
+        );
+
+        assert!(mm_access.open().is_ok());
+        assert!(mm_access.regions()[0].is_open());
+        assert_eq!(mm_access.lock(), Err(EfiError::DeviceError));
+
+        assert!(mm_access.close().is_ok());
+        assert!(mm_access.lock().is_ok());
+        assert!(mm_access.regions()[0].is_locked());
+        assert_eq!(mm_access.open(), Err(EfiError::AccessDenied));
+        assert!(!mm_access.is_open());
+    }
+
+    #[test]
+    fn test_tseg_mm_access_without_tseg() {
+        let mm_access = TsegMmAccess::with_registers(Box::new(FakeChipset::with_tseg(0, 0x8000)));
+        assert!(mm_access.regions().is_empty());
+        assert!(mm_access.entry_point(Commands::mock()).is_ok());
+    }
+
+    #[test]
+    fn test_tseg_mm_control_trigger_and_clear() {
+        let chipset = FakeChipset::default();
+        let mut mm_control = TsegMmControl::with_registers(Box::new(chipset.clone()));
+        mm_control.acpi_base = AcpiBase::Io(ACPI_BASE);
+        (mm_control.cmd_port, mm_control.data_port) = (0xB2, 0xB3);
+
+        assert!(mm_control.enable().is_ok());
+        assert_eq!(chipset.read_io32(ACPI_BASE + ICH9_SMI_EN), ICH9_SMI_EN_GBL_SMI_EN | ICH9_SMI_EN_APMC_EN);
+
+        assert!(!mm_control.is_pending());
+        assert!(unsafe { mm_control.trigger(0xFF, 0x01) }.is_ok());
+        assert_eq!(*chipset.io8_writes.borrow(), [(0xB3, 0x01), (0xB2, 0xFF)]);
+        assert!(mm_control.is_pending());
+
+        assert!(mm_control.clear().is_ok());
+        assert!(!mm_control.is_pending());
+        assert_ne!(chipset.read_io32(ACPI_BASE + ICH9_SMI_EN) & ICH9_SMI_EN_EOS, 0);
+    }
+
+    #[test]
+    fn test_tseg_mm_control_entry_point() {
+        let mm_control = TsegMmControl::with_registers(Box::new(FakeChipset::default()));
+        assert!(mm_control.entry_point(Config::mock(config(MmiPort::Smi(0xB2))), Commands::mock()).is_ok());
+
+        let mm_control = TsegMmControl::with_registers(Box::new(FakeChipset::default()));
+        assert_eq!(
+            mm_control.entry_point(Config::mock(config(MmiPort::Smc(0x8400_0000))), Commands::mock()),
+            Err(EfiError::Unsupported)
+        );
+
+        let mm_control = TsegMmControl::with_registers(Box::new(FakeChipset::default()));
+        assert_eq!(
+            mm_control.entry_point(Config::mock(MmCommunicationConfiguration::default()), Commands::mock()),
+            Err(EfiError::InvalidParameter)
+        );
+    }
+}
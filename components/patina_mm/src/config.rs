@@ -20,12 +20,19 @@
 //!
 extern crate alloc;
 use alloc::vec::Vec;
+use core::ffi::c_void;
 use core::fmt;
 use core::pin::Pin;
 use core::ptr::NonNull;
 
 use patina::Guid;
 use patina::base::UEFI_PAGE_MASK;
+use patina::boot_services::{
+    BootServices,
+    allocation::{AllocType, MemoryType},
+};
+use patina::runtime_services::RuntimeServices;
+use patina::uefi_size_to_pages;
 use r_efi::efi;
 
 /// Management Mode (MM) Configuration
@@ -154,15 +161,33 @@ pub enum CommunicateBufferStatus {
     NotAligned,
     /// Buffer creation failed due to address space validation errors.
     AddressValidationFailed,
+    /// The buffer memory could not be allocated.
+    AllocationFailed,
+    /// The buffer is not in runtime services data memory, so it cannot be used after ExitBootServices.
+    NotRuntime,
+    /// The buffer address could not be converted to a virtual address.
+    AddressConversionFailed,
 }
 
 /// Management Mode (MM) Communicate Buffer
 ///
 /// A buffer used for communication between the MM handler and the caller.
+///
+/// ## Runtime Buffers
+///
+/// A buffer that is used after ExitBootServices must be in runtime services data memory, so that the OS keeps it
+/// mapped, and its address must be converted when the OS switches to virtual addressing. Such a buffer is created with
+/// [CommunicateBuffer::allocate_runtime] or [CommunicateBuffer::from_runtime_region], and converted with
+/// [CommunicateBuffer::convert_to_virtual]. The physical address that MM uses to access the buffer remains available
+/// through [CommunicateBuffer::physical_address].
 #[derive(Clone)]
 pub struct CommunicateBuffer {
     /// Pointer to the buffer in memory.
     buffer: NonNull<[u8]>,
+    /// Physical address of the buffer.
+    physical_address: usize,
+    /// Whether the buffer is in runtime services data memory.
+    runtime: bool,
     /// ID of the buffer.
     id: u8,
     /// Length of the total buffer in bytes.
@@ -189,7 +214,15 @@ impl CommunicateBuffer {
         let ptr: NonNull<[u8]> = NonNull::from_mut(Pin::into_inner(buffer));
 
         log::trace!(target: "mm_comm", "CommunicateBuffer {} created successfully at address {:p}", id, ptr);
-        Self { buffer: ptr, id, length, private_recipient: None, private_message_length: 0 }
+        Self {
+            buffer: ptr,
+            physical_address: ptr.as_ptr().cast::<u8>() as usize,
+            runtime: false,
+            id,
+            length,
+            private_recipient: None,
+            private_message_length: 0,
+        }
     }
 
     /// Returns a reference to the buffer as a slice of bytes.
@@ -296,6 +329,88 @@ impl CommunicateBuffer {
         unsafe { Self::from_raw_parts(ptr, size_bytes, buffer_id) }
     }
 
+    /// Allocates a `CommunicateBuffer` of at least `size_bytes` in runtime services data memory.
+    ///
+    /// The buffer can be used after ExitBootServices. It is never freed.
+    ///
+    /// ## Returns
+    ///
+    /// - `Ok(CommunicateBuffer)` - Successfully allocated buffer
+    /// - `Err(CommunicateBufferStatus)` - The allocation or buffer validation failed
+    pub fn allocate_runtime(
+        boot_services: &impl BootServices,
+        size_bytes: usize,
+        buffer_id: u8,
+    ) -> Result<Self, CommunicateBufferStatus> {
+        let pages = uefi_size_to_pages!(size_bytes);
+        let address = boot_services
+            .allocate_pages(AllocType::AnyPage, MemoryType::RUNTIME_SERVICES_DATA, pages)
+            .map_err(|status| {
+                log::error!(target: "mm_comm", "Failed to allocate runtime buffer {}: pages={}, status={:?}", buffer_id, pages, status);
+                CommunicateBufferStatus::AllocationFailed
+            })?;
+
+        // SAFETY: The pages were just allocated for this buffer and are never freed
+        let mut buffer = unsafe { Self::from_raw_parts(address as *mut u8, size_bytes, buffer_id)? };
+        buffer.runtime = true;
+        Ok(buffer)
+    }
+
+    /// Creates a `CommunicateBuffer` from a firmware-provided memory region that is in runtime services data memory.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must uphold the requirements of [CommunicateBuffer::from_firmware_region], and must ensure that the
+    /// memory region was allocated as runtime services data.
+    pub unsafe fn from_runtime_region(
+        address: u64,
+        size_bytes: usize,
+        buffer_id: u8,
+    ) -> Result<Self, CommunicateBufferStatus> {
+        // SAFETY: Safety is upheld by the caller to this function (the function is marked unsafe)
+        let mut buffer = unsafe { Self::from_firmware_region(address, size_bytes, buffer_id)? };
+        buffer.runtime = true;
+        Ok(buffer)
+    }
+
+    /// Returns whether the buffer is in runtime services data memory, and so can be used after ExitBootServices.
+    pub fn is_runtime(&self) -> bool {
+        self.runtime
+    }
+
+    /// Returns the physical address of the buffer.
+    ///
+    /// This is the address MM uses to access the buffer. It is the same as [CommunicateBuffer::as_ptr] until the
+    /// buffer is converted to a virtual address.
+    pub fn physical_address(&self) -> usize {
+        self.physical_address
+    }
+
+    /// Converts the buffer address to the virtual address assigned by the OS.
+    ///
+    /// ## Safety
+    ///
+    /// Must only be called once, from an `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` notify function. Clones of this buffer
+    /// are not converted, and must not be used after the conversion.
+    pub unsafe fn convert_to_virtual(
+        &mut self,
+        runtime_services: &impl RuntimeServices,
+    ) -> Result<(), CommunicateBufferStatus> {
+        if !self.runtime {
+            log::error!(target: "mm_comm", "Buffer {} is not a runtime buffer and cannot be converted", self.id);
+            return Err(CommunicateBufferStatus::NotRuntime);
+        }
+
+        let mut address = self.as_ptr() as *mut c_void;
+        // SAFETY: The buffer is in runtime memory and the caller ensures a virtual address change is in progress
+        unsafe { runtime_services.convert_pointer(&mut address) }
+            .map_err(|_| CommunicateBufferStatus::AddressConversionFailed)?;
+        let address = NonNull::new(address as *mut u8).ok_or(CommunicateBufferStatus::AddressConversionFailed)?;
+
+        self.buffer = NonNull::slice_from_raw_parts(address, self.length);
+        Ok(())
+    }
+
     /// Returns the length of the buffer.
     pub fn len(&self) -> usize {
         self.length
@@ -526,6 +641,29 @@ impl CommunicateBuffer {
         Ok(message)
     }
 
+    /// Copies the message part of the communicate buffer into `message` and returns the message length.
+    ///
+    /// Unlike [CommunicateBuffer::get_message], this does not allocate, so it can be used after ExitBootServices.
+    pub fn read_message(&self, message: &mut [u8]) -> Result<usize, CommunicateBufferStatus> {
+        self.verify_state_consistency()?;
+
+        let length = self.private_message_length;
+        if Self::MESSAGE_START_OFFSET + length > self.len() {
+            log::error!(target: "mm_comm", "Buffer {} message extends beyond buffer: message_len={}, buffer_len={}",
+                self.id, length, self.len());
+            return Err(CommunicateBufferStatus::TooSmallForMessage);
+        }
+        if length > message.len() {
+            log::error!(target: "mm_comm", "Buffer {} message does not fit: message_len={}, destination_len={}",
+                self.id, length, message.len());
+            return Err(CommunicateBufferStatus::TooSmallForMessage);
+        }
+
+        message[..length]
+            .copy_from_slice(&self.as_slice()[Self::MESSAGE_START_OFFSET..Self::MESSAGE_START_OFFSET + length]);
+        Ok(length)
+    }
+
     /// Returns the header GUID from the current communicate buffer.
     /// This method uses the internal state and verifies consistency with memory.
     ///
@@ -887,6 +1025,47 @@ mod tests {
         assert!(matches!(result, Err(CommunicateBufferStatus::AddressValidationFailed)));
     }
 
+    #[test]
+    fn test_allocate_runtime() {
+        use patina::boot_services::MockBootServices;
+
+        let address = Box::leak(Box::new(AlignedBuffer([0u8; 64]))).0.as_mut_ptr() as usize;
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_allocate_pages()
+            .once()
+            .withf(|alloc_type, memory_type, pages| {
+                matches!(alloc_type, AllocType::AnyPage)
+                    && *memory_type == MemoryType::RUNTIME_SERVICES_DATA
+                    && *pages == 1
+            })
+            .returning(move |_, _, _| Ok(address));
+        boot_services.expect_allocate_pages().returning(|_, _, _| Err(efi::Status::OUT_OF_RESOURCES));
+
+        let mut comm_buffer = CommunicateBuffer::allocate_runtime(&boot_services, 64, 2).unwrap();
+        assert!(comm_buffer.is_runtime());
+        assert_eq!(comm_buffer.physical_address(), address);
+        assert!(matches!(
+            CommunicateBuffer::allocate_runtime(&boot_services, 64, 3),
+            Err(CommunicateBufferStatus::AllocationFailed)
+        ));
+
+        let recipient_guid = Guid::try_from_string("12345678-1234-5678-90AB-CDEF01234567").unwrap();
+        comm_buffer.set_message_info(recipient_guid).unwrap();
+        comm_buffer.set_message(b"MM Handler!").unwrap();
+        let mut message = [0u8; 16];
+        assert_eq!(comm_buffer.read_message(&mut message), Ok(11));
+        assert_eq!(&message[..11], b"MM Handler!");
+        assert_eq!(comm_buffer.read_message(&mut message[..4]), Err(CommunicateBufferStatus::TooSmallForMessage));
+
+        let mut boot_time_buffer = CommunicateBuffer::new(Pin::new(Box::leak(Box::new([0u8; 64]))), 4);
+        let runtime_services = patina::runtime_services::MockRuntimeServices::new();
+        assert_eq!(
+            unsafe { boot_time_buffer.convert_to_virtual(&runtime_services) },
+            Err(CommunicateBufferStatus::NotRuntime)
+        );
+    }
+
     #[test]
     fn test_from_raw_parts_success() {
         use patina::base::UEFI_PAGE_SIZE;
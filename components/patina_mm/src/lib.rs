@@ -140,4 +140,5 @@
 
 pub mod component;
 pub mod config;
+pub mod runtime;
 pub mod service;
@@ -0,0 +1,363 @@
+//! Runtime Management Mode (MM) Communication
+//!
+//! Components, and the services they produce, run in boot services memory, so the `MmCommunication` service cannot be
+//! used once ExitBootServices has been called. OS-present MM communication, such as the runtime variable services,
+//! needs a communicator that is owned by a runtime driver and only uses runtime memory:
+//!
+//! - The communicate buffer must be a runtime buffer, created with [CommunicateBuffer::allocate_runtime] or
+//!   [CommunicateBuffer::from_runtime_region].
+//! - The communicator must be registered for the virtual address change, so that the buffer address is converted when
+//!   the OS switches to virtual addressing.
+//! - MMIs must be triggered without a boot services dependency, such as with [SwMmiPortExecutor].
+//!
+//! The communicator records the number of calls and the round-trip latency of its communications by recipient GUID in
+//! a fixed size [MmHandlerProfileTable], which never allocates. The runtime driver can publish the table with
+//! [RuntimeMmCommunicator::publish_profile], so that OS-present diagnostic tools can find handlers that cause SMI
+//! storms or stall the OS.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use patina::{
+//!     Guid,
+//!     boot_services::StandardBootServices,
+//!     runtime_services::StandardRuntimeServices,
+//! };
+//! use patina_mm::{
+//!     config::{CommunicateBuffer, MmiPort},
+//!     runtime::{RuntimeMmCommunicator, SwMmiPortExecutor},
+//! };
+//!
+//! // A static of the runtime driver, so it remains mapped after ExitBootServices.
+//! static mut COMMUNICATOR: Option<RuntimeMmCommunicator<SwMmiPortExecutor, StandardRuntimeServices>> = None;
+//!
+//! fn init(boot_services: &StandardBootServices, runtime_services: StandardRuntimeServices) {
+//!     let buffer = CommunicateBuffer::allocate_runtime(boot_services, 0x1000, 0).unwrap();
+//!     let executor = SwMmiPortExecutor::new(MmiPort::Smi(0xB2), MmiPort::Smi(0xB3)).unwrap();
+//!     let communicator = RuntimeMmCommunicator::new(buffer, executor, runtime_services).unwrap();
+//!
+//!     // SAFETY: The communicator is stored in a static that is never moved.
+//!     unsafe {
+//!         let communicator = (*&raw mut COMMUNICATOR).insert(communicator);
+//!         communicator.register_virtual_address_change(boot_services).unwrap();
+//!         communicator.publish_profile(boot_services).unwrap();
+//!     }
+//! }
+//!
+//! fn request(recipient: Guid, request: &[u8], response: &mut [u8]) -> usize {
+//!     // SAFETY: Runtime service calls are not reentrant, so the communicator is not borrowed elsewhere.
+//!     let communicator = unsafe { (*&raw mut COMMUNICATOR).as_mut().unwrap() };
+//!     communicator.communicate(request, recipient, response).unwrap_or(0)
+//! }
+//! ```
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this module using the `mm_comm` log target.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use crate::component::communicator::{MmExecutor, Status};
+use crate::config::{CommunicateBuffer, CommunicateBufferStatus, EfiMmCommunicateHeader, MmiPort};
+use crate::service::mm_handler_profile::{MM_HANDLER_PROFILE_TABLE_GUID, MmHandlerProfileTable};
+use core::ffi::c_void;
+use mu_rust_helpers::perf_timer::{Arch, ArchFunctionality};
+use patina::Guid;
+use patina::boot_services::{BootServices, event::EventType, tpl::Tpl};
+use patina::runtime_services::RuntimeServices;
+use r_efi::efi;
+
+#[cfg(any(feature = "doc", all(target_os = "uefi", target_arch = "x86_64")))]
+use x86_64::instructions::port::Port;
+
+/// MM executor that triggers a software MMI by writing the MMI ports directly.
+///
+/// It does not depend on boot services, so it can be used after ExitBootServices. The ports are written with the same
+/// values as the `SwMmiTrigger` service is invoked with by the `MmCommunicator`.
+#[derive(Debug, Clone, Copy)]
+pub struct SwMmiPortExecutor {
+    cmd_port: u16,
+    data_port: u16,
+}
+
+impl SwMmiPortExecutor {
+    /// The value written to the command port to request MM communication.
+    const CMD_VALUE: u8 = 0xFF;
+    /// The value written to the data port to request MM communication.
+    const DATA_VALUE: u8 = 0x00;
+
+    /// Create a new `SwMmiPortExecutor` for the given command and data ports.
+    ///
+    /// Returns `None` if either port is not an SMI port, as SMC communication is not supported.
+    pub fn new(cmd_port: MmiPort, data_port: MmiPort) -> Option<Self> {
+        match (cmd_port, data_port) {
+            (MmiPort::Smi(cmd_port), MmiPort::Smi(data_port)) => Some(Self { cmd_port, data_port }),
+            _ => {
+                log::error!(target: "mm_comm", "SMC MMI ports are not supported: cmd_port: {:?}, data_port: {:?}", cmd_port, data_port);
+                None
+            }
+        }
+    }
+}
+
+impl MmExecutor for SwMmiPortExecutor {
+    #[coverage(off)]
+    fn execute_mm(&self, _comm_buffer: &mut CommunicateBuffer) -> Result<(), Status> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(feature = "doc", all(target_os = "uefi", target_arch = "x86_64")))] {
+                // The MMI is generated by the write to the command port, so the data must be in place first.
+                unsafe {
+                    Port::new(self.data_port).write(Self::DATA_VALUE);
+                    Port::new(self.cmd_port).write(Self::CMD_VALUE);
+                }
+            } else {
+                log::trace!(target: "mm_comm", "SMI port writes skipped (not on target platform): {:#X}={:#X}, {:#X}={:#X}",
+                    self.data_port, Self::DATA_VALUE, self.cmd_port, Self::CMD_VALUE);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of recipient GUIDs that the profile of a [RuntimeMmCommunicator] records.
+pub const RUNTIME_PROFILE_RECIPIENTS: usize = 16;
+
+/// The profile of the communications made through a [RuntimeMmCommunicator].
+pub type RuntimeMmHandlerProfile = MmHandlerProfileTable<RUNTIME_PROFILE_RECIPIENTS>;
+
+/// MM Communicator for use after ExitBootServices
+///
+/// Exchanges messages with MM handlers through a runtime communicate buffer. It does not allocate, so it only depends
+/// on the memory it is stored in, the buffer, and the executor.
+pub struct RuntimeMmCommunicator<E: MmExecutor, R: RuntimeServices> {
+    comm_buffer: CommunicateBuffer,
+    mm_executor: E,
+    runtime_services: R,
+    profile: RuntimeMmHandlerProfile,
+}
+
+impl<E: MmExecutor + 'static, R: RuntimeServices + 'static> RuntimeMmCommunicator<E, R> {
+    /// Create a new `RuntimeMmCommunicator` instance.
+    ///
+    /// Returns `Status::CommBufferNotRuntime` if `comm_buffer` is not a runtime buffer. The `mm_executor` must not
+    /// depend on boot services.
+    pub fn new(comm_buffer: CommunicateBuffer, mm_executor: E, runtime_services: R) -> Result<Self, Status> {
+        if !comm_buffer.is_runtime() {
+            log::error!(target: "mm_comm", "Buffer {} is not a runtime buffer: ptr={:p}", comm_buffer.id(), comm_buffer.as_ptr());
+            return Err(Status::CommBufferNotRuntime);
+        }
+        Ok(Self {
+            comm_buffer,
+            mm_executor,
+            runtime_services,
+            profile: RuntimeMmHandlerProfile::new(Arch::perf_frequency()),
+        })
+    }
+
+    /// Returns the communicate buffer used by this communicator.
+    pub fn comm_buffer(&self) -> &CommunicateBuffer {
+        &self.comm_buffer
+    }
+
+    /// Returns the profile of the communications made through this communicator.
+    pub fn profile(&self) -> &RuntimeMmHandlerProfile {
+        &self.profile
+    }
+
+    /// Clears the profile of the communications made through this communicator.
+    pub fn reset_profile(&mut self) {
+        self.profile.reset();
+    }
+
+    /// Publishes the profile as the configuration table with [MM_HANDLER_PROFILE_TABLE_GUID].
+    ///
+    /// ## Safety
+    ///
+    /// The communicator must remain at its current address for the rest of the boot, in memory that is mapped at
+    /// runtime, such as a static of a runtime driver.
+    pub unsafe fn publish_profile(&self, boot_services: &impl BootServices) -> Result<(), efi::Status> {
+        // SAFETY: The table has the layout documented for the GUID, and the caller guarantees it outlives the boot.
+        unsafe {
+            boot_services.install_configuration_table_unchecked(
+                &MM_HANDLER_PROFILE_TABLE_GUID,
+                &self.profile as *const RuntimeMmHandlerProfile as *mut c_void,
+            )
+        }
+    }
+
+    /// Registers the communicator to convert its buffer address when the OS switches to virtual addressing.
+    ///
+    /// ## Safety
+    ///
+    /// The communicator must remain at its current address for the rest of the boot, in memory that is mapped at
+    /// runtime, such as a static of a runtime driver. It must not be borrowed while the OS calls SetVirtualAddressMap.
+    pub unsafe fn register_virtual_address_change(
+        &mut self,
+        boot_services: &impl BootServices,
+    ) -> Result<efi::Event, efi::Status> {
+        boot_services.create_event(
+            EventType::SIGNAL_VIRTUAL_ADDRESS_CHANGE,
+            Tpl::NOTIFY,
+            Some(Self::virtual_address_change),
+            self as *mut Self,
+        )
+    }
+
+    extern "efiapi" fn virtual_address_change(_event: efi::Event, this: *mut Self) {
+        // SAFETY: The communicator was registered with its address, which the caller guarantees is stable and
+        //         not borrowed during SetVirtualAddressMap.
+        let this = unsafe { &mut *this };
+        // SAFETY: This is the virtual address change notify function and the buffer is a runtime buffer.
+        if let Err(status) = unsafe { this.comm_buffer.convert_to_virtual(&this.runtime_services) } {
+            log::error!(target: "mm_comm", "Failed to convert buffer {} to a virtual address: {:?}", this.comm_buffer.id(), status);
+        }
+    }
+
+    /// Sends `data_buffer` to the MM handler registered for `recipient`, and copies the response into `response`.
+    ///
+    /// Returns the length of the response.
+    pub fn communicate(&mut self, data_buffer: &[u8], recipient: Guid, response: &mut [u8]) -> Result<usize, Status> {
+        log::debug!(target: "mm_comm", "Starting runtime MM communication: buffer_id={}, data_size={}, recipient={:?}",
+            self.comm_buffer.id(), data_buffer.len(), recipient);
+
+        if data_buffer.is_empty() {
+            log::warn!(target: "mm_comm", "Invalid data buffer: empty");
+            return Err(Status::InvalidDataBuffer);
+        }
+
+        if self.comm_buffer.len() < EfiMmCommunicateHeader::size() + data_buffer.len() {
+            log::warn!(target: "mm_comm", "Communication buffer too small: available={}, required={}",
+                self.comm_buffer.len(), EfiMmCommunicateHeader::size() + data_buffer.len());
+            return Err(Status::CommBufferTooSmall);
+        }
+
+        let recipient_guid = recipient.to_efi_guid();
+        self.comm_buffer.reset();
+        self.comm_buffer.set_message_info(recipient).map_err(|_| Status::CommBufferInitError)?;
+        self.comm_buffer.set_message(data_buffer).map_err(|_| Status::CommBufferInitError)?;
+
+        let start = Arch::cpu_count();
+        let result = self.mm_executor.execute_mm(&mut self.comm_buffer);
+        self.profile.record(recipient_guid, Arch::cpu_count().wrapping_sub(start), result.is_ok());
+        result?;
+
+        self.comm_buffer.read_message(response).map_err(|status| match status {
+            CommunicateBufferStatus::TooSmallForMessage => Status::CommBufferTooSmall,
+            _ => Status::InvalidResponse,
+        })
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::component::communicator::MockMmExecutor;
+    use patina::boot_services::MockBootServices;
+    use patina::runtime_services::MockRuntimeServices;
+
+    #[repr(align(4096))]
+    struct AlignedBuffer([u8; 0x1000]);
+
+    fn runtime_buffer() -> CommunicateBuffer {
+        let buffer = Box::leak(Box::new(AlignedBuffer([0; 0x1000])));
+        unsafe { CommunicateBuffer::from_runtime_region(buffer.0.as_mut_ptr() as u64, buffer.0.len(), 0) }.unwrap()
+    }
+
+    fn recipient() -> Guid<'static> {
+        Guid::try_from_string("12345678-1234-5678-90AB-CDEF01234567").unwrap()
+    }
+
+    #[test]
+    fn test_runtime_communicator_requires_runtime_buffer() {
+        let buffer = Box::leak(Box::new(AlignedBuffer([0; 0x1000])));
+        let boot_time_buffer =
+            unsafe { CommunicateBuffer::from_firmware_region(buffer.0.as_mut_ptr() as u64, buffer.0.len(), 0) }
+                .unwrap();
+
+        let result = RuntimeMmCommunicator::new(boot_time_buffer, MockMmExecutor::new(), MockRuntimeServices::new());
+        assert!(matches!(result, Err(Status::CommBufferNotRuntime)));
+    }
+
+    #[test]
+    fn test_runtime_communicate() {
+        let mut mm_executor = MockMmExecutor::new();
+        mm_executor.expect_execute_mm().times(2).returning(|comm_buffer| {
+            comm_buffer.reset();
+            comm_buffer.set_message_info(recipient()).unwrap();
+            comm_buffer.set_message(b"MM response").unwrap();
+            Ok(())
+        });
+        let mut communicator =
+            RuntimeMmCommunicator::new(runtime_buffer(), mm_executor, MockRuntimeServices::new()).unwrap();
+
+        let mut response = [0u8; 16];
+        assert_eq!(communicator.communicate(b"Request", recipient(), &mut response), Ok(11));
+        assert_eq!(&response[..11], b"MM response");
+
+        let mut response = [0u8; 4];
+        assert_eq!(communicator.communicate(b"Request", recipient(), &mut response), Err(Status::CommBufferTooSmall));
+        assert_eq!(communicator.communicate(&[], recipient(), &mut response), Err(Status::InvalidDataBuffer));
+
+        // Both communications reached MM, the one with the empty request did not.
+        let entries = communicator.profile().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].recipient, entries[0].count, entries[0].failures), (recipient().to_efi_guid(), 2, 0));
+
+        communicator.reset_profile();
+        assert!(communicator.profile().entries().is_empty());
+    }
+
+    #[test]
+    fn test_runtime_communicator_publishes_profile() {
+        let communicator =
+            RuntimeMmCommunicator::new(runtime_buffer(), MockMmExecutor::new(), MockRuntimeServices::new()).unwrap();
+        let table = communicator.profile() as *const RuntimeMmHandlerProfile as usize;
+
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_install_configuration_table_unchecked()
+            .once()
+            .withf(move |guid, table_ptr| *guid == MM_HANDLER_PROFILE_TABLE_GUID && *table_ptr as usize == table)
+            .returning(|_, _| Ok(()));
+
+        assert!(unsafe { communicator.publish_profile(&boot_services) }.is_ok());
+    }
+
+    #[test]
+    fn test_runtime_communicator_converts_buffer_on_virtual_address_change() {
+        const VIRTUAL_OFFSET: usize = 0x8000_0000_0000;
+
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_convert_pointer().once().returning(|address| {
+            *address = (*address as usize + VIRTUAL_OFFSET) as *mut c_void;
+            Ok(())
+        });
+        let mut communicator =
+            RuntimeMmCommunicator::new(runtime_buffer(), MockMmExecutor::new(), runtime_services).unwrap();
+        let physical_address = communicator.comm_buffer().physical_address();
+
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event::<*mut RuntimeMmCommunicator<MockMmExecutor, MockRuntimeServices>>()
+            .once()
+            .withf_st(|event_type, notify_tpl, notify_function, _| {
+                *event_type == EventType::SIGNAL_VIRTUAL_ADDRESS_CHANGE
+                    && *notify_tpl == Tpl::NOTIFY
+                    && notify_function.is_some()
+            })
+            .returning_st(|_, _, notify_function, context| {
+                // signal the virtual address change.
+                notify_function.unwrap()(core::ptr::null_mut(), context);
+                Ok(1_usize as efi::Event)
+            });
+
+        assert!(unsafe { communicator.register_virtual_address_change(&boot_services) }.is_ok());
+        assert_eq!(communicator.comm_buffer().as_ptr() as usize, physical_address + VIRTUAL_OFFSET);
+        assert_eq!(communicator.comm_buffer().physical_address(), physical_address);
+    }
+}
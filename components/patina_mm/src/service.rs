@@ -7,8 +7,14 @@
 //! Copyright (C) Microsoft Corporation.
 //!
 //! SPDX-License-Identifier: Apache-2.0
+pub mod mm_access;
+pub mod mm_control;
+pub mod mm_handler_profile;
 pub mod platform_mm_control;
 
 pub use crate::component::communicator::MmCommunication;
 pub use crate::component::sw_mmi_manager::SwMmiTrigger;
+pub use mm_access::{MmAccess, MmramDescriptor};
+pub use mm_control::MmControl;
+pub use mm_handler_profile::MmHandlerProfile;
 pub use platform_mm_control::PlatformMmControl;
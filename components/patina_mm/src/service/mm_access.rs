@@ -0,0 +1,92 @@
+//! Management Mode RAM (MMRAM) Access Service Trait
+//!
+//! The DXE-side equivalent of the PI `EFI_MM_ACCESS_PROTOCOL`. MM infrastructure components, such as a MM IPL, use
+//! this service to describe the MMRAM regions of the platform and to open, close, and lock them while the MM
+//! environment is loaded.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+use alloc::vec::Vec;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The MMRAM region is visible outside of MM.
+pub const MMRAM_OPEN: u64 = 0x0000_0001;
+/// The MMRAM region is only visible in MM.
+pub const MMRAM_CLOSED: u64 = 0x0000_0002;
+/// The MMRAM region can no longer be opened until the next reset.
+pub const MMRAM_LOCKED: u64 = 0x0000_0004;
+/// The MMRAM region is cacheable.
+pub const MMRAM_CACHEABLE: u64 = 0x0000_0008;
+/// The MMRAM region is allocated.
+pub const MMRAM_ALLOCATED: u64 = 0x0000_0010;
+/// The MMRAM region needs to be tested before it is used.
+pub const MMRAM_NEEDS_TESTING: u64 = 0x0000_0020;
+/// The MMRAM region needs ECC initialization before it is used.
+pub const MMRAM_NEEDS_ECC_INITIALIZATION: u64 = 0x0000_0040;
+
+/// Management Mode RAM (MMRAM) Region Descriptor
+///
+/// Describes a MMRAM region with the same layout as the PI `EFI_MMRAM_DESCRIPTOR`, so that the regions can be handed
+/// to the MM environment as-is.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MmramDescriptor {
+    /// The physical address of the region.
+    pub physical_start: u64,
+    /// The address of the region as seen by the processor in MM.
+    pub cpu_start: u64,
+    /// The size of the region in bytes.
+    pub physical_size: u64,
+    /// The state of the region, a combination of the `MMRAM_*` flags.
+    pub region_state: u64,
+}
+
+impl MmramDescriptor {
+    /// Returns true if the region is visible outside of MM.
+    pub fn is_open(&self) -> bool {
+        self.region_state & MMRAM_OPEN != 0
+    }
+
+    /// Returns true if the region is locked.
+    pub fn is_locked(&self) -> bool {
+        self.region_state & MMRAM_LOCKED != 0
+    }
+}
+
+/// Management Mode RAM (MMRAM) Access Service
+///
+/// Controls the visibility of MMRAM outside of MM. MMRAM is opened while the MM environment is loaded into it, closed
+/// once it is loaded, and locked before any code that is not trusted runs, so that it can no longer be opened.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MmAccess {
+    /// Opens MMRAM so that it is visible outside of MM.
+    ///
+    /// Returns `EfiError::AccessDenied` if MMRAM is locked, and `EfiError::DeviceError` if MMRAM could not be opened.
+    fn open(&self) -> patina::error::Result<()>;
+
+    /// Closes MMRAM so that it is only visible in MM.
+    ///
+    /// Returns `EfiError::DeviceError` if MMRAM could not be closed.
+    fn close(&self) -> patina::error::Result<()>;
+
+    /// Locks MMRAM so that it cannot be opened until the next reset.
+    ///
+    /// Returns `EfiError::DeviceError` if MMRAM is open or could not be locked.
+    fn lock(&self) -> patina::error::Result<()>;
+
+    /// Returns true if MMRAM is visible outside of MM.
+    fn is_open(&self) -> bool;
+
+    /// Returns true if MMRAM is locked.
+    fn is_locked(&self) -> bool;
+
+    /// Returns the MMRAM regions of the platform, with their current state.
+    fn regions(&self) -> Vec<MmramDescriptor>;
+}
@@ -0,0 +1,40 @@
+//! Management Mode (MM) Control Service Trait
+//!
+//! The DXE-side equivalent of the PI `EFI_MM_CONTROL_PROTOCOL`. MM infrastructure components use this service to
+//! generate software Management Mode Interrupts (MMIs) and to clear them once they have been handled.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Management Mode (MM) Control Service
+///
+/// Generates software MMIs on the platform. Unlike [SwMmiTrigger](crate::service::SwMmiTrigger), which only writes the
+/// MMI ports, an implementation of this service owns the MMI source of the platform: it enables software MMIs before
+/// it is made available, and reports and clears the pending software MMI.
+///
+/// ## Safety
+///
+/// This trait is unsafe because an implementation needs to ensure that software MMIs are enabled and that the MM
+/// environment is ready to handle them before the service is made available.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub unsafe trait MmControl {
+    /// Generates a software MMI with `command` written to the command port and `data` written to the data port.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the MM handlers run with the state of the system at the time of the MMI. It is
+    /// the caller's responsibility to ensure that the system is in a safe state before calling this function.
+    unsafe fn trigger(&self, command: u8, data: u8) -> patina::error::Result<()>;
+
+    /// Returns true if a software MMI has been generated and not yet cleared.
+    fn is_pending(&self) -> bool;
+
+    /// Clears the pending software MMI, so that the next software MMI can be generated.
+    fn clear(&self) -> patina::error::Result<()>;
+}
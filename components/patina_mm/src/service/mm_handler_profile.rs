@@ -0,0 +1,252 @@
+//! Management Mode (MM) Handler Profile Service Trait
+//!
+//! Every MMI stalls all processors, including the OS while it runs, so MM handlers that are called too often (SMI
+//! storms) or that take too long hurt OS latency. The `MmCommunicator` records the number of calls and the round-trip
+//! latency of every MM communication by recipient GUID, and produces this service so that a diagnostic component can
+//! dump the profile to find the chatty or slow handlers.
+//!
+//! MM communication after ExitBootServices goes through a `RuntimeMmCommunicator` instead, which records into a fixed
+//! size [MmHandlerProfileTable] so that profiling never allocates. The runtime driver can publish the table as a
+//! configuration table under [MM_HANDLER_PROFILE_TABLE_GUID], so that OS-present diagnostic tools can dump the profile
+//! of the MMIs raised while the OS runs.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::Service;
+//! use patina_mm::service::MmHandlerProfile;
+//!
+//! fn dump_mm_profile(profile: Service<dyn MmHandlerProfile>) {
+//!     log::info!("{}", profile.profile());
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use patina::Guid;
+use r_efi::efi;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The GUID of the configuration table that holds the [MmHandlerProfileTable] of runtime MM communication.
+pub const MM_HANDLER_PROFILE_TABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0x5c0e2f71, 0x8a3d, 0x4b62, 0x9e, 0x14, &[0x27, 0xd3, 0x6a, 0x90, 0xb1, 0x4f]);
+
+/// The calls made to the MM handlers registered for one recipient GUID.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmHandlerProfileEntry {
+    /// The recipient GUID in the MM communicate header.
+    pub recipient: efi::Guid,
+    /// The number of communications sent to the recipient.
+    pub count: u64,
+    /// The number of communications that failed to trigger MM.
+    pub failures: u64,
+    /// The total round-trip latency of the communications, in performance counter ticks.
+    pub total_ticks: u64,
+    /// The shortest round-trip latency, in performance counter ticks.
+    pub min_ticks: u64,
+    /// The longest round-trip latency, in performance counter ticks.
+    pub max_ticks: u64,
+}
+
+impl MmHandlerProfileEntry {
+    /// Create a new entry for `recipient` with no calls.
+    pub const fn new(recipient: efi::Guid) -> Self {
+        Self { recipient, count: 0, failures: 0, total_ticks: 0, min_ticks: u64::MAX, max_ticks: 0 }
+    }
+
+    /// Records a call with a round-trip latency of `ticks`.
+    pub fn record(&mut self, ticks: u64, success: bool) {
+        self.count += 1;
+        if !success {
+            self.failures += 1;
+        }
+        self.total_ticks = self.total_ticks.saturating_add(ticks);
+        self.min_ticks = self.min_ticks.min(ticks);
+        self.max_ticks = self.max_ticks.max(ticks);
+    }
+
+    /// Returns the average round-trip latency, in performance counter ticks.
+    pub fn average_ticks(&self) -> u64 {
+        self.total_ticks.checked_div(self.count).unwrap_or_default()
+    }
+}
+
+/// A profile of the MM handlers called through MM communication.
+///
+/// Displays as a table with the recipients that spent the most time in MM first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MmHandlerProfileReport {
+    /// The entries, one for each recipient GUID, in the order the recipients were first called.
+    pub entries: Vec<MmHandlerProfileEntry>,
+    /// The frequency of the performance counter in Hz, or 0 if it is not known.
+    pub frequency: u64,
+}
+
+impl MmHandlerProfileReport {
+    fn format_ticks(&self, f: &mut fmt::Formatter<'_>, ticks: u64) -> fmt::Result {
+        match self.frequency {
+            0 => write!(f, " {ticks:>12}"),
+            frequency => write!(f, " {:>10}us", (ticks as u128 * 1_000_000 / frequency as u128) as u64),
+        }
+    }
+}
+
+impl fmt::Display for MmHandlerProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.frequency == 0 { "ticks" } else { "us" };
+        writeln!(f, "MM Handler Profile ({unit}):")?;
+        writeln!(
+            f,
+            "  {:<36} {:>8} {:>8} {:>12} {:>12} {:>12} {:>12}",
+            "Recipient", "Calls", "Failed", "Total", "Average", "Min", "Max"
+        )?;
+
+        let mut entries = self.entries.clone();
+        entries.sort_by(|a, b| b.total_ticks.cmp(&a.total_ticks));
+        for entry in entries.iter().filter(|entry| entry.count != 0) {
+            write!(f, "  {:<36} {:>8} {:>8}", Guid::from_ref(&entry.recipient), entry.count, entry.failures)?;
+            for ticks in [entry.total_ticks, entry.average_ticks(), entry.min_ticks, entry.max_ticks] {
+                self.format_ticks(f, ticks)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A fixed size profile of MM communications by recipient GUID.
+///
+/// Recording never allocates, so the profile can be kept by code that runs after ExitBootServices. Communications to
+/// recipients beyond the first `N` are only counted as untracked. The layout is `repr(C)`, as the table is published
+/// as a configuration table for OS-present diagnostic tools: the counter frequency, the capacity, the number of valid
+/// entries and the untracked count, each as a `u64`, followed by the entries.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmHandlerProfileTable<const N: usize> {
+    frequency: u64,
+    capacity: u64,
+    len: u64,
+    untracked: u64,
+    entries: [MmHandlerProfileEntry; N],
+}
+
+impl<const N: usize> MmHandlerProfileTable<N> {
+    const UNUSED: MmHandlerProfileEntry = MmHandlerProfileEntry::new(efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]));
+
+    /// Create a new, empty table for a performance counter with the given `frequency` in Hz (0 if not known).
+    pub const fn new(frequency: u64) -> Self {
+        Self { frequency, capacity: N as u64, len: 0, untracked: 0, entries: [Self::UNUSED; N] }
+    }
+
+    /// Records a call to `recipient` with a round-trip latency of `ticks`.
+    pub fn record(&mut self, recipient: efi::Guid, ticks: u64, success: bool) {
+        let len = self.len as usize;
+        match self.entries[..len].iter().position(|entry| entry.recipient == recipient) {
+            Some(index) => self.entries[index].record(ticks, success),
+            None if len < N => {
+                self.entries[len] = MmHandlerProfileEntry::new(recipient);
+                self.entries[len].record(ticks, success);
+                self.len += 1;
+            }
+            None => self.untracked += 1,
+        }
+    }
+
+    /// Returns the entries, one for each recipient GUID, in the order the recipients were first called.
+    pub fn entries(&self) -> &[MmHandlerProfileEntry] {
+        &self.entries[..self.len as usize]
+    }
+
+    /// Returns the number of calls that were not recorded, as every entry was taken by another recipient.
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+
+    /// Clears the profile.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.frequency);
+    }
+
+    /// Returns the profile as a report that can be displayed. This allocates, so it must not be used after
+    /// ExitBootServices.
+    pub fn report(&self) -> MmHandlerProfileReport {
+        MmHandlerProfileReport { entries: self.entries().to_vec(), frequency: self.frequency }
+    }
+}
+
+/// Management Mode (MM) Handler Profile Service
+///
+/// Provides the number of calls and the round-trip latency of MM communications by recipient GUID.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MmHandlerProfile {
+    /// Returns the profile of the MM communications made since boot, or since the last reset.
+    fn profile(&self) -> MmHandlerProfileReport;
+
+    /// Clears the profile, such as to measure one phase of boot.
+    fn reset(&self);
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_report() {
+        let chatty = efi::Guid::from_fields(0x1, 0, 0, 0, 0, &[0; 6]);
+        let slow = efi::Guid::from_fields(0x2, 0, 0, 0, 0, &[0; 6]);
+
+        let mut chatty_entry = MmHandlerProfileEntry::new(chatty);
+        for ticks in [10, 20, 30] {
+            chatty_entry.record(ticks, true);
+        }
+        let mut slow_entry = MmHandlerProfileEntry::new(slow);
+        slow_entry.record(1_000, false);
+        assert_eq!((chatty_entry.count, chatty_entry.average_ticks(), chatty_entry.min_ticks), (3, 20, 10));
+        assert_eq!((slow_entry.failures, slow_entry.max_ticks), (1, 1_000));
+        assert_eq!(MmHandlerProfileEntry::new(slow).average_ticks(), 0);
+
+        let report = MmHandlerProfileReport { entries: vec![chatty_entry, slow_entry], frequency: 0 };
+        let output = format!("{report}");
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].contains("00000002-0000-0000-0000-000000000000"));
+        assert!(lines[3].contains("00000001-0000-0000-0000-000000000000"));
+
+        let report = MmHandlerProfileReport { entries: vec![slow_entry], frequency: 1_000_000 };
+        assert!(format!("{report}").contains("1000us"));
+    }
+
+    #[test]
+    fn test_profile_table_has_a_fixed_capacity() {
+        let recipients = [1, 2, 3].map(|id| efi::Guid::from_fields(id, 0, 0, 0, 0, &[0; 6]));
+
+        let mut table = MmHandlerProfileTable::<2>::new(1_000);
+        table.record(recipients[0], 10, true);
+        table.record(recipients[1], 20, true);
+        table.record(recipients[0], 30, false);
+        table.record(recipients[2], 40, true);
+
+        assert_eq!(table.entries().len(), 2);
+        assert_eq!((table.entries()[0].count, table.entries()[0].failures, table.entries()[0].max_ticks), (2, 1, 30));
+        assert_eq!(table.untracked(), 1);
+        assert_eq!(table.report().frequency, 1_000);
+        assert_eq!(table.report().entries, table.entries());
+
+        table.reset();
+        assert!(table.entries().is_empty());
+        assert_eq!(table.untracked(), 0);
+        assert_eq!(table.report().frequency, 1_000);
+    }
+}
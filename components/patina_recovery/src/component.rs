@@ -0,0 +1,417 @@
+//! Recovery Component
+//!
+//! Checks the configured [`RecoveryTrigger`]s and, once one fires, boots the first golden recovery firmware volume
+//! from the configured [`RecoverySource`]s that the platform's [`RecoveryImageVerifier`] verifies.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this component using the `recovery` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::{cell::Cell, ffi::c_void, slice};
+
+use patina::{
+    boot_services::{BootServices, StandardBootServices, event::EventType, tpl::Tpl},
+    component::{IntoComponent, params::Config, service::Service},
+    error::EfiError,
+    runtime_services::{RuntimeServices, StandardRuntimeServices},
+    uefi_protocol::fv_loader::FvLoaderProtocol,
+};
+use patina_boot_counter::component::BOOT_FALLBACK_EVENT_GROUP;
+use r_efi::efi;
+
+use crate::{
+    config::{RecoveryConfig, RecoverySource, RecoveryTrigger},
+    service::{RecoveryImageProvider, RecoveryImageVerifier, RecoverySignal},
+};
+
+/// The services the [`Recovery`] component uses: the required verifier, and the optional provider and signal.
+type RecoveryServices = (
+    Service<dyn RecoveryImageVerifier>,
+    Option<Service<dyn RecoveryImageProvider>>,
+    Option<Service<dyn RecoverySignal>>,
+);
+
+/// A component that boots a verified golden recovery firmware volume when recovery is triggered.
+///
+/// Triggers other than [`RecoveryTrigger::BootFailures`] are checked when the component executes. Recovery is
+/// attempted at most once per boot.
+#[derive(Debug, Default, IntoComponent)]
+pub struct Recovery;
+
+impl Recovery {
+    /// Entry point of [`Recovery`]
+    #[coverage(off)] // This is tested via the generic version, see _entry_point.
+    fn entry_point(
+        self,
+        config: Config<RecoveryConfig>,
+        (verifier, provider, signal): RecoveryServices,
+        boot_services: StandardBootServices,
+        runtime_services: StandardRuntimeServices,
+    ) -> patina::error::Result<()> {
+        self._entry_point(&config, verifier, provider, signal, boot_services, runtime_services)
+    }
+
+    /// Entry point that has generic parameters.
+    fn _entry_point<BB, B, RR, R>(
+        self,
+        config: &RecoveryConfig,
+        verifier: Service<dyn RecoveryImageVerifier>,
+        provider: Option<Service<dyn RecoveryImageProvider>>,
+        signal: Option<Service<dyn RecoverySignal>>,
+        boot_services: BB,
+        runtime_services: RR,
+    ) -> patina::error::Result<()>
+    where
+        BB: AsRef<B> + 'static,
+        B: BootServices + 'static,
+        RR: AsRef<R>,
+        R: RuntimeServices,
+    {
+        let mut requested = false;
+        for trigger in &config.triggers {
+            // every trigger is checked, so that each requesting variable is deleted.
+            requested |= is_requested(trigger, signal.as_ref(), runtime_services.as_ref());
+        }
+
+        let golden_image = GoldenImage {
+            sources: config.sources.clone(),
+            verifier,
+            provider,
+            boot_services,
+            attempted: Cell::new(false),
+        };
+        if requested {
+            return golden_image.boot::<B>().map(|_| ());
+        }
+
+        if config.triggers.contains(&RecoveryTrigger::BootFailures) {
+            // the boot counter signals the group once the variable services are available, after this component ran.
+            let golden_image: &'static GoldenImage<BB> = Box::leak(Box::new(golden_image));
+            golden_image.boot_services.as_ref().create_event_ex(
+                EventType::NOTIFY_SIGNAL,
+                Tpl::CALLBACK,
+                Some(boot_fallback_activated::<BB, B>),
+                golden_image,
+                &BOOT_FALLBACK_EVENT_GROUP,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Boots the golden image once the boot counter applies its fallback actions.
+extern "efiapi" fn boot_fallback_activated<BB, B>(_event: efi::Event, golden_image: &'static GoldenImage<BB>)
+where
+    BB: AsRef<B>,
+    B: BootServices,
+{
+    log::warn!(target: "recovery", "Recovery triggered by repeated failed boots.");
+    // failures are logged when the image is booted.
+    let _ = golden_image.boot::<B>();
+}
+
+/// Returns true if `trigger`, other than [`RecoveryTrigger::BootFailures`], requests recovery.
+fn is_requested(
+    trigger: &RecoveryTrigger,
+    signal: Option<&Service<dyn RecoverySignal>>,
+    runtime_services: &impl RuntimeServices,
+) -> bool {
+    match trigger {
+        RecoveryTrigger::BootFailures => false,
+        RecoveryTrigger::Variable { name, namespace } => {
+            let name: Vec<u16> = name.encode_utf16().chain(core::iter::once(0)).collect();
+            if runtime_services.get_variable_size_and_attributes(&name, namespace).is_err() {
+                return false;
+            }
+            log::warn!(
+                target: "recovery",
+                "Recovery requested by variable {}.",
+                alloc::string::String::from_utf16_lossy(&name[..name.len() - 1])
+            );
+            if let Err(status) = runtime_services.set_variable(&name, namespace, 0, &[0u8; 0]) {
+                log::error!(target: "recovery", "Failed to delete the recovery request variable: {status:?}");
+            }
+            true
+        }
+        RecoveryTrigger::Signal => match signal {
+            Some(signal) if signal.recovery_requested() => {
+                log::warn!(target: "recovery", "Recovery requested by the platform signal.");
+                true
+            }
+            Some(_) => false,
+            None => {
+                log::error!(target: "recovery", "Recovery signal trigger configured, but no RecoverySignal service.");
+                false
+            }
+        },
+    }
+}
+
+/// The sources of the golden image, and the services needed to verify and boot it.
+struct GoldenImage<BB> {
+    sources: Vec<RecoverySource>,
+    verifier: Service<dyn RecoveryImageVerifier>,
+    provider: Option<Service<dyn RecoveryImageProvider>>,
+    boot_services: BB,
+    attempted: Cell<bool>,
+}
+
+impl<BB> GoldenImage<BB> {
+    /// Installs the first verified image from the sources, and dispatches its drivers. Returns the handle of the
+    /// installed firmware volume.
+    fn boot<B>(&self) -> Result<efi::Handle, EfiError>
+    where
+        BB: AsRef<B>,
+        B: BootServices,
+    {
+        if self.attempted.replace(true) {
+            return Err(EfiError::AlreadyStarted);
+        }
+
+        for source in &self.sources {
+            let image = match self.load(source) {
+                Ok(image) => image,
+                Err(err) => {
+                    log::warn!(target: "recovery", "No recovery image from {source:?}: {err:?}");
+                    continue;
+                }
+            };
+            if let Err(err) = self.verifier.verify(&image) {
+                log::error!(target: "recovery", "Recovery image from {source:?} failed verification: {err:?}");
+                continue;
+            }
+            match install(self.boot_services.as_ref(), &image) {
+                Ok(handle) => {
+                    log::info!(target: "recovery", "Booting the recovery image from {source:?}.");
+                    return Ok(handle);
+                }
+                Err(err) => {
+                    log::error!(target: "recovery", "Failed to install recovery image from {source:?}: {err:?}")
+                }
+            }
+        }
+        log::error!(target: "recovery", "No verified recovery image was found.");
+        Err(EfiError::NotFound)
+    }
+
+    /// Returns a copy of the image in `source`.
+    ///
+    /// The image is copied so that the image that is verified is the image that is installed, even if the source
+    /// changes in between.
+    fn load(&self, source: &RecoverySource) -> Result<Vec<u8>, EfiError> {
+        match source {
+            RecoverySource::Flash { base_address, size } => {
+                if *base_address == 0 || *size == 0 {
+                    return Err(EfiError::InvalidParameter);
+                }
+                // SAFETY: The platform configured a firmware volume of `size` bytes memory mapped at `base_address`.
+                Ok(unsafe { slice::from_raw_parts(*base_address as usize as *const u8, *size) }.to_vec())
+            }
+            RecoverySource::Provider => self.provider.as_ref().ok_or(EfiError::NotFound)?.load(),
+        }
+    }
+}
+
+/// Installs the firmware volume in `image` with the core's firmware volume loader, which dispatches its drivers.
+fn install(boot_services: &impl BootServices, image: &[u8]) -> Result<efi::Handle, EfiError> {
+    // SAFETY: The protocol interface is produced by the core and is valid for the remainder of boot.
+    let fv_loader = unsafe { boot_services.locate_protocol::<FvLoaderProtocol>(None) }.map_err(EfiError::from)?;
+    let mut handle: efi::Handle = core::ptr::null_mut();
+    let status = (fv_loader.install_fv_from_buffer)(
+        fv_loader as *mut FvLoaderProtocol,
+        image.as_ptr() as *const c_void,
+        image.len(),
+        &mut handle,
+    );
+    EfiError::status_to_result(status).map(|_| handle)
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::service::{MockRecoveryImageProvider, MockRecoveryImageVerifier, MockRecoverySignal};
+    use alloc::{rc::Rc, vec};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use patina::{boot_services::MockBootServices, runtime_services::MockRuntimeServices};
+
+    const TEST_GUID: efi::Guid =
+        efi::Guid::from_fields(0x1b2d2a3c, 0x4e5f, 0x4a6b, 0x8c, 0x7d, &[0x9e, 0x0f, 0x1a, 0x2b, 0x3c, 0x4d]);
+
+    static INSTALLED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "efiapi" fn install_fv_from_buffer(
+        _: *mut FvLoaderProtocol,
+        buffer: *const c_void,
+        size: usize,
+        handle: *mut efi::Handle,
+    ) -> efi::Status {
+        // only the provider's image is verified in these tests.
+        assert_eq!(unsafe { slice::from_raw_parts(buffer as *const u8, size) }, b"golden");
+        INSTALLED.fetch_add(1, Ordering::SeqCst);
+        unsafe { handle.write(1_usize as efi::Handle) };
+        efi::Status::SUCCESS
+    }
+
+    fn boot_services_with_fv_loader() -> MockBootServices {
+        let fv_loader: &'static mut FvLoaderProtocol =
+            Box::leak(Box::new(FvLoaderProtocol::new(install_fv_from_buffer)));
+        let fv_loader_ptr = fv_loader as *mut FvLoaderProtocol;
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_locate_protocol::<FvLoaderProtocol>()
+            .returning_st(move |_| Ok(unsafe { &mut *fv_loader_ptr }));
+        boot_services
+    }
+
+    fn verifier() -> Service<dyn RecoveryImageVerifier> {
+        let mut verifier = MockRecoveryImageVerifier::new();
+        verifier.expect_verify().returning(|image| match image {
+            b"golden" => Ok(()),
+            _ => Err(EfiError::SecurityViolation),
+        });
+        Service::mock(Box::new(verifier))
+    }
+
+    fn provider(image: &'static [u8]) -> Option<Service<dyn RecoveryImageProvider>> {
+        let mut provider = MockRecoveryImageProvider::new();
+        provider.expect_load().returning(move || Ok(image.to_vec()));
+        Some(Service::mock(Box::new(provider)))
+    }
+
+    fn signal(requested: bool) -> Option<Service<dyn RecoverySignal>> {
+        let mut signal = MockRecoverySignal::new();
+        signal.expect_recovery_requested().return_const(requested);
+        Some(Service::mock(Box::new(signal)))
+    }
+
+    #[test]
+    fn test_no_recovery_unless_requested() {
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_get_variable_size_and_attributes().returning(|_, _| Err(efi::Status::NOT_FOUND));
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_locate_protocol::<FvLoaderProtocol>().never();
+
+        let config = RecoveryConfig::default()
+            .with_trigger(RecoveryTrigger::Variable { name: "RecoveryRequest", namespace: TEST_GUID })
+            .with_trigger(RecoveryTrigger::Signal)
+            .with_source(RecoverySource::Provider);
+        assert!(
+            Recovery
+                ._entry_point(
+                    &config,
+                    verifier(),
+                    provider(b"golden"),
+                    signal(false),
+                    Rc::new(boot_services),
+                    Rc::new(runtime_services),
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_requested_recovery_boots_the_first_verified_image() {
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_get_variable_size_and_attributes().once().returning(|_, _| Ok((1, 0)));
+        runtime_services
+            .expect_set_variable::<[u8; 0]>()
+            .once()
+            .withf(|_, namespace, attributes, data| namespace == &TEST_GUID && *attributes == 0 && data.is_empty())
+            .returning(|_, _, _, _| Ok(()));
+
+        // the flash image is not signed, so the provider's image is booted.
+        let flash: &'static [u8] = Box::leak(Box::new(*b"tampered"));
+        let config = RecoveryConfig::default()
+            .with_trigger(RecoveryTrigger::Variable { name: "RecoveryRequest", namespace: TEST_GUID })
+            .with_source(RecoverySource::Flash { base_address: flash.as_ptr() as u64, size: flash.len() })
+            .with_source(RecoverySource::Provider);
+        let installed = INSTALLED.load(Ordering::SeqCst);
+        assert!(
+            Recovery
+                ._entry_point(
+                    &config,
+                    verifier(),
+                    provider(b"golden"),
+                    None,
+                    Rc::new(boot_services_with_fv_loader()),
+                    Rc::new(runtime_services),
+                )
+                .is_ok()
+        );
+        assert_eq!(INSTALLED.load(Ordering::SeqCst), installed + 1);
+    }
+
+    #[test]
+    fn test_boot_failures_trigger_waits_for_the_boot_counter() {
+        let mut boot_services = boot_services_with_fv_loader();
+        boot_services
+            .expect_create_event_ex::<&'static GoldenImage<Rc<MockBootServices>>>()
+            .once()
+            .withf_st(|event_type, notify_tpl, notify_function, _, event_group| {
+                assert_eq!(&EventType::NOTIFY_SIGNAL, event_type);
+                assert_eq!(&Tpl::CALLBACK, notify_tpl);
+                assert_eq!(
+                    boot_fallback_activated::<Rc<MockBootServices>, MockBootServices> as usize,
+                    notify_function.unwrap() as usize
+                );
+                assert_eq!(&BOOT_FALLBACK_EVENT_GROUP, event_group);
+                true
+            })
+            .returning_st(|_, _, notify_function, golden_image, _| {
+                // signal the group, as the boot counter does.
+                notify_function.unwrap()(1_usize as efi::Event, golden_image);
+                Ok(1_usize as efi::Event)
+            });
+
+        let config =
+            RecoveryConfig::default().with_trigger(RecoveryTrigger::BootFailures).with_source(RecoverySource::Provider);
+        let installed = INSTALLED.load(Ordering::SeqCst);
+        assert!(
+            Recovery
+                ._entry_point(
+                    &config,
+                    verifier(),
+                    provider(b"golden"),
+                    None,
+                    Rc::new(boot_services),
+                    Rc::new(MockRuntimeServices::new()),
+                )
+                .is_ok()
+        );
+        assert_eq!(INSTALLED.load(Ordering::SeqCst), installed + 1);
+    }
+
+    #[test]
+    fn test_unverified_images_are_not_booted() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_locate_protocol::<FvLoaderProtocol>().never();
+        let golden_image = GoldenImage {
+            sources: vec![RecoverySource::Provider, RecoverySource::Flash { base_address: 0, size: 0 }],
+            verifier: verifier(),
+            provider: provider(b"unsigned"),
+            boot_services: Rc::new(boot_services),
+            attempted: Cell::new(false),
+        };
+        assert_eq!(golden_image.boot::<MockBootServices>(), Err(EfiError::NotFound));
+        // recovery is only attempted once.
+        assert_eq!(golden_image.boot::<MockBootServices>(), Err(EfiError::AlreadyStarted));
+    }
+
+    #[test]
+    fn test_signal_trigger() {
+        let runtime_services = MockRuntimeServices::new();
+        assert!(is_requested(&RecoveryTrigger::Signal, signal(true).as_ref(), &runtime_services));
+        assert!(!is_requested(&RecoveryTrigger::Signal, None, &runtime_services));
+        assert!(!is_requested(&RecoveryTrigger::BootFailures, signal(true).as_ref(), &runtime_services));
+    }
+}
@@ -0,0 +1,71 @@
+//! Recovery Configuration
+//!
+//! Defines the [`RecoveryConfig`] used to configure the [`Recovery`](crate::component::Recovery) component.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+
+use r_efi::efi;
+
+/// A condition that starts recovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryTrigger {
+    /// The [`BootCounter`](patina_boot_counter::component::BootCounter) component applied its fallback actions, as
+    /// the number of consecutive failed boots reached its threshold.
+    BootFailures,
+    /// A UEFI variable with this name exists in this namespace, such as one set by the OS or a recovery tool. The
+    /// variable is deleted when recovery starts, so that recovery is requested for a single boot.
+    Variable {
+        /// The name of the variable.
+        name: &'static str,
+        /// The namespace of the variable.
+        namespace: efi::Guid,
+    },
+    /// The platform's [`RecoverySignal`](crate::service::RecoverySignal) service reports that recovery is requested,
+    /// such as from a GPIO strap or a jumper.
+    Signal,
+}
+
+/// A place the golden recovery firmware volume is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoverySource {
+    /// A firmware volume that is memory mapped at `base_address`, such as a golden image in a protected flash region.
+    Flash {
+        /// The address of the firmware volume.
+        base_address: u64,
+        /// The size of the firmware volume in bytes.
+        size: usize,
+    },
+    /// A firmware volume loaded by the platform's [`RecoveryImageProvider`](crate::service::RecoveryImageProvider)
+    /// service, such as from USB storage or the network.
+    Provider,
+}
+
+/// The configuration of the [`Recovery`](crate::component::Recovery) component.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryConfig {
+    /// The conditions that start recovery.
+    pub triggers: Vec<RecoveryTrigger>,
+    /// The places the recovery firmware volume is loaded from, in order. The first that provides a firmware volume
+    /// that is verified is booted.
+    pub sources: Vec<RecoverySource>,
+}
+
+impl RecoveryConfig {
+    /// Adds a condition that starts recovery.
+    pub fn with_trigger(mut self, trigger: RecoveryTrigger) -> Self {
+        self.triggers.push(trigger);
+        self
+    }
+
+    /// Adds a place the recovery firmware volume is loaded from.
+    pub fn with_source(mut self, source: RecoverySource) -> Self {
+        self.sources.push(source);
+        self
+    }
+}
@@ -0,0 +1,51 @@
+//! Verified Golden Image Recovery
+//!
+//! This crate provides a recovery path for platforms that cannot boot their primary firmware, such as after a corrupted
+//! update or repeated boot failures.
+//!
+//! The [`component::Recovery`] component boots a signed golden recovery firmware volume when one of the configured
+//! [`config::RecoveryTrigger`]s fires: the [`BootCounter`](patina_boot_counter::component::BootCounter) activating its
+//! fallback actions after repeated failed boots, a UEFI variable set by the OS or a recovery tool, or a platform signal
+//! such as a GPIO strap. The image is loaded from the first configured [`config::RecoverySource`] that provides one,
+//! from flash or through the platform's [`service::RecoveryImageProvider`] (for example from USB or the network), and
+//! is only installed once the platform's [`service::RecoveryImageVerifier`] verified its signature. The image is
+//! installed through the core's firmware volume loader, which also authenticates it with the Security2 Architectural
+//! Protocol if one is installed, and the dispatcher then dispatches its drivers.
+//!
+//! ## Examples
+//!
+//! ```rust,ignore
+//! use patina_recovery::{
+//!     component::Recovery,
+//!     config::{RecoveryConfig, RecoverySource, RecoveryTrigger},
+//! };
+//!
+//! patina_dxe_core::Core::default()
+//!   .init_memory(physical_hob_list)
+//!   .with_service(PlatformRecoveryVerifier::new(GOLDEN_IMAGE_PUBLIC_KEY))
+//!   .with_config(
+//!       RecoveryConfig::default()
+//!           .with_trigger(RecoveryTrigger::BootFailures)
+//!           .with_trigger(RecoveryTrigger::Variable { name: "RecoveryRequest", namespace: PLATFORM_GUID })
+//!           .with_source(RecoverySource::Flash { base_address: GOLDEN_FV_BASE, size: GOLDEN_FV_SIZE })
+//!           .with_source(RecoverySource::Provider),
+//!   )
+//!   .with_component(Recovery)
+//!   .start()
+//!   .unwrap();
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![feature(coverage_attribute)]
+
+extern crate alloc;
+
+pub mod component;
+pub mod config;
+pub mod service;
@@ -0,0 +1,42 @@
+//! Recovery Service Definitions
+//!
+//! The platform specific parts of recovery: verifying the signature of a recovery image with the platform's keys, and
+//! optionally loading the image from a device and reading a recovery request from hardware. `mockall` mocks are
+//! available for testing (`MockRecoveryImageVerifier`, `MockRecoveryImageProvider` and `MockRecoverySignal`).
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+use patina::error::EfiError;
+
+/// Verifies that a recovery image is a genuine golden image.
+///
+/// The [`Recovery`](crate::component::Recovery) component requires this service, and never boots an image that it
+/// does not verify.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait RecoveryImageVerifier {
+    /// Verifies the signature of `image`, returning [`EfiError::SecurityViolation`] if it is not signed by a key
+    /// trusted for recovery images.
+    fn verify(&self, image: &[u8]) -> Result<(), EfiError>;
+}
+
+/// Loads a recovery image from a platform specific location, such as USB storage or the network.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait RecoveryImageProvider {
+    /// Returns the recovery firmware volume, or [`EfiError::NotFound`] if none is available.
+    fn load(&self) -> Result<Vec<u8>, EfiError>;
+}
+
+/// Reports whether recovery is requested by hardware, such as a GPIO strap or a jumper.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait RecoverySignal {
+    /// Returns true if recovery is requested.
+    fn recovery_requested(&self) -> bool;
+}
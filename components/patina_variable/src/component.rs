@@ -0,0 +1,14 @@
+//! Variable Services Components
+//!
+//! This module provides components that produce the UEFI variable services.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+pub mod flash_store;
+pub mod memory_store;
+
+mod variable_services;
@@ -0,0 +1,124 @@
+//! Flash Backed Variable Store Component
+//!
+//! Produces the UEFI variable services (GetVariable, GetNextVariableName, SetVariable and QueryVariableInfo) from a
+//! [`VariableStore`] persisted to flash through the platform's [`FlashDevice`] service, and installs the Variable and
+//! Variable Write architectural protocols.
+//!
+//! Non-volatile variables are written with [`FaultTolerantStore`], so a reset during a variable update leaves either
+//! the previous or the new set of variables intact. This component is intended for platforms that run the variable
+//! services outside of MM. Because the component executes from boot services memory, the variable services must not be
+//! called after ExitBootServices.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this component using the `variable` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::boxed::Box;
+
+use patina::{
+    boot_services::StandardBootServices,
+    component::{IntoComponent, params::Config, service::Service},
+    error::{EfiError, Result},
+    runtime_services::StandardRuntimeServices,
+};
+
+use crate::{
+    component::variable_services::{self, StoreServices},
+    config::FlashVariableStoreConfig,
+    ftw::FaultTolerantStore,
+    service::FlashDevice,
+    store::VariableStore,
+};
+
+/// A component that produces the UEFI variable services from flash.
+#[derive(Debug, Default, IntoComponent)]
+pub struct FlashVariableStore;
+
+impl FlashVariableStore {
+    /// Entry point for the flash backed variable store.
+    ///
+    /// Recovers any interrupted flash update, loads the non-volatile variables from flash, installs the variable
+    /// services in the runtime services table and installs the Variable and Variable Write architectural protocols.
+    /// If the platform produces an [`AuthVariableVerifier`] service, it is used to verify authenticated variable
+    /// writes, and if it produces a [`VariableQuota`] service, its quotas are enforced on top of the store limits. If
+    /// the Memory Overwrite Request variables are configured, a [`MemoryClearScheduler`] service produced by the
+    /// platform is asked to clear memory on the next boot when the OS requests it.
+    ///
+    /// [`AuthVariableVerifier`]: patina::runtime_services::variable_services::authenticated::AuthVariableVerifier
+    /// [`VariableQuota`]: crate::service::VariableQuota
+    /// [`MemoryClearScheduler`]: crate::service::MemoryClearScheduler
+    fn entry_point(
+        self,
+        config: Config<FlashVariableStoreConfig>,
+        flash: Service<dyn FlashDevice>,
+        (verifier, quota, memory_clear): StoreServices,
+        (bs, rs): (StandardBootServices, StandardRuntimeServices),
+    ) -> Result<()> {
+        if config.variable_store_blocks == 0 {
+            log::error!(target: "variable", "FlashVariableStoreConfig::variable_store_blocks is not configured.");
+            return Err(EfiError::InvalidParameter);
+        }
+
+        log::info!(target: "variable", "Flash variable store: {} blocks at LBA {}.",
+            config.variable_store_blocks, config.base_lba);
+        let storage = FaultTolerantStore::new(*flash, config.base_lba, config.variable_store_blocks)?;
+
+        let mut store = VariableStore::new(config.limits());
+        if let Some(verifier) = verifier {
+            store = store.with_verifier(*verifier);
+        }
+        if let Some(quota) = quota {
+            store = store.with_quota(*quota);
+        }
+        let mut store = store.with_nv_storage(Box::new(storage))?;
+        if config.memory_overwrite_request {
+            store = store.with_memory_overwrite_request(memory_clear.map(|memory_clear| *memory_clear));
+        }
+
+        variable_services::install(store, &bs, &rs)?;
+        if config.memory_overwrite_request {
+            variable_services::install_memory_overwrite_events(&bs)?;
+        }
+        log::info!(target: "variable", "Flash backed variable services installed.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::service::flash_device::MockFlashDevice;
+
+    fn mock_flash() -> Service<dyn FlashDevice> {
+        let mut flash = MockFlashDevice::new();
+        flash.expect_block_size().return_const(0x1000usize);
+        flash.expect_block_count().return_const(4usize);
+        Service::mock(Box::new(flash))
+    }
+
+    #[test]
+    fn test_entry_point_rejects_invalid_layout() {
+        let unconfigured = FlashVariableStore.entry_point(
+            Config::mock(FlashVariableStoreConfig::default()),
+            mock_flash(),
+            (None, None, None),
+            (StandardBootServices::new_uninit(), StandardRuntimeServices::new_uninit()),
+        );
+        assert_eq!(unconfigured, Err(EfiError::InvalidParameter));
+
+        let too_large = FlashVariableStore.entry_point(
+            Config::mock(FlashVariableStoreConfig { variable_store_blocks: 2, ..Default::default() }),
+            mock_flash(),
+            (None, None, None),
+            (StandardBootServices::new_uninit(), StandardRuntimeServices::new_uninit()),
+        );
+        assert_eq!(too_large, Err(EfiError::InvalidParameter));
+    }
+}
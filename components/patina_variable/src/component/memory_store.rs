@@ -0,0 +1,152 @@
+//! Memory Backed Variable Store Component
+//!
+//! Produces the UEFI variable services (GetVariable, GetNextVariableName, SetVariable and QueryVariableInfo) from a
+//! [`VariableStore`] held in memory, and installs the Variable and Variable Write architectural protocols.
+//!
+//! This component is intended for platform bring-up and emulators. Non-volatile variables are only persisted if the
+//! platform provides a reserved memory region through [`MemoryVariableStoreConfig`]; otherwise they are lost on
+//! reset. Because the component executes from boot services memory, the variable services must not be called after
+//! ExitBootServices.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this component using the `variable` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec::Vec};
+use core::slice;
+
+use patina::{
+    boot_services::StandardBootServices,
+    component::{IntoComponent, params::Config},
+    error::{EfiError, Result},
+    runtime_services::StandardRuntimeServices,
+};
+
+use crate::{
+    component::variable_services::{self, StoreServices},
+    config::MemoryVariableStoreConfig,
+    store::{NvStorage, VariableStore},
+};
+
+/// Non-volatile storage in a reserved memory region that is preserved across warm resets.
+pub struct MemoryRegionStorage {
+    base: u64,
+    size: usize,
+}
+
+impl MemoryRegionStorage {
+    /// Creates storage over the memory region at `base` of `size` bytes.
+    ///
+    /// ## Safety
+    ///
+    /// The region must be mapped, reserved for this purpose and not used by anything else.
+    pub unsafe fn new(base: u64, size: usize) -> Self {
+        Self { base, size }
+    }
+
+    fn region(&self) -> &[u8] {
+        // SAFETY: The caller of `new` guarantees the region is valid and exclusively owned.
+        unsafe { slice::from_raw_parts(self.base as *const u8, self.size) }
+    }
+}
+
+impl NvStorage for MemoryRegionStorage {
+    fn read(&self) -> Result<Vec<u8>> {
+        // The image records its own size after the signature and version.
+        let region = self.region();
+        let size = region.get(8..12).map_or(0, |s| u32::from_le_bytes(s.try_into().unwrap()) as usize);
+        Ok(region.get(..size).filter(|image| image.len() >= 12).map(<[u8]>::to_vec).unwrap_or_default())
+    }
+
+    fn write(&mut self, image: &[u8]) -> Result<()> {
+        if image.len() > self.size {
+            return Err(EfiError::OutOfResources);
+        }
+        // SAFETY: The caller of `new` guarantees the region is valid and exclusively owned.
+        unsafe { slice::from_raw_parts_mut(self.base as *mut u8, image.len()) }.copy_from_slice(image);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.size
+    }
+}
+
+/// A component that produces the UEFI variable services from memory.
+#[derive(Debug, Default, IntoComponent)]
+pub struct MemoryVariableStore;
+
+impl MemoryVariableStore {
+    /// Entry point for the memory backed variable store.
+    ///
+    /// Loads any non-volatile variables from the configured memory region, installs the variable services in the
+    /// runtime services table and installs the Variable and Variable Write architectural protocols. If the platform
+    /// produces an [`AuthVariableVerifier`] service, it is used to verify authenticated variable writes, and if it
+    /// produces a [`VariableQuota`] service, its quotas are enforced on top of the store limits. If the Memory
+    /// Overwrite Request variables are configured, a [`MemoryClearScheduler`] service produced by the platform is asked
+    /// to clear memory on the next boot when the OS requests it.
+    ///
+    /// [`AuthVariableVerifier`]: patina::runtime_services::variable_services::authenticated::AuthVariableVerifier
+    /// [`VariableQuota`]: crate::service::VariableQuota
+    /// [`MemoryClearScheduler`]: crate::service::MemoryClearScheduler
+    fn entry_point(
+        self,
+        config: Config<MemoryVariableStoreConfig>,
+        (verifier, quota, memory_clear): StoreServices,
+        bs: StandardBootServices,
+        rs: StandardRuntimeServices,
+    ) -> Result<()> {
+        let mut store = VariableStore::new(config.limits());
+        if let Some(verifier) = verifier {
+            store = store.with_verifier(*verifier);
+        }
+        if let Some(quota) = quota {
+            store = store.with_quota(*quota);
+        }
+        if config.nv_region_base != 0 && config.nv_region_size != 0 {
+            log::info!(target: "variable", "Persisting non-volatile variables at {:#x} ({:#x} bytes).",
+                config.nv_region_base, config.nv_region_size);
+            // SAFETY: The platform configured this region to be reserved for variable storage.
+            let storage = unsafe { MemoryRegionStorage::new(config.nv_region_base, config.nv_region_size) };
+            store = store.with_nv_storage(Box::new(storage))?;
+        } else {
+            log::warn!(target: "variable", "No non-volatile region configured. Variables will not persist across reset.");
+        }
+        if config.memory_overwrite_request {
+            store = store.with_memory_overwrite_request(memory_clear.map(|memory_clear| *memory_clear));
+        }
+        variable_services::install(store, &bs, &rs)?;
+        if config.memory_overwrite_request {
+            variable_services::install_memory_overwrite_events(&bs)?;
+        }
+        log::info!(target: "variable", "Memory backed variable services installed.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_memory_region_storage_round_trip() {
+        let mut region = vec![0u8; 64];
+        // SAFETY: The region is owned by this test.
+        let mut storage = unsafe { MemoryRegionStorage::new(region.as_mut_ptr() as u64, region.len()) };
+        assert!(storage.read().unwrap().is_empty());
+
+        let mut image = vec![0u8; 16];
+        image[8..12].copy_from_slice(&16u32.to_le_bytes());
+        storage.write(&image).unwrap();
+        assert_eq!(storage.read().unwrap(), image);
+        assert_eq!(storage.write(&[0; 65]), Err(EfiError::OutOfResources));
+    }
+}
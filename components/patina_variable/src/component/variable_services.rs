@@ -0,0 +1,356 @@
+//! UEFI Variable Services Installation
+//!
+//! Implements the UEFI variable services (GetVariable, GetNextVariableName, SetVariable and QueryVariableInfo) over a
+//! global [`VariableStore`], and installs them in the runtime services table on behalf of the variable store
+//! components. When the store produces the Memory Overwrite Request variables, also clears or schedules the requested
+//! memory clear at ReadyToBoot and ExitBootServices.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::boxed::Box;
+use core::{ffi::c_void, mem::size_of, slice};
+
+use patina::{
+    boot_services::{BootServices, StandardBootServices, event::EventType, tpl::Tpl},
+    component::service::Service,
+    error::{EfiError, Result},
+    runtime_services::{StandardRuntimeServices, variable_services::authenticated::AuthVariableVerifier},
+    uefi_protocol::ProtocolInterface,
+};
+use r_efi::{efi, system::EVENT_GROUP_READY_TO_BOOT};
+
+use crate::{
+    service::{MemoryClearScheduler, VariableQuota},
+    store::VariableStore,
+};
+
+/// The optional platform services used by the variable store components: the authenticated variable verifier, the
+/// variable quotas and the memory clear scheduler.
+pub(crate) type StoreServices = (
+    Option<Service<dyn AuthVariableVerifier>>,
+    Option<Service<dyn VariableQuota>>,
+    Option<Service<dyn MemoryClearScheduler>>,
+);
+
+static STORE: spin::Mutex<Option<VariableStore>> = spin::Mutex::new(None);
+
+/// The Variable architectural protocol, installed once GetVariable and GetNextVariableName are available.
+#[repr(C)]
+struct VariableArchProtocol;
+
+unsafe impl ProtocolInterface for VariableArchProtocol {
+    const PROTOCOL_GUID: efi::Guid =
+        efi::Guid::from_fields(0x1e5668e2, 0x8481, 0x11d4, 0xbc, 0xf1, &[0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]);
+}
+
+/// The Variable Write architectural protocol, installed once SetVariable is available.
+#[repr(C)]
+struct VariableWriteArchProtocol;
+
+unsafe impl ProtocolInterface for VariableWriteArchProtocol {
+    const PROTOCOL_GUID: efi::Guid =
+        efi::Guid::from_fields(0x6441f818, 0x6362, 0x4e44, 0xb5, 0x70, &[0x7d, 0xba, 0x31, 0xdd, 0x24, 0x53]);
+}
+
+/// Makes `store` the backing store of the UEFI variable services.
+///
+/// Installs the variable services in the runtime services table, then installs the Variable and Variable Write
+/// architectural protocols, which causes the core to recalculate the table checksum.
+pub(crate) fn install(store: VariableStore, bs: &StandardBootServices, rs: &StandardRuntimeServices) -> Result<()> {
+    let rt = rs.as_mut_ptr();
+    if rt.is_null() {
+        return Err(EfiError::NotReady);
+    }
+    *STORE.lock() = Some(store);
+
+    // SAFETY: The runtime services table is valid for the lifetime of boot, and the table checksum is recalculated
+    // by the core when the architectural protocols below are installed.
+    unsafe {
+        (*rt).get_variable = get_variable;
+        (*rt).get_next_variable_name = get_next_variable_name;
+        (*rt).set_variable = set_variable;
+        (*rt).query_variable_info = query_variable_info;
+    }
+
+    bs.install_protocol_interface(None, Box::new(VariableArchProtocol)).map_err(EfiError::from)?;
+    bs.install_protocol_interface(None, Box::new(VariableWriteArchProtocol)).map_err(EfiError::from)?;
+    Ok(())
+}
+
+/// Registers the ReadyToBoot and ExitBootServices events that act on the Memory Overwrite Request of the installed
+/// store. See [`crate::mor`].
+pub(crate) fn install_memory_overwrite_events(bs: &StandardBootServices) -> Result<()> {
+    bs.create_event_ex(
+        EventType::NOTIFY_SIGNAL,
+        Tpl::CALLBACK,
+        Some(auto_clear_memory_overwrite_request),
+        core::ptr::null_mut(),
+        &EVENT_GROUP_READY_TO_BOOT,
+    )
+    .map_err(EfiError::from)?;
+    bs.create_event(
+        EventType::SIGNAL_EXIT_BOOT_SERVICES,
+        Tpl::CALLBACK,
+        Some(schedule_memory_clear),
+        core::ptr::null_mut(),
+    )
+    .map_err(EfiError::from)?;
+    Ok(())
+}
+
+/// Clears the memory clear request that was served before DXE, so that only a boot that does not shut down orderly
+/// clears memory again.
+extern "efiapi" fn auto_clear_memory_overwrite_request(_event: efi::Event, _context: *mut c_void) {
+    if let Err(err) = with_store(|store| store.auto_clear_memory_overwrite_request()) {
+        log::error!(target: "variable", "Failed to clear the memory overwrite request: {err:?}");
+    }
+}
+
+/// Schedules a memory clear for the next boot if the OS requested one.
+extern "efiapi" fn schedule_memory_clear(_event: efi::Event, _context: *mut c_void) {
+    match with_store(|store| store.schedule_memory_clear()) {
+        Ok(true) => log::info!(target: "variable", "Memory clear scheduled for the next boot."),
+        Ok(false) => {}
+        Err(err) => log::error!(target: "variable", "Failed to schedule the memory clear: {err:?}"),
+    }
+}
+
+/// Returns the null terminated UCS-2 string at `name`, without the terminator.
+///
+/// ## Safety
+///
+/// `name` must point to a valid null terminated string.
+unsafe fn name_from_ptr<'a>(name: *const u16) -> &'a [u16] {
+    let mut len = 0;
+    // SAFETY: The caller guarantees the string is null terminated.
+    while unsafe { *name.add(len) } != 0 {
+        len += 1;
+    }
+    // SAFETY: `len` characters were just read from `name`.
+    unsafe { slice::from_raw_parts(name, len) }
+}
+
+fn with_store<T>(f: impl FnOnce(&mut VariableStore) -> Result<T>) -> Result<T> {
+    STORE.lock().as_mut().ok_or(EfiError::NotReady).and_then(f)
+}
+
+fn to_status(result: Result<()>) -> efi::Status {
+    result.map_or_else(efi::Status::from, |_| efi::Status::SUCCESS)
+}
+
+extern "efiapi" fn get_variable(
+    name: *mut efi::Char16,
+    namespace: *mut efi::Guid,
+    attributes: *mut u32,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> efi::Status {
+    if name.is_null() || namespace.is_null() || data_size.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: Pointers were checked for null; the caller is responsible for their validity.
+    let (name, namespace, data_size) = unsafe { (name_from_ptr(name), &*namespace, &mut *data_size) };
+    to_status(with_store(|store| {
+        let (attr, value) = store.get(name, namespace)?;
+        if !attributes.is_null() {
+            // SAFETY: Checked for null above; the caller is responsible for its validity.
+            unsafe { attributes.write(attr) };
+        }
+        if *data_size < value.len() {
+            *data_size = value.len();
+            return Err(EfiError::BufferTooSmall);
+        }
+        if data.is_null() {
+            return Err(EfiError::InvalidParameter);
+        }
+        // SAFETY: The caller provided a buffer of at least `data_size` bytes.
+        unsafe { slice::from_raw_parts_mut(data as *mut u8, value.len()) }.copy_from_slice(value);
+        *data_size = value.len();
+        Ok(())
+    }))
+}
+
+extern "efiapi" fn get_next_variable_name(
+    name_size: *mut usize,
+    name: *mut efi::Char16,
+    namespace: *mut efi::Guid,
+) -> efi::Status {
+    if name_size.is_null() || name.is_null() || namespace.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: Pointers were checked for null; the caller is responsible for their validity.
+    let (name_size, namespace) = unsafe { (&mut *name_size, &mut *namespace) };
+    // SAFETY: The caller provided a buffer of `name_size` bytes.
+    let buffer = unsafe { slice::from_raw_parts_mut(name, *name_size / size_of::<u16>()) };
+    let Some(len) = buffer.iter().position(|&c| c == 0) else {
+        return efi::Status::INVALID_PARAMETER;
+    };
+    to_status(with_store(|store| {
+        let (next_name, next_namespace) = store.get_next_name(&buffer[..len], namespace)?;
+        let required = (next_name.len() + 1) * size_of::<u16>();
+        if *name_size < required {
+            *name_size = required;
+            return Err(EfiError::BufferTooSmall);
+        }
+        buffer[..next_name.len()].copy_from_slice(next_name);
+        buffer[next_name.len()] = 0;
+        *name_size = required;
+        *namespace = next_namespace;
+        Ok(())
+    }))
+}
+
+extern "efiapi" fn set_variable(
+    name: *mut efi::Char16,
+    namespace: *mut efi::Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *mut c_void,
+) -> efi::Status {
+    if name.is_null() || namespace.is_null() || (data.is_null() && data_size != 0) {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // SAFETY: Pointers were checked for null; the caller is responsible for their validity.
+    let (name, namespace) = unsafe { (name_from_ptr(name), &*namespace) };
+    let data = match data_size {
+        0 => &[][..],
+        // SAFETY: The caller provided a buffer of `data_size` bytes.
+        _ => unsafe { slice::from_raw_parts(data as *const u8, data_size) },
+    };
+    let result = with_store(|store| store.set(name, namespace, attributes, data));
+    if let Err(err) = result {
+        log::debug!(target: "variable", "SetVariable({}) failed: {err:?}", alloc::string::String::from_utf16_lossy(name));
+    }
+    to_status(result)
+}
+
+extern "efiapi" fn query_variable_info(
+    attributes: u32,
+    maximum_storage_size: *mut u64,
+    remaining_storage_size: *mut u64,
+    maximum_variable_size: *mut u64,
+) -> efi::Status {
+    if maximum_storage_size.is_null() || remaining_storage_size.is_null() || maximum_variable_size.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    to_status(with_store(|store| {
+        let info = store.query_info(attributes)?;
+        // SAFETY: Pointers were checked for null; the caller is responsible for their validity.
+        unsafe {
+            maximum_storage_size.write(info.maximum_storage_size);
+            remaining_storage_size.write(info.remaining_storage_size);
+            maximum_variable_size.write(info.maximum_variable_size);
+        }
+        Ok(())
+    }))
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::store::StoreLimits;
+    use alloc::{vec, vec::Vec};
+
+    const VENDOR_GUID: efi::Guid =
+        efi::Guid::from_fields(0x12345678, 0x1234, 0x5678, 0x9a, 0xbc, &[0xde, 0xf0, 0x12, 0x34, 0x56, 0x78]);
+
+    static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn with_test_store(f: impl FnOnce()) {
+        let _guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        *STORE.lock() = Some(VariableStore::new(StoreLimits {
+            max_variable_size: 0x100,
+            nv_store_size: 0x1000,
+            volatile_store_size: 0x1000,
+        }));
+        f();
+        *STORE.lock() = None;
+    }
+
+    fn name(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(core::iter::once(0)).collect()
+    }
+
+    #[test]
+    fn test_set_and_get_variable_through_efiapi() {
+        with_test_store(|| {
+            let mut name = name("BootOrder");
+            let mut guid = VENDOR_GUID;
+            let mut data = [1u8, 0, 2, 0];
+            let attributes = efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+            assert_eq!(
+                set_variable(name.as_mut_ptr(), &mut guid, attributes, data.len(), data.as_mut_ptr() as *mut c_void),
+                efi::Status::SUCCESS
+            );
+
+            let mut attr = 0;
+            let mut size = 2;
+            let mut buffer = [0u8; 4];
+            assert_eq!(
+                get_variable(name.as_mut_ptr(), &mut guid, &mut attr, &mut size, buffer.as_mut_ptr() as *mut c_void),
+                efi::Status::BUFFER_TOO_SMALL
+            );
+            assert_eq!(size, 4);
+            assert_eq!(
+                get_variable(name.as_mut_ptr(), &mut guid, &mut attr, &mut size, buffer.as_mut_ptr() as *mut c_void),
+                efi::Status::SUCCESS
+            );
+            assert_eq!(attr, attributes);
+            assert_eq!(buffer, data);
+        });
+    }
+
+    #[test]
+    fn test_get_next_variable_name_through_efiapi() {
+        with_test_store(|| {
+            let mut buffer = vec![0u16; 4];
+            let mut size = 2;
+            let mut guid = VENDOR_GUID;
+
+            // The store always contains at least SecureBoot and SetupMode.
+            assert_eq!(
+                get_next_variable_name(&mut size, buffer.as_mut_ptr(), &mut guid),
+                efi::Status::BUFFER_TOO_SMALL
+            );
+            assert!(size > 2);
+
+            let mut buffer = vec![0u16; size / 2];
+            let mut count = 0;
+            while get_next_variable_name(&mut size, buffer.as_mut_ptr(), &mut guid) == efi::Status::SUCCESS {
+                count += 1;
+                size = buffer.len() * 2;
+            }
+            assert_eq!(count, 2);
+
+            // A name that is not null terminated within the buffer is rejected.
+            let mut buffer = vec![b'A' as u16; 2];
+            let mut size = 4;
+            assert_eq!(
+                get_next_variable_name(&mut size, buffer.as_mut_ptr(), &mut guid),
+                efi::Status::INVALID_PARAMETER
+            );
+        });
+    }
+
+    #[test]
+    fn test_query_variable_info_through_efiapi() {
+        with_test_store(|| {
+            let (mut max, mut remaining, mut max_var) = (0, 0, 0);
+            assert_eq!(
+                query_variable_info(efi::VARIABLE_BOOTSERVICE_ACCESS, &mut max, &mut remaining, &mut max_var),
+                efi::Status::SUCCESS
+            );
+            assert_eq!(max, 0x1000);
+            assert_eq!(max_var, 0x100);
+            assert!(remaining < max);
+            assert_eq!(query_variable_info(0, &mut max, &mut remaining, &mut max_var), efi::Status::INVALID_PARAMETER);
+        });
+    }
+}
@@ -0,0 +1,101 @@
+//! Variable Store Configuration
+//!
+//! Defines the configuration used by the variable store components.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use crate::store::StoreLimits;
+
+/// Configuration for the [`MemoryVariableStore`](crate::component::memory_store::MemoryVariableStore) component.
+///
+/// By default, all variables (including non-volatile ones) are kept in boot services memory and are lost on reset.
+/// A platform can emulate non-volatile storage by reserving a memory region that is preserved across warm resets
+/// (e.g. on an emulator) and providing it in [`nv_region_base`](Self::nv_region_base) and
+/// [`nv_region_size`](Self::nv_region_size).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryVariableStoreConfig {
+    /// The physical base address of the reserved memory region used to persist non-volatile variables, or 0 if none.
+    pub nv_region_base: u64,
+    /// The size in bytes of the reserved memory region used to persist non-volatile variables.
+    pub nv_region_size: usize,
+    /// The maximum size of the data of a single variable.
+    pub max_variable_size: usize,
+    /// The total size available to non-volatile variables.
+    pub nv_store_size: usize,
+    /// The total size available to volatile variables.
+    pub volatile_store_size: usize,
+    /// Whether the store produces the TCG Memory Overwrite Request variables. See [`crate::mor`].
+    pub memory_overwrite_request: bool,
+}
+
+impl MemoryVariableStoreConfig {
+    /// Returns the store limits described by this configuration.
+    pub fn limits(&self) -> StoreLimits {
+        StoreLimits {
+            max_variable_size: self.max_variable_size,
+            nv_store_size: self.nv_store_size,
+            volatile_store_size: self.volatile_store_size,
+        }
+    }
+}
+
+impl Default for MemoryVariableStoreConfig {
+    fn default() -> Self {
+        Self {
+            nv_region_base: 0,
+            nv_region_size: 0,
+            max_variable_size: 0x8000,
+            nv_store_size: 0x4_0000,
+            volatile_store_size: 0x4_0000,
+            memory_overwrite_request: false,
+        }
+    }
+}
+
+/// Configuration for the [`FlashVariableStore`](crate::component::flash_store::FlashVariableStore) component.
+///
+/// The flash region used by the store starts at [`base_lba`](Self::base_lba) and consists of
+/// [`variable_store_blocks`](Self::variable_store_blocks) blocks of variable store, one working block and
+/// [`variable_store_blocks`](Self::variable_store_blocks) blocks of spare area. See [`crate::ftw`] for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashVariableStoreConfig {
+    /// The first block of the flash region used by the variable store.
+    pub base_lba: usize,
+    /// The number of blocks in the variable store. Must be configured by the platform.
+    pub variable_store_blocks: usize,
+    /// The maximum size of the data of a single variable.
+    pub max_variable_size: usize,
+    /// The total size available to volatile variables.
+    pub volatile_store_size: usize,
+    /// Whether the store produces the TCG Memory Overwrite Request variables. See [`crate::mor`].
+    pub memory_overwrite_request: bool,
+}
+
+impl FlashVariableStoreConfig {
+    /// Returns the store limits described by this configuration.
+    ///
+    /// The size available to non-volatile variables is limited by the capacity of the flash region.
+    pub fn limits(&self) -> StoreLimits {
+        StoreLimits {
+            max_variable_size: self.max_variable_size,
+            nv_store_size: usize::MAX,
+            volatile_store_size: self.volatile_store_size,
+        }
+    }
+}
+
+impl Default for FlashVariableStoreConfig {
+    fn default() -> Self {
+        Self {
+            base_lba: 0,
+            variable_store_blocks: 0,
+            max_variable_size: 0x8000,
+            volatile_store_size: 0x4_0000,
+            memory_overwrite_request: false,
+        }
+    }
+}
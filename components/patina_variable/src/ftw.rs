@@ -0,0 +1,532 @@
+//! Fault Tolerant Flash Variable Storage
+//!
+//! [`FaultTolerantStore`] implements [`NvStorage`] over a [`FlashDevice`], persisting the non-volatile variable image
+//! such that a reset at any point leaves either the previous or the new image intact.
+//!
+//! The flash region is split into three areas, starting at the configured base block:
+//!
+//! ```text
+//! +-------------------------+--------------+-------------------------+
+//! | Variable area (N blocks)| Working block| Spare area (N blocks)   |
+//! +-------------------------+--------------+-------------------------+
+//! ```
+//!
+//! The variable area is a log of images. Each write appends a record containing the complete image and retires the
+//! previous record; record states are advanced by clearing bits so that no erase is needed. When the log is full, the
+//! variable area is reclaimed (garbage collected) by rewriting it with only the latest image. Reclaim is made fault
+//! tolerant by first staging the new contents in the spare area and tracking progress in the working block, so that an
+//! interrupted reclaim is completed on the next boot.
+//!
+//! ## Logging
+//!
+//! Detailed logging is available for this module using the `variable` log target.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{vec, vec::Vec};
+
+use patina::error::{EfiError, Result};
+
+use crate::{service::FlashDevice, store::NvStorage};
+
+/// The signature of the variable area header ("PVFS").
+const AREA_SIGNATURE: u32 = u32::from_le_bytes(*b"PVFS");
+
+/// The signature of the working block header ("PFTW").
+const WORKING_SIGNATURE: u32 = u32::from_le_bytes(*b"PFTW");
+
+/// The version of the variable area and working block formats.
+const FORMAT_VERSION: u32 = 1;
+
+/// The size of the variable area and working block headers.
+const HEADER_SIZE: usize = 16;
+
+/// The size of the header of an image record in the variable area.
+const RECORD_HEADER_SIZE: usize = 16;
+
+/// The size of a write record in the working block.
+const WRITE_RECORD_SIZE: usize = 16;
+
+/// The value of erased flash.
+const ERASED: u8 = 0xFF;
+
+/// The image record header has been written, but the image may be incomplete.
+const RECORD_HEADER_VALID: u8 = 0x7F;
+/// The image record is complete.
+const RECORD_DATA_VALID: u8 = 0x3F;
+/// The image record has been superseded by a later record.
+const RECORD_OBSOLETE: u8 = 0x1F;
+
+/// A reclaim has started, but the spare area may be incomplete.
+const WRITE_ALLOCATED: u8 = 0x7F;
+/// The spare area holds the complete new contents of the variable area.
+const WRITE_SPARE_COMPLETE: u8 = 0x3F;
+/// The variable area has been updated from the spare area (or the reclaim was abandoned).
+const WRITE_COMPLETE: u8 = 0x1F;
+
+fn align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn is_erased(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&b| b == ERASED)
+}
+
+/// Builds a 16 byte header: a state byte, then `fields` as little endian `u32`s from offset 4.
+fn header(state: u8, fields: &[u32]) -> [u8; 16] {
+    let mut header = [ERASED; 16];
+    header[0] = state;
+    for (i, field) in fields.iter().enumerate() {
+        header[4 + i * 4..8 + i * 4].copy_from_slice(&field.to_le_bytes());
+    }
+    header
+}
+
+/// Fault tolerant non-volatile variable storage over a flash device.
+pub struct FaultTolerantStore {
+    flash: &'static dyn FlashDevice,
+    block_size: usize,
+    blocks: usize,
+    variable_lba: usize,
+    working_lba: usize,
+    spare_lba: usize,
+    /// The offset in the variable area of the record holding the latest image.
+    current: Option<usize>,
+    /// The offset in the variable area at which the next record is appended.
+    append_offset: usize,
+    /// Whether the variable area must be reclaimed before the next append.
+    needs_reclaim: bool,
+    /// The offset in the working block of the next free write record, if any.
+    next_write_record: Option<usize>,
+}
+
+// SAFETY: The flash device is a service registered with the component storage, which lives for the remainder of
+// boot. UEFI boot services execute on a single thread, so the device is never accessed concurrently.
+unsafe impl Send for FaultTolerantStore {}
+
+impl FaultTolerantStore {
+    /// Creates storage over `blocks` blocks of variable area starting at `base_lba`, followed by the working block
+    /// and `blocks` blocks of spare area.
+    ///
+    /// Completes any reclaim that was interrupted by a reset. Returns [`EfiError::InvalidParameter`] if the layout
+    /// does not fit in the device.
+    pub fn new(flash: &'static dyn FlashDevice, base_lba: usize, blocks: usize) -> Result<Self> {
+        let block_size = flash.block_size();
+        let end = blocks.checked_mul(2).and_then(|b| b.checked_add(base_lba + 1));
+        if blocks == 0 || block_size < HEADER_SIZE + WRITE_RECORD_SIZE || end.is_none_or(|e| e > flash.block_count()) {
+            log::error!(target: "variable", "Invalid flash variable store layout: {blocks} blocks at LBA {base_lba}.");
+            return Err(EfiError::InvalidParameter);
+        }
+
+        let mut store = Self {
+            flash,
+            block_size,
+            blocks,
+            variable_lba: base_lba,
+            working_lba: base_lba + blocks,
+            spare_lba: base_lba + blocks + 1,
+            current: None,
+            append_offset: HEADER_SIZE,
+            needs_reclaim: false,
+            next_write_record: None,
+        };
+        store.recover()?;
+        store.mount()?;
+        Ok(store)
+    }
+
+    fn area_size(&self) -> usize {
+        self.blocks * self.block_size
+    }
+
+    fn variable_offset(&self, offset: usize) -> usize {
+        self.variable_lba * self.block_size + offset
+    }
+
+    fn working_offset(&self, offset: usize) -> usize {
+        self.working_lba * self.block_size + offset
+    }
+
+    fn read_at(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; len];
+        self.flash.read(offset, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn erase_area(&self, lba: usize) -> Result<()> {
+        (lba..lba + self.blocks).try_for_each(|lba| self.flash.erase(lba))
+    }
+
+    fn format_working_block(&mut self) -> Result<()> {
+        self.flash.erase(self.working_lba)?;
+        self.flash.write(self.working_offset(0), &header(ERASED, &[WORKING_SIGNATURE, FORMAT_VERSION]))?;
+        self.next_write_record = Some(HEADER_SIZE);
+        Ok(())
+    }
+
+    /// Completes or abandons a reclaim that was interrupted by a reset.
+    fn recover(&mut self) -> Result<()> {
+        let block = self.read_at(self.working_offset(0), self.block_size)?;
+        if read_u32(&block, 4) != WORKING_SIGNATURE || read_u32(&block, 8) != FORMAT_VERSION {
+            // A reclaim is only started with a valid working block, so there is nothing to recover.
+            if !is_erased(&block) {
+                log::warn!(target: "variable", "Reformatting invalid fault tolerant write working block.");
+            }
+            return self.format_working_block();
+        }
+
+        // Records are written in order, and only the last one can be incomplete. A partially written record is never
+        // reused, so the next record is the first one that is completely erased.
+        let mut last = None;
+        self.next_write_record = None;
+        for offset in (HEADER_SIZE..=self.block_size - WRITE_RECORD_SIZE).step_by(WRITE_RECORD_SIZE) {
+            let record = &block[offset..offset + WRITE_RECORD_SIZE];
+            if is_erased(record) {
+                self.next_write_record = Some(offset);
+                break;
+            }
+            last = Some(offset);
+        }
+
+        let Some(offset) = last else {
+            return Ok(());
+        };
+        let record = &block[offset..offset + WRITE_RECORD_SIZE];
+        match record[0] {
+            WRITE_ALLOCATED => {
+                log::warn!(target: "variable", "Abandoning interrupted reclaim; the variable area is unchanged.");
+            }
+            WRITE_SPARE_COMPLETE => {
+                let (crc, len) = (read_u32(record, 4), read_u32(record, 8) as usize);
+                let spare = self.read_at(self.spare_lba * self.block_size, len.min(self.area_size()))?;
+                if len > self.area_size() || crc32fast::hash(&spare) != crc {
+                    log::error!(target: "variable", "Spare area does not match the interrupted reclaim.");
+                } else {
+                    log::warn!(target: "variable", "Completing interrupted reclaim from the spare area.");
+                    self.erase_area(self.variable_lba)?;
+                    self.flash.write(self.variable_offset(0), &spare)?;
+                }
+            }
+            _ => return Ok(()),
+        }
+        self.flash.write(self.working_offset(offset), &[WRITE_COMPLETE])
+    }
+
+    /// Locates the latest image and the end of the log in the variable area.
+    fn mount(&mut self) -> Result<()> {
+        let area = self.read_at(self.variable_offset(0), self.area_size())?;
+        self.current = None;
+        self.needs_reclaim = false;
+        if is_erased(&area[..HEADER_SIZE]) {
+            self.flash.write(self.variable_offset(0), &header(ERASED, &[AREA_SIGNATURE, FORMAT_VERSION]))?;
+            self.append_offset = HEADER_SIZE;
+            return Ok(());
+        }
+        if read_u32(&area, 4) != AREA_SIGNATURE || read_u32(&area, 8) != FORMAT_VERSION {
+            log::warn!(target: "variable", "Variable area is corrupted and will be reformatted on the next write.");
+            self.needs_reclaim = true;
+            return Ok(());
+        }
+
+        let mut offset = HEADER_SIZE;
+        while offset + RECORD_HEADER_SIZE <= area.len() {
+            let record = &area[offset..offset + RECORD_HEADER_SIZE];
+            if is_erased(record) {
+                break;
+            }
+            let (len, crc) = (read_u32(record, 4) as usize, read_u32(record, 8));
+            let data_offset = offset + RECORD_HEADER_SIZE;
+            if record[0] == ERASED || len > area.len() - data_offset {
+                // A record header that was torn by a reset. Nothing can be appended after it.
+                self.needs_reclaim = true;
+                break;
+            }
+            if record[0] == RECORD_DATA_VALID && crc32fast::hash(&area[data_offset..data_offset + len]) == crc {
+                self.current = Some(offset);
+            }
+            offset = (data_offset + align(len)).min(area.len());
+        }
+        self.append_offset = offset;
+        if !is_erased(&area[offset..]) {
+            self.needs_reclaim = true;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the variable area with only `image`, staging it in the spare area first.
+    fn reclaim(&mut self, image: &[u8]) -> Result<()> {
+        log::info!(target: "variable", "Reclaiming flash variable store.");
+        let mut contents = Vec::with_capacity(HEADER_SIZE + RECORD_HEADER_SIZE + image.len());
+        contents.extend_from_slice(&header(ERASED, &[AREA_SIGNATURE, FORMAT_VERSION]));
+        contents.extend_from_slice(&header(RECORD_DATA_VALID, &[image.len() as u32, crc32fast::hash(image)]));
+        contents.extend_from_slice(image);
+
+        // Until the reclaim completes, the variable area cannot be appended to.
+        self.needs_reclaim = true;
+        let record = match self.next_write_record {
+            Some(record) => record,
+            None => {
+                self.format_working_block()?;
+                HEADER_SIZE
+            }
+        };
+        let next = record + WRITE_RECORD_SIZE;
+        self.next_write_record = (next + WRITE_RECORD_SIZE <= self.block_size).then_some(next);
+
+        let write_record = header(WRITE_ALLOCATED, &[crc32fast::hash(&contents), contents.len() as u32]);
+        self.flash.write(self.working_offset(record), &write_record)?;
+        self.erase_area(self.spare_lba)?;
+        self.flash.write(self.spare_lba * self.block_size, &contents)?;
+        self.flash.write(self.working_offset(record), &[WRITE_SPARE_COMPLETE])?;
+        self.erase_area(self.variable_lba)?;
+        self.flash.write(self.variable_offset(0), &contents)?;
+        self.flash.write(self.working_offset(record), &[WRITE_COMPLETE])?;
+
+        self.current = Some(HEADER_SIZE);
+        self.append_offset = HEADER_SIZE + RECORD_HEADER_SIZE + align(image.len());
+        self.needs_reclaim = false;
+        Ok(())
+    }
+}
+
+impl NvStorage for FaultTolerantStore {
+    fn read(&self) -> Result<Vec<u8>> {
+        let Some(offset) = self.current else {
+            return Ok(Vec::new());
+        };
+        let record = self.read_at(self.variable_offset(offset), RECORD_HEADER_SIZE)?;
+        self.read_at(self.variable_offset(offset + RECORD_HEADER_SIZE), read_u32(&record, 4) as usize)
+    }
+
+    fn write(&mut self, image: &[u8]) -> Result<()> {
+        if image.len() > self.capacity() {
+            return Err(EfiError::OutOfResources);
+        }
+        let offset = self.append_offset;
+        let size = RECORD_HEADER_SIZE + align(image.len());
+        if self.needs_reclaim || offset + size > self.area_size() {
+            return self.reclaim(image);
+        }
+
+        // Never reuse space that may have been partially written.
+        self.append_offset = offset + size;
+        let record = header(RECORD_HEADER_VALID, &[image.len() as u32, crc32fast::hash(image)]);
+        self.flash.write(self.variable_offset(offset), &record)?;
+        self.flash.write(self.variable_offset(offset + RECORD_HEADER_SIZE), image)?;
+        self.flash.write(self.variable_offset(offset), &[RECORD_DATA_VALID])?;
+
+        // The new image is durable at this point; a stale record is resolved in favor of the later one on mount.
+        if let Some(previous) = self.current.replace(offset)
+            && let Err(err) = self.flash.write(self.variable_offset(previous), &[RECORD_OBSOLETE])
+        {
+            log::warn!(target: "variable", "Failed to retire previous variable image: {err:?}");
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.area_size() - HEADER_SIZE - RECORD_HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::service::flash_device::MockFlashDevice;
+    use alloc::boxed::Box;
+    use core::cell::{Cell, RefCell};
+
+    const BLOCK_SIZE: usize = 0x100;
+
+    /// A simulated NOR flash device that can lose power after a given number of write and erase operations.
+    struct SimFlash {
+        data: RefCell<Vec<u8>>,
+        operations_until_failure: Cell<Option<usize>>,
+    }
+
+    impl SimFlash {
+        fn new(blocks: usize) -> &'static Self {
+            Box::leak(Box::new(Self {
+                data: RefCell::new(vec![ERASED; blocks * BLOCK_SIZE]),
+                operations_until_failure: Cell::new(None),
+            }))
+        }
+
+        fn fail_after(&self, operations: usize) {
+            self.operations_until_failure.set(Some(operations));
+        }
+
+        /// Returns true if power has been lost, updating the number of remaining operations.
+        fn power_lost(&self) -> bool {
+            match self.operations_until_failure.get() {
+                Some(0) => true,
+                Some(n) => {
+                    self.operations_until_failure.set(Some(n - 1));
+                    false
+                }
+                None => false,
+            }
+        }
+
+        fn restore_power(&self) {
+            self.operations_until_failure.set(None);
+        }
+    }
+
+    impl FlashDevice for SimFlash {
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn block_count(&self) -> usize {
+            self.data.borrow().len() / BLOCK_SIZE
+        }
+
+        fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+            buffer.copy_from_slice(&self.data.borrow()[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn write(&self, offset: usize, data: &[u8]) -> Result<()> {
+            // Power loss tears the write in half.
+            let torn = self.power_lost();
+            let len = if torn { data.len() / 2 } else { data.len() };
+            let mut flash = self.data.borrow_mut();
+            for (dst, src) in flash[offset..offset + len].iter_mut().zip(data) {
+                *dst &= src;
+            }
+            if torn { Err(EfiError::DeviceError) } else { Ok(()) }
+        }
+
+        fn erase(&self, lba: usize) -> Result<()> {
+            let torn = self.power_lost();
+            let len = if torn { BLOCK_SIZE / 2 } else { BLOCK_SIZE };
+            self.data.borrow_mut()[lba * BLOCK_SIZE..lba * BLOCK_SIZE + len].fill(ERASED);
+            if torn { Err(EfiError::DeviceError) } else { Ok(()) }
+        }
+    }
+
+    fn image(seed: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| seed.wrapping_add(i as u8)).collect()
+    }
+
+    #[test]
+    fn test_invalid_layout_is_rejected() {
+        let flash = SimFlash::new(4);
+        assert!(FaultTolerantStore::new(flash, 0, 0).is_err());
+        assert!(FaultTolerantStore::new(flash, 0, 2).is_err());
+        assert!(FaultTolerantStore::new(flash, 1, 1).is_ok());
+        assert!(FaultTolerantStore::new(flash, usize::MAX, usize::MAX).is_err());
+
+        let mut tiny = MockFlashDevice::new();
+        tiny.expect_block_size().return_const(16usize);
+        tiny.expect_block_count().return_const(16usize);
+        assert_eq!(FaultTolerantStore::new(Box::leak(Box::new(tiny)), 0, 1).err(), Some(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn test_blank_flash_is_formatted() {
+        let flash = SimFlash::new(5);
+        let store = FaultTolerantStore::new(flash, 0, 2).unwrap();
+        assert!(store.read().unwrap().is_empty());
+        assert_eq!(store.capacity(), 2 * BLOCK_SIZE - HEADER_SIZE - RECORD_HEADER_SIZE);
+
+        let data = flash.data.borrow();
+        assert_eq!(read_u32(&data, 4), AREA_SIGNATURE);
+        assert_eq!(read_u32(&data[2 * BLOCK_SIZE..], 4), WORKING_SIGNATURE);
+    }
+
+    #[test]
+    fn test_images_persist_across_reclaims() {
+        let flash = SimFlash::new(5);
+        let mut store = FaultTolerantStore::new(flash, 0, 2).unwrap();
+
+        // Each image consumes 0x70 bytes of the 0x1F0 byte log, so reclaims happen regularly.
+        for seed in 0..20 {
+            store.write(&image(seed, 0x5A)).unwrap();
+            assert_eq!(store.read().unwrap(), image(seed, 0x5A));
+            let remounted = FaultTolerantStore::new(flash, 0, 2).unwrap();
+            assert_eq!(remounted.read().unwrap(), image(seed, 0x5A));
+        }
+
+        // The working block fills up with records and is reformatted.
+        let store = FaultTolerantStore::new(flash, 0, 2).unwrap();
+        assert!(store.next_write_record.is_some());
+    }
+
+    #[test]
+    fn test_oversized_image_is_rejected() {
+        let flash = SimFlash::new(3);
+        let mut store = FaultTolerantStore::new(flash, 0, 1).unwrap();
+        assert_eq!(store.write(&image(0, BLOCK_SIZE)), Err(EfiError::OutOfResources));
+        store.write(&image(0, store.capacity())).unwrap();
+        assert_eq!(FaultTolerantStore::new(flash, 0, 1).unwrap().read().unwrap(), image(0, store.capacity()));
+    }
+
+    #[test]
+    fn test_power_loss_at_every_operation_preserves_an_image() {
+        // Exercise both an append (seed 1) and a reclaim (seed 2, which does not fit after seed 1).
+        for (seed, len) in [(1, 0x40), (2, 0xC0)] {
+            for operations in 0.. {
+                let flash = SimFlash::new(5);
+                let mut store = FaultTolerantStore::new(flash, 0, 2).unwrap();
+                store.write(&image(0, 0x80)).unwrap();
+                if seed == 2 {
+                    store.write(&image(1, 0x40)).unwrap();
+                }
+                let previous = store.read().unwrap();
+
+                flash.fail_after(operations);
+                let result = store.write(&image(seed, len));
+                flash.restore_power();
+
+                let recovered = FaultTolerantStore::new(flash, 0, 2).unwrap().read().unwrap();
+                if result.is_ok() {
+                    assert_eq!(recovered, image(seed, len));
+                    break;
+                }
+                assert!(
+                    recovered == previous || recovered == image(seed, len),
+                    "lost image after {operations} operations"
+                );
+
+                // The store remains usable after recovery.
+                let mut store = FaultTolerantStore::new(flash, 0, 2).unwrap();
+                store.write(&image(9, 0x10)).unwrap();
+                assert_eq!(FaultTolerantStore::new(flash, 0, 2).unwrap().read().unwrap(), image(9, 0x10));
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupted_variable_area_is_reformatted_on_write() {
+        let flash = SimFlash::new(3);
+        flash.data.borrow_mut()[..4].copy_from_slice(b"JUNK");
+        let mut store = FaultTolerantStore::new(flash, 0, 1).unwrap();
+        assert!(store.read().unwrap().is_empty());
+        assert!(store.needs_reclaim);
+
+        store.write(&image(3, 0x20)).unwrap();
+        assert_eq!(FaultTolerantStore::new(flash, 0, 1).unwrap().read().unwrap(), image(3, 0x20));
+    }
+
+    #[test]
+    fn test_invalid_working_block_is_reformatted() {
+        let flash = SimFlash::new(3);
+        flash.data.borrow_mut()[BLOCK_SIZE..BLOCK_SIZE + 4].copy_from_slice(b"JUNK");
+        let store = FaultTolerantStore::new(flash, 0, 1).unwrap();
+        assert_eq!(store.next_write_record, Some(HEADER_SIZE));
+        assert_eq!(read_u32(&flash.data.borrow()[BLOCK_SIZE..], 4), WORKING_SIGNATURE);
+    }
+}
@@ -0,0 +1,50 @@
+//! UEFI Variable Services
+//!
+//! This crate provides UEFI variable services support for Patina based firmware:
+//!
+//! - [`store::VariableStore`]: An implementation of the UEFI variable services semantics, including authenticated
+//!   variables and the Secure Boot key variables, over pluggable non-volatile storage.
+//! - [`component::memory_store::MemoryVariableStore`]: A development component that produces the variable services
+//!   from memory, optionally persisting non-volatile variables in a reserved memory region. It is intended for
+//!   platform bring-up and emulators, allowing `Boot####`/`BootOrder` and Secure Boot key management to be exercised
+//!   before a flash backed variable store is available.
+//! - [`component::flash_store::FlashVariableStore`]: A component that produces the variable services from a
+//!   platform provided [`service::FlashDevice`], persisting non-volatile variables with fault tolerant writes
+//!   ([`ftw::FaultTolerantStore`]). It is intended for platforms that run the variable services outside of MM.
+//! - [`mor`]: The TCG Memory Overwrite Request variables, which either component can produce when
+//!   `memory_overwrite_request` is set in its configuration.
+//!
+//! ## Examples
+//!
+//! ```rust,ignore
+//! use patina_variable::{component::memory_store::MemoryVariableStore, config::MemoryVariableStoreConfig};
+//!
+//! patina_dxe_core::Core::default()
+//!   .init_memory(physical_hob_list)
+//!   .with_config(MemoryVariableStoreConfig {
+//!       nv_region_base: 0x7F00_0000,
+//!       nv_region_size: 0x4_0000,
+//!       ..Default::default()
+//!   })
+//!   .with_component(MemoryVariableStore::default())
+//!   .start()
+//!   .unwrap();
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![feature(coverage_attribute)]
+
+extern crate alloc;
+
+pub mod component;
+pub mod config;
+pub mod ftw;
+pub mod mor;
+pub mod service;
+pub mod store;
@@ -0,0 +1,221 @@
+//! Memory Overwrite Request (MOR) Support
+//!
+//! Definitions and write checks for the `MemoryOverwriteRequestControl` variable of the TCG Platform Reset Attack
+//! Mitigation Specification and the `MemoryOverwriteRequestControlLock` variable of the TCG Secure MOR
+//! implementation.
+//!
+//! The OS sets the [`MOR_CLEAR_MEMORY`] bit of `MemoryOverwriteRequestControl` while secrets are in memory and clears
+//! it on an orderly shutdown. If the bit is still set on the next boot, the platform clears memory before any code
+//! outside of the platform firmware runs. Memory is cleared before DXE, either by a pre-DXE phase that reads the
+//! variable itself or on request of the [`MemoryClearScheduler`](crate::service::MemoryClearScheduler) service.
+//!
+//! `MemoryOverwriteRequestControlLock` is unlocked on every boot. Once the OS locks it, with or without an 8 byte key,
+//! `MemoryOverwriteRequestControl` can no longer be changed until the lock is released with the same key or the
+//! platform resets. A write with a wrong key locks the variable until reset.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina::error::{EfiError, Result};
+use r_efi::efi;
+
+/// The name of the Memory Overwrite Request control variable.
+pub const MOR_CONTROL_NAME: &str = "MemoryOverwriteRequestControl";
+
+/// The namespace of the Memory Overwrite Request control variable.
+pub const MOR_CONTROL_GUID: efi::Guid =
+    efi::Guid::from_fields(0xe20939be, 0x32d4, 0x41be, 0xa1, 0x50, &[0x89, 0x7f, 0x85, 0xd4, 0x98, 0x29]);
+
+/// The name of the Memory Overwrite Request control lock variable.
+pub const MOR_LOCK_NAME: &str = "MemoryOverwriteRequestControlLock";
+
+/// The namespace of the Memory Overwrite Request control lock variable.
+pub const MOR_LOCK_GUID: efi::Guid =
+    efi::Guid::from_fields(0xbb983ccf, 0x151d, 0x40e1, 0xa0, 0x7b, &[0x4a, 0x17, 0xbe, 0x16, 0x82, 0x92]);
+
+/// The attributes of both Memory Overwrite Request variables.
+pub const MOR_ATTRIBUTES: u32 =
+    efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+
+/// Set in `MemoryOverwriteRequestControl` to request that memory is cleared on the next boot.
+pub const MOR_CLEAR_MEMORY: u8 = 0x01;
+
+/// Set in `MemoryOverwriteRequestControl` to keep the firmware from clearing [`MOR_CLEAR_MEMORY`] once memory has
+/// been cleared.
+pub const MOR_DISABLE_AUTO_DETECT: u8 = 0x10;
+
+/// The size of the key that `MemoryOverwriteRequestControlLock` can be locked with.
+pub const MOR_LOCK_KEY_SIZE: usize = 8;
+
+/// The `MemoryOverwriteRequestControlLock` value while unlocked.
+pub const MOR_LOCK_UNLOCKED: u8 = 0x00;
+
+/// The `MemoryOverwriteRequestControlLock` value while locked without a key.
+pub const MOR_LOCK_LOCKED: u8 = 0x01;
+
+/// The `MemoryOverwriteRequestControlLock` value while locked with a key.
+pub const MOR_LOCK_LOCKED_WITH_KEY: u8 = 0x02;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LockState {
+    Unlocked,
+    Locked,
+    LockedWithKey([u8; MOR_LOCK_KEY_SIZE]),
+}
+
+/// The state of `MemoryOverwriteRequestControlLock` for the current boot.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MorLock {
+    state: LockState,
+}
+
+impl core::fmt::Debug for MorLock {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // the key is a secret of the OS, so only the value is shown.
+        f.debug_struct("MorLock").field("value", &self.value()).finish()
+    }
+}
+
+impl Default for MorLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MorLock {
+    /// Creates an unlocked lock.
+    pub const fn new() -> Self {
+        Self { state: LockState::Unlocked }
+    }
+
+    /// Returns the value of `MemoryOverwriteRequestControlLock`.
+    pub fn value(&self) -> u8 {
+        match self.state {
+            LockState::Unlocked => MOR_LOCK_UNLOCKED,
+            LockState::Locked => MOR_LOCK_LOCKED,
+            LockState::LockedWithKey(_) => MOR_LOCK_LOCKED_WITH_KEY,
+        }
+    }
+
+    /// Returns true if `MemoryOverwriteRequestControl` is locked.
+    pub fn is_locked(&self) -> bool {
+        self.state != LockState::Unlocked
+    }
+
+    /// Applies a write of `data` with `attributes` to `MemoryOverwriteRequestControlLock`.
+    ///
+    /// While unlocked, a single byte of [`MOR_LOCK_LOCKED`] locks without a key and [`MOR_LOCK_KEY_SIZE`] bytes lock
+    /// with those bytes as the key. While locked with a key, writing the key unlocks and any other write locks without
+    /// a key. While locked without a key, all writes are denied.
+    pub fn write(&mut self, attributes: u32, data: &[u8]) -> Result<()> {
+        if attributes == 0 || data.is_empty() {
+            return Err(EfiError::WriteProtected);
+        }
+        if attributes != MOR_ATTRIBUTES {
+            return Err(EfiError::InvalidParameter);
+        }
+
+        match self.state {
+            LockState::Unlocked => match data {
+                [MOR_LOCK_UNLOCKED] => Ok(()),
+                [MOR_LOCK_LOCKED] => {
+                    self.state = LockState::Locked;
+                    Ok(())
+                }
+                _ => match <[u8; MOR_LOCK_KEY_SIZE]>::try_from(data) {
+                    Ok(key) => {
+                        self.state = LockState::LockedWithKey(key);
+                        Ok(())
+                    }
+                    Err(_) => Err(EfiError::InvalidParameter),
+                },
+            },
+            LockState::LockedWithKey(key) => {
+                // compare every byte, so that the time taken does not reveal how much of the key matched.
+                let matches =
+                    data.len() == key.len() && data.iter().zip(key).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0;
+                if matches {
+                    self.state = LockState::Unlocked;
+                    Ok(())
+                } else {
+                    log::warn!(target: "variable", "Wrong MOR lock key, locking until reset.");
+                    self.state = LockState::Locked;
+                    Err(EfiError::AccessDenied)
+                }
+            }
+            LockState::Locked => Err(EfiError::AccessDenied),
+        }
+    }
+
+    /// Checks a write of `data` with `attributes` to `MemoryOverwriteRequestControl`.
+    ///
+    /// The variable is a single byte with [`MOR_ATTRIBUTES`] that cannot be deleted, and cannot be written while
+    /// locked.
+    pub fn check_control_write(&self, attributes: u32, data: &[u8]) -> Result<()> {
+        if self.is_locked() {
+            return Err(EfiError::AccessDenied);
+        }
+        if attributes != MOR_ATTRIBUTES || data.len() != 1 {
+            return Err(EfiError::InvalidParameter);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; MOR_LOCK_KEY_SIZE] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    #[test]
+    fn test_lock_without_key_until_reset() {
+        let mut lock = MorLock::new();
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[MOR_LOCK_UNLOCKED]), Ok(()));
+        assert!(!lock.is_locked());
+
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[MOR_LOCK_LOCKED]), Ok(()));
+        assert_eq!(lock.value(), MOR_LOCK_LOCKED);
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[MOR_LOCK_UNLOCKED]), Err(EfiError::AccessDenied));
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &KEY), Err(EfiError::AccessDenied));
+        assert_eq!(lock.check_control_write(MOR_ATTRIBUTES, &[0]), Err(EfiError::AccessDenied));
+    }
+
+    #[test]
+    fn test_lock_with_key() {
+        let mut lock = MorLock::new();
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &KEY), Ok(()));
+        assert_eq!(lock.value(), MOR_LOCK_LOCKED_WITH_KEY);
+        assert_eq!(lock.check_control_write(MOR_ATTRIBUTES, &[0]), Err(EfiError::AccessDenied));
+
+        // the same key unlocks.
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &KEY), Ok(()));
+        assert_eq!(lock.value(), MOR_LOCK_UNLOCKED);
+        assert_eq!(lock.check_control_write(MOR_ATTRIBUTES, &[MOR_CLEAR_MEMORY]), Ok(()));
+
+        // a wrong key locks until reset.
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &KEY), Ok(()));
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[8, 7, 6, 5, 4, 3, 2, 1]), Err(EfiError::AccessDenied));
+        assert_eq!(lock.value(), MOR_LOCK_LOCKED);
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &KEY), Err(EfiError::AccessDenied));
+    }
+
+    #[test]
+    fn test_invalid_writes() {
+        let mut lock = MorLock::new();
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[]), Err(EfiError::WriteProtected));
+        assert_eq!(lock.write(0, &[MOR_LOCK_LOCKED]), Err(EfiError::WriteProtected));
+        assert_eq!(lock.write(efi::VARIABLE_BOOTSERVICE_ACCESS, &[MOR_LOCK_LOCKED]), Err(EfiError::InvalidParameter));
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[MOR_LOCK_LOCKED_WITH_KEY]), Err(EfiError::InvalidParameter));
+        assert_eq!(lock.write(MOR_ATTRIBUTES, &[0; 4]), Err(EfiError::InvalidParameter));
+        assert!(!lock.is_locked());
+
+        assert_eq!(lock.check_control_write(MOR_ATTRIBUTES, &[0, 0]), Err(EfiError::InvalidParameter));
+        assert_eq!(lock.check_control_write(MOR_ATTRIBUTES, &[]), Err(EfiError::InvalidParameter));
+        assert_eq!(lock.check_control_write(efi::VARIABLE_BOOTSERVICE_ACCESS, &[0]), Err(EfiError::InvalidParameter));
+    }
+}
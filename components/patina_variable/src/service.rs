@@ -0,0 +1,17 @@
+//! Variable Services Platform Services
+//!
+//! The services a platform may produce to back the variable store components.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+pub mod flash_device;
+pub mod memory_clear;
+pub mod variable_quota;
+
+pub use flash_device::FlashDevice;
+pub use memory_clear::MemoryClearScheduler;
+pub use variable_quota::VariableQuota;
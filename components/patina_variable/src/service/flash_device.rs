@@ -0,0 +1,41 @@
+//! Flash Device Service Trait
+//!
+//! A service that must be produced by the platform to use the
+//! [`FlashVariableStore`](crate::component::flash_store::FlashVariableStore) component. It provides block level access
+//! to the flash device (typically SPI NOR flash) that holds the non-volatile variable store.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Flash Device Service
+///
+/// Provides access to a block erasable flash device with NOR semantics: erasing a block sets all of its bytes to
+/// `0xFF`, and writing can only clear bits. Offsets are in bytes from the start of the device.
+///
+/// Implementations do not need to be fault tolerant; the variable store tolerates a reset during any read, write or
+/// erase operation.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait FlashDevice {
+    /// Returns the size in bytes of an erase block.
+    fn block_size(&self) -> usize;
+
+    /// Returns the number of erase blocks in the device.
+    fn block_count(&self) -> usize;
+
+    /// Reads `buffer.len()` bytes starting at `offset`.
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> patina::error::Result<()>;
+
+    /// Writes `data` starting at `offset`.
+    ///
+    /// Bits that are already clear in the device remain clear.
+    fn write(&self, offset: usize, data: &[u8]) -> patina::error::Result<()>;
+
+    /// Erases the block at `lba`, setting all of its bytes to `0xFF`.
+    fn erase(&self, lba: usize) -> patina::error::Result<()>;
+}
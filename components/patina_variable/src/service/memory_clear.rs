@@ -0,0 +1,26 @@
+//! Memory Clear Scheduler Service Trait
+//!
+//! A service that may be produced by the platform to clear memory on the next boot when the OS requested it through
+//! the `MemoryOverwriteRequestControl` variable (see [`crate::mor`]). Platforms whose pre-DXE phase reads the variable
+//! itself do not need it.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Memory Clear Scheduler Service
+///
+/// Called from ExitBootServices when [`MOR_CLEAR_MEMORY`](crate::mor::MOR_CLEAR_MEMORY) is set, so it must not
+/// allocate or free memory.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MemoryClearScheduler {
+    /// Schedules memory to be cleared early in the next boot, before any code outside of the platform firmware runs,
+    /// for example by setting a flag in a register preserved across reset or in the data the pre-DXE phase of the next
+    /// boot turns into a HOB.
+    fn schedule_memory_clear(&self) -> patina::error::Result<()>;
+}
@@ -0,0 +1,29 @@
+//! Variable Quota Service Trait
+//!
+//! A service that may be produced by the platform to limit the storage used by variables with given attributes, on top
+//! of the capacity of the variable store. For example, a quota on non-volatile runtime variables keeps an OS that
+//! creates `Boot####` variables in a loop from exhausting the flash needed by the rest of the firmware.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Variable Quota Service
+///
+/// The variable store charges each variable against the quota of its attributes (without
+/// [`VARIABLE_APPEND_WRITE`](r_efi::efi::VARIABLE_APPEND_WRITE)). SetVariable fails with
+/// [`EfiError::OutOfResources`](patina::error::EfiError::OutOfResources) when a write would exceed the quota, and
+/// QueryVariableInfo reports the quota as the maximum storage size for the attributes.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait VariableQuota {
+    /// Returns the size in bytes available to variables with exactly `attributes`, or `None` if they are only limited
+    /// by the capacity of the store.
+    ///
+    /// Sizes include the bookkeeping overhead the store charges for each variable.
+    fn quota(&self, attributes: u32) -> Option<usize>;
+}
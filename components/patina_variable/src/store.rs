@@ -0,0 +1,886 @@
+//! In-memory UEFI variable store.
+//!
+//! [`VariableStore`] implements the semantics of the UEFI variable services (GetVariable, GetNextVariableName,
+//! SetVariable and QueryVariableInfo) over an in-memory map. Non-volatile variables are additionally serialized to an
+//! [`NvStorage`] backend, if one is provided, after every change so that they survive a reset.
+//!
+//! Authenticated variables are supported through [`patina::runtime_services::variable_services::authenticated`].
+//! Writes to the Secure Boot key variables are authorized against the certificates enrolled in `PK` and `KEK`, and
+//! the `SetupMode` and `SecureBoot` variables are kept in sync with the presence of a Platform Key.
+//!
+//! The store can also produce the TCG Memory Overwrite Request variables, enforcing the checks of [`crate::mor`] on
+//! writes to them.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::mem::size_of;
+
+use patina::{
+    error::{EfiError, Result},
+    runtime_services::variable_services::authenticated::{
+        self, AuthVariableVerifier, AuthenticationState, GLOBAL_VARIABLE_GUID, SecureBootKey, Timestamp,
+    },
+};
+use r_efi::efi;
+
+use crate::{
+    mor::{self, MorLock},
+    service::{MemoryClearScheduler, VariableQuota},
+};
+
+/// The attributes that may be stored with a variable.
+const VALID_ATTRIBUTES: u32 = efi::VARIABLE_NON_VOLATILE
+    | efi::VARIABLE_BOOTSERVICE_ACCESS
+    | efi::VARIABLE_RUNTIME_ACCESS
+    | efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS
+    | efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS;
+
+/// The attributes that require an authentication descriptor on every write.
+const AUTHENTICATED_ATTRIBUTES: u32 =
+    efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS | efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS;
+
+/// The bookkeeping overhead charged against the store capacity for each variable.
+const VARIABLE_HEADER_SIZE: usize = 64;
+
+/// The signature of a serialized non-volatile variable image ("PVAR").
+const NV_IMAGE_SIGNATURE: u32 = u32::from_le_bytes(*b"PVAR");
+
+/// The version of the serialized non-volatile variable image.
+const NV_IMAGE_VERSION: u32 = 1;
+
+/// Backing storage for non-volatile variables.
+///
+/// The store hands the backend a complete, self-describing image of all non-volatile variables whenever one of them
+/// changes. The backend is responsible for making the image available to [`NvStorage::read`] on the next boot.
+#[cfg_attr(test, mockall::automock)]
+pub trait NvStorage {
+    /// Returns the most recently written image, or an empty buffer if no image has been written.
+    fn read(&self) -> Result<Vec<u8>>;
+
+    /// Persists `image`, replacing the previously written image.
+    fn write(&mut self, image: &[u8]) -> Result<()>;
+
+    /// Returns the maximum size of an image that can be persisted.
+    fn capacity(&self) -> usize;
+}
+
+/// The sizes reported by [`VariableStore::query_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageInfo {
+    /// The size of the storage space available for variables with the queried attributes.
+    pub maximum_storage_size: u64,
+    /// The remaining size of the storage space available for variables with the queried attributes.
+    pub remaining_storage_size: u64,
+    /// The maximum size of an individual variable with the queried attributes.
+    pub maximum_variable_size: u64,
+}
+
+/// The size limits of a [`VariableStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreLimits {
+    /// The maximum size of the data of a single variable.
+    pub max_variable_size: usize,
+    /// The total size available to non-volatile variables.
+    pub nv_store_size: usize,
+    /// The total size available to volatile variables.
+    pub volatile_store_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct VariableKey {
+    namespace: [u8; 16],
+    name: Vec<u16>,
+}
+
+impl VariableKey {
+    fn new(name: &[u16], namespace: &efi::Guid) -> Self {
+        let name = name.strip_suffix(&[0]).unwrap_or(name);
+        Self { namespace: *namespace.as_bytes(), name: name.to_vec() }
+    }
+
+    fn is(&self, name: &str, namespace: &efi::Guid) -> bool {
+        self.namespace == *namespace.as_bytes() && self.name.iter().copied().eq(name.encode_utf16())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Variable {
+    attributes: u32,
+    data: Vec<u8>,
+    auth: AuthenticationState,
+}
+
+impl Variable {
+    fn size(key: &VariableKey, data_len: usize) -> usize {
+        VARIABLE_HEADER_SIZE + (key.name.len() + 1) * size_of::<u16>() + data_len
+    }
+}
+
+/// An in-memory UEFI variable store with optional non-volatile persistence.
+pub struct VariableStore {
+    variables: BTreeMap<VariableKey, Variable>,
+    limits: StoreLimits,
+    nv_storage: Option<alloc::boxed::Box<dyn NvStorage + Send>>,
+    verifier: Option<&'static dyn AuthVariableVerifier>,
+    quota: Option<&'static dyn VariableQuota>,
+    mor_lock: Option<MorLock>,
+    memory_clear: Option<&'static dyn MemoryClearScheduler>,
+}
+
+// SAFETY: The verifier, quota and memory clear scheduler are services registered with the component storage, which
+// lives for the remainder of boot.
+// UEFI boot services execute on a single thread, so they are never accessed concurrently.
+unsafe impl Send for VariableStore {}
+
+impl VariableStore {
+    /// Creates an empty store with the given limits.
+    pub fn new(limits: StoreLimits) -> Self {
+        let mut store = Self {
+            variables: BTreeMap::new(),
+            limits,
+            nv_storage: None,
+            verifier: None,
+            quota: None,
+            mor_lock: None,
+            memory_clear: None,
+        };
+        store.refresh_secure_boot_mode();
+        store
+    }
+
+    /// Sets the verifier used to check the signatures of authenticated variable writes.
+    ///
+    /// Without a verifier, authenticated writes are only accepted for the Secure Boot key variables while in Setup
+    /// Mode.
+    pub fn with_verifier(mut self, verifier: &'static dyn AuthVariableVerifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Sets the quotas charged for variables by their attributes, on top of the store limits.
+    pub fn with_quota(mut self, quota: &'static dyn VariableQuota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Attaches non-volatile storage to the store, loading any variables it contains.
+    ///
+    /// A corrupted or incompatible image is discarded with a warning so that the platform can still boot.
+    pub fn with_nv_storage(mut self, nv_storage: alloc::boxed::Box<dyn NvStorage + Send>) -> Result<Self> {
+        let image = nv_storage.read()?;
+        self.limits.nv_store_size = self.limits.nv_store_size.min(nv_storage.capacity());
+        if !image.is_empty() {
+            match deserialize(&image) {
+                Ok(variables) => self.variables.extend(variables),
+                Err(err) => log::warn!("Discarding corrupted non-volatile variable image: {err:?}"),
+            }
+        }
+        self.nv_storage = Some(nv_storage);
+        self.refresh_secure_boot_mode();
+        Ok(self)
+    }
+
+    /// Produces the Memory Overwrite Request variables, creating `MemoryOverwriteRequestControl` if it does not exist
+    /// yet. Call after [`with_nv_storage`](Self::with_nv_storage), so that the value set by the OS is kept.
+    ///
+    /// If a `scheduler` is given, [`schedule_memory_clear`](Self::schedule_memory_clear) uses it to clear memory on
+    /// the next boot.
+    pub fn with_memory_overwrite_request(mut self, scheduler: Option<&'static dyn MemoryClearScheduler>) -> Self {
+        let key = VariableKey::new(&utf16(mor::MOR_CONTROL_NAME), &mor::MOR_CONTROL_GUID);
+        if !self.variables.get(&key).is_some_and(|v| v.attributes == mor::MOR_ATTRIBUTES && v.data.len() == 1) {
+            let variable = Variable {
+                attributes: mor::MOR_ATTRIBUTES,
+                data: alloc::vec![0],
+                auth: AuthenticationState::default(),
+            };
+            self.variables.insert(key, variable);
+            if let Err(err) = self.flush() {
+                log::error!(target: "variable", "Failed to persist {}: {err:?}", mor::MOR_CONTROL_NAME);
+            }
+        }
+        self.mor_lock = Some(MorLock::new());
+        self.memory_clear = scheduler;
+        self.refresh_mor_lock();
+        self
+    }
+
+    /// Returns the value of `MemoryOverwriteRequestControl`, if the store produces the Memory Overwrite Request
+    /// variables.
+    pub fn memory_overwrite_request(&self) -> Option<u8> {
+        self.mor_lock?;
+        let (_, data) = self.get(&utf16(mor::MOR_CONTROL_NAME), &mor::MOR_CONTROL_GUID).ok()?;
+        data.first().copied()
+    }
+
+    /// Clears [`MOR_CLEAR_MEMORY`](mor::MOR_CLEAR_MEMORY) from `MemoryOverwriteRequestControl`, as memory has been
+    /// cleared before DXE, unless the OS set [`MOR_DISABLE_AUTO_DETECT`](mor::MOR_DISABLE_AUTO_DETECT). The OS sets the
+    /// bit again when it boots.
+    pub fn auto_clear_memory_overwrite_request(&mut self) -> Result<()> {
+        let Some(value) = self.memory_overwrite_request() else {
+            return Ok(());
+        };
+        if value & mor::MOR_CLEAR_MEMORY == 0 || value & mor::MOR_DISABLE_AUTO_DETECT != 0 {
+            return Ok(());
+        }
+        let key = VariableKey::new(&utf16(mor::MOR_CONTROL_NAME), &mor::MOR_CONTROL_GUID);
+        if let Some(variable) = self.variables.get_mut(&key) {
+            variable.data[0] = value & !mor::MOR_CLEAR_MEMORY;
+        }
+        self.flush()
+    }
+
+    /// Asks the memory clear scheduler to clear memory on the next boot if [`MOR_CLEAR_MEMORY`](mor::MOR_CLEAR_MEMORY)
+    /// is set in `MemoryOverwriteRequestControl`. Returns true if a memory clear was scheduled.
+    pub fn schedule_memory_clear(&self) -> Result<bool> {
+        match (self.memory_overwrite_request(), self.memory_clear) {
+            (Some(value), Some(scheduler)) if value & mor::MOR_CLEAR_MEMORY != 0 => {
+                scheduler.schedule_memory_clear()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Returns the attributes and data of a variable.
+    pub fn get(&self, name: &[u16], namespace: &efi::Guid) -> Result<(u32, &[u8])> {
+        let key = VariableKey::new(name, namespace);
+        if key.name.is_empty() {
+            return Err(EfiError::InvalidParameter);
+        }
+        self.variables.get(&key).map(|v| (v.attributes, v.data.as_slice())).ok_or(EfiError::NotFound)
+    }
+
+    /// Returns the name (without a null terminator) and namespace of the variable following the given one.
+    ///
+    /// An empty `name` returns the first variable. Returns [`EfiError::InvalidParameter`] if the given variable does
+    /// not exist, and [`EfiError::NotFound`] once all variables have been returned.
+    pub fn get_next_name(&self, name: &[u16], namespace: &efi::Guid) -> Result<(&[u16], efi::Guid)> {
+        let key = VariableKey::new(name, namespace);
+        let mut iter = if key.name.is_empty() {
+            self.variables.range(..)
+        } else {
+            if !self.variables.contains_key(&key) {
+                return Err(EfiError::InvalidParameter);
+            }
+            let mut iter = self.variables.range(key..);
+            iter.next();
+            iter
+        };
+        iter.next().map(|(k, _)| (k.name.as_slice(), efi::Guid::from_bytes(&k.namespace))).ok_or(EfiError::NotFound)
+    }
+
+    /// Creates, updates, appends to or deletes a variable.
+    ///
+    /// Follows the semantics of `SetVariable()`: a write with no data or no access attributes deletes the variable,
+    /// and [`efi::VARIABLE_APPEND_WRITE`] appends to existing data.
+    pub fn set(&mut self, name: &[u16], namespace: &efi::Guid, attributes: u32, data: &[u8]) -> Result<()> {
+        let key = VariableKey::new(name, namespace);
+        if key.name.is_empty() {
+            return Err(EfiError::InvalidParameter);
+        }
+
+        let append = attributes & efi::VARIABLE_APPEND_WRITE != 0;
+        let stored_attributes = attributes & !efi::VARIABLE_APPEND_WRITE;
+        if stored_attributes & !VALID_ATTRIBUTES != 0 {
+            if attributes & (efi::VARIABLE_HARDWARE_ERROR_RECORD | efi::VARIABLE_AUTHENTICATED_WRITE_ACCESS) != 0 {
+                return Err(EfiError::Unsupported);
+            }
+            return Err(EfiError::InvalidParameter);
+        }
+        if stored_attributes & efi::VARIABLE_RUNTIME_ACCESS != 0
+            && stored_attributes & efi::VARIABLE_BOOTSERVICE_ACCESS == 0
+        {
+            return Err(EfiError::InvalidParameter);
+        }
+        if stored_attributes & AUTHENTICATED_ATTRIBUTES == AUTHENTICATED_ATTRIBUTES {
+            return Err(EfiError::InvalidParameter);
+        }
+        if self.is_read_only(&key) {
+            return Err(EfiError::WriteProtected);
+        }
+        if let Some(mor_lock) = self.mor_lock.as_mut() {
+            if key.is(mor::MOR_LOCK_NAME, &mor::MOR_LOCK_GUID) {
+                let result = mor_lock.write(attributes, data);
+                self.refresh_mor_lock();
+                return result;
+            }
+            if key.is(mor::MOR_CONTROL_NAME, &mor::MOR_CONTROL_GUID) {
+                mor_lock.check_control_write(attributes, data)?;
+            }
+        }
+
+        let existing = self.variables.get(&key);
+        if let Some(existing) = existing
+            && stored_attributes != 0
+            && existing.attributes != stored_attributes
+        {
+            return Err(EfiError::InvalidParameter);
+        }
+
+        let authorities = authenticated::secure_boot_authorities(&key.name, namespace);
+        if authorities.is_some() && stored_attributes & efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS == 0 {
+            return Err(EfiError::SecurityViolation);
+        }
+
+        let (payload, auth) = if stored_attributes & AUTHENTICATED_ATTRIBUTES != 0 {
+            let verifier: &dyn AuthVariableVerifier = match (authorities, self.verifier) {
+                (Some(_), _) if self.in_setup_mode() => &SetupModeVerifier,
+                (_, Some(verifier)) => verifier,
+                (_, None) => {
+                    log::error!("Authenticated variable write rejected: no verifier is available.");
+                    return Err(EfiError::SecurityViolation);
+                }
+            };
+            let anchors = self.trust_anchors(authorities)?;
+            let anchors: Vec<&[u8]> = anchors.iter().map(|a| a.as_slice()).collect();
+            authenticated::authenticate_write(
+                verifier,
+                &key.name,
+                namespace,
+                attributes,
+                data,
+                existing.map(|v| &v.auth),
+                &anchors,
+            )?
+        } else {
+            if existing.is_some_and(|v| v.attributes & AUTHENTICATED_ATTRIBUTES != 0) {
+                return Err(EfiError::SecurityViolation);
+            }
+            (data, AuthenticationState::default())
+        };
+
+        let delete = !append && (payload.is_empty() || stored_attributes & efi::VARIABLE_BOOTSERVICE_ACCESS == 0);
+        let previous = if delete {
+            match self.variables.remove(&key) {
+                Some(previous) => Some(previous),
+                None => return Err(EfiError::NotFound),
+            }
+        } else {
+            if append && payload.is_empty() {
+                return Ok(());
+            }
+            let mut new_data = match (append, existing) {
+                (true, Some(existing)) => existing.data.clone(),
+                _ => Vec::new(),
+            };
+            new_data.extend_from_slice(payload);
+            if new_data.len() > self.limits.max_variable_size {
+                return Err(EfiError::OutOfResources);
+            }
+            self.check_capacity(&key, stored_attributes, new_data.len())?;
+            self.variables.insert(key.clone(), Variable { attributes: stored_attributes, data: new_data, auth })
+        };
+
+        let non_volatile = previous.as_ref().map_or(stored_attributes, |v| v.attributes) & efi::VARIABLE_NON_VOLATILE;
+        if non_volatile != 0
+            && let Err(err) = self.flush()
+        {
+            // Roll back so the in-memory view matches what is persisted.
+            match previous {
+                Some(previous) => self.variables.insert(key, previous),
+                None => self.variables.remove(&key),
+            };
+            return Err(err);
+        }
+
+        if key.is("PK", &GLOBAL_VARIABLE_GUID) {
+            self.refresh_secure_boot_mode();
+        }
+        Ok(())
+    }
+
+    /// Returns the storage information for variables with the given attributes.
+    pub fn query_info(&self, attributes: u32) -> Result<StorageInfo> {
+        if attributes & efi::VARIABLE_BOOTSERVICE_ACCESS == 0 || attributes & !VALID_ATTRIBUTES != 0 {
+            if attributes & efi::VARIABLE_HARDWARE_ERROR_RECORD != 0 {
+                return Err(EfiError::Unsupported);
+            }
+            return Err(EfiError::InvalidParameter);
+        }
+        let non_volatile = attributes & efi::VARIABLE_NON_VOLATILE != 0;
+        let mut maximum = self.capacity(non_volatile);
+        let mut remaining = maximum.saturating_sub(self.used(non_volatile));
+        if let Some(quota) = self.quota_for(attributes) {
+            maximum = maximum.min(quota);
+            remaining = remaining.min(quota.saturating_sub(self.used_by(attributes)));
+        }
+        Ok(StorageInfo {
+            maximum_storage_size: maximum as u64,
+            remaining_storage_size: remaining as u64,
+            maximum_variable_size: self.limits.max_variable_size.min(maximum) as u64,
+        })
+    }
+
+    /// Returns true if the platform is in Secure Boot Setup Mode, i.e. no Platform Key is enrolled.
+    pub fn in_setup_mode(&self) -> bool {
+        !self.variables.contains_key(&VariableKey::new(&utf16("PK"), &GLOBAL_VARIABLE_GUID))
+    }
+
+    fn capacity(&self, non_volatile: bool) -> usize {
+        if non_volatile { self.limits.nv_store_size } else { self.limits.volatile_store_size }
+    }
+
+    fn used(&self, non_volatile: bool) -> usize {
+        self.variables
+            .iter()
+            .filter(|(_, v)| (v.attributes & efi::VARIABLE_NON_VOLATILE != 0) == non_volatile)
+            .map(|(k, v)| Variable::size(k, v.data.len()))
+            .sum()
+    }
+
+    fn quota_for(&self, attributes: u32) -> Option<usize> {
+        self.quota.and_then(|quota| quota.quota(attributes))
+    }
+
+    fn used_by(&self, attributes: u32) -> usize {
+        self.variables
+            .iter()
+            .filter(|(_, v)| v.attributes == attributes)
+            .map(|(k, v)| Variable::size(k, v.data.len()))
+            .sum()
+    }
+
+    fn check_capacity(&self, key: &VariableKey, attributes: u32, data_len: usize) -> Result<()> {
+        let non_volatile = attributes & efi::VARIABLE_NON_VOLATILE != 0;
+        // An existing variable always has the same attributes, so it is charged against the same capacity and quota.
+        let replaced = self.variables.get(key).map_or(0, |v| Variable::size(key, v.data.len()));
+        let size = Variable::size(key, data_len);
+        if self.used(non_volatile) - replaced + size > self.capacity(non_volatile) {
+            return Err(EfiError::OutOfResources);
+        }
+        if let Some(quota) = self.quota_for(attributes)
+            && self.used_by(attributes) - replaced + size > quota
+        {
+            log::warn!(target: "variable", "Variable quota of {quota:#x} bytes exceeded for attributes {attributes:#x}.");
+            return Err(EfiError::OutOfResources);
+        }
+        Ok(())
+    }
+
+    fn trust_anchors(&self, authorities: Option<&[SecureBootKey]>) -> Result<Vec<Vec<u8>>> {
+        let mut anchors = Vec::new();
+        for authority in authorities.unwrap_or_default() {
+            let name = match authority {
+                SecureBootKey::Pk => "PK",
+                SecureBootKey::Kek => "KEK",
+            };
+            if let Some(variable) = self.variables.get(&VariableKey::new(&utf16(name), &GLOBAL_VARIABLE_GUID)) {
+                anchors.extend(authenticated::x509_certificates(&variable.data)?.into_iter().map(|c| c.to_vec()));
+            }
+        }
+        Ok(anchors)
+    }
+
+    fn is_read_only(&self, key: &VariableKey) -> bool {
+        key.is("SetupMode", &GLOBAL_VARIABLE_GUID) || key.is("SecureBoot", &GLOBAL_VARIABLE_GUID)
+    }
+
+    /// Updates the read-only `SetupMode` and `SecureBoot` variables to reflect whether a Platform Key is enrolled.
+    fn refresh_secure_boot_mode(&mut self) {
+        let setup_mode = self.in_setup_mode() as u8;
+        let attributes = efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+        for (name, value) in [("SetupMode", setup_mode), ("SecureBoot", 1 - setup_mode)] {
+            self.variables.insert(
+                VariableKey::new(&utf16(name), &GLOBAL_VARIABLE_GUID),
+                Variable { attributes, data: alloc::vec![value], auth: AuthenticationState::default() },
+            );
+        }
+    }
+
+    /// Updates the `MemoryOverwriteRequestControlLock` variable to reflect the state of the lock.
+    fn refresh_mor_lock(&mut self) {
+        if let Some(mor_lock) = self.mor_lock {
+            self.variables.insert(
+                VariableKey::new(&utf16(mor::MOR_LOCK_NAME), &mor::MOR_LOCK_GUID),
+                Variable {
+                    attributes: mor::MOR_ATTRIBUTES,
+                    data: alloc::vec![mor_lock.value()],
+                    auth: AuthenticationState::default(),
+                },
+            );
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let Some(nv_storage) = self.nv_storage.as_mut() else {
+            return Ok(());
+        };
+        // the lock is non-volatile by specification, but it is unlocked on every boot, so it is never persisted.
+        let image = serialize(self.variables.iter().filter(|(k, v)| {
+            v.attributes & efi::VARIABLE_NON_VOLATILE != 0 && !k.is(mor::MOR_LOCK_NAME, &mor::MOR_LOCK_GUID)
+        }));
+        if image.len() > nv_storage.capacity() {
+            return Err(EfiError::OutOfResources);
+        }
+        nv_storage.write(&image)
+    }
+}
+
+/// Accepts all signatures. Used for Secure Boot key variables while no Platform Key is enrolled.
+struct SetupModeVerifier;
+
+impl AuthVariableVerifier for SetupModeVerifier {
+    fn verify_pkcs7(&self, _signature: &[u8], _payload: &[u8], _trust_anchors: &[&[u8]]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn utf16(s: &str) -> Vec<u16> {
+    s.encode_utf16().collect()
+}
+
+/// Serializes variables into a non-volatile image.
+///
+/// The image is a header (signature, version, size) followed by one record per variable:
+/// name length, data length, attributes, namespace, timestamp, nonce length, signing certificate length, and the
+/// name, data, nonce and certificate bytes. All integers are little endian.
+fn serialize<'a>(variables: impl Iterator<Item = (&'a VariableKey, &'a Variable)>) -> Vec<u8> {
+    let mut image = Vec::new();
+    image.extend_from_slice(&NV_IMAGE_SIGNATURE.to_le_bytes());
+    image.extend_from_slice(&NV_IMAGE_VERSION.to_le_bytes());
+    image.extend_from_slice(&0u32.to_le_bytes());
+    for (key, variable) in variables {
+        let nonce = variable.auth.nonce.as_deref().unwrap_or_default();
+        let cert = variable.auth.signing_cert.as_deref().unwrap_or_default();
+        image.extend_from_slice(&(key.name.len() as u32).to_le_bytes());
+        image.extend_from_slice(&(variable.data.len() as u32).to_le_bytes());
+        image.extend_from_slice(&variable.attributes.to_le_bytes());
+        image.extend_from_slice(&key.namespace);
+        image.push(variable.auth.timestamp.is_some() as u8);
+        image.extend_from_slice(&variable.auth.timestamp.unwrap_or_default().to_bytes());
+        image.extend_from_slice(&(nonce.len() as u32).to_le_bytes());
+        image.extend_from_slice(&(cert.len() as u32).to_le_bytes());
+        key.name.iter().for_each(|c| image.extend_from_slice(&c.to_le_bytes()));
+        image.extend_from_slice(&variable.data);
+        image.extend_from_slice(nonce);
+        image.extend_from_slice(cert);
+    }
+    let size = image.len() as u32;
+    image[8..12].copy_from_slice(&size.to_le_bytes());
+    image
+}
+
+/// Deserializes a non-volatile image produced by [`serialize`].
+fn deserialize(image: &[u8]) -> Result<Vec<(VariableKey, Variable)>> {
+    let mut reader = Reader(image);
+    if reader.u32()? != NV_IMAGE_SIGNATURE || reader.u32()? != NV_IMAGE_VERSION {
+        return Err(EfiError::VolumeCorrupted);
+    }
+    let size = reader.u32()? as usize;
+    let mut reader = Reader(image.get(12..size).ok_or(EfiError::VolumeCorrupted)?);
+
+    let mut variables = Vec::new();
+    while !reader.0.is_empty() {
+        let name_len = reader.u32()? as usize;
+        let data_len = reader.u32()? as usize;
+        let attributes = reader.u32()?;
+        let namespace: [u8; 16] = reader.bytes(16)?.try_into().unwrap();
+        let has_timestamp = reader.bytes(1)?[0] != 0;
+        let timestamp =
+            Timestamp::parse(reader.bytes(size_of::<efi::Time>())?).map_err(|_| EfiError::VolumeCorrupted)?;
+        let nonce_len = reader.u32()? as usize;
+        let cert_len = reader.u32()? as usize;
+        let name = reader
+            .bytes(name_len.checked_mul(2).ok_or(EfiError::VolumeCorrupted)?)?
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        let data = reader.bytes(data_len)?.to_vec();
+        let nonce = reader.bytes(nonce_len)?;
+        let cert = reader.bytes(cert_len)?;
+        let auth = AuthenticationState {
+            timestamp: has_timestamp.then_some(timestamp),
+            nonce: (!nonce.is_empty()).then(|| nonce.to_vec()),
+            signing_cert: (!cert.is_empty()).then(|| cert.to_vec()),
+        };
+        variables.push((VariableKey { namespace, name }, Variable { attributes, data, auth }));
+    }
+    Ok(variables)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(EfiError::VolumeCorrupted);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::{boxed::Box, vec};
+    use patina::runtime_services::variable_services::authenticated::IMAGE_SECURITY_DATABASE_GUID;
+    use std::sync::{Arc, Mutex};
+
+    const VENDOR_GUID: efi::Guid =
+        efi::Guid::from_fields(0x12345678, 0x1234, 0x5678, 0x9a, 0xbc, &[0xde, 0xf0, 0x12, 0x34, 0x56, 0x78]);
+    const BS_RT: u32 = efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+    const NV_BS_RT: u32 = efi::VARIABLE_NON_VOLATILE | BS_RT;
+
+    const LIMITS: StoreLimits =
+        StoreLimits { max_variable_size: 0x100, nv_store_size: 0x400, volatile_store_size: 0x400 };
+
+    /// An `NvStorage` that shares its image with the test, emulating memory that survives a reset.
+    #[derive(Clone, Default)]
+    struct SharedStorage(Arc<Mutex<Vec<u8>>>);
+
+    impl NvStorage for SharedStorage {
+        fn read(&self) -> Result<Vec<u8>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        fn write(&mut self, image: &[u8]) -> Result<()> {
+            *self.0.lock().unwrap() = image.to_vec();
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            0x1000
+        }
+    }
+
+    fn auth2(second: u8, data: &[u8]) -> Vec<u8> {
+        let timestamp = Timestamp { year: 2025, month: 1, day: 1, hour: 0, minute: 0, second };
+        let mut buffer = timestamp.to_bytes().to_vec();
+        buffer.extend_from_slice(&(24u32 + 1).to_le_bytes());
+        buffer.extend_from_slice(&authenticated::WIN_CERT_REVISION.to_le_bytes());
+        buffer.extend_from_slice(&authenticated::WIN_CERT_TYPE_EFI_GUID.to_le_bytes());
+        buffer.extend_from_slice(authenticated::CERT_TYPE_PKCS7_GUID.as_bytes());
+        buffer.push(0xAA);
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    #[test]
+    fn test_set_get_delete() {
+        let mut store = VariableStore::new(LIMITS);
+        let name = utf16("BootOrder\0");
+
+        assert_eq!(store.get(&name, &VENDOR_GUID), Err(EfiError::NotFound));
+        store.set(&name, &VENDOR_GUID, NV_BS_RT, &[1, 0]).unwrap();
+        assert_eq!(store.get(&name, &VENDOR_GUID), Ok((NV_BS_RT, &[1u8, 0][..])));
+
+        store.set(&name, &VENDOR_GUID, NV_BS_RT | efi::VARIABLE_APPEND_WRITE, &[2, 0]).unwrap();
+        assert_eq!(store.get(&name, &VENDOR_GUID), Ok((NV_BS_RT, &[1u8, 0, 2, 0][..])));
+
+        // Attributes of an existing variable cannot change.
+        assert_eq!(store.set(&name, &VENDOR_GUID, BS_RT, &[1]), Err(EfiError::InvalidParameter));
+
+        store.set(&name, &VENDOR_GUID, 0, &[]).unwrap();
+        assert_eq!(store.get(&name, &VENDOR_GUID), Err(EfiError::NotFound));
+        assert_eq!(store.set(&name, &VENDOR_GUID, 0, &[]), Err(EfiError::NotFound));
+    }
+
+    #[test]
+    fn test_invalid_attributes() {
+        let mut store = VariableStore::new(LIMITS);
+        let name = utf16("Test");
+        assert_eq!(store.set(&name, &VENDOR_GUID, efi::VARIABLE_RUNTIME_ACCESS, &[1]), Err(EfiError::InvalidParameter));
+        assert_eq!(
+            store.set(&name, &VENDOR_GUID, BS_RT | efi::VARIABLE_HARDWARE_ERROR_RECORD, &[1]),
+            Err(EfiError::Unsupported)
+        );
+        assert_eq!(store.set(&[0], &VENDOR_GUID, BS_RT, &[1]), Err(EfiError::InvalidParameter));
+        assert_eq!(store.set(&utf16("SetupMode"), &GLOBAL_VARIABLE_GUID, BS_RT, &[0]), Err(EfiError::WriteProtected));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut store = VariableStore::new(LIMITS);
+        assert_eq!(store.set(&utf16("Big"), &VENDOR_GUID, BS_RT, &[0; 0x101]), Err(EfiError::OutOfResources));
+
+        let before = store.query_info(efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS).unwrap();
+        assert_eq!(before.maximum_storage_size, 0x400);
+        assert_eq!(before.remaining_storage_size, 0x400);
+
+        for i in 0..3u16 {
+            store.set(&[b'A' as u16 + i], &VENDOR_GUID, NV_BS_RT, &[0; 0x100]).unwrap();
+        }
+        assert_eq!(store.set(&utf16("D"), &VENDOR_GUID, NV_BS_RT, &[0; 0x100]), Err(EfiError::OutOfResources));
+
+        let after = store.query_info(efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS).unwrap();
+        assert_eq!(after.remaining_storage_size, 0x400 - 3 * (VARIABLE_HEADER_SIZE + 4 + 0x100) as u64);
+        assert_eq!(store.query_info(0), Err(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn test_quota_limits_variables_with_the_same_attributes() {
+        let mut quota = crate::service::variable_quota::MockVariableQuota::new();
+        quota.expect_quota().returning(|attributes| (attributes == NV_BS_RT).then_some(0x200));
+        let mut store = VariableStore::new(LIMITS).with_quota(Box::leak(Box::new(quota)));
+
+        let info = store.query_info(NV_BS_RT).unwrap();
+        assert_eq!((info.maximum_storage_size, info.remaining_storage_size), (0x200, 0x200));
+        assert_eq!(info.maximum_variable_size, 0x100);
+
+        // Each Boot#### variable takes 0x64 + 0x12 bytes, so only the first two fit in the quota.
+        for i in 0..2u16 {
+            store.set(&utf16(&std::format!("Boot{i:04X}")), &VENDOR_GUID, NV_BS_RT, &[0; 0x64]).unwrap();
+        }
+        assert_eq!(store.set(&utf16("Boot0002"), &VENDOR_GUID, NV_BS_RT, &[0; 0x64]), Err(EfiError::OutOfResources));
+        let info = store.query_info(NV_BS_RT).unwrap();
+        assert_eq!(info.remaining_storage_size, 0x200 - 2 * (VARIABLE_HEADER_SIZE + 0x12 + 0x64) as u64);
+
+        // Variables with other attributes are only limited by the store capacity.
+        store
+            .set(
+                &utf16("Other"),
+                &VENDOR_GUID,
+                efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS,
+                &[0; 0x64],
+            )
+            .unwrap();
+        assert_eq!(store.query_info(efi::VARIABLE_BOOTSERVICE_ACCESS).unwrap().maximum_storage_size, 0x400);
+    }
+
+    #[test]
+    fn test_get_next_name() {
+        let mut store = VariableStore::new(LIMITS);
+        store.set(&utf16("A"), &VENDOR_GUID, BS_RT, &[1]).unwrap();
+        store.set(&utf16("B"), &VENDOR_GUID, BS_RT, &[1]).unwrap();
+
+        let mut names = Vec::new();
+        let mut current = (vec![0u16], VENDOR_GUID);
+        loop {
+            match store.get_next_name(&current.0, &current.1) {
+                Ok((name, namespace)) => {
+                    names.push(std::string::String::from_utf16(name).unwrap());
+                    current = (name.to_vec(), namespace);
+                }
+                Err(err) => {
+                    assert_eq!(err, EfiError::NotFound);
+                    break;
+                }
+            }
+        }
+        names.sort();
+        assert_eq!(names, ["A", "B", "SecureBoot", "SetupMode"]);
+        assert_eq!(store.get_next_name(&utf16("Missing"), &VENDOR_GUID), Err(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn test_non_volatile_variables_are_persisted() {
+        let storage = SharedStorage::default();
+        let mut store = VariableStore::new(LIMITS).with_nv_storage(Box::new(storage.clone())).unwrap();
+        store.set(&utf16("Boot0000"), &VENDOR_GUID, NV_BS_RT, &[1, 2, 3]).unwrap();
+        store.set(&utf16("Volatile"), &VENDOR_GUID, BS_RT, &[4]).unwrap();
+
+        let store = VariableStore::new(LIMITS).with_nv_storage(Box::new(storage)).unwrap();
+        assert_eq!(store.get(&utf16("Boot0000"), &VENDOR_GUID), Ok((NV_BS_RT, &[1u8, 2, 3][..])));
+        assert_eq!(store.get(&utf16("Volatile"), &VENDOR_GUID), Err(EfiError::NotFound));
+    }
+
+    #[test]
+    fn test_failed_flush_rolls_back() {
+        let mut storage = MockNvStorage::new();
+        storage.expect_read().returning(|| Ok(Vec::new()));
+        storage.expect_capacity().return_const(0x1000usize);
+        storage.expect_write().returning(|_| Err(EfiError::DeviceError));
+
+        let mut store = VariableStore::new(LIMITS).with_nv_storage(Box::new(storage)).unwrap();
+        assert_eq!(store.set(&utf16("Boot0000"), &VENDOR_GUID, NV_BS_RT, &[1]), Err(EfiError::DeviceError));
+        assert_eq!(store.get(&utf16("Boot0000"), &VENDOR_GUID), Err(EfiError::NotFound));
+    }
+
+    #[test]
+    fn test_corrupted_image_is_discarded() {
+        let storage = SharedStorage::default();
+        *storage.0.lock().unwrap() = vec![0xFF; 32];
+        let store = VariableStore::new(LIMITS).with_nv_storage(Box::new(storage)).unwrap();
+        assert!(store.in_setup_mode());
+    }
+
+    #[test]
+    fn test_secure_boot_enrollment() {
+        let mut store = VariableStore::new(LIMITS);
+        let attributes = NV_BS_RT | efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS;
+        let secure_boot = utf16("SecureBoot");
+        assert_eq!(store.get(&secure_boot, &GLOBAL_VARIABLE_GUID), Ok((BS_RT, &[0u8][..])));
+
+        // Secure Boot key variables must be authenticated.
+        assert_eq!(
+            store.set(&utf16("db"), &IMAGE_SECURITY_DATABASE_GUID, NV_BS_RT, &[1]),
+            Err(EfiError::SecurityViolation)
+        );
+
+        // In setup mode, key variables can be enrolled without a verifier.
+        store.set(&utf16("db"), &IMAGE_SECURITY_DATABASE_GUID, attributes, &auth2(1, &[1])).unwrap();
+        store.set(&utf16("PK"), &GLOBAL_VARIABLE_GUID, attributes, &auth2(1, &[2])).unwrap();
+        assert!(!store.in_setup_mode());
+        assert_eq!(store.get(&secure_boot, &GLOBAL_VARIABLE_GUID), Ok((BS_RT, &[1u8][..])));
+
+        // Once a PK is enrolled, a verifier is required.
+        assert_eq!(
+            store.set(&utf16("db"), &IMAGE_SECURITY_DATABASE_GUID, attributes, &auth2(2, &[3])),
+            Err(EfiError::SecurityViolation)
+        );
+    }
+
+    #[test]
+    fn test_memory_overwrite_request_variables() {
+        let storage = SharedStorage::default();
+        let control = utf16(mor::MOR_CONTROL_NAME);
+        let lock = utf16(mor::MOR_LOCK_NAME);
+        let key = [0x5A; mor::MOR_LOCK_KEY_SIZE];
+
+        let store = VariableStore::new(LIMITS).with_nv_storage(Box::new(storage.clone())).unwrap();
+        assert_eq!(store.memory_overwrite_request(), None);
+        let mut store = store.with_memory_overwrite_request(None);
+        assert_eq!(store.get(&control, &mor::MOR_CONTROL_GUID), Ok((NV_BS_RT, &[0u8][..])));
+        assert_eq!(store.get(&lock, &mor::MOR_LOCK_GUID), Ok((NV_BS_RT, &[mor::MOR_LOCK_UNLOCKED][..])));
+
+        store.set(&control, &mor::MOR_CONTROL_GUID, NV_BS_RT, &[mor::MOR_CLEAR_MEMORY]).unwrap();
+        assert_eq!(store.set(&control, &mor::MOR_CONTROL_GUID, 0, &[]), Err(EfiError::InvalidParameter));
+        store.set(&lock, &mor::MOR_LOCK_GUID, NV_BS_RT, &key).unwrap();
+        assert_eq!(store.get(&lock, &mor::MOR_LOCK_GUID), Ok((NV_BS_RT, &[mor::MOR_LOCK_LOCKED_WITH_KEY][..])));
+        assert_eq!(store.set(&control, &mor::MOR_CONTROL_GUID, NV_BS_RT, &[0]), Err(EfiError::AccessDenied));
+        assert_eq!(store.memory_overwrite_request(), Some(mor::MOR_CLEAR_MEMORY));
+
+        // after a reset, the request is kept and the lock is released.
+        let store =
+            VariableStore::new(LIMITS).with_nv_storage(Box::new(storage)).unwrap().with_memory_overwrite_request(None);
+        assert_eq!(store.memory_overwrite_request(), Some(mor::MOR_CLEAR_MEMORY));
+        assert_eq!(store.get(&lock, &mor::MOR_LOCK_GUID), Ok((NV_BS_RT, &[mor::MOR_LOCK_UNLOCKED][..])));
+    }
+
+    #[test]
+    fn test_memory_overwrite_request_is_scheduled_and_auto_cleared() {
+        let mut scheduler = crate::service::memory_clear::MockMemoryClearScheduler::new();
+        scheduler.expect_schedule_memory_clear().once().returning(|| Ok(()));
+        let scheduler: &'static dyn MemoryClearScheduler = Box::leak(Box::new(scheduler));
+        let control = utf16(mor::MOR_CONTROL_NAME);
+
+        let mut store = VariableStore::new(LIMITS).with_memory_overwrite_request(Some(scheduler));
+        assert_eq!(store.schedule_memory_clear(), Ok(false));
+
+        store.set(&control, &mor::MOR_CONTROL_GUID, NV_BS_RT, &[mor::MOR_CLEAR_MEMORY]).unwrap();
+        assert_eq!(store.schedule_memory_clear(), Ok(true));
+
+        store.auto_clear_memory_overwrite_request().unwrap();
+        assert_eq!(store.memory_overwrite_request(), Some(0));
+
+        // the request is kept when the OS disabled auto detection.
+        let value = mor::MOR_CLEAR_MEMORY | mor::MOR_DISABLE_AUTO_DETECT;
+        store.set(&control, &mor::MOR_CONTROL_GUID, NV_BS_RT, &[value]).unwrap();
+        store.auto_clear_memory_overwrite_request().unwrap();
+        assert_eq!(store.memory_overwrite_request(), Some(value));
+    }
+}
@@ -145,7 +145,11 @@ impl PatinaTarget {
                 let start: usize = tokens.next().and_then(|token| token.parse().ok()).unwrap_or(0);
                 let mut printed = 0;
                 for module in state.modules.get_modules().iter().skip(start) {
-                    let _ = writeln!(out, "\t{}: {:#x} : {:#x}", module.name, module.base, module.size);
+                    let _ = write!(out, "\t{}: {:#x} : {:#x}", module.name, module.base, module.size);
+                    let _ = match &module.symbols {
+                        Some((pdb_path, symbol_server_key)) => writeln!(out, " : {pdb_path} {symbol_server_key}"),
+                        None => writeln!(out),
+                    };
                     printed += 1;
                     if printed >= count {
                         break;
@@ -22,7 +22,7 @@ use patina_internal_cpu::interrupts::{ExceptionType, HandlerType, InterruptHandl
 use spin::Mutex;
 
 use crate::{
-    DebugError, Debugger, DebuggerLoggingPolicy, ExceptionInfo,
+    DebugError, Debugger, DebuggerLoggingPolicy, ExceptionInfo, ModuleSymbols,
     arch::{DebuggerArch, SystemArch},
     dbg_target::PatinaTarget,
     system::SystemState,
@@ -178,6 +178,7 @@ impl<T: SerialIO> PatinaDebugger<T> {
                 // useful hint to the client that a break has occurred. This allows
                 // the debugger to reconnect on scenarios like reboots.
                 self.transport.write("$T05thread:01;#07".as_bytes());
+                self.transport.flush();
 
                 // SAFETY: The buffer will only ever be used by the paired GDB stub
                 // within the internal state lock. Because there is no GDB stub at
@@ -316,14 +317,20 @@ impl<T: SerialIO> Debugger for PatinaDebugger<T> {
         self.config.read().enabled
     }
 
-    fn notify_module_load(&'static self, module_name: &str, address: usize, length: usize) {
+    fn notify_module_load(
+        &'static self,
+        module_name: &str,
+        address: usize,
+        length: usize,
+        symbols: Option<ModuleSymbols<'_>>,
+    ) {
         if !self.enabled() {
             return;
         }
 
         let breakpoint = {
             let mut state = self.system_state.lock();
-            state.modules.add_module(module_name, address, length);
+            state.modules.add_module(module_name, address, length, symbols);
             state.modules.check_module_breakpoints(module_name)
         };
 
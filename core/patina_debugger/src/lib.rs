@@ -59,7 +59,7 @@
 //!     patina_debugger::initialize(&mut interrupt_manager);
 //!
 //!     // Notify the debugger of a module load.
-//!     patina_debugger::notify_module_load("module.efi", 0x420000, 0x10000);
+//!     patina_debugger::notify_module_load("module.efi", 0x420000, 0x10000, None);
 //!
 //!     // Poll the debugger for any pending interrupts.
 //!     patina_debugger::poll_debugger();
@@ -77,6 +77,10 @@
 //! for more details. Notably, if the device is using the same transport for
 //! logging and debugger, it is advisable to use `.without_log_init()`.
 //!
+//! Systems without an accessible UART can run the debugger over the network by
+//! wrapping a [UdpDevice] provided by the platform's network driver in a
+//! [UdpTransport], which can be used anywhere a serial transport is expected.
+//!
 //! ## Features
 //!
 //! `windbg_workarounds` - (Default) Enables workarounds for Windbg compatibility.
@@ -103,6 +107,7 @@ mod transport;
 extern crate alloc;
 
 pub use debugger::PatinaDebugger;
+pub use transport::{UdpDevice, UdpTransport};
 
 use arch::{DebuggerArch, SystemArch};
 use patina::serial::SerialIO;
@@ -118,6 +123,15 @@ use patina_internal_cpu::interrupts::{ExceptionContext, InterruptManager};
 ///
 static DEBUGGER: spin::Once<&dyn Debugger> = spin::Once::new();
 
+/// The PDB of a module, as described by the CodeView RSDS entry of its debug directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleSymbols<'a> {
+    /// The path of the PDB when the module was linked.
+    pub pdb_path: &'a str,
+    /// The key of the PDB on a symbol server, the GUID of the PDB as 32 hex digits followed by its age in hex.
+    pub symbol_server_key: &'a str,
+}
+
 /// Type for monitor command functions. This will be invoked by the debugger when
 /// the associated monitor command is invoked.
 ///
@@ -141,7 +155,13 @@ trait Debugger: Sync {
     fn enabled(&'static self) -> bool;
 
     /// Notifies the debugger of a module load.
-    fn notify_module_load(&'static self, module_name: &str, _address: usize, _length: usize);
+    fn notify_module_load(
+        &'static self,
+        module_name: &str,
+        _address: usize,
+        _length: usize,
+        _symbols: Option<ModuleSymbols<'_>>,
+    );
 
     /// Polls the debugger for any pending interrupts.
     fn poll_debugger(&'static self);
@@ -208,9 +228,12 @@ pub fn breakpoint() {
 
 /// Notifies the debugger of a module load at the provided address and length.
 /// This should be invoked before the module has begun execution.
-pub fn notify_module_load(module_name: &str, address: usize, length: usize) {
+///
+/// The symbols, if known, let the debugger resolve the PDB of the module from a
+/// symbol server rather than by its file name.
+pub fn notify_module_load(module_name: &str, address: usize, length: usize, symbols: Option<ModuleSymbols<'_>>) {
     if let Some(debugger) = DEBUGGER.get() {
-        debugger.notify_module_load(module_name, address, length);
+        debugger.notify_module_load(module_name, address, length, symbols);
     }
 }
 
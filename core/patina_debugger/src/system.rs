@@ -10,7 +10,7 @@
 
 use alloc::{string::String, vec::Vec};
 
-use crate::MonitorCommandFn;
+use crate::{ModuleSymbols, MonitorCommandFn};
 
 pub(crate) struct SystemState {
     /// Tracks modules state.
@@ -66,6 +66,8 @@ pub(crate) struct ModuleInfo {
     pub name: String,
     pub base: usize,
     pub size: usize,
+    /// The PDB path and symbol server key, if the module has a CodeView RSDS entry.
+    pub symbols: Option<(String, String)>,
 }
 
 /// Manages loaded modules and module breakpoints.
@@ -80,8 +82,9 @@ impl Modules {
         Modules { modules: Vec::new(), module_breakpoints: Vec::new(), break_all: false }
     }
 
-    pub fn add_module(&mut self, name: &str, base: usize, size: usize) {
-        self.modules.push(ModuleInfo { name: String::from(name), base, size });
+    pub fn add_module(&mut self, name: &str, base: usize, size: usize, symbols: Option<ModuleSymbols<'_>>) {
+        let symbols = symbols.map(|symbols| (String::from(symbols.pdb_path), String::from(symbols.symbol_server_key)));
+        self.modules.push(ModuleInfo { name: String::from(name), base, size, symbols });
     }
 
     pub fn check_module_breakpoints(&self, name: &str) -> bool {
@@ -146,11 +149,19 @@ mod tests {
     #[test]
     fn test_add_module() {
         let mut modules = Modules::new();
-        modules.add_module("test_module", 0x1000, 0x2000);
-        assert_eq!(modules.get_modules().len(), 1);
+        modules.add_module("test_module", 0x1000, 0x2000, None);
+        let symbols =
+            ModuleSymbols { pdb_path: "c:\\build\\other.pdb", symbol_server_key: "0123456789ABCDEF0123456789ABCDEF1" };
+        modules.add_module("other_module", 0x4000, 0x1000, Some(symbols));
+        assert_eq!(modules.get_modules().len(), 2);
         assert_eq!(modules.get_modules()[0].name, "test_module");
         assert_eq!(modules.get_modules()[0].base, 0x1000);
         assert_eq!(modules.get_modules()[0].size, 0x2000);
+        assert_eq!(modules.get_modules()[0].symbols, None);
+        assert_eq!(
+            modules.get_modules()[1].symbols,
+            Some((String::from("c:\\build\\other.pdb"), String::from("0123456789ABCDEF0123456789ABCDEF1")))
+        );
     }
 
     #[test]
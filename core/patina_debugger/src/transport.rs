@@ -1,7 +1,8 @@
 //! Debugger Transport Implementations.
 //!
 //! This modules contains the implementation Connection traits for a SerialIO
-//! debugger transport as well as other related implementations.
+//! debugger transport as well as other related implementations, including a
+//! [UdpTransport] for debugging over the network.
 //!
 //! ## License
 //!
@@ -10,6 +11,10 @@
 //! SPDX-License-Identifier: Apache-2.0
 //!
 
+mod udp;
+
+pub use udp::{UdpDevice, UdpTransport};
+
 use core::result::Result;
 use gdbstub::conn::{Connection, ConnectionExt};
 use patina::serial::SerialIO;
@@ -44,7 +49,7 @@ impl<T: SerialIO> Connection for SerialConnection<'_, T> {
 
     /// Flush the serial transport.
     fn flush(&mut self) -> Result<(), Self::Error> {
-        // Nothing to do for SerialIO.
+        self.transport.flush();
         Ok(())
     }
 }
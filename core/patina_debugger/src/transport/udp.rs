@@ -0,0 +1,197 @@
+//! UDP Debugger Transport.
+//!
+//! Allows the debugger to communicate over a network on systems without an
+//! accessible UART. The platform provides a [UdpDevice], typically backed by a
+//! polled network device driver, which sends and receives UDP payloads to and
+//! from the debugger host. [UdpTransport] presents the device as a [SerialIO]
+//! byte stream, so that it can be used as the transport for [crate::PatinaDebugger].
+//!
+//! Writes are buffered and sent as a single datagram when the GDB stub flushes
+//! a packet, or when a datagram is full.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+use patina::serial::SerialIO;
+use spin::Mutex;
+
+/// The largest UDP payload that fits in a standard Ethernet frame without fragmentation.
+const UDP_PAYLOAD_SIZE: usize = 1472;
+
+/// A UDP endpoint connected to the debugger host.
+///
+/// The debugger runs in exception context with interrupts disabled, so implementations
+/// must poll the network device directly and must not depend on boot services, timers
+/// or interrupts.
+pub trait UdpDevice: Sync {
+    /// Initialize the network device and the UDP endpoint.
+    fn init(&self);
+    /// Send `payload` to the debugger host as a single datagram.
+    fn send(&self, payload: &[u8]);
+    /// Receive a datagram from the debugger host into `buffer`, returning the size of
+    /// the payload, or `None` if no datagram is available.
+    fn try_receive(&self, buffer: &mut [u8]) -> Option<usize>;
+}
+
+/// Buffered datagram and the portion of it that is in use.
+struct Datagram {
+    buffer: [u8; UDP_PAYLOAD_SIZE],
+    start: usize,
+    end: usize,
+}
+
+impl Datagram {
+    const fn new() -> Self {
+        Datagram { buffer: [0; UDP_PAYLOAD_SIZE], start: 0, end: 0 }
+    }
+}
+
+/// Debugger transport over a [UdpDevice].
+///
+/// ## Example
+///
+/// ```rust,ignore
+/// static DEBUGGER: patina_debugger::PatinaDebugger<patina_debugger::UdpTransport<PlatformNic>> =
+///     patina_debugger::PatinaDebugger::new(patina_debugger::UdpTransport::new(PlatformNic::new()));
+/// ```
+pub struct UdpTransport<D: UdpDevice> {
+    device: D,
+    tx: Mutex<Datagram>,
+    rx: Mutex<Datagram>,
+}
+
+impl<D: UdpDevice> UdpTransport<D> {
+    /// Creates a new UDP transport over the provided device.
+    pub const fn new(device: D) -> Self {
+        UdpTransport { device, tx: Mutex::new(Datagram::new()), rx: Mutex::new(Datagram::new()) }
+    }
+
+    fn send(&self, tx: &mut Datagram) {
+        if tx.end > 0 {
+            self.device.send(&tx.buffer[..tx.end]);
+            tx.end = 0;
+        }
+    }
+}
+
+impl<D: UdpDevice> SerialIO for UdpTransport<D> {
+    fn init(&self) {
+        self.device.init();
+    }
+
+    fn write(&self, buffer: &[u8]) {
+        let mut tx = self.tx.lock();
+        for chunk in buffer.chunks(UDP_PAYLOAD_SIZE) {
+            if tx.end + chunk.len() > UDP_PAYLOAD_SIZE {
+                self.send(&mut tx);
+            }
+            let start = tx.end;
+            tx.buffer[start..start + chunk.len()].copy_from_slice(chunk);
+            tx.end += chunk.len();
+        }
+    }
+
+    fn read(&self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_read() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        let mut rx = self.rx.lock();
+        if rx.start == rx.end {
+            let size = self.device.try_receive(&mut rx.buffer)?;
+            rx.start = 0;
+            rx.end = size.min(UDP_PAYLOAD_SIZE);
+            if rx.end == 0 {
+                return None;
+            }
+        }
+        let byte = rx.buffer[rx.start];
+        rx.start += 1;
+        Some(byte)
+    }
+
+    fn flush(&self) {
+        self.send(&mut self.tx.lock());
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{collections::VecDeque, vec, vec::Vec};
+
+    #[derive(Default)]
+    struct MockDevice {
+        sent: Mutex<Vec<Vec<u8>>>,
+        received: Mutex<VecDeque<Vec<u8>>>,
+    }
+
+    impl UdpDevice for MockDevice {
+        fn init(&self) {}
+
+        fn send(&self, payload: &[u8]) {
+            self.sent.lock().push(payload.to_vec());
+        }
+
+        fn try_receive(&self, buffer: &mut [u8]) -> Option<usize> {
+            let datagram = self.received.lock().pop_front()?;
+            buffer[..datagram.len()].copy_from_slice(&datagram);
+            Some(datagram.len())
+        }
+    }
+
+    #[test]
+    fn test_writes_are_sent_on_flush() {
+        let transport = UdpTransport::new(MockDevice::default());
+        transport.write(b"$g");
+        transport.write(b"#67");
+        assert!(transport.device.sent.lock().is_empty());
+
+        transport.flush();
+        assert_eq!(*transport.device.sent.lock(), vec![b"$g#67".to_vec()]);
+
+        // nothing is sent when there is nothing buffered.
+        transport.flush();
+        assert_eq!(transport.device.sent.lock().len(), 1);
+    }
+
+    #[test]
+    fn test_large_writes_are_split() {
+        let transport = UdpTransport::new(MockDevice::default());
+        transport.write(&[0xAA; UDP_PAYLOAD_SIZE - 1]);
+        transport.write(&[0xBB; UDP_PAYLOAD_SIZE + 1]);
+        transport.flush();
+
+        let sent = transport.device.sent.lock();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], vec![0xAA; UDP_PAYLOAD_SIZE - 1]);
+        assert_eq!(sent[1], vec![0xBB; UDP_PAYLOAD_SIZE]);
+        assert_eq!(sent[2], vec![0xBB; 1]);
+    }
+
+    #[test]
+    fn test_reads_span_datagrams() {
+        let transport = UdpTransport::new(MockDevice::default());
+        assert_eq!(transport.try_read(), None);
+
+        transport.device.received.lock().extend([b"ab".to_vec(), Vec::new(), b"c".to_vec()]);
+        assert_eq!(transport.try_read(), Some(b'a'));
+        assert_eq!(transport.read(), b'b');
+        // an empty datagram does not produce a byte.
+        assert_eq!(transport.try_read(), None);
+        assert_eq!(transport.read(), b'c');
+        assert_eq!(transport.try_read(), None);
+    }
+}
@@ -0,0 +1,65 @@
+//! UEFI Cache Maintenance Module
+//!
+//! Code that the CPU writes as data, such as an image that was copied into its buffer and relocated, is not
+//! guaranteed to be visible to instruction fetches on architectures whose instruction caches are not coherent with the
+//! data caches. On AArch64 the written range must be cleaned from the data cache to the point of unification and
+//! invalidated in the instruction cache before it is executed; otherwise the processor may fetch stale instructions
+//! and fault. x86_64 keeps its instruction caches coherent in hardware, so no maintenance is required there.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
+        mod aarch64;
+        use aarch64 as arch;
+    } else {
+        mod null;
+        use null as arch;
+    }
+}
+
+/// Makes instructions written to `len` bytes at `base` visible to instruction fetches.
+///
+/// Must be called after code is written and before it is executed. This is a no-op on architectures with coherent
+/// instruction caches.
+pub fn synchronize_instruction_cache(base: usize, len: usize) {
+    if len != 0 {
+        arch::synchronize_instruction_cache(base, len);
+    }
+}
+
+// Returns the addresses of the `line_size` byte cache lines that cover `len` bytes at `base`.
+#[cfg_attr(not(all(target_os = "uefi", target_arch = "aarch64")), allow(dead_code))]
+fn cache_lines(base: usize, len: usize, line_size: usize) -> impl Iterator<Item = usize> {
+    let start = base & !(line_size - 1);
+    let end = base.saturating_add(len);
+    (start..end).step_by(line_size)
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_cache_lines_cover_the_range() {
+        assert_eq!(cache_lines(0x1000, 0x80, 0x40).collect::<Vec<_>>(), [0x1000, 0x1040]);
+        assert_eq!(cache_lines(0x103F, 2, 0x40).collect::<Vec<_>>(), [0x1000, 0x1040]);
+        assert_eq!(cache_lines(0x1010, 1, 0x40).collect::<Vec<_>>(), [0x1000]);
+        assert_eq!(cache_lines(0x1000, 0, 0x40).count(), 0);
+    }
+
+    #[test]
+    fn test_synchronize_instruction_cache() {
+        let code = [0u8; 64];
+        synchronize_instruction_cache(code.as_ptr() as usize, code.len());
+        synchronize_instruction_cache(0, 0);
+    }
+}
@@ -0,0 +1,51 @@
+//! AArch64 Cache Maintenance implementation
+//!
+//! The cache line sizes and the required maintenance are read from `CTR_EL0`. Processors that report `IDC` do not
+//! require the data cache to be cleaned to the point of unification, and processors that report `DIC` do not require
+//! the instruction cache to be invalidated, but an `ISB` is still needed before the new instructions are executed.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::arch::asm;
+
+use super::cache_lines;
+
+// CTR_EL0.IminLine (bits 3:0) and CTR_EL0.DminLine (bits 19:16) - log2 of the smallest line size in words.
+const CTR_IMINLINE_SHIFT: u64 = 0;
+const CTR_DMINLINE_SHIFT: u64 = 16;
+// CTR_EL0.IDC - data cache clean to the point of unification is not required for instruction to data coherence.
+const CTR_IDC: u64 = 1 << 28;
+// CTR_EL0.DIC - instruction cache invalidation to the point of unification is not required.
+const CTR_DIC: u64 = 1 << 29;
+
+fn line_size(ctr: u64, shift: u64) -> usize {
+    4 << ((ctr >> shift) & 0xF)
+}
+
+pub(super) fn synchronize_instruction_cache(base: usize, len: usize) {
+    let ctr: u64;
+    // SAFETY: reading CTR_EL0 has no side effects.
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack, preserves_flags)) };
+
+    // SAFETY: cache maintenance by virtual address and barriers do not change the contents of memory.
+    unsafe {
+        if ctr & CTR_IDC == 0 {
+            for line in cache_lines(base, len, line_size(ctr, CTR_DMINLINE_SHIFT)) {
+                asm!("dc cvau, {}", in(reg) line, options(nostack, preserves_flags));
+            }
+        }
+        asm!("dsb ish", options(nostack, preserves_flags));
+
+        if ctr & CTR_DIC == 0 {
+            for line in cache_lines(base, len, line_size(ctr, CTR_IMINLINE_SHIFT)) {
+                asm!("ic ivau, {}", in(reg) line, options(nostack, preserves_flags));
+            }
+            asm!("dsb ish", options(nostack, preserves_flags));
+        }
+        asm!("isb", options(nostack, preserves_flags));
+    }
+}
@@ -0,0 +1,10 @@
+//! Null Cache Maintenance implementation - For doc tests and architectures with coherent instruction caches
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+pub(super) fn synchronize_instruction_cache(_base: usize, _len: usize) {}
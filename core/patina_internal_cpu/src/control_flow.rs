@@ -0,0 +1,90 @@
+//! UEFI Control Flow Protection Module
+//!
+//! This module provides access to the hardware control flow integrity features of the processor that this layer can
+//! enable. Currently that is only pointer authentication of return addresses on AArch64; other architectures report
+//! no features. CET shadow stacks and AArch64 branch target identification are not offered, as both require page table
+//! encodings (shadow stack pages and guarded pages) that the paging layer does not produce.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
+        mod aarch64;
+        use aarch64 as arch;
+    } else {
+        mod null;
+        use null as arch;
+    }
+}
+
+/// A set of hardware control flow integrity features.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ControlFlowFeatures {
+    /// Authentication of return addresses with the instruction A key (AArch64 pointer authentication).
+    pub pointer_authentication: bool,
+}
+
+impl ControlFlowFeatures {
+    /// No control flow features.
+    pub const NONE: Self = Self { pointer_authentication: false };
+
+    /// Returns the features that are present in both `self` and `other`.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self { pointer_authentication: self.pointer_authentication && other.pointer_authentication }
+    }
+
+    /// Returns true if no features are set.
+    pub const fn is_empty(self) -> bool {
+        !self.pointer_authentication
+    }
+}
+
+/// Returns the control flow features that the processor implements and that can be enabled by this module.
+pub fn supported() -> ControlFlowFeatures {
+    arch::supported()
+}
+
+/// Returns the control flow features that are currently enabled on the processor.
+pub fn enabled() -> ControlFlowFeatures {
+    arch::enabled()
+}
+
+/// Enables the given control flow features on the processor, and disables all others. Unsupported features are
+/// ignored.
+///
+/// ## Safety
+///
+/// All code executed while a feature is enabled must be compatible with it. For pointer authentication, the caller must
+/// also ensure that no function returns through a frame that was entered with a different pointer authentication
+/// state, as the return address of such a frame will fail authentication.
+pub unsafe fn set_enabled(features: ControlFlowFeatures) {
+    unsafe { arch::set_enabled(features.intersection(supported())) }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intersection() {
+        let all = ControlFlowFeatures { pointer_authentication: true };
+
+        assert_eq!(all.intersection(all), all);
+        assert_eq!(all.intersection(ControlFlowFeatures::NONE), ControlFlowFeatures::NONE);
+        assert!(ControlFlowFeatures::default().is_empty());
+        assert!(!all.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_features_are_not_enabled() {
+        unsafe { set_enabled(ControlFlowFeatures { pointer_authentication: true }) };
+        assert_eq!(supported(), ControlFlowFeatures::NONE);
+        assert_eq!(enabled(), ControlFlowFeatures::NONE);
+    }
+}
@@ -0,0 +1,116 @@
+//! AArch64 Control Flow Protection implementation
+//!
+//! Pointer authentication is controlled through `SCTLR_ELx.EnIA` for the current exception level. The instruction A
+//! key is programmed once, the first time the feature is enabled, as changing it would invalidate any return address
+//! signed with the previous key.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::ControlFlowFeatures;
+
+// ID_AA64ISAR1_EL1.APA (bits 7:4) and ID_AA64ISAR1_EL1.API (bits 11:8) - address authentication.
+const ID_AA64ISAR1_ADDRESS_AUTH_MASK: u64 = 0xFF0;
+// SCTLR_ELx.EnIA - enables pointer authentication with the instruction A key.
+const SCTLR_ENIA: u64 = 1 << 31;
+// CurrentEL value for EL2.
+const CURRENT_EL_EL2: u64 = 0x8;
+
+static KEY_PROGRAMMED: AtomicBool = AtomicBool::new(false);
+
+fn current_el() -> u64 {
+    let current_el: u64;
+    // SAFETY: reading CurrentEL has no side effects.
+    unsafe { asm!("mrs {}, CurrentEL", out(reg) current_el, options(nomem, nostack, preserves_flags)) };
+    current_el
+}
+
+fn read_sctlr() -> u64 {
+    let sctlr: u64;
+    // SAFETY: reading SCTLR has no side effects.
+    unsafe {
+        if current_el() == CURRENT_EL_EL2 {
+            asm!("mrs {}, sctlr_el2", out(reg) sctlr, options(nomem, nostack, preserves_flags));
+        } else {
+            asm!("mrs {}, sctlr_el1", out(reg) sctlr, options(nomem, nostack, preserves_flags));
+        }
+    }
+    sctlr
+}
+
+unsafe fn write_sctlr(sctlr: u64) {
+    unsafe {
+        if current_el() == CURRENT_EL_EL2 {
+            asm!("msr sctlr_el2, {}", "isb", in(reg) sctlr, options(nostack, preserves_flags));
+        } else {
+            asm!("msr sctlr_el1, {}", "isb", in(reg) sctlr, options(nostack, preserves_flags));
+        }
+    }
+}
+
+// Derives a key from the physical counter. This only needs to be unpredictable to code that cannot read the key
+// registers, not cryptographically strong.
+fn generate_key_half(seed: u64) -> u64 {
+    let counter: u64;
+    // SAFETY: reading CNTPCT_EL0 has no side effects.
+    unsafe { asm!("mrs {}, cntpct_el0", out(reg) counter, options(nomem, nostack, preserves_flags)) };
+
+    let mut z = counter.wrapping_add(seed).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn program_key() {
+    if KEY_PROGRAMMED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let lo = generate_key_half(&KEY_PROGRAMMED as *const _ as u64);
+    let hi = generate_key_half(lo);
+    // SAFETY: the key is only programmed before pointer authentication is first enabled, so no return address has
+    // been signed with it yet. APIAKeyLo_EL1 and APIAKeyHi_EL1 are written by encoding, as not all assemblers accept
+    // their names without the pointer authentication extension enabled.
+    unsafe {
+        asm!(
+            "msr S3_0_C2_C1_0, {lo}",
+            "msr S3_0_C2_C1_1, {hi}",
+            "isb",
+            lo = in(reg) lo,
+            hi = in(reg) hi,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+pub(super) fn supported() -> ControlFlowFeatures {
+    let isar1: u64;
+    // SAFETY: reading ID_AA64ISAR1_EL1 has no side effects.
+    unsafe { asm!("mrs {}, id_aa64isar1_el1", out(reg) isar1, options(nomem, nostack, preserves_flags)) };
+
+    ControlFlowFeatures { pointer_authentication: isar1 & ID_AA64ISAR1_ADDRESS_AUTH_MASK != 0 }
+}
+
+pub(super) fn enabled() -> ControlFlowFeatures {
+    ControlFlowFeatures { pointer_authentication: read_sctlr() & SCTLR_ENIA != 0 }
+}
+
+pub(super) unsafe fn set_enabled(features: ControlFlowFeatures) {
+    if features.pointer_authentication {
+        program_key();
+    }
+
+    let sctlr = read_sctlr();
+    let new_sctlr = if features.pointer_authentication { sctlr | SCTLR_ENIA } else { sctlr & !SCTLR_ENIA };
+    if new_sctlr != sctlr {
+        unsafe { write_sctlr(new_sctlr) };
+    }
+}
@@ -0,0 +1,19 @@
+//! Null Control Flow Protection implementation - For doc tests and architectures without pointer authentication
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use super::ControlFlowFeatures;
+
+pub(super) fn supported() -> ControlFlowFeatures {
+    ControlFlowFeatures::NONE
+}
+
+pub(super) fn enabled() -> ControlFlowFeatures {
+    ControlFlowFeatures::NONE
+}
+
+pub(super) unsafe fn set_enabled(_features: ControlFlowFeatures) {}
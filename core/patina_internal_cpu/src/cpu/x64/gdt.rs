@@ -24,6 +24,7 @@ use x86_64::{
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
 // 0xcf92000000ffff
 pub const LINEAR_SEL: DescriptorFlags = DescriptorFlags::from_bits_truncate(
@@ -155,6 +156,13 @@ lazy_static! {
 
             VirtAddr::from_ptr(addr_of!(STACK)) + STACK_SIZE as u64
         };
+        // Only used if a dedicated exception stack is requested, see [crate::interrupts::use_dedicated_exception_stack].
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = 4096 * 8;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            VirtAddr::from_ptr(addr_of!(STACK)) + STACK_SIZE as u64
+        };
         tss
     };
 }
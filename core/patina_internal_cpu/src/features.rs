@@ -0,0 +1,55 @@
+//! CPU Feature Detection
+//!
+//! Reports the optional instruction set extensions that accelerated implementations of hot routines can use, so that
+//! callers can select an implementation at runtime and fall back to portable code on processors without them.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+cfg_if::cfg_if! {
+    // CPUID is available to all privilege levels, so detection also works when running tests on an x86_64 host.
+    if #[cfg(target_arch = "x86_64")] {
+        mod x64;
+        use x64 as arch;
+    } else if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
+        mod aarch64;
+        use aarch64 as arch;
+    } else {
+        mod null;
+        use null as arch;
+    }
+}
+
+/// A set of optional instruction set extensions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFeatures {
+    /// 64-bit carry-less multiplication (PCLMULQDQ with SSE4.1 on x86_64).
+    pub carryless_multiply: bool,
+    /// CRC32 instructions for the IEEE 802.3 polynomial (the CRC32 extension on AArch64).
+    pub crc32: bool,
+}
+
+impl CpuFeatures {
+    /// No optional features.
+    pub const NONE: Self = Self { carryless_multiply: false, crc32: false };
+}
+
+/// Returns the optional features that the processor implements.
+pub fn detect() -> CpuFeatures {
+    arch::detect()
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_should_be_stable() {
+        assert_eq!(detect(), detect());
+    }
+}
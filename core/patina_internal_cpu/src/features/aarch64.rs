@@ -0,0 +1,28 @@
+//! AArch64 CPU Feature Detection
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::arch::asm;
+
+use super::CpuFeatures;
+
+// ID_AA64ISAR0_EL1.AES (bits 7:4) - 0b0010 indicates PMULL/PMULL2 on 64-bit elements.
+const ID_AA64ISAR0_AES_SHIFT: u64 = 4;
+const ID_AA64ISAR0_AES_PMULL: u64 = 0b0010;
+// ID_AA64ISAR0_EL1.CRC32 (bits 19:16) - CRC32 instructions.
+const ID_AA64ISAR0_CRC32_SHIFT: u64 = 16;
+
+pub(super) fn detect() -> CpuFeatures {
+    let isar0: u64;
+    // SAFETY: reading ID_AA64ISAR0_EL1 has no side effects.
+    unsafe { asm!("mrs {}, ID_AA64ISAR0_EL1", out(reg) isar0, options(nomem, nostack, preserves_flags)) };
+
+    CpuFeatures {
+        carryless_multiply: (isar0 >> ID_AA64ISAR0_AES_SHIFT) & 0xF >= ID_AA64ISAR0_AES_PMULL,
+        crc32: (isar0 >> ID_AA64ISAR0_CRC32_SHIFT) & 0xF != 0,
+    }
+}
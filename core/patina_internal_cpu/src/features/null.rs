@@ -0,0 +1,13 @@
+//! Null CPU Feature Detection - For doc tests and architectures without support
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use super::CpuFeatures;
+
+pub(super) fn detect() -> CpuFeatures {
+    CpuFeatures::NONE
+}
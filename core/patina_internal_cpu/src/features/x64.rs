@@ -0,0 +1,25 @@
+//! X64 CPU Feature Detection
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::arch::x86_64::__cpuid;
+
+use super::CpuFeatures;
+
+// CPUID.(EAX=01H):ECX[1] - PCLMULQDQ.
+const CPUID_1_ECX_PCLMULQDQ: u32 = 1 << 1;
+// CPUID.(EAX=01H):ECX[19] - SSE4.1.
+const CPUID_1_ECX_SSE4_1: u32 = 1 << 19;
+
+pub(super) fn detect() -> CpuFeatures {
+    // SAFETY: CPUID is available on all x86_64 processors, and leaf 1 is implemented by all of them.
+    let leaf_1 = unsafe { __cpuid(1) };
+    let required = CPUID_1_ECX_PCLMULQDQ | CPUID_1_ECX_SSE4_1;
+
+    // x86_64 has no instruction for the IEEE 802.3 CRC32 polynomial; SSE4.2 CRC32 uses the Castagnoli polynomial.
+    CpuFeatures { carryless_multiply: leaf_1.ecx & required == required, crc32: false }
+}
@@ -15,8 +15,11 @@
 //! SPDX-License-Identifier: Apache-2.0
 //!
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use patina::error::EfiError;
 use patina_pi::protocols::cpu_arch::EfiSystemContext;
+use spin::rwlock::RwLock;
 
 mod exception_handling;
 
@@ -28,6 +31,7 @@ cfg_if::cfg_if! {
         mod aarch64;
         pub type Interrupts = aarch64::InterruptsAarch64;
         pub use aarch64::gic_manager;
+        pub use aarch64::gic_its;
     } else if #[cfg(feature = "doc")] {
         mod x64;
         mod aarch64;
@@ -128,6 +132,73 @@ pub(crate) trait EfiExceptionStackTrace {
     fn dump_system_context_registers(&self);
 }
 
+/// Trait for decoding memory faults from architecture specific context.
+pub(crate) trait DecodeMemoryFault {
+    /// Returns the memory fault described by the context, or `None` if the exception is not a memory fault.
+    fn decode_memory_fault(&self, exception_type: ExceptionType) -> Option<MemoryFault>;
+}
+
+/// The kind of access that caused a memory fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryFaultAccess {
+    /// A data read.
+    Read,
+    /// A data write.
+    Write,
+    /// An instruction fetch.
+    Execute,
+}
+
+/// A memory fault, such as a page protection violation, decoded from an architecture specific exception context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryFault {
+    /// The address that was accessed.
+    pub address: u64,
+    /// The address of the faulting instruction.
+    pub instruction_pointer: u64,
+    /// The kind of access that faulted.
+    pub access: MemoryFaultAccess,
+}
+
+/// A callback consulted by the default memory fault handlers before a fault is treated as fatal.
+///
+/// Returns `true` if the fault was resolved, for example by relaxing the attributes of the accessed page, in which case
+/// the faulting instruction is retried. The callback runs in exception context.
+pub type MemoryFaultFilter = fn(&MemoryFault) -> bool;
+
+static MEMORY_FAULT_FILTER: RwLock<Option<MemoryFaultFilter>> = RwLock::new(None);
+
+/// Sets the filter consulted by the default memory fault handlers, replacing any previous filter.
+pub fn set_memory_fault_filter(filter: Option<MemoryFaultFilter>) {
+    *MEMORY_FAULT_FILTER.write() = filter;
+}
+
+/// Returns `true` if the context describes a memory fault that was resolved by the memory fault filter.
+#[allow(unused)]
+pub(crate) fn filter_memory_fault(context: &impl DecodeMemoryFault, exception_type: ExceptionType) -> bool {
+    let Some(fault) = context.decode_memory_fault(exception_type) else {
+        return false;
+    };
+    // A fault while the filter is being replaced is treated as fatal rather than waiting on the lock.
+    MEMORY_FAULT_FILTER.try_read().and_then(|filter| *filter).is_some_and(|filter| filter(&fault))
+}
+
+static DEDICATED_EXCEPTION_STACK: AtomicBool = AtomicBool::new(false);
+
+/// Requests that page faults are handled on a dedicated exception stack, so that a fault caused by a stack overflow
+/// can still be reported.
+///
+/// Must be called before interrupts are initialized. Currently only supported on x64; ignored on other architectures.
+pub fn use_dedicated_exception_stack() {
+    DEDICATED_EXCEPTION_STACK.store(true, Ordering::SeqCst);
+}
+
+/// Returns `true` if a dedicated exception stack was requested.
+#[allow(unused)]
+pub(crate) fn dedicated_exception_stack_requested() -> bool {
+    DEDICATED_EXCEPTION_STACK.load(Ordering::SeqCst)
+}
+
 /// Trait for structs that implement and manage interrupts.
 ///
 /// Generic trait that can be used to abstract the architecture and platform
@@ -195,3 +266,67 @@ cfg_if::cfg_if! {
         pub use null::get_interrupt_state;
     }
 }
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    fn permit_low_addresses(fault: &MemoryFault) -> bool {
+        fault.address < 0x1000
+    }
+
+    #[test]
+    fn test_decode_x64_page_fault() {
+        // SAFETY: the x64 system context is plain register values, for which zero is valid.
+        let mut context: x64::ExceptionContextX64 = unsafe { core::mem::zeroed() };
+        context.cr2 = 0x10;
+        context.rip = 0x8000;
+
+        context.exception_data = 0x3;
+        let fault = context.decode_memory_fault(14).unwrap();
+        assert_eq!(fault, MemoryFault { address: 0x10, instruction_pointer: 0x8000, access: MemoryFaultAccess::Write });
+
+        context.exception_data = 0x11;
+        assert_eq!(context.decode_memory_fault(14).unwrap().access, MemoryFaultAccess::Execute);
+        context.exception_data = 0x0;
+        assert_eq!(context.decode_memory_fault(14).unwrap().access, MemoryFaultAccess::Read);
+        assert_eq!(context.decode_memory_fault(13), None);
+    }
+
+    #[test]
+    fn test_decode_aarch64_abort() {
+        // SAFETY: the AArch64 system context is plain register values, for which zero is valid.
+        let mut context: aarch64::ExceptionContextAArch64 = unsafe { core::mem::zeroed() };
+        context.far = 0x20;
+        context.elr = 0x9000;
+
+        context.esr = (0x25 << 26) | (1 << 6);
+        let fault = context.decode_memory_fault(0).unwrap();
+        assert_eq!(fault, MemoryFault { address: 0x20, instruction_pointer: 0x9000, access: MemoryFaultAccess::Write });
+
+        context.esr = 0x21 << 26;
+        assert_eq!(context.decode_memory_fault(0).unwrap().access, MemoryFaultAccess::Execute);
+        context.esr = (0x24 << 26) | (1 << 10);
+        assert_eq!(context.decode_memory_fault(0), None, "FAR is not valid");
+        context.esr = 0x15 << 26;
+        assert_eq!(context.decode_memory_fault(0), None, "SVC is not a memory fault");
+    }
+
+    #[test]
+    fn test_memory_fault_filter() {
+        // SAFETY: the x64 system context is plain register values, for which zero is valid.
+        let mut context: x64::ExceptionContextX64 = unsafe { core::mem::zeroed() };
+        assert!(!filter_memory_fault(&context, 14));
+
+        set_memory_fault_filter(Some(permit_low_addresses));
+        assert!(filter_memory_fault(&context, 14));
+        context.cr2 = 0x1000;
+        assert!(!filter_memory_fault(&context, 14));
+        assert!(!filter_memory_fault(&null::ExceptionContextNull, 14));
+
+        set_memory_fault_filter(None);
+        context.cr2 = 0;
+        assert!(!filter_memory_fault(&context, 14));
+    }
+}
@@ -16,6 +16,7 @@ mod sysreg;
 cfg_if::cfg_if! {
     if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
         mod interrupt_manager;
+        pub mod gic_its;
         pub mod gic_manager;
         pub use interrupt_manager::InterruptsAarch64;
         use core::arch::asm;
@@ -23,6 +24,8 @@ cfg_if::cfg_if! {
     } else if #[cfg(feature = "doc")] {
         pub use interrupt_manager::InterruptsAarch64;
         mod interrupt_manager;
+    } else if #[cfg(test)] {
+        mod gic_its;
     }
 }
 
@@ -34,6 +37,27 @@ impl super::EfiSystemContextFactory for ExceptionContextAArch64 {
     }
 }
 
+impl super::DecodeMemoryFault for ExceptionContextAArch64 {
+    fn decode_memory_fault(&self, _exception_type: super::ExceptionType) -> Option<super::MemoryFault> {
+        let ec = (self.esr >> 26) & 0x3F;
+        let iss = self.esr & 0xFFFFFF;
+        let access = match ec {
+            // Instruction Abort from a lower EL or same EL
+            0x20 | 0x21 => super::MemoryFaultAccess::Execute,
+            // Data Abort from a lower EL or same EL, with ISS.WnR set for writes
+            0x24 | 0x25 if iss & (1 << 6) != 0 => super::MemoryFaultAccess::Write,
+            0x24 | 0x25 => super::MemoryFaultAccess::Read,
+            _ => return None,
+        };
+
+        // ISS.FnV is set when the FAR does not hold the faulting address.
+        if iss & (1 << 10) != 0 {
+            return None;
+        }
+        Some(super::MemoryFault { address: self.far, instruction_pointer: self.elr, access })
+    }
+}
+
 impl super::EfiExceptionStackTrace for ExceptionContextAArch64 {
     fn dump_stack_trace(&self) {
         if let Err(err) = unsafe { StackTrace::dump_with(self.elr, self.sp) } {
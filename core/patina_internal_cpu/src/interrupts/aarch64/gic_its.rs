@@ -0,0 +1,754 @@
+//! GICv3/v4 Interrupt Translation Service (ITS) and LPI support
+//!
+//! The ITS translates MSI writes from devices into Locality-specific Peripheral Interrupts (LPIs). This module enables
+//! LPIs on the boot CPU's redistributor, configures the ITS tables and command queue, and allocates MSI doorbells by
+//! mapping a `(DeviceID, EventID)` pair to a newly allocated LPI that is routed to the boot CPU.
+//!
+//! All tables are flat, and only a single collection, targeting the boot CPU, is used.
+//!
+//! See "Arm Generic Interrupt Controller Architecture Specification GIC architecture version 3 and version 4"
+//! (Arm IHI 0069H.b ID041224), chapters 5 and 6.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::alloc::{GlobalAlloc, Layout};
+use patina::{component::service::msi::MsiVector, error::EfiError};
+
+/// The first LPI INTID.
+pub const LPI_START: u32 = 8192;
+
+/// The maximum number of INTID bits used for LPIs, which bounds the size of the LPI configuration and pending tables.
+const MAX_LPI_ID_BITS: u32 = 16;
+/// The maximum number of DeviceID bits used, which bounds the size of the flat device table.
+const MAX_DEVICE_ID_BITS: u32 = 16;
+/// The number of EventID bits used per device, which is the number of vectors a single device can allocate.
+const EVENT_ID_BITS: u32 = 6;
+
+/// The size of the command queue, which holds 2048 commands.
+const COMMAND_QUEUE_SIZE: usize = 0x10000;
+/// The size of a single ITS command.
+const COMMAND_SIZE: usize = 32;
+/// The number of times the ITS is polled for command completion before giving up.
+const COMMAND_TIMEOUT: usize = 10_000_000;
+
+/// The priority of LPIs, matching the priority given to the other interrupts in `gic_initialize`.
+const LPI_PRIORITY: u8 = 0x80;
+/// The enable bit of an LPI configuration table entry.
+const LPI_ENABLE: u8 = 0x1;
+
+/// Normal, Inner Read-allocate, Write-allocate, Write-back cacheability.
+const CACHE_WRITE_BACK: u64 = 0b111;
+/// Normal, Inner Non-cacheable cacheability.
+const CACHE_NON_CACHEABLE: u64 = 0b001;
+/// Inner Shareable shareability.
+const SHAREABILITY_INNER: u64 = 0b01;
+
+/// ITS register offsets, relative to the ITS control frame.
+mod gits {
+    pub const CTLR: usize = 0x0000;
+    pub const TYPER: usize = 0x0008;
+    pub const CBASER: usize = 0x0080;
+    pub const CWRITER: usize = 0x0088;
+    pub const CREADR: usize = 0x0090;
+    pub const BASER: usize = 0x0100;
+    pub const BASER_COUNT: usize = 8;
+    /// `GITS_TRANSLATER` lives in the translation frame, which follows the 64KB control frame.
+    pub const TRANSLATER: u64 = 0x1_0040;
+
+    pub const CTLR_ENABLED: u32 = 1 << 0;
+    pub const CTLR_QUIESCENT: u32 = 1 << 31;
+
+    pub const BASER_TYPE_DEVICES: u64 = 1;
+    pub const BASER_TYPE_COLLECTIONS: u64 = 4;
+}
+
+/// Redistributor register offsets, relative to the `RD_base` frame.
+mod gicr {
+    pub const CTLR: usize = 0x0000;
+    pub const TYPER: usize = 0x0008;
+    pub const PROPBASER: usize = 0x0070;
+    pub const PENDBASER: usize = 0x0078;
+
+    pub const CTLR_ENABLE_LPIS: u32 = 1 << 0;
+    pub const TYPER_PLPIS: u64 = 1 << 0;
+    pub const PENDBASER_PTZ: u64 = 1 << 62;
+}
+
+/// A region of memory mapped registers.
+#[derive(Debug, Clone, Copy)]
+struct Mmio(usize);
+
+impl Mmio {
+    fn read32(&self, offset: usize) -> u32 {
+        // SAFETY: The base address was validated as a register frame when the ITS was initialized.
+        unsafe { core::ptr::read_volatile((self.0 + offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        // SAFETY: The base address was validated as a register frame when the ITS was initialized.
+        unsafe { core::ptr::write_volatile((self.0 + offset) as *mut u32, value) }
+    }
+
+    fn read64(&self, offset: usize) -> u64 {
+        // SAFETY: The base address was validated as a register frame when the ITS was initialized.
+        unsafe { core::ptr::read_volatile((self.0 + offset) as *const u64) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        // SAFETY: The base address was validated as a register frame when the ITS was initialized.
+        unsafe { core::ptr::write_volatile((self.0 + offset) as *mut u64, value) }
+    }
+}
+
+/// Extracts the `width` bit field starting at bit `shift` of `value`.
+const fn field(value: u64, shift: u32, width: u32) -> u64 {
+    (value >> shift) & ((1 << width) - 1)
+}
+
+/// An ITS command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ItsCommand {
+    /// Maps a device to its interrupt translation table.
+    Mapd { device_id: u32, itt_address: u64, event_id_bits: u32 },
+    /// Maps a collection to a redistributor.
+    Mapc { collection: u16, rd_base: u64 },
+    /// Maps an event of a device to an LPI in a collection.
+    Mapti { device_id: u32, event_id: u32, intid: u32, collection: u16 },
+    /// Reloads the configuration of the LPI mapped to an event of a device.
+    Inv { device_id: u32, event_id: u32 },
+    /// Removes the mapping of an event of a device.
+    Discard { device_id: u32, event_id: u32 },
+    /// Waits for the effects of all previous commands targeting a redistributor to complete.
+    Sync { rd_base: u64 },
+}
+
+impl ItsCommand {
+    const VALID: u64 = 1 << 63;
+
+    /// Encodes the command as the four double words written to the command queue.
+    fn encode(&self) -> [u64; 4] {
+        match *self {
+            Self::Mapd { device_id, itt_address, event_id_bits } => [
+                0x08 | (device_id as u64) << 32,
+                (event_id_bits - 1) as u64,
+                Self::VALID | (itt_address & 0x000F_FFFF_FFFF_FF00),
+                0,
+            ],
+            Self::Mapc { collection, rd_base } => {
+                [0x09, 0, Self::VALID | (rd_base & 0x7_FFFF_FFFF) << 16 | collection as u64, 0]
+            }
+            Self::Mapti { device_id, event_id, intid, collection } => {
+                [0x0A | (device_id as u64) << 32, event_id as u64 | (intid as u64) << 32, collection as u64, 0]
+            }
+            Self::Inv { device_id, event_id } => [0x0C | (device_id as u64) << 32, event_id as u64, 0, 0],
+            Self::Discard { device_id, event_id } => [0x0F | (device_id as u64) << 32, event_id as u64, 0, 0],
+            Self::Sync { rd_base } => [0x05, 0, (rd_base & 0x7_FFFF_FFFF) << 16, 0],
+        }
+    }
+}
+
+/// Allocates LPI INTIDs, reusing freed INTIDs first.
+#[derive(Debug)]
+struct LpiAllocator {
+    next: u32,
+    limit: u32,
+    free: Vec<u32>,
+}
+
+impl LpiAllocator {
+    fn new(lpi_id_bits: u32) -> Self {
+        Self { next: LPI_START, limit: 1 << lpi_id_bits, free: Vec::new() }
+    }
+
+    fn allocate(&mut self) -> Option<u32> {
+        if let Some(intid) = self.free.pop() {
+            return Some(intid);
+        }
+        if self.next >= self.limit {
+            return None;
+        }
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    fn free(&mut self, intid: u32) {
+        self.free.push(intid);
+    }
+}
+
+/// A device mapped to an interrupt translation table.
+#[derive(Debug)]
+struct ItsDevice {
+    /// A bitmap of the allocated EventIDs.
+    events: u64,
+}
+
+/// The Interrupt Translation Service of a GICv3/v4.
+pub struct GicIts {
+    its: Mmio,
+    its_base: u64,
+    rd_base: u64,
+    coherent: bool,
+    property_table: usize,
+    device_id_bits: u32,
+    event_id_bits: u32,
+    itt_entry_size: usize,
+    command_queue: usize,
+    cwriter: usize,
+    allocator: &'static (dyn GlobalAlloc + Sync),
+    lpis: LpiAllocator,
+    devices: BTreeMap<u32, ItsDevice>,
+    routes: BTreeMap<u32, (u32, u32)>,
+}
+
+impl core::fmt::Debug for GicIts {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("GicIts")
+            .field("its_base", &self.its_base)
+            .field("rd_base", &self.rd_base)
+            .field("coherent", &self.coherent)
+            .field("device_id_bits", &self.device_id_bits)
+            .field("event_id_bits", &self.event_id_bits)
+            .field("lpis", &self.lpis)
+            .finish()
+    }
+}
+
+impl GicIts {
+    /// Enables LPIs on the redistributor at `gicr_base` and initializes the ITS at `its_base`.
+    ///
+    /// `gicd_id_bits` is the number of INTID bits supported by the distributor. Tables are allocated from `allocator`,
+    /// which must provide memory that is not reclaimed by the OS, as the redistributor keeps using the LPI tables
+    /// until the OS reprograms it.
+    ///
+    /// # Safety
+    ///
+    /// `its_base` must be the base address of the ITS register frames, and `gicr_base` the base address of the boot
+    /// CPU's redistributor. Both must be identity mapped as device memory.
+    pub unsafe fn initialize(
+        its_base: u64,
+        gicr_base: u64,
+        gicd_id_bits: u32,
+        allocator: &'static (dyn GlobalAlloc + Sync),
+    ) -> Result<Self, EfiError> {
+        let rd = Mmio(gicr_base as usize);
+        let its = Mmio(its_base as usize);
+
+        let gicr_typer = rd.read64(gicr::TYPER);
+        if gicr_typer & gicr::TYPER_PLPIS == 0 {
+            log::error!("GICR does not support physical LPIs");
+            return Err(EfiError::Unsupported);
+        }
+        if rd.read32(gicr::CTLR) & gicr::CTLR_ENABLE_LPIS != 0 {
+            // The LPI tables cannot be reprogrammed once LPIs are enabled.
+            log::error!("LPIs are already enabled on the GICR");
+            return Err(EfiError::AlreadyStarted);
+        }
+
+        let lpi_id_bits = gicd_id_bits.min(MAX_LPI_ID_BITS);
+        if (1 << lpi_id_bits) <= LPI_START {
+            log::error!("GICD supports {gicd_id_bits} INTID bits, which leaves no room for LPIs");
+            return Err(EfiError::Unsupported);
+        }
+
+        let mut this = Self {
+            its,
+            its_base,
+            rd_base: 0,
+            coherent: true,
+            property_table: 0,
+            device_id_bits: 0,
+            event_id_bits: 0,
+            itt_entry_size: 0,
+            command_queue: 0,
+            cwriter: 0,
+            allocator,
+            lpis: LpiAllocator::new(lpi_id_bits),
+            devices: BTreeMap::new(),
+            routes: BTreeMap::new(),
+        };
+
+        this.initialize_redistributor_tables(rd, lpi_id_bits)?;
+        this.initialize_its_tables()?;
+
+        this.rd_base = if field(its.read64(gits::TYPER), 19, 1) != 0 {
+            // PTA: collections target the physical address of the redistributor.
+            gicr_base >> 16
+        } else {
+            field(gicr_typer, 8, 16)
+        };
+
+        its.write32(gits::CTLR, its.read32(gits::CTLR) | gits::CTLR_ENABLED);
+        rd.write32(gicr::CTLR, rd.read32(gicr::CTLR) | gicr::CTLR_ENABLE_LPIS);
+
+        this.send(&[
+            ItsCommand::Mapc { collection: 0, rd_base: this.rd_base },
+            ItsCommand::Sync { rd_base: this.rd_base },
+        ])?;
+
+        log::info!("GIC ITS initialized: {this:x?}");
+        Ok(this)
+    }
+
+    /// Allocates and programs the LPI configuration and pending tables of the redistributor.
+    fn initialize_redistributor_tables(&mut self, rd: Mmio, lpi_id_bits: u32) -> Result<(), EfiError> {
+        let property_size = (1usize << lpi_id_bits) - LPI_START as usize;
+        let property_table = self.allocate_table(property_size, 0x1000)?;
+        // SAFETY: The table was just allocated with the given size.
+        unsafe { core::ptr::write_bytes(property_table as *mut u8, LPI_PRIORITY, property_size) };
+        self.property_table = property_table;
+
+        let pending_size = (1usize << lpi_id_bits) / 8;
+        let pending_table = self.allocate_table(pending_size, 0x10000)?;
+
+        let propbaser =
+            property_table as u64 | SHAREABILITY_INNER << 10 | CACHE_WRITE_BACK << 7 | (lpi_id_bits - 1) as u64;
+        rd.write64(gicr::PROPBASER, propbaser);
+        if field(rd.read64(gicr::PROPBASER), 10, 2) == 0 {
+            // The redistributor does not snoop the caches, so keep the tables non-cacheable from its point of view.
+            self.coherent = false;
+            rd.write64(gicr::PROPBASER, propbaser & !(0b11 << 10 | 0b111 << 7) | CACHE_NON_CACHEABLE << 7);
+            clean_data_cache(property_table, property_size);
+            clean_data_cache(pending_table, pending_size);
+        }
+
+        let cache =
+            if self.coherent { CACHE_WRITE_BACK << 7 | SHAREABILITY_INNER << 10 } else { CACHE_NON_CACHEABLE << 7 };
+        rd.write64(gicr::PENDBASER, pending_table as u64 | gicr::PENDBASER_PTZ | cache);
+        Ok(())
+    }
+
+    /// Allocates and programs the ITS device and collection tables and the command queue.
+    fn initialize_its_tables(&mut self) -> Result<(), EfiError> {
+        let its = self.its;
+
+        if its.read32(gits::CTLR) & gits::CTLR_ENABLED != 0 {
+            its.write32(gits::CTLR, its.read32(gits::CTLR) & !gits::CTLR_ENABLED);
+        }
+        if !self.poll(|| its.read32(gits::CTLR) & gits::CTLR_QUIESCENT != 0) {
+            log::error!("GIC ITS did not become quiescent");
+            return Err(EfiError::Timeout);
+        }
+
+        let typer = its.read64(gits::TYPER);
+        self.itt_entry_size = field(typer, 4, 4) as usize + 1;
+        self.event_id_bits = (field(typer, 8, 5) as u32 + 1).min(EVENT_ID_BITS);
+        self.device_id_bits = (field(typer, 13, 5) as u32 + 1).min(MAX_DEVICE_ID_BITS);
+
+        let (cache, shareability) =
+            if self.coherent { (CACHE_WRITE_BACK, SHAREABILITY_INNER) } else { (CACHE_NON_CACHEABLE, 0) };
+
+        for index in 0..gits::BASER_COUNT {
+            let offset = gits::BASER + index * 8;
+            let baser = its.read64(offset);
+            let entry_size = field(baser, 48, 5) as usize + 1;
+            let entries = match field(baser, 56, 3) {
+                gits::BASER_TYPE_DEVICES => 1usize << self.device_id_bits,
+                gits::BASER_TYPE_COLLECTIONS => 1,
+                _ => continue,
+            };
+
+            // Allocate for the largest page size, so that the table fits whichever page size the ITS supports.
+            let size = (entries * entry_size).next_multiple_of(0x10000);
+            let table = self.allocate_table(size, 0x10000)?;
+            clean_data_cache(table, size);
+
+            let accepted = [(0b10, 0x10000), (0b01, 0x4000), (0b00, 0x1000)].into_iter().find(|&(page_size, bytes)| {
+                let value = 1 << 63
+                    | cache << 59
+                    | field(baser, 56, 3) << 56
+                    | field(baser, 48, 5) << 48
+                    | table as u64 & 0x0000_FFFF_FFFF_F000
+                    | shareability << 10
+                    | page_size << 8
+                    | ((size / bytes) as u64 - 1).min(0xFF);
+                its.write64(offset, value);
+                field(its.read64(offset), 8, 2) == page_size
+            });
+            if accepted.is_none() {
+                log::error!("GIC ITS rejected all page sizes for table {index}");
+                return Err(EfiError::Unsupported);
+            }
+        }
+
+        self.command_queue = self.allocate_table(COMMAND_QUEUE_SIZE, 0x10000)?;
+        its.write64(
+            gits::CBASER,
+            1 << 63
+                | cache << 59
+                | self.command_queue as u64 & 0x000F_FFFF_FFFF_F000
+                | shareability << 10
+                | (COMMAND_QUEUE_SIZE / 0x1000 - 1) as u64,
+        );
+        its.write64(gits::CWRITER, 0);
+        self.cwriter = 0;
+        Ok(())
+    }
+
+    /// Allocates a zeroed table from the table allocator. Tables are never freed.
+    fn allocate_table(&self, size: usize, align: usize) -> Result<usize, EfiError> {
+        let layout = Layout::from_size_align(size, align).map_err(|_| EfiError::InvalidParameter)?;
+        // SAFETY: The layout has a non-zero size.
+        let table = unsafe { self.allocator.alloc_zeroed(layout) };
+        if table.is_null() {
+            log::error!("Failed to allocate {size:#x} bytes for a GIC ITS table");
+            return Err(EfiError::OutOfResources);
+        }
+        Ok(table as usize)
+    }
+
+    /// Polls `condition` until it is true, returning false if it is not true before the timeout.
+    fn poll(&self, mut condition: impl FnMut() -> bool) -> bool {
+        for _ in 0..COMMAND_TIMEOUT {
+            if condition() {
+                return true;
+            }
+            // The ITS is emulated by another thread in tests.
+            #[cfg(test)]
+            std::thread::yield_now();
+            core::hint::spin_loop();
+        }
+        false
+    }
+
+    /// Writes `commands` to the command queue and waits for the ITS to process them.
+    fn send(&mut self, commands: &[ItsCommand]) -> Result<(), EfiError> {
+        for command in commands {
+            let slot = self.command_queue + self.cwriter;
+            for (index, dword) in command.encode().into_iter().enumerate() {
+                // SAFETY: The slot lies within the command queue, which is COMMAND_SIZE aligned.
+                unsafe { core::ptr::write_volatile((slot as *mut u64).add(index), dword) };
+            }
+            if !self.coherent {
+                clean_data_cache(slot, COMMAND_SIZE);
+            }
+            self.cwriter = (self.cwriter + COMMAND_SIZE) % COMMAND_QUEUE_SIZE;
+        }
+
+        data_synchronization_barrier();
+        self.its.write64(gits::CWRITER, self.cwriter as u64);
+
+        let its = self.its;
+        let cwriter = self.cwriter as u64;
+        let mut stalled = false;
+        let done = self.poll(|| {
+            let creadr = its.read64(gits::CREADR);
+            stalled = creadr & 1 != 0;
+            stalled || creadr & 0xF_FFE0 == cwriter
+        });
+        match (done, stalled) {
+            (_, true) => {
+                log::error!("GIC ITS command queue stalled on {commands:x?}");
+                Err(EfiError::DeviceError)
+            }
+            (false, _) => {
+                log::error!("GIC ITS timed out processing {commands:x?}");
+                Err(EfiError::Timeout)
+            }
+            (true, false) => Ok(()),
+        }
+    }
+
+    /// Returns the number of LPIs that can be allocated.
+    pub fn lpi_count(&self) -> usize {
+        (self.lpis.limit - LPI_START) as usize
+    }
+
+    /// Returns whether `intid` is an LPI that is currently mapped to a device.
+    pub fn is_mapped_lpi(&self, intid: u32) -> bool {
+        self.routes.contains_key(&intid)
+    }
+
+    /// Allocates an MSI vector for the device identified by `device_id`, mapping it to a new LPI.
+    ///
+    /// The LPI is disabled until it is enabled with [GicIts::set_lpi_enabled].
+    pub fn allocate_msi(&mut self, device_id: u32) -> Result<MsiVector, EfiError> {
+        if device_id as u64 >= 1 << self.device_id_bits {
+            return Err(EfiError::InvalidParameter);
+        }
+
+        if !self.devices.contains_key(&device_id) {
+            let itt_size = (1usize << self.event_id_bits) * self.itt_entry_size;
+            let itt = self.allocate_table(itt_size.max(256), 256)?;
+            clean_data_cache(itt, itt_size.max(256));
+            self.send(&[
+                ItsCommand::Mapd { device_id, itt_address: itt as u64, event_id_bits: self.event_id_bits },
+                ItsCommand::Sync { rd_base: self.rd_base },
+            ])?;
+            self.devices.insert(device_id, ItsDevice { events: 0 });
+        }
+
+        let event_limit = 1u32 << self.event_id_bits;
+        let events = self.devices[&device_id].events;
+        let event_id = (!events).trailing_zeros();
+        if event_id >= event_limit {
+            return Err(EfiError::OutOfResources);
+        }
+        let intid = self.lpis.allocate().ok_or(EfiError::OutOfResources)?;
+
+        if let Err(err) = self.send(&[
+            ItsCommand::Mapti { device_id, event_id, intid, collection: 0 },
+            ItsCommand::Sync { rd_base: self.rd_base },
+        ]) {
+            self.lpis.free(intid);
+            return Err(err);
+        }
+
+        if let Some(device) = self.devices.get_mut(&device_id) {
+            device.events |= 1 << event_id;
+        }
+        self.routes.insert(intid, (device_id, event_id));
+
+        Ok(MsiVector {
+            device_id,
+            address: self.its_base + gits::TRANSLATER,
+            data: event_id,
+            interrupt_source: intid as u64,
+        })
+    }
+
+    /// Frees an MSI vector returned by [GicIts::allocate_msi], disabling and unmapping its LPI.
+    pub fn free_msi(&mut self, vector: MsiVector) -> Result<(), EfiError> {
+        let intid: u32 = vector.interrupt_source.try_into().map_err(|_| EfiError::InvalidParameter)?;
+        if self.routes.get(&intid) != Some(&(vector.device_id, vector.data)) {
+            return Err(EfiError::InvalidParameter);
+        }
+
+        self.set_lpi_enabled(intid, false)?;
+        self.send(&[
+            ItsCommand::Discard { device_id: vector.device_id, event_id: vector.data },
+            ItsCommand::Sync { rd_base: self.rd_base },
+        ])?;
+
+        self.routes.remove(&intid);
+        if let Some(device) = self.devices.get_mut(&vector.device_id) {
+            device.events &= !(1 << vector.data);
+        }
+        self.lpis.free(intid);
+        Ok(())
+    }
+
+    fn property_entry(&self, intid: u32) -> Result<*mut u8, EfiError> {
+        if !self.is_mapped_lpi(intid) {
+            return Err(EfiError::InvalidParameter);
+        }
+        Ok((self.property_table + (intid - LPI_START) as usize) as *mut u8)
+    }
+
+    /// Enables or disables a mapped LPI.
+    pub fn set_lpi_enabled(&mut self, intid: u32, enable: bool) -> Result<(), EfiError> {
+        let entry = self.property_entry(intid)?;
+        let value = if enable { LPI_PRIORITY | LPI_ENABLE } else { LPI_PRIORITY };
+        // SAFETY: The entry lies within the LPI configuration table, as the LPI is mapped.
+        unsafe { core::ptr::write_volatile(entry, value) };
+        if !self.coherent {
+            clean_data_cache(entry as usize, 1);
+        }
+
+        let (device_id, event_id) = self.routes[&intid];
+        self.send(&[ItsCommand::Inv { device_id, event_id }, ItsCommand::Sync { rd_base: self.rd_base }])
+    }
+
+    /// Returns whether a mapped LPI is enabled.
+    pub fn is_lpi_enabled(&self, intid: u32) -> Result<bool, EfiError> {
+        let entry = self.property_entry(intid)?;
+        // SAFETY: The entry lies within the LPI configuration table, as the LPI is mapped.
+        Ok(unsafe { core::ptr::read_volatile(entry) } & LPI_ENABLE != 0)
+    }
+}
+
+/// Cleans a range of the data cache to the point of coherency, so that a non-snooping ITS observes CPU writes.
+fn clean_data_cache(_address: usize, _size: usize) {
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    {
+        let ctr_el0: u64;
+        // SAFETY: Reading CTR_EL0 and cleaning cache lines has no side effects on program state.
+        unsafe {
+            core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr_el0, options(nomem, nostack));
+            let line = 4usize << field(ctr_el0, 16, 4);
+            let mut address = _address & !(line - 1);
+            while address < _address + _size {
+                core::arch::asm!("dc cvac, {}", in(reg) address, options(nostack, preserves_flags));
+                address += line;
+            }
+        }
+    }
+    data_synchronization_barrier();
+}
+
+fn data_synchronization_barrier() {
+    #[cfg(all(not(test), target_arch = "aarch64"))]
+    {
+        // SAFETY: A barrier has no side effects on program state.
+        unsafe { core::arch::asm!("dsb sy", options(nostack, preserves_flags)) };
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use std::{sync::Arc, thread};
+
+    /// A 4KB aligned region of memory standing in for register frames.
+    struct Frame(&'static mut [u8]);
+
+    impl Frame {
+        fn new(size: usize) -> Self {
+            let layout = Layout::from_size_align(size, 0x10000).unwrap();
+            // SAFETY: The layout has a non-zero size. The frame is leaked so that it outlives the ITS emulator thread.
+            Frame(unsafe { core::slice::from_raw_parts_mut(std::alloc::alloc_zeroed(layout), size) })
+        }
+
+        fn base(&self) -> u64 {
+            self.0.as_ptr() as u64
+        }
+
+        fn mmio(&self) -> Mmio {
+            Mmio(self.0.as_ptr() as usize)
+        }
+    }
+
+    /// Emulates an ITS that immediately consumes every command written to its queue, recording the commands.
+    fn emulate_its(its: Mmio, stop: Arc<AtomicBool>) -> thread::JoinHandle<Vec<[u64; 4]>> {
+        thread::spawn(move || {
+            let mut commands = Vec::new();
+            let mut creadr = 0;
+            while !stop.load(Ordering::Relaxed) {
+                if its.read32(gits::CTLR) & gits::CTLR_QUIESCENT == 0 {
+                    its.write32(gits::CTLR, gits::CTLR_QUIESCENT);
+                }
+                let cwriter = its.read64(gits::CWRITER);
+                let queue = (its.read64(gits::CBASER) & 0x000F_FFFF_FFFF_F000) as usize;
+                while queue != 0 && creadr != cwriter {
+                    let slot = (queue + creadr as usize) as *const [u64; 4];
+                    // SAFETY: The slot lies within the command queue allocated by the ITS.
+                    commands.push(unsafe { core::ptr::read_volatile(slot) });
+                    creadr = (creadr + COMMAND_SIZE as u64) % COMMAND_QUEUE_SIZE as u64;
+                    its.write64(gits::CREADR, creadr);
+                }
+                thread::yield_now();
+            }
+            commands
+        })
+    }
+
+    fn its_frames() -> (Frame, Frame) {
+        let its = Frame::new(0x20000);
+        let rd = Frame::new(0x10000);
+        // GITS_TYPER: 8 byte ITT entries, 16 EventID bits, 20 DeviceID bits.
+        its.mmio().write64(gits::TYPER, 7 << 4 | 15 << 8 | 19 << 13);
+        // GITS_BASER0 holds 8 byte device entries, GITS_BASER1 holds 8 byte collection entries.
+        its.mmio().write64(gits::BASER, gits::BASER_TYPE_DEVICES << 56 | 7 << 48);
+        its.mmio().write64(gits::BASER + 8, gits::BASER_TYPE_COLLECTIONS << 56 | 7 << 48);
+        // GICR_TYPER: physical LPIs supported, processor number 3.
+        rd.mmio().write64(gicr::TYPER, gicr::TYPER_PLPIS | 3 << 8);
+        (its, rd)
+    }
+
+    #[test]
+    fn test_command_encoding() {
+        assert_eq!(
+            ItsCommand::Mapd { device_id: 0x10, itt_address: 0x8000_0100, event_id_bits: 6 }.encode(),
+            [0x0000_0010_0000_0008, 5, 0x8000_0000_8000_0100, 0]
+        );
+        assert_eq!(ItsCommand::Mapc { collection: 2, rd_base: 3 }.encode(), [0x09, 0, 0x8000_0000_0003_0002, 0]);
+        assert_eq!(
+            ItsCommand::Mapti { device_id: 1, event_id: 4, intid: 8200, collection: 0 }.encode(),
+            [0x0000_0001_0000_000A, 0x0000_2008_0000_0004, 0, 0]
+        );
+        assert_eq!(ItsCommand::Inv { device_id: 1, event_id: 4 }.encode(), [0x0000_0001_0000_000C, 4, 0, 0]);
+        assert_eq!(ItsCommand::Discard { device_id: 1, event_id: 4 }.encode(), [0x0000_0001_0000_000F, 4, 0, 0]);
+        assert_eq!(ItsCommand::Sync { rd_base: 3 }.encode(), [0x05, 0, 0x3_0000, 0]);
+    }
+
+    #[test]
+    fn test_lpi_allocator() {
+        let mut lpis = LpiAllocator::new(14);
+        assert_eq!(lpis.allocate(), Some(LPI_START));
+        assert_eq!(lpis.allocate(), Some(LPI_START + 1));
+        lpis.free(LPI_START);
+        assert_eq!(lpis.allocate(), Some(LPI_START));
+
+        lpis.next = lpis.limit - 1;
+        assert_eq!(lpis.allocate(), Some((1 << 14) - 1));
+        assert_eq!(lpis.allocate(), None);
+    }
+
+    #[test]
+    fn test_initialize_requires_physical_lpis() {
+        let (its, rd) = its_frames();
+        rd.mmio().write64(gicr::TYPER, 0);
+        // SAFETY: The frames stand in for the ITS and redistributor.
+        let result = unsafe { GicIts::initialize(its.base(), rd.base(), 16, &std::alloc::System) };
+        assert_eq!(result.err(), Some(EfiError::Unsupported));
+
+        rd.mmio().write64(gicr::TYPER, gicr::TYPER_PLPIS);
+        // SAFETY: The frames stand in for the ITS and redistributor.
+        let result = unsafe { GicIts::initialize(its.base(), rd.base(), 13, &std::alloc::System) };
+        assert_eq!(result.err(), Some(EfiError::Unsupported));
+    }
+
+    #[test]
+    fn test_initialize_and_allocate_msi() {
+        let (its_frame, rd) = its_frames();
+        let stop = Arc::new(AtomicBool::new(false));
+        let emulator = emulate_its(its_frame.mmio(), stop.clone());
+
+        // SAFETY: The frames stand in for the ITS and redistributor.
+        let mut its = unsafe { GicIts::initialize(its_frame.base(), rd.base(), 16, &std::alloc::System) }.unwrap();
+
+        assert_ne!(rd.mmio().read32(gicr::CTLR) & gicr::CTLR_ENABLE_LPIS, 0);
+        assert_eq!(rd.mmio().read64(gicr::PROPBASER) & 0x1F, 15);
+        assert_ne!(its_frame.mmio().read32(gits::CTLR) & gits::CTLR_ENABLED, 0);
+        for baser in [gits::BASER, gits::BASER + 8] {
+            let value = its_frame.mmio().read64(baser);
+            assert_ne!(value & 1 << 63, 0);
+            assert_eq!(field(value, 8, 2), 0b10);
+        }
+        assert_eq!(its.lpi_count(), (1 << MAX_LPI_ID_BITS) - LPI_START as usize);
+        assert_eq!(its.event_id_bits, EVENT_ID_BITS);
+        assert_eq!(its.device_id_bits, MAX_DEVICE_ID_BITS);
+
+        let first = its.allocate_msi(0x100).unwrap();
+        assert_eq!(first.address, its_frame.base() + gits::TRANSLATER);
+        assert_eq!((first.device_id, first.data, first.interrupt_source), (0x100, 0, LPI_START as u64));
+        let second = its.allocate_msi(0x100).unwrap();
+        assert_eq!((second.data, second.interrupt_source), (1, LPI_START as u64 + 1));
+        assert_eq!(its.allocate_msi(1 << MAX_DEVICE_ID_BITS), Err(EfiError::InvalidParameter));
+
+        assert_eq!(its.is_lpi_enabled(LPI_START), Ok(false));
+        its.set_lpi_enabled(LPI_START, true).unwrap();
+        assert_eq!(its.is_lpi_enabled(LPI_START), Ok(true));
+        assert_eq!(its.is_lpi_enabled(LPI_START + 2), Err(EfiError::InvalidParameter));
+
+        its.free_msi(first).unwrap();
+        assert!(!its.is_mapped_lpi(LPI_START));
+        assert_eq!(its.free_msi(first), Err(EfiError::InvalidParameter));
+        let reused = its.allocate_msi(0x100).unwrap();
+        assert_eq!((reused.data, reused.interrupt_source), (0, LPI_START as u64));
+
+        stop.store(true, Ordering::Relaxed);
+        let commands = emulator.join().unwrap();
+        let opcodes: Vec<u64> = commands.iter().map(|command| command[0] & 0xFF).collect();
+        assert_eq!(
+            opcodes,
+            [
+                0x09, 0x05, // MAPC, SYNC
+                0x08, 0x05, 0x0A, 0x05, // MAPD, SYNC, MAPTI, SYNC
+                0x0A, 0x05, // MAPTI, SYNC
+                0x0C, 0x05, // INV, SYNC
+                0x0C, 0x05, 0x0F, 0x05, // INV, SYNC, DISCARD, SYNC
+                0x0A, 0x05, // MAPTI, SYNC
+            ]
+        );
+        // The collection targets the processor number of the redistributor, as PTA is clear.
+        assert_eq!(commands[0][2], ItsCommand::Mapc { collection: 0, rd_base: 3 }.encode()[2]);
+    }
+}
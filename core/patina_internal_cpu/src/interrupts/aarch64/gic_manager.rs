@@ -2,10 +2,13 @@ use arm_gic::{
     IntId, Trigger,
     gicv3::{GicV3, InterruptGroup},
 };
-use patina::error::EfiError;
+use patina::{component::service::msi::MsiVector, error::EfiError};
 use safe_mmio::field;
 
-use crate::interrupts::aarch64::sysreg::{read_sysreg, write_sysreg};
+use crate::interrupts::aarch64::{
+    gic_its::{GicIts, LPI_START},
+    sysreg::{read_sysreg, write_sysreg},
+};
 
 // Create basic enum for GIC version
 #[derive(PartialEq)]
@@ -116,12 +119,19 @@ pub unsafe fn gic_initialize<'a>(gicd_base: *mut u64, gicr_base: *mut u64) -> Re
 
 pub struct AArch64InterruptInitializer<'a> {
     pub gic_v3: GicV3<'a>,
+    pub its: Option<GicIts>,
 }
 
 impl AArch64InterruptInitializer<'_> {
     fn source_to_intid(&self, interrupt_source: u64) -> Result<IntId, EfiError> {
         let int_id: u32 = interrupt_source.try_into().map_err(|_| EfiError::InvalidParameter)?;
         let int_id = match int_id {
+            x if x >= LPI_START => {
+                if !self.its.as_ref().is_some_and(|its| its.is_mapped_lpi(x)) {
+                    Err(EfiError::InvalidParameter)?;
+                }
+                IntId::lpi(x - LPI_START)
+            }
             x if x < IntId::SGI_COUNT => IntId::sgi(x),
             x if x < IntId::SGI_COUNT + IntId::PPI_COUNT => IntId::ppi(x - IntId::SGI_COUNT),
             x => {
@@ -135,15 +145,28 @@ impl AArch64InterruptInitializer<'_> {
         Ok(int_id)
     }
 
+    /// Returns the ITS if the interrupt is an LPI. LPIs are configured through the ITS rather than the GIC registers.
+    fn lpi_its(&mut self, int_id: IntId) -> Option<&mut GicIts> {
+        if u32::from(int_id) >= LPI_START { self.its.as_mut() } else { None }
+    }
+
     /// Enables the specified interrupt source.
     pub fn enable_interrupt_source(&mut self, interrupt_source: u64) -> Result<(), EfiError> {
-        self.gic_v3.enable_interrupt(self.source_to_intid(interrupt_source)?, Some(0), true);
+        let int_id = self.source_to_intid(interrupt_source)?;
+        if let Some(its) = self.lpi_its(int_id) {
+            return its.set_lpi_enabled(int_id.into(), true);
+        }
+        self.gic_v3.enable_interrupt(int_id, Some(0), true);
         Ok(())
     }
 
     /// Disables the specified interrupt source.
     pub fn disable_interrupt_source(&mut self, interrupt_source: u64) -> Result<(), EfiError> {
-        self.gic_v3.enable_interrupt(self.source_to_intid(interrupt_source)?, Some(0), false);
+        let int_id = self.source_to_intid(interrupt_source)?;
+        if let Some(its) = self.lpi_its(int_id) {
+            return its.set_lpi_enabled(int_id.into(), false);
+        }
+        self.gic_v3.enable_interrupt(int_id, Some(0), false);
         Ok(())
     }
 
@@ -155,7 +178,9 @@ impl AArch64InterruptInitializer<'_> {
         // validates the interrupt source
         let int_id = self.source_to_intid(interrupt_source)?;
 
-        if int_id.is_private() {
+        if let Some(its) = self.lpi_its(int_id) {
+            its.is_lpi_enabled(int_id.into())
+        } else if int_id.is_private() {
             let mut sgi = self.gic_v3.sgi_ptr(0);
             Ok(field!(sgi, isenabler0).read() & bit != 0)
         } else {
@@ -179,7 +204,10 @@ impl AArch64InterruptInitializer<'_> {
         // validates the interrupt source
         let int_id = self.source_to_intid(interrupt_source)?;
 
-        let level = if int_id.is_private() {
+        // LPIs are always edge triggered.
+        let level = if self.lpi_its(int_id).is_some() {
+            false
+        } else if int_id.is_private() {
             let mut sgi = self.gic_v3.sgi_ptr(0);
             field!(sgi, icfgr).get(index).unwrap().read() & bit != 0
         } else {
@@ -193,12 +221,35 @@ impl AArch64InterruptInitializer<'_> {
 
     /// Sets the trigger type for the specified interrupt.
     pub fn set_trigger_type(&mut self, interrupt_source: u64, trigger_type: Trigger) -> Result<(), EfiError> {
-        self.gic_v3.set_trigger(self.source_to_intid(interrupt_source)?, Some(0), trigger_type);
+        let int_id = self.source_to_intid(interrupt_source)?;
+        if self.lpi_its(int_id).is_some() {
+            // LPIs are always edge triggered.
+            return if trigger_type == Trigger::Edge { Ok(()) } else { Err(EfiError::Unsupported) };
+        }
+        self.gic_v3.set_trigger(int_id, Some(0), trigger_type);
         Ok(())
     }
 
+    /// Allocates an MSI vector for the device identified by `device_id`.
+    ///
+    /// Returns [EfiError::Unsupported] if no ITS was configured.
+    pub fn allocate_msi(&mut self, device_id: u32) -> Result<MsiVector, EfiError> {
+        self.its.as_mut().ok_or(EfiError::Unsupported)?.allocate_msi(device_id)
+    }
+
+    /// Frees an MSI vector returned by [AArch64InterruptInitializer::allocate_msi].
+    pub fn free_msi(&mut self, vector: MsiVector) -> Result<(), EfiError> {
+        self.its.as_mut().ok_or(EfiError::Unsupported)?.free_msi(vector)
+    }
+
     /// Instantiates a new AArch64InterruptInitializer
     pub fn new(gic_v3: GicV3<'static>) -> Self {
-        AArch64InterruptInitializer { gic_v3 }
+        AArch64InterruptInitializer { gic_v3, its: None }
+    }
+
+    /// Attaches an initialized ITS, allowing LPIs to be used as interrupt sources.
+    pub fn with_its(mut self, its: GicIts) -> Self {
+        self.its = Some(its);
+        self
     }
 }
@@ -21,7 +21,7 @@ use patina_stacktrace::StackTrace;
 use crate::interrupts::aarch64::sysreg::{read_sysreg, write_sysreg};
 use crate::interrupts::{
     EfiExceptionStackTrace, EfiSystemContext, HandlerType, InterruptManager, aarch64::ExceptionContextAArch64,
-    exception_handling::FaultAllocator,
+    exception_handling::FaultAllocator, filter_memory_fault,
 };
 use crate::interrupts::{disable_interrupts, enable_interrupts};
 
@@ -152,6 +152,10 @@ extern "efiapi" fn synchronous_exception_handler(_exception_type: isize, context
     // to report. The system is dead anyway.
     let aarch64_context = unsafe { context.system_context_aarch64.as_ref().unwrap() };
 
+    if filter_memory_fault(aarch64_context, 0) {
+        return;
+    }
+
     log::error!("");
     log::error!("EXCEPTION: Synchronous Exception");
 
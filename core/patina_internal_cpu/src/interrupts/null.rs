@@ -24,6 +24,12 @@ impl super::EfiSystemContextFactory for ExceptionContextNull {
     }
 }
 
+impl super::DecodeMemoryFault for ExceptionContextNull {
+    fn decode_memory_fault(&self, _exception_type: super::ExceptionType) -> Option<super::MemoryFault> {
+        None
+    }
+}
+
 impl super::EfiExceptionStackTrace for ExceptionContextNull {
     fn dump_stack_trace(&self) {}
     fn dump_system_context_registers(&self) {}
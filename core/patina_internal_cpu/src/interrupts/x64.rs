@@ -31,6 +31,24 @@ impl super::EfiSystemContextFactory for ExceptionContextX64 {
     }
 }
 
+impl super::DecodeMemoryFault for ExceptionContextX64 {
+    fn decode_memory_fault(&self, exception_type: super::ExceptionType) -> Option<super::MemoryFault> {
+        const PAGE_FAULT: super::ExceptionType = 14;
+        if exception_type != PAGE_FAULT {
+            return None;
+        }
+
+        let access = if self.exception_data & 0x10 != 0 {
+            super::MemoryFaultAccess::Execute
+        } else if self.exception_data & 0x2 != 0 {
+            super::MemoryFaultAccess::Write
+        } else {
+            super::MemoryFaultAccess::Read
+        };
+        Some(super::MemoryFault { address: self.cr2, instruction_pointer: self.rip, access })
+    }
+}
+
 impl super::EfiExceptionStackTrace for ExceptionContextX64 {
     fn dump_stack_trace(&self) {
         if let Err(err) = unsafe { StackTrace::dump_with(self.rip, self.rsp) } {
@@ -20,7 +20,8 @@ use x86_64::structures::idt::InterruptDescriptorTable;
 use x86_64::structures::idt::InterruptStackFrame;
 
 use crate::interrupts::{
-    EfiExceptionStackTrace, HandlerType, InterruptManager, exception_handling::FaultAllocator, x64::ExceptionContextX64,
+    EfiExceptionStackTrace, HandlerType, InterruptManager, dedicated_exception_stack_requested,
+    exception_handling::FaultAllocator, filter_memory_fault, x64::ExceptionContextX64,
 };
 
 global_asm!(include_str!("interrupt_handler.asm"));
@@ -62,6 +63,12 @@ lazy_static! {
             idt.security_exception.set_handler_addr(get_vector_address(30));
         }
 
+        // Page faults from a stack overflow cannot be handled on the overflowed stack, so they optionally switch to
+        // the page fault IST stack set up in the TSS.
+        if dedicated_exception_stack_requested() {
+            unsafe { idt.page_fault.set_handler_addr(get_vector_address(14)).set_stack_index(1) };
+        }
+
         // Initialize generic interrupts.
         for vector in 32..=255 {
             unsafe { idt[vector].set_handler_addr(get_vector_address(vector.into())) };
@@ -157,6 +164,10 @@ extern "efiapi" fn general_protection_fault_handler(_exception_type: isize, cont
 extern "efiapi" fn page_fault_handler(_exception_type: isize, context: EfiSystemContext) {
     let x64_context = unsafe { context.system_context_x64.as_ref().unwrap() };
 
+    if filter_memory_fault(x64_context, 14) {
+        return;
+    }
+
     log::error!("EXCEPTION: PAGE FAULT");
     log::error!("Accessed Address: {:#X?}", x64_context.cr2);
     log::error!("Paging Enabled: {}", x64_context.cr0 & 0x80000000 != 0);
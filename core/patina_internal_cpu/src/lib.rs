@@ -13,6 +13,10 @@
 #![feature(coverage_attribute)]
 extern crate alloc;
 
+pub mod cache;
+pub mod control_flow;
 pub mod cpu;
+pub mod features;
 pub mod interrupts;
 pub mod paging;
+pub mod tlb;
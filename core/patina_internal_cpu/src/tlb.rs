@@ -0,0 +1,33 @@
+//! UEFI TLB Maintenance Module
+//!
+//! The page table code invalidates the translations it changes on the processor that changes them, but other
+//! processors may still hold the previous translations in their TLBs. Once application processors (APs) are running,
+//! the core asks each of them to invalidate its TLB after an existing mapping is changed or removed; see
+//! [flush_local_tlb].
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "uefi", target_arch = "x86_64"))] {
+        mod x64;
+        use x64 as arch;
+    } else if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
+        mod aarch64;
+        use aarch64 as arch;
+    } else {
+        mod null;
+        use null as arch;
+    }
+}
+
+/// Invalidates all translations cached in the TLB of the calling processor.
+///
+/// This only affects the calling processor, so it is suitable to be run as an AP procedure.
+pub fn flush_local_tlb() {
+    arch::flush_local_tlb();
+}
@@ -0,0 +1,33 @@
+//! AArch64 TLB Maintenance implementation
+//!
+//! The translation regime that is invalidated depends on the exception level the firmware runs at, which is read
+//! from `CurrentEL`.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::arch::asm;
+
+// CurrentEL.EL (bits 3:2)
+const CURRENT_EL_SHIFT: u64 = 2;
+const CURRENT_EL_MASK: u64 = 0x3;
+
+pub(super) fn flush_local_tlb() {
+    let current_el: u64;
+    // SAFETY: reading CurrentEL has no side effects.
+    unsafe { asm!("mrs {}, CurrentEL", out(reg) current_el, options(nomem, nostack, preserves_flags)) };
+
+    // SAFETY: TLB invalidation and barriers do not change the contents of memory or the active page table.
+    unsafe {
+        asm!("dsb ishst", options(nostack, preserves_flags));
+        if (current_el >> CURRENT_EL_SHIFT) & CURRENT_EL_MASK == 2 {
+            asm!("tlbi alle2", options(nostack, preserves_flags));
+        } else {
+            asm!("tlbi vmalle1", options(nostack, preserves_flags));
+        }
+        asm!("dsb nsh", "isb", options(nostack, preserves_flags));
+    }
+}
@@ -0,0 +1,10 @@
+//! Null TLB Maintenance implementation - For doc tests and architectures without a TLB maintenance implementation
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+pub(super) fn flush_local_tlb() {}
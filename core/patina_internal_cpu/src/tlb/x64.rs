@@ -0,0 +1,24 @@
+//! x64 TLB Maintenance implementation
+//!
+//! Reloading `CR3` invalidates all non-global translations. The page tables built by the core do not set the global
+//! bit, so this invalidates every translation the core may have changed.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::arch::asm;
+
+pub(super) fn flush_local_tlb() {
+    // SAFETY: writing back the current value of CR3 does not change the active page table.
+    unsafe {
+        asm!(
+            "mov {cr3}, cr3",
+            "mov cr3, {cr3}",
+            cr3 = out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+}
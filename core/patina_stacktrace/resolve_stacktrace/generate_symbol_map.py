@@ -0,0 +1,63 @@
+# Generates the symbol map embedded in an image with the `symbol_map` feature from the lld-link map of the image.
+#
+# Build the image with `-Clink-arg=/lldmap:<linker map>`, then run:
+#
+#   python generate_symbol_map.py <linker map> <symbol map>
+#
+# and build the image again with PATINA_SYMBOL_MAP=<symbol map>. The format of the symbol map is described in
+# `src/symbol_map.rs`.
+#
+# Copyright (c) Microsoft Corporation.
+#
+# SPDX-License-Identifier: Apache-2.0
+
+import argparse
+import re
+import struct
+
+# Each line starts with the RVA, size and alignment, followed by the output section, input section or symbol, each
+# indented by another 8 spaces.
+LINE = re.compile(r"^([0-9a-fA-F]{8,16}) [0-9a-fA-F]{8,16} +\d+ ( *)(\S.*)$")
+SYMBOL_INDENT = 16
+
+
+def read_functions(path):
+    functions = {}
+    in_code = False
+    with open(path, encoding="utf-8", errors="replace") as linker_map:
+        for line in linker_map:
+            match = LINE.match(line.rstrip("\n"))
+            if match is None:
+                continue
+            rva, indent, name = int(match.group(1), 16), len(match.group(2)), match.group(3)
+            if indent == 0:
+                in_code = name.startswith(".text")
+            elif indent == SYMBOL_INDENT and in_code:
+                # Keep the first name of functions that were folded together.
+                functions.setdefault(rva, name)
+    return sorted(functions.items())
+
+
+def write_symbol_map(functions, path):
+    entries = bytearray()
+    strings = bytearray()
+    for rva, name in functions:
+        entries += struct.pack("<II", rva, len(strings))
+        strings += name.encode("utf-8") + b"\0"
+    with open(path, "wb") as symbol_map:
+        symbol_map.write(b"PSYM" + struct.pack("<I", len(functions)) + entries + strings)
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Generates an embedded symbol map from an lld-link map.")
+    parser.add_argument("linker_map", help="the map written by lld-link /lldmap")
+    parser.add_argument("symbol_map", help="the symbol map to write")
+    args = parser.parse_args()
+
+    functions = read_functions(args.linker_map)
+    write_symbol_map(functions, args.symbol_map)
+    print(f"Wrote {len(functions)} symbols to {args.symbol_map}")
+
+
+if __name__ == "__main__":
+    main()
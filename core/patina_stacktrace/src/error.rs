@@ -37,6 +37,9 @@ pub enum Error {
     /// Failed to dump all the frames in the stack trace
     StackTraceDumpFailed(Option<&'static str>),
 
+    /// The symbol map does not match the image it is registered for
+    SymbolMapMismatch(Option<&'static str>),
+
     /// Failed to load module(mainly in tests)
     #[cfg(test)]
     ModuleLoadFailed(Option<&'static str>),
@@ -90,6 +93,13 @@ impl fmt::Display for Error {
                     module.as_ref().unwrap_or(&no_module_str)
                 )
             }
+            Error::SymbolMapMismatch(module) => {
+                write!(
+                    fmt,
+                    "Symbol map does not match module {}. Regenerate it from the linker map of this build",
+                    module.as_ref().unwrap_or(&no_module_str)
+                )
+            }
             #[cfg(test)]
             Error::ModuleLoadFailed(module) => {
                 write!(fmt, "Failed to load module: {}", module.as_ref().unwrap_or(&no_module_str))
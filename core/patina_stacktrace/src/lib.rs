@@ -108,6 +108,14 @@
 //!     StackTrace::dump();
 //! ```
 //!
+//! ## Embedded Symbol Map
+//!
+//! An image can embed a [symbol_map::SymbolMap] generated from its linker map
+//! with `resolve_stacktrace/generate_symbol_map.py` and register it with
+//! [symbol_map::register]. Frames in that image are then displayed as
+//! `module!function+<offset>` without offline resolution. The DXE core does so
+//! with its `symbol_map` feature.
+//!
 //! ## Reference
 //!
 //! More reference test cases are in `src\x64\tests\*.rs`
@@ -121,6 +129,7 @@ mod byte_reader;
 pub mod error;
 mod pe;
 mod stacktrace;
+pub mod symbol_map;
 
 cfg_if::cfg_if! {
     if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
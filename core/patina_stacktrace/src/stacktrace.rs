@@ -1,6 +1,7 @@
 use crate::error::Error;
 use crate::error::StResult;
 use crate::pe::PE;
+use crate::symbol_map;
 use core::arch::asm;
 
 cfg_if::cfg_if! {
@@ -56,7 +57,12 @@ impl StackTrace {
             let unwind_info = runtime_function.get_unwind_info()?;
             let (curr_sp, _curr_pc, prev_sp, prev_pc) = unwind_info.get_current_stack_frame(sp, pc)?;
 
-            log::info!("      {i} {curr_sp:016X}      {prev_pc:016X}       {image_name}+{pc_rva:X}");
+            match symbol_map::resolve(pc) {
+                Some((function, offset)) => {
+                    log::info!("      {i} {curr_sp:016X}      {prev_pc:016X}       {image_name}!{function}+{offset:X}")
+                }
+                None => log::info!("      {i} {curr_sp:016X}      {prev_pc:016X}       {image_name}+{pc_rva:X}"),
+            }
 
             sp = prev_sp;
             pc = prev_pc;
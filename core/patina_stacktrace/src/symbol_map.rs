@@ -0,0 +1,199 @@
+//! Embedded Symbol Map
+//!
+//! A symbol map lists the functions of one image, by RVA, so that stack traces through that image show
+//! `module!function+offset` rather than `module+rva` without needing the PDB. The map is generated from the linker
+//! map of the image with `resolve_stacktrace/generate_symbol_map.py` and embedded in the image by a second build.
+//!
+//! The map is laid out as follows, all integers in little endian:
+//!
+//! ```text
+//! magic       "PSYM"
+//! count       u32
+//! entries     count x { rva: u32, name_offset: u32 }, in increasing RVA order
+//! strings     NUL terminated function names, name_offset is relative to the start of the strings
+//! ```
+//!
+//! Since the map is generated from a previous build, it can be out of date. [register] checks that the map resolves
+//! a known function of the image to its own name before stack traces use it.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use spin::Once;
+
+use crate::byte_reader::ByteReader;
+use crate::error::{Error, StResult};
+use crate::pe::PE;
+
+/// The signature at the start of a symbol map.
+pub const SYMBOL_MAP_MAGIC: &[u8; 4] = b"PSYM";
+
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 8;
+
+/// A parsed symbol map.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolMap<'a> {
+    entries: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> SymbolMap<'a> {
+    /// Parses the symbol map in `bytes`.
+    pub fn parse(bytes: &'a [u8]) -> StResult<Self> {
+        if bytes.get(..SYMBOL_MAP_MAGIC.len()) != Some(SYMBOL_MAP_MAGIC.as_slice()) {
+            return Err(Error::Malformed("symbol map signature"));
+        }
+        let count = bytes.read32(SYMBOL_MAP_MAGIC.len())? as usize;
+        let strings_offset = count
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .filter(|&offset| offset <= bytes.len())
+            .ok_or(Error::BufferTooShort(bytes.len()))?;
+
+        let map = Self { entries: &bytes[HEADER_SIZE..strings_offset], strings: &bytes[strings_offset..] };
+        let mut previous_rva = 0;
+        for index in 0..count {
+            let (rva, _) = map.entry(index)?;
+            if rva < previous_rva {
+                return Err(Error::Malformed("symbol map is not sorted by rva"));
+            }
+            previous_rva = rva;
+        }
+        Ok(map)
+    }
+
+    /// Returns the number of symbols in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// Returns true if the map has no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the name of the function containing `rva` and the offset of `rva` into it, if any function starts at or
+    /// below `rva`.
+    pub fn resolve(&self, rva: u32) -> Option<(&'a str, u32)> {
+        // The number of functions starting at or below `rva`.
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.entry(middle).ok()?.0 <= rva {
+                true => low = middle + 1,
+                false => high = middle,
+            }
+        }
+        let (start, name_offset) = self.entry(low.checked_sub(1)?).ok()?;
+        Some((self.name(name_offset)?, rva - start))
+    }
+
+    fn entry(&self, index: usize) -> StResult<(u32, u32)> {
+        let offset = index * ENTRY_SIZE;
+        Ok((self.entries.read32(offset)?, self.entries.read32(offset + 4)?))
+    }
+
+    fn name(&self, offset: u32) -> Option<&'a str> {
+        let name = self.strings.get(offset as usize..)?;
+        let length = name.iter().position(|&byte| byte == 0)?;
+        core::str::from_utf8(&name[..length]).ok()
+    }
+}
+
+/// A symbol map registered for the image it was generated from.
+struct RegisteredMap {
+    base_address: u64,
+    size_of_image: u32,
+    map: SymbolMap<'static>,
+}
+
+static REGISTERED_MAP: Once<RegisteredMap> = Once::new();
+
+/// Registers `map` as the symbol map of the image containing the function at `anchor`, which must be named
+/// `anchor_name` in the map. Only one map can be registered.
+///
+/// # Safety
+///
+/// `anchor` must be the address of a function in a loaded PE image, as the image headers are located by reading the
+/// memory below it.
+pub unsafe fn register(map: SymbolMap<'static>, anchor: u64, anchor_name: &str) -> StResult<()> {
+    let image = unsafe { PE::locate_image(anchor) }?;
+    let anchor_rva = (anchor - image.base_address) as u32;
+    match map.resolve(anchor_rva) {
+        Some((name, 0)) if name.contains(anchor_name) => {}
+        _ => return Err(Error::SymbolMapMismatch(image.image_name)),
+    }
+    REGISTERED_MAP.call_once(|| RegisteredMap {
+        base_address: image.base_address,
+        size_of_image: image._size_of_image,
+        map,
+    });
+    Ok(())
+}
+
+/// Returns the function containing `address` and the offset of `address` into it, if `address` is in the image of
+/// the registered symbol map.
+pub fn resolve(address: u64) -> Option<(&'static str, u32)> {
+    let registered = REGISTERED_MAP.get()?;
+    let rva = address.checked_sub(registered.base_address).filter(|&rva| rva < registered.size_of_image as u64)?;
+    registered.map.resolve(rva as u32)
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn build_map(symbols: &[(u32, &str)]) -> Vec<u8> {
+        let mut entries = Vec::new();
+        let mut strings = Vec::new();
+        for &(rva, name) in symbols {
+            entries.extend_from_slice(&rva.to_le_bytes());
+            entries.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+        }
+        let mut map = SYMBOL_MAP_MAGIC.to_vec();
+        map.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+        map.extend(entries);
+        map.extend(strings);
+        map
+    }
+
+    #[test]
+    fn test_resolve_finds_the_containing_function() {
+        let bytes = build_map(&[(0x1000, "core::entry"), (0x1040, "core::dispatch"), (0x2000, "core::exit")]);
+        let map = SymbolMap::parse(&bytes).unwrap();
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.resolve(0xFFF), None);
+        assert_eq!(map.resolve(0x1000), Some(("core::entry", 0)));
+        assert_eq!(map.resolve(0x103F), Some(("core::entry", 0x3F)));
+        assert_eq!(map.resolve(0x1040), Some(("core::dispatch", 0)));
+        assert_eq!(map.resolve(0x2010), Some(("core::exit", 0x10)));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_maps() {
+        assert!(SymbolMap::parse(b"").is_err());
+        assert!(SymbolMap::parse(b"MZ\0\0\0\0\0\0").is_err());
+
+        let mut truncated = build_map(&[(0x1000, "a"), (0x2000, "b")]);
+        truncated.truncate(HEADER_SIZE + ENTRY_SIZE);
+        assert_eq!(SymbolMap::parse(&truncated).unwrap_err(), Error::BufferTooShort(truncated.len()));
+
+        let unsorted = build_map(&[(0x2000, "a"), (0x1000, "b")]);
+        assert!(SymbolMap::parse(&unsorted).is_err());
+
+        let empty = build_map(&[]);
+        assert!(SymbolMap::parse(&empty).unwrap().is_empty());
+        assert_eq!(SymbolMap::parse(&empty).unwrap().resolve(0x1000), None);
+    }
+}
@@ -0,0 +1,30 @@
+//! Build script for the DXE Core.
+//!
+//! With the `symbol_map` feature, embeds the symbol map named by the `PATINA_SYMBOL_MAP` environment variable in the
+//! core. Without the variable an empty map is embedded, so the first of the two builds (the one whose linker map the
+//! symbol map is generated from) has the same layout as the second.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=PATINA_SYMBOL_MAP");
+    if env::var_os("CARGO_FEATURE_SYMBOL_MAP").is_none() {
+        return;
+    }
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR")).join("symbol_map.bin");
+    let map = match env::var_os("PATINA_SYMBOL_MAP") {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", path.to_string_lossy());
+            fs::read(&path).unwrap_or_else(|err| panic!("Failed to read PATINA_SYMBOL_MAP {path:?}: {err}"))
+        }
+        None => Vec::new(),
+    };
+    fs::write(&out, map).unwrap_or_else(|err| panic!("Failed to write {out:?}: {err}"));
+}
@@ -7,16 +7,10 @@
 //! SPDX-License-Identifier: Apache-2.0
 //!
 mod fixed_size_block_allocator;
+mod page_zero;
 mod uefi_allocator;
 
-use core::{
-    ffi::c_void,
-    fmt::Debug,
-    mem,
-    ops::Range,
-    ptr::NonNull,
-    slice::{self, from_raw_parts_mut},
-};
+use core::{ffi::c_void, fmt::Debug, mem, ops::Range, ptr::NonNull, slice};
 
 extern crate alloc;
 use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
@@ -25,9 +19,11 @@ use mu_rust_helpers::function;
 use crate::{
     GCD, config_tables,
     gcd::{self, AllocateType as AllocationStrategy},
+    image::with_image_at_address,
     memory_attributes_table::MemoryAttributesTable,
     protocol_db::{self, INVALID_HANDLE},
     protocols::PROTOCOL_DB,
+    reserved_regions,
     systemtables::EfiSystemTable,
     tpl_lock,
 };
@@ -40,6 +36,10 @@ pub use uefi_allocator::UefiAllocator;
 
 use patina::{
     base::{SIZE_4KB, UEFI_PAGE_MASK, UEFI_PAGE_SIZE},
+    component::service::{
+        IntoService,
+        memory_map::{MemoryMap, MemoryMapSnapshot},
+    },
     error::EfiError,
     guids::{self, HOB_MEMORY_ALLOC_STACK},
     uefi_size_to_pages,
@@ -48,6 +48,60 @@ use patina::{
 // Allocation Strategy when not specified by caller.
 pub const DEFAULT_ALLOCATION_STRATEGY: AllocationStrategy = AllocationStrategy::TopDown(None);
 
+/// When the core zeroes pages allocated or freed with AllocatePages and FreePages.
+///
+/// Pages are not zeroed unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, MemoryZeroPolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(MemoryZeroPolicy { zero_on_free: true, skip_boot_services_data: true, ..Default::default() })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryZeroPolicy {
+    /// Zero pages before they are freed, so that their contents do not leak to the next owner.
+    pub zero_on_free: bool,
+    /// Zero pages when they are allocated.
+    pub zero_on_allocate: bool,
+    /// Do not zero boot services data pages when they are allocated or freed. This is a performance opt-out for the
+    /// many boot services data allocations made during boot; the pages are handed to the OS with their contents.
+    /// It does not apply to `zero_boot_services_at_exit`.
+    pub skip_boot_services_data: bool,
+    /// Zero the boot services code and data pages that are still allocated at ExitBootServices(), so that secrets
+    /// left in driver buffers do not persist into memory owned by the OS.
+    pub zero_boot_services_at_exit: bool,
+}
+
+impl MemoryZeroPolicy {
+    fn zeroes(&self, memory_type: efi::MemoryType) -> bool {
+        !(self.skip_boot_services_data && memory_type == efi::BOOT_SERVICES_DATA)
+    }
+}
+
+static ZERO_POLICY: spin::RwLock<MemoryZeroPolicy> = spin::RwLock::new(MemoryZeroPolicy {
+    zero_on_free: false,
+    zero_on_allocate: false,
+    skip_boot_services_data: false,
+    zero_boot_services_at_exit: false,
+});
+
+/// Applies the platform's page zeroing policy to subsequent page allocations and frees.
+pub(crate) fn set_memory_zero_policy(policy: &MemoryZeroPolicy) {
+    *ZERO_POLICY.write() = *policy;
+}
+
+/// Returns whether boot services memory will be zeroed at the end of ExitBootServices().
+pub(crate) fn zeroes_boot_services_at_exit() -> bool {
+    ZERO_POLICY.read().zero_boot_services_at_exit && !crate::runtime::addressing_checks_enabled()
+}
+
 // Private tracking guid used to generate new handles for allocator tracking
 // {9D1FA6E9-0C86-4F7F-A99B-DD229C9B3893}
 const PRIVATE_ALLOCATOR_TRACKING_GUID: efi::Guid =
@@ -190,8 +244,8 @@ fn memory_attributes_to_str(f: &mut core::fmt::Formatter<'_>, attributes: u64) -
     write!(f, "{:<20}", attrs.join("|"))
 }
 
-fn memory_type_to_str(f: &mut core::fmt::Formatter<'_>, memory_type: efi::MemoryType) -> core::fmt::Result {
-    let string = match memory_type {
+fn memory_type_name(memory_type: efi::MemoryType) -> &'static str {
+    match memory_type {
         efi::RESERVED_MEMORY_TYPE => "Reserved Memory",
         efi::LOADER_CODE => "Loader Code",
         efi::LOADER_DATA => "Loader Data",
@@ -208,9 +262,16 @@ fn memory_type_to_str(f: &mut core::fmt::Formatter<'_>, memory_type: efi::Memory
         efi::PAL_CODE => "PAL Code",
         efi::PERSISTENT_MEMORY => "Persistent Memory",
         _ => "Unknown Memory Type",
-    };
+    }
+}
+
+/// Returns the name of the memory type managed by the allocator with `handle`, or `None` if no allocator has it.
+pub(crate) fn allocator_memory_type_name(handle: efi::Handle) -> Option<&'static str> {
+    ALLOCATORS.lock().memory_type_for_handle(handle).map(memory_type_name)
+}
 
-    write!(f, "{string:<25}")
+fn memory_type_to_str(f: &mut core::fmt::Formatter<'_>, memory_type: efi::MemoryType) -> core::fmt::Result {
+    write!(f, "{:<25}", memory_type_name(memory_type))
 }
 
 pub struct MemoryDescriptorSlice<'a>(pub &'a [efi::MemoryDescriptor]);
@@ -506,6 +567,13 @@ pub fn core_allocate_pages(
         Err(err) => Err(err),
     };
 
+    // zero the pages after the allocator lock is released, so that interrupts are not held off while zeroing.
+    let policy = *ZERO_POLICY.read();
+    if res.is_ok() && policy.zero_on_allocate && policy.zeroes(memory_type) {
+        // Safety: the pages were just allocated, and caller must ensure that "memory" is a valid pointer.
+        unsafe { page_zero::zero_pages(memory.read_unaligned() as *mut u8, pages * UEFI_PAGE_SIZE) };
+    }
+
     // If the memory type is runtime services code or data, we need to install the memory attributes table to reflect
     // the update. The MAT logic will decide if it is a proper time to install the MAT or not.
     match memory_type {
@@ -520,6 +588,182 @@ pub fn core_allocate_pages(
     res
 }
 
+// Zeroes pages that are about to be freed if the policy requires it. Pages are only zeroed if they are all allocated
+// to the same allocator and are writable, as freed pages are unmapped and code pages may be read-only.
+fn zero_pages_before_free(memory: efi::PhysicalAddress, size: usize) {
+    let policy = *ZERO_POLICY.read();
+    if !policy.zero_on_free {
+        return;
+    }
+
+    let Ok(first) = GCD.get_memory_descriptor_for_address(memory) else {
+        return;
+    };
+    let Some(memory_type) = ALLOCATORS.lock().memory_type_for_handle(first.image_handle) else {
+        return;
+    };
+    if !policy.zeroes(memory_type) {
+        return;
+    }
+
+    let end = memory + size as u64;
+    let mut address = memory;
+    while address < end {
+        match GCD.get_memory_descriptor_for_address(address) {
+            Ok(desc)
+                if desc.image_handle == first.image_handle
+                    && desc.attributes & (efi::MEMORY_RO | efi::MEMORY_RP) == 0 =>
+            {
+                address = desc.base_address + desc.length;
+            }
+            _ => {
+                log::trace!(target: "allocations", "[{}] Not zeroing pages at {memory:#x} before free", function!());
+                return;
+            }
+        }
+    }
+
+    // Safety: the pages are allocated and writable, and are being freed by their owner.
+    unsafe { page_zero::zero_pages(memory as *mut u8, size) };
+}
+
+// The most boot services ranges that are zeroed at ExitBootServices, after adjacent ranges are merged.
+const MAX_EXIT_ZERO_RANGES: usize = 256;
+
+// The most ranges that are kept out of the boot services memory zeroed at ExitBootServices.
+const MAX_EXIT_KEPT_RANGES: usize = 64;
+
+// A fixed capacity list of (base, end) ranges, as nothing can be allocated once the memory map is terminated.
+struct RangeList<const N: usize> {
+    ranges: [(u64, u64); N],
+    count: usize,
+}
+
+impl<const N: usize> RangeList<N> {
+    const fn new() -> Self {
+        Self { ranges: [(0, 0); N], count: 0 }
+    }
+
+    // Adds a range, merging it into the last range if they are adjacent. Returns false if the list is full.
+    fn push(&mut self, base: u64, end: u64) -> bool {
+        if let Some(last) = self.ranges[..self.count].last_mut()
+            && last.1 == base
+        {
+            last.1 = end;
+            return true;
+        }
+        if self.count == N {
+            return false;
+        }
+        self.ranges[self.count] = (base, end);
+        self.count += 1;
+        true
+    }
+
+    fn as_slice(&self) -> &[(u64, u64)] {
+        &self.ranges[..self.count]
+    }
+}
+
+// Calls `f` with each part of `base..end` that is outside of all of the `kept` ranges, in ascending order.
+pub(crate) fn for_each_unkept_range(base: u64, end: u64, kept: &[(u64, u64)], mut f: impl FnMut(u64, u64)) {
+    let mut cursor = base;
+    while cursor < end {
+        // the kept range holding the cursor, or else the lowest kept range above it.
+        let next = kept
+            .iter()
+            .filter(|&&(kept_base, kept_end)| kept_end > cursor && kept_base < end)
+            .min_by_key(|&&(kept_base, _)| kept_base);
+        match next {
+            Some(&(kept_base, kept_end)) if kept_base <= cursor => cursor = kept_end,
+            Some(&(kept_base, _)) => {
+                f(cursor, kept_base);
+                cursor = kept_base;
+            }
+            None => {
+                f(cursor, end);
+                cursor = end;
+            }
+        }
+    }
+}
+
+/// Zeroes the boot services code and data pages that are still allocated, if the [`MemoryZeroPolicy`] asks for it.
+///
+/// Called last in ExitBootServices(), once the core no longer uses boot services memory. Read-only and
+/// read-protected pages, which include the page tables, are not zeroed. The DXE core image, the running stack and the
+/// allocations holding each of the `in_use` addresses are kept whole. Nothing is allocated or logged once zeroing
+/// starts, as the GCD and the logger may live in the memory being zeroed.
+pub(crate) fn zero_boot_services_memory(in_use: impl IntoIterator<Item = efi::PhysicalAddress>) {
+    let policy = *ZERO_POLICY.read();
+    if !policy.zero_boot_services_at_exit {
+        return;
+    }
+    if crate::runtime::addressing_checks_enabled() {
+        log::warn!("Not zeroing boot services memory, as the runtime addressing checks use it after ExitBootServices.");
+        return;
+    }
+
+    let mut kept = RangeList::<MAX_EXIT_KEPT_RANGES>::new();
+    let mut keep_allocation = |address: efi::PhysicalAddress| match GCD.get_memory_descriptor_for_address(address) {
+        Ok(descriptor) => kept.push(descriptor.base_address, descriptor.base_address + descriptor.length),
+        Err(_) => true,
+    };
+    let stack_marker = 0u8;
+    let mut all_kept = keep_allocation(core::ptr::addr_of!(stack_marker) as efi::PhysicalAddress);
+    for address in in_use {
+        all_kept &= keep_allocation(address);
+    }
+    if let Some((base, size)) = crate::image::dxe_core_image_range() {
+        all_kept &=
+            kept.push(base & !(UEFI_PAGE_SIZE as u64 - 1), (base + size).next_multiple_of(UEFI_PAGE_SIZE as u64));
+    }
+    for (base, len) in GCD.unprotected_page_table_ranges() {
+        all_kept &= kept.push(base as u64, (base + len) as u64);
+    }
+    if !all_kept {
+        log::warn!("Not zeroing boot services memory, as more than {MAX_EXIT_KEPT_RANGES} ranges must be kept.");
+        return;
+    }
+
+    let mut zeroed = RangeList::<MAX_EXIT_ZERO_RANGES>::new();
+    let mut address = 0;
+    while let Ok(descriptor) = GCD.get_memory_descriptor_for_address(address) {
+        let end = descriptor.base_address + descriptor.length;
+        if end <= address {
+            break;
+        }
+        address = end;
+
+        let Some(memory_type) = ALLOCATORS.lock().memory_type_for_handle(descriptor.image_handle) else {
+            continue;
+        };
+        if !matches!(memory_type, efi::BOOT_SERVICES_CODE | efi::BOOT_SERVICES_DATA)
+            || descriptor.attributes & (efi::MEMORY_RO | efi::MEMORY_RP) != 0
+        {
+            continue;
+        }
+        if !zeroed.push(descriptor.base_address, end) {
+            log::warn!("Only the first {MAX_EXIT_ZERO_RANGES} boot services ranges will be zeroed.");
+            break;
+        }
+    }
+
+    let mut total = 0;
+    for &(base, end) in zeroed.as_slice() {
+        for_each_unkept_range(base, end, kept.as_slice(), |base, end| total += end - base);
+    }
+    log::info!("Zeroing {total:#x} bytes of boot services memory.");
+
+    for &(base, end) in zeroed.as_slice() {
+        for_each_unkept_range(base, end, kept.as_slice(), |base, end| {
+            // Safety: the pages are writable boot services pages that are no longer used by the core, and the OS
+            // does not own them until ExitBootServices returns.
+            unsafe { page_zero::zero_pages(base as *mut u8, (end - base) as usize) };
+        });
+    }
+}
+
 pub fn core_get_allocator(memory_type: efi::MemoryType) -> Result<&'static UefiAllocator, EfiError> {
     let handle = AllocatorMap::handle_for_memory_type(memory_type)?;
     ALLOCATORS.lock().get_or_create_allocator(memory_type, handle)
@@ -546,6 +790,8 @@ pub fn core_free_pages(memory: efi::PhysicalAddress, pages: usize) -> Result<(),
         return Err(EfiError::InvalidParameter);
     }
 
+    zero_pages_before_free(memory, size);
+
     let allocators = ALLOCATORS.lock();
 
     let mut memory_type = efi::CONVENTIONAL_MEMORY;
@@ -580,17 +826,174 @@ pub fn core_free_pages(memory: efi::PhysicalAddress, pages: usize) -> Result<(),
     res
 }
 
+/// Debug checks the core applies to the CopyMem and SetMem boot services.
+///
+/// Checks are off unless the platform registers this config. They cost a memory space lookup per call, so they are
+/// intended for debug builds of a platform.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, MemoryWriteChecks};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(MemoryWriteChecks { flag_read_only_destination: true })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryWriteChecks {
+    /// Log an error naming the running image when CopyMem or SetMem is about to write to read-only or read-protected
+    /// memory, before the write faults.
+    pub flag_read_only_destination: bool,
+}
+
+static WRITE_CHECKS: spin::RwLock<MemoryWriteChecks> =
+    spin::RwLock::new(MemoryWriteChecks { flag_read_only_destination: false });
+
+/// Applies the platform's debug checks to subsequent CopyMem and SetMem calls.
+pub(crate) fn set_memory_write_checks(checks: &MemoryWriteChecks) {
+    *WRITE_CHECKS.write() = *checks;
+}
+
+// Returns the first memory space descriptor in `start..start + length` that cannot be written, looking descriptors up
+// with `descriptor_at`. Memory that is not described is not reported.
+fn find_read_only_destination(
+    start: u64,
+    length: usize,
+    descriptor_at: impl Fn(u64) -> Result<MemorySpaceDescriptor, EfiError>,
+) -> Option<MemorySpaceDescriptor> {
+    let end = start.saturating_add(length as u64);
+    let mut address = start;
+    while address < end {
+        let descriptor = descriptor_at(address).ok()?;
+        if descriptor.attributes & (efi::MEMORY_RO | efi::MEMORY_RP) != 0 {
+            return Some(descriptor);
+        }
+        let next = descriptor.base_address.saturating_add(descriptor.length);
+        if next <= address {
+            return None;
+        }
+        address = next;
+    }
+    None
+}
+
+// Logs an error when the platform asked to flag writes to read-only memory and `destination` is not writable.
+fn check_write_destination(service: &str, destination: *mut u8, length: usize) {
+    if !WRITE_CHECKS.read().flag_read_only_destination {
+        return;
+    }
+    if let Some(descriptor) =
+        find_read_only_destination(destination as u64, length, |address| GCD.get_memory_descriptor_for_address(address))
+    {
+        let caller = crate::image::core_current_image_name().unwrap_or_else(|| "DXE Core".into());
+        log::error!(
+            "{service} by {caller} writes {length:#x} bytes at {destination:p} into protected memory {:#x}-{:#x} with attributes {:#x}.",
+            descriptor.base_address,
+            descriptor.base_address.saturating_add(descriptor.length),
+            descriptor.attributes
+        );
+    }
+}
+
+const WORD_SIZE: usize = mem::size_of::<usize>();
+
+// Copies `length` bytes from `source` to `destination` a word at a time once the destination is word aligned. The
+// buffers may overlap; the copy runs backwards when the destination follows the source.
+//
+// Safety: source must be valid for reads and destination valid for writes of length bytes.
+unsafe fn copy_bytes(destination: *mut u8, source: *const u8, length: usize) {
+    if (destination as usize).wrapping_sub(source as usize) >= length {
+        // destination precedes the source or does not overlap it, so copy forwards. Each word is read before the
+        // write that could overwrite it.
+        let head = destination.align_offset(WORD_SIZE).min(length);
+        let words = (length - head) / WORD_SIZE;
+        // Safety: all offsets are below length.
+        unsafe {
+            for i in 0..head {
+                destination.add(i).write(source.add(i).read());
+            }
+            for i in 0..words {
+                let offset = head + i * WORD_SIZE;
+                let word = (source.add(offset) as *const usize).read_unaligned();
+                (destination.add(offset) as *mut usize).write(word);
+            }
+            for i in head + words * WORD_SIZE..length {
+                destination.add(i).write(source.add(i).read());
+            }
+        }
+    } else {
+        // destination follows the source within it, so copy backwards from the end.
+        let tail = (destination as usize).wrapping_add(length) % WORD_SIZE;
+        let tail = tail.min(length);
+        let words = (length - tail) / WORD_SIZE;
+        let body_start = length - tail - words * WORD_SIZE;
+        // Safety: all offsets are below length.
+        unsafe {
+            for i in (length - tail..length).rev() {
+                destination.add(i).write(source.add(i).read());
+            }
+            for i in (0..words).rev() {
+                let offset = body_start + i * WORD_SIZE;
+                let word = (source.add(offset) as *const usize).read_unaligned();
+                (destination.add(offset) as *mut usize).write(word);
+            }
+            for i in (0..body_start).rev() {
+                destination.add(i).write(source.add(i).read());
+            }
+        }
+    }
+}
+
+// Sets `length` bytes at `destination` to `value` a word at a time once the destination is word aligned.
+//
+// Safety: destination must be valid for writes of length bytes.
+unsafe fn fill_bytes(destination: *mut u8, length: usize, value: u8) {
+    let head = destination.align_offset(WORD_SIZE).min(length);
+    let words = (length - head) / WORD_SIZE;
+    let word = usize::from_ne_bytes([value; WORD_SIZE]);
+    // Safety: all offsets are below length.
+    unsafe {
+        for i in 0..head {
+            destination.add(i).write(value);
+        }
+        for i in 0..words {
+            (destination.add(head + i * WORD_SIZE) as *mut usize).write(word);
+        }
+        for i in head + words * WORD_SIZE..length {
+            destination.add(i).write(value);
+        }
+    }
+}
+
 extern "efiapi" fn copy_mem(destination: *mut c_void, source: *mut c_void, length: usize) {
-    // Safety: caller must ensure that the source and destination are valid for length bytes.
-    unsafe { core::ptr::copy(source as *mut u8, destination as *mut u8, length) }
+    if length == 0 || destination == source {
+        return;
+    }
+    if destination.is_null() || source.is_null() {
+        log::error!("CopyMem called with a null buffer: destination {destination:p}, source {source:p}.");
+        return;
+    }
+    check_write_destination("CopyMem", destination as *mut u8, length);
+    // Safety: caller must ensure that the source and destination are valid for length bytes. They are null-checked
+    // above, and copy_bytes handles overlapping buffers.
+    unsafe { copy_bytes(destination as *mut u8, source as *const u8, length) }
 }
 
 extern "efiapi" fn set_mem(buffer: *mut c_void, size: usize, value: u8) {
-    // Safety: caller must ensure that the buffer is valid for size bytes.
-    unsafe {
-        let dst_buffer = from_raw_parts_mut(buffer as *mut u8, size);
-        dst_buffer.fill(value);
+    if size == 0 {
+        return;
+    }
+    if buffer.is_null() {
+        log::error!("SetMem called with a null buffer.");
+        return;
     }
+    check_write_destination("SetMem", buffer as *mut u8, size);
+    // Safety: caller must ensure that the buffer is valid for size bytes. It is null-checked above.
+    unsafe { fill_bytes(buffer as *mut u8, size, value) }
 }
 
 fn merge_blocks(
@@ -639,8 +1042,9 @@ pub(crate) fn get_memory_map_descriptors(active_attributes: bool) -> Result<Vec<
         .filter_map(|descriptor| {
             let memory_type = ALLOCATORS.lock().memory_type_for_handle(descriptor.image_handle).or({
                 match descriptor.memory_type {
-                    // free memory not tracked by any allocator.
-                    GcdMemoryType::SystemMemory => Some(efi::CONVENTIONAL_MEMORY),
+                    // free memory not tracked by any allocator. More reliable memory is reported as conventional
+                    // memory with the EFI_MEMORY_MORE_RELIABLE attribute from its capabilities.
+                    GcdMemoryType::SystemMemory | GcdMemoryType::MoreReliable => Some(efi::CONVENTIONAL_MEMORY),
 
                     // MMIO. Note: there could also be MMIO tracked by the allocators which would not hit this case.
                     GcdMemoryType::MemoryMappedIo => {
@@ -702,6 +1106,57 @@ pub(crate) fn get_memory_map_descriptors(active_attributes: bool) -> Result<Vec<
         .fold(merged_descriptors, merge_blocks))
 }
 
+/// Writes the memory map, as it would be returned by GetMemoryMap(), for the `memmap` debugger monitor command.
+pub(crate) fn write_memory_map(out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+    match get_memory_map_descriptors(false) {
+        Ok(descriptors) => write!(out, "{:?}", MemoryDescriptorSlice(&descriptors)),
+        Err(err) => write!(out, "Failed to get the memory map: {err:?}"),
+    }
+}
+
+/// Writes what is known about the owner of `address` for the `alloc` debugger monitor command: the GCD descriptor
+/// containing it, the allocator that manages it, the loaded image that contains it, and the pool allocation that starts
+/// at it.
+pub(crate) fn write_allocation_info(
+    out: &mut dyn core::fmt::Write,
+    address: efi::PhysicalAddress,
+) -> core::fmt::Result {
+    match GCD.get_memory_descriptor_for_address(address) {
+        Ok(descriptor) => writeln!(
+            out,
+            "GCD: {:?} {:#x}-{:#x} attributes {:#x} capabilities {:#x} owner {:?}",
+            descriptor.memory_type,
+            descriptor.base_address,
+            descriptor.base_address.saturating_add(descriptor.length).saturating_sub(1),
+            descriptor.attributes,
+            descriptor.capabilities,
+            descriptor.image_handle
+        )?,
+        Err(err) => writeln!(out, "GCD: no descriptor ({err:?})")?,
+    }
+
+    let Some(ptr) = NonNull::new(address as usize as *mut u8) else {
+        return Ok(());
+    };
+    match ALLOCATORS.try_lock() {
+        Some(allocators) => match allocators.iter().find(|allocator| allocator.contains(ptr)) {
+            Some(allocator) => {
+                writeln!(out, "Allocator: {} ({:?})", memory_type_name(allocator.memory_type()), allocator.handle())?;
+                if let Some(size) = allocator.pool_allocation_size(ptr) {
+                    writeln!(out, "Pool: allocation of {size:#x} bytes starts here")?;
+                }
+            }
+            None => writeln!(out, "Allocator: none")?,
+        },
+        None => writeln!(out, "Allocator: allocators are locked")?,
+    }
+
+    match with_image_at_address(address, |name, offset| writeln!(out, "Image: {name} + {offset:#x}")) {
+        Some(result) => result,
+        None => writeln!(out, "Image: none"),
+    }
+}
+
 extern "efiapi" fn get_memory_map(
     memory_map_size: *mut usize,
     memory_map: *mut efi::MemoryDescriptor,
@@ -756,8 +1211,7 @@ extern "efiapi" fn get_memory_map(
         core::ptr::copy(efi_descriptors_ptr, memory_map as *mut u8, required_map_size);
 
         if !map_key.is_null() {
-            let memory_map_as_bytes = slice::from_raw_parts(memory_map as *mut u8, required_map_size);
-            map_key.write_unaligned(crc32fast::hash(memory_map_as_bytes) as usize);
+            map_key.write_unaligned(memory_map_key(&efi_descriptors));
         }
     }
 
@@ -766,15 +1220,35 @@ extern "efiapi" fn get_memory_map(
     efi::Status::SUCCESS
 }
 
-pub fn terminate_memory_map(map_key: usize) -> Result<(), EfiError> {
-    let mm_desc = get_memory_map_descriptors(false)?;
-    let mm_desc_size = mm_desc.len() * mem::size_of::<efi::MemoryDescriptor>();
-    let mm_desc_bytes: &[u8] = unsafe { slice::from_raw_parts(mm_desc.as_ptr() as *const u8, mm_desc_size) };
+/// Returns the map key of the memory map made of `descriptors`, which is the CRC32 of the descriptors.
+fn memory_map_key(descriptors: &[efi::MemoryDescriptor]) -> usize {
+    // Safety: the descriptors are plain old data, so they can be viewed as the bytes of the slice.
+    let bytes = unsafe { slice::from_raw_parts(descriptors.as_ptr() as *const u8, mem::size_of_val(descriptors)) };
+    crate::crc32::hash(bytes) as usize
+}
 
-    let current_map_key = crc32fast::hash(mm_desc_bytes) as usize;
+pub fn terminate_memory_map(map_key: usize) -> Result<(), EfiError> {
+    let current_map_key = memory_map_key(&get_memory_map_descriptors(false)?);
     if map_key == current_map_key { Ok(()) } else { Err(EfiError::InvalidParameter) }
 }
 
+/// Provides the memory map to components, as GetMemoryMap() would return it.
+#[derive(IntoService)]
+#[service(dyn MemoryMap)]
+pub(crate) struct CoreMemoryMap;
+
+impl MemoryMap for CoreMemoryMap {
+    fn snapshot(&self) -> Result<MemoryMapSnapshot, EfiError> {
+        let descriptors = get_memory_map_descriptors(false)?;
+        let map_key = memory_map_key(&descriptors);
+        Ok(MemoryMapSnapshot::new(descriptors, map_key))
+    }
+
+    fn map_key(&self) -> Result<usize, EfiError> {
+        Ok(memory_map_key(&get_memory_map_descriptors(false)?))
+    }
+}
+
 pub fn install_memory_type_info_table(system_table: &mut EfiSystemTable) -> Result<(), EfiError> {
     let table_ptr = NonNull::from(GCD.memory_type_info_table()).cast::<c_void>().as_ptr();
     config_tables::core_install_configuration_table(guids::MEMORY_TYPE_INFORMATION, table_ptr, system_table)
@@ -1077,6 +1551,9 @@ pub fn init_memory_support(hob_list: &HobList) {
     // process pre-DXE allocations from the Hob list
     process_hob_allocations(hob_list);
 
+    // reserve the platform's carve-outs before anything else can be allocated from them.
+    reserved_regions::reserve_regions();
+
     // After this point the GCD and existing allocations are fully processed and it is safe to arbitrarily allocate.
 
     // If memory type info HOB is available, then pre-allocate the corresponding buckets.
@@ -1722,6 +2199,114 @@ mod tests {
         });
     }
 
+    #[test]
+    fn pages_should_be_zeroed_according_to_policy() {
+        with_locked_state(0x1000000, || {
+            let allocate_dirty = |memory_type: efi::MemoryType| {
+                let mut memory: efi::PhysicalAddress = 0;
+                core_allocate_pages(efi::ALLOCATE_ANY_PAGES, memory_type, 0x20, &mut memory, None).unwrap();
+                unsafe { slice::from_raw_parts_mut(memory as *mut u8, 0x20 * UEFI_PAGE_SIZE) }.fill(0xA5);
+                memory
+            };
+            let is_zero = |memory: efi::PhysicalAddress| unsafe {
+                slice::from_raw_parts(memory as *const u8, 0x20 * UEFI_PAGE_SIZE).iter().all(|&b| b == 0)
+            };
+
+            set_memory_zero_policy(&MemoryZeroPolicy {
+                zero_on_free: true,
+                zero_on_allocate: true,
+                skip_boot_services_data: true,
+                ..Default::default()
+            });
+
+            let code = allocate_dirty(efi::BOOT_SERVICES_CODE);
+            core_free_pages(code, 0x20).unwrap();
+            assert!(is_zero(code));
+
+            set_memory_zero_policy(&MemoryZeroPolicy { zero_on_allocate: true, ..Default::default() });
+            let code = allocate_dirty(efi::BOOT_SERVICES_CODE);
+            core_free_pages(code, 0x20).unwrap();
+            assert!(!is_zero(code));
+            let mut memory = code;
+            core_allocate_pages(efi::ALLOCATE_ADDRESS, efi::BOOT_SERVICES_CODE, 0x20, &mut memory, None).unwrap();
+            assert!(is_zero(code));
+            core_free_pages(code, 0x20).unwrap();
+
+            set_memory_zero_policy(&MemoryZeroPolicy {
+                zero_on_free: true,
+                zero_on_allocate: true,
+                skip_boot_services_data: true,
+                ..Default::default()
+            });
+
+            // boot services data is never zeroed with this policy.
+            let data = allocate_dirty(efi::BOOT_SERVICES_DATA);
+            core_free_pages(data, 0x20).unwrap();
+            assert!(!is_zero(data));
+
+            set_memory_zero_policy(&MemoryZeroPolicy::default());
+            let code = allocate_dirty(efi::BOOT_SERVICES_CODE);
+            core_free_pages(code, 0x20).unwrap();
+            assert!(!is_zero(code));
+        });
+    }
+
+    #[test]
+    fn unkept_ranges_should_skip_kept_ranges() {
+        let unkept = |base, end, kept: &[(u64, u64)]| {
+            let mut ranges = Vec::new();
+            for_each_unkept_range(base, end, kept, |base, end| ranges.push((base, end)));
+            ranges
+        };
+
+        assert_eq!(unkept(0x1000, 0x5000, &[]), [(0x1000, 0x5000)]);
+        assert_eq!(unkept(0x1000, 0x5000, &[(0x0, 0x2000), (0x4000, 0x8000)]), [(0x2000, 0x4000)]);
+        assert_eq!(
+            unkept(0x1000, 0x8000, &[(0x5000, 0x6000), (0x2000, 0x3000), (0x2800, 0x4000)]),
+            [(0x1000, 0x2000), (0x4000, 0x5000), (0x6000, 0x8000)]
+        );
+        assert_eq!(unkept(0x1000, 0x5000, &[(0x0, 0x1000), (0x5000, 0x6000)]), [(0x1000, 0x5000)]);
+        assert!(unkept(0x1000, 0x5000, &[(0x0, 0x8000)]).is_empty());
+    }
+
+    #[test]
+    fn boot_services_memory_should_be_zeroed_at_exit() {
+        with_locked_state(0x1000000, || {
+            let allocate_dirty = |memory_type: efi::MemoryType| {
+                let mut memory: efi::PhysicalAddress = 0;
+                core_allocate_pages(efi::ALLOCATE_ANY_PAGES, memory_type, 0x20, &mut memory, None).unwrap();
+                unsafe { slice::from_raw_parts_mut(memory as *mut u8, 0x20 * UEFI_PAGE_SIZE) }.fill(0xA5);
+                memory
+            };
+            let is_zero = |memory: efi::PhysicalAddress| unsafe {
+                slice::from_raw_parts(memory as *const u8, 0x20 * UEFI_PAGE_SIZE).iter().all(|&b| b == 0)
+            };
+
+            let code = allocate_dirty(efi::BOOT_SERVICES_CODE);
+            let data = allocate_dirty(efi::BOOT_SERVICES_DATA);
+            let runtime_data = allocate_dirty(efi::RUNTIME_SERVICES_DATA);
+            let kept_data = allocate_dirty(efi::BOOT_SERVICES_DATA);
+
+            zero_boot_services_memory([]);
+            assert!(!is_zero(code));
+
+            set_memory_zero_policy(&MemoryZeroPolicy {
+                skip_boot_services_data: true,
+                zero_boot_services_at_exit: true,
+                ..Default::default()
+            });
+            zero_boot_services_memory([kept_data + 0x1000]);
+            set_memory_zero_policy(&MemoryZeroPolicy::default());
+
+            assert!(is_zero(code));
+            assert!(!is_zero(runtime_data));
+            assert!(!is_zero(kept_data));
+
+            // Skipping boot services data on allocate and free does not keep it from being zeroed at exit.
+            assert!(is_zero(data));
+        });
+    }
+
     #[test]
     fn copy_mem_should_copy_mem() {
         let mut dest = vec![0xa5u8; 0x10];
@@ -1737,6 +2322,85 @@ mod tests {
         assert_eq!(dest, vec![0x00u8; 0x10]);
     }
 
+    #[test]
+    fn copy_mem_should_handle_overlapping_buffers() {
+        let original = (0..200u8).collect::<Vec<_>>();
+        for length in [1, 7, 8, 9, 31, 64, 100] {
+            for source_offset in [0, 1, 3, 8, 13] {
+                for destination_offset in [0, 1, 2, 8, 9, 50] {
+                    let mut actual = original.clone();
+                    let mut expected = original.clone();
+                    expected.copy_within(source_offset..source_offset + length, destination_offset);
+                    let base = actual.as_mut_ptr();
+                    copy_mem(
+                        unsafe { base.add(destination_offset) } as *mut c_void,
+                        unsafe { base.add(source_offset) } as *mut c_void,
+                        length,
+                    );
+                    assert_eq!(
+                        actual, expected,
+                        "length {length} source {source_offset} destination {destination_offset}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn copy_mem_and_set_mem_should_ignore_empty_and_null_buffers() {
+        let mut buffer = vec![0xa5u8; 0x10];
+        copy_mem(core::ptr::null_mut(), core::ptr::null_mut(), 0);
+        copy_mem(buffer.as_mut_ptr() as *mut c_void, core::ptr::null_mut(), 0x10);
+        copy_mem(core::ptr::null_mut(), buffer.as_mut_ptr() as *mut c_void, 0x10);
+        set_mem(core::ptr::null_mut(), 0, 0);
+        set_mem(core::ptr::null_mut(), 0x10, 0);
+        set_mem(buffer.as_mut_ptr() as *mut c_void, 0, 0);
+        assert_eq!(buffer, vec![0xa5u8; 0x10]);
+    }
+
+    #[test]
+    fn set_mem_should_set_unaligned_ranges() {
+        for offset in 0..9 {
+            for length in [1, 7, 8, 9, 17, 40] {
+                let mut buffer = vec![0u8; 64];
+                set_mem(unsafe { buffer.as_mut_ptr().add(offset) } as *mut c_void, length, 0x3c);
+                for (i, byte) in buffer.iter().enumerate() {
+                    let expected = if (offset..offset + length).contains(&i) { 0x3c } else { 0 };
+                    assert_eq!(*byte, expected, "offset {offset} length {length} index {i}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_read_only_destination_should_report_protected_memory() {
+        let descriptor = |base_address: u64, attributes: u64| MemorySpaceDescriptor {
+            memory_type: GcdMemoryType::SystemMemory,
+            base_address,
+            length: 0x1000,
+            capabilities: efi::MEMORY_RO | efi::MEMORY_RP | efi::MEMORY_XP,
+            attributes,
+            image_handle: core::ptr::null_mut(),
+            device_handle: core::ptr::null_mut(),
+        };
+        let descriptor_at = |address: u64| -> Result<MemorySpaceDescriptor, EfiError> {
+            match address & !0xfff {
+                0x1000 => Ok(descriptor(0x1000, efi::MEMORY_XP)),
+                0x2000 => Ok(descriptor(0x2000, efi::MEMORY_RO)),
+                0x3000 => Ok(descriptor(0x3000, efi::MEMORY_RP)),
+                _ => Err(EfiError::NotFound),
+            }
+        };
+
+        assert_eq!(find_read_only_destination(0x1000, 0x1000, descriptor_at), None);
+        assert_eq!(find_read_only_destination(0x1800, 0x800, descriptor_at), None);
+        assert_eq!(find_read_only_destination(0x1ff0, 0x20, descriptor_at).map(|d| d.base_address), Some(0x2000));
+        assert_eq!(find_read_only_destination(0x3010, 0x10, descriptor_at).map(|d| d.base_address), Some(0x3000));
+        assert_eq!(find_read_only_destination(0x2000, 0, descriptor_at), None);
+        // memory that is not described is not reported.
+        assert_eq!(find_read_only_destination(0x4000, 0x10, descriptor_at), None);
+    }
+
     #[test]
     fn get_memory_map_should_return_a_memory_map() {
         with_locked_state(0x1000000, || {
@@ -1926,4 +2590,30 @@ mod tests {
             assert_eq!(terminate_memory_map(map_key + 1), Err(EfiError::InvalidParameter));
         });
     }
+
+    #[test]
+    fn memory_map_service_should_track_the_map_key() {
+        with_locked_state(0x1000000, || {
+            let snapshot = CoreMemoryMap.snapshot().unwrap();
+            assert_eq!(CoreMemoryMap.map_key(), Ok(snapshot.map_key()));
+            assert!(!CoreMemoryMap.has_changed_since(&snapshot));
+            assert!(terminate_memory_map(snapshot.map_key()).is_ok());
+
+            let mut buffer_ptr: *mut u8 = core::ptr::null_mut();
+            assert_eq!(
+                allocate_pages(
+                    efi::ALLOCATE_ANY_PAGES,
+                    0x71234567,
+                    0x10,
+                    core::ptr::addr_of_mut!(buffer_ptr) as *mut efi::PhysicalAddress
+                ),
+                efi::Status::SUCCESS
+            );
+            assert!(CoreMemoryMap.has_changed_since(&snapshot));
+
+            let snapshot = CoreMemoryMap.snapshot().unwrap();
+            let descriptor = snapshot.descriptor_for_address(buffer_ptr as efi::PhysicalAddress).unwrap();
+            assert_eq!(descriptor.r#type, 0x71234567);
+        });
+    }
 }
@@ -0,0 +1,107 @@
+//! Bulk Page Zeroing
+//!
+//! Zeroing large allocations with regular stores pulls every line of the buffer through the cache and evicts the
+//! working set. Large ranges are zeroed with non-temporal stores on x86_64 and with cache-line zero instructions
+//! (`DC ZVA`) on AArch64 instead; smaller ranges and other architectures use `write_bytes`, which the compiler lowers
+//! to the widest stores available.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina::base::UEFI_PAGE_SIZE;
+
+// Ranges smaller than this are likely to be used right away, so they are zeroed through the cache.
+const BULK_ZERO_THRESHOLD: usize = 16 * UEFI_PAGE_SIZE;
+
+/// Zeroes `len` bytes at `base`.
+///
+/// ## Safety
+///
+/// `base` must be page aligned and valid for writes of `len` bytes, and `len` must be a multiple of the page size.
+pub(crate) unsafe fn zero_pages(base: *mut u8, len: usize) {
+    debug_assert!(base.addr().is_multiple_of(UEFI_PAGE_SIZE) && len.is_multiple_of(UEFI_PAGE_SIZE));
+    if len < BULK_ZERO_THRESHOLD {
+        // Safety: caller must ensure that base is valid for writes of len bytes.
+        unsafe { core::ptr::write_bytes(base, 0, len) };
+    } else {
+        // Safety: caller must ensure that base is valid for writes of len bytes, and that both are page aligned.
+        unsafe { bulk_zero(base, len) };
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn bulk_zero(base: *mut u8, len: usize) {
+    // Safety: base is valid for writes of len bytes, and len is a non-zero multiple of the 32 bytes stored per loop.
+    unsafe {
+        core::arch::asm!(
+            "2:",
+            "movnti [{ptr}], {zero}",
+            "movnti [{ptr} + 8], {zero}",
+            "movnti [{ptr} + 16], {zero}",
+            "movnti [{ptr} + 24], {zero}",
+            "add {ptr}, 32",
+            "sub {len}, 32",
+            "jnz 2b",
+            // order the non-temporal stores before any later stores to the range.
+            "sfence",
+            ptr = inout(reg) base => _,
+            len = inout(reg) len => _,
+            zero = in(reg) 0u64,
+            options(nostack),
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn bulk_zero(base: *mut u8, len: usize) {
+    let dczid: u64;
+    // Safety: DCZID_EL0 is readable at all exception levels.
+    unsafe { core::arch::asm!("mrs {}, dczid_el0", out(reg) dczid, options(nomem, nostack, preserves_flags)) };
+
+    // DCZID_EL0.DZP prohibits DC ZVA, and DCZID_EL0.BS is the log2 of the block size in words.
+    let block_size = 4usize << (dczid & 0xf);
+    if dczid & (1 << 4) != 0 || !base.addr().is_multiple_of(block_size) || !len.is_multiple_of(block_size) {
+        // Safety: base is valid for writes of len bytes.
+        unsafe { core::ptr::write_bytes(base, 0, len) };
+        return;
+    }
+
+    for offset in (0..len).step_by(block_size) {
+        // Safety: base is valid for writes of len bytes, and each block is aligned and within the range.
+        unsafe { core::arch::asm!("dc zva, {}", in(reg) base.add(offset), options(nostack, preserves_flags)) };
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+unsafe fn bulk_zero(base: *mut u8, len: usize) {
+    // Safety: base is valid for writes of len bytes.
+    unsafe { core::ptr::write_bytes(base, 0, len) };
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use std::alloc::{Layout, alloc, dealloc};
+
+    #[test]
+    fn zero_pages_should_zero_small_and_bulk_ranges() {
+        for len in [UEFI_PAGE_SIZE, BULK_ZERO_THRESHOLD, BULK_ZERO_THRESHOLD + 3 * UEFI_PAGE_SIZE] {
+            let layout = Layout::from_size_align(len + 2 * UEFI_PAGE_SIZE, UEFI_PAGE_SIZE).unwrap();
+            let buffer = unsafe { alloc(layout) };
+            unsafe { core::ptr::write_bytes(buffer, 0xA5, layout.size()) };
+
+            unsafe { zero_pages(buffer.add(UEFI_PAGE_SIZE), len) };
+
+            let bytes = unsafe { core::slice::from_raw_parts(buffer, layout.size()) };
+            assert!(bytes[..UEFI_PAGE_SIZE].iter().all(|&b| b == 0xA5));
+            assert!(bytes[UEFI_PAGE_SIZE..UEFI_PAGE_SIZE + len].iter().all(|&b| b == 0));
+            assert!(bytes[UEFI_PAGE_SIZE + len..].iter().all(|&b| b == 0xA5));
+            unsafe { dealloc(buffer, layout) };
+        }
+    }
+}
@@ -71,7 +71,6 @@ impl UefiAllocator {
     /// Indicates whether the given pointer falls within a memory region managed by this allocator.
     ///
     /// See [`SpinLockedFixedSizeBlockAllocator::contains`]
-    #[allow(dead_code)]
     pub fn contains(&self, ptr: NonNull<u8>) -> bool {
         self.allocator.contains(ptr)
     }
@@ -140,6 +139,26 @@ impl UefiAllocator {
         }
     }
 
+    /// Returns the size of the pool allocation that starts at `buffer`, or `None` if `buffer` is not the start of a
+    /// pool allocation from this allocator.
+    ///
+    /// This is intended for diagnostics. Pool allocations are not tracked, so a stale or forged pool header in memory
+    /// managed by this allocator is reported as an allocation.
+    pub(crate) fn pool_allocation_size(&self, buffer: NonNull<u8>) -> Option<usize> {
+        let (_, offset) =
+            Layout::new::<AllocationInfo>().extend(Layout::from_size_align(0, UEFI_POOL_ALIGN).ok()?).ok()?;
+        let allocation_info = NonNull::new((buffer.as_ptr() as usize).checked_sub(offset)? as *mut u8)?;
+        if !self.contains(allocation_info) {
+            return None;
+        }
+        // Safety: the header is in memory managed by this allocator, so it is mapped and readable.
+        let allocation_info = unsafe { allocation_info.cast::<AllocationInfo>().as_ptr().read_unaligned() };
+        if allocation_info.signature != POOL_SIG || allocation_info.memory_type != self.memory_type() {
+            return None;
+        }
+        allocation_info.layout.size().checked_sub(offset)
+    }
+
     /// Frees a buffer allocated by [`Self::allocate_pool`]
     ///
     /// ## Safety
@@ -359,6 +378,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_pool_allocation_size() {
+        with_locked_state(|| {
+            static GCD: SpinLockedGcd = SpinLockedGcd::new(None);
+
+            init_gcd(&GCD, 0x400000);
+
+            let ua = UefiAllocator::new(
+                &GCD,
+                NonNull::from_ref(GCD.memory_type_info(efi::BOOT_SERVICES_DATA)),
+                1 as _,
+                DEFAULT_PAGE_ALLOCATION_GRANULARITY,
+            );
+
+            let mut buffer: *mut c_void = core::ptr::null_mut();
+            assert!(unsafe { ua.allocate_pool(0x123, core::ptr::addr_of_mut!(buffer)) }.is_ok());
+            let buffer = NonNull::new(buffer as *mut u8).unwrap();
+            assert_eq!(ua.pool_allocation_size(buffer), Some(0x123));
+
+            // an address inside the allocation is not the start of a pool allocation.
+            assert_eq!(ua.pool_allocation_size(unsafe { buffer.add(8) }), None);
+
+            // memory outside of the allocator is never reported.
+            let outside = 0u64;
+            assert_eq!(ua.pool_allocation_size(NonNull::from_ref(&outside).cast()), None);
+
+            unsafe { ua.free_pool(buffer.as_ptr() as *mut c_void) }.unwrap();
+            assert_eq!(ua.pool_allocation_size(buffer), None);
+        });
+    }
+
     #[test]
     fn test_free_pool() {
         with_granularity_modulation(|granularity| {
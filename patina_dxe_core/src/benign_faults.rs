@@ -0,0 +1,230 @@
+//! Benign Memory Fault Handling
+//!
+//! Enabling strict memory protections on a platform often uncovers drivers that touch memory they should not, such as
+//! the null page or legacy ranges. Platforms can describe such known-problematic ranges with [BenignFaultRanges]. A
+//! memory fault in one of these ranges is logged with the faulting image, offset and access type, the attributes of
+//! the accessed page are relaxed to permit the access, and boot continues. The violations are summarized again at
+//! ReadyToBoot, so that an inventory can be collected from the field before the protections are enforced.
+//!
+//! Faults outside the configured ranges remain fatal.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::{
+    ffi::c_void,
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use patina::base::UEFI_PAGE_MASK;
+use patina_internal_cpu::interrupts::{self, MemoryFault, MemoryFaultAccess};
+use r_efi::efi;
+use spin::{Mutex, RwLock};
+
+use crate::{dxe_services, events::EVENT_DB, image::with_image_at_address};
+
+/// Memory ranges in which memory protection faults are logged and permitted rather than treated as fatal.
+///
+/// This is intended to inventory protection violations on platforms in the field before strict protections are
+/// enforced. No faults are permitted unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{BenignFaultRanges, Core};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(BenignFaultRanges(vec![0x0..0x1000, 0xA0000..0x100000]))
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BenignFaultRanges(pub Vec<Range<u64>>);
+
+// The most faults that are recorded for the ReadyToBoot summary. The inventory is allocated up front, as faults are
+// handled in exception context.
+const MAX_RECORDED_FAULTS: usize = 64;
+
+static RANGES: RwLock<Vec<Range<u64>>> = RwLock::new(Vec::new());
+static INVENTORY: Mutex<Vec<MemoryFault>> = Mutex::new(Vec::new());
+static FAULT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Permits memory faults in `ranges` and registers a summary of the permitted faults for ReadyToBoot.
+pub fn enable_benign_faults(ranges: &BenignFaultRanges) {
+    if ranges.0.is_empty() {
+        return;
+    }
+
+    log::warn!("Memory faults in {:#X?} are permitted, memory protections are not enforced there.", ranges.0);
+    *RANGES.write() = ranges.0.clone();
+    INVENTORY.lock().reserve_exact(MAX_RECORDED_FAULTS);
+    interrupts::set_memory_fault_filter(Some(permit_benign_fault));
+
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(report_benign_faults_event),
+        None,
+        Some(efi::EVENT_GROUP_READY_TO_BOOT),
+    ) {
+        log::error!("Failed to register an event at Ready to Boot to report benign faults! Status {status:#X?}");
+    }
+}
+
+fn is_benign(fault: &MemoryFault) -> bool {
+    RANGES.try_read().is_some_and(|ranges| ranges.iter().any(|range| range.contains(&fault.address)))
+}
+
+fn log_fault(fault: &MemoryFault) {
+    let logged = with_image_at_address(fault.instruction_pointer, |name, offset| {
+        log::warn!(
+            "Benign memory fault: {:?} of {:#X} by {name}+{offset:#X} (IP {:#X})",
+            fault.access,
+            fault.address,
+            fault.instruction_pointer
+        )
+    });
+    if logged.is_none() {
+        log::warn!(
+            "Benign memory fault: {:?} of {:#X} by an unknown image (IP {:#X})",
+            fault.access,
+            fault.address,
+            fault.instruction_pointer
+        );
+    }
+}
+
+// Relaxes the attributes of the page containing the faulting address so that the access is permitted.
+fn permit_access(fault: &MemoryFault) -> bool {
+    let page = fault.address & !(UEFI_PAGE_MASK as u64);
+    let blocking = match fault.access {
+        MemoryFaultAccess::Read => efi::MEMORY_RP,
+        MemoryFaultAccess::Write => efi::MEMORY_RP | efi::MEMORY_RO,
+        MemoryFaultAccess::Execute => efi::MEMORY_RP | efi::MEMORY_XP,
+    };
+
+    let attributes = match dxe_services::core_get_memory_space_descriptor(page) {
+        Ok(desc) => desc.attributes & !blocking,
+        Err(status) => {
+            log::error!("Failed to find GCD desc for benign fault at {page:#X} with Status {status:#X?}");
+            return false;
+        }
+    };
+
+    match dxe_services::core_set_memory_space_attributes(page, UEFI_PAGE_MASK as u64 + 1, attributes) {
+        Ok(()) => true,
+        Err(status) => {
+            log::error!("Failed to permit benign fault at {page:#X} with Status {status:#X?}");
+            false
+        }
+    }
+}
+
+fn permit_benign_fault(fault: &MemoryFault) -> bool {
+    if !is_benign(fault) {
+        return false;
+    }
+
+    log_fault(fault);
+    FAULT_COUNT.fetch_add(1, Ordering::SeqCst);
+    if let Some(mut inventory) = INVENTORY.try_lock()
+        && inventory.len() < inventory.capacity()
+    {
+        inventory.push(*fault);
+    }
+
+    permit_access(fault)
+}
+
+extern "efiapi" fn report_benign_faults_event(event: efi::Event, _context: *mut c_void) {
+    let count = FAULT_COUNT.load(Ordering::SeqCst);
+    if count == 0 {
+        log::info!("No benign memory faults were permitted.");
+    } else {
+        log::warn!("{count} benign memory fault(s) were permitted:");
+        INVENTORY.lock().iter().for_each(log_fault);
+        if count > MAX_RECORDED_FAULTS {
+            log::warn!("{} more fault(s) were not recorded.", count - MAX_RECORDED_FAULTS);
+        }
+    }
+
+    if let Err(status) = EVENT_DB.close_event(event) {
+        log::error!("Failed to close the benign fault report event: {status:?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    use crate::test_support;
+    use patina::base::UEFI_PAGE_SIZE;
+
+    fn with_locked_state<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
+        test_support::with_global_lock(|| {
+            RANGES.write().clear();
+            *INVENTORY.lock() = Vec::new();
+            FAULT_COUNT.store(0, Ordering::SeqCst);
+
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::reset_allocators();
+            }
+            f();
+        })
+        .unwrap();
+    }
+
+    fn allocate_page(attributes: u64) -> u64 {
+        let mut page: efi::PhysicalAddress = 0;
+        crate::allocator::core_allocate_pages(efi::ALLOCATE_ANY_PAGES, efi::BOOT_SERVICES_DATA, 1, &mut page, None)
+            .unwrap();
+        let desc = dxe_services::core_get_memory_space_descriptor(page).unwrap();
+        dxe_services::core_set_memory_space_capabilities(page, UEFI_PAGE_SIZE as u64, desc.capabilities | attributes)
+            .unwrap();
+        dxe_services::core_set_memory_space_attributes(page, UEFI_PAGE_SIZE as u64, desc.attributes | attributes)
+            .unwrap();
+        page
+    }
+
+    #[test]
+    fn test_faults_outside_ranges_are_fatal() {
+        with_locked_state(|| {
+            let page = allocate_page(efi::MEMORY_RP);
+            let fault = MemoryFault { address: page, instruction_pointer: 0, access: MemoryFaultAccess::Read };
+            assert!(!permit_benign_fault(&fault));
+
+            *RANGES.write() = alloc::vec![page + UEFI_PAGE_SIZE as u64..page + 2 * UEFI_PAGE_SIZE as u64];
+            assert!(!permit_benign_fault(&fault));
+            assert_eq!(FAULT_COUNT.load(Ordering::SeqCst), 0);
+        });
+    }
+
+    #[test]
+    fn test_benign_faults_relax_only_the_blocking_attributes() {
+        with_locked_state(|| {
+            let page = allocate_page(efi::MEMORY_RP | efi::MEMORY_RO | efi::MEMORY_XP);
+            *RANGES.write() = alloc::vec![page..page + UEFI_PAGE_SIZE as u64];
+            INVENTORY.lock().reserve_exact(MAX_RECORDED_FAULTS);
+
+            let fault = MemoryFault { address: page + 0x10, instruction_pointer: 0, access: MemoryFaultAccess::Write };
+            assert!(permit_benign_fault(&fault));
+
+            let attributes = dxe_services::core_get_memory_space_descriptor(page).unwrap().attributes;
+            assert_eq!(attributes & (efi::MEMORY_RP | efi::MEMORY_RO), 0);
+            assert_ne!(attributes & efi::MEMORY_XP, 0);
+
+            assert_eq!(FAULT_COUNT.load(Ordering::SeqCst), 1);
+            assert_eq!(INVENTORY.lock().as_slice(), &[fault]);
+        });
+    }
+}
@@ -0,0 +1,103 @@
+//! Boot Checkpoints
+//!
+//! Reports the [BootCheckpoint]s reached during boot to the platform's [CheckpointSink] service, if one is registered
+//! with the core, so that the cause of a hardware watchdog reset can be determined from the last checkpoint the
+//! platform persisted. ReadyToBoot and ExitBootServices() are reported from event notifications, as they are reached
+//! after the core hands off to BDS.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::ffi::c_void;
+
+use patina::component::service::{
+    Service,
+    boot_checkpoint::{BootCheckpoint, CheckpointSink},
+};
+use r_efi::efi;
+
+use crate::events::EVENT_DB;
+
+struct Sink(Service<dyn CheckpointSink>);
+
+// Safety: the sink is set once and only used to call the platform's service, which the core calls from a single
+// thread, so it is safe to mark it send and sync.
+unsafe impl Send for Sink {}
+unsafe impl Sync for Sink {}
+
+static SINK: spin::Once<Sink> = spin::Once::new();
+
+/// Registers the service that checkpoints are reported to, and reports that memory is initialized.
+pub(crate) fn register_checkpoint_sink(sink: Service<dyn CheckpointSink>) {
+    if SINK.is_completed() {
+        return;
+    }
+    SINK.call_once(|| Sink(sink));
+    report(BootCheckpoint::MemoryInitialized);
+
+    for (group, checkpoint) in [
+        (efi::EVENT_GROUP_READY_TO_BOOT, BootCheckpoint::ReadyToBoot),
+        (efi::EVENT_GROUP_EXIT_BOOT_SERVICES, BootCheckpoint::ExitBootServices),
+    ] {
+        // the checkpoint is passed as the context, as it is not a pointer to anything.
+        if let Err(status) = EVENT_DB.create_event(
+            efi::EVT_NOTIFY_SIGNAL,
+            efi::TPL_CALLBACK,
+            Some(report_checkpoint_event),
+            Some(checkpoint as usize as *mut c_void),
+            Some(group),
+        ) {
+            log::error!("Failed to register an event to report the {checkpoint:?} checkpoint! Status {status:#X?}");
+        }
+    }
+}
+
+/// Reports that boot reached `checkpoint` to the registered sink, if any.
+pub(crate) fn report(checkpoint: BootCheckpoint) {
+    if let Some(sink) = SINK.get() {
+        sink.0.checkpoint(checkpoint);
+    }
+}
+
+extern "efiapi" fn report_checkpoint_event(_event: efi::Event, context: *mut c_void) {
+    match context as usize {
+        value if value == BootCheckpoint::ReadyToBoot as usize => report(BootCheckpoint::ReadyToBoot),
+        value if value == BootCheckpoint::ExitBootServices as usize => report(BootCheckpoint::ExitBootServices),
+        _ => log::error!("Unexpected boot checkpoint context {context:p}"),
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use patina::component::service::boot_checkpoint::MockCheckpointSink;
+    use std::{boxed::Box, sync::Mutex, vec::Vec};
+
+    static REPORTED: Mutex<Vec<BootCheckpoint>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn test_checkpoints_are_reported() {
+        test_support::with_global_lock(|| {
+            // nothing is reported before a sink is registered.
+            report(BootCheckpoint::DispatchStart);
+
+            let mut sink = MockCheckpointSink::new();
+            sink.expect_checkpoint().returning(|checkpoint| REPORTED.lock().unwrap().push(checkpoint));
+            register_checkpoint_sink(Service::mock(Box::new(sink)));
+
+            report(BootCheckpoint::DispatchStart);
+            report_checkpoint_event(core::ptr::null_mut(), BootCheckpoint::ReadyToBoot as usize as *mut c_void);
+            report_checkpoint_event(core::ptr::null_mut(), 0xFF as *mut c_void);
+            assert_eq!(
+                *REPORTED.lock().unwrap(),
+                [BootCheckpoint::MemoryInitialized, BootCheckpoint::DispatchStart, BootCheckpoint::ReadyToBoot]
+            );
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! DXE Core Boot Configuration
+//!
+//! Serializes the configuration registered with the [Core](crate::Core) into a compact binary blob, and restores it at
+//! boot from a HOB with [BOOT_CONFIG_HOB_GUID]. Only configuration registered with
+//! [Core::with_boot_config](crate::Core::with_boot_config) is serialized; each datum is identified in the blob by its
+//! [BootConfig::CONFIG_GUID]. The blob also records the names of the registered components so that a blob produced for
+//! a different platform binary can be detected.
+//!
+//! ## Blob Format
+//!
+//! All integers are little endian.
+//!
+//! | Field           | Size                | Description                                     |
+//! |-----------------|---------------------|-------------------------------------------------|
+//! | signature       | 4                   | `PBCF`                                          |
+//! | version         | 4                   | `1`                                             |
+//! | config count    | 4                   | The number of configuration entries.            |
+//! | component count | 4                   | The number of component entries.                |
+//! | configs         | variable            | A 16 byte GUID, a `u32` length, and the data.   |
+//! | components      | variable            | A `u16` length and the UTF-8 component name.    |
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use patina::{
+    OwnedGuid,
+    component::{Storage, boot_config::BootConfig},
+};
+use r_efi::efi;
+
+/// The GUID of the HOB containing a boot configuration blob, produced by [Core::boot_config_blob](crate::Core).
+pub const BOOT_CONFIG_HOB_GUID: efi::Guid =
+    efi::Guid::from_fields(0xebc2ee67, 0x0c3d, 0x499f, 0x81, 0xb7, &[0xd6, 0x3e, 0x6e, 0xef, 0x24, 0x58]);
+
+const SIGNATURE: [u8; 4] = *b"PBCF";
+const VERSION: u32 = 1;
+
+/// A configuration datum registered with [Core::with_boot_config](crate::Core::with_boot_config).
+pub(crate) struct BootConfigEntry {
+    guid: OwnedGuid,
+    name: &'static str,
+    serialize: fn(&Storage) -> Option<Vec<u8>>,
+    load: fn(&[u8], &mut Storage) -> bool,
+}
+
+impl BootConfigEntry {
+    pub(crate) fn new<C: BootConfig>() -> Self {
+        Self {
+            guid: C::CONFIG_GUID,
+            name: core::any::type_name::<C>(),
+            serialize: |storage| storage.get_config::<C>().map(|config| config.to_bytes()),
+            load: |bytes, storage| match (C::from_bytes(bytes), storage.get_config_mut::<C>()) {
+                (Some(value), Some(mut config)) => {
+                    *config = value;
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Returns the guid identifying the configuration in a blob.
+    pub(crate) fn guid(&self) -> &OwnedGuid {
+        &self.guid
+    }
+}
+
+/// A decoded boot configuration blob.
+#[derive(Debug, PartialEq)]
+pub(crate) struct BootConfigBlob<'a> {
+    configs: Vec<(efi::Guid, &'a [u8])>,
+    components: Vec<&'a str>,
+}
+
+impl<'a> BootConfigBlob<'a> {
+    /// Serializes the registered boot configuration and component names into a blob.
+    pub(crate) fn encode<'n>(
+        entries: &[BootConfigEntry],
+        storage: &Storage,
+        components: impl Iterator<Item = &'n str>,
+    ) -> Vec<u8> {
+        let configs: Vec<_> =
+            entries.iter().filter_map(|entry| Some((entry.guid.as_bytes(), (entry.serialize)(storage)?))).collect();
+        let components: Vec<_> = components.collect();
+
+        let mut blob = Vec::new();
+        blob.extend_from_slice(&SIGNATURE);
+        blob.extend_from_slice(&VERSION.to_le_bytes());
+        blob.extend_from_slice(&(configs.len() as u32).to_le_bytes());
+        blob.extend_from_slice(&(components.len() as u32).to_le_bytes());
+        for (guid, data) in configs {
+            blob.extend_from_slice(&guid);
+            blob.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            blob.extend_from_slice(&data);
+        }
+        for name in components {
+            let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+            blob.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            blob.extend_from_slice(name);
+        }
+        blob
+    }
+
+    /// Decodes a blob, returning `None` if it is malformed or of an unsupported version.
+    pub(crate) fn decode(blob: &'a [u8]) -> Option<Self> {
+        let mut reader = Reader(blob);
+        if reader.take(SIGNATURE.len())? != SIGNATURE || reader.u32()? != VERSION {
+            return None;
+        }
+        let config_count = reader.u32()?;
+        let component_count = reader.u32()?;
+
+        let mut configs = Vec::new();
+        for _ in 0..config_count {
+            let guid = efi::Guid::from_bytes(reader.take(size_of::<efi::Guid>())?.try_into().ok()?);
+            let len = reader.u32()? as usize;
+            configs.push((guid, reader.take(len)?));
+        }
+        let mut components = Vec::new();
+        for _ in 0..component_count {
+            let len = u16::from_le_bytes(reader.take(2)?.try_into().ok()?) as usize;
+            components.push(core::str::from_utf8(reader.take(len)?).ok()?);
+        }
+        reader.0.is_empty().then_some(Self { configs, components })
+    }
+
+    /// Replaces the registered boot configuration with the values in the blob.
+    ///
+    /// Configuration in the blob that is not registered, or that fails to deserialize, is ignored. A warning is logged
+    /// if the component names in the blob do not match `components`, as the blob may have been produced for a different
+    /// platform binary.
+    pub(crate) fn apply<'n>(
+        &self,
+        entries: &[BootConfigEntry],
+        storage: &mut Storage,
+        components: impl Iterator<Item = &'n str>,
+    ) {
+        for (guid, data) in &self.configs {
+            match entries.iter().find(|entry| entry.guid == OwnedGuid::from(*guid)) {
+                Some(entry) if (entry.load)(data, storage) => {
+                    log::info!("Boot configuration: loaded {} from HOB.", entry.name)
+                }
+                Some(entry) => log::error!("Boot configuration: ignoring malformed {} in HOB.", entry.name),
+                None => log::warn!("Boot configuration: ignoring unregistered config {guid:?} in HOB."),
+            }
+        }
+
+        let components: Vec<_> = components.collect();
+        if components != self.components {
+            let missing: Vec<_> = self.components.iter().filter(|name| !components.contains(name)).collect();
+            let added: Vec<_> = components.iter().filter(|name| !self.components.contains(name)).collect();
+            log::warn!(
+                "Boot configuration HOB was produced for a different component list. Missing: {missing:?}, Added: {added:?}"
+            );
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (head, tail) = self.0.split_at_checked(len)?;
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(size_of::<u32>())?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct TestConfig(u32);
+
+    impl BootConfig for TestConfig {
+        const CONFIG_GUID: OwnedGuid = OwnedGuid::from_fields(1, 2, 3, 4, 5, [6; 6]);
+
+        fn to_bytes(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            Some(TestConfig(u32::from_le_bytes(bytes.try_into().ok()?)))
+        }
+    }
+
+    #[test]
+    fn test_boot_config_blob_round_trip() {
+        let entries = [BootConfigEntry::new::<TestConfig>()];
+        let mut storage = Storage::new();
+        storage.add_config(TestConfig(0x1234));
+        let blob = BootConfigBlob::encode(&entries, &storage, ["a", "b"].into_iter());
+
+        let decoded = BootConfigBlob::decode(&blob).unwrap();
+        assert_eq!(decoded.configs, vec![(TestConfig::CONFIG_GUID.to_efi_guid(), &0x1234u32.to_le_bytes()[..])]);
+        assert_eq!(decoded.components, vec!["a", "b"]);
+
+        let mut target = Storage::new();
+        target.add_config(TestConfig(0));
+        decoded.apply(&entries, &mut target, ["a"].into_iter());
+        assert_eq!(*target.get_config::<TestConfig>().unwrap(), TestConfig(0x1234));
+    }
+
+    #[test]
+    fn test_boot_config_blob_ignores_unknown_and_malformed_configs() {
+        let entries = [BootConfigEntry::new::<TestConfig>()];
+        let other = efi::Guid::from_fields(9, 9, 9, 9, 9, &[9; 6]);
+        let blob = BootConfigBlob {
+            configs: vec![(other, &[1, 2, 3, 4]), (TestConfig::CONFIG_GUID.to_efi_guid(), &[1, 2])],
+            components: vec![],
+        };
+
+        let mut storage = Storage::new();
+        storage.add_config(TestConfig(7));
+        blob.apply(&entries, &mut storage, core::iter::empty());
+        assert_eq!(*storage.get_config::<TestConfig>().unwrap(), TestConfig(7));
+    }
+
+    #[test]
+    fn test_boot_config_blob_rejects_malformed_blobs() {
+        let blob = BootConfigBlob::encode(&[], &Storage::new(), ["component"].into_iter());
+        assert!(BootConfigBlob::decode(&blob).is_some());
+        assert!(BootConfigBlob::decode(&blob[..blob.len() - 1]).is_none());
+        assert!(BootConfigBlob::decode(&[blob.as_slice(), &[0]].concat()).is_none());
+
+        let mut bad_signature = blob.clone();
+        bad_signature[0] = b'X';
+        assert!(BootConfigBlob::decode(&bad_signature).is_none());
+
+        let mut bad_version = blob;
+        bad_version[4] = 2;
+        assert!(BootConfigBlob::decode(&bad_version).is_none());
+    }
+}
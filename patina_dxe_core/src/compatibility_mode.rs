@@ -0,0 +1,130 @@
+//! Compatibility Mode Telemetry
+//!
+//! Records each image that is not NX compatible and would need compatibility mode, with the protections that were
+//! relaxed for it, so that OEMs can measure how often legacy OS loaders degrade platform security in the field. The
+//! activations are available through the
+//! [CompatibilityModeTelemetry](patina::component::service::compatibility_mode::CompatibilityModeTelemetry) service,
+//! and each is reported as a status code for telemetry drivers to forward.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{string::String, vec::Vec};
+
+use patina::{
+    component::service::{
+        IntoService,
+        compatibility_mode::{
+            CompatibilityModeActivation, CompatibilityModeStatusCodeData, CompatibilityModeTelemetry,
+            RelaxedProtections,
+        },
+    },
+    guids,
+};
+use patina_pi::{
+    protocols::status_code,
+    status_code::{EFI_ERROR_CODE, EFI_ERROR_MINOR, EFI_SOFTWARE_DXE_CORE, EFI_SW_EC_ILLEGAL_SOFTWARE_STATE},
+};
+use spin::Mutex;
+
+use crate::protocols::PROTOCOL_DB;
+
+static ACTIVATIONS: Mutex<Vec<CompatibilityModeActivation>> = Mutex::new(Vec::new());
+
+/// Records that `image`, which is not NX compatible, was loaded with compatibility mode if `allowed`, or refused.
+///
+/// Must be called before compatibility mode is activated for the image, as only the first image allowed to load
+/// relaxes the protections of the whole platform.
+pub(crate) fn record_activation(image: String, image_base: u64, image_size: u64, allowed: bool) {
+    let activation = {
+        let mut activations = ACTIVATIONS.lock();
+        let relaxed = match allowed {
+            false => RelaxedProtections::default(),
+            true if activations.iter().any(|activation| activation.allowed) => {
+                RelaxedProtections { image_writable_and_executable: true, ..Default::default() }
+            }
+            true => RelaxedProtections::ALL,
+        };
+        let activation = CompatibilityModeActivation { image, image_base, image_size, allowed, relaxed };
+        activations.push(activation.clone());
+        activation
+    };
+
+    log::warn!(
+        "Compatibility mode {} for {} at {:#x}, relaxing {:?}.",
+        if allowed { "activated" } else { "refused" },
+        activation.image,
+        activation.image_base,
+        activation.relaxed
+    );
+    report_status_code(&activation);
+}
+
+fn report_status_code(activation: &CompatibilityModeActivation) {
+    let data = CompatibilityModeStatusCodeData::from(activation);
+    match PROTOCOL_DB.locate_protocol(status_code::PROTOCOL_GUID) {
+        Ok(status_code_ptr) => {
+            // Safety: the protocol database returned the interface installed for the status code protocol.
+            let status_code_protocol = unsafe { &*(status_code_ptr as *mut status_code::Protocol) };
+            (status_code_protocol.report_status_code)(
+                EFI_ERROR_CODE | EFI_ERROR_MINOR,
+                EFI_SOFTWARE_DXE_CORE | EFI_SW_EC_ILLEGAL_SOFTWARE_STATE,
+                0,
+                &guids::DXE_CORE,
+                &data.header,
+            );
+        }
+        Err(err) => log::error!("Unable to locate status code runtime protocol to report compatibility mode: {err:?}"),
+    }
+}
+
+/// Returns the recorded activations, in the order the images were loaded.
+pub(crate) fn activations() -> Vec<CompatibilityModeActivation> {
+    ACTIVATIONS.lock().clone()
+}
+
+/// The core's [CompatibilityModeTelemetry] service, which reports the recorded activations.
+#[derive(IntoService)]
+#[service(dyn CompatibilityModeTelemetry)]
+pub(crate) struct CoreCompatibilityModeTelemetry;
+
+impl CompatibilityModeTelemetry for CoreCompatibilityModeTelemetry {
+    fn activations(&self) -> Vec<CompatibilityModeActivation> {
+        activations()
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use crate::test_support;
+
+    #[test]
+    fn test_only_the_first_allowed_image_relaxes_platform_protections() {
+        test_support::with_global_lock(|| {
+            ACTIVATIONS.lock().clear();
+            record_activation(String::from("refused.efi"), 0x1000, 0x2000, false);
+            record_activation(String::from("grubx64.efi"), 0x10000, 0x2000, true);
+            record_activation(String::from("Unknown"), 0x20000, 0x1000, true);
+
+            let activations = CoreCompatibilityModeTelemetry.activations();
+            let relaxed = activations.iter().map(|activation| activation.relaxed).collect::<Vec<_>>();
+            assert_eq!(
+                relaxed,
+                [
+                    RelaxedProtections::default(),
+                    RelaxedProtections::ALL,
+                    RelaxedProtections { image_writable_and_executable: true, ..Default::default() }
+                ]
+            );
+            assert_eq!(activations[1].image, "grubx64.efi");
+            ACTIVATIONS.lock().clear();
+        })
+        .unwrap();
+    }
+}
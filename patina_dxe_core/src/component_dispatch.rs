@@ -0,0 +1,204 @@
+//! Parallel Component Dispatch
+//!
+//! Components are dispatched one at a time on the boot strap processor (BSP) by default. Platforms can register
+//! [ComponentDispatch::Parallel] to dispatch independent components at the same time on the application processors
+//! (APs) once the MP Services protocol is installed. Each dispatch pass first selects a batch of components whose
+//! [Access] requirements allow them to run on an AP, and that share no config or service with each other; the batch is
+//! run on the APs, and the remaining components are then dispatched on the BSP as usual.
+//!
+//! Components that take boot or runtime services, [Commands](patina::component::params::Commands), or the storage
+//! itself are always dispatched on the BSP. So are components that take any service that was not added with
+//! [Storage::add_ap_safe_service], as the core cannot check what a service does internally; its producer must vouch
+//! for it through [ApSafe](patina::component::service::ApSafe).
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::{
+    ffi::c_void,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, vec::Vec};
+use patina::{
+    component::{Access, Component, Storage, UnsafeStorageCell},
+    error::Result,
+};
+use r_efi::{efi, protocols::mp_services};
+use spin::Mutex;
+
+use crate::{protocols::PROTOCOL_DB, tlb_shootdown, tpl_lock};
+
+/// Selects how the core dispatches Patina components.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{ComponentDispatch, Core};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ComponentDispatch::Parallel)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComponentDispatch {
+    /// Components are dispatched one at a time on the BSP.
+    #[default]
+    Serial,
+    /// Once the MP Services protocol is installed, independent components are dispatched at the same time on the APs.
+    Parallel,
+}
+
+// A component dispatched on an AP, and its result once it ran.
+struct Job {
+    index: usize,
+    component: *mut dyn Component,
+    result: Mutex<Option<Result<bool>>>,
+}
+
+// The batch of components dispatched on the APs. Each AP takes the next job until none remain.
+struct Batch<'s> {
+    jobs: Vec<Job>,
+    next: AtomicUsize,
+    storage: UnsafeStorageCell<'s>,
+    tlb_generation: usize,
+}
+
+// SAFETY: each job is taken by exactly one AP, and the components in a batch share no config or service.
+unsafe impl Sync for Batch<'_> {}
+
+/// Returns the indices of the components that can be dispatched at the same time on the APs.
+///
+/// A component is only selected if every service it takes satisfies `is_ap_safe`.
+fn select_batch<'a>(accesses: impl Iterator<Item = &'a Access>, is_ap_safe: impl Fn(usize) -> bool) -> Vec<usize> {
+    let mut selected: Vec<(usize, &Access)> = Vec::new();
+    for (index, access) in accesses.enumerate() {
+        if !access.requires_bsp()
+            && access.services().all(&is_ap_safe)
+            && selected.iter().all(|(_, other)| access.is_compatible(other))
+        {
+            selected.push((index, access));
+        }
+    }
+    selected.into_iter().map(|(index, _)| index).collect()
+}
+
+extern "efiapi" fn run_batch(context: *mut c_void) {
+    // SAFETY: the context is the batch passed to StartupAllAPs, which outlives the blocking call.
+    let batch = unsafe { &*(context as *const Batch) };
+    let mut tlb_generation = batch.tlb_generation;
+    loop {
+        // mappings changed by the components on the other processors are flushed before each component runs.
+        tlb_shootdown::flush_local_tlb_if_stale(&mut tlb_generation);
+        let Some(job) = batch.jobs.get(batch.next.fetch_add(1, Ordering::SeqCst)) else {
+            break;
+        };
+        // SAFETY: the job was taken by this AP alone, and the batch was selected so that its components do not access
+        //         the same parts of storage.
+        let result = unsafe { (*job.component).run_unsafe(batch.storage) };
+        *job.result.lock() = Some(result);
+    }
+}
+
+/// Dispatches a batch of independent components on the APs, if the MP Services protocol is installed.
+///
+/// Returns the results of the components that ran, by their index in `components`. Components that did not run are
+/// left for the serial dispatch on the BSP.
+pub(crate) fn dispatch_on_aps(
+    components: &mut [Box<dyn Component>],
+    storage: &mut Storage,
+) -> Vec<(usize, Result<bool>)> {
+    let Ok(mp_services) = PROTOCOL_DB.locate_protocol(mp_services::PROTOCOL_GUID) else {
+        return Vec::new();
+    };
+    let mp_services = mp_services as *mut mp_services::Protocol;
+
+    let batch = select_batch(components.iter().map(|component| component.metadata().access()), |id| {
+        storage.is_ap_safe_service(id)
+    });
+    if batch.len() < 2 {
+        return Vec::new();
+    }
+
+    let jobs = components
+        .iter_mut()
+        .enumerate()
+        .filter(|(index, _)| batch.contains(index))
+        .map(|(index, component)| Job { index, component: &mut **component, result: Mutex::new(None) })
+        .collect();
+    let tlb_generation = tlb_shootdown::generation();
+    let batch = Batch { jobs, next: AtomicUsize::new(0), storage: UnsafeStorageCell::from(storage), tlb_generation };
+
+    tpl_lock::set_mp_services(mp_services);
+    // SAFETY: the protocol is identified by its GUID and should be a valid pointer to an EFI_MP_SERVICES_PROTOCOL
+    //         structure. A null wait event runs the procedure in blocking mode, so every AP is done with the batch
+    //         when the call returns.
+    let status = unsafe {
+        ((*mp_services).startup_all_aps)(
+            mp_services,
+            run_batch,
+            efi::Boolean::FALSE,
+            ptr::null_mut(),
+            0,
+            &batch as *const Batch as *mut c_void,
+            ptr::null_mut(),
+        )
+    };
+    tpl_lock::set_mp_services(ptr::null_mut());
+    tlb_shootdown::complete_ap_batch(tlb_generation);
+
+    match status {
+        efi::Status::SUCCESS => {}
+        // NOT_STARTED is returned when there are no enabled APs, so the batch is dispatched on the BSP.
+        efi::Status::NOT_STARTED => {}
+        status => log::error!("Failed to dispatch components on the APs: {status:#x?}"),
+    }
+
+    batch.jobs.into_iter().filter_map(|job| job.result.into_inner().map(|result| (job.index, result))).collect()
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    fn access(configs: &[usize], services: &[usize]) -> Access {
+        let mut access = Access::new();
+        configs.iter().for_each(|&id| access.add_config_read(id));
+        services.iter().for_each(|&id| access.add_service(id));
+        access
+    }
+
+    #[test]
+    fn test_batch_holds_independent_components() {
+        let mut bsp = access(&[], &[]);
+        bsp.boot_services();
+        let accesses = [access(&[0], &[0]), access(&[1], &[0]), bsp, access(&[1], &[1]), access(&[0], &[2])];
+
+        assert_eq!(select_batch(accesses.iter(), |_| true), [0, 3]);
+    }
+
+    #[test]
+    fn test_batch_skips_components_that_require_the_bsp() {
+        let mut deferred = access(&[], &[]);
+        deferred.deferred();
+        let mut storage = access(&[], &[]);
+        storage.writes_all_configs();
+
+        assert!(select_batch([deferred, storage].iter(), |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_batch_skips_components_with_services_that_are_not_ap_safe() {
+        let accesses = [access(&[], &[0]), access(&[], &[1]), access(&[], &[2, 3]), access(&[], &[])];
+
+        assert_eq!(select_batch(accesses.iter(), |id| id != 1 && id != 3), [0, 3]);
+    }
+}
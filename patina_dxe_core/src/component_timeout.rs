@@ -0,0 +1,185 @@
+//! Component Dispatch Timeouts
+//!
+//! A component whose entry point never returns wedges [Core::start](crate::Core::start) without any indication of
+//! which component is at fault. Platforms can register [ComponentTimeouts] to watch each entry point: the dispatcher
+//! arms a watch before it runs a component, and the timer interrupt handler adds the elapsed time on every tick. Once
+//! a component is past its timeout, the core logs its name, then either lets it continue or panics, per the
+//! [ComponentTimeoutAction].
+//!
+//! The watch relies on timer interrupts, so it cannot detect a component that hangs with interrupts disabled, such as
+//! while holding a TPL_HIGH_LEVEL lock. Components dispatched on the application processors are not watched.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use r_efi::efi;
+
+use crate::tpl_lock::TplMutex;
+
+/// What the core does when a component is still in its entry point after its timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComponentTimeoutAction {
+    /// Logs the component's name, and lets it continue.
+    #[default]
+    Log,
+    /// Logs the component's name, and panics, so that the [PanicPolicy](crate::PanicPolicy) is applied.
+    Panic,
+}
+
+/// Timeouts for the entry points of Patina components.
+///
+/// Components are not watched unless the platform registers this config. Overrides are matched against the name of
+/// the component, either the full path or the type name alone.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{ComponentTimeoutAction, ComponentTimeouts, Core};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ComponentTimeouts::new(500, ComponentTimeoutAction::Panic).with_override("NetworkStack", 5000))
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentTimeouts {
+    /// The timeout in milliseconds for components without an override. Zero disables the watch for them.
+    pub default_timeout_ms: u64,
+    /// The timeouts in milliseconds for specific components, by name. Zero disables the watch for the component.
+    pub overrides: Vec<(&'static str, u64)>,
+    /// What the core does when a component is past its timeout.
+    pub action: ComponentTimeoutAction,
+}
+
+impl ComponentTimeouts {
+    /// Creates a config that applies `default_timeout_ms` to every component.
+    pub fn new(default_timeout_ms: u64, action: ComponentTimeoutAction) -> Self {
+        Self { default_timeout_ms, overrides: Vec::new(), action }
+    }
+
+    /// Applies `timeout_ms` to the component named `component` instead of the default.
+    pub fn with_override(mut self, component: &'static str, timeout_ms: u64) -> Self {
+        self.overrides.push((component, timeout_ms));
+        self
+    }
+
+    // Returns the timeout in milliseconds for the component named `name`.
+    fn timeout_ms(&self, name: &str) -> u64 {
+        self.overrides
+            .iter()
+            .find(|(component, _)| {
+                name.strip_suffix(component).is_some_and(|path| path.is_empty() || path.ends_with("::"))
+            })
+            .map_or(self.default_timeout_ms, |&(_, timeout_ms)| timeout_ms)
+    }
+}
+
+// The component in its entry point, and the time it has been there in 100ns units.
+struct Watch {
+    name: Option<&'static str>,
+    action: ComponentTimeoutAction,
+    timeout_ms: u64,
+    elapsed: u64,
+}
+
+// Locked at TPL_HIGH_LEVEL, as it is updated from the timer interrupt handler.
+static WATCH: TplMutex<Watch> = TplMutex::new(
+    efi::TPL_HIGH_LEVEL,
+    Watch { name: None, action: ComponentTimeoutAction::Log, timeout_ms: 0, elapsed: 0 },
+    "ComponentWatchLock",
+);
+
+/// Runs `entry_point` for the component named `name`, watching it per `timeouts`.
+pub(crate) fn watch<R>(timeouts: Option<&ComponentTimeouts>, name: &'static str, entry_point: impl FnOnce() -> R) -> R {
+    let timeout_ms = timeouts.map_or(0, |timeouts| timeouts.timeout_ms(name));
+    if timeout_ms == 0 {
+        return entry_point();
+    }
+    let action = timeouts.map(|timeouts| timeouts.action).unwrap_or_default();
+    *WATCH.lock() = Watch { name: Some(name), action, timeout_ms, elapsed: 0 };
+    let result = entry_point();
+    WATCH.lock().name = None;
+    result
+}
+
+/// Called on every timer tick with the time since the previous tick, in 100ns units.
+pub(crate) fn timer_tick(time: u64) {
+    let mut watch = WATCH.lock();
+    let Some(name) = watch.name else {
+        return;
+    };
+    watch.elapsed += time;
+    if watch.elapsed < watch.timeout_ms.saturating_mul(10_000) {
+        return;
+    }
+    // Report the component once.
+    watch.name = None;
+    let (action, timeout_ms) = (watch.action, watch.timeout_ms);
+    drop(watch);
+
+    log::error!("Component {name:?} has been in its entry point for more than {timeout_ms} ms.");
+    if action == ComponentTimeoutAction::Panic {
+        panic!("Component {name:?} timed out in its entry point.");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_overrides_match_the_full_path_or_type_name() {
+        let timeouts = ComponentTimeouts::new(100, ComponentTimeoutAction::Log)
+            .with_override("Recovery", 200)
+            .with_override("patina_net::Stack", 0);
+
+        assert_eq!(timeouts.timeout_ms("patina_recovery::component::Recovery"), 200);
+        assert_eq!(timeouts.timeout_ms("patina_recovery::GoldenRecovery"), 100);
+        assert_eq!(timeouts.timeout_ms("patina_net::Stack"), 0);
+        assert_eq!(timeouts.timeout_ms("Other"), 100);
+    }
+
+    #[test]
+    fn test_a_component_past_its_timeout_is_reported_once() {
+        test_support::with_global_lock(|| {
+            let timeouts = ComponentTimeouts::new(1, ComponentTimeoutAction::Panic);
+            // 0.5 ms elapses in the entry point.
+            watch(Some(&timeouts), "Fast", || timer_tick(5_000));
+
+            let timeouts = ComponentTimeouts::new(1, ComponentTimeoutAction::Log);
+            watch(Some(&timeouts), "Slow", || {
+                timer_tick(10_000);
+                assert!(WATCH.lock().name.is_none());
+                timer_tick(10_000);
+            });
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_a_component_past_its_timeout_panics_per_policy() {
+        let result = test_support::with_global_lock(|| {
+            let timeouts = ComponentTimeouts::new(1, ComponentTimeoutAction::Panic);
+            watch(Some(&timeouts), "Hung", || timer_tick(10_000));
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_components_are_not_watched_without_a_timeout() {
+        test_support::with_global_lock(|| {
+            watch(None, "Unwatched", || assert!(WATCH.lock().name.is_none()));
+            let timeouts = ComponentTimeouts::new(0, ComponentTimeoutAction::Panic);
+            watch(Some(&timeouts), "Unwatched", || timer_tick(u64::MAX / 2));
+        })
+        .unwrap();
+    }
+}
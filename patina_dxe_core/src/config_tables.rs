@@ -7,19 +7,104 @@
 //! SPDX-License-Identifier: Apache-2.0
 //!
 pub(crate) mod debug_image_info_table;
+pub(crate) mod hob_list_table;
 pub(crate) mod memory_attributes_table;
 
-use alloc::{boxed::Box, vec};
-use core::{ffi::c_void, slice::from_raw_parts_mut};
-use patina::error::EfiError;
-use r_efi::efi;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{
+    ffi::c_void,
+    slice::{from_raw_parts, from_raw_parts_mut},
+};
+use patina::{error::EfiError, guids};
+use r_efi::{efi, system};
 
 use crate::{
     allocator::EFI_RUNTIME_SERVICES_DATA_ALLOCATOR,
     events::EVENT_DB,
+    guid_names,
+    image::core_current_image_name,
     systemtables::{EfiSystemTable, SYSTEM_TABLE},
+    tpl_lock,
 };
 
+/// Configuration tables that may not be replaced or removed after ReadyToBoot.
+///
+/// Once the ReadyToBoot event group is signaled, an InstallConfigurationTable call that would change or delete the
+/// table registered for one of these GUIDs fails with `EFI_ACCESS_DENIED`. Installing a table for a GUID that is not
+/// yet present, or reinstalling the pointer that is already registered, is still allowed. The lock is taken at
+/// ReadyToBoot rather than EndOfDxe because platform drivers legitimately republish ACPI, SMBIOS, and the HOB list
+/// between the two; locking at ReadyToBoot still guards the tables the OS consumes against being swapped by
+/// third-party code (such as option ROMs and boot applications) late in boot. No tables are locked unless the platform
+/// registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, LockedConfigurationTables};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(LockedConfigurationTables::recommended())
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockedConfigurationTables(pub Vec<efi::Guid>);
+
+impl LockedConfigurationTables {
+    /// Locks the ACPI, SMBIOS, and HOB list configuration tables.
+    pub fn recommended() -> Self {
+        Self(vec![
+            system::ACPI_20_TABLE_GUID,
+            system::ACPI_10_TABLE_GUID,
+            system::SMBIOS_TABLE_GUID,
+            system::SMBIOS3_TABLE_GUID,
+            guids::HOB_LIST,
+        ])
+    }
+}
+
+struct TableLock {
+    guids: Vec<efi::Guid>,
+    active: bool,
+}
+
+static TABLE_LOCK: tpl_lock::TplMutex<TableLock> =
+    tpl_lock::TplMutex::new(efi::TPL_HIGH_LEVEL, TableLock { guids: Vec::new(), active: false }, "ConfigTableLock");
+
+/// Sets the configuration tables that are locked once ReadyToBoot is signaled.
+pub fn set_locked_configuration_tables(locked: &LockedConfigurationTables) {
+    TABLE_LOCK.lock().guids = locked.0.clone();
+}
+
+extern "efiapi" fn lock_configuration_tables(event: efi::Event, _context: *mut c_void) {
+    let mut table_lock = TABLE_LOCK.lock();
+    if !table_lock.guids.is_empty() {
+        log::info!("Locking {} configuration table(s) at ReadyToBoot.", table_lock.guids.len());
+    }
+    table_lock.active = true;
+    drop(table_lock);
+
+    if let Err(status) = EVENT_DB.close_event(event) {
+        log::error!("Failed to close the configuration table lock event: {status:?}");
+    }
+}
+
+fn is_table_locked(vendor_guid: &efi::Guid) -> bool {
+    let table_lock = TABLE_LOCK.lock();
+    table_lock.active && table_lock.guids.contains(vendor_guid)
+}
+
+fn find_table(system_table: &efi::SystemTable, vendor_guid: &efi::Guid) -> Option<*mut c_void> {
+    if system_table.configuration_table.is_null() {
+        return None;
+    }
+    // Safety: the configuration table pointer and entry count are maintained by core_install_configuration_table.
+    let tables = unsafe { from_raw_parts(system_table.configuration_table, system_table.number_of_table_entries) };
+    tables.iter().find(|x| x.vendor_guid == *vendor_guid).map(|x| x.vendor_table)
+}
+
 extern "efiapi" fn install_configuration_table(table_guid: *mut efi::Guid, table: *mut c_void) -> efi::Status {
     if table_guid.is_null() {
         return efi::Status::INVALID_PARAMETER;
@@ -46,6 +131,19 @@ pub fn core_install_configuration_table(
     efi_system_table: &mut EfiSystemTable,
 ) -> Result<(), EfiError> {
     let system_table = efi_system_table.as_mut();
+    let existing_table = find_table(system_table, &vendor_guid);
+    let caller = core_current_image_name().unwrap_or_else(|| "DXE Core".into());
+
+    // Reinstalling the pointer that is already registered does not change the table, so it is allowed even when locked.
+    if existing_table.is_some_and(|table| table != vendor_table) && is_table_locked(&vendor_guid) {
+        log::error!(
+            "Denied {} of locked configuration table {:?} by {caller} after ReadyToBoot.",
+            if vendor_table.is_null() { "removal" } else { "replacement" },
+            guid_names::named(&vendor_guid),
+        );
+        return Err(EfiError::AccessDenied);
+    }
+
     //if a table is already present, reconstruct it from the pointer and length in the st.
     let old_cfg_table = if system_table.configuration_table.is_null() {
         assert_eq!(system_table.number_of_table_entries, 0);
@@ -118,6 +216,20 @@ pub fn core_install_configuration_table(
     //since we modified the system table, re-calculate CRC.
     efi_system_table.checksum();
 
+    match (existing_table, vendor_table.is_null()) {
+        (None, _) => log::info!(
+            "Configuration table {:?} installed at {vendor_table:p} by {caller}.",
+            guid_names::named(&vendor_guid)
+        ),
+        (Some(old), false) => log::info!(
+            "Configuration table {:?} replaced ({old:p} -> {vendor_table:p}) by {caller}.",
+            guid_names::named(&vendor_guid)
+        ),
+        (Some(old), true) => {
+            log::info!("Configuration table {:?} at {old:p} removed by {caller}.", guid_names::named(&vendor_guid))
+        }
+    }
+
     //signal the table guid as an event group
     EVENT_DB.signal_group(vendor_guid);
 
@@ -126,4 +238,95 @@ pub fn core_install_configuration_table(
 
 pub fn init_config_tables_support(bs: &mut efi::BootServices) {
     bs.install_configuration_table = install_configuration_table;
+
+    EVENT_DB
+        .create_event(
+            efi::EVT_NOTIFY_SIGNAL,
+            efi::TPL_CALLBACK,
+            Some(lock_configuration_tables),
+            None,
+            Some(efi::EVENT_GROUP_READY_TO_BOOT),
+        )
+        .expect("Failed to create the configuration table lock event.");
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    use crate::{systemtables::init_system_table, test_support};
+
+    const TEST_GUID: efi::Guid =
+        efi::Guid::from_fields(0x6c6c1b2e, 0x4e1a, 0x4b7d, 0x93, 0x2f, &[0x1d, 0x5e, 0x7a, 0x20, 0x8c, 0x41]);
+
+    fn with_locked_state<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
+        test_support::with_global_lock(|| {
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::reset_allocators();
+                init_system_table();
+            }
+            *TABLE_LOCK.lock() = TableLock { guids: Vec::new(), active: false };
+            f();
+        })
+        .unwrap();
+    }
+
+    fn install(guid: efi::Guid, table: usize) -> Result<(), EfiError> {
+        let mut st = SYSTEM_TABLE.lock();
+        core_install_configuration_table(guid, table as *mut c_void, st.as_mut().unwrap())
+    }
+
+    fn lookup(guid: efi::Guid) -> Option<*mut c_void> {
+        find_table(SYSTEM_TABLE.lock().as_ref().unwrap().system_table(), &guid)
+    }
+
+    #[test]
+    fn test_install_modify_delete() {
+        with_locked_state(|| {
+            assert_eq!(install(TEST_GUID, 0), Err(EfiError::NotFound));
+            assert_eq!(install(TEST_GUID, 0x1000), Ok(()));
+            assert_eq!(lookup(TEST_GUID), Some(0x1000 as *mut c_void));
+            assert_eq!(install(TEST_GUID, 0x2000), Ok(()));
+            assert_eq!(lookup(TEST_GUID), Some(0x2000 as *mut c_void));
+            assert_eq!(install(TEST_GUID, 0), Ok(()));
+            assert_eq!(lookup(TEST_GUID), None);
+        });
+    }
+
+    #[test]
+    fn test_locked_tables_are_only_enforced_after_ready_to_boot() {
+        with_locked_state(|| {
+            set_locked_configuration_tables(&LockedConfigurationTables::recommended());
+            assert_eq!(install(system::ACPI_20_TABLE_GUID, 0x1000), Ok(()));
+            assert_eq!(install(system::ACPI_20_TABLE_GUID, 0x2000), Ok(()));
+
+            lock_configuration_tables(core::ptr::null_mut(), core::ptr::null_mut());
+
+            assert_eq!(install(system::ACPI_20_TABLE_GUID, 0x3000), Err(EfiError::AccessDenied));
+            assert_eq!(install(system::ACPI_20_TABLE_GUID, 0), Err(EfiError::AccessDenied));
+            assert_eq!(lookup(system::ACPI_20_TABLE_GUID), Some(0x2000 as *mut c_void));
+
+            // Reinstalling the registered pointer is not a change, so it is still allowed.
+            assert_eq!(install(system::ACPI_20_TABLE_GUID, 0x2000), Ok(()));
+            assert_eq!(lookup(system::ACPI_20_TABLE_GUID), Some(0x2000 as *mut c_void));
+
+            // Locked GUIDs that are not yet installed may still be installed, and other GUIDs are unaffected.
+            assert_eq!(install(system::SMBIOS3_TABLE_GUID, 0x4000), Ok(()));
+            assert_eq!(install(TEST_GUID, 0x5000), Ok(()));
+            assert_eq!(install(TEST_GUID, 0), Ok(()));
+        });
+    }
+
+    #[test]
+    fn test_nothing_is_locked_by_default() {
+        with_locked_state(|| {
+            assert_eq!(install(system::SMBIOS_TABLE_GUID, 0x1000), Ok(()));
+            lock_configuration_tables(core::ptr::null_mut(), core::ptr::null_mut());
+            assert_eq!(install(system::SMBIOS_TABLE_GUID, 0x2000), Ok(()));
+            assert_eq!(install(system::SMBIOS_TABLE_GUID, 0), Ok(()));
+        });
+    }
 }
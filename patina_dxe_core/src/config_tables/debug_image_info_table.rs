@@ -164,7 +164,8 @@ pub(crate) fn initialize_debug_image_info_table(system_table: &mut EfiSystemTabl
             },
         );
 
-        let crc32 = crc32fast::hash(alloc::slice::from_raw_parts(ptr as *const u8, size_of::<EfiSystemTablePointer>()));
+        let crc32 =
+            crate::crc32::hash(alloc::slice::from_raw_parts(ptr as *const u8, size_of::<EfiSystemTablePointer>()));
 
         ptr::write_volatile(&mut (*ptr).crc32, crc32);
     }
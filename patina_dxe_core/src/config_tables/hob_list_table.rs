@@ -0,0 +1,298 @@
+//! DXE Core HOB List Configuration Table
+//!
+//! The relocated HOB list is published as a configuration table for consumers that parse HOBs after the DXE core has
+//! started. Drivers have historically corrupted the HOB list with wild writes, so the table is placed in its own pages,
+//! mapped read-only, and checked against a CRC computed at installation when ReadyToBoot is signaled. HOBs produced
+//! during DXE through the [HobProducer] service are appended to a new copy of the table, which is then re-published.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::{
+    ffi::c_void,
+    mem::size_of,
+    slice,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use patina::{
+    base::UEFI_PAGE_SIZE,
+    component::service::{
+        IntoService,
+        hob_producer::{HobProducer, encode_hob},
+    },
+    error::EfiError,
+    guids, uefi_size_to_pages,
+};
+use patina_pi::hob::{self, PhaseHandoffInformationTable, header};
+use r_efi::efi;
+
+use crate::{
+    allocator::{core_allocate_pages, core_free_pages},
+    config_tables::core_install_configuration_table,
+    dxe_services,
+    events::EVENT_DB,
+    systemtables::{EfiSystemTable, SYSTEM_TABLE},
+};
+
+// The location, length and CRC32 of the HOB list table, recorded when it is installed.
+static HOB_LIST_TABLE_BASE: AtomicU64 = AtomicU64::new(0);
+static HOB_LIST_TABLE_LEN: AtomicUsize = AtomicUsize::new(0);
+static HOB_LIST_TABLE_CRC: AtomicU32 = AtomicU32::new(0);
+
+/// Copies `hob_list` to newly allocated pages and installs it as the HOB list configuration table.
+///
+/// The pages are mapped read-only and an integrity check is registered for ReadyToBoot. Failure to apply the
+/// protection or register the check is logged, but does not fail the installation.
+pub fn install_hob_list_table(hob_list: &[u8], system_table: &mut EfiSystemTable) -> Result<(), EfiError> {
+    let first_install = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst) == 0;
+
+    let num_pages = uefi_size_to_pages!(hob_list.len());
+    let mut base: efi::PhysicalAddress = 0;
+    core_allocate_pages(efi::ALLOCATE_ANY_PAGES, efi::BOOT_SERVICES_DATA, num_pages, &mut base, None)?;
+
+    // Safety: the pages were just allocated, and are large enough to hold the HOB list.
+    let table = unsafe { slice::from_raw_parts_mut(base as *mut u8, hob_list.len()) };
+    table.copy_from_slice(hob_list);
+    update_end_of_hob_list(table, base);
+
+    if let Err(err) = core_install_configuration_table(guids::HOB_LIST, base as *mut c_void, system_table) {
+        if let Err(status) = core_free_pages(base, num_pages) {
+            log::error!("Failed to free HOB list table pages at {base:#X} with Status {status:#X?}");
+        }
+        return Err(err);
+    }
+
+    HOB_LIST_TABLE_BASE.store(base, Ordering::SeqCst);
+    HOB_LIST_TABLE_LEN.store(table.len(), Ordering::SeqCst);
+    HOB_LIST_TABLE_CRC.store(crate::crc32::hash(table), Ordering::SeqCst);
+
+    protect_hob_list_table(base, num_pages);
+
+    if first_install
+        && let Err(status) = EVENT_DB.create_event(
+            efi::EVT_NOTIFY_SIGNAL,
+            efi::TPL_CALLBACK,
+            Some(verify_hob_list_table_event),
+            None,
+            Some(efi::EVENT_GROUP_READY_TO_BOOT),
+        )
+    {
+        log::error!("Failed to register an event at Ready to Boot to verify the HOB list! Status {status:#X?}");
+    }
+
+    Ok(())
+}
+
+/// Appends an encoded HOB to the HOB list table, re-publishing the table.
+///
+/// The HOB list is copied to new pages with `hob` inserted before the End of HOB List HOB. The previous copy is not
+/// freed, as consumers may still hold pointers into it.
+pub fn append_hob(hob: &[u8], system_table: &mut EfiSystemTable) -> Result<(), EfiError> {
+    let base = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst);
+    if base == 0 {
+        return Err(EfiError::NotReady);
+    }
+    if !hob_list_table_intact() {
+        log::error!("Refusing to append to the HOB list table at {base:#X}, it was modified after it was installed!");
+        return Err(EfiError::CompromisedData);
+    }
+
+    // Safety: the HOB list table pages are allocated by install_hob_list_table and never freed.
+    let current = unsafe { slice::from_raw_parts(base as *const u8, HOB_LIST_TABLE_LEN.load(Ordering::SeqCst)) };
+    let end_offset = current.len() - size_of::<header::Hob>();
+
+    let mut hob_list = Vec::with_capacity(current.len() + hob.len());
+    hob_list.extend_from_slice(&current[..end_offset]);
+    hob_list.extend_from_slice(hob);
+    hob_list.extend_from_slice(&current[end_offset..]);
+
+    install_hob_list_table(&hob_list, system_table)
+}
+
+// Points the PHIT HOB's end of HOB list field at the End of HOB List HOB of the table at `base`.
+fn update_end_of_hob_list(table: &mut [u8], base: efi::PhysicalAddress) {
+    if table.len() < size_of::<PhaseHandoffInformationTable>() + size_of::<header::Hob>() {
+        return;
+    }
+
+    let phit = table.as_mut_ptr() as *mut PhaseHandoffInformationTable;
+    // Safety: the table is large enough to hold a PHIT HOB, which is read and written unaligned.
+    unsafe {
+        let mut handoff = phit.read_unaligned();
+        if handoff.header.r#type == hob::HANDOFF {
+            handoff.end_of_hob_list = base + (table.len() - size_of::<header::Hob>()) as u64;
+            phit.write_unaligned(handoff);
+        }
+    }
+}
+
+/// Produces the [HobProducer] service, which appends HOBs to the HOB list table.
+#[derive(IntoService)]
+#[service(dyn HobProducer)]
+pub(crate) struct CoreHobProducer;
+
+impl HobProducer for CoreHobProducer {
+    fn produce_hob(&self, hob_type: u16, body: &[u8]) -> Result<(), EfiError> {
+        let hob = encode_hob(hob_type, body)?;
+        let mut st = SYSTEM_TABLE.lock();
+        let st = st.as_mut().ok_or(EfiError::NotReady)?;
+        append_hob(&hob, st)?;
+        log::info!("Appended HOB of type {hob_type:#06X} and length {:#X} to the HOB list table.", hob.len());
+        Ok(())
+    }
+}
+
+// Maps the HOB list table pages read-only, preserving the existing cache attributes.
+fn protect_hob_list_table(base: efi::PhysicalAddress, num_pages: usize) {
+    let len = (num_pages * UEFI_PAGE_SIZE) as u64;
+    let (attributes, capabilities) = match dxe_services::core_get_memory_space_descriptor(base) {
+        Ok(desc) => (desc.attributes | efi::MEMORY_RO, desc.capabilities | efi::MEMORY_RO),
+        Err(status) => {
+            log::error!("Failed to find GCD desc for HOB list table {base:#X} with Status {status:#X?}");
+            return;
+        }
+    };
+
+    if let Err(status) = dxe_services::core_set_memory_space_capabilities(base, len, capabilities) {
+        log::error!("Failed to set GCD capabilities for HOB list table {base:#X} with Status {status:#X?}");
+    }
+
+    match dxe_services::core_set_memory_space_attributes(base, len, attributes) {
+        Ok(()) => log::info!("HOB list table at {base:#X} for len {len:#X} mapped read-only."),
+        Err(status) => {
+            log::error!("Failed to set GCD attributes for HOB list table {base:#X} with Status {status:#X?}")
+        }
+    }
+}
+
+// Returns true if the HOB list table still matches the CRC recorded when it was installed.
+fn hob_list_table_intact() -> bool {
+    let base = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst);
+    let len = HOB_LIST_TABLE_LEN.load(Ordering::SeqCst);
+    if base == 0 {
+        return true;
+    }
+
+    // Safety: the HOB list table pages are allocated by install_hob_list_table and never freed.
+    let hob_list = unsafe { slice::from_raw_parts(base as *const u8, len) };
+    crate::crc32::hash(hob_list) == HOB_LIST_TABLE_CRC.load(Ordering::SeqCst)
+}
+
+extern "efiapi" fn verify_hob_list_table_event(event: efi::Event, _context: *mut c_void) {
+    if hob_list_table_intact() {
+        log::info!("HOB list table integrity verified at Ready to Boot.");
+    } else {
+        log::error!(
+            "HOB list table at {:#X} was modified after it was installed!",
+            HOB_LIST_TABLE_BASE.load(Ordering::SeqCst)
+        );
+        debug_assert!(false, "HOB list table corrupted.");
+    }
+
+    if let Err(status) = EVENT_DB.close_event(event) {
+        log::error!("Failed to close the HOB list verification event: {status:?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    use crate::{systemtables::init_system_table, test_support};
+
+    fn with_locked_state<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
+        test_support::with_global_lock(|| {
+            HOB_LIST_TABLE_BASE.store(0, Ordering::SeqCst);
+            HOB_LIST_TABLE_LEN.store(0, Ordering::SeqCst);
+            HOB_LIST_TABLE_CRC.store(0, Ordering::SeqCst);
+
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::reset_allocators();
+                init_system_table();
+            }
+            f();
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_hob_list_table_is_installed_read_only() {
+        with_locked_state(|| {
+            let hob_list = [0x5a_u8; 0x1800];
+            install_hob_list_table(&hob_list, SYSTEM_TABLE.lock().as_mut().unwrap()).unwrap();
+
+            let base = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst);
+            assert_eq!(base % UEFI_PAGE_SIZE as u64, 0);
+            assert_eq!(unsafe { slice::from_raw_parts(base as *const u8, hob_list.len()) }, &hob_list);
+
+            let st = SYSTEM_TABLE.lock();
+            let system_table = st.as_ref().unwrap().system_table();
+            let tables = unsafe {
+                slice::from_raw_parts(system_table.configuration_table, system_table.number_of_table_entries)
+            };
+            assert!(tables.iter().any(|t| t.vendor_guid == guids::HOB_LIST && t.vendor_table as u64 == base));
+
+            let desc = dxe_services::core_get_memory_space_descriptor(base).unwrap();
+            assert_ne!(desc.attributes & efi::MEMORY_RO, 0);
+            let desc = dxe_services::core_get_memory_space_descriptor(base + UEFI_PAGE_SIZE as u64).unwrap();
+            assert_ne!(desc.attributes & efi::MEMORY_RO, 0);
+        });
+    }
+
+    #[test]
+    fn test_hob_list_table_corruption_is_detected() {
+        with_locked_state(|| {
+            assert!(hob_list_table_intact());
+
+            let hob_list = [0xa5_u8; 64];
+            install_hob_list_table(&hob_list, SYSTEM_TABLE.lock().as_mut().unwrap()).unwrap();
+            assert!(hob_list_table_intact());
+
+            // The host has no page tables backing the GCD attributes, so the write succeeds.
+            let base = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst);
+            unsafe { (base as *mut u8).add(10).write(0) };
+            assert!(!hob_list_table_intact());
+        });
+    }
+
+    #[test]
+    fn test_produced_hobs_are_appended_to_the_table() {
+        with_locked_state(|| {
+            assert_eq!(CoreHobProducer.produce_hob(hob::CPU, &[0; 8]), Err(EfiError::NotReady));
+
+            let physical_hob_list = test_support::build_test_hob_list(0x1000000);
+            let len = unsafe { patina_pi::hob::get_c_hob_list_size(physical_hob_list) };
+            let original = unsafe { slice::from_raw_parts(physical_hob_list as *const u8, len) };
+            install_hob_list_table(original, SYSTEM_TABLE.lock().as_mut().unwrap()).unwrap();
+            let old_base = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst);
+
+            let name =
+                efi::Guid::from_fields(0x39f62cce, 0x6825, 0x4669, 0xbb, 0x56, &[0x54, 0x1a, 0xba, 0x75, 0x3a, 0x07]);
+            patina::component::service::hob_producer::produce_guid_hob(&CoreHobProducer, &name, &[1, 2, 3]).unwrap();
+
+            let base = HOB_LIST_TABLE_BASE.load(Ordering::SeqCst);
+            assert_ne!(base, old_base);
+            assert_eq!(HOB_LIST_TABLE_LEN.load(Ordering::SeqCst), len + 32);
+            assert!(hob_list_table_intact());
+
+            let mut hob_list = hob::HobList::default();
+            hob_list.discover_hobs(base as *const c_void);
+            assert!(hob_list.iter().any(|hob| matches!(hob, hob::Hob::GuidHob(guid, data)
+                if guid.name == name && data[..3] == [1, 2, 3])));
+            let Some(hob::Hob::Handoff(handoff)) = hob_list.iter().next() else { panic!("PHIT HOB missing") };
+            assert_eq!(handoff.end_of_hob_list, base + (len + 32 - size_of::<header::Hob>()) as u64);
+
+            // A corrupted table is not re-published.
+            unsafe { (base as *mut u8).add(len).write(0xff) };
+            assert_eq!(CoreHobProducer.produce_hob(hob::CPU, &[0; 8]), Err(EfiError::CompromisedData));
+        });
+    }
+}
@@ -0,0 +1,130 @@
+//! Control Flow Protection
+//!
+//! Platforms can enable AArch64 pointer authentication of return addresses with [ControlFlowProtection]. It is only
+//! used if the processor supports it and the DXE core image itself advertises return address protection compatibility
+//! (CET_COMPAT) in its PE extended DLL characteristics, as core code runs with the protection in effect whenever it is
+//! called back from a protected image. On other architectures the config has no effect.
+//!
+//! Pointer authentication is enabled while a dispatched image runs, if its PE header advertises compatibility, and the
+//! previous state is restored when the image exits. It is applied per image, as incompatible code simply does not sign
+//! its return addresses.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina_internal_cpu::control_flow::{self, ControlFlowFeatures};
+use spin::RwLock;
+
+use crate::{image::with_dxe_core_pe_info, pecoff::UefiPeInfo};
+
+/// Hardware control flow protection to enable for the DXE core and compatible dispatched images.
+///
+/// No protection is enabled unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{ControlFlowProtection, Core};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ControlFlowProtection { pointer_authentication: true })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ControlFlowProtection {
+    /// Authenticate return addresses with AArch64 pointer authentication.
+    pub pointer_authentication: bool,
+}
+
+static ACTIVE: RwLock<ControlFlowFeatures> = RwLock::new(ControlFlowFeatures::NONE);
+
+fn image_compatibility(pe_info: &UefiPeInfo) -> ControlFlowFeatures {
+    ControlFlowFeatures { pointer_authentication: pe_info.cet_compat }
+}
+
+/// Activates the protections requested by `config` that are supported by the processor and the DXE core image.
+pub(crate) fn enable_control_flow_protection(config: &ControlFlowProtection) {
+    let requested = ControlFlowFeatures { pointer_authentication: config.pointer_authentication };
+    let supported = control_flow::supported();
+    let core_compatible = with_dxe_core_pe_info(image_compatibility).unwrap_or_default();
+    activate(requested, supported, core_compatible);
+}
+
+fn activate(requested: ControlFlowFeatures, supported: ControlFlowFeatures, core_compatible: ControlFlowFeatures) {
+    let active = requested.intersection(supported).intersection(core_compatible);
+    if requested.intersection(supported) != requested {
+        log::warn!("Control flow protections {requested:?} requested, but the processor only supports {supported:?}.");
+    }
+    if requested.intersection(core_compatible) != requested {
+        log::warn!(
+            "Control flow protections {requested:?} requested, but the DXE core is only compatible with {core_compatible:?}."
+        );
+    }
+
+    log::info!("Control flow protections active for compatible images: {active:?}");
+    *ACTIVE.write() = active;
+}
+
+/// Returns the protections to enable while the image described by `pe_info` runs.
+pub(crate) fn image_features(pe_info: &UefiPeInfo) -> ControlFlowFeatures {
+    ACTIVE.read().intersection(image_compatibility(pe_info))
+}
+
+/// Restores the protections that were enabled before an image was started.
+///
+/// ## Safety
+///
+/// `previous` must be the state that was enabled when the image was started, and control must be transferred back to
+/// the frame that started the image without returning through any frame of the image.
+pub(crate) unsafe fn restore(previous: ControlFlowFeatures) {
+    unsafe { control_flow::set_enabled(previous) }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use crate::test_support;
+
+    const ALL: ControlFlowFeatures = ControlFlowFeatures { pointer_authentication: true };
+
+    fn pe_info(cet_compat: bool) -> UefiPeInfo {
+        UefiPeInfo { cet_compat, ..Default::default() }
+    }
+
+    #[test]
+    fn test_active_protections_are_limited_by_processor_and_core() {
+        test_support::with_global_lock(|| {
+            activate(ALL, ALL, ControlFlowFeatures::NONE);
+            assert_eq!(*ACTIVE.read(), ControlFlowFeatures::NONE);
+
+            activate(ALL, ControlFlowFeatures::NONE, ALL);
+            assert_eq!(*ACTIVE.read(), ControlFlowFeatures::NONE);
+
+            activate(ALL, ALL, ALL);
+            assert_eq!(*ACTIVE.read(), ALL);
+
+            *ACTIVE.write() = ControlFlowFeatures::NONE;
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_incompatible_images_are_not_protected() {
+        test_support::with_global_lock(|| {
+            activate(ALL, ALL, ALL);
+            assert_eq!(image_features(&pe_info(true)), ALL);
+            assert_eq!(image_features(&pe_info(false)), ControlFlowFeatures::NONE);
+
+            *ACTIVE.write() = ControlFlowFeatures::NONE;
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! DXE Core Information
+//!
+//! Reports what was built into, and is active in, the DXE Core so that field images can be audited for the protections
+//! and features they contain. The report includes the build information, a digest of the boot configuration, the
+//! enabled cargo features, the active policies and the registered services.
+//!
+//! The build information is also embedded in the core image as a [BuildInfo] blob, so that a field image can be
+//! identified from the binary alone, and is available through the `build_info` debugger monitor command from the time
+//! memory is initialized. The git hash is taken from the `PATINA_BUILD_GIT_HASH` environment variable at build time,
+//! which the platform build should set to the revision of the platform repository.
+//!
+//! The report is published as a configuration table with [CORE_INFO_TABLE_GUID], and is available through the `core_info`
+//! debugger monitor command. The configuration table is a [CoreInfoTableHeader] followed by the report as NUL
+//! terminated UTF-8 text.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{string::String, vec::Vec};
+use core::{
+    ffi::c_void,
+    fmt::{self, Display},
+    mem::size_of,
+};
+
+use patina::error::EfiError;
+use r_efi::efi;
+
+use crate::{GCD, config_tables, dispatcher, systemtables};
+
+/// The GUID of the configuration table containing the DXE Core information.
+pub const CORE_INFO_TABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0xd34efe2e, 0x864f, 0x457e, 0xa2, 0x35, &[0xb6, 0xb1, 0x1d, 0x38, 0x09, 0xba]);
+
+/// Cargo features that affect the protections and behavior of the DXE Core, with the bit used for each in
+/// [CoreInfoTableHeader::features].
+const FEATURES: &[(&str, bool)] = &[
+    ("compatibility_mode_allowed", cfg!(feature = "compatibility_mode_allowed")),
+    ("symbol_map", cfg!(feature = "symbol_map")),
+];
+
+static REPORT: spin::Once<String> = spin::Once::new();
+
+/// Build information embedded in the DXE Core image.
+///
+/// Tooling can locate the blob by scanning the image for [BuildInfo::SIGNATURE]. Text fields are NUL padded UTF-8.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BuildInfo {
+    /// `PATBUILD`
+    pub signature: [u8; 8],
+    /// The version of the blob format.
+    pub version: u32,
+    /// A bitmask of the enabled cargo features, as in [CoreInfoTableHeader::features].
+    pub features: u32,
+    /// The version of the DXE Core crate.
+    pub crate_version: [u8; 16],
+    /// The git revision the platform was built from, or `unknown`.
+    pub git_hash: [u8; 48],
+    /// The version of rustc the core was built with.
+    pub rustc_version: [u8; 32],
+    /// The UTC time the core was built.
+    pub build_timestamp: [u8; 32],
+}
+
+impl BuildInfo {
+    /// The signature of the blob.
+    pub const SIGNATURE: [u8; 8] = *b"PATBUILD";
+    /// The current version of the blob format.
+    pub const VERSION: u32 = 1;
+
+    const fn new() -> Self {
+        let git_hash = match option_env!("PATINA_BUILD_GIT_HASH") {
+            Some(git_hash) => git_hash,
+            None => "unknown",
+        };
+        Self {
+            signature: Self::SIGNATURE,
+            version: Self::VERSION,
+            features: enabled_feature_bits(),
+            crate_version: padded(env!("CARGO_PKG_VERSION")),
+            git_hash: padded(git_hash),
+            rustc_version: padded(compile_time::rustc_version_str!()),
+            build_timestamp: padded(compile_time::datetime_str!()),
+        }
+    }
+}
+
+impl Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version: {}", text(&self.crate_version))?;
+        writeln!(f, "git_hash: {}", text(&self.git_hash))?;
+        writeln!(f, "rustc: {}", text(&self.rustc_version))?;
+        writeln!(f, "built: {}", text(&self.build_timestamp))
+    }
+}
+
+#[used]
+static BUILD_INFO: BuildInfo = BuildInfo::new();
+
+/// Returns the build information embedded in the core image.
+pub(crate) fn build_info() -> &'static BuildInfo {
+    &BUILD_INFO
+}
+
+// Copies `value` into a NUL padded field, truncating it if it does not fit.
+const fn padded<const N: usize>(value: &str) -> [u8; N] {
+    let bytes = value.as_bytes();
+    let mut field = [0; N];
+    let mut i = 0;
+    while i < bytes.len() && i < N {
+        field[i] = bytes[i];
+        i += 1;
+    }
+    field
+}
+
+// Returns the text in a NUL padded field.
+fn text(field: &[u8]) -> &str {
+    let len = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("<invalid>")
+}
+
+const fn enabled_feature_bits() -> u32 {
+    let mut bits = 0;
+    let mut i = 0;
+    while i < FEATURES.len() {
+        if FEATURES[i].1 {
+            bits |= 1 << i;
+        }
+        i += 1;
+    }
+    bits
+}
+
+/// The header of the DXE Core information configuration table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CoreInfoTableHeader {
+    /// `PCIN`
+    pub signature: u32,
+    /// The version of the table format.
+    pub version: u32,
+    /// The length of the table, including the header and report.
+    pub length: u32,
+    /// A bitmask of the enabled cargo features. Bit 0 is `compatibility_mode_allowed`, bit 1 is `symbol_map`.
+    pub features: u32,
+}
+
+impl CoreInfoTableHeader {
+    /// The signature of the table.
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"PCIN");
+    /// The current version of the table format.
+    pub const VERSION: u32 = 1;
+}
+
+/// A report of what is built into and active in the DXE Core.
+#[derive(Debug)]
+pub(crate) struct CoreInfo {
+    build: &'static BuildInfo,
+    config_digest: u32,
+    features: Vec<&'static str>,
+    policies: Vec<String>,
+    services: Vec<&'static str>,
+}
+
+impl CoreInfo {
+    /// Collects the core information, including the digest of the boot configuration and the given registered services.
+    pub(crate) fn collect(config_digest: u32, services: impl Iterator<Item = &'static str>) -> Self {
+        let mut policies = Vec::new();
+        policies.push(alloc::format!("dispatch_policy: {:?}", dispatcher::dispatch_policy()));
+        policies.push(alloc::format!("override_fvs: {:#x?}", dispatcher::override_fvs()));
+        policies.push(alloc::format!("prioritize_32_bit_memory: {}", GCD.is_32_bit_memory_prioritized()));
+
+        Self {
+            build: build_info(),
+            config_digest,
+            features: FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect(),
+            policies,
+            services: services.collect(),
+        }
+    }
+
+    fn feature_bits(&self) -> u32 {
+        FEATURES
+            .iter()
+            .enumerate()
+            .filter(|(_, (name, _))| self.features.contains(name))
+            .fold(0, |bits, (i, _)| bits | 1 << i)
+    }
+
+    /// Builds the configuration table for the report.
+    fn to_table(&self, report: &str) -> Vec<u8> {
+        let length = size_of::<CoreInfoTableHeader>() + report.len() + 1;
+        let header = CoreInfoTableHeader {
+            signature: CoreInfoTableHeader::SIGNATURE,
+            version: CoreInfoTableHeader::VERSION,
+            length: length as u32,
+            features: self.feature_bits(),
+        };
+
+        let mut table = Vec::with_capacity(length);
+        for field in [header.signature, header.version, header.length, header.features] {
+            table.extend_from_slice(&field.to_le_bytes());
+        }
+        table.extend_from_slice(report.as_bytes());
+        table.push(0);
+        table
+    }
+
+    /// Publishes the report as a configuration table and through the `core_info` monitor command.
+    pub(crate) fn publish(self) -> Result<(), EfiError> {
+        let report = REPORT.call_once(|| alloc::format!("{self}"));
+        log::info!("DXE Core Information:\n{report}");
+
+        let table = self.to_table(report).leak();
+        let mut st = systemtables::SYSTEM_TABLE.lock();
+        let st = st.as_mut().expect("System Table not initialized!");
+        config_tables::core_install_configuration_table(CORE_INFO_TABLE_GUID, table.as_mut_ptr() as *mut c_void, st)?;
+
+        patina_debugger::add_monitor_command("core_info", "Prints the DXE Core information", |_, out| {
+            let _ = out.write_str(REPORT.get().map_or("Core information not yet collected.", |r| r.as_str()));
+        });
+        Ok(())
+    }
+}
+
+impl Display for CoreInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.build)?;
+        writeln!(f, "config_digest: {:#010x}", self.config_digest)?;
+        writeln!(f, "features: {:?}", self.features)?;
+        writeln!(f, "policies:")?;
+        self.policies.iter().try_for_each(|policy| writeln!(f, "  {policy}"))?;
+        writeln!(f, "services:")?;
+        self.services.iter().try_for_each(|service| writeln!(f, "  {service}"))
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_core_info_table() {
+        static BUILD: BuildInfo = BuildInfo {
+            signature: BuildInfo::SIGNATURE,
+            version: BuildInfo::VERSION,
+            features: 1,
+            crate_version: padded("1.2.3"),
+            git_hash: padded("0123abcd"),
+            rustc_version: padded("1.90.0"),
+            build_timestamp: padded("2025-01-01T00:00:00Z"),
+        };
+        let info = CoreInfo {
+            build: &BUILD,
+            config_digest: 0x1234,
+            features: vec!["compatibility_mode_allowed"],
+            policies: vec![String::from("dispatch_policy: AllowAll")],
+            services: vec!["dyn Cpu"],
+        };
+        let report = alloc::format!("{info}");
+        assert_eq!(
+            report,
+            "version: 1.2.3\ngit_hash: 0123abcd\nrustc: 1.90.0\nbuilt: 2025-01-01T00:00:00Z\nconfig_digest: 0x00001234\nfeatures: [\"compatibility_mode_allowed\"]\npolicies:\n  dispatch_policy: AllowAll\nservices:\n  dyn Cpu\n"
+        );
+
+        let table = info.to_table(&report);
+        assert_eq!(&table[0..4], b"PCIN");
+        assert_eq!(u32::from_le_bytes(table[8..12].try_into().unwrap()) as usize, table.len());
+        assert_eq!(u32::from_le_bytes(table[12..16].try_into().unwrap()), 1);
+        assert_eq!(&table[16..table.len() - 1], report.as_bytes());
+        assert_eq!(table.last(), Some(&0));
+    }
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+        assert_eq!(info.signature, *b"PATBUILD");
+        assert_eq!(text(&info.crate_version), env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.features, CoreInfo::collect(0, core::iter::empty()).feature_bits());
+
+        // values that do not fit are truncated, and fields without a NUL are read in full.
+        let field: [u8; 4] = padded("abcdef");
+        assert_eq!(text(&field), "abcd");
+        let field: [u8; 8] = padded("ab");
+        assert_eq!(text(&field), "ab");
+    }
+}
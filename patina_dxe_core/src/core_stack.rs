@@ -0,0 +1,153 @@
+//! DXE Core Stack
+//!
+//! The DXE core is entered on the stack handed over by the HOB producer phase, which has no guard page, so a core
+//! stack overflow silently corrupts whatever lies below it. Once memory services are available, the core allocates
+//! its own stack with a guard page below it, like the stacks allocated for image entry points, and runs the rest of
+//! initialization and dispatch on it. An overflow then faults on the guard page.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use patina::{
+    base::{DEFAULT_CACHE_ATTR, UEFI_PAGE_SIZE},
+    error::EfiError,
+    uefi_size_to_pages,
+};
+use r_efi::efi;
+use uefi_corosensei::{
+    on_stack,
+    stack::{Stack, StackPointer},
+};
+
+use crate::{allocator::core_allocate_pages, dxe_services};
+
+/// The size of the stack the DXE core runs on, not including the guard page.
+pub const CORE_STACK_SIZE: usize = 0x100000;
+
+// The guard page at the bottom of the core stack, or 0 if no core stack has been allocated.
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy)]
+struct CoreStack {
+    limit: usize,
+}
+
+impl CoreStack {
+    fn get() -> Option<Self> {
+        match GUARD_PAGE.load(Ordering::SeqCst) {
+            0 => None,
+            guard_page => Some(CoreStack { limit: guard_page as usize + UEFI_PAGE_SIZE }),
+        }
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        (self.limit..self.limit + CORE_STACK_SIZE).contains(&address)
+    }
+}
+
+unsafe impl Stack for CoreStack {
+    fn base(&self) -> StackPointer {
+        //stack grows downward, so "base" is the highest address.
+        self.limit().checked_add(CORE_STACK_SIZE).expect("Stack base address overflow.")
+    }
+    fn limit(&self) -> StackPointer {
+        StackPointer::new(self.limit).expect("Stack pointer address was zero, but it should always be nonzero.")
+    }
+}
+
+/// Allocates the core stack and its guard page. If the allocation fails, the core continues on the handoff stack.
+pub(crate) fn allocate_core_stack() {
+    if GUARD_PAGE.load(Ordering::SeqCst) != 0 {
+        return;
+    }
+
+    let mut stack: efi::PhysicalAddress = 0;
+    let pages = uefi_size_to_pages!(CORE_STACK_SIZE) + 1;
+    if let Err(err) = core_allocate_pages(efi::ALLOCATE_ANY_PAGES, efi::BOOT_SERVICES_DATA, pages, &mut stack, None) {
+        log::error!("Failed to allocate the DXE core stack, continuing on the handoff stack: {err:?}");
+        return;
+    }
+
+    GUARD_PAGE.store(stack, Ordering::SeqCst);
+    protect_guard_page();
+    log::info!("DXE core stack at {:#x?} with guard page at {stack:#x?}", stack + UEFI_PAGE_SIZE as u64);
+}
+
+/// Marks the guard page below the core stack read protected.
+///
+/// This must be called again once paging is initialized, as mapping allocated memory into the new page table resets
+/// the guard page attributes.
+pub(crate) fn protect_guard_page() {
+    let guard_page = GUARD_PAGE.load(Ordering::SeqCst);
+    if guard_page == 0 {
+        return;
+    }
+
+    let attributes = match dxe_services::core_get_memory_space_descriptor(guard_page) {
+        Ok(descriptor) => descriptor.attributes,
+        Err(_) => DEFAULT_CACHE_ATTR,
+    };
+    match dxe_services::core_set_memory_space_attributes(guard_page, UEFI_PAGE_SIZE as u64, attributes | efi::MEMORY_RP)
+    {
+        // before paging is initialized, only the GCD is updated.
+        Ok(()) | Err(EfiError::NotReady) => {}
+        Err(err) => log::error!("Failed to set memory space attributes for core stack guard page: {err:?}"),
+    }
+}
+
+/// Runs `f` on the core stack. Runs `f` on the current stack if no core stack has been allocated, or if the core is
+/// already running on it.
+pub(crate) fn run_on_core_stack<R>(f: impl FnOnce() -> R) -> R {
+    let marker = 0u8;
+    match CoreStack::get() {
+        Some(stack) if !stack.contains(&marker as *const u8 as usize) => on_stack(stack, f),
+        _ => f(),
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use crate::test_support;
+
+    #[test]
+    fn test_core_runs_on_guarded_stack() {
+        test_support::with_global_lock(|| {
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::reset_allocators();
+            }
+            GUARD_PAGE.store(0, Ordering::SeqCst);
+
+            // without a core stack, the closure runs on the current stack.
+            assert!(run_on_core_stack(|| CoreStack::get().is_none()));
+
+            allocate_core_stack();
+            let stack = CoreStack::get().unwrap();
+            let guard_page = GUARD_PAGE.load(Ordering::SeqCst);
+            let attributes = dxe_services::core_get_memory_space_descriptor(guard_page).unwrap().attributes;
+            assert_ne!(attributes & efi::MEMORY_RP, 0);
+
+            let on_core_stack = run_on_core_stack(|| {
+                let marker = 0u8;
+                // nested calls stay on the same stack rather than starting over from its base.
+                let nested = run_on_core_stack(|| {
+                    let nested_marker = 0u8;
+                    (&nested_marker as *const u8 as usize) < (&marker as *const u8 as usize)
+                });
+                stack.contains(&marker as *const u8 as usize) && nested
+            });
+            assert!(on_core_stack);
+
+            GUARD_PAGE.store(0, Ordering::SeqCst);
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+//! CRC32 Calculation
+//!
+//! The core computes CRC32 (IEEE 802.3) checksums for the CalculateCrc32 boot service, the system table headers and
+//! the memory map key. The implementation is selected once at runtime from the features the processor implements:
+//! carry-less multiplication folding on x86_64 (PCLMULQDQ), the CRC32 instructions on AArch64, and a table driven
+//! implementation on everything else. [`Hasher`] computes a checksum over data supplied in pieces, and [`table_hash`]
+//! checksums an EFI table in place.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina_internal_cpu::features::{self, CpuFeatures};
+use r_efi::efi;
+use spin::Once;
+
+static FEATURES: Once<CpuFeatures> = Once::new();
+
+/// Returns the CRC32 of `data`.
+pub(crate) fn hash(data: &[u8]) -> u32 {
+    update(0, data)
+}
+
+/// Returns the CRC32 of `table`, an EFI table starting with an `efi::TableHeader`, as if its `crc32` field were zero.
+///
+/// The table is neither modified nor copied, so the checksum can be computed or verified in place.
+pub(crate) fn table_hash(table: &[u8]) -> u32 {
+    const CRC32_OFFSET: usize = core::mem::offset_of!(efi::TableHeader, crc32);
+    const CRC32_END: usize = CRC32_OFFSET + size_of::<u32>();
+    assert!(table.len() >= size_of::<efi::TableHeader>(), "table is smaller than its header");
+
+    let mut hasher = Hasher::new();
+    hasher.update(&table[..CRC32_OFFSET]);
+    hasher.update(&[0; size_of::<u32>()]);
+    hasher.update(&table[CRC32_END..]);
+    hasher.finalize()
+}
+
+/// Computes a CRC32 over data that is supplied in pieces.
+///
+/// The result is the same as [`hash`] over the concatenation of the pieces, without assembling them into one buffer.
+/// Each piece is hashed with the accelerated implementation, so larger pieces are faster.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Hasher {
+    crc: u32,
+}
+
+impl Hasher {
+    /// Creates a hasher for an empty input.
+    pub(crate) const fn new() -> Self {
+        Self { crc: 0 }
+    }
+
+    /// Adds `data` to the input.
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.crc = update(self.crc, data);
+    }
+
+    /// Returns the CRC32 of the input so far.
+    pub(crate) fn finalize(self) -> u32 {
+        self.crc
+    }
+}
+
+// Continues the CRC32 `crc` of some preceding data over `data` with the best implementation for the processor.
+fn update(crc: u32, data: &[u8]) -> u32 {
+    let features = FEATURES.call_once(features::detect);
+
+    #[cfg(target_arch = "x86_64")]
+    if features.carryless_multiply {
+        // Safety: the processor implements PCLMULQDQ and SSE4.1.
+        return unsafe { x64::update(crc, data) };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if features.crc32 {
+        // Safety: the processor implements the CRC32 instructions.
+        return unsafe { aarch64::update(crc, data) };
+    }
+
+    let _ = features;
+    update_scalar(crc, data)
+}
+
+// Continues the CRC32 `crc` of some preceding data over `data`.
+fn update_scalar(crc: u32, data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x64 {
+    //! CRC32 by folding with carry-less multiplication, as described in Intel's "Fast CRC Computation for Generic
+    //! Polynomials Using PCLMULQDQ Instruction", for the bit reflected IEEE 802.3 polynomial.
+    use core::arch::x86_64::{
+        __m128i, _mm_and_si128, _mm_clmulepi64_si128, _mm_cvtsi32_si128, _mm_extract_epi32, _mm_loadu_si128,
+        _mm_set_epi32, _mm_set_epi64x, _mm_srli_si128, _mm_xor_si128,
+    };
+
+    // Folding constants: x^(4*128+32) mod P, x^(4*128-32) mod P, x^(128+32) mod P, x^(128-32) mod P and x^64 mod P.
+    const K1: i64 = 0x1_5444_2bd4;
+    const K2: i64 = 0x1_c6e4_1596;
+    const K3: i64 = 0x1_7519_97d0;
+    const K4: i64 = 0x0_ccaa_009e;
+    const K5: i64 = 0x1_63cd_6124;
+    // The polynomial and its Barrett reduction constant, floor(x^64 / P).
+    const P_X: i64 = 0x1_db71_0641;
+    const U_PRIME: i64 = 0x1_f701_1641;
+
+    // Below this length, folding does not pay for its setup.
+    const MIN_FOLD_LEN: usize = 128;
+
+    /// Continues the CRC32 `crc` of some preceding data over `data`.
+    ///
+    /// ## Safety
+    ///
+    /// The processor must implement PCLMULQDQ and SSE4.1.
+    #[target_feature(enable = "pclmulqdq,sse4.1")]
+    pub(super) unsafe fn update(crc: u32, mut data: &[u8]) -> u32 {
+        if data.len() < MIN_FOLD_LEN {
+            return super::update_scalar(crc, data);
+        }
+
+        // fold 64 bytes at a time into four 128-bit accumulators, starting with the incoming CRC.
+        let mut x3 = _mm_xor_si128(next(&mut data), _mm_cvtsi32_si128(!crc as i32));
+        let mut x2 = next(&mut data);
+        let mut x1 = next(&mut data);
+        let mut x0 = next(&mut data);
+
+        let k1k2 = _mm_set_epi64x(K2, K1);
+        while data.len() >= 64 {
+            x3 = fold(x3, next(&mut data), k1k2);
+            x2 = fold(x2, next(&mut data), k1k2);
+            x1 = fold(x1, next(&mut data), k1k2);
+            x0 = fold(x0, next(&mut data), k1k2);
+        }
+
+        // fold the accumulators into one, and then the remaining 16 byte blocks into it.
+        let k3k4 = _mm_set_epi64x(K4, K3);
+        let mut x = fold(fold(fold(x3, x2, k3k4), x1, k3k4), x0, k3k4);
+        while data.len() >= 16 {
+            x = fold(x, next(&mut data), k3k4);
+        }
+
+        // reduce 128 bits to 64 bits.
+        let low_32 = _mm_set_epi32(0, 0, 0, !0);
+        let x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+        let x = _mm_xor_si128(
+            _mm_clmulepi64_si128(_mm_and_si128(x, low_32), _mm_set_epi64x(0, K5), 0x00),
+            _mm_srli_si128(x, 4),
+        );
+
+        // Barrett reduction from 64 bits to 32 bits. For bit reflected input, the result is in the upper half.
+        let pu = _mm_set_epi64x(U_PRIME, P_X);
+        let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low_32), pu, 0x10);
+        let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low_32), pu, 0x00);
+        let crc = !(_mm_extract_epi32(_mm_xor_si128(x, t2), 1) as u32);
+
+        super::update_scalar(crc, data)
+    }
+
+    #[target_feature(enable = "pclmulqdq,sse4.1")]
+    fn fold(accumulator: __m128i, block: __m128i, keys: __m128i) -> __m128i {
+        let low = _mm_clmulepi64_si128(accumulator, keys, 0x00);
+        let high = _mm_clmulepi64_si128(accumulator, keys, 0x11);
+        _mm_xor_si128(_mm_xor_si128(block, low), high)
+    }
+
+    #[target_feature(enable = "pclmulqdq,sse4.1")]
+    fn next(data: &mut &[u8]) -> __m128i {
+        let (block, rest) = data.split_at(16);
+        *data = rest;
+        // Safety: block is 16 bytes long, and the load is unaligned.
+        unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    //! CRC32 with the AArch64 CRC32 instructions, 8 bytes at a time.
+    use core::arch::aarch64::{__crc32b, __crc32d};
+
+    /// Continues the CRC32 `crc` of some preceding data over `data`.
+    ///
+    /// ## Safety
+    ///
+    /// The processor must implement the CRC32 instructions.
+    #[target_feature(enable = "crc")]
+    pub(super) unsafe fn update(crc: u32, data: &[u8]) -> u32 {
+        let mut crc = !crc;
+        let mut words = data.chunks_exact(8);
+        for word in &mut words {
+            crc = __crc32d(crc, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+        for &byte in words.remainder() {
+            crc = __crc32b(crc, byte);
+        }
+        !crc
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use alloc::vec::Vec;
+
+    #[test]
+    fn hash_should_match_the_reference_implementation() {
+        let data = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<_>>();
+        for len in [0, 1, 15, 16, 127, 128, 129, 191, 192, 255, 1000, 4096] {
+            for offset in [0, 3] {
+                let slice = &data[offset..offset + len.min(data.len() - offset)];
+                assert_eq!(hash(slice), crc32fast::hash(slice), "length {len} offset {offset}");
+            }
+        }
+        assert_eq!(hash(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn hasher_should_match_hash_over_the_concatenated_pieces() {
+        let data = (0..2048u32).map(|i| (i.wrapping_mul(40503) >> 7) as u8).collect::<Vec<_>>();
+        for split in [0, 1, 16, 100, 128, 1000, 2047, 2048] {
+            let mut hasher = Hasher::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finalize(), crc32fast::hash(&data), "split at {split}");
+        }
+
+        let mut hasher = Hasher::default();
+        for chunk in data.chunks(37) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), crc32fast::hash(&data));
+        assert_eq!(Hasher::new().finalize(), 0);
+    }
+
+    #[test]
+    fn table_hash_should_ignore_the_crc32_field() {
+        let mut table = [0xA5u8; 96];
+        let crc32_offset = core::mem::offset_of!(efi::TableHeader, crc32);
+
+        let mut zeroed = table;
+        zeroed[crc32_offset..crc32_offset + 4].fill(0);
+        let expected = crc32fast::hash(&zeroed);
+
+        assert_eq!(table_hash(&table), expected);
+        table[crc32_offset..crc32_offset + 4].copy_from_slice(&expected.to_le_bytes());
+        assert_eq!(table_hash(&table), expected);
+
+        // changes outside of the crc32 field change the hash.
+        table[0] = 0;
+        assert_ne!(table_hash(&table), expected);
+    }
+
+    #[test]
+    #[should_panic = "table is smaller than its header"]
+    fn table_hash_should_reject_a_table_smaller_than_its_header() {
+        table_hash(&[0; 8]);
+    }
+
+    #[test]
+    fn scalar_update_should_continue_a_crc() {
+        let data = [0x5Au8; 300];
+        assert_eq!(update_scalar(update_scalar(0, &data[..100]), &data[100..]), crc32fast::hash(&data));
+
+        #[cfg(target_arch = "x86_64")]
+        if features::detect().carryless_multiply {
+            assert_eq!(unsafe { x64::update(update_scalar(0, &data[..7]), &data[7..]) }, crc32fast::hash(&data));
+        }
+    }
+}
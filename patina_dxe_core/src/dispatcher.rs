@@ -11,10 +11,15 @@ use alloc::{
     collections::{BTreeMap, BTreeSet},
     vec::Vec,
 };
-use core::{cmp::Ordering, ffi::c_void};
-use mu_rust_helpers::{function, guid::guid_fmt};
+use core::{
+    cmp::Ordering,
+    ffi::c_void,
+    mem::{size_of, size_of_val},
+};
+use mu_rust_helpers::function;
 use patina::{
-    component::service::Service,
+    OwnedGuid,
+    component::{boot_config::BootConfig, service::Service},
     error::EfiError,
     performance::{
         logging::{perf_function_begin, perf_function_end},
@@ -22,7 +27,8 @@ use patina::{
     },
 };
 use patina_ffs::{
-    section::{Section, SectionExtractor},
+    file::FileRef,
+    section::{Section, SectionExtractor, SectionIterator},
     volume::VolumeRef,
 };
 use patina_internal_depex::{AssociatedDependency, Depex, Opcode};
@@ -35,8 +41,10 @@ use mu_rust_helpers::guid::CALLER_ID;
 use crate::{
     decompress::CoreExtractor,
     events::EVENT_DB,
+    file_prehash,
     fv::{core_install_firmware_volume, device_path_bytes_for_fv_file},
-    image::{core_load_image, core_start_image},
+    guid_names,
+    image::{core_load_image, core_start_image, execute_in_place_enabled},
     protocol_db::DXE_CORE_HANDLE,
     protocols::PROTOCOL_DB,
     tpl_lock::TplMutex,
@@ -76,10 +84,29 @@ struct PendingDriver {
     file_name: efi::Guid,
     depex: Option<Depex>,
     pe32: Section,
+    pe32_in_fv: Option<&'static [u8]>,
     image_handle: Option<efi::Handle>,
     security_status: efi::Status,
 }
 
+impl PendingDriver {
+    // the image data passed to LoadImage for this driver.
+    fn image_data(&self) -> Result<&[u8], EfiError> {
+        match self.pe32_in_fv {
+            Some(data) => Ok(data),
+            None => Ok(self.pe32.try_content_as_slice()?),
+        }
+    }
+}
+
+impl Drop for PendingDriver {
+    fn drop(&mut self) {
+        if let Ok(data) = self.image_data() {
+            file_prehash::forget_file(data);
+        }
+    }
+}
+
 struct PendingFirmwareVolumeImage {
     parent_fv_handle: efi::Handle,
     file_name: efi::Guid,
@@ -129,6 +156,143 @@ impl Ord for OrdGuid {
     }
 }
 
+/// The GUID of the HOB used to override the [`DispatchPolicy`] configured by the platform.
+///
+/// The HOB data is a `u32` mode (`0` for a denylist, `1` for an allowlist, `2` to allow all), a `u32` count, and `count`
+/// file GUIDs.
+pub const DISPATCH_POLICY_HOB_GUID: efi::Guid =
+    efi::Guid::from_fields(0x0b3e1c9d, 0x5e7a, 0x4f3b, 0x8a, 0x61, &[0x2d, 0x94, 0x7c, 0x1e, 0x50, 0xb8]);
+
+/// A policy restricting which drivers are dispatched, by FFS file GUID.
+///
+/// The policy only applies to driver files; firmware volume image files are always processed so that the drivers they
+/// contain are subject to the policy. A platform configures the policy with [`Core::with_config`](crate::Core), and
+/// it can be overridden without rebuilding the firmware by producing a HOB with [`DISPATCH_POLICY_HOB_GUID`].
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, DispatchPolicy};
+/// # use r_efi::efi;
+/// # let physical_hob_list = core::ptr::null();
+/// # const KNOWN_BAD_DRIVER: efi::Guid = efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]);
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(DispatchPolicy::Deny(vec![KNOWN_BAD_DRIVER]))
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DispatchPolicy {
+    /// Dispatch all drivers.
+    #[default]
+    AllowAll,
+    /// Dispatch all drivers except those with the listed file GUIDs.
+    Deny(Vec<efi::Guid>),
+    /// Dispatch only the drivers with the listed file GUIDs.
+    Allow(Vec<efi::Guid>),
+}
+
+impl DispatchPolicy {
+    const DENY: u32 = 0;
+    const ALLOW: u32 = 1;
+    const ALLOW_ALL: u32 = 2;
+
+    /// Returns whether the driver with the given file GUID may be dispatched.
+    pub fn is_allowed(&self, file_name: &efi::Guid) -> bool {
+        match self {
+            DispatchPolicy::AllowAll => true,
+            DispatchPolicy::Deny(list) => !list.contains(file_name),
+            DispatchPolicy::Allow(list) => list.contains(file_name),
+        }
+    }
+
+    /// Parses the data of a dispatch policy HOB, returning `None` if it is malformed.
+    pub fn from_hob_data(data: &[u8]) -> Option<Self> {
+        let mode = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+        let count = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?) as usize;
+        let guids = data.get(8..)?;
+        if guids.len() != count.checked_mul(size_of::<efi::Guid>())? {
+            return None;
+        }
+        let list = guids.chunks_exact(size_of::<efi::Guid>()).map(|g| efi::Guid::from_bytes(g.try_into().unwrap()));
+        match mode {
+            Self::DENY => Some(DispatchPolicy::Deny(list.collect())),
+            Self::ALLOW => Some(DispatchPolicy::Allow(list.collect())),
+            Self::ALLOW_ALL if count == 0 => Some(DispatchPolicy::AllowAll),
+            _ => None,
+        }
+    }
+
+    /// Serializes the policy into the data of a dispatch policy HOB.
+    pub fn to_hob_data(&self) -> Vec<u8> {
+        let (mode, list) = match self {
+            DispatchPolicy::AllowAll => (Self::ALLOW_ALL, &[][..]),
+            DispatchPolicy::Deny(list) => (Self::DENY, list.as_slice()),
+            DispatchPolicy::Allow(list) => (Self::ALLOW, list.as_slice()),
+        };
+        let mut data = Vec::with_capacity(8 + size_of_val(list));
+        data.extend_from_slice(&mode.to_le_bytes());
+        data.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        list.iter().for_each(|guid| data.extend_from_slice(guid.as_bytes()));
+        data
+    }
+}
+
+impl BootConfig for DispatchPolicy {
+    const CONFIG_GUID: OwnedGuid =
+        OwnedGuid::from_fields(0x481d8323, 0xf975, 0x4f23, 0xaa, 0x3a, [0x7f, 0x16, 0x22, 0xbb, 0xf8, 0xce]);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_hob_data()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::from_hob_data(bytes)
+    }
+}
+
+/// Firmware volumes whose drivers take precedence over drivers with the same file GUID in other firmware volumes.
+///
+/// Override firmware volumes are identified by their base address, and allow a hotfix or developer firmware volume to
+/// substitute drivers without rebuilding the main flash image. A driver in another firmware volume is not dispatched if
+/// an override firmware volume contains a driver with the same file GUID. The override firmware volume must be
+/// installed before the substituted driver is dispatched, which is always the case for firmware volumes described by
+/// HOBs.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, OverrideFirmwareVolumes};
+/// # let physical_hob_list = core::ptr::null();
+/// # let hotfix_fv_base = 0x1000_0000;
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(OverrideFirmwareVolumes(vec![hotfix_fv_base]))
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverrideFirmwareVolumes(pub Vec<u64>);
+
+impl BootConfig for OverrideFirmwareVolumes {
+    const CONFIG_GUID: OwnedGuid =
+        OwnedGuid::from_fields(0xb0f336e3, 0xfd01, 0x4798, 0x9f, 0x5a, [0x57, 0x32, 0x4d, 0x61, 0x3e, 0x12]);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|base_address| base_address.to_le_bytes()).collect()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(size_of::<u64>()) {
+            return None;
+        }
+        Some(Self(bytes.chunks_exact(size_of::<u64>()).map(|b| u64::from_le_bytes(b.try_into().unwrap())).collect()))
+    }
+}
+
 #[derive(Default)]
 struct DispatcherContext {
     executing: bool,
@@ -140,6 +304,9 @@ struct DispatcherContext {
     associated_after: BTreeMap<OrdGuid, Vec<PendingDriver>>,
     processed_fvs: BTreeSet<efi::Handle>,
     section_extractor: CoreExtractor,
+    policy: DispatchPolicy,
+    override_fvs: BTreeSet<u64>,
+    overridden_files: BTreeSet<OrdGuid>,
 }
 
 impl DispatcherContext {
@@ -154,6 +321,23 @@ impl DispatcherContext {
             associated_after: BTreeMap::new(),
             processed_fvs: BTreeSet::new(),
             section_extractor: CoreExtractor::new(),
+            policy: DispatchPolicy::AllowAll,
+            override_fvs: BTreeSet::new(),
+            overridden_files: BTreeSet::new(),
+        }
+    }
+}
+
+impl DispatcherContext {
+    /// Removes a driver that has not yet been dispatched, so that it can be substituted.
+    fn remove_pending_driver(&mut self, file_name: &efi::Guid) {
+        let matches = |driver: &PendingDriver| OrdGuid(driver.file_name) == OrdGuid(*file_name);
+        let mut removed = self.pending_drivers.extract_if(.., |d| matches(d)).count();
+        for drivers in self.associated_before.values_mut().chain(self.associated_after.values_mut()) {
+            removed += drivers.extract_if(.., |d| matches(d)).count();
+        }
+        if removed > 0 {
+            log::info!("Driver {:?} overridden by an override firmware volume.", guid_names::named(file_name));
         }
     }
 }
@@ -177,7 +361,7 @@ pub fn dispatch() -> Result<bool, EfiError> {
         let driver_candidates: Vec<_> = dispatcher.pending_drivers.drain(..).collect();
         let mut scheduled_driver_candidates = Vec::new();
         for mut candidate in driver_candidates {
-            log::trace!("Evaluating depex for candidate: {:?}", guid_fmt!(candidate.file_name));
+            log::trace!("Evaluating depex for candidate: {:?}", guid_names::named(&candidate.file_name));
             let depex_satisfied = match candidate.depex {
                 Some(ref mut depex) => depex.eval(&PROTOCOL_DB.registered_protocols()),
                 None => dispatcher.arch_protocols_available,
@@ -216,9 +400,12 @@ pub fn dispatch() -> Result<bool, EfiError> {
     let mut dispatch_attempted = false;
     for mut driver in scheduled {
         if driver.image_handle.is_none() {
-            log::info!("Loading file: {:?}", guid_fmt!(driver.file_name));
-            let data = driver.pe32.try_content_as_slice()?;
-            match core_load_image(false, DXE_CORE_HANDLE, driver.device_path, Some(data)) {
+            log::info!("Loading file: {:?}", guid_names::named(&driver.file_name));
+            let data = driver.image_data()?;
+            let result = core_load_image(false, DXE_CORE_HANDLE, driver.device_path, Some(data));
+            // the image has been measured and verified by now, so its digest is no longer needed.
+            file_prehash::forget_file(data);
+            match result {
                 Ok((image_handle, security_status)) => {
                     driver.image_handle = Some(image_handle);
                     driver.security_status = match security_status {
@@ -241,7 +428,7 @@ pub fn dispatch() -> Result<bool, EfiError> {
                 efi::Status::SECURITY_VIOLATION => {
                     log::info!(
                         "Deferring driver: {:?} due to security status: {:x?}",
-                        guid_fmt!(driver.file_name),
+                        guid_names::named(&driver.file_name),
                         efi::Status::SECURITY_VIOLATION
                     );
                     DISPATCHER_CONTEXT.lock().pending_drivers.push(driver);
@@ -249,7 +436,7 @@ pub fn dispatch() -> Result<bool, EfiError> {
                 unexpected_status => {
                     log::info!(
                         "Dropping driver: {:?} due to security status: {:x?}",
-                        guid_fmt!(driver.file_name),
+                        guid_names::named(&driver.file_name),
                         unexpected_status
                     );
                 }
@@ -283,7 +470,7 @@ pub fn dispatch() -> Result<bool, EfiError> {
                     } else {
                         log::warn!(
                             "couldn't install firmware volume image {:?}: {:?}",
-                            guid_fmt!(candidate.file_name),
+                            guid_names::named(&candidate.file_name),
                             res
                         );
                     }
@@ -328,6 +515,11 @@ fn add_fv_handles(new_handles: Vec<efi::Handle>) -> Result<(), EfiError> {
                 continue;
             }
 
+            let is_override_fv = dispatcher.override_fvs.contains(&fv_address);
+            if is_override_fv {
+                log::info!("Firmware volume at {fv_address:#x} overrides drivers in other firmware volumes.");
+            }
+
             let fv_device_path =
                 PROTOCOL_DB.get_interface_for_handle(handle, efi::protocols::device_path::PROTOCOL_GUID);
             let fv_device_path =
@@ -349,6 +541,20 @@ fn add_fv_handles(new_handles: Vec<efi::Handle>) -> Result<(), EfiError> {
                 if file.file_type_raw() == ffs::file::raw::r#type::DRIVER {
                     let file = file.clone();
                     let file_name = file.name();
+                    if !dispatcher.policy.is_allowed(&file_name) {
+                        log::info!("Driver {:?} excluded by dispatch policy.", guid_names::named(&file_name));
+                        continue;
+                    }
+                    if is_override_fv {
+                        dispatcher.overridden_files.insert(OrdGuid(file_name));
+                        dispatcher.remove_pending_driver(&file_name);
+                    } else if dispatcher.overridden_files.contains(&OrdGuid(file_name)) {
+                        log::info!(
+                            "Driver {:?} overridden by an override firmware volume.",
+                            guid_names::named(&file_name)
+                        );
+                        continue;
+                    }
                     let sections = file.sections_with_extractor(&dispatcher.section_extractor)?;
 
                     let depex = sections
@@ -407,17 +613,22 @@ fn add_fv_handles(new_handles: Vec<efi::Handle>) -> Result<(), EfiError> {
                             .map(|full_path| Box::into_raw(full_path) as *mut efi::protocols::device_path::Protocol)
                             .unwrap_or(fv_device_path);
 
-                        dispatcher.pending_drivers.push(PendingDriver {
+                        let driver = PendingDriver {
                             file_name,
                             firmware_volume_handle: handle,
                             pe32: pe32_section,
+                            pe32_in_fv: if execute_in_place_enabled() { fv_resident_pe32(&file) } else { None },
                             device_path: full_device_path_for_file,
                             depex,
                             image_handle: None,
                             security_status: efi::Status::NOT_READY,
-                        });
+                        };
+                        if let Ok(data) = driver.image_data() {
+                            file_prehash::queue_file(data);
+                        }
+                        dispatcher.pending_drivers.push(driver);
                     } else {
-                        log::warn!("driver {:?} does not contain a PE32 section.", guid_fmt!(file_name));
+                        log::warn!("driver {:?} does not contain a PE32 section.", guid_names::named(&file_name));
                     }
                 }
                 if file.file_type_raw() == ffs::file::raw::r#type::FIRMWARE_VOLUME_IMAGE {
@@ -450,7 +661,7 @@ fn add_fv_handles(new_handles: Vec<efi::Handle>) -> Result<(), EfiError> {
                     } else {
                         log::warn!(
                             "firmware volume image {:?} does not contain a firmware volume image section.",
-                            guid_fmt!(file_name)
+                            guid_names::named(&file_name)
                         );
                     }
                 }
@@ -460,6 +671,24 @@ fn add_fv_handles(new_handles: Vec<efi::Handle>) -> Result<(), EfiError> {
     Ok(())
 }
 
+// Returns the PE32 section contents of `file` where they are mapped in the FV, so that the image can be executed in
+// place. Returns None if the PE32 section is encapsulated, e.g. compressed, as it is not mapped in the FV then.
+fn fv_resident_pe32(file: &FileRef<'_>) -> Option<&'static [u8]> {
+    let content = file.content();
+    let mut offset = 0;
+    for section in SectionIterator::new(content) {
+        let header = section.ok()?.header().clone();
+        if header.section_type() == Some(ffs::section::Type::Pe32) {
+            let data = content.get(offset + header.content_offset()..offset + header.total_section_size())?;
+            // Safety: the memory backing the FV is essentially permanent while the dispatcher is running (see
+            // add_fv_handles).
+            return Some(unsafe { core::slice::from_raw_parts(data.as_ptr(), data.len()) });
+        }
+        offset += header.total_section_size().next_multiple_of(4);
+    }
+    None
+}
+
 pub fn core_schedule(handle: efi::Handle, file: &efi::Guid) -> Result<(), EfiError> {
     let mut dispatcher = DISPATCHER_CONTEXT.lock();
     for driver in dispatcher.pending_drivers.iter_mut() {
@@ -518,9 +747,25 @@ pub fn register_section_extractor(extractor: Service<dyn SectionExtractor>) {
     DISPATCHER_CONTEXT.lock().section_extractor.set_extractor(extractor);
 }
 
+pub fn set_dispatch_policy(policy: DispatchPolicy) {
+    DISPATCHER_CONTEXT.lock().policy = policy;
+}
+
+pub fn register_override_fv(base_address: u64) {
+    DISPATCHER_CONTEXT.lock().override_fvs.insert(base_address);
+}
+
+pub fn dispatch_policy() -> DispatchPolicy {
+    DISPATCHER_CONTEXT.lock().policy.clone()
+}
+
+pub fn override_fvs() -> Vec<u64> {
+    DISPATCHER_CONTEXT.lock().override_fvs.iter().copied().collect()
+}
+
 pub fn display_discovered_not_dispatched() {
     for driver in &DISPATCHER_CONTEXT.lock().pending_drivers {
-        log::warn!("Driver {:?} found but not dispatched.", guid_fmt!(driver.file_name));
+        log::warn!("Driver {:?} found but not dispatched.", guid_names::named(&driver.file_name));
     }
 }
 
@@ -680,6 +925,121 @@ mod tests {
         let _dropped_fv = unsafe { Box::from_raw(fv_raw) };
     }
 
+    #[test]
+    fn test_add_fv_handle_with_dispatch_policy() {
+        set_logger();
+        let mut file = File::open(test_collateral!("DXEFV.Fv")).unwrap();
+        let mut fv: Vec<u8> = Vec::new();
+        file.read_to_end(&mut fv).expect("failed to read test file");
+        let fv = fv.into_boxed_slice();
+        let fv_raw = Box::into_raw(fv);
+
+        with_locked_state(|| {
+            // Safety: fv is leaked to ensure it is not freed and remains valid for the duration of the program.
+            let handle =
+                unsafe { crate::fv::core_install_firmware_volume(fv_raw.expose_provenance() as u64, None).unwrap() };
+
+            add_fv_handles(vec![handle]).expect("Failed to add FV handle");
+            let all_drivers: Vec<_> =
+                DISPATCHER_CONTEXT.lock().pending_drivers.iter().map(|driver| driver.file_name).collect();
+
+            let denied = vec![all_drivers[0], all_drivers[1]];
+            *DISPATCHER_CONTEXT.lock() = DispatcherContext::new();
+            set_dispatch_policy(DispatchPolicy::Deny(denied.clone()));
+            add_fv_handles(vec![handle]).expect("Failed to add FV handle");
+            let pending: Vec<_> =
+                DISPATCHER_CONTEXT.lock().pending_drivers.iter().map(|driver| driver.file_name).collect();
+            assert_eq!(pending.len(), all_drivers.len() - denied.len());
+            assert!(!pending.iter().any(|name| denied.contains(name)));
+
+            *DISPATCHER_CONTEXT.lock() = DispatcherContext::new();
+            set_dispatch_policy(DispatchPolicy::Allow(vec![all_drivers[2]]));
+            add_fv_handles(vec![handle]).expect("Failed to add FV handle");
+            let pending: Vec<_> =
+                DISPATCHER_CONTEXT.lock().pending_drivers.iter().map(|driver| driver.file_name).collect();
+            assert_eq!(pending, vec![all_drivers[2]]);
+        });
+
+        let _dropped_fv = unsafe { Box::from_raw(fv_raw) };
+    }
+
+    #[test]
+    fn test_add_fv_handle_with_override_fv() {
+        set_logger();
+        let mut file = File::open(test_collateral!("DXEFV.Fv")).unwrap();
+        let mut fv: Vec<u8> = Vec::new();
+        file.read_to_end(&mut fv).expect("failed to read test file");
+        let main_fv_raw = Box::into_raw(fv.clone().into_boxed_slice());
+        let override_fv_raw = Box::into_raw(fv.into_boxed_slice());
+
+        with_locked_state(|| {
+            const DRIVERS_IN_DXEFV: usize = 130;
+            let main_address = main_fv_raw.expose_provenance() as u64;
+            let override_address = override_fv_raw.expose_provenance() as u64;
+            // Safety: the FVs are leaked to ensure they are not freed and remain valid for the duration of the program.
+            let main_handle = unsafe { crate::fv::core_install_firmware_volume(main_address, None).unwrap() };
+            let override_handle = unsafe { crate::fv::core_install_firmware_volume(override_address, None).unwrap() };
+
+            // The override FV replaces drivers that were already discovered, and suppresses drivers discovered later.
+            for handles in [[main_handle, override_handle], [override_handle, main_handle]] {
+                *DISPATCHER_CONTEXT.lock() = DispatcherContext::new();
+                register_override_fv(override_address);
+                add_fv_handles(handles.to_vec()).expect("Failed to add FV handles");
+
+                let dispatcher = DISPATCHER_CONTEXT.lock();
+                assert_eq!(dispatcher.pending_drivers.len(), DRIVERS_IN_DXEFV);
+                assert!(dispatcher.pending_drivers.iter().all(|d| d.firmware_volume_handle == override_handle));
+            }
+
+            // Without an override, drivers from both FVs are discovered.
+            *DISPATCHER_CONTEXT.lock() = DispatcherContext::new();
+            add_fv_handles(vec![main_handle, override_handle]).expect("Failed to add FV handles");
+            assert_eq!(DISPATCHER_CONTEXT.lock().pending_drivers.len(), 2 * DRIVERS_IN_DXEFV);
+        });
+
+        let _dropped_fv = unsafe { Box::from_raw(main_fv_raw) };
+        let _dropped_fv = unsafe { Box::from_raw(override_fv_raw) };
+    }
+
+    #[test]
+    fn test_dispatch_policy_from_hob_data() {
+        let guid1 = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+        let guid2 = efi::Guid::from_fields(7, 8, 9, 10, 11, &[12; 6]);
+        let hob_data = |mode: u32, count: u32, guids: &[efi::Guid]| {
+            let mut data = [mode.to_le_bytes(), count.to_le_bytes()].concat();
+            guids.iter().for_each(|g| data.extend_from_slice(g.as_bytes()));
+            data
+        };
+
+        let deny = DispatchPolicy::from_hob_data(&hob_data(0, 2, &[guid1, guid2])).unwrap();
+        assert_eq!(deny, DispatchPolicy::Deny(vec![guid1, guid2]));
+        assert!(!deny.is_allowed(&guid1));
+        assert!(deny.is_allowed(&efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6])));
+
+        let allow = DispatchPolicy::from_hob_data(&hob_data(1, 1, &[guid2])).unwrap();
+        assert!(allow.is_allowed(&guid2));
+        assert!(!allow.is_allowed(&guid1));
+        assert!(DispatchPolicy::AllowAll.is_allowed(&guid1));
+
+        assert_eq!(DispatchPolicy::from_hob_data(&hob_data(1, 0, &[])), Some(DispatchPolicy::Allow(vec![])));
+        assert_eq!(DispatchPolicy::from_hob_data(&hob_data(2, 1, &[guid1])), None);
+        assert_eq!(DispatchPolicy::from_hob_data(&hob_data(0, 2, &[guid1])), None);
+        assert_eq!(DispatchPolicy::from_hob_data(&hob_data(0, u32::MAX, &[guid1])), None);
+        assert_eq!(DispatchPolicy::from_hob_data(&[0; 6]), None);
+    }
+
+    #[test]
+    fn test_dispatch_boot_config_round_trip() {
+        let guid = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+        for policy in [DispatchPolicy::AllowAll, DispatchPolicy::Deny(vec![guid]), DispatchPolicy::Allow(vec![guid])] {
+            assert_eq!(DispatchPolicy::from_bytes(&policy.to_bytes()), Some(policy));
+        }
+
+        let overrides = OverrideFirmwareVolumes(vec![0x1000, 0xFFFF_0000_0000]);
+        assert_eq!(OverrideFirmwareVolumes::from_bytes(&overrides.to_bytes()), Some(overrides));
+        assert_eq!(OverrideFirmwareVolumes::from_bytes(&[0; 7]), None);
+    }
+
     #[test]
     fn test_add_fv_handle_with_invalid_handle() {
         set_logger();
@@ -887,6 +1247,31 @@ mod tests {
         let _dropped_fv = unsafe { Box::from_raw(fv_raw) };
     }
 
+    #[test]
+    fn test_fv_resident_pe32_points_into_the_fv() {
+        let mut file = File::open(test_collateral!("DXEFV.Fv")).unwrap();
+        let mut fv: Vec<u8> = Vec::new();
+        file.read_to_end(&mut fv).expect("failed to read test file");
+        let fv_range = fv.as_ptr_range();
+
+        let mut resident = 0;
+        for file in VolumeRef::new(&fv).unwrap().files() {
+            let file = file.unwrap();
+            if file.file_type_raw() != ffs::file::raw::r#type::DRIVER {
+                continue;
+            }
+            let Some(data) = fv_resident_pe32(&file) else {
+                continue;
+            };
+            let pe32 =
+                file.sections().unwrap().into_iter().find(|x| x.section_type() == Some(ffs::section::Type::Pe32));
+            assert_eq!(data, pe32.unwrap().try_content_as_slice().unwrap());
+            assert!(fv_range.contains(&data.as_ptr()));
+            resident += 1;
+        }
+        assert!(resident > 0);
+    }
+
     #[test]
     fn test_core_schedule() {
         set_logger();
@@ -6,9 +6,13 @@
 //!
 //! SPDX-License-Identifier: Apache-2.0
 //!
-use alloc::{collections::BTreeMap, collections::BTreeSet, vec::Vec};
-use core::ptr::NonNull;
+use alloc::{collections::BTreeSet, string::String, vec, vec::Vec};
+use core::{ffi::c_void, ptr::NonNull};
 use patina::{
+    component::service::{
+        IntoService,
+        driver_diagnostics::{DiagnosticResult, DiagnosticType, DriverDiagnostics},
+    },
     error::EfiError,
     performance::{
         logging::{
@@ -22,7 +26,7 @@ use patina_internal_device_path::{concat_device_path_to_boxed_slice, copy_device
 
 use r_efi::efi;
 
-use crate::protocols::PROTOCOL_DB;
+use crate::{allocator::core_free_pool, protocols::PROTOCOL_DB};
 
 fn get_bindings_for_handles(handles: Vec<efi::Handle>) -> Vec<*mut efi::protocols::driver_binding::Protocol> {
     handles
@@ -36,8 +40,30 @@ fn get_bindings_for_handles(handles: Vec<efi::Handle>) -> Vec<*mut efi::protocol
         .collect()
 }
 
+// returns the driver bindings produced by each of the given images, in the order of the images. Overrides name the
+// image handle of a driver, which may install its driver bindings on other handles, so this includes the driver
+// binding on the image handle itself followed by every binding whose image_handle is the image, highest version first.
+fn get_bindings_for_images(
+    image_handles: Vec<efi::Handle>,
+    all_bindings: &[*mut efi::protocols::driver_binding::Protocol],
+) -> Vec<*mut efi::protocols::driver_binding::Protocol> {
+    let mut bindings = Vec::new();
+    for image_handle in image_handles {
+        let image_bindings = get_bindings_for_handles(vec![image_handle]).into_iter().chain(
+            all_bindings.iter().copied().filter(|binding| unsafe { (*(*binding)).image_handle } == image_handle),
+        );
+        for binding in image_bindings {
+            if !bindings.contains(&binding) {
+                bindings.push(binding);
+            }
+        }
+    }
+    bindings
+}
+
 fn get_platform_driver_override_bindings(
     controller_handle: efi::Handle,
+    all_bindings: &[*mut efi::protocols::driver_binding::Protocol],
 ) -> Vec<*mut efi::protocols::driver_binding::Protocol> {
     let driver_override_protocol = match PROTOCOL_DB
         .locate_protocol(efi::protocols::platform_driver_override::PROTOCOL_GUID)
@@ -62,19 +88,14 @@ fn get_platform_driver_override_bindings(
         driver_overrides.push(driver_image_handle);
     }
 
-    get_bindings_for_handles(driver_overrides)
+    get_bindings_for_images(driver_overrides, all_bindings)
 }
 
 fn get_family_override_bindings() -> Vec<*mut efi::protocols::driver_binding::Protocol> {
-    let driver_binding_handles = match PROTOCOL_DB.locate_handles(Some(efi::protocols::driver_binding::PROTOCOL_GUID)) {
-        Err(_) => return Vec::new(),
-        Ok(handles) => handles,
-    };
-
-    let mut driver_override_map: BTreeMap<u32, efi::Handle> = BTreeMap::new();
+    let mut driver_overrides: Vec<(u32, efi::Handle)> = Vec::new();
 
-    // insert all the handles that have DRIVER_FAMILY_OVERRIDE_PROTOCOL on them into a sorted map
-    for handle in driver_binding_handles {
+    // collect all the handles that have DRIVER_FAMILY_OVERRIDE_PROTOCOL on them along with their versions
+    for handle in PROTOCOL_DB.handles_with::<efi::protocols::driver_binding::Protocol>() {
         match PROTOCOL_DB.get_interface_for_handle(handle, efi::protocols::driver_family_override::PROTOCOL_GUID) {
             Ok(protocol) => {
                 let driver_override_protocol = unsafe {
@@ -83,18 +104,20 @@ fn get_family_override_bindings() -> Vec<*mut efi::protocols::driver_binding::Pr
                         .expect("bad protocol ptr")
                 };
                 let version = (driver_override_protocol.get_version)(driver_override_protocol);
-                driver_override_map.insert(version, handle);
+                driver_overrides.push((version, handle));
             }
             Err(_) => continue,
         }
     }
 
-    //return the driver bindings for the values from the map in reverse order (highest versions first)
-    get_bindings_for_handles(driver_override_map.into_values().rev().collect())
+    //return the driver bindings highest versions first; drivers with the same version keep their handle order
+    driver_overrides.sort_by(|(a, _), (b, _)| b.cmp(a));
+    get_bindings_for_handles(driver_overrides.into_iter().map(|(_, handle)| handle).collect())
 }
 
 fn get_bus_specific_override_bindings(
     controller_handle: efi::Handle,
+    all_bindings: &[*mut efi::protocols::driver_binding::Protocol],
 ) -> Vec<*mut efi::protocols::driver_binding::Protocol> {
     let bus_specific_override_protocol = match PROTOCOL_DB
         .get_interface_for_handle(controller_handle, efi::protocols::bus_specific_driver_override::PROTOCOL_GUID)
@@ -120,17 +143,17 @@ fn get_bus_specific_override_bindings(
         bus_overrides.push(driver_image_handle);
     }
 
-    get_bindings_for_handles(bus_overrides)
+    get_bindings_for_images(bus_overrides, all_bindings)
 }
 
 fn get_all_driver_bindings() -> Vec<*mut efi::protocols::driver_binding::Protocol> {
-    let mut driver_bindings = match PROTOCOL_DB.locate_handles(Some(efi::protocols::driver_binding::PROTOCOL_GUID)) {
-        Err(_) => return Vec::new(),
-        Ok(handles) if handles.is_empty() => return Vec::new(),
-        Ok(handles) => get_bindings_for_handles(handles),
-    };
+    let mut driver_bindings: Vec<_> = PROTOCOL_DB
+        .interfaces_with::<efi::protocols::driver_binding::Protocol>()
+        .map(|(_, driver_binding)| driver_binding)
+        .collect();
 
-    driver_bindings.sort_unstable_by(|a, b| unsafe { (*(*b)).version.cmp(&(*(*a)).version) });
+    //highest versions first; bindings with the same version keep their handle order
+    driver_bindings.sort_by(|a, b| unsafe { (*(*b)).version.cmp(&(*(*a)).version) });
 
     driver_bindings
 }
@@ -189,13 +212,15 @@ fn core_connect_single_controller(
 ) -> Result<(), EfiError> {
     PROTOCOL_DB.validate_handle(controller_handle)?;
 
+    let all_bindings = get_all_driver_bindings();
+
     //The following sources for driver instances are considered per UEFI Spec 2.10 section 7.3.12:
     //1. Context Override
     let mut driver_candidates = Vec::new();
-    driver_candidates.extend(get_bindings_for_handles(driver_handles));
+    driver_candidates.extend(get_bindings_for_images(driver_handles, &all_bindings));
 
     //2. Platform Driver Override
-    let mut platform_override_drivers = get_platform_driver_override_bindings(controller_handle);
+    let mut platform_override_drivers = get_platform_driver_override_bindings(controller_handle, &all_bindings);
     platform_override_drivers.retain(|x| !driver_candidates.contains(x));
     driver_candidates.append(&mut platform_override_drivers);
 
@@ -205,20 +230,21 @@ fn core_connect_single_controller(
     driver_candidates.append(&mut family_override_drivers);
 
     //4. Bus Specific Driver Override
-    let mut bus_override_drivers = get_bus_specific_override_bindings(controller_handle);
+    let mut bus_override_drivers = get_bus_specific_override_bindings(controller_handle, &all_bindings);
     bus_override_drivers.retain(|x| !driver_candidates.contains(x));
     driver_candidates.append(&mut bus_override_drivers);
 
     //5. Driver Binding Search
-    let mut driver_bindings = get_all_driver_bindings();
+    let mut driver_bindings = all_bindings;
     driver_bindings.retain(|x| !driver_candidates.contains(x));
     driver_candidates.append(&mut driver_bindings);
 
-    //loop until no more drivers can be started on handle.
+    //loop until no more drivers can be started on handle. Starting a driver may change which of the remaining drivers
+    //support the controller, so the search restarts from the highest priority driver after each one is started.
     let mut one_started = false;
     loop {
-        let mut started_drivers = Vec::new();
-        for driver_binding_interface in driver_candidates.clone() {
+        let mut started_driver = None;
+        for (index, &driver_binding_interface) in driver_candidates.iter().enumerate() {
             let driver_binding = unsafe { &mut *(driver_binding_interface) };
             let device_path = remaining_device_path.or(Some(core::ptr::null_mut())).expect("must be some");
 
@@ -237,7 +263,7 @@ fn core_connect_single_controller(
                         create_performance_measurement,
                     );
 
-                    started_drivers.push(driver_binding_interface);
+                    started_driver = Some(index);
 
                     perf_driver_binding_start_begin(
                         driver_binding.driver_binding_handle,
@@ -256,6 +282,7 @@ fn core_connect_single_controller(
                         controller_handle,
                         create_performance_measurement,
                     );
+                    break;
                 }
                 _ => {
                     perf_driver_binding_support_end(
@@ -267,10 +294,10 @@ fn core_connect_single_controller(
                 }
             }
         }
-        if started_drivers.is_empty() {
-            break;
+        match started_driver {
+            Some(index) => _ = driver_candidates.remove(index),
+            None => break,
         }
-        driver_candidates.retain(|x| !started_drivers.contains(x));
     }
 
     if one_started {
@@ -287,6 +314,31 @@ fn core_connect_single_controller(
     Err(EfiError::NotFound)
 }
 
+// returns the driver binding handles of the drivers that have the controller open BY_DRIVER, without duplicates.
+fn get_drivers_managing_controller(controller_handle: efi::Handle) -> Vec<efi::Handle> {
+    let mut drivers_managing_controller: Vec<efi::Handle> =
+        match PROTOCOL_DB.get_open_protocol_information(controller_handle) {
+            Ok(info) => info
+                .iter()
+                .flat_map(|(_guid, open_info)| {
+                    open_info.iter().filter_map(|x| {
+                        if (x.attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0 {
+                            Some(x.agent_handle.expect("BY_DRIVER usage must have an agent handle"))
+                        } else {
+                            None
+                        }
+                    })
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+    // remove duplicates but preserve ordering.
+    let mut driver_set = BTreeSet::new();
+    drivers_managing_controller.retain(|x| driver_set.insert(*x));
+    drivers_managing_controller
+}
+
 /// Connects a controller to drivers
 ///
 /// This function matches the behavior of EFI_BOOT_SERVICES.ConnectController() API in the UEFI spec 2.10 section
@@ -402,27 +454,7 @@ pub unsafe fn core_disconnect_controller(
     }
 
     // determine which driver_handles should be stopped.
-    let mut drivers_managing_controller = {
-        match PROTOCOL_DB.get_open_protocol_information(controller_handle) {
-            Ok(info) => info
-                .iter()
-                .flat_map(|(_guid, open_info)| {
-                    open_info.iter().filter_map(|x| {
-                        if (x.attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0 {
-                            Some(x.agent_handle.expect("BY_DRIVER usage must have an agent handle"))
-                        } else {
-                            None
-                        }
-                    })
-                })
-                .collect(),
-            Err(_) => Vec::new(),
-        }
-    };
-
-    // remove duplicates but preserve ordering.
-    let mut driver_set = BTreeSet::new();
-    drivers_managing_controller.retain(|x| driver_set.insert(*x));
+    let mut drivers_managing_controller = get_drivers_managing_controller(controller_handle);
 
     // if the driver image was specified, only disconnect that one (if it is actually managing it)
     if let Some(driver) = driver_image_handle {
@@ -526,6 +558,102 @@ extern "efiapi" fn disconnect_controller(
     }
 }
 
+/// The core implementation of the [DriverDiagnostics] service.
+#[derive(IntoService)]
+#[service(dyn DriverDiagnostics)]
+pub(crate) struct CoreDriverDiagnostics;
+
+impl DriverDiagnostics for CoreDriverDiagnostics {
+    fn run_diagnostics(
+        &self,
+        controller_handle: efi::Handle,
+        child_handle: Option<efi::Handle>,
+        diagnostic_type: DiagnosticType,
+        language: &str,
+    ) -> Result<Vec<DiagnosticResult>, EfiError> {
+        PROTOCOL_DB.validate_handle(controller_handle)?;
+        if let Some(handle) = child_handle {
+            PROTOCOL_DB.validate_handle(handle)?;
+        }
+
+        // the language is passed to the drivers as a null-terminated ASCII string.
+        if !language.is_ascii() || language.contains('\0') {
+            return Err(EfiError::InvalidParameter);
+        }
+        let mut language: Vec<u8> = language.bytes().chain(core::iter::once(0)).collect();
+
+        let results: Vec<DiagnosticResult> = get_drivers_managing_controller(controller_handle)
+            .into_iter()
+            .filter_map(|driver_handle| {
+                let protocol = PROTOCOL_DB
+                    .get_interface_for_handle(driver_handle, efi::protocols::driver_diagnostics2::PROTOCOL_GUID)
+                    .ok()? as *mut efi::protocols::driver_diagnostics2::Protocol;
+                Some(run_driver_diagnostics(
+                    driver_handle,
+                    protocol,
+                    controller_handle,
+                    child_handle,
+                    diagnostic_type,
+                    language.as_mut_ptr() as *mut efi::Char8,
+                ))
+            })
+            .collect();
+
+        if results.is_empty() {
+            return Err(EfiError::NotFound);
+        }
+        Ok(results)
+    }
+}
+
+// runs the diagnostics of one driver and takes ownership of the message buffer that the driver returns.
+fn run_driver_diagnostics(
+    driver_handle: efi::Handle,
+    protocol: *mut efi::protocols::driver_diagnostics2::Protocol,
+    controller_handle: efi::Handle,
+    child_handle: Option<efi::Handle>,
+    diagnostic_type: DiagnosticType,
+    language: *mut efi::Char8,
+) -> DiagnosticResult {
+    let mut error_type: *mut efi::Guid = core::ptr::null_mut();
+    let mut buffer_size: usize = 0;
+    let mut buffer: *mut efi::Char16 = core::ptr::null_mut();
+
+    // SAFETY: the protocol is identified by its GUID on the driver handle and should be a valid pointer to an
+    //         EFI_DRIVER_DIAGNOSTICS2_PROTOCOL structure.
+    let status = unsafe {
+        ((*protocol).run_diagnostics)(
+            protocol,
+            controller_handle,
+            child_handle.unwrap_or(core::ptr::null_mut()),
+            diagnostic_type.into(),
+            language,
+            &mut error_type,
+            &mut buffer_size,
+            &mut buffer,
+        )
+    };
+
+    // SAFETY: the error type is owned by the driver and is only read here.
+    let error_type = unsafe { error_type.as_ref() }.copied();
+
+    let message = if buffer.is_null() {
+        None
+    } else {
+        // SAFETY: the driver returns a buffer of buffer_size bytes that it allocated from pool and that the caller
+        //         must free.
+        let buffer_slice = unsafe { core::slice::from_raw_parts(buffer, buffer_size / size_of::<efi::Char16>()) };
+        let end = buffer_slice.iter().position(|&c| c == 0).unwrap_or(buffer_slice.len());
+        let message = String::from_utf16_lossy(&buffer_slice[..end]);
+        if let Err(err) = core_free_pool(buffer as *mut c_void) {
+            log::error!("Failed to free driver diagnostics buffer: {err:?}");
+        }
+        Some(message)
+    };
+
+    DiagnosticResult { driver_handle, status, error_type, message }
+}
+
 pub fn init_driver_services(bs: &mut efi::BootServices) {
     bs.connect_controller = connect_controller;
     bs.disconnect_controller = disconnect_controller;
@@ -813,7 +941,7 @@ mod tests {
             CALL_COUNT.store(0, Ordering::SeqCst);
 
             // Test the function
-            let bindings = get_platform_driver_override_bindings(controller_handle);
+            let bindings = get_platform_driver_override_bindings(controller_handle, &[]);
 
             // Verify results
             assert_eq!(CALL_COUNT.load(Ordering::SeqCst), 1, "Should call get_driver once and break on failure");
@@ -978,6 +1106,74 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_get_family_override_bindings_keeps_drivers_with_the_same_version() {
+        with_locked_state(|| {
+            let family_override =
+                Box::new(efi::protocols::driver_family_override::Protocol { get_version: mock_get_version_100 });
+            let family_override_ptr = Box::into_raw(family_override) as *mut core::ffi::c_void;
+
+            for (version, handle) in [(10, 0x1 as efi::Handle), (20, 0x2 as efi::Handle)] {
+                let binding_ptr = Box::into_raw(create_default_driver_binding(version, handle)) as *mut c_void;
+                PROTOCOL_DB
+                    .install_protocol_interface(
+                        Some(handle),
+                        efi::protocols::driver_binding::PROTOCOL_GUID,
+                        binding_ptr,
+                    )
+                    .unwrap();
+                PROTOCOL_DB
+                    .install_protocol_interface(
+                        Some(handle),
+                        efi::protocols::driver_family_override::PROTOCOL_GUID,
+                        family_override_ptr,
+                    )
+                    .unwrap();
+            }
+
+            let bindings = get_family_override_bindings();
+            assert_eq!(bindings.len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_get_bindings_for_images_includes_bindings_on_other_handles() {
+        with_locked_state(|| {
+            // the image installs two driver bindings on handles other than its image handle.
+            let mut binding1 = create_default_driver_binding(10, 0x1 as efi::Handle);
+            let image_handle = binding1.image_handle;
+            let mut binding2 = create_default_driver_binding(20, 0x2 as efi::Handle);
+            binding2.image_handle = image_handle;
+            let other_binding = create_default_driver_binding(30, 0x3 as efi::Handle);
+
+            for (binding, handle) in [(binding1.as_mut(), 0x1), (binding2.as_mut(), 0x2)] {
+                PROTOCOL_DB
+                    .install_protocol_interface(
+                        Some(handle as efi::Handle),
+                        efi::protocols::driver_binding::PROTOCOL_GUID,
+                        binding as *mut _ as *mut c_void,
+                    )
+                    .unwrap();
+            }
+            let other_binding_ptr = Box::into_raw(other_binding);
+            PROTOCOL_DB
+                .install_protocol_interface(
+                    Some(0x3 as efi::Handle),
+                    efi::protocols::driver_binding::PROTOCOL_GUID,
+                    other_binding_ptr as *mut c_void,
+                )
+                .unwrap();
+
+            let all_bindings = get_all_driver_bindings();
+            let bindings = get_bindings_for_images(vec![image_handle], &all_bindings);
+            assert_eq!(bindings, vec![binding2.as_mut() as *mut _, binding1.as_mut() as *mut _]);
+
+            // a binding on the image handle itself comes first, and bindings are not repeated.
+            let bindings = get_bindings_for_images(vec![0x3 as efi::Handle, 0x3 as efi::Handle], &all_bindings);
+            assert_eq!(bindings, vec![other_binding_ptr]);
+        });
+    }
+
     #[test]
     fn test_get_all_driver_bindings() {
         with_locked_state(|| {
@@ -1177,6 +1373,66 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_core_connect_single_controller_restarts_search_after_start() {
+        static START_ORDER: std::sync::Mutex<Vec<u32>> = std::sync::Mutex::new(Vec::new());
+
+        extern "efiapi" fn record_start(
+            this: *mut efi::protocols::driver_binding::Protocol,
+            _controller_handle: efi::Handle,
+            _remaining_device_path: *mut efi::protocols::device_path::Protocol,
+        ) -> efi::Status {
+            START_ORDER.lock().unwrap().push(unsafe { (*this).version });
+            efi::Status::SUCCESS
+        }
+
+        // supports the controller only once the version 20 driver has started on it.
+        extern "efiapi" fn supported_after_version_20(
+            _this: *mut efi::protocols::driver_binding::Protocol,
+            _controller_handle: efi::Handle,
+            _remaining_device_path: *mut efi::protocols::device_path::Protocol,
+        ) -> efi::Status {
+            if START_ORDER.lock().unwrap().contains(&20) { efi::Status::SUCCESS } else { efi::Status::UNSUPPORTED }
+        }
+
+        with_locked_state(|| {
+            START_ORDER.lock().unwrap().clear();
+            let (controller_handle, _) = PROTOCOL_DB
+                .install_protocol_interface(
+                    None,
+                    efi::protocols::device_path::PROTOCOL_GUID,
+                    0x1111 as *mut core::ffi::c_void,
+                )
+                .unwrap();
+
+            let bindings = [
+                create_driver_binding(
+                    30,
+                    0x1 as efi::Handle,
+                    supported_after_version_20,
+                    record_start,
+                    mock_stop_success,
+                ),
+                create_driver_binding(20, 0x2 as efi::Handle, mock_supported_success, record_start, mock_stop_success),
+                create_driver_binding(10, 0x3 as efi::Handle, mock_supported_success, record_start, mock_stop_success),
+            ];
+            for binding in bindings {
+                let handle = binding.driver_binding_handle;
+                PROTOCOL_DB
+                    .install_protocol_interface(
+                        Some(handle),
+                        efi::protocols::driver_binding::PROTOCOL_GUID,
+                        Box::into_raw(binding) as *mut c_void,
+                    )
+                    .unwrap();
+            }
+
+            // the version 30 driver is tried again, and started, before the lower priority version 10 driver.
+            core_connect_single_controller(controller_handle, Vec::new(), None).unwrap();
+            assert_eq!(*START_ORDER.lock().unwrap(), vec![20, 30, 10]);
+        });
+    }
+
     #[test]
     fn test_core_connect_controller() {
         with_locked_state(|| {
@@ -1773,6 +2029,109 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_run_diagnostics_on_managing_drivers() {
+        const ERROR_TYPE: efi::Guid =
+            efi::Guid::from_fields(0x9a1c3a0e, 0x5f0a, 0x4c36, 0x8e, 0x4e, &[0x1b, 0x58, 0x2f, 0x8d, 0x6a, 0x11]);
+
+        extern "efiapi" fn mock_run_diagnostics(
+            _this: *mut efi::protocols::driver_diagnostics2::Protocol,
+            _controller_handle: efi::Handle,
+            child_handle: efi::Handle,
+            diagnostic_type: efi::protocols::driver_diagnostics2::Type,
+            language: *mut efi::Char8,
+            error_type: *mut *mut efi::Guid,
+            buffer_size: *mut usize,
+            buffer: *mut *mut efi::Char16,
+        ) -> efi::Status {
+            assert!(child_handle.is_null());
+            assert_eq!(diagnostic_type, efi::protocols::driver_diagnostics2::TYPE_EXTENDED);
+            assert_eq!(unsafe { core::ffi::CStr::from_ptr(language as *const _) }, c"en-US");
+
+            let message: Vec<u16> = "Bad sector\0".encode_utf16().collect();
+            let size = message.len() * size_of::<efi::Char16>();
+            let message_buffer = crate::allocator::core_allocate_pool(efi::BOOT_SERVICES_DATA, size).unwrap();
+            unsafe {
+                core::ptr::copy_nonoverlapping(message.as_ptr(), message_buffer as *mut u16, message.len());
+                *error_type = &ERROR_TYPE as *const _ as *mut _;
+                *buffer_size = size;
+                *buffer = message_buffer as *mut efi::Char16;
+            }
+            efi::Status::DEVICE_ERROR
+        }
+
+        test_support::with_global_lock(|| {
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::init_test_protocol_db();
+            }
+
+            let (controller_handle, _) = PROTOCOL_DB
+                .install_protocol_interface(None, efi::protocols::device_path::PROTOCOL_GUID, 0x1111 as *mut c_void)
+                .unwrap();
+
+            // no driver manages the controller yet.
+            let result =
+                CoreDriverDiagnostics.run_diagnostics(controller_handle, None, DiagnosticType::Extended, "en-US");
+            assert_eq!(result, Err(EfiError::NotFound));
+
+            // two drivers manage the controller, and only one of them supports diagnostics.
+            let diagnostics = Box::new(efi::protocols::driver_diagnostics2::Protocol {
+                run_diagnostics: mock_run_diagnostics,
+                supported_languages: c"en-US".as_ptr() as *mut efi::Char8,
+            });
+            let (diagnostics_driver, _) = PROTOCOL_DB
+                .install_protocol_interface(
+                    None,
+                    efi::protocols::driver_diagnostics2::PROTOCOL_GUID,
+                    Box::into_raw(diagnostics) as *mut c_void,
+                )
+                .unwrap();
+            let (other_driver, _) = PROTOCOL_DB
+                .install_protocol_interface(None, efi::protocols::device_path::PROTOCOL_GUID, 0x2222 as *mut c_void)
+                .unwrap();
+            PROTOCOL_DB
+                .install_protocol_interface(
+                    Some(controller_handle),
+                    efi::protocols::block_io::PROTOCOL_GUID,
+                    0x3333 as *mut c_void,
+                )
+                .unwrap();
+            for (driver, protocol) in [
+                (diagnostics_driver, efi::protocols::device_path::PROTOCOL_GUID),
+                (other_driver, efi::protocols::block_io::PROTOCOL_GUID),
+            ] {
+                PROTOCOL_DB
+                    .add_protocol_usage(
+                        controller_handle,
+                        protocol,
+                        Some(driver),
+                        Some(controller_handle),
+                        efi::OPEN_PROTOCOL_BY_DRIVER,
+                    )
+                    .unwrap();
+            }
+
+            let results = CoreDriverDiagnostics
+                .run_diagnostics(controller_handle, None, DiagnosticType::Extended, "en-US")
+                .unwrap();
+            assert_eq!(
+                results,
+                vec![DiagnosticResult {
+                    driver_handle: diagnostics_driver,
+                    status: efi::Status::DEVICE_ERROR,
+                    error_type: Some(ERROR_TYPE),
+                    message: Some(String::from("Bad sector")),
+                }]
+            );
+
+            let result =
+                CoreDriverDiagnostics.run_diagnostics(0x1234 as efi::Handle, None, DiagnosticType::Standard, "en-US");
+            assert_eq!(result, Err(EfiError::InvalidParameter));
+        })
+        .unwrap();
+    }
+
     #[test]
     fn test_init_driver_services() {
         // Create dummy function pointers to use for initialization
@@ -12,7 +12,13 @@ use core::{
     mem,
     slice::{self, from_raw_parts},
 };
-use patina::error::EfiError;
+use patina::{
+    component::service::{
+        IntoService,
+        io_space::{IoAllocateType, IoSpace},
+    },
+    error::EfiError,
+};
 use patina_ffs::volume::VolumeRef;
 
 use patina_pi::dxe_services;
@@ -24,7 +30,7 @@ use crate::{
     config_tables,
     dispatcher::{core_dispatcher, core_schedule, core_trust},
     fv::core_install_firmware_volume,
-    gcd,
+    gcd, mmio_placement,
     systemtables::EfiSystemTable,
 };
 
@@ -76,7 +82,7 @@ extern "efiapi" fn allocate_memory_space(
         _ => return efi::Status::INVALID_PARAMETER,
     };
 
-    let result = GCD.allocate_memory_space(
+    let result = mmio_placement::allocate_memory_space(
         allocate_type,
         gcd_memory_type,
         alignment,
@@ -170,6 +176,21 @@ pub fn core_set_memory_space_attributes(
     }
 }
 
+/// Sets attributes on several `(base_address, length, attributes)` memory space ranges with a single update of the GCD
+/// and the page table. See [core_set_memory_space_attributes].
+pub fn core_set_memory_space_attributes_multi(ranges: &[(efi::PhysicalAddress, u64, u64)]) -> Result<(), EfiError> {
+    let ranges = ranges
+        .iter()
+        .map(|&(base_address, length, attributes)| (base_address as usize, length as usize, attributes))
+        .collect::<Vec<_>>();
+    match GCD.set_memory_space_attributes_multi(&ranges) {
+        // as in core_set_memory_space_attributes, NotReady only means the page table is not installed yet, unless
+        // the GCD itself is uninitialized.
+        Err(EfiError::NotReady) if GCD.is_ready() => Ok(()),
+        result => result,
+    }
+}
+
 extern "efiapi" fn set_memory_space_capabilities(
     base_address: efi::PhysicalAddress,
     length: u64,
@@ -314,28 +335,21 @@ extern "efiapi" fn get_io_space_descriptor(
         return efi::Status::INVALID_PARAMETER;
     }
 
-    //Note: this would be more efficient if it was done in the GCD; rather than retrieving all the descriptors and
-    //searching them here. It is done this way for simplicity - it can be optimized if it proves too slow.
-
-    //allocate an empty vector with enough space for all the descriptors with some padding (in the event)
-    //that extra descriptors come into being after creation but before usage.
-    let mut descriptors: Vec<dxe_services::IoSpaceDescriptor> = Vec::with_capacity(GCD.io_descriptor_count() + 10);
-    let result = GCD.get_io_descriptors(&mut descriptors);
-
-    if let Err(err) = result {
-        return efi::Status::from(err);
-    }
-
-    let target_descriptor =
-        descriptors.iter().find(|x| (x.base_address <= base_address) && (base_address < (x.base_address + x.length)));
-
-    if let Some(target_descriptor) = target_descriptor {
+    match core_get_io_space_descriptor(base_address) {
+        Err(err) => return err.into(),
+        Ok(target_descriptor) =>
         // Safety: caller must ensure that descriptor is a valid pointer. It is null-checked above.
-        unsafe { descriptor.write_unaligned(*target_descriptor) };
-        efi::Status::SUCCESS
-    } else {
-        efi::Status::NOT_FOUND
+        unsafe {
+            descriptor.write_unaligned(target_descriptor);
+        },
     }
+    efi::Status::SUCCESS
+}
+
+pub fn core_get_io_space_descriptor(
+    base_address: efi::PhysicalAddress,
+) -> Result<dxe_services::IoSpaceDescriptor, EfiError> {
+    GCD.get_io_descriptor_for_address(base_address)
 }
 
 extern "efiapi" fn get_io_space_map(
@@ -345,14 +359,11 @@ extern "efiapi" fn get_io_space_map(
     if number_of_descriptors.is_null() || io_space_map.is_null() {
         return efi::Status::INVALID_PARAMETER;
     }
-    //allocate an empty vector with enough space for all the descriptors with some padding (in the event)
-    //that extra descriptors come into being after creation but before usage.
-    let mut descriptors: Vec<dxe_services::IoSpaceDescriptor> = Vec::with_capacity(GCD.io_descriptor_count() + 10);
-    let result = GCD.get_io_descriptors(&mut descriptors);
 
-    if let Err(err) = result {
-        return efi::Status::from(err);
-    }
+    let descriptors = match core_get_io_space_map() {
+        Ok(descriptors) => descriptors,
+        Err(err) => return efi::Status::from(err),
+    };
 
     //caller is supposed to free the handle buffer using free pool, so we need to allocate it using allocate pool.
     let buffer_size = descriptors.len() * mem::size_of::<dxe_services::IoSpaceDescriptor>();
@@ -370,6 +381,59 @@ extern "efiapi" fn get_io_space_map(
     }
 }
 
+pub fn core_get_io_space_map() -> Result<Vec<dxe_services::IoSpaceDescriptor>, EfiError> {
+    //allocate an empty vector with enough space for all the descriptors with some padding (in the event)
+    //that extra descriptors come into being after creation but before usage.
+    let mut descriptors: Vec<dxe_services::IoSpaceDescriptor> = Vec::with_capacity(GCD.io_descriptor_count() + 10);
+    GCD.get_io_descriptors(&mut descriptors)?;
+    Ok(descriptors)
+}
+
+/// The core implementation of the [IoSpace] service.
+#[derive(IntoService)]
+#[service(dyn IoSpace)]
+pub(crate) struct CoreIoSpace;
+
+impl IoSpace for CoreIoSpace {
+    fn add_io_space(&self, io_type: dxe_services::GcdIoType, base_address: u64, length: u64) -> Result<(), EfiError> {
+        GCD.add_io_space(io_type, base_address as usize, length as usize).map(|_| ())
+    }
+
+    fn allocate_io_space(
+        &self,
+        allocate_type: IoAllocateType,
+        io_type: dxe_services::GcdIoType,
+        align_shift: usize,
+        length: u64,
+        image_handle: efi::Handle,
+        device_handle: Option<efi::Handle>,
+    ) -> Result<u64, EfiError> {
+        let allocate_type = match allocate_type {
+            IoAllocateType::Address(address) => gcd::AllocateType::Address(address as usize),
+            IoAllocateType::BottomUp(limit) => gcd::AllocateType::BottomUp(limit.map(|limit| limit as usize)),
+            IoAllocateType::TopDown(limit) => gcd::AllocateType::TopDown(limit.map(|limit| limit as usize)),
+        };
+        GCD.allocate_io_space(allocate_type, io_type, align_shift, length as usize, image_handle, device_handle)
+            .map(|address| address as u64)
+    }
+
+    fn free_io_space(&self, base_address: u64, length: u64) -> Result<(), EfiError> {
+        GCD.free_io_space(base_address as usize, length as usize)
+    }
+
+    fn remove_io_space(&self, base_address: u64, length: u64) -> Result<(), EfiError> {
+        GCD.remove_io_space(base_address as usize, length as usize)
+    }
+
+    fn get_io_space_descriptor(&self, address: u64) -> Result<dxe_services::IoSpaceDescriptor, EfiError> {
+        core_get_io_space_descriptor(address)
+    }
+
+    fn get_io_space_map(&self) -> Result<Vec<dxe_services::IoSpaceDescriptor>, EfiError> {
+        core_get_io_space_map()
+    }
+}
+
 extern "efiapi" fn dispatch() -> efi::Status {
     match core_dispatcher() {
         Err(err) => err.into(),
@@ -464,7 +528,7 @@ pub fn init_dxe_services(system_table: &mut EfiSystemTable) {
     };
     let dxe_system_table_ptr = &dxe_system_table as *const dxe_services::DxeServicesTable;
     let crc32 = unsafe {
-        crc32fast::hash(from_raw_parts(
+        crate::crc32::table_hash(from_raw_parts(
             dxe_system_table_ptr as *const u8,
             mem::size_of::<dxe_services::DxeServicesTable>(),
         ))
@@ -2282,12 +2346,10 @@ mod tests {
             assert_eq!(dxe_tbl.header.signature, efi::BOOT_SERVICES_SIGNATURE);
             assert_eq!(dxe_tbl.header.revision, efi::BOOT_SERVICES_REVISION);
 
-            // Recompute CRC32 by zeroing the field in a local copy
-            let mut copy = unsafe { core::ptr::read(dxe_tbl) };
-            copy.header.crc32 = 0;
-            let crc = crc32fast::hash(unsafe {
+            // Recompute CRC32 in place, with the crc32 field treated as zero
+            let crc = crate::crc32::table_hash(unsafe {
                 core::slice::from_raw_parts(
-                    (&copy as *const dxe_services::DxeServicesTable) as *const u8,
+                    (dxe_tbl as *const dxe_services::DxeServicesTable) as *const u8,
                     core::mem::size_of::<dxe_services::DxeServicesTable>(),
                 )
             });
@@ -2299,4 +2361,34 @@ mod tests {
             assert_eq!(dxe_tbl.process_firmware_volume as usize, process_firmware_volume as usize);
         });
     }
+
+    #[test]
+    fn test_io_space_service_round_trip() {
+        with_locked_state(|| {
+            let io = CoreIoSpace;
+            let image_handle = 1 as efi::Handle;
+
+            io.add_io_space(GcdIoType::Io, 0x3000, 0x1000).unwrap();
+            assert_eq!(io.add_io_space(GcdIoType::Io, 0x3800, 0x100), Err(EfiError::AccessDenied));
+
+            let base = io.allocate_io_space(IoAllocateType::TopDown(None), GcdIoType::Io, 8, 0x100, image_handle, None);
+            assert_eq!(base, Ok(0x3F00));
+            let descriptor = io.get_io_space_descriptor(0x3F80).unwrap();
+            assert_eq!(
+                (descriptor.base_address, descriptor.length, descriptor.image_handle),
+                (0x3F00, 0x100, image_handle)
+            );
+            assert!(io.get_io_space_map().unwrap().contains(&descriptor));
+
+            // allocated space cannot be removed until it is freed.
+            assert_eq!(io.remove_io_space(0x3F00, 0x100), Err(EfiError::AccessDenied));
+            io.free_io_space(0x3F00, 0x100).unwrap();
+            io.remove_io_space(0x3000, 0x1000).unwrap();
+            assert_eq!(io.get_io_space_descriptor(0x3F80).unwrap().io_type, GcdIoType::NonExistent);
+
+            let mut descriptor = core::mem::MaybeUninit::<dxe_services::IoSpaceDescriptor>::uninit();
+            assert_eq!(get_io_space_descriptor(0x3000, descriptor.as_mut_ptr()), efi::Status::SUCCESS);
+            assert_eq!(unsafe { descriptor.assume_init() }.io_type, GcdIoType::NonExistent);
+        });
+    }
 }
@@ -13,10 +13,10 @@
 extern crate alloc;
 
 use alloc::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, VecDeque},
     vec::Vec,
 };
-use core::{cmp::Ordering, ffi::c_void, fmt};
+use core::{ffi::c_void, fmt};
 use patina::error::EfiError;
 use r_efi::efi;
 
@@ -151,42 +151,60 @@ impl fmt::Debug for EventNotification {
     }
 }
 
-//This type is necessary because the HeapSort used to order BTreeSet is not stable with respect
-//to insertion order. So we have to tag each event notification as it is added so that we can
-//use insertion order as part of the element comparison.
-#[derive(Debug, Clone)]
-struct TaggedEventNotification(EventNotification, u64);
+// Number of TPLs that notifications can be queued at; notify TPLs are validated to be at most TPL_HIGH_LEVEL.
+const NOTIFY_TPL_COUNT: usize = efi::TPL_HIGH_LEVEL + 1;
 
-impl PartialOrd for TaggedEventNotification {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+// Pending event notifications, with a FIFO queue of event ids per TPL like the per-TPL lists in the reference C
+// implementation. Queueing a notify is O(1), and the highest TPL with pending notifies is found from a bitmap rather
+// than by scanning the queued events.
+struct NotifyQueues {
+    queues: [VecDeque<usize>; NOTIFY_TPL_COUNT],
+    // bit n is set if queues[n] is not empty.
+    pending_tpls: u32,
 }
 
-impl Ord for TaggedEventNotification {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self.0.event.cmp(&other.0.event) {
-            // events are unique - so if event ids match, they are the same event.
-            Ordering::Equal => Ordering::Equal,
-            _ =>
-            // Higher TPL has precedence; if TPLs are equal, then earlier insertion has precedence.
-            {
-                match other.0.notify_tpl.cmp(&self.0.notify_tpl) {
-                    Ordering::Equal => self.1.cmp(&other.1),
-                    non_eq => non_eq,
-                }
-            }
+impl NotifyQueues {
+    const fn new() -> Self {
+        NotifyQueues { queues: [const { VecDeque::new() }; NOTIFY_TPL_COUNT], pending_tpls: 0 }
+    }
+
+    fn push(&mut self, tpl: efi::Tpl, event_id: usize) {
+        self.queues[tpl].push_back(event_id);
+        self.pending_tpls |= 1 << tpl;
+    }
+
+    // returns the highest TPL with pending notifies.
+    fn highest_tpl(&self) -> Option<efi::Tpl> {
+        match self.pending_tpls {
+            0 => None,
+            pending => Some((u32::BITS - 1 - pending.leading_zeros()) as efi::Tpl),
         }
     }
-}
 
-impl PartialEq for TaggedEventNotification {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.event == other.0.event
+    fn pop(&mut self, tpl: efi::Tpl) -> Option<usize> {
+        let event_id = self.queues[tpl].pop_front();
+        if self.queues[tpl].is_empty() {
+            self.pending_tpls &= !(1 << tpl);
+        }
+        event_id
     }
-}
 
-impl Eq for TaggedEventNotification {}
+    // returns the queued event ids in the order they will be dispatched.
+    #[cfg(test)]
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.queues.iter().rev().flatten().copied()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool {
+        self.pending_tpls == 0
+    }
+}
 
 // Note: this Event type is a distinct data structure from efi::Event.
 // Event defined here is a private data structure that tracks the data related to the event,
@@ -201,6 +219,7 @@ struct Event {
     signaled: bool,
 
     //Only used for NOTIFY events.
+    notify_queued: bool,
     notify_tpl: efi::Tpl,
     notify_function: Option<efi::EventNotify>,
     notify_context: Option<*mut c_void>,
@@ -272,6 +291,7 @@ impl Event {
             notify_context,
             event_group,
             signaled: false,
+            notify_queued: false,
             trigger_time: None,
             period: None,
         })
@@ -285,12 +305,7 @@ impl Event {
 struct EventDb {
     events: BTreeMap<usize, Event>,
     next_event_id: usize,
-    //TODO: using a BTreeSet here as a priority queue is slower [O(log n)] vs. the
-    //per-TPL lists used in the reference C implementation [O(1)] for (de)queueing of event notifies.
-    //Benchmarking would need to be done to see whether that perf impact plays out to significantly
-    //impact real-world usage.
-    pending_notifies: BTreeSet<TaggedEventNotification>,
-    notify_tags: u64, //used to ensure that each notify gets a unique tag in increasing order
+    pending_notifies: NotifyQueues,
 }
 
 impl EventDb {
@@ -298,7 +313,7 @@ impl EventDb {
     const RT_EVENT: usize = 1 << (usize::BITS - 1);
 
     const fn new() -> Self {
-        EventDb { events: BTreeMap::new(), next_event_id: 1, pending_notifies: BTreeSet::new(), notify_tags: 0 }
+        EventDb { events: BTreeMap::new(), next_event_id: 1, pending_notifies: NotifyQueues::new() }
     }
 
     fn create_event(
@@ -351,17 +366,10 @@ impl EventDb {
     }
 
     //private helper function for signal_event.
-    fn queue_notify_event(pending_notifies: &mut BTreeSet<TaggedEventNotification>, event: &mut Event, tag: u64) {
-        if event.event_type.is_notify_signal() || event.event_type.is_notify_wait() {
-            pending_notifies.insert(TaggedEventNotification(
-                EventNotification {
-                    event: event.efi_event(),
-                    notify_tpl: event.notify_tpl,
-                    notify_function: event.notify_function,
-                    notify_context: event.notify_context,
-                },
-                tag,
-            ));
+    fn queue_notify_event(pending_notifies: &mut NotifyQueues, event: &mut Event) {
+        if (event.event_type.is_notify_signal() || event.event_type.is_notify_wait()) && !event.notify_queued {
+            event.notify_queued = true;
+            pending_notifies.push(event.notify_tpl, event.event_id);
         }
     }
 
@@ -369,32 +377,34 @@ impl EventDb {
         let id = event as usize;
         let current_event = self.events.get_mut(&id).ok_or(EfiError::InvalidParameter)?;
 
+        //signal all the members of the same event group (including the current one), if present. This is done even if
+        //the current event is already signaled, so that members created since the group was last signaled are signaled.
+        if let Some(target_group) = current_event.event_group {
+            self.signal_group(target_group);
+            return Ok(());
+        }
+
         //explicitly match the EDK II C implementation by not queueing an additional notify.
         if current_event.signaled {
             return Ok(());
         }
 
-        //signal all the members of the same event group (including the current one), if present.
-        if let Some(target_group) = current_event.event_group {
-            self.signal_group(target_group);
-        } else {
-            // if no group, signal the event by itself.
-            current_event.signaled = true;
-            if current_event.event_type.is_notify_signal() {
-                Self::queue_notify_event(&mut self.pending_notifies, current_event, self.notify_tags);
-                self.notify_tags += 1;
-            }
+        // if no group, signal the event by itself.
+        current_event.signaled = true;
+        if current_event.event_type.is_notify_signal() {
+            Self::queue_notify_event(&mut self.pending_notifies, current_event);
         }
         Ok(())
     }
 
+    //members that are already signaled are skipped so that no additional notify is queued for them. Events are not
+    //signaled by a group signal that happened before they were created.
     fn signal_group(&mut self, group: efi::Guid) {
         for member_event in self.events.values_mut().rev().filter(|e| e.event_group == Some(group) && !e.signaled) {
             member_event.signaled = true;
 
             if member_event.event_type.is_notify_signal() {
-                Self::queue_notify_event(&mut self.pending_notifies, member_event, self.notify_tags);
-                self.notify_tags += 1;
+                Self::queue_notify_event(&mut self.pending_notifies, member_event);
             }
         }
     }
@@ -415,8 +425,7 @@ impl EventDb {
         let id = event as usize;
         let current_event = self.events.get_mut(&id).ok_or(EfiError::InvalidParameter)?;
 
-        Self::queue_notify_event(&mut self.pending_notifies, current_event, self.notify_tags);
-        self.notify_tags += 1;
+        Self::queue_notify_event(&mut self.pending_notifies, current_event);
 
         Ok(())
     }
@@ -500,7 +509,13 @@ impl EventDb {
                 && trigger_time <= current_time
             {
                 if let Some(period) = current_event.period {
-                    current_event.trigger_time = Some(current_time + period);
+                    // re-arm from the time the timer was due rather than the time the tick arrived, so that late ticks
+                    // do not accumulate drift. Periods missed entirely are skipped rather than signaled in a burst.
+                    let next_trigger_time = match period {
+                        0 => current_time,
+                        _ => trigger_time + period * ((current_time - trigger_time) / period + 1),
+                    };
+                    current_event.trigger_time = Some(next_trigger_time);
                 } else {
                     //no period means it's a one-shot event; another call to set_timer is required to "re-arm"
                     current_event.trigger_time = None;
@@ -513,23 +528,22 @@ impl EventDb {
     }
 
     fn consume_next_event_notify(&mut self, tpl_level: efi::Tpl) -> Option<EventNotification> {
-        //if items at front of queue don't exist (e.g. due to close_event), silently pop them off.
-        while let Some(item) = self.pending_notifies.first() {
-            if !self.events.contains_key(&(item.0.event as usize)) {
-                self.pending_notifies.pop_first();
-            } else {
-                break;
-            }
-        }
-        //if item at front of queue is not higher than desired efi::TPL, then return none
-        //otherwise, pop it off, mark it un-signaled, and return it.
-        if let Some(item) = self.pending_notifies.first() {
-            if item.0.notify_tpl <= tpl_level {
+        //if the highest pending notify is not higher than desired efi::TPL, then return none
+        //otherwise, pop it off and return it.
+        while let Some(tpl) = self.pending_notifies.highest_tpl() {
+            if tpl <= tpl_level {
                 return None;
-            } else if let Some(item) = self.pending_notifies.pop_first() {
-                return Some(item.0);
-            } else {
-                log::error!("Pending_notifies was empty, but it should have at least one item.");
+            }
+            let event_id = self.pending_notifies.pop(tpl)?;
+            //if the event no longer exists (e.g. due to close_event), silently drop the notify.
+            if let Some(event) = self.events.get_mut(&event_id) {
+                event.notify_queued = false;
+                return Some(EventNotification {
+                    event: event.efi_event(),
+                    notify_tpl: event.notify_tpl,
+                    notify_function: event.notify_function,
+                    notify_context: event.notify_context,
+                });
             }
         }
         None
@@ -604,6 +618,9 @@ impl SpinLockedEventDb {
     /// This function closely matches the semantics of the EFI_BOOT_SERVICES.SignalEvent() API in
     /// UEFI spec 2.10 section 7.1.4. Please refer to the spec for details on the input parameters.
     ///
+    /// If the event is a member of an event group, every member of the group that is not already signaled is
+    /// signaled, as with [`signal_group`](SpinLockedEventDb::signal_group).
+    ///
     /// ## Errors
     ///
     /// Returns r_efi:efi::Status::INVALID_PARAMETER if incorrect parameters are given.
@@ -616,6 +633,9 @@ impl SpinLockedEventDb {
     /// This routine signals all events in the given event group. There isn't an equivalent UEFI spec API for this; the
     /// equivalent would need to be accomplished by creating a dummy event that is a member of the group and signalling
     /// that event.
+    ///
+    /// The group may be any GUID. Members that are already signaled do not have an additional notify queued, and
+    /// events that join the group after it is signaled are not signaled until the group is signaled again.
     pub fn signal_group(&self, group: efi::Guid) {
         self.lock().signal_group(group)
     }
@@ -1104,11 +1124,77 @@ mod tests {
             let queue = &mut event_db.pending_notifies;
             assert_eq!(queue.len(), 10);
             for (group_item, queue_item) in iter::zip(group_events.iter().rev(), queue.iter()) {
-                assert_eq!(group_item, &queue_item.0.event);
+                assert_eq!(*group_item as usize, queue_item);
             }
         })
     }
 
+    #[test]
+    fn events_joining_a_signaled_group_should_be_signaled_by_the_next_group_signal() {
+        with_locked_state(|| {
+            let group = Guid::try_from_string("0c5f2d3e-8f0a-4b7e-9d61-2a4c6e8b1f03").unwrap().to_efi_guid();
+            let empty_group = Guid::try_from_string("b9e1c7a4-3d52-4f86-a0e8-7c2b5d9f6e14").unwrap().to_efi_guid();
+            static SPIN_LOCKED_EVENT_DB: SpinLockedEventDb = SpinLockedEventDb::new();
+
+            // signaling a group without members does nothing.
+            SPIN_LOCKED_EVENT_DB.signal_group(empty_group);
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().pending_notifies.len(), 0);
+
+            let wait_event = SPIN_LOCKED_EVENT_DB
+                .create_event(efi::EVT_NOTIFY_WAIT, efi::TPL_NOTIFY, Some(test_notify_function), None, Some(group))
+                .unwrap();
+            SPIN_LOCKED_EVENT_DB.signal_group(group);
+            assert!(SPIN_LOCKED_EVENT_DB.is_signaled(wait_event));
+
+            // a member created after the group was signaled is not signaled.
+            let late_event = SPIN_LOCKED_EVENT_DB
+                .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(test_notify_function), None, Some(group))
+                .unwrap();
+            assert!(!SPIN_LOCKED_EVENT_DB.is_signaled(late_event));
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().pending_notifies.len(), 0);
+
+            // signaling the member that is still signaled signals the rest of the group.
+            SPIN_LOCKED_EVENT_DB.signal_event(wait_event).unwrap();
+            assert!(SPIN_LOCKED_EVENT_DB.is_signaled(late_event));
+            assert_eq!(
+                SPIN_LOCKED_EVENT_DB.lock().pending_notifies.iter().collect::<Vec<_>>(),
+                vec![late_event as usize]
+            );
+
+            // signaling the group again does not queue another notify for the signaled member.
+            SPIN_LOCKED_EVENT_DB.signal_event(late_event).unwrap();
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().pending_notifies.len(), 1);
+        })
+    }
+
+    #[test]
+    fn signaled_group_members_should_notify_by_tpl_and_skip_closed_members() {
+        with_locked_state(|| {
+            let group = Guid::try_from_string("6d2e8a1b-94c3-4f57-b0a2-e3f8c1d7a596").unwrap().to_efi_guid();
+            static SPIN_LOCKED_EVENT_DB: SpinLockedEventDb = SpinLockedEventDb::new();
+
+            let callback_event = SPIN_LOCKED_EVENT_DB
+                .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(test_notify_function), None, Some(group))
+                .unwrap();
+            let notify_event = SPIN_LOCKED_EVENT_DB
+                .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_NOTIFY, Some(test_notify_function), None, Some(group))
+                .unwrap();
+            let closed_event = SPIN_LOCKED_EVENT_DB
+                .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_NOTIFY, Some(test_notify_function), None, Some(group))
+                .unwrap();
+
+            SPIN_LOCKED_EVENT_DB.signal_event(callback_event).unwrap();
+            SPIN_LOCKED_EVENT_DB.close_event(closed_event).unwrap();
+
+            // higher TPL notifies are dispatched first, and the closed member's notify is dropped.
+            let notification = SPIN_LOCKED_EVENT_DB.consume_next_event_notify(efi::TPL_APPLICATION).unwrap();
+            assert_eq!(notification.event, notify_event);
+            let notification = SPIN_LOCKED_EVENT_DB.consume_next_event_notify(efi::TPL_APPLICATION).unwrap();
+            assert_eq!(notification.event, callback_event);
+            assert!(SPIN_LOCKED_EVENT_DB.consume_next_event_notify(efi::TPL_APPLICATION).is_none());
+        })
+    }
+
     #[test]
     fn clear_signal_should_clear_signaled_state() {
         with_locked_state(|| {
@@ -1220,12 +1306,12 @@ mod tests {
             {
                 let mut event_db = SPIN_LOCKED_EVENT_DB.lock();
                 let queue = &mut event_db.pending_notifies;
-                assert_eq!(queue.pop_first().unwrap().0.event, high_evt1);
-                assert_eq!(queue.pop_first().unwrap().0.event, high_evt2);
-                assert_eq!(queue.pop_first().unwrap().0.event, notify_evt1);
-                assert_eq!(queue.pop_first().unwrap().0.event, notify_evt2);
-                assert_eq!(queue.pop_first().unwrap().0.event, callback_evt1);
-                assert_eq!(queue.pop_first().unwrap().0.event, callback_evt2);
+                assert_eq!(queue.pop(queue.highest_tpl().unwrap()), Some(high_evt1 as usize));
+                assert_eq!(queue.pop(queue.highest_tpl().unwrap()), Some(high_evt2 as usize));
+                assert_eq!(queue.pop(queue.highest_tpl().unwrap()), Some(notify_evt1 as usize));
+                assert_eq!(queue.pop(queue.highest_tpl().unwrap()), Some(notify_evt2 as usize));
+                assert_eq!(queue.pop(queue.highest_tpl().unwrap()), Some(callback_evt1 as usize));
+                assert_eq!(queue.pop(queue.highest_tpl().unwrap()), Some(callback_evt2 as usize));
             }
         });
     }
@@ -1356,6 +1442,35 @@ mod tests {
         });
     }
 
+    #[test]
+    fn closed_events_should_be_dropped_from_the_pending_queue() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_EVENT_DB: SpinLockedEventDb = SpinLockedEventDb::new();
+
+            let events: Vec<efi::Event> = (0..3)
+                .map(|_| {
+                    SPIN_LOCKED_EVENT_DB
+                        .create_event(
+                            efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+                            efi::TPL_CALLBACK,
+                            Some(test_notify_function),
+                            None,
+                            None,
+                        )
+                        .unwrap()
+                })
+                .collect();
+            for event in &events {
+                SPIN_LOCKED_EVENT_DB.signal_event(*event).unwrap();
+            }
+            SPIN_LOCKED_EVENT_DB.close_event(events[1]).unwrap();
+
+            let event_iter = iter::from_fn(|| SPIN_LOCKED_EVENT_DB.consume_next_event_notify(efi::TPL_APPLICATION));
+            assert_eq!(event_iter.map(|notify| notify.event).collect::<Vec<_>>(), vec![events[0], events[2]]);
+            assert!(SPIN_LOCKED_EVENT_DB.lock().pending_notifies.is_empty());
+        });
+    }
+
     #[test]
     fn signalling_an_event_more_than_once_should_not_queue_it_more_than_once() {
         with_locked_state(|| {
@@ -1674,6 +1789,63 @@ mod tests {
         });
     }
 
+    #[test]
+    fn periodic_timers_should_not_drift_when_ticks_are_late() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_EVENT_DB: SpinLockedEventDb = SpinLockedEventDb::new();
+            let event = SPIN_LOCKED_EVENT_DB
+                .create_event(
+                    efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+                    efi::TPL_NOTIFY,
+                    Some(test_notify_function),
+                    None,
+                    None,
+                )
+                .unwrap();
+            let every_tick_event = SPIN_LOCKED_EVENT_DB
+                .create_event(
+                    efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+                    efi::TPL_NOTIFY,
+                    Some(test_notify_function),
+                    None,
+                    None,
+                )
+                .unwrap();
+
+            SPIN_LOCKED_EVENT_DB.set_timer(event, TimerDelay::Periodic, Some(100), Some(100)).unwrap();
+            SPIN_LOCKED_EVENT_DB.set_timer(every_tick_event, TimerDelay::Periodic, Some(0), Some(0)).unwrap();
+
+            let tick = |current_time| {
+                SPIN_LOCKED_EVENT_DB.timer_tick(current_time);
+                let events: Vec<_> =
+                    iter::from_fn(|| SPIN_LOCKED_EVENT_DB.consume_next_event_notify(efi::TPL_APPLICATION))
+                        .map(|notification| notification.event)
+                        .collect();
+                for event in events.iter() {
+                    let _ = SPIN_LOCKED_EVENT_DB.clear_signal(*event);
+                }
+                events.contains(&event)
+            };
+
+            // a late tick re-arms the timer for its original cadence.
+            assert!(tick(130));
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().events[&(event as usize)].trigger_time, Some(200));
+            assert!(!tick(199));
+            assert!(tick(205));
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().events[&(event as usize)].trigger_time, Some(300));
+
+            // missed periods are skipped with a single signal.
+            assert!(tick(450));
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().events[&(event as usize)].trigger_time, Some(500));
+            assert!(tick(500));
+
+            // a zero period fires on every tick.
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().events[&(every_tick_event as usize)].trigger_time, Some(500));
+            assert!(!tick(501));
+            assert_eq!(SPIN_LOCKED_EVENT_DB.lock().events[&(every_tick_event as usize)].trigger_time, Some(501));
+        })
+    }
+
     #[test]
     fn periodic_timers_should_rearm_after_tick() {
         with_locked_state(|| {
@@ -6,6 +6,8 @@
 //!
 //! SPDX-License-Identifier: Apache-2.0
 //!
+use alloc::vec;
+
 use core::{
     ffi::c_void,
     sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
@@ -18,15 +20,46 @@ use patina_pi::protocols::timer;
 use patina_internal_cpu::interrupts;
 
 use crate::{
+    component_timeout,
     event_db::{SpinLockedEventDb, TimerDelay},
-    gcd,
+    file_prehash, gcd, interrupt_latency,
     protocols::PROTOCOL_DB,
 };
 
 pub static EVENT_DB: SpinLockedEventDb = SpinLockedEventDb::new();
 
+/// Programs the period of the platform timer interrupt when the Timer Architectural Protocol is installed.
+///
+/// Timer events can only fire as often as the platform timer interrupt, so a platform whose timer supports a shorter
+/// period than the one it starts with can use this to give SetTimer() a finer resolution. The period is in 100ns units,
+/// the finest unit of the Timer Architectural Protocol, and is rounded up by the platform timer to the nearest period
+/// its hardware supports. If the platform timer cannot be reprogrammed, its own period is kept and a warning is logged.
+/// Unless the platform registers this config, the period of the platform timer is not changed.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, TimerPeriod};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(TimerPeriod { period: 10_000 })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimerPeriod {
+    /// The period of the platform timer interrupt, in 100ns units. A period of zero leaves the platform timer unchanged.
+    pub period: u64,
+}
+
+// the platform timer period to program, in 100ns units, or zero to keep the period of the platform timer.
+static TIMER_PERIOD: AtomicU64 = AtomicU64::new(0);
+
 static CURRENT_TPL: AtomicUsize = AtomicUsize::new(efi::TPL_APPLICATION);
 static SYSTEM_TIME: AtomicU64 = AtomicU64::new(0);
+static WAIT_FOR_EVENT_START: AtomicUsize = AtomicUsize::new(0);
 
 extern "efiapi" fn create_event(
     event_type: u32,
@@ -95,7 +128,11 @@ extern "efiapi" fn create_event_ex(
 
 pub extern "efiapi" fn close_event(event: efi::Event) -> efi::Status {
     match EVENT_DB.close_event(event) {
-        Ok(()) => efi::Status::SUCCESS,
+        Ok(()) => {
+            // protocol notify registrations for the event are no longer valid.
+            PROTOCOL_DB.unregister_protocol_notify_events(vec![event]);
+            efi::Status::SUCCESS
+        }
         Err(err) => err.into(),
     }
 }
@@ -129,15 +166,30 @@ extern "efiapi" fn wait_for_event(
         return efi::Status::UNSUPPORTED;
     }
 
-    //spin on the list
+    // Safety: caller must ensure that event_array is a valid pointer and number_of_events is correct. event_array is null-checked above.
+    let events = unsafe { core::slice::from_raw_parts(event_array, number_of_events) };
+
+    // validate all the events up front, so that an invalid event is reported regardless of which events are signaled.
+    for (index, &event) in events.iter().enumerate() {
+        let status = match EVENT_DB.get_event_type(event) {
+            Ok(event_type) if event_type.is_notify_signal() => efi::Status::INVALID_PARAMETER,
+            Ok(_) => continue,
+            Err(err) => err.into(),
+        };
+        // Safety: caller must ensure that out_index is a valid pointer. It is null-checked above.
+        unsafe { out_index.write_unaligned(index) };
+        return status;
+    }
+
+    //spin on the list. Each wait starts checking after the event that satisfied the previous wait, so that an event
+    //that is always signaled (e.g. a periodic timer) does not starve the events after it.
+    let start = WAIT_FOR_EVENT_START.load(Ordering::SeqCst) % number_of_events;
     loop {
-        let mut event_ptr = event_array;
-        for index in 0..number_of_events {
-            // Safety: caller must ensure that event_array is a valid pointer and number_of_events is correct. event_array is null-checked above.
-            let event = unsafe { event_ptr.read_unaligned() };
-            match check_event(event) {
+        for index in (start..number_of_events).chain(0..start) {
+            match check_event(events[index]) {
                 efi::Status::NOT_READY => (),
                 status => {
+                    WAIT_FOR_EVENT_START.store(index + 1, Ordering::SeqCst);
                     // Safety: caller must ensure that out_index is a valid pointer. It is null-checked above.
                     unsafe {
                         out_index.write_unaligned(index);
@@ -145,9 +197,10 @@ extern "efiapi" fn wait_for_event(
                     return status;
                 }
             }
-            // Safety: caller must ensure that event_array is a valid pointer and number_of_events is correct. event_array is null-checked above.
-            event_ptr = unsafe { event_ptr.add(1) };
         }
+
+        // none of the events are signaled yet, so use the time to pre-hash files that are about to be dispatched.
+        file_prehash::hash_next_file();
     }
 }
 
@@ -170,6 +223,11 @@ pub extern "efiapi" fn check_event(event: efi::Event) -> efi::Status {
         Err(err) => return err.into(),
     }
 
+    // only EVT_NOTIFY_WAIT events have a notify function that may signal the event when it is checked.
+    if !event_type.is_notify_wait() {
+        return efi::Status::NOT_READY;
+    }
+
     match EVENT_DB.queue_event_notify(event) {
         Ok(()) => (),
         Err(err) => return err.into(),
@@ -209,6 +267,11 @@ pub extern "efiapi" fn set_timer(event: efi::Event, timer_type: efi::TimerDelay,
     }
 }
 
+/// Returns the current TPL.
+pub fn current_tpl() -> efi::Tpl {
+    CURRENT_TPL.load(Ordering::SeqCst)
+}
+
 pub extern "efiapi" fn raise_tpl(new_tpl: efi::Tpl) -> efi::Tpl {
     assert!(new_tpl <= efi::TPL_HIGH_LEVEL, "Invalid attempt to raise TPL above TPL_HIGH_LEVEL");
 
@@ -221,6 +284,7 @@ pub extern "efiapi" fn raise_tpl(new_tpl: efi::Tpl) -> efi::Tpl {
 
     if (new_tpl == efi::TPL_HIGH_LEVEL) && (prev_tpl < efi::TPL_HIGH_LEVEL) {
         interrupts::disable_interrupts();
+        interrupt_latency::interrupts_disabled();
     }
     prev_tpl
 }
@@ -258,9 +322,11 @@ pub extern "efiapi" fn restore_tpl(new_tpl: efi::Tpl) {
                 break; /* no pending events */
             };
             if event.notify_tpl < efi::TPL_HIGH_LEVEL {
+                interrupt_latency::interrupts_enabled();
                 interrupts::enable_interrupts();
             } else {
                 interrupts::disable_interrupts();
+                interrupt_latency::interrupts_disabled();
             }
             CURRENT_TPL.store(event.notify_tpl, Ordering::SeqCst);
             let notify_context = event.notify_context.unwrap_or(core::ptr::null_mut());
@@ -282,6 +348,7 @@ pub extern "efiapi" fn restore_tpl(new_tpl: efi::Tpl) {
     }
 
     if new_tpl < efi::TPL_HIGH_LEVEL {
+        interrupt_latency::interrupts_enabled();
         interrupts::enable_interrupts();
     }
     CURRENT_TPL.store(new_tpl, Ordering::SeqCst);
@@ -291,16 +358,42 @@ extern "efiapi" fn timer_tick(time: u64) {
     let old_tpl = raise_tpl(efi::TPL_HIGH_LEVEL);
     SYSTEM_TIME.fetch_add(time, Ordering::SeqCst);
     let current_time = SYSTEM_TIME.load(Ordering::SeqCst);
+    component_timeout::timer_tick(time);
     EVENT_DB.timer_tick(current_time);
     restore_tpl(old_tpl); //implicitly dispatches timer notifies if any.
 }
 
+/// Applies the platform timer period to program when the Timer Architectural Protocol is installed.
+pub(crate) fn set_platform_timer_period(config: &TimerPeriod) {
+    TIMER_PERIOD.store(config.period, Ordering::SeqCst);
+}
+
+fn program_platform_timer_period(timer_arch_ptr: *mut timer::Protocol) {
+    let period = TIMER_PERIOD.load(Ordering::SeqCst);
+    if period == 0 {
+        return;
+    }
+
+    // Safety: the caller passes the installed Timer Architectural Protocol interface.
+    let timer_arch = unsafe { &*(timer_arch_ptr) };
+    match (timer_arch.set_timer_period)(timer_arch_ptr, period) {
+        efi::Status::SUCCESS => {
+            // the platform timer rounds the period up to one its hardware supports.
+            let mut actual_period = period;
+            let _ = (timer_arch.get_timer_period)(timer_arch_ptr, &mut actual_period);
+            log::info!("Platform timer period set to {actual_period} (requested {period}) in 100ns units.");
+        }
+        status => log::warn!("Platform timer period could not be set to {period} in 100ns units: {status:?}."),
+    }
+}
+
 extern "efiapi" fn timer_available_callback(event: efi::Event, _context: *mut c_void) {
     match PROTOCOL_DB.locate_protocol(timer::PROTOCOL_GUID) {
         Ok(timer_arch_ptr) => {
             let timer_arch_ptr = timer_arch_ptr as *mut timer::Protocol;
             let timer_arch = unsafe { &*(timer_arch_ptr) };
             (timer_arch.register_handler)(timer_arch_ptr, timer_tick);
+            program_platform_timer_period(timer_arch_ptr);
             if let Err(status_err) = EVENT_DB.close_event(event) {
                 log::warn!("Could not close event for timer_available_callback due to error {status_err:?}");
             }
@@ -555,6 +648,43 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_signal_event_ex_group_member_notifies_every_member() {
+        static GROUP_NOTIFIES: AtomicUsize = AtomicUsize::new(0);
+        extern "efiapi" fn group_notify(_event: efi::Event, _context: *mut c_void) {
+            GROUP_NOTIFIES.fetch_add(1, Ordering::SeqCst);
+        }
+
+        with_locked_state(|| {
+            CURRENT_TPL.store(efi::TPL_APPLICATION, Ordering::SeqCst);
+            GROUP_NOTIFIES.store(0, Ordering::SeqCst);
+            let group: efi::Guid =
+                efi::Guid::from_fields(0x5b1e9c42, 0x7d3a, 0x4e68, 0x91, 0x0f, &[0x2c, 0x84, 0xa6, 0x3e, 0xd5, 0x17]);
+
+            let mut members = [ptr::null_mut(); 3];
+            for member in members.iter_mut() {
+                let status = create_event_ex(
+                    efi::EVT_NOTIFY_SIGNAL,
+                    efi::TPL_CALLBACK,
+                    Some(group_notify),
+                    ptr::null(),
+                    &group,
+                    member,
+                );
+                assert_eq!(status, efi::Status::SUCCESS);
+            }
+
+            assert_eq!(signal_event(members[1]), efi::Status::SUCCESS);
+            assert_eq!(GROUP_NOTIFIES.load(Ordering::SeqCst), 3);
+
+            // a member closed before the group is signaled again is not notified.
+            assert_eq!(close_event(members[0]), efi::Status::SUCCESS);
+            assert_eq!(signal_event(members[2]), efi::Status::SUCCESS);
+            assert_eq!(GROUP_NOTIFIES.load(Ordering::SeqCst), 5);
+            assert_eq!(signal_event(members[0]), efi::Status::INVALID_PARAMETER);
+        });
+    }
+
     #[test]
     fn test_wait_for_event_signaled() {
         with_locked_state(|| {
@@ -872,6 +1002,175 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_program_platform_timer_period() {
+        static PROGRAMMED_PERIOD: AtomicU64 = AtomicU64::new(0);
+        extern "efiapi" fn register_handler(
+            _this: *mut timer::Protocol,
+            _notify: timer::EfiTimerNotify,
+        ) -> efi::Status {
+            efi::Status::SUCCESS
+        }
+        extern "efiapi" fn set_timer_period(_this: *mut timer::Protocol, period: u64) -> efi::Status {
+            // the hardware supports multiples of 50 (5us).
+            PROGRAMMED_PERIOD.store(period.div_ceil(50) * 50, Ordering::SeqCst);
+            efi::Status::SUCCESS
+        }
+        extern "efiapi" fn unsupported_set_timer_period(_this: *mut timer::Protocol, _period: u64) -> efi::Status {
+            efi::Status::UNSUPPORTED
+        }
+        extern "efiapi" fn get_timer_period(_this: *mut timer::Protocol, period: *mut u64) -> efi::Status {
+            unsafe { period.write(PROGRAMMED_PERIOD.load(Ordering::SeqCst)) };
+            efi::Status::SUCCESS
+        }
+        extern "efiapi" fn generate_soft_interrupt(_this: *mut timer::Protocol) -> efi::Status {
+            efi::Status::UNSUPPORTED
+        }
+
+        with_locked_state(|| {
+            let mut timer_arch =
+                timer::Protocol { register_handler, set_timer_period, get_timer_period, generate_soft_interrupt };
+            PROGRAMMED_PERIOD.store(100_000, Ordering::SeqCst);
+
+            // without a config, the platform timer period is kept.
+            program_platform_timer_period(&mut timer_arch);
+            assert_eq!(PROGRAMMED_PERIOD.load(Ordering::SeqCst), 100_000);
+
+            set_platform_timer_period(&TimerPeriod { period: 120 });
+            program_platform_timer_period(&mut timer_arch);
+            assert_eq!(PROGRAMMED_PERIOD.load(Ordering::SeqCst), 150);
+
+            // a platform timer that cannot be reprogrammed keeps its period.
+            timer_arch.set_timer_period = unsupported_set_timer_period;
+            program_platform_timer_period(&mut timer_arch);
+            assert_eq!(PROGRAMMED_PERIOD.load(Ordering::SeqCst), 150);
+
+            set_platform_timer_period(&TimerPeriod { period: 0 });
+        });
+    }
+
+    #[test]
+    fn test_wait_for_event_round_robin() {
+        with_locked_state(|| {
+            CURRENT_TPL.store(efi::TPL_APPLICATION, Ordering::SeqCst);
+            WAIT_FOR_EVENT_START.store(0, Ordering::SeqCst);
+            let mut events: [efi::Event; 3] = [ptr::null_mut(); 3];
+            for event in events.iter_mut() {
+                assert_eq!(create_event(0, efi::TPL_APPLICATION, None, ptr::null_mut(), event), efi::Status::SUCCESS);
+            }
+            let mut index: usize = usize::MAX;
+            let mut wait = || {
+                assert_eq!(wait_for_event(3, events.as_ptr() as *mut efi::Event, &mut index), efi::Status::SUCCESS);
+                index
+            };
+
+            // the first event is signaled before every wait, but the other signaled events are not starved.
+            signal_event(events[0]);
+            signal_event(events[2]);
+            assert_eq!(wait(), 0);
+            signal_event(events[0]);
+            assert_eq!(wait(), 2);
+            signal_event(events[0]);
+            assert_eq!(wait(), 0);
+            signal_event(events[1]);
+            assert_eq!(wait(), 1);
+
+            for event in events {
+                let _ = close_event(event);
+            }
+        });
+    }
+
+    #[test]
+    fn test_wait_for_event_reports_invalid_event_index() {
+        with_locked_state(|| {
+            CURRENT_TPL.store(efi::TPL_APPLICATION, Ordering::SeqCst);
+            let mut wait_event: efi::Event = ptr::null_mut();
+            let mut signal_type_event: efi::Event = ptr::null_mut();
+            create_event(0, efi::TPL_APPLICATION, None, ptr::null_mut(), &mut wait_event);
+            create_event(
+                efi::EVT_NOTIFY_SIGNAL,
+                efi::TPL_NOTIFY,
+                Some(test_notify),
+                ptr::null_mut(),
+                &mut signal_type_event,
+            );
+            signal_event(wait_event);
+
+            // an EVT_NOTIFY_SIGNAL event is rejected even though an earlier event is signaled.
+            let events = [wait_event, signal_type_event];
+            let mut index: usize = usize::MAX;
+            let status = wait_for_event(2, events.as_ptr() as *mut efi::Event, &mut index);
+            assert_eq!(status, efi::Status::INVALID_PARAMETER);
+            assert_eq!(index, 1);
+
+            // the signaled event is left signaled.
+            assert_eq!(check_event(wait_event), efi::Status::SUCCESS);
+
+            let events = [wait_event, 0xdead_usize as efi::Event];
+            let status = wait_for_event(2, events.as_ptr() as *mut efi::Event, &mut index);
+            assert_eq!(status, efi::Status::INVALID_PARAMETER);
+            assert_eq!(index, 1);
+
+            let _ = close_event(wait_event);
+            let _ = close_event(signal_type_event);
+        });
+    }
+
+    #[test]
+    fn test_check_event_notify_wait_semantics() {
+        static WAIT_NOTIFIES: AtomicUsize = AtomicUsize::new(0);
+        extern "efiapi" fn signaling_wait_notify(event: efi::Event, _context: *mut c_void) {
+            WAIT_NOTIFIES.fetch_add(1, Ordering::SeqCst);
+            signal_event(event);
+        }
+
+        with_locked_state(|| {
+            CURRENT_TPL.store(efi::TPL_APPLICATION, Ordering::SeqCst);
+            WAIT_NOTIFIES.store(0, Ordering::SeqCst);
+            let mut event: efi::Event = ptr::null_mut();
+            let status = create_event(
+                efi::EVT_NOTIFY_WAIT,
+                efi::TPL_CALLBACK,
+                Some(signaling_wait_notify),
+                ptr::null_mut(),
+                &mut event,
+            );
+            assert_eq!(status, efi::Status::SUCCESS);
+
+            // the notify function runs when the event is checked and signals it.
+            assert_eq!(check_event(event), efi::Status::SUCCESS);
+            assert_eq!(WAIT_NOTIFIES.load(Ordering::SeqCst), 1);
+
+            // at or above the notify TPL, the notify function is queued but does not run until the TPL is lowered.
+            CURRENT_TPL.store(efi::TPL_CALLBACK, Ordering::SeqCst);
+            assert_eq!(check_event(event), efi::Status::NOT_READY);
+            assert_eq!(check_event(event), efi::Status::NOT_READY);
+            assert_eq!(WAIT_NOTIFIES.load(Ordering::SeqCst), 1);
+            restore_tpl(efi::TPL_APPLICATION);
+            assert_eq!(WAIT_NOTIFIES.load(Ordering::SeqCst), 2);
+            assert_eq!(check_event(event), efi::Status::SUCCESS);
+
+            let _ = close_event(event);
+        });
+    }
+
+    #[test]
+    fn test_check_event_without_notify() {
+        with_locked_state(|| {
+            CURRENT_TPL.store(efi::TPL_APPLICATION, Ordering::SeqCst);
+            let mut event: efi::Event = ptr::null_mut();
+            assert_eq!(create_event(0, efi::TPL_APPLICATION, None, ptr::null_mut(), &mut event), efi::Status::SUCCESS);
+
+            assert_eq!(check_event(event), efi::Status::NOT_READY);
+            signal_event(event);
+            assert_eq!(check_event(event), efi::Status::SUCCESS);
+            assert_eq!(check_event(event), efi::Status::NOT_READY);
+
+            let _ = close_event(event);
+        });
+    }
+
     // Tests for TPL functions
     #[test]
     fn test_raise_tpl_sequence() {
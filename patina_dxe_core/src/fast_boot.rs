@@ -0,0 +1,404 @@
+//! Fast Boot
+//!
+//! Keeps the results that components record in the [BootCache] from one boot to the next, so that a warm boot can
+//! skip connecting all controllers and other slow enumeration. Platforms enable fast boot with
+//! [FastBootPolicy::Enabled], and the core then saves the recorded results with the [PersistedBlob] service at
+//! ReadyToBoot, along with a fingerprint of the hardware.
+//!
+//! The results of the previous boot are only used if the fingerprint of this boot matches: the fingerprint covers the
+//! resource descriptor and CPU HOBs, which change when memory or the processor changes, and the hardware signature
+//! the platform provides, such as one derived from its board and SKU straps. Boot modes that ask for a full
+//! configuration, such as a flash update or recovery boot, also take the full path. The results are loaded once the
+//! [PersistedBlob] service can load them, which for the core's default service is once the variable drivers are
+//! dispatched.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+use patina::{
+    component::service::{IntoService, Service, boot_cache::BootCache, persisted_blob::PersistedBlob},
+    error::EfiError,
+};
+use patina_pi::{
+    BootMode,
+    hob::{Hob, HobList, ResourceDescriptorV2},
+};
+use r_efi::efi;
+use spin::Mutex;
+
+use crate::events::EVENT_DB;
+
+/// Whether the core keeps the [BootCache] across boots.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, FastBootPolicy};
+/// # let physical_hob_list = core::ptr::null();
+/// # let board_id = 0;
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(FastBootPolicy::Enabled { hardware_signature: board_id })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FastBootPolicy {
+    /// Results are not kept across boots, and every boot takes the full path. This is the default.
+    #[default]
+    Disabled,
+    /// Results are kept across boots, and are used while the hardware is unchanged.
+    Enabled {
+        /// A value identifying hardware that the HOBs do not describe, such as the board and SKU. A change in this
+        /// value invalidates the results of the previous boot.
+        hardware_signature: u64,
+    },
+}
+
+/// The GUID the boot cache is saved as with the [PersistedBlob] service.
+pub const FAST_BOOT_CACHE_GUID: efi::Guid =
+    efi::Guid::from_fields(0x8d4f1e2a, 0x6b37, 0x4c90, 0xa5, 0x1e, &[0x73, 0xc2, 0x09, 0xbd, 0x4e, 0x68]);
+
+const SIGNATURE: u32 = u32::from_le_bytes(*b"FBC1");
+
+type Results = Vec<(efi::Guid, Vec<u8>)>;
+
+/// The results of the previous boot.
+enum Previous {
+    /// Not yet loaded, as the persisted blob service was not ready.
+    Unloaded,
+    /// Loaded and valid for this boot.
+    Loaded(Results),
+    /// Missing, or not valid for this boot.
+    Invalid,
+}
+
+struct Cache {
+    fingerprint: u64,
+    blobs: Service<dyn PersistedBlob>,
+    previous: Previous,
+    current: Results,
+    invalidated: bool,
+}
+
+// Safety: the cache is only used from the boot services thread, and is protected by the lock, so it is safe to mark
+// it send.
+struct State(Option<Cache>);
+unsafe impl Send for State {}
+
+static CACHE: Mutex<State> = Mutex::new(State(None));
+
+/// Enables the boot cache, if `policy` enables fast boot.
+pub(crate) fn enable(policy: &FastBootPolicy, hob_list: &HobList, blobs: Service<dyn PersistedBlob>) {
+    let FastBootPolicy::Enabled { hardware_signature } = *policy else {
+        return;
+    };
+
+    let previous = match boot_mode(hob_list) {
+        Some(
+            mode @ (BootMode::BootWithFullConfigurationPlusDiagnostic
+            | BootMode::BootWithDefaultSettings
+            | BootMode::BootWithMfgModeSettings
+            | BootMode::BootOnFlashUpdate
+            | BootMode::BootInRecoveryMode),
+        ) => {
+            log::info!("Fast boot: not using the previous boot's results in boot mode {mode}.");
+            Previous::Invalid
+        }
+        _ => Previous::Unloaded,
+    };
+    let fingerprint = fingerprint(hardware_signature, hob_list);
+    log::info!("Fast boot enabled, hardware fingerprint {fingerprint:#018x}.");
+    CACHE.lock().0 = Some(Cache { fingerprint, blobs, previous, current: Vec::new(), invalidated: false });
+
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(save_event),
+        None,
+        Some(efi::EVENT_GROUP_READY_TO_BOOT),
+    ) {
+        log::error!("Failed to register an event to save the boot cache! Status {status:#X?}");
+    }
+}
+
+fn boot_mode(hob_list: &HobList) -> Option<BootMode> {
+    hob_list.iter().find_map(|hob| match hob {
+        Hob::Handoff(handoff) => Some(handoff.boot_mode),
+        _ => None,
+    })
+}
+
+// A FNV-1a hash of the hardware signature, and the HOBs that describe the hardware.
+fn fingerprint(hardware_signature: u64, hob_list: &HobList) -> u64 {
+    let mut values = Vec::from([hardware_signature]);
+    for hob in hob_list.iter() {
+        let resource = match hob {
+            Hob::ResourceDescriptor(resource) => ResourceDescriptorV2::from(**resource),
+            Hob::ResourceDescriptorV2(resource) => **resource,
+            Hob::Cpu(cpu) => {
+                values.extend([cpu.size_of_memory_space as u64, cpu.size_of_io_space as u64]);
+                continue;
+            }
+            _ => continue,
+        };
+        values.extend([
+            resource.v1.resource_type as u64,
+            resource.v1.resource_attribute as u64,
+            resource.v1.physical_start,
+            resource.v1.resource_length,
+        ]);
+    }
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+// The cache is saved as its signature, the number of results, and the fingerprint, followed by the GUID, length and
+// data of each result.
+fn serialize(fingerprint: u64, results: &Results) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&SIGNATURE.to_le_bytes());
+    data.extend_from_slice(&(results.len() as u32).to_le_bytes());
+    data.extend_from_slice(&fingerprint.to_le_bytes());
+    for (class, result) in results {
+        data.extend_from_slice(class.as_bytes());
+        data.extend_from_slice(&(result.len() as u32).to_le_bytes());
+        data.extend_from_slice(result);
+    }
+    data
+}
+
+// Returns the results in `data`, if it is a valid cache for the hardware with `fingerprint`.
+fn deserialize(fingerprint: u64, data: &[u8]) -> Option<Results> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        let (head, tail) = data.split_at_checked(len)?;
+        *data = tail;
+        Some(head)
+    }
+    let u32_at = |data: &mut &[u8]| Some(u32::from_le_bytes(take(data, 4)?.try_into().ok()?));
+
+    let mut data = data;
+    if u32_at(&mut data)? != SIGNATURE {
+        return None;
+    }
+    let count = u32_at(&mut data)?;
+    if u64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?) != fingerprint {
+        log::info!("Fast boot: the hardware changed since the previous boot.");
+        return None;
+    }
+    let mut results = Vec::new();
+    for _ in 0..count {
+        let class = efi::Guid::from_bytes(take(&mut data, 16)?.try_into().ok()?);
+        let len = u32_at(&mut data)? as usize;
+        results.push((class, take(&mut data, len)?.to_vec()));
+    }
+    data.is_empty().then_some(results)
+}
+
+impl Cache {
+    /// Returns the results of the previous boot, loading them if needed, if they are valid for this boot.
+    fn previous(&mut self) -> Option<&Results> {
+        if matches!(self.previous, Previous::Unloaded) {
+            self.previous = match self.blobs.load(&FAST_BOOT_CACHE_GUID) {
+                Ok(data) => match deserialize(self.fingerprint, &data) {
+                    Some(results) => Previous::Loaded(results),
+                    None => Previous::Invalid,
+                },
+                Err(EfiError::NotFound) => Previous::Invalid,
+                // the service is not ready yet, so try again later.
+                Err(_) => return None,
+            };
+        }
+        match &self.previous {
+            Previous::Loaded(results) => Some(results),
+            _ => None,
+        }
+    }
+}
+
+extern "efiapi" fn save_event(event: efi::Event, _context: *mut c_void) {
+    let _ = EVENT_DB.close_event(event);
+    save();
+}
+
+/// Saves the results recorded in this boot, or deletes the saved results if they were invalidated.
+fn save() {
+    let mut state = CACHE.lock();
+    let Some(cache) = state.0.as_mut() else {
+        return;
+    };
+    let result = match cache.invalidated {
+        true => cache.blobs.delete(&FAST_BOOT_CACHE_GUID),
+        false => cache.blobs.save(&FAST_BOOT_CACHE_GUID, &serialize(cache.fingerprint, &cache.current)),
+    };
+    match result {
+        Ok(()) => log::info!("Fast boot: saved {} results for the next boot.", cache.current.len()),
+        Err(err) => log::error!("Fast boot: failed to save the boot cache: {err:?}"),
+    }
+}
+
+/// The core's [BootCache] service. Nothing is cached unless fast boot is enabled.
+#[derive(IntoService)]
+#[service(dyn BootCache)]
+pub(crate) struct CoreBootCache;
+
+impl BootCache for CoreBootCache {
+    fn is_valid(&self) -> bool {
+        CACHE.lock().0.as_mut().is_some_and(|cache| cache.previous().is_some())
+    }
+
+    fn get(&self, class: &efi::Guid) -> Option<Vec<u8>> {
+        let mut state = CACHE.lock();
+        let results = state.0.as_mut()?.previous()?;
+        results.iter().find(|(other, _)| other == class).map(|(_, result)| result.clone())
+    }
+
+    fn set(&self, class: &efi::Guid, data: &[u8]) {
+        let mut state = CACHE.lock();
+        let Some(cache) = state.0.as_mut() else {
+            return;
+        };
+        match cache.current.iter_mut().find(|(other, _)| other == class) {
+            Some((_, result)) => *result = data.to_vec(),
+            None => cache.current.push((*class, data.to_vec())),
+        }
+    }
+
+    fn invalidate(&self) {
+        if let Some(cache) = CACHE.lock().0.as_mut() {
+            log::info!("Fast boot: the previous boot's results were invalidated.");
+            cache.previous = Previous::Invalid;
+            cache.invalidated = true;
+        }
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use patina::component::service::persisted_blob::MockPersistedBlob;
+    use patina_pi::hob::{self, PhaseHandoffInformationTable, ResourceDescriptor};
+    use std::{boxed::Box, sync::Arc};
+
+    const CLASS: efi::Guid = patina::component::service::boot_cache::CONNECTED_CONSOLES;
+
+    fn handoff(boot_mode: BootMode) -> PhaseHandoffInformationTable {
+        PhaseHandoffInformationTable {
+            header: hob::header::Hob {
+                r#type: hob::HANDOFF,
+                length: size_of::<PhaseHandoffInformationTable>() as u16,
+                reserved: 0,
+            },
+            version: 0x00010000,
+            boot_mode,
+            memory_top: 0,
+            memory_bottom: 0,
+            free_memory_top: 0,
+            free_memory_bottom: 0,
+            end_of_hob_list: 0,
+        }
+    }
+
+    fn memory(length: u64) -> ResourceDescriptor {
+        ResourceDescriptor {
+            header: hob::header::Hob {
+                r#type: hob::RESOURCE_DESCRIPTOR,
+                length: size_of::<ResourceDescriptor>() as u16,
+                reserved: 0,
+            },
+            owner: patina::guids::ZERO,
+            resource_type: hob::EFI_RESOURCE_SYSTEM_MEMORY,
+            resource_attribute: hob::EFI_RESOURCE_ATTRIBUTE_PRESENT,
+            physical_start: 0,
+            resource_length: length,
+        }
+    }
+
+    // Returns a persisted blob service backed by `stored`.
+    fn blobs(stored: &Arc<spin::Mutex<Option<Vec<u8>>>>) -> Service<dyn PersistedBlob> {
+        let mut blobs = MockPersistedBlob::new();
+        let load = stored.clone();
+        blobs.expect_load().returning(move |_| load.lock().clone().ok_or(EfiError::NotFound));
+        let save = stored.clone();
+        blobs.expect_save().returning(move |_, data| {
+            *save.lock() = Some(data.to_vec());
+            Ok(())
+        });
+        let delete = stored.clone();
+        blobs.expect_delete().returning(move |_| {
+            *delete.lock() = None;
+            Ok(())
+        });
+        Service::mock(Box::new(blobs))
+    }
+
+    // Simulates a boot on hardware with `memory_length` bytes of memory.
+    fn boot(stored: &Arc<spin::Mutex<Option<Vec<u8>>>>, memory_length: u64, boot_mode: BootMode) -> bool {
+        let (handoff, memory) = (handoff(boot_mode), memory(memory_length));
+        let mut hob_list = HobList::new();
+        hob_list.push(Hob::Handoff(&handoff));
+        hob_list.push(Hob::ResourceDescriptor(&memory));
+        enable(&FastBootPolicy::Enabled { hardware_signature: 1 }, &hob_list, blobs(stored));
+        CoreBootCache.is_valid()
+    }
+
+    #[test]
+    fn test_results_are_kept_while_the_hardware_is_unchanged() {
+        test_support::with_global_lock(|| {
+            let stored = Arc::new(spin::Mutex::new(None));
+
+            // the first boot takes the full path.
+            assert!(!boot(&stored, 0x1000_0000, BootMode::BootWithFullConfiguration));
+            assert_eq!(CoreBootCache.get(&CLASS), None);
+            CoreBootCache.set(&CLASS, b"consoles");
+            save();
+
+            assert!(boot(&stored, 0x1000_0000, BootMode::BootWithFullConfiguration));
+            assert_eq!(CoreBootCache.get(&CLASS).as_deref(), Some(b"consoles".as_slice()));
+            CoreBootCache.set(&CLASS, b"consoles");
+            save();
+
+            // a flash update boot takes the full path, but the results are still saved.
+            assert!(!boot(&stored, 0x1000_0000, BootMode::BootOnFlashUpdate));
+            CoreBootCache.set(&CLASS, b"consoles");
+            save();
+
+            // changing the memory changes the fingerprint.
+            assert!(!boot(&stored, 0x2000_0000, BootMode::BootWithFullConfiguration));
+            CoreBootCache.set(&CLASS, b"consoles");
+            save();
+            assert!(boot(&stored, 0x2000_0000, BootMode::BootWithFullConfiguration));
+
+            // invalidated results are not used, and are not saved for the next boot.
+            CoreBootCache.invalidate();
+            assert_eq!(CoreBootCache.get(&CLASS), None);
+            save();
+            assert!(stored.lock().is_none());
+
+            CACHE.lock().0 = None;
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_rejects_malformed_caches() {
+        let results = Vec::from([(CLASS, Vec::from(*b"data"))]);
+        let data = serialize(7, &results);
+        assert_eq!(deserialize(7, &data), Some(results));
+        assert_eq!(deserialize(8, &data), None);
+        assert_eq!(deserialize(7, &data[..data.len() - 1]), None);
+        assert_eq!(deserialize(7, &[data.as_slice(), &[0]].concat()), None);
+        assert_eq!(deserialize(7, &[0u8; 4]), None);
+    }
+}
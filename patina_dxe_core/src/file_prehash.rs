@@ -0,0 +1,182 @@
+//! FFS File Pre-Hashing
+//!
+//! Measured boot and UEFI Secure Boot handlers hash each driver image when it is loaded. If the platform produces the
+//! [FileDigest] service, the dispatcher queues each driver image for hashing as it is discovered, and the queue is
+//! worked off one file at a time while the core spins in WaitForEvent, e.g. while a driver waits for hardware. The
+//! digests are handed to measurement and verification handlers through the [PrecomputedFileDigests] service, so that
+//! hashing overlaps with other boot work instead of adding to driver load time.
+//!
+//! Files are identified by the address and length of the buffer that holds them, so a file must be forgotten with
+//! [forget_file] before that buffer is freed.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use patina::component::service::{
+    IntoService, Service,
+    file_digest::{FileDigest, PrecomputedFileDigests},
+};
+use r_efi::efi;
+
+use crate::tpl_lock::TplMutex;
+
+// The address and length of the buffer holding a file.
+type FileKey = (usize, usize);
+
+fn file_key(data: &[u8]) -> FileKey {
+    (data.as_ptr() as usize, data.len())
+}
+
+struct PrehashState {
+    file_digest: Option<Service<dyn FileDigest>>,
+    queue: VecDeque<FileKey>,
+    digests: BTreeMap<FileKey, Vec<u8>>,
+}
+
+impl PrehashState {
+    const fn new() -> Self {
+        Self { file_digest: None, queue: VecDeque::new(), digests: BTreeMap::new() }
+    }
+}
+
+// Safety: access to the state is only through the mutex guard, so it is safe to mark it send.
+unsafe impl Send for PrehashState {}
+
+static STATE: TplMutex<PrehashState> = TplMutex::new(efi::TPL_NOTIFY, PrehashState::new(), "PrehashLock");
+
+// Set while a file is being hashed, in case the digest implementation waits on an event itself.
+static HASHING: AtomicBool = AtomicBool::new(false);
+
+/// Registers the service used to pre-hash files. Files are only queued once this is registered.
+pub(crate) fn register_file_digest(file_digest: Service<dyn FileDigest>) {
+    STATE.lock().file_digest = Some(file_digest);
+}
+
+/// Queues `data` to be hashed when the core is idle.
+///
+/// `data` must remain valid until it is passed to [forget_file].
+pub(crate) fn queue_file(data: &[u8]) {
+    let mut state = STATE.lock();
+    let key = file_key(data);
+    if state.file_digest.is_some() && !state.digests.contains_key(&key) && !state.queue.contains(&key) {
+        state.queue.push_back(key);
+    }
+}
+
+/// Drops the queue entry and digest for `data`, e.g. once its image is loaded or before its buffer is freed.
+pub(crate) fn forget_file(data: &[u8]) {
+    let mut state = STATE.lock();
+    let key = file_key(data);
+    state.queue.retain(|queued| *queued != key);
+    state.digests.remove(&key);
+}
+
+/// Hashes the next queued file, if any. Called while the core has nothing else to do.
+pub(crate) fn hash_next_file() {
+    if HASHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    // the lock is not held while hashing, so that the digest implementation can use boot services.
+    let next = {
+        let mut state = STATE.lock();
+        state.file_digest.clone().zip(state.queue.pop_front())
+    };
+
+    if let Some((file_digest, key @ (address, len))) = next {
+        // Safety: queued files remain valid until they are forgotten, which also removes them from the queue.
+        let data = unsafe { core::slice::from_raw_parts(address as *const u8, len) };
+        match file_digest.digest(data) {
+            Ok(digest) => {
+                STATE.lock().digests.insert(key, digest);
+            }
+            Err(err) => log::warn!("Failed to pre-hash file at {address:#x} of length {len:#x}: {err:?}"),
+        }
+    }
+
+    HASHING.store(false, Ordering::SeqCst);
+}
+
+/// Produces the [PrecomputedFileDigests] service from the digests computed by [hash_next_file].
+#[derive(IntoService)]
+#[service(dyn PrecomputedFileDigests)]
+pub(crate) struct CorePrecomputedFileDigests;
+
+impl PrecomputedFileDigests for CorePrecomputedFileDigests {
+    fn precomputed_digest(&self, data: &[u8]) -> Option<Vec<u8>> {
+        STATE.lock().digests.get(&file_key(data)).cloned()
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use alloc::{boxed::Box, vec};
+    use patina::error::EfiError;
+
+    use crate::test_support;
+
+    // "hashes" a file into four copies of its first byte, failing for files that start with 3.
+    struct TestFileDigest;
+
+    impl FileDigest for TestFileDigest {
+        fn digest(&self, data: &[u8]) -> Result<Vec<u8>, EfiError> {
+            match data[0] {
+                3 => Err(EfiError::DeviceError),
+                value => Ok(vec![value; 4]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_files_are_hashed_in_queue_order_until_forgotten() {
+        test_support::with_global_lock(|| {
+            *STATE.lock() = PrehashState::new();
+            let first = [1u8; 16];
+            let second = [2u8; 32];
+            let failing = [3u8; 8];
+
+            // nothing is queued until a digest service is registered.
+            queue_file(&first);
+            assert!(STATE.lock().queue.is_empty());
+
+            register_file_digest(Service::mock(Box::new(TestFileDigest)));
+
+            queue_file(&first);
+            queue_file(&second);
+            queue_file(&first);
+            queue_file(&failing);
+            assert_eq!(STATE.lock().queue.len(), 3);
+
+            hash_next_file();
+            assert_eq!(CorePrecomputedFileDigests.precomputed_digest(&first), Some(vec![1; 4]));
+            assert_eq!(CorePrecomputedFileDigests.precomputed_digest(&second), None);
+
+            // a copy of a hashed file is not recognized.
+            let copy = first;
+            assert_eq!(CorePrecomputedFileDigests.precomputed_digest(&copy), None);
+
+            forget_file(&second);
+            hash_next_file();
+            assert_eq!(CorePrecomputedFileDigests.precomputed_digest(&second), None);
+            assert_eq!(CorePrecomputedFileDigests.precomputed_digest(&failing), None);
+            assert!(STATE.lock().queue.is_empty());
+
+            forget_file(&first);
+            assert_eq!(CorePrecomputedFileDigests.precomputed_digest(&first), None);
+            *STATE.lock() = PrehashState::new();
+        })
+        .unwrap();
+    }
+}
@@ -815,6 +815,23 @@ pub fn device_path_bytes_for_fv_file(fv_handle: efi::Handle, file_name: efi::Gui
     )
 }
 
+/// Returns true if `data` lies entirely within an installed firmware volume.
+pub(crate) fn is_fv_resident(data: &[u8]) -> bool {
+    let start = data.as_ptr() as u64;
+    let Some(end) = start.checked_add(data.len() as u64) else {
+        return false;
+    };
+
+    PRIVATE_FV_DATA.lock().fv_information.values().any(|item| match item {
+        PrivateDataItem::FvData(fv_data) => {
+            // Safety: fv_data.physical_address must point to a valid FV (i.e. private_data is correctly constructed).
+            unsafe { VolumeRef::new_from_address(fv_data.physical_address) }
+                .is_ok_and(|fv| start >= fv_data.physical_address && end <= fv_data.physical_address + fv.size())
+        }
+        PrivateDataItem::FvbData(_) => false,
+    })
+}
+
 /// Parse the FVs defined in the HOB list.
 pub fn parse_hob_fvs(hob_list: &hob::HobList) -> Result<(), efi::Status> {
     let fv_hobs = hob_list.iter().filter_map(|h| if let hob::Hob::FirmwareVolume(fv) = h { Some(*fv) } else { None });
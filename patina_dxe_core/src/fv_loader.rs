@@ -0,0 +1,218 @@
+//! DXE Core Firmware Volume Loader
+//!
+//! Installs firmware volumes provided in memory buffers at runtime of the DXE phase, such as a firmware volume read
+//! from the ESP by a shell application during recovery, or one downloaded during driver development. The firmware
+//! volume is copied into memory owned by the core, authenticated with the Security2 Architectural Protocol, installed
+//! and its drivers dispatched.
+//!
+//! The functionality is available to the platform through [install_fv_from_buffer] and to drivers and applications
+//! through the [FvLoaderProtocol].
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec};
+use core::{ffi::c_void, mem::size_of, ptr, slice};
+
+use patina::{
+    boot_services::BootServices,
+    component::{IntoComponent, Storage},
+    error::EfiError,
+    uefi_protocol::fv_loader::FvLoaderProtocol,
+};
+use patina_ffs::volume::VolumeRef;
+use r_efi::efi;
+
+use crate::{dispatcher::core_dispatcher, fv::core_install_firmware_volume, protocols::PROTOCOL_DB};
+
+/// Component to install the [FvLoaderProtocol].
+#[derive(IntoComponent, Default)]
+pub(crate) struct FvLoaderProtocolInstaller;
+
+impl FvLoaderProtocolInstaller {
+    fn entry_point(self, storage: &mut Storage) -> patina::error::Result<()> {
+        let protocol = Box::new(FvLoaderProtocol::new(efi_install_fv_from_buffer));
+
+        match storage.boot_services().install_protocol_interface(None, protocol) {
+            Ok(_) => Ok(()),
+            Err(err) => EfiError::status_to_result(err),
+        }
+    }
+}
+
+// authenticate the firmware volume image via the Security2 Architectural Protocol
+fn authenticate(image: &[u8]) -> Result<(), EfiError> {
+    let security2_protocol = unsafe {
+        match PROTOCOL_DB.locate_protocol(patina_pi::protocols::security2::PROTOCOL_GUID) {
+            Ok(protocol) => (protocol as *mut patina_pi::protocols::security2::Protocol)
+                .as_ref()
+                .expect("Security2 Protocol should not be null"),
+            //If security2 protocol is not located, then assume it has not yet been produced and implicitly trust the
+            //Firmware Volume.
+            Err(_) => {
+                log::warn!("Security2 Architectural Protocol not installed; trusting firmware volume from buffer.");
+                return Ok(());
+            }
+        }
+    };
+
+    //The buffer did not originate from a device, so no device path is available to describe it.
+    let status = (security2_protocol.file_authentication)(
+        security2_protocol as *const _ as *mut patina_pi::protocols::security2::Protocol,
+        ptr::null_mut(),
+        image.as_ptr() as *mut c_void,
+        image.len(),
+        false,
+    );
+    EfiError::status_to_result(status)
+}
+
+/// Installs the firmware volume in `buffer` and dispatches the drivers it contains.
+///
+/// The firmware volume is copied into memory owned by the core, so the caller may free `buffer` once this function
+/// returns. The copy is validated as a firmware volume and authenticated with the Security2 Architectural Protocol, if
+/// it has been installed, before it is installed. If the dispatcher is already running, such as when called from a
+/// driver entry point, the drivers are dispatched by the running dispatcher instead.
+///
+/// Returns the handle of the installed firmware volume.
+pub fn install_fv_from_buffer(buffer: &[u8]) -> Result<efi::Handle, EfiError> {
+    // Copy into an 8 byte aligned allocation, as required by the firmware file system. The copy is leaked as the
+    // installed firmware volume must remain valid for the remainder of boot.
+    let mut storage = vec![0u64; buffer.len().div_ceil(size_of::<u64>())].into_boxed_slice();
+    // Safety: storage is at least buffer.len() bytes long.
+    let image = unsafe { slice::from_raw_parts_mut(storage.as_mut_ptr() as *mut u8, buffer.len()) };
+    image.copy_from_slice(buffer);
+
+    VolumeRef::new(image)?;
+    authenticate(image).inspect_err(|err| log::error!("Firmware volume from buffer failed authentication: {err:?}"))?;
+
+    let base_address = Box::leak(storage).as_ptr() as u64;
+    // Safety: base_address is a validated firmware volume that is never freed.
+    let handle = unsafe { core_install_firmware_volume(base_address, None)? };
+    log::info!("Installed firmware volume from buffer at {base_address:#x} with handle {handle:?}.");
+
+    match core_dispatcher() {
+        Ok(()) | Err(EfiError::NotFound) | Err(EfiError::AlreadyStarted) => Ok(handle),
+        Err(err) => Err(err),
+    }
+}
+
+extern "efiapi" fn efi_install_fv_from_buffer(
+    _this: *mut FvLoaderProtocol,
+    buffer: *const c_void,
+    size: usize,
+    firmware_volume_handle: *mut efi::Handle,
+) -> efi::Status {
+    if buffer.is_null() || firmware_volume_handle.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+
+    // Safety: caller must ensure that buffer is valid for size bytes. It is null-checked above.
+    let buffer = unsafe { slice::from_raw_parts(buffer as *const u8, size) };
+    match install_fv_from_buffer(buffer) {
+        Ok(handle) => {
+            // Safety: caller must ensure that firmware_volume_handle is a valid pointer. It is null-checked above.
+            unsafe { firmware_volume_handle.write_unaligned(handle) };
+            efi::Status::SUCCESS
+        }
+        Err(err) => err.into(),
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::{test_collateral, test_support};
+    use std::{fs::File, io::Read, vec::Vec};
+
+    fn with_locked_state<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
+        test_support::with_global_lock(|| {
+            unsafe { test_support::init_test_protocol_db() };
+            f();
+        })
+        .unwrap();
+    }
+
+    fn read_test_fv() -> Vec<u8> {
+        let mut file = File::open(test_collateral!("DXEFV.Fv")).unwrap();
+        let mut fv: Vec<u8> = Vec::new();
+        file.read_to_end(&mut fv).expect("failed to read test file");
+        fv
+    }
+
+    #[test]
+    fn test_install_fv_from_buffer() {
+        let fv = read_test_fv();
+        with_locked_state(|| {
+            let mut handle: efi::Handle = ptr::null_mut();
+            let status = efi_install_fv_from_buffer(
+                ptr::null_mut(),
+                fv.as_ptr() as *const c_void,
+                fv.len(),
+                &mut handle as *mut efi::Handle,
+            );
+            assert_eq!(status, efi::Status::SUCCESS);
+            assert!(!handle.is_null());
+
+            // The firmware volume is installed from a copy of the buffer.
+            let fvb = PROTOCOL_DB
+                .get_interface_for_handle(handle, patina_pi::protocols::firmware_volume_block::PROTOCOL_GUID)
+                .unwrap() as *mut patina_pi::protocols::firmware_volume_block::Protocol;
+            let mut address = 0;
+            assert_eq!(unsafe { ((*fvb).get_physical_address)(fvb, &mut address) }, efi::Status::SUCCESS);
+            assert_ne!(address, fv.as_ptr() as u64);
+            assert_eq!(unsafe { slice::from_raw_parts(address as *const u8, fv.len()) }, fv.as_slice());
+        });
+    }
+
+    #[test]
+    fn test_install_fv_from_buffer_rejects_invalid_input() {
+        with_locked_state(|| {
+            let mut handle: efi::Handle = ptr::null_mut();
+            let bad_buffer = [0u8; 16];
+            assert_eq!(install_fv_from_buffer(&bad_buffer), Err(EfiError::VolumeCorrupted));
+            assert_eq!(
+                efi_install_fv_from_buffer(ptr::null_mut(), ptr::null(), 0, &mut handle),
+                efi::Status::INVALID_PARAMETER
+            );
+            assert_eq!(
+                efi_install_fv_from_buffer(ptr::null_mut(), bad_buffer.as_ptr() as *const c_void, 16, ptr::null_mut()),
+                efi::Status::INVALID_PARAMETER
+            );
+        });
+    }
+
+    #[test]
+    fn test_install_fv_from_buffer_with_security2_denial() {
+        extern "efiapi" fn deny(
+            _this: *mut patina_pi::protocols::security2::Protocol,
+            file: *mut efi::protocols::device_path::Protocol,
+            file_buffer: *mut c_void,
+            file_size: usize,
+            _boot_policy: bool,
+        ) -> efi::Status {
+            assert!(file.is_null());
+            assert!(!file_buffer.is_null());
+            assert_ne!(file_size, 0);
+            efi::Status::ACCESS_DENIED
+        }
+
+        let fv = read_test_fv();
+        with_locked_state(|| {
+            let security2 = patina_pi::protocols::security2::Protocol { file_authentication: deny };
+            PROTOCOL_DB
+                .install_protocol_interface(
+                    None,
+                    patina_pi::protocols::security2::PROTOCOL_GUID,
+                    &security2 as *const _ as *mut _,
+                )
+                .unwrap();
+
+            assert_eq!(install_fv_from_buffer(&fv), Err(EfiError::AccessDenied));
+        });
+    }
+}
@@ -218,14 +218,25 @@ pub fn add_hob_resource_descriptors_to_gcd(hob_list: &HobList) {
                 log::info!(
                     "Mapping memory range {split_range:#x?} as {gcd_mem_type:?} with attributes {resource_attributes:#x?}",
                 );
-                unsafe {
+                let result = unsafe {
                     GCD.add_memory_space(
                         gcd_mem_type,
                         split_range.start as usize,
                         split_range.end.saturating_sub(split_range.start) as usize,
                         spin_locked_gcd::get_capabilities(gcd_mem_type, resource_attributes as u64),
                     )
-                    .expect("Failed to add memory space to GCD");
+                };
+                match result {
+                    Ok(_) => (),
+                    // 64-bit MMIO apertures may be described beyond the address width in the CPU HOB. They cannot be
+                    // used, but that should not prevent boot.
+                    Err(EfiError::Unsupported) if gcd_mem_type == GcdMemoryType::MemoryMappedIo => {
+                        log::error!(
+                            "MMIO range {split_range:#x?} is beyond the address width of the CPU HOB, ignoring it."
+                        );
+                        continue;
+                    }
+                    Err(err) => panic!("Failed to add memory space to GCD: {err:?}"),
                 }
                 if let Some(attributes) = memory_attributes {
                     match GCD.set_memory_space_attributes(
@@ -8,7 +8,11 @@
 //!
 use crate::pecoff::{self, UefiPeInfo};
 use alloc::{boxed::Box, slice, vec, vec::Vec};
-use core::{fmt::Display, ptr};
+use core::{
+    fmt::Display,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
 use patina::{base::DEFAULT_CACHE_ATTR, error::EfiError};
 
 use mu_rust_helpers::function;
@@ -25,8 +29,14 @@ use patina_pi::{
 use r_efi::efi;
 
 use crate::{
-    GCD, allocator::DEFAULT_ALLOCATION_STRATEGY, ensure, error, events::EVENT_DB, protocol_db,
-    protocol_db::INVALID_HANDLE, tpl_lock,
+    GCD,
+    allocator::DEFAULT_ALLOCATION_STRATEGY,
+    ensure, error,
+    events::EVENT_DB,
+    memory_attribute_journal, protocol_db,
+    protocol_db::INVALID_HANDLE,
+    tlb_shootdown::{TlbShootdownScope, request_tlb_shootdown},
+    tpl_lock,
 };
 use patina_internal_cpu::paging::create_cpu_paging;
 use patina_paging::{MemoryAttributes, PageTable, PtError, PtResult, page_allocator::PageAllocator};
@@ -48,6 +58,37 @@ const IO_BLOCK_SLICE_SIZE: usize = IO_BLOCK_SLICE_LEN * node_size::<IoBlock>();
 
 const PAGE_POOL_CAPACITY: usize = 512;
 
+// The most page table allocations that can be waiting to be mapped read-only. Allocations are tracked in a fixed
+// array, as they are made with the page table lock held and so cannot allocate from the heap.
+const MAX_UNPROTECTED_PAGE_TABLE_RANGES: usize = 32;
+
+// Page table pages that have been allocated but not yet mapped read-only.
+#[derive(Clone, Copy)]
+struct UnprotectedPageTableRanges {
+    ranges: [(usize, usize); MAX_UNPROTECTED_PAGE_TABLE_RANGES],
+    count: usize,
+}
+
+impl UnprotectedPageTableRanges {
+    const fn new() -> Self {
+        Self { ranges: [(0, 0); MAX_UNPROTECTED_PAGE_TABLE_RANGES], count: 0 }
+    }
+
+    fn push(&mut self, base_address: usize, len: usize) {
+        if self.count == MAX_UNPROTECTED_PAGE_TABLE_RANGES {
+            log::error!("Too many page table allocations to track, {base_address:#x?} will not be mapped read-only.");
+            return;
+        }
+        self.ranges[self.count] = (base_address, len);
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        self.count = self.count.checked_sub(1)?;
+        Some(self.ranges[self.count])
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum InternalError {
     MemoryBlock(MemoryBlockError),
@@ -74,7 +115,7 @@ struct GcdAttributeConversionEntry {
     memory: bool,
 }
 
-const ATTRIBUTE_CONVERSION_TABLE: [GcdAttributeConversionEntry; 15] = [
+const ATTRIBUTE_CONVERSION_TABLE: [GcdAttributeConversionEntry; 16] = [
     GcdAttributeConversionEntry {
         attribute: hob::EFI_RESOURCE_ATTRIBUTE_UNCACHEABLE,
         capability: efi::MEMORY_UC,
@@ -145,6 +186,11 @@ const ATTRIBUTE_CONVERSION_TABLE: [GcdAttributeConversionEntry; 15] = [
         capability: hob::EFI_MEMORY_MORE_RELIABLE,
         memory: true,
     },
+    GcdAttributeConversionEntry {
+        attribute: hob::EFI_RESOURCE_ATTRIBUTE_SPECIAL_PURPOSE,
+        capability: efi::MEMORY_SP,
+        memory: true,
+    },
     GcdAttributeConversionEntry { attribute: 0, capability: 0, memory: false },
 ];
 
@@ -220,8 +266,8 @@ impl PageAllocator for PagingAllocator<'_> {
                 protocol_db::EFI_BOOT_SERVICES_DATA_ALLOCATOR_HANDLE,
                 None,
             );
-            match res {
-                Ok(root_page) => Ok(root_page as u64),
+            let root_page = match res {
+                Ok(root_page) => root_page as u64,
                 Err(_) => {
                     // if we failed, try again with normal allocation
                     log::error!(
@@ -236,14 +282,16 @@ impl PageAllocator for PagingAllocator<'_> {
                         protocol_db::EFI_BOOT_SERVICES_DATA_ALLOCATOR_HANDLE,
                         None,
                     ) {
-                        Ok(root_page) => Ok(root_page as u64),
+                        Ok(root_page) => root_page as u64,
                         Err(e) => {
                             // okay we are good and dead now
                             panic!("Failed to allocate root page for the page table page pool: {e:?}");
                         }
                     }
                 }
-            }
+            };
+            self.gcd.unprotected_page_table_pages.lock().push(root_page as usize, uefi_pages_to_size!(len));
+            Ok(root_page)
         } else {
             match self.page_pool.pop() {
                 Some(page) => Ok(page),
@@ -262,6 +310,7 @@ impl PageAllocator for PagingAllocator<'_> {
                         None,
                     ) {
                         Ok(addr) => {
+                            self.gcd.unprotected_page_table_pages.lock().push(addr, uefi_pages_to_size!(len));
                             for i in 0..len {
                                 self.page_pool.push(addr as u64 + ((i * UEFI_PAGE_SIZE) as u64));
                             }
@@ -284,20 +333,10 @@ struct GCD {
     memory_blocks: Rbt<'static, MemoryBlock>,
     allocate_memory_space_fn: GcdAllocateFn,
     free_memory_space_fn: GcdFreeFn,
-    /// Default attributes for memory allocations
-    /// This is efi::MEMORY_XP unless we have entered compatibility mode, in which case it is 0, e.g. no protection
-    default_attributes: u64,
     /// Whether to prioritize 32-bit memory allocations
     prioritize_32_bit_memory: bool,
 }
 
-impl GCD {
-    /// Returns true if the GCD is initialized and ready for use.
-    pub fn is_ready(&self) -> bool {
-        self.maximum_address != 0
-    }
-}
-
 impl core::fmt::Debug for GCD {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("GCD")
@@ -317,7 +356,6 @@ impl GCD {
             maximum_address: 1 << processor_address_bits,
             allocate_memory_space_fn: Self::allocate_memory_space_internal,
             free_memory_space_fn: Self::free_memory_space_worker,
-            default_attributes: efi::MEMORY_XP,
             prioritize_32_bit_memory: false,
         }
     }
@@ -679,7 +717,8 @@ impl GCD {
             }
             ensure!(addr + len <= max_address, EfiError::NotFound);
 
-            if mb.as_ref().memory_type != memory_type {
+            // special-purpose memory is left for the agents that own it, which allocate it by address.
+            if mb.as_ref().memory_type != memory_type || mb.as_ref().capabilities & efi::MEMORY_SP != 0 {
                 current = memory_blocks.next_idx(idx);
                 continue;
             }
@@ -768,7 +807,8 @@ impl GCD {
                 continue;
             }
 
-            if mb.as_ref().memory_type != memory_type {
+            // special-purpose memory is left for the agents that own it, which allocate it by address.
+            if mb.as_ref().memory_type != memory_type || mb.as_ref().capabilities & efi::MEMORY_SP != 0 {
                 current = memory_blocks.prev_idx(idx);
                 continue;
             }
@@ -1146,15 +1186,6 @@ impl GCD {
         self.memory_blocks.len()
     }
 
-    #[cfg(feature = "compatibility_mode_allowed")]
-    /// This function activates compatibility mode for the GCD, which is just to set the default attributes to 0,
-    /// which will prevent new memory from being allocated as non-executable. This function is purposefully not set
-    /// to be pub(crate) because the only caller of it is SpinLockedGcd.activate_compatibility_mode(). And this should
-    /// not be called except by that function.
-    fn activate_compatibility_mode(&mut self) {
-        self.default_attributes = 0;
-    }
-
     //Note: truncated strings here are expected and are for alignment with EDK2 reference prints.
     const GCD_MEMORY_TYPE_NAMES: [&'static str; 8] = [
         "NonExist ", // EfiGcdMemoryTypeNonExistent
@@ -1302,7 +1333,7 @@ impl IoGCD {
     ) -> Result<usize, EfiError> {
         ensure!(self.maximum_address != 0, EfiError::NotReady);
         ensure!(len > 0, EfiError::InvalidParameter);
-        ensure!(base_address + len <= self.maximum_address, EfiError::Unsupported);
+        ensure!(base_address.checked_add(len).is_some_and(|end| end <= self.maximum_address), EfiError::Unsupported);
 
         log::trace!(target: "allocations", "[{}] Adding IO space at {:#x}", function!(), base_address);
         log::trace!(target: "allocations", "[{}]   Length: {:#x}", function!(), len);
@@ -1336,7 +1367,7 @@ impl IoGCD {
     pub fn remove_io_space(&mut self, base_address: usize, len: usize) -> Result<(), EfiError> {
         ensure!(self.maximum_address != 0, EfiError::NotReady);
         ensure!(len > 0, EfiError::InvalidParameter);
-        ensure!(base_address + len <= self.maximum_address, EfiError::Unsupported);
+        ensure!(base_address.checked_add(len).is_some_and(|end| end <= self.maximum_address), EfiError::Unsupported);
 
         log::trace!(target: "allocations", "[{}] Removing IO space at {:#x}", function!(), base_address);
         log::trace!(target: "allocations", "[{}]   Length: {:#x}\n", function!(), len);
@@ -1404,7 +1435,7 @@ impl IoGCD {
                 max_address.unwrap_or(usize::MAX),
             ),
             AllocateType::Address(address) => {
-                ensure!(address + len <= self.maximum_address, EfiError::Unsupported);
+                ensure!(address.checked_add(len).is_some_and(|end| end <= self.maximum_address), EfiError::Unsupported);
                 self.allocate_address(io_type, alignment, len, image_handle, device_handle, address)
             }
         }
@@ -1594,7 +1625,7 @@ impl IoGCD {
     pub fn free_io_space(&mut self, base_address: usize, len: usize) -> Result<(), EfiError> {
         ensure!(self.maximum_address != 0, EfiError::NotReady);
         ensure!(len > 0, EfiError::InvalidParameter);
-        ensure!(base_address + len <= self.maximum_address, EfiError::Unsupported);
+        ensure!(base_address.checked_add(len).is_some_and(|end| end <= self.maximum_address), EfiError::Unsupported);
 
         log::trace!(target: "allocations", "[{}] Free IO space at {:#?}", function!(), base_address);
         log::trace!(target: "allocations", "[{}]   Length: {:#x}\n", function!(), len);
@@ -1643,6 +1674,26 @@ impl IoGCD {
         Ok(())
     }
 
+    /// This service returns the descriptor of the IO block that contains the given address.
+    pub fn get_io_descriptor_for_address(
+        &mut self,
+        address: efi::PhysicalAddress,
+    ) -> Result<dxe_services::IoSpaceDescriptor, EfiError> {
+        ensure!(self.maximum_address != 0, EfiError::NotReady);
+        ensure!(address < self.maximum_address as u64, EfiError::NotFound);
+
+        if self.io_blocks.capacity() == 0 {
+            self.init_io_blocks()?;
+        }
+
+        log::trace!(target: "gcd_measure", "search");
+        let idx = self.io_blocks.get_closest_idx(&address).ok_or(EfiError::NotFound)?;
+        let ib = self.io_blocks.get_with_idx(idx).expect("idx is valid from get_closest_idx");
+        match ib {
+            IoBlock::Allocated(descriptor) | IoBlock::Unallocated(descriptor) => Ok(*descriptor),
+        }
+    }
+
     fn split_state_transition_at_idx(
         io_blocks: &mut Rbt<IoBlock>,
         idx: usize,
@@ -1793,18 +1844,28 @@ pub enum MapChangeType {
 pub type MapChangeCallback = fn(MapChangeType);
 
 /// Implements a spin locked GCD suitable for use as a static global.
+///
+/// The memory space, the I/O space and the page table each have their own lock, so that an operation on one does not
+/// wait on the others. Flags that are read on every allocation, such as readiness and the default allocation
+/// attributes, are atomics rather than state behind the memory space lock, so reading them does not disable interrupts.
 pub struct SpinLockedGcd {
     memory: tpl_lock::TplMutex<GCD>,
     io: tpl_lock::TplMutex<IoGCD>,
+    ready: AtomicBool,
+    /// Default attributes for memory allocations
+    /// This is efi::MEMORY_XP unless we have entered compatibility mode, in which case it is 0, e.g. no protection
+    default_attributes: AtomicU64,
     memory_change_callback: Option<MapChangeCallback>,
     memory_type_info_table: [EFiMemoryTypeInformation; 17],
     page_table: tpl_lock::TplMutex<Option<Box<dyn PageTable>>>,
+    unprotected_page_table_pages: tpl_lock::TplMutex<UnprotectedPageTableRanges>,
+    page_table_protection_active: AtomicBool,
 }
 
 impl SpinLockedGcd {
     /// Returns true if the underlying GCD is initialized and ready for use.
     pub fn is_ready(&self) -> bool {
-        self.memory.lock().is_ready()
+        self.ready.load(Ordering::SeqCst)
     }
 
     /// Creates a new uninitialized GCD. [`Self::init`] must be invoked before any other functions or they will return
@@ -1819,7 +1880,6 @@ impl SpinLockedGcd {
                     memory_blocks: Rbt::new(),
                     allocate_memory_space_fn: GCD::allocate_memory_space_internal,
                     free_memory_space_fn: GCD::free_memory_space_worker,
-                    default_attributes: efi::MEMORY_XP,
                     prioritize_32_bit_memory: false,
                 },
                 "GcdMemLock",
@@ -1829,6 +1889,8 @@ impl SpinLockedGcd {
                 IoGCD { maximum_address: 0, io_blocks: Rbt::new() },
                 "GcdIoLock",
             ),
+            ready: AtomicBool::new(false),
+            default_attributes: AtomicU64::new(efi::MEMORY_XP),
             memory_change_callback,
             memory_type_info_table: [
                 EFiMemoryTypeInformation { memory_type: efi::RESERVED_MEMORY_TYPE, number_of_pages: 0 },
@@ -1850,6 +1912,12 @@ impl SpinLockedGcd {
                 EFiMemoryTypeInformation { memory_type: 16 /*EfiMaxMemoryType*/, number_of_pages: 0 },
             ],
             page_table: tpl_lock::TplMutex::new(efi::TPL_HIGH_LEVEL, None, "GcdPageTableLock"),
+            unprotected_page_table_pages: tpl_lock::TplMutex::new(
+                efi::TPL_HIGH_LEVEL,
+                UnprotectedPageTableRanges::new(),
+                "GcdPageTablePagesLock",
+            ),
+            page_table_protection_active: AtomicBool::new(false),
         }
     }
 
@@ -1857,6 +1925,11 @@ impl SpinLockedGcd {
         self.memory.lock().prioritize_32_bit_memory = value;
     }
 
+    /// Returns whether 32-bit memory is prioritized when not otherwise specified.
+    pub fn is_32_bit_memory_prioritized(&self) -> bool {
+        self.memory.lock().prioritize_32_bit_memory
+    }
+
     /// Returns a reference to the memory type information table.
     pub const fn memory_type_info_table(&self) -> &[EFiMemoryTypeInformation; 17] {
         &self.memory_type_info_table
@@ -1867,8 +1940,14 @@ impl SpinLockedGcd {
         &self.memory_type_info_table[memory_type as usize]
     }
 
-    fn set_paging_attributes(&self, base_address: usize, len: usize, attributes: u64) -> Result<(), EfiError> {
-        if let Some(page_table) = &mut *self.page_table.lock() {
+    // applies attributes to the page table, which the caller has locked.
+    fn set_paging_attributes(
+        page_table: &mut Option<Box<dyn PageTable>>,
+        base_address: usize,
+        len: usize,
+        attributes: u64,
+    ) -> Result<(), EfiError> {
+        if let Some(page_table) = page_table {
             // only apply page table attributes to the page table, not our virtual GCD attributes
             let paging_attrs = MemoryAttributes::from_bits_truncate(attributes)
                 & (MemoryAttributes::AccessAttributesMask | MemoryAttributes::CacheAttributesMask);
@@ -1928,6 +2007,7 @@ impl SpinLockedGcd {
                             target: "paging",
                             "Memory region {base_address:#x?} of length {len:#x?} unmapped",
                         );
+                        request_tlb_shootdown();
                         return Ok(());
                     }
                     Err(e) => {
@@ -1966,6 +2046,11 @@ impl SpinLockedGcd {
                         EVENT_DB.signal_group(CACHE_ATTRIBUTE_CHANGE_EVENT_GROUP);
                     }
 
+                    // other processors may have cached the previous mapping of this region.
+                    if !unmapped {
+                        request_tlb_shootdown();
+                    }
+
                     log::trace!(
                         target: "paging",
                         "Memory region {base_address:#x?} of length {len:#x?} mapped with attributes {paging_attrs:#x?}",
@@ -2015,12 +2100,15 @@ impl SpinLockedGcd {
         mem.memory_blocks = Rbt::new();
         io.maximum_address = 0;
         io.io_blocks = Rbt::new();
+        self.ready.store(false, Ordering::SeqCst);
+        self.default_attributes.store(efi::MEMORY_XP, Ordering::SeqCst);
     }
 
     /// Initializes the underlying memory GCD and I/O GCD with the given address bits.
     pub fn init(&self, memory_address_bits: u32, io_address_bits: u32) {
         self.memory.lock().init(memory_address_bits);
         self.io.lock().init(io_address_bits);
+        self.ready.store(true, Ordering::SeqCst);
     }
 
     // Take control of our own destiny and create a page table that the GCD controls
@@ -2162,7 +2250,16 @@ impl SpinLockedGcd {
             // table
             let base_address = desc.base_address as usize & !UEFI_PAGE_MASK;
             let len = (desc.length as usize + UEFI_PAGE_MASK) & !UEFI_PAGE_MASK;
-            let new_attributes = (desc.attributes & efi::CACHE_ATTRIBUTE_MASK) | efi::MEMORY_XP;
+            // MMIO described without a cache attribute, as resource descriptor HOBs without attributes are, is mapped
+            // uncached rather than with the page table's default cache attribute.
+            let mut cache_attributes = desc.attributes & efi::CACHE_ATTRIBUTE_MASK;
+            if cache_attributes == 0
+                && desc.memory_type == dxe_services::GcdMemoryType::MemoryMappedIo
+                && desc.capabilities & efi::MEMORY_UC != 0
+            {
+                cache_attributes = efi::MEMORY_UC;
+            }
+            let new_attributes = cache_attributes | efi::MEMORY_XP;
 
             log::trace!(
                 target: "paging",
@@ -2202,9 +2299,51 @@ impl SpinLockedGcd {
 
         self.page_table.lock().as_mut().unwrap().install_page_table().expect("Failed to install the page table");
 
+        // Once installed, the page table is only updated through its self map, so the identity mapping of the page
+        // table pages can be made read-only to catch stray writes to the translation tables.
+        self.page_table_protection_active.store(true, Ordering::SeqCst);
+        self.protect_page_table_pages();
+
         log::info!("Paging initialized for the GCD");
     }
 
+    // Maps any page table pages allocated since the last call read-only. Mapping a range may split a large page and
+    // allocate another page table chunk, which is picked up by the same loop. Does nothing until the page table is
+    // installed, as the page table pages are written through their identity mapping until then.
+    fn protect_page_table_pages(&self) {
+        // protection is paused while the pages are being protected, so that the attribute updates made here do not
+        // recurse back into this function
+        if !self.page_table_protection_active.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        loop {
+            let Some((base_address, len)) = self.unprotected_page_table_pages.lock().pop() else {
+                break;
+            };
+
+            let cache_attributes = match self.get_memory_descriptor_for_address(base_address as efi::PhysicalAddress) {
+                Ok(desc) => desc.attributes & efi::CACHE_ATTRIBUTE_MASK,
+                Err(e) => {
+                    log::error!("Page table pages {base_address:#x?} not found in GCD {e:?}");
+                    debug_assert!(false);
+                    continue;
+                }
+            };
+
+            if let Err(e) =
+                self.set_memory_space_attributes(base_address, len, cache_attributes | efi::MEMORY_XP | efi::MEMORY_RO)
+            {
+                log::error!(
+                    "Failed to map page table pages {base_address:#x?} of length {len:#x?} read-only. Error: {e:?}"
+                );
+                debug_assert!(false);
+            }
+        }
+
+        self.page_table_protection_active.store(true, Ordering::SeqCst);
+    }
+
     /// This service adds reserved memory, system memory, or memory-mapped I/O resources to the global coherency domain of the processor.
     ///
     /// # Safety
@@ -2234,11 +2373,12 @@ impl SpinLockedGcd {
     /// # Documentation
     /// UEFI Platform Initialization Specification, Release 1.8, Section II-7.2.4.4
     pub fn remove_memory_space(&self, base_address: usize, len: usize) -> Result<(), EfiError> {
+        let _shootdown = TlbShootdownScope;
         let result = self.memory.lock().remove_memory_space(base_address, len);
         if result.is_ok() {
             if let Some(page_table) = &mut *self.page_table.lock() {
                 match page_table.unmap_memory_region(base_address as u64, len as u64) {
-                    Ok(_) => {}
+                    Ok(_) => request_tlb_shootdown(),
                     Err(status) => {
                         log::error!(
                             "Failed to unmap memory region {base_address:#x?} of length {len:#x?}. Status: {status:#x?} during
@@ -2290,7 +2430,7 @@ impl SpinLockedGcd {
                 // because we set efi::MEMORY_XP as a capability on all memory ranges we add to the GCD. A driver could
                 // call set_memory_space_capabilities to remove the XP capability, but that is something that should
                 // be caught and fixed.
-                let default_attributes = self.memory.lock().default_attributes;
+                let default_attributes = self.default_attributes.load(Ordering::SeqCst);
                 match self.set_memory_space_attributes(
                     *base_address,
                     len,
@@ -2334,6 +2474,7 @@ impl SpinLockedGcd {
     /// # Documentation
     /// UEFI Platform Initialization Specification, Release 1.8, Section II-7.2.4.3
     pub fn free_memory_space(&self, base_address: usize, len: usize) -> Result<(), EfiError> {
+        let _shootdown = TlbShootdownScope;
         let mut result = self.memory.lock().free_memory_space(base_address, len);
 
         match result {
@@ -2344,7 +2485,7 @@ impl SpinLockedGcd {
                 // keep track of state in the GCD
                 if let Some(page_table) = &mut *self.page_table.lock() {
                     match page_table.unmap_memory_region(base_address as u64, len as u64) {
-                        Ok(_) => {}
+                        Ok(_) => request_tlb_shootdown(),
                         Err(status) => {
                             log::error!(
                                 "Failed to unmap memory region {base_address:#x?} of length {len:#x?}. Status: {status:#x?}",
@@ -2400,6 +2541,7 @@ impl SpinLockedGcd {
         len: usize,
         attributes: u64,
     ) -> Result<(), EfiError> {
+        let _shootdown = TlbShootdownScope;
         // this API allows for setting attributes across multiple descriptors in the GCD (assuming the capabilities
         // allow it). The lower level set_memory_space_attributes will only operate on a single entry in the GCD/page
         // table, so at this level we need to check to see if the range spans multiple entries and if so, we need to
@@ -2410,18 +2552,18 @@ impl SpinLockedGcd {
         let mut res = Ok(());
         let range_end = (base_address + len) as u64;
         while current_base < range_end {
-            let descriptor = self.get_memory_descriptor_for_address(current_base as efi::PhysicalAddress)?;
+            // the descriptor is looked up and updated under a single acquisition of the memory space lock.
+            let mut memory = self.memory.lock();
+            let descriptor = memory.get_memory_descriptor_for_address(current_base as efi::PhysicalAddress)?;
             let descriptor_end = descriptor.base_address + descriptor.length;
 
             // it is still legal to split a descriptor and only set the attributes on part of it
             let next_base = u64::min(descriptor_end, range_end);
             let current_len = next_base - current_base;
-            match self.memory.lock().set_memory_space_attributes(
-                current_base as usize,
-                current_len as usize,
-                attributes,
-            ) {
-                Ok(()) => {}
+            match memory.set_memory_space_attributes(current_base as usize, current_len as usize, attributes) {
+                Ok(()) => {
+                    memory_attribute_journal::record(current_base, current_len, descriptor.attributes, attributes)
+                }
                 Err(e) => {
                     log::error!(
                         "Failed to set GCD memory attributes for memory region {current_base:#x?} of length {current_len:#x?} with attributes {attributes:#x?}. Status: {e:#x?}",
@@ -2429,13 +2571,19 @@ impl SpinLockedGcd {
                     debug_assert!(false);
                 }
             }
+            drop(memory);
 
             // 0 is a valid value for paging attributes: it means RWX. 0 is invalid for cache attributes. edk2 has a
             // behavior where if the caller passes 0 for cache and paging attributes, then 0 (RWX) is not applied to
             // the page table and only the virtual attribute(s) are applied to the GCD, such as EFI_RUNTIME. In order
             // to maintain compatibility with existing drivers, we preserve this poor paradigm.
             if attributes & (efi::CACHE_ATTRIBUTE_MASK | efi::MEMORY_ACCESS_MASK) != 0 {
-                match self.set_paging_attributes(current_base as usize, current_len as usize, attributes) {
+                match Self::set_paging_attributes(
+                    &mut self.page_table.lock(),
+                    current_base as usize,
+                    current_len as usize,
+                    attributes,
+                ) {
                     Ok(_) => {}
                     Err(EfiError::NotReady) => {
                         // before the page table is installed, we expect to get a return of NotReady. This means the GCD
@@ -2455,11 +2603,20 @@ impl SpinLockedGcd {
                         // to the previous attributes for this range. We may have partially updated this range in the GCD
                         // and the page table, but they will be in sync. We could attempt to continue here, but we need
                         // to return an error to the caller, so we might as well stop here.
-                        if let Err(rollback_err) = self.memory.lock().set_memory_space_attributes(
+                        let rollback = self.memory.lock().set_memory_space_attributes(
                             current_base as usize,
                             current_len as usize,
                             descriptor.attributes,
-                        ) {
+                        );
+                        if rollback.is_ok() {
+                            memory_attribute_journal::record(
+                                current_base,
+                                current_len,
+                                attributes,
+                                descriptor.attributes,
+                            );
+                        }
+                        if let Err(rollback_err) = rollback {
                             // well, we did our best. The GCD and page table are now out of sync, which is a critical error.
                             log::error!(
                                 "Failed to roll back GCD attributes after page table attribute set failure. This is a critical error. GCD and page table are now out of sync. Rollback error: {:?}",
@@ -2480,6 +2637,115 @@ impl SpinLockedGcd {
         if let Some(callback) = self.memory_change_callback {
             callback(MapChangeType::SetMemoryAttributes);
         }
+
+        // updating the page table may have allocated more page table pages, which must be protected as well
+        self.protect_page_table_pages();
+        res
+    }
+
+    /// Sets attributes on several memory space ranges, as [Self::set_memory_space_attributes] does for each
+    /// `(base_address, len, attributes)` range.
+    ///
+    /// All ranges are applied to the GCD under a single acquisition of the memory space lock and then to the page
+    /// table under a single acquisition of the page table lock, and the map change callback and the protection of new
+    /// page table pages run once for the whole batch rather than once per range. Unlike
+    /// [Self::set_memory_space_attributes], this allocates, so it must not be used while servicing an allocation.
+    pub fn set_memory_space_attributes_multi(&self, ranges: &[(usize, usize, u64)]) -> Result<(), EfiError> {
+        let _shootdown = TlbShootdownScope;
+        // the GCD descriptors that were updated: (base, length, new attributes, previous attributes).
+        let mut segments: Vec<(usize, usize, u64, u64)> = Vec::with_capacity(ranges.len());
+        let mut res = Ok(());
+
+        let mut memory = self.memory.lock();
+        'ranges: for &(base_address, len, attributes) in ranges {
+            let Some(range_end) = base_address.checked_add(len) else {
+                res = Err(EfiError::Unsupported);
+                break;
+            };
+
+            // split each range at descriptor boundaries, as set_memory_space_attributes does.
+            let mut current_base = base_address;
+            while current_base < range_end {
+                let descriptor = match memory.get_memory_descriptor_for_address(current_base as efi::PhysicalAddress) {
+                    Ok(descriptor) => descriptor,
+                    Err(e) => {
+                        res = Err(e);
+                        break 'ranges;
+                    }
+                };
+                let next_base = usize::min((descriptor.base_address + descriptor.length) as usize, range_end);
+                let current_len = next_base - current_base;
+                match memory.set_memory_space_attributes(current_base, current_len, attributes) {
+                    Ok(()) => {
+                        memory_attribute_journal::record(
+                            current_base as u64,
+                            current_len as u64,
+                            descriptor.attributes,
+                            attributes,
+                        );
+                        segments.push((current_base, current_len, attributes, descriptor.attributes));
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "Failed to set GCD memory attributes for memory region {current_base:#x?} of length {current_len:#x?} with attributes {attributes:#x?}. Status: {e:#x?}",
+                        );
+                        debug_assert!(false);
+                    }
+                }
+                current_base = next_base;
+            }
+        }
+        drop(memory);
+
+        let mut page_table = self.page_table.lock();
+        for (idx, &(base_address, len, attributes, _)) in segments.iter().enumerate() {
+            // as in set_memory_space_attributes, attributes without cache or access attributes only apply to the GCD.
+            if attributes & (efi::CACHE_ATTRIBUTE_MASK | efi::MEMORY_ACCESS_MASK) == 0 {
+                continue;
+            }
+            match Self::set_paging_attributes(&mut page_table, base_address, len, attributes) {
+                Ok(()) => {}
+                // the page table is not installed yet; it is built from the GCD when it is.
+                Err(EfiError::NotReady) => res = res.and(Err(EfiError::NotReady)),
+                Err(e) => {
+                    log::error!(
+                        "Failed to set page table memory attributes for memory region {base_address:#x?} of length {len:#x?} with attributes {attributes:#x?}. Status: {e:#x?}",
+                    );
+                    debug_assert!(false);
+
+                    // this and the remaining segments were only applied to the GCD, so roll them back to keep the GCD
+                    // and the page table in sync.
+                    let mut memory = self.memory.lock();
+                    for &(base_address, len, attributes, previous_attributes) in &segments[idx..] {
+                        let rollback = memory.set_memory_space_attributes(base_address, len, previous_attributes);
+                        if rollback.is_ok() {
+                            memory_attribute_journal::record(
+                                base_address as u64,
+                                len as u64,
+                                attributes,
+                                previous_attributes,
+                            );
+                        }
+                        if let Err(rollback_err) = rollback {
+                            log::error!(
+                                "Failed to roll back GCD attributes after page table attribute set failure. This is a critical error. GCD and page table are now out of sync. Rollback error: {:?}",
+                                rollback_err
+                            );
+                        }
+                    }
+                    res = Err(e);
+                    break;
+                }
+            }
+        }
+        drop(page_table);
+
+        if let Some(callback) = self.memory_change_callback {
+            callback(MapChangeType::SetMemoryAttributes);
+        }
+
+        // updating the page table may have allocated more page table pages, which must be protected as well
+        self.protect_page_table_pages();
         res
     }
 
@@ -2518,6 +2784,12 @@ impl SpinLockedGcd {
         self.memory.lock().get_memory_descriptor_for_address(address)
     }
 
+    /// Returns the page table pages that have not been mapped read-only yet, as (base address, length) pairs.
+    pub fn unprotected_page_table_ranges(&self) -> impl Iterator<Item = (usize, usize)> {
+        let ranges = *self.unprotected_page_table_pages.lock();
+        (0..ranges.count).map(move |index| ranges.ranges[index])
+    }
+
     /// returns the current count of blocks in the list.
     pub fn memory_descriptor_count(&self) -> usize {
         self.memory.lock().memory_descriptor_count()
@@ -2561,6 +2833,14 @@ impl SpinLockedGcd {
         self.io.lock().get_io_descriptors(buffer)
     }
 
+    /// Acquires lock and delegates to [`IoGCD::get_io_descriptor_for_address`]
+    pub fn get_io_descriptor_for_address(
+        &self,
+        address: efi::PhysicalAddress,
+    ) -> Result<dxe_services::IoSpaceDescriptor, EfiError> {
+        self.io.lock().get_io_descriptor_for_address(address)
+    }
+
     /// Acquires lock and delegates to [`IoGCD::io_descriptor_count`]
     pub fn io_descriptor_count(&self) -> usize {
         self.io.lock().io_descriptor_count()
@@ -2620,7 +2900,8 @@ impl SpinLockedGcd {
             }
             address += size;
         }
-        self.memory.lock().activate_compatibility_mode();
+        // new memory is no longer allocated as non-executable.
+        self.default_attributes.store(0, Ordering::SeqCst);
     }
 }
 
@@ -3622,7 +3903,6 @@ mod tests {
             maximum_address: 0,
             allocate_memory_space_fn: GCD::allocate_memory_space_internal,
             free_memory_space_fn: GCD::free_memory_space_worker,
-            default_attributes: efi::MEMORY_XP,
             prioritize_32_bit_memory: false,
         };
         assert_eq!(Err(EfiError::NotReady), gcd.set_memory_space_attributes(0, 0x50000, 0b1111));
@@ -3971,6 +4251,48 @@ mod tests {
         assert_eq!(Ok(()), gcd.free_io_space(100, 10));
     }
 
+    #[test]
+    fn test_io_space_ranges_that_overflow_are_unsupported() {
+        let mut gcd = IoGCD::_new(16);
+
+        assert_eq!(Err(EfiError::Unsupported), gcd.add_io_space(dxe_services::GcdIoType::Io, usize::MAX, 2));
+        assert_eq!(Err(EfiError::Unsupported), gcd.remove_io_space(usize::MAX, 2));
+        assert_eq!(Err(EfiError::Unsupported), gcd.free_io_space(usize::MAX, 2));
+        assert_eq!(
+            Err(EfiError::Unsupported),
+            gcd.allocate_io_space(AllocateType::Address(usize::MAX), dxe_services::GcdIoType::Io, 0, 2, 1 as _, None)
+        );
+    }
+
+    #[test]
+    fn test_get_io_descriptor_for_address() {
+        use dxe_services::GcdIoType;
+        let mut gcd = IoGCD::_new(16);
+
+        // before any IO space is added, the whole range is reported as non-existent.
+        let descriptor = gcd.get_io_descriptor_for_address(0x80).unwrap();
+        assert_eq!(
+            (descriptor.io_type, descriptor.base_address, descriptor.length),
+            (GcdIoType::NonExistent, 0, 0x10000)
+        );
+
+        gcd.add_io_space(GcdIoType::Io, 0x1000, 0x1000).unwrap();
+        gcd.allocate_io_space(AllocateType::Address(0x1800), GcdIoType::Io, 0, 0x100, 1 as _, Some(2 as _)).unwrap();
+
+        let descriptor = gcd.get_io_descriptor_for_address(0x1000).unwrap();
+        assert_eq!((descriptor.io_type, descriptor.base_address, descriptor.length), (GcdIoType::Io, 0x1000, 0x800));
+        assert!(descriptor.image_handle.is_null());
+
+        let descriptor = gcd.get_io_descriptor_for_address(0x18FF).unwrap();
+        assert_eq!((descriptor.base_address, descriptor.length), (0x1800, 0x100));
+        assert_eq!((descriptor.image_handle, descriptor.device_handle), (1 as _, 2 as _));
+
+        let descriptor = gcd.get_io_descriptor_for_address(0x1900).unwrap();
+        assert_eq!((descriptor.base_address, descriptor.length), (0x1900, 0x700));
+
+        assert_eq!(Err(EfiError::NotFound), gcd.get_io_descriptor_for_address(0x10000));
+    }
+
     fn create_gcd() -> (GCD, usize) {
         let mem = unsafe { get_memory(MEMORY_BLOCK_SLICE_SIZE) };
         let address = mem.as_ptr() as usize;
@@ -4017,6 +4339,24 @@ mod tests {
         unsafe { core::slice::from_raw_parts_mut(addr, size) }
     }
 
+    #[test]
+    fn spin_locked_gcd_flags_do_not_require_the_memory_lock() {
+        with_locked_state(|| {
+            static GCD: SpinLockedGcd = SpinLockedGcd::new(None);
+            assert!(!GCD.is_ready());
+
+            GCD.init(48, 16);
+            // readiness is reported even while the memory space is locked, e.g. by an allocation in progress.
+            let memory = GCD.memory.lock();
+            assert!(GCD.is_ready());
+            assert_eq!(GCD.default_attributes.load(Ordering::SeqCst), efi::MEMORY_XP);
+            drop(memory);
+
+            unsafe { GCD.reset() };
+            assert!(!GCD.is_ready());
+        });
+    }
+
     #[test]
     fn spin_locked_allocator_should_error_if_not_initialized() {
         with_locked_state(|| {
@@ -4183,6 +4523,57 @@ mod tests {
         });
     }
 
+    #[test]
+    fn special_purpose_memory_should_only_be_allocated_by_address() {
+        with_locked_state(|| {
+            assert_eq!(
+                get_capabilities(
+                    dxe_services::GcdMemoryType::SystemMemory,
+                    hob::EFI_RESOURCE_ATTRIBUTE_SPECIAL_PURPOSE as u64
+                ),
+                efi::MEMORY_SP
+            );
+
+            use std::alloc::GlobalAlloc;
+            const GCD_SIZE: usize = 0x100000;
+            const SP_BASE: usize = 0x1000_0000_0000;
+            static GCD: SpinLockedGcd = SpinLockedGcd::new(None);
+            GCD.init(48, 16);
+
+            let layout = Layout::from_size_align(GCD_SIZE, 0x1000).unwrap();
+            let base = unsafe { std::alloc::System.alloc(layout) as usize };
+            unsafe {
+                GCD.add_memory_space(dxe_services::GcdMemoryType::SystemMemory, base, GCD_SIZE, efi::MEMORY_WB)
+                    .unwrap();
+                GCD.add_memory_space(
+                    dxe_services::GcdMemoryType::SystemMemory,
+                    SP_BASE,
+                    0x1000000,
+                    efi::MEMORY_WB | efi::MEMORY_SP,
+                )
+                .unwrap();
+            }
+            let allocate = |allocate_type, len| {
+                GCD.allocate_memory_space(
+                    allocate_type,
+                    dxe_services::GcdMemoryType::SystemMemory,
+                    12,
+                    len,
+                    1 as _,
+                    None,
+                )
+            };
+
+            // searches only find memory that is not special-purpose.
+            for allocate_type in [AllocateType::TopDown(None), AllocateType::BottomUp(None)] {
+                let address = allocate(allocate_type, 0x1000).unwrap();
+                assert!((base..base + GCD_SIZE).contains(&address));
+                assert!(allocate(allocate_type, 2 * GCD_SIZE).is_err());
+            }
+            assert_eq!(allocate(AllocateType::Address(SP_BASE), 2 * GCD_SIZE), Ok(SP_BASE));
+        });
+    }
+
     #[test]
     fn allocate_top_down_should_allocate_decreasing_addresses() {
         with_locked_state(|| {
@@ -4331,4 +4722,137 @@ mod tests {
         );
         assert!(res.is_ok(), "Failed to fallback to higher memory as expected");
     }
+
+    #[test]
+    fn test_page_table_pages_are_mapped_read_only_once_installed() {
+        static MAPPED: std::sync::Mutex<Vec<(u64, u64, MemoryAttributes)>> = std::sync::Mutex::new(Vec::new());
+
+        struct RecordingPageTable;
+        impl PageTable for RecordingPageTable {
+            fn map_memory_region(&mut self, address: u64, size: u64, attributes: MemoryAttributes) -> PtResult<()> {
+                MAPPED.lock().unwrap().push((address, size, attributes));
+                Ok(())
+            }
+            fn unmap_memory_region(&mut self, _address: u64, _size: u64) -> PtResult<()> {
+                Ok(())
+            }
+            fn install_page_table(&mut self) -> PtResult<()> {
+                Ok(())
+            }
+            fn query_memory_region(&self, _address: u64, _size: u64) -> PtResult<MemoryAttributes> {
+                Err(PtError::NoMapping)
+            }
+            fn dump_page_tables(&self, _address: u64, _size: u64) -> PtResult<()> {
+                Ok(())
+            }
+        }
+
+        with_locked_state(|| {
+            use std::alloc::GlobalAlloc;
+            const GCD_SIZE: usize = 0x800000;
+            static GCD: SpinLockedGcd = SpinLockedGcd::new(None);
+            GCD.init(48, 16);
+
+            let layout = Layout::from_size_align(GCD_SIZE, 0x1000).unwrap();
+            let base = unsafe { std::alloc::System.alloc(layout) as usize };
+            unsafe {
+                GCD.add_memory_space(
+                    dxe_services::GcdMemoryType::SystemMemory,
+                    base,
+                    GCD_SIZE,
+                    efi::MEMORY_WB | efi::MEMORY_RO | efi::MEMORY_XP,
+                )
+                .unwrap();
+            }
+
+            // pages allocated before the page table is installed are left writable
+            let mut allocator = PagingAllocator::new(&GCD);
+            let page = allocator.allocate_page(UEFI_PAGE_SIZE as u64, UEFI_PAGE_SIZE as u64, false).unwrap();
+            GCD.protect_page_table_pages();
+            assert_eq!(GCD.get_memory_descriptor_for_address(page).unwrap().attributes & efi::MEMORY_RO, 0);
+
+            *GCD.page_table.lock() = Some(Box::new(RecordingPageTable));
+            GCD.page_table_protection_active.store(true, Ordering::SeqCst);
+            GCD.protect_page_table_pages();
+
+            let desc = GCD.get_memory_descriptor_for_address(page).unwrap();
+            assert_eq!(desc.attributes & (efi::MEMORY_RO | efi::MEMORY_XP), efi::MEMORY_RO | efi::MEMORY_XP);
+            assert!(desc.base_address <= page && page < desc.base_address + desc.length);
+            assert!(MAPPED.lock().unwrap().iter().any(|(address, size, attributes)| {
+                *address <= page
+                    && page < address + size
+                    && attributes.contains(MemoryAttributes::ReadOnly | MemoryAttributes::ExecuteProtect)
+            }));
+            assert!(GCD.unprotected_page_table_pages.lock().pop().is_none());
+            assert!(GCD.page_table_protection_active.load(Ordering::SeqCst));
+        });
+    }
+
+    #[test]
+    fn test_set_memory_space_attributes_multi() {
+        static MAPPED: std::sync::Mutex<Vec<(u64, u64, MemoryAttributes)>> = std::sync::Mutex::new(Vec::new());
+        static CALLBACKS: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+        struct RecordingPageTable;
+        impl PageTable for RecordingPageTable {
+            fn map_memory_region(&mut self, address: u64, size: u64, attributes: MemoryAttributes) -> PtResult<()> {
+                MAPPED.lock().unwrap().push((address, size, attributes));
+                Ok(())
+            }
+            fn unmap_memory_region(&mut self, _address: u64, _size: u64) -> PtResult<()> {
+                Ok(())
+            }
+            fn install_page_table(&mut self) -> PtResult<()> {
+                Ok(())
+            }
+            fn query_memory_region(&self, _address: u64, _size: u64) -> PtResult<MemoryAttributes> {
+                Err(PtError::NoMapping)
+            }
+            fn dump_page_tables(&self, _address: u64, _size: u64) -> PtResult<()> {
+                Ok(())
+            }
+        }
+
+        fn map_callback(map_change_type: MapChangeType) {
+            if map_change_type == MapChangeType::SetMemoryAttributes {
+                CALLBACKS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        with_locked_state(|| {
+            static GCD: SpinLockedGcd = SpinLockedGcd::new(Some(map_callback));
+            GCD.init(48, 16);
+
+            // the GCD keeps its block list at the start of the first memory added, so use the memory after it.
+            let mem = unsafe { get_memory(MEMORY_BLOCK_SLICE_SIZE * 3) };
+            let address = align_up(mem.as_ptr() as usize, 0x1000).unwrap();
+            let base = address + MEMORY_BLOCK_SLICE_SIZE;
+            unsafe {
+                GCD.add_memory_space(
+                    dxe_services::GcdMemoryType::SystemMemory,
+                    address,
+                    MEMORY_BLOCK_SLICE_SIZE * 2,
+                    efi::MEMORY_WB | efi::MEMORY_RO | efi::MEMORY_XP,
+                )
+                .unwrap();
+            }
+            *GCD.page_table.lock() = Some(Box::new(RecordingPageTable));
+
+            // the ranges are applied with a single map change notification.
+            let code = efi::MEMORY_WB | efi::MEMORY_RO;
+            let data = efi::MEMORY_WB | efi::MEMORY_XP;
+            GCD.set_memory_space_attributes_multi(&[(base, 0x1000, code), (base + 0x1000, 0x2000, data)]).unwrap();
+            assert_eq!(CALLBACKS.load(Ordering::SeqCst), 1);
+            assert_eq!(GCD.get_memory_descriptor_for_address(base as u64).unwrap().attributes, code);
+            let desc = GCD.get_memory_descriptor_for_address(base as u64 + 0x1000).unwrap();
+            assert_eq!((desc.attributes, desc.length), (data, 0x2000));
+            assert_eq!(
+                *MAPPED.lock().unwrap(),
+                [
+                    (base as u64, 0x1000, MemoryAttributes::from_bits_truncate(code)),
+                    (base as u64 + 0x1000, 0x2000, MemoryAttributes::from_bits_truncate(data))
+                ]
+            );
+        });
+    }
 }
@@ -0,0 +1,202 @@
+//! GUID Names
+//!
+//! Maps well-known protocol, HOB, configuration table and event group GUIDs to names, so that diagnostics show
+//! `Loaded Image (5B1B31A1-9562-11D2-8E3F-00A0C969723B)` rather than only the GUID. Platforms can name their own
+//! protocols, HOBs and driver files by registering [GuidNames].
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Display};
+
+use patina::{Guid, guids};
+use patina_pi::protocols;
+use r_efi::efi;
+use spin::RwLock;
+
+/// Names for GUIDs that the platform defines, used in core diagnostics in addition to the well-known names.
+///
+/// A name registered here takes precedence over the built-in name for the same GUID.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, GuidNames};
+/// use r_efi::efi;
+/// # let physical_hob_list = core::ptr::null();
+///
+/// const PLATFORM_CONFIG_HOB: efi::Guid =
+///     efi::Guid::from_fields(0x7c3b0e6a, 0x51d2, 0x4a8f, 0x9e, 0x14, &[0x2d, 0x6b, 0x80, 0xc1, 0x3f, 0x55]);
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(GuidNames(vec![(PLATFORM_CONFIG_HOB, "Platform Config HOB")]))
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GuidNames(pub Vec<(efi::Guid, &'static str)>);
+
+// Architectural protocols without a definition in patina_pi.
+pub(crate) const VARIABLE_ARCH_PROTOCOL: efi::Guid =
+    efi::Guid::from_fields(0x1e5668e2, 0x8481, 0x11d4, 0xbc, 0xf1, &[0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]);
+pub(crate) const VARIABLE_WRITE_ARCH_PROTOCOL: efi::Guid =
+    efi::Guid::from_fields(0x6441f818, 0x6362, 0x4e44, 0xb5, 0x70, &[0x7d, 0xba, 0x31, 0xdd, 0x24, 0x53]);
+pub(crate) const CAPSULE_ARCH_PROTOCOL: efi::Guid =
+    efi::Guid::from_fields(0x5053697e, 0x2cbc, 0x4819, 0x90, 0xd9, &[0x05, 0x80, 0xde, 0xee, 0x57, 0x54]);
+pub(crate) const RESET_ARCH_PROTOCOL: efi::Guid =
+    efi::Guid::from_fields(0x27cfac88, 0x46cc, 0x11d4, 0x9a, 0x38, &[0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+pub(crate) const REAL_TIME_CLOCK_ARCH_PROTOCOL: efi::Guid =
+    efi::Guid::from_fields(0x27cfac87, 0x46cc, 0x11d4, 0x9a, 0x38, &[0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+
+const WELL_KNOWN_NAMES: &[(efi::Guid, &str)] = &[
+    // Architectural protocols.
+    (protocols::security::PROTOCOL_GUID, "Security Arch"),
+    (protocols::security2::PROTOCOL_GUID, "Security2 Arch"),
+    (protocols::cpu_arch::PROTOCOL_GUID, "Cpu Arch"),
+    (protocols::metronome::PROTOCOL_GUID, "Metronome Arch"),
+    (protocols::timer::PROTOCOL_GUID, "Timer Arch"),
+    (protocols::bds::PROTOCOL_GUID, "Bds Arch"),
+    (protocols::watchdog::PROTOCOL_GUID, "Watchdog Arch"),
+    (protocols::runtime::PROTOCOL_GUID, "Runtime Arch"),
+    (protocols::monotonic_counter::PROTOCOL_GUID, "Monotonic Counter Arch"),
+    (VARIABLE_ARCH_PROTOCOL, "Variable Arch"),
+    (VARIABLE_WRITE_ARCH_PROTOCOL, "Variable Write Arch"),
+    (CAPSULE_ARCH_PROTOCOL, "Capsule Arch"),
+    (RESET_ARCH_PROTOCOL, "Reset Arch"),
+    (REAL_TIME_CLOCK_ARCH_PROTOCOL, "Real Time Clock Arch"),
+    // Other PI and UEFI protocols.
+    (protocols::status_code::PROTOCOL_GUID, "Status Code Runtime"),
+    (protocols::firmware_volume::PROTOCOL_GUID, "Firmware Volume2"),
+    (protocols::firmware_volume_block::PROTOCOL_GUID, "Firmware Volume Block2"),
+    (efi::protocols::loaded_image::PROTOCOL_GUID, "Loaded Image"),
+    (efi::protocols::loaded_image_device_path::PROTOCOL_GUID, "Loaded Image Device Path"),
+    (efi::protocols::device_path::PROTOCOL_GUID, "Device Path"),
+    (efi::protocols::driver_binding::PROTOCOL_GUID, "Driver Binding"),
+    (efi::protocols::driver_family_override::PROTOCOL_GUID, "Driver Family Override"),
+    (efi::protocols::bus_specific_driver_override::PROTOCOL_GUID, "Bus Specific Driver Override"),
+    (efi::protocols::platform_driver_override::PROTOCOL_GUID, "Platform Driver Override"),
+    (efi::protocols::decompress::PROTOCOL_GUID, "Decompress"),
+    (efi::protocols::load_file::PROTOCOL_GUID, "Load File"),
+    (efi::protocols::load_file2::PROTOCOL_GUID, "Load File2"),
+    (efi::protocols::block_io::PROTOCOL_GUID, "Block IO"),
+    (efi::protocols::disk_io::PROTOCOL_GUID, "Disk IO"),
+    (efi::protocols::simple_file_system::PROTOCOL_GUID, "Simple File System"),
+    (efi::protocols::simple_text_input::PROTOCOL_GUID, "Simple Text Input"),
+    (efi::protocols::simple_text_output::PROTOCOL_GUID, "Simple Text Output"),
+    (efi::protocols::graphics_output::PROTOCOL_GUID, "Graphics Output"),
+    (efi::protocols::memory_attribute::PROTOCOL_GUID, "Memory Attribute"),
+    (efi::protocols::pci_io::PROTOCOL_GUID, "PCI IO"),
+    (guids::HARDWARE_INTERRUPT_PROTOCOL, "Hardware Interrupt"),
+    (guids::HARDWARE_INTERRUPT_PROTOCOL_V2, "Hardware Interrupt2"),
+    (guids::PERFORMANCE_PROTOCOL, "Performance"),
+    (guids::SMM_COMMUNICATION_PROTOCOL, "SMM Communication"),
+    // HOBs, configuration tables and event groups.
+    (guids::HOB_LIST, "HOB List"),
+    (guids::HOB_MEMORY_ALLOC_STACK, "Memory Allocation Stack HOB"),
+    (guids::MEMORY_TYPE_INFORMATION, "Memory Type Information"),
+    (patina_pi::dxe_services::DXE_SERVICES_TABLE_GUID, "DXE Services Table"),
+    (crate::BOOT_CONFIG_HOB_GUID, "Boot Config HOB"),
+    (crate::DISPATCH_POLICY_HOB_GUID, "Dispatch Policy HOB"),
+    (crate::MEMORY_PROTECTION_SETTINGS_HOB_GUID, "Memory Protection Settings HOB"),
+    (crate::CORE_INFO_TABLE_GUID, "Core Info Table"),
+    (crate::PROTOCOL_DB_SNAPSHOT_TABLE_GUID, "Protocol DB Snapshot Table"),
+    (guids::DXE_CORE, "DXE Core"),
+    (guids::EVENT_GROUP_END_OF_DXE, "End of DXE"),
+    (guids::EBS_FAILED, "Exit Boot Services Failed"),
+    (efi::EVENT_GROUP_READY_TO_BOOT, "Ready to Boot"),
+    (efi::EVENT_GROUP_EXIT_BOOT_SERVICES, "Exit Boot Services"),
+    (efi::EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE, "Virtual Address Change"),
+];
+
+static PLATFORM_NAMES: RwLock<Vec<(efi::Guid, &'static str)>> = RwLock::new(Vec::new());
+
+/// Adds the platform's GUID names to those used in diagnostics.
+pub(crate) fn register_guid_names(names: &GuidNames) {
+    PLATFORM_NAMES.write().extend_from_slice(&names.0);
+}
+
+/// Returns the name of `guid`, preferring a name registered by the platform.
+pub(crate) fn guid_name(guid: &efi::Guid) -> Option<&'static str> {
+    // the platform names are skipped if they are being updated, so that diagnostics never block.
+    let platform_name = PLATFORM_NAMES
+        .try_read()
+        .and_then(|names| names.iter().rev().find(|(name_guid, _)| name_guid == guid).map(|(_, name)| *name));
+    platform_name.or_else(|| WELL_KNOWN_NAMES.iter().find(|(name_guid, _)| name_guid == guid).map(|(_, name)| *name))
+}
+
+/// Formats a GUID for diagnostics, with its name if it has one.
+pub(crate) struct NamedGuid<'a>(&'a efi::Guid);
+
+/// Returns `guid` formatted for diagnostics, with its name if it has one.
+pub(crate) fn named(guid: &efi::Guid) -> NamedGuid<'_> {
+    NamedGuid(guid)
+}
+
+impl Display for NamedGuid<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match guid_name(self.0) {
+            Some(name) => write!(f, "{name} ({})", Guid::from_ref(self.0)),
+            None => write!(f, "{}", Guid::from_ref(self.0)),
+        }
+    }
+}
+
+impl Debug for NamedGuid<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::{format, vec};
+
+    use crate::test_support;
+
+    const PLATFORM_GUID: efi::Guid =
+        efi::Guid::from_fields(0x7c3b0e6a, 0x51d2, 0x4a8f, 0x9e, 0x14, &[0x2d, 0x6b, 0x80, 0xc1, 0x3f, 0x55]);
+
+    #[test]
+    fn test_well_known_names() {
+        assert_eq!(guid_name(&efi::protocols::loaded_image::PROTOCOL_GUID), Some("Loaded Image"));
+        assert_eq!(
+            format!("{}", named(&efi::protocols::loaded_image::PROTOCOL_GUID)),
+            "Loaded Image (5B1B31A1-9562-11D2-8E3F-00A0C969723B)"
+        );
+        assert_eq!(format!("{:?}", named(&guids::ZERO)), "00000000-0000-0000-0000-000000000000");
+
+        // every well-known GUID has a single name.
+        for (idx, (guid, name)) in WELL_KNOWN_NAMES.iter().enumerate() {
+            assert!(WELL_KNOWN_NAMES[..idx].iter().all(|(other, _)| other != guid), "{name} is listed more than once");
+        }
+    }
+
+    #[test]
+    fn test_platform_names() {
+        test_support::with_global_lock(|| {
+            PLATFORM_NAMES.write().clear();
+            assert_eq!(guid_name(&PLATFORM_GUID), None);
+
+            register_guid_names(&GuidNames(vec![
+                (PLATFORM_GUID, "Platform Config HOB"),
+                (efi::protocols::block_io::PROTOCOL_GUID, "Platform Block IO"),
+            ]));
+            assert_eq!(guid_name(&PLATFORM_GUID), Some("Platform Config HOB"));
+            assert_eq!(guid_name(&efi::protocols::block_io::PROTOCOL_GUID), Some("Platform Block IO"));
+
+            // a later registration for the same GUID takes precedence.
+            register_guid_names(&GuidNames(vec![(PLATFORM_GUID, "Renamed HOB")]));
+            assert_eq!(guid_name(&PLATFORM_GUID), Some("Renamed HOB"));
+
+            PLATFORM_NAMES.write().clear();
+        })
+        .unwrap();
+    }
+}
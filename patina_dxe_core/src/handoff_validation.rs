@@ -0,0 +1,583 @@
+//! ACPI and SMBIOS Handoff Validation
+//!
+//! Sanity checks the ACPI and SMBIOS tables installed in the system table when ReadyToBoot is signaled, which is the
+//! last point at which the tables are changed before they are handed off to the OS. Corrupted tables otherwise only
+//! surface as an OS boot failure, far from the driver that produced them.
+//!
+//! The ACPI checks cover the RSDP, XSDT (or RSDT) and every table it references, including the DSDT and FACS
+//! referenced by the FADT, and the presence of the [HandoffValidationConfig::required_acpi_tables]. The SMBIOS checks
+//! cover the 2.x and 3.0 entry points and the structure table, including structure lengths, string sets, duplicate
+//! handles, the end-of-table structure and the presence of the [HandoffValidationConfig::required_smbios_types].
+//!
+//! Every issue found is logged as an error, and debug builds assert that no issues were found.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, collections::BTreeSet, string::String, vec::Vec};
+use core::fmt::{self, Display};
+
+use patina::{
+    boot_services::{BootServices, StandardBootServices, event::EventType, tpl::Tpl},
+    component::{IntoComponent, params::Config},
+};
+use r_efi::{
+    efi,
+    system::{self, EVENT_GROUP_READY_TO_BOOT},
+};
+
+use crate::systemtables::SYSTEM_TABLE;
+
+/// The length of an ACPI system description table header.
+const SDT_HEADER_LENGTH: usize = 36;
+
+/// Configuration for the ACPI and SMBIOS handoff validation performed at ReadyToBoot.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, HandoffValidationConfig};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// let config = HandoffValidationConfig { require_smbios: true, ..Default::default() };
+/// let core = Core::default()
+///    .init_memory(physical_hob_list)
+///    .with_config(config)
+///    .start()
+///    .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandoffValidationConfig {
+    /// Whether the absence of ACPI tables is an issue.
+    pub require_acpi: bool,
+    /// Whether the absence of SMBIOS tables is an issue.
+    pub require_smbios: bool,
+    /// The signatures of the ACPI tables that must be referenced by the XSDT (or RSDT), if ACPI tables are present.
+    pub required_acpi_tables: Vec<[u8; 4]>,
+    /// The SMBIOS structure types that must be present, if SMBIOS tables are present.
+    pub required_smbios_types: Vec<u8>,
+}
+
+impl Default for HandoffValidationConfig {
+    fn default() -> Self {
+        Self {
+            require_acpi: false,
+            require_smbios: false,
+            required_acpi_tables: alloc::vec![*b"FACP", *b"APIC"],
+            required_smbios_types: alloc::vec![0, 1],
+        }
+    }
+}
+
+/// An issue found in the ACPI or SMBIOS tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HandoffIssue {
+    /// No ACPI tables are installed.
+    MissingAcpi,
+    /// No SMBIOS tables are installed.
+    MissingSmbios,
+    /// A table does not have the expected signature.
+    InvalidSignature { table: String, address: u64 },
+    /// A table does not have a valid checksum.
+    InvalidChecksum { table: String, address: u64 },
+    /// A table has a length that is too small to be valid.
+    InvalidLength { table: String, address: u64, length: usize },
+    /// A pointer to a table is null.
+    NullPointer { table: String },
+    /// A required ACPI table is not referenced by the XSDT (or RSDT).
+    MissingAcpiTable([u8; 4]),
+    /// An SMBIOS structure is malformed or overruns the structure table.
+    MalformedSmbiosStructure { offset: usize },
+    /// The SMBIOS structure table does not end with an end-of-table structure.
+    MissingSmbiosEndOfTable,
+    /// More than one SMBIOS structure has the same handle.
+    DuplicateSmbiosHandle(u16),
+    /// A required SMBIOS structure type is not present.
+    MissingSmbiosType(u8),
+}
+
+impl Display for HandoffIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAcpi => write!(f, "no ACPI tables are installed"),
+            Self::MissingSmbios => write!(f, "no SMBIOS tables are installed"),
+            Self::InvalidSignature { table, address } => write!(f, "{table} at {address:#x} has an invalid signature"),
+            Self::InvalidChecksum { table, address } => write!(f, "{table} at {address:#x} has an invalid checksum"),
+            Self::InvalidLength { table, address, length } => {
+                write!(f, "{table} at {address:#x} has an invalid length of {length:#x}")
+            }
+            Self::NullPointer { table } => write!(f, "the pointer to the {table} is null"),
+            Self::MissingAcpiTable(signature) => {
+                write!(f, "required ACPI table {} is not installed", String::from_utf8_lossy(signature))
+            }
+            Self::MalformedSmbiosStructure { offset } => {
+                write!(f, "SMBIOS structure at offset {offset:#x} is malformed")
+            }
+            Self::MissingSmbiosEndOfTable => write!(f, "SMBIOS structure table has no end-of-table structure"),
+            Self::DuplicateSmbiosHandle(handle) => write!(f, "SMBIOS handle {handle:#x} is used more than once"),
+            Self::MissingSmbiosType(smbios_type) => write!(f, "required SMBIOS type {smbios_type} is not present"),
+        }
+    }
+}
+
+/// Returns `length` bytes at `address`.
+///
+/// # Safety
+///
+/// `address` must be readable for `length` bytes.
+unsafe fn bytes<'a>(address: u64, length: usize) -> &'a [u8] {
+    unsafe { core::slice::from_raw_parts(address as *const u8, length) }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn checksum_is_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn signature_name(signature: &[u8]) -> String {
+    String::from_utf8_lossy(signature).into_owned()
+}
+
+/// Validates the ACPI and SMBIOS tables in `tables` against `config`, returning every issue found.
+///
+/// The tables are trusted to point to readable memory, which holds for identity mapped firmware.
+pub(crate) fn validate(tables: &[efi::ConfigurationTable], config: &HandoffValidationConfig) -> Vec<HandoffIssue> {
+    let mut validator = Validator { config, issues: Vec::new() };
+    let find = |guid: &efi::Guid| tables.iter().find(|table| table.vendor_guid == *guid).map(|t| t.vendor_table as u64);
+
+    match find(&system::ACPI_20_TABLE_GUID).or_else(|| find(&system::ACPI_10_TABLE_GUID)) {
+        Some(rsdp) => validator.validate_acpi(rsdp),
+        None if config.require_acpi => validator.issues.push(HandoffIssue::MissingAcpi),
+        None => (),
+    }
+
+    let smbios3 = find(&system::SMBIOS3_TABLE_GUID);
+    let smbios = find(&system::SMBIOS_TABLE_GUID);
+    if let Some(entry_point) = smbios3 {
+        validator.validate_smbios3(entry_point);
+    }
+    if let Some(entry_point) = smbios {
+        validator.validate_smbios(entry_point);
+    }
+    if smbios3.is_none() && smbios.is_none() && config.require_smbios {
+        validator.issues.push(HandoffIssue::MissingSmbios);
+    }
+
+    validator.issues
+}
+
+struct Validator<'a> {
+    config: &'a HandoffValidationConfig,
+    issues: Vec<HandoffIssue>,
+}
+
+impl Validator<'_> {
+    fn validate_acpi(&mut self, rsdp: u64) {
+        if rsdp == 0 {
+            return self.issues.push(HandoffIssue::NullPointer { table: "RSDP".into() });
+        }
+
+        // SAFETY: The ACPI 1.0 RSDP is 20 bytes.
+        let rsdp_v1 = unsafe { bytes(rsdp, 20) };
+        if &rsdp_v1[..8] != b"RSD PTR " {
+            return self.issues.push(HandoffIssue::InvalidSignature { table: "RSDP".into(), address: rsdp });
+        }
+        if !checksum_is_valid(rsdp_v1) {
+            self.issues.push(HandoffIssue::InvalidChecksum { table: "RSDP".into(), address: rsdp });
+        }
+
+        let mut xsdt = 0;
+        if rsdp_v1[15] >= 2 {
+            // SAFETY: The ACPI 2.0 RSDP is at least 36 bytes.
+            let length = read_u32(unsafe { bytes(rsdp, 36) }, 20) as usize;
+            if length < 36 {
+                return self.issues.push(HandoffIssue::InvalidLength { table: "RSDP".into(), address: rsdp, length });
+            }
+            // SAFETY: The length of the RSDP was validated above.
+            let rsdp_v2 = unsafe { bytes(rsdp, length) };
+            if !checksum_is_valid(rsdp_v2) {
+                self.issues.push(HandoffIssue::InvalidChecksum { table: "RSDP extended".into(), address: rsdp });
+            }
+            xsdt = read_u64(rsdp_v2, 24);
+        }
+
+        let entries = if xsdt != 0 {
+            self.root_table_entries(xsdt, b"XSDT", 8)
+        } else {
+            match read_u32(rsdp_v1, 16) as u64 {
+                0 => return self.issues.push(HandoffIssue::NullPointer { table: "RSDT".into() }),
+                rsdt => self.root_table_entries(rsdt, b"RSDT", 4),
+            }
+        };
+        let Some(entries) = entries else {
+            return;
+        };
+
+        let mut signatures = Vec::new();
+        for address in entries {
+            if address == 0 {
+                self.issues.push(HandoffIssue::NullPointer { table: "root table entry".into() });
+                continue;
+            }
+            let Some((signature, length)) = self.validate_sdt(address, None) else {
+                continue;
+            };
+            if &signature == b"FACP" {
+                self.validate_fadt(address, length);
+            }
+            signatures.push(signature);
+        }
+
+        for required in &self.config.required_acpi_tables {
+            if !signatures.contains(required) {
+                self.issues.push(HandoffIssue::MissingAcpiTable(*required));
+            }
+        }
+    }
+
+    /// Validates the header and checksum of a system description table, returning its signature and length.
+    fn validate_sdt(&mut self, address: u64, expected: Option<&[u8; 4]>) -> Option<([u8; 4], usize)> {
+        // SAFETY: Every system description table starts with a header.
+        let header = unsafe { bytes(address, SDT_HEADER_LENGTH) };
+        let signature: [u8; 4] = header[..4].try_into().unwrap();
+        if let Some(expected) = expected
+            && signature != *expected
+        {
+            self.issues.push(HandoffIssue::InvalidSignature { table: signature_name(expected), address });
+            return None;
+        }
+
+        let length = read_u32(header, 4) as usize;
+        if length < SDT_HEADER_LENGTH {
+            self.issues.push(HandoffIssue::InvalidLength { table: signature_name(&signature), address, length });
+            return None;
+        }
+        // SAFETY: The table length was validated above.
+        if !checksum_is_valid(unsafe { bytes(address, length) }) {
+            self.issues.push(HandoffIssue::InvalidChecksum { table: signature_name(&signature), address });
+        }
+        Some((signature, length))
+    }
+
+    /// Validates the XSDT or RSDT, returning the addresses of the tables it references.
+    fn root_table_entries(&mut self, address: u64, signature: &[u8; 4], entry_size: usize) -> Option<Vec<u64>> {
+        let (_, length) = self.validate_sdt(address, Some(signature))?;
+        // SAFETY: The table length was validated by validate_sdt.
+        let table = unsafe { bytes(address, length) };
+        Some(
+            table[SDT_HEADER_LENGTH..]
+                .chunks_exact(entry_size)
+                .map(|entry| if entry_size == 8 { read_u64(entry, 0) } else { read_u32(entry, 0) as u64 })
+                .collect(),
+        )
+    }
+
+    /// Validates the DSDT and FACS referenced by the FADT, preferring the 64-bit pointers where they are set.
+    fn validate_fadt(&mut self, address: u64, length: usize) {
+        // SAFETY: The table length was validated by validate_sdt.
+        let fadt = unsafe { bytes(address, length) };
+        let pointer = |offset: usize, x_offset: usize| match fadt.len() {
+            len if len >= x_offset + 8 && read_u64(fadt, x_offset) != 0 => read_u64(fadt, x_offset),
+            len if len >= offset + 4 => read_u32(fadt, offset) as u64,
+            _ => 0,
+        };
+
+        match pointer(40, 140) {
+            0 => self.issues.push(HandoffIssue::NullPointer { table: "DSDT".into() }),
+            dsdt => _ = self.validate_sdt(dsdt, Some(b"DSDT")),
+        }
+
+        // The FACS is optional on hardware-reduced ACPI platforms.
+        let hardware_reduced = fadt.len() >= 116 && read_u32(fadt, 112) & (1 << 20) != 0;
+        match pointer(36, 132) {
+            0 if hardware_reduced => (),
+            0 => self.issues.push(HandoffIssue::NullPointer { table: "FACS".into() }),
+            // SAFETY: The FACS starts with a signature.
+            facs if unsafe { bytes(facs, 4) } != b"FACS" => {
+                self.issues.push(HandoffIssue::InvalidSignature { table: "FACS".into(), address: facs })
+            }
+            _ => (),
+        }
+    }
+
+    /// Validates an SMBIOS 3.0 entry point and the structure table it references.
+    fn validate_smbios3(&mut self, address: u64) {
+        let table = "SMBIOS 3.0 entry point";
+        // SAFETY: The SMBIOS 3.0 entry point is at least 24 bytes.
+        let entry_point = unsafe { bytes(address, 24) };
+        if &entry_point[..5] != b"_SM3_" {
+            return self.issues.push(HandoffIssue::InvalidSignature { table: table.into(), address });
+        }
+        let length = entry_point[6] as usize;
+        if length < 24 {
+            return self.issues.push(HandoffIssue::InvalidLength { table: table.into(), address, length });
+        }
+        // SAFETY: The entry point length was validated above.
+        if !checksum_is_valid(unsafe { bytes(address, length) }) {
+            self.issues.push(HandoffIssue::InvalidChecksum { table: table.into(), address });
+        }
+        self.validate_smbios_structures(read_u64(entry_point, 16), read_u32(entry_point, 12) as usize);
+    }
+
+    /// Validates an SMBIOS 2.x entry point and the structure table it references.
+    fn validate_smbios(&mut self, address: u64) {
+        let table = "SMBIOS entry point";
+        // SAFETY: The SMBIOS 2.x entry point is at least 31 bytes.
+        let entry_point = unsafe { bytes(address, 31) };
+        if &entry_point[..4] != b"_SM_" || &entry_point[16..21] != b"_DMI_" {
+            return self.issues.push(HandoffIssue::InvalidSignature { table: table.into(), address });
+        }
+        let length = entry_point[5] as usize;
+        if length < 31 {
+            return self.issues.push(HandoffIssue::InvalidLength { table: table.into(), address, length });
+        }
+        // SAFETY: The entry point length was validated above.
+        if !checksum_is_valid(unsafe { bytes(address, length) }) || !checksum_is_valid(&entry_point[16..31]) {
+            self.issues.push(HandoffIssue::InvalidChecksum { table: table.into(), address });
+        }
+        self.validate_smbios_structures(read_u32(entry_point, 24) as u64, read_u16(entry_point, 22) as usize);
+    }
+
+    /// Walks the SMBIOS structure table at `address`, which is at most `size` bytes.
+    fn validate_smbios_structures(&mut self, address: u64, size: usize) {
+        if address == 0 {
+            return self.issues.push(HandoffIssue::NullPointer { table: "SMBIOS structure table".into() });
+        }
+        // SAFETY: The entry point reports the table is at most `size` bytes.
+        let table = unsafe { bytes(address, size) };
+
+        let mut offset = 0;
+        let mut types = BTreeSet::new();
+        let mut handles = BTreeSet::new();
+        loop {
+            if offset + 4 > table.len() {
+                self.issues.push(HandoffIssue::MissingSmbiosEndOfTable);
+                break;
+            }
+            let (structure_type, length, handle) =
+                (table[offset], table[offset + 1] as usize, read_u16(table, offset + 2));
+            // The formatted area is followed by a string set, which ends with two NUL bytes.
+            let strings_end = table
+                .get(offset + length..)
+                .and_then(|strings| strings.windows(2).position(|pair| pair == [0, 0]))
+                .filter(|_| length >= 4);
+            let Some(strings_end) = strings_end else {
+                self.issues.push(HandoffIssue::MalformedSmbiosStructure { offset });
+                break;
+            };
+            if !handles.insert(handle) {
+                self.issues.push(HandoffIssue::DuplicateSmbiosHandle(handle));
+            }
+            types.insert(structure_type);
+            offset += length + strings_end + 2;
+            if structure_type == 127 {
+                break;
+            }
+        }
+
+        for required in &self.config.required_smbios_types {
+            if !types.contains(required) {
+                self.issues.push(HandoffIssue::MissingSmbiosType(*required));
+            }
+        }
+    }
+}
+
+extern "efiapi" fn validate_at_ready_to_boot(_event: efi::Event, config: Box<HandoffValidationConfig>) {
+    let tables: Vec<efi::ConfigurationTable> = {
+        let st = SYSTEM_TABLE.lock();
+        let Some(st) = st.as_ref() else {
+            return;
+        };
+        let st = st.system_table();
+        if st.configuration_table.is_null() {
+            Vec::new()
+        } else {
+            // SAFETY: The system table holds a valid configuration table array of the given length.
+            unsafe { core::slice::from_raw_parts(st.configuration_table, st.number_of_table_entries) }.to_vec()
+        }
+    };
+
+    let issues = validate(&tables, &config);
+    for issue in &issues {
+        log::error!("ACPI/SMBIOS handoff validation: {issue}");
+    }
+    debug_assert!(issues.is_empty(), "ACPI/SMBIOS handoff validation found {} issue(s)", issues.len());
+    if issues.is_empty() {
+        log::info!("ACPI/SMBIOS handoff validation passed");
+    }
+}
+
+/// A component that validates the ACPI and SMBIOS tables when ReadyToBoot is signaled.
+#[derive(IntoComponent, Default)]
+pub(crate) struct HandoffValidator;
+
+impl HandoffValidator {
+    fn entry_point(
+        self,
+        config: Config<HandoffValidationConfig>,
+        boot_services: StandardBootServices,
+    ) -> patina::error::Result<()> {
+        boot_services.create_event_ex(
+            EventType::NOTIFY_SIGNAL,
+            Tpl::CALLBACK,
+            Some(validate_at_ready_to_boot),
+            Box::new((*config).clone()),
+            &EVENT_GROUP_READY_TO_BOOT,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Sets the checksum byte at `offset` so that `bytes` sums to zero.
+    fn fix_checksum(bytes: &mut [u8], offset: usize) {
+        bytes[offset] = 0;
+        bytes[offset] = 0u8.wrapping_sub(bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+    }
+
+    fn sdt(signature: &[u8; 4], length: usize) -> Vec<u8> {
+        let mut table = vec![0u8; length];
+        table[..4].copy_from_slice(signature);
+        table[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        fix_checksum(&mut table, 9);
+        table
+    }
+
+    /// Leaks `bytes`, returning its address.
+    fn leak(bytes: Vec<u8>) -> u64 {
+        Box::leak(bytes.into_boxed_slice()).as_ptr() as u64
+    }
+
+    fn config_table(guid: efi::Guid, address: u64) -> efi::ConfigurationTable {
+        efi::ConfigurationTable { vendor_guid: guid, vendor_table: address as *mut _ }
+    }
+
+    /// Builds an RSDP, XSDT, FADT, DSDT, FACS and MADT, returning the address of the RSDP.
+    fn acpi_tables(corrupt_dsdt: bool) -> u64 {
+        let mut dsdt = sdt(b"DSDT", 40);
+        if corrupt_dsdt {
+            dsdt[39] = 0xFF;
+        }
+        let dsdt = leak(dsdt);
+        let mut facs = vec![0u8; 64];
+        facs[..4].copy_from_slice(b"FACS");
+        let facs = leak(facs);
+
+        let mut fadt = sdt(b"FACP", 276);
+        fadt[132..140].copy_from_slice(&facs.to_le_bytes());
+        fadt[140..148].copy_from_slice(&dsdt.to_le_bytes());
+        fix_checksum(&mut fadt, 9);
+        let fadt = leak(fadt);
+        let madt = leak(sdt(b"APIC", 44));
+
+        let mut xsdt = sdt(b"XSDT", SDT_HEADER_LENGTH + 16);
+        xsdt[36..44].copy_from_slice(&fadt.to_le_bytes());
+        xsdt[44..52].copy_from_slice(&madt.to_le_bytes());
+        fix_checksum(&mut xsdt, 9);
+        let xsdt = leak(xsdt);
+
+        let mut rsdp = vec![0u8; 36];
+        rsdp[..8].copy_from_slice(b"RSD PTR ");
+        rsdp[15] = 2;
+        rsdp[20..24].copy_from_slice(&36u32.to_le_bytes());
+        rsdp[24..32].copy_from_slice(&xsdt.to_le_bytes());
+        fix_checksum(&mut rsdp[..20], 8);
+        fix_checksum(&mut rsdp, 32);
+        leak(rsdp)
+    }
+
+    /// Builds an SMBIOS 3.0 entry point and structure table, returning the address of the entry point.
+    fn smbios3_tables(structures: &[(u8, u16)], end_of_table: bool) -> u64 {
+        let mut table = Vec::new();
+        for &(structure_type, handle) in structures {
+            table.extend_from_slice(&[structure_type, 4]);
+            table.extend_from_slice(&handle.to_le_bytes());
+            table.extend_from_slice(b"Patina\0\0");
+        }
+        if end_of_table {
+            table.extend_from_slice(&[127, 4, 0xFF, 0xFF, 0, 0]);
+        }
+        let size = table.len() as u32;
+        let table = leak(table);
+
+        let mut entry_point = vec![0u8; 24];
+        entry_point[..5].copy_from_slice(b"_SM3_");
+        entry_point[6] = 24;
+        entry_point[12..16].copy_from_slice(&size.to_le_bytes());
+        entry_point[16..24].copy_from_slice(&table.to_le_bytes());
+        fix_checksum(&mut entry_point, 5);
+        leak(entry_point)
+    }
+
+    #[test]
+    fn test_valid_tables_have_no_issues() {
+        let tables = [
+            config_table(system::ACPI_20_TABLE_GUID, acpi_tables(false)),
+            config_table(system::SMBIOS3_TABLE_GUID, smbios3_tables(&[(0, 0), (1, 1)], true)),
+        ];
+        let config = HandoffValidationConfig { require_acpi: true, require_smbios: true, ..Default::default() };
+        assert_eq!(validate(&tables, &config), vec![]);
+    }
+
+    #[test]
+    fn test_acpi_issues_are_reported() {
+        let rsdp = acpi_tables(true);
+        let tables = [config_table(system::ACPI_20_TABLE_GUID, rsdp)];
+        let config = HandoffValidationConfig { required_acpi_tables: vec![*b"FACP", *b"HPET"], ..Default::default() };
+
+        let issues = validate(&tables, &config);
+        assert_eq!(issues.len(), 2);
+        assert!(matches!(&issues[0], HandoffIssue::InvalidChecksum { table, .. } if table == "DSDT"));
+        assert_eq!(issues[1], HandoffIssue::MissingAcpiTable(*b"HPET"));
+
+        // SAFETY: The RSDP was leaked by acpi_tables, so it is valid to modify.
+        unsafe { (rsdp as *mut u8).write(b'X') };
+        assert_eq!(
+            validate(&tables, &config),
+            vec![HandoffIssue::InvalidSignature { table: "RSDP".into(), address: rsdp }]
+        );
+    }
+
+    #[test]
+    fn test_smbios_issues_are_reported() {
+        let tables = [config_table(system::SMBIOS3_TABLE_GUID, smbios3_tables(&[(0, 0), (2, 0)], false))];
+        let issues = validate(&tables, &HandoffValidationConfig::default());
+        assert_eq!(
+            issues,
+            vec![
+                HandoffIssue::DuplicateSmbiosHandle(0),
+                HandoffIssue::MissingSmbiosEndOfTable,
+                HandoffIssue::MissingSmbiosType(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_tables_are_only_reported_when_required() {
+        assert_eq!(validate(&[], &HandoffValidationConfig::default()), vec![]);
+
+        let config = HandoffValidationConfig { require_acpi: true, require_smbios: true, ..Default::default() };
+        assert_eq!(validate(&[], &config), vec![HandoffIssue::MissingAcpi, HandoffIssue::MissingSmbios]);
+        assert_eq!(HandoffIssue::MissingAcpiTable(*b"APIC").to_string(), "required ACPI table APIC is not installed");
+    }
+}
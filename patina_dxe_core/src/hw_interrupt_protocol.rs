@@ -1,9 +1,11 @@
 use crate::GicBases;
+use crate::allocator::EFI_RUNTIME_SERVICES_DATA_ALLOCATOR;
 use crate::tpl_lock::TplMutex;
 use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::ffi::c_void;
+use patina_internal_cpu::interrupts::gic_its::{GicIts, LPI_START};
 use patina_internal_cpu::interrupts::gic_manager::{AArch64InterruptInitializer, gic_initialize};
 use patina_internal_cpu::interrupts::{ExceptionContext, InterruptHandler, InterruptManager};
 use r_efi::efi;
@@ -13,7 +15,15 @@ use arm_gic::{
     gicv3::{GicV3, InterruptGroup},
 };
 use patina::boot_services::{BootServices, StandardBootServices};
-use patina::component::{IntoComponent, params::Config, service::Service};
+use patina::component::{
+    IntoComponent,
+    params::{Commands, Config},
+    service::{
+        IntoService, Service,
+        msi::{MsiController, MsiVector},
+    },
+};
+use patina::error::EfiError;
 use patina::guids::{HARDWARE_INTERRUPT_PROTOCOL, HARDWARE_INTERRUPT_PROTOCOL_V2};
 use patina::uefi_protocol::ProtocolInterface;
 
@@ -375,7 +385,9 @@ impl From<HardwareInterrupt2TriggerType> for Trigger {
 }
 
 struct HwInterruptProtocolHandler {
+    /// Handlers for the interrupt sources below `lpi_slot`, followed by handlers for the LPIs.
     handlers: TplMutex<Vec<Option<HwInterruptHandler>>>,
+    lpi_slot: usize,
     aarch64_int: TplMutex<AArch64InterruptInitializer<'static>>,
 }
 
@@ -390,7 +402,7 @@ impl InterruptHandler for HwInterruptProtocolHandler {
         let int_id = int_id.unwrap();
         let raw_value: u32 = int_id.into();
 
-        if raw_value >= self.handlers.lock().len() as u32 {
+        let Some(slot) = self.handler_slot(raw_value as usize) else {
             match raw_value {
                 1021 | 1022 | 1023 => {
                     // The special interrupt do not need to be acknowledged
@@ -400,9 +412,9 @@ impl InterruptHandler for HwInterruptProtocolHandler {
                 }
             }
             return;
-        }
+        };
 
-        if let Some(handler) = self.handlers.lock()[raw_value as usize] {
+        if let Some(handler) = self.handlers.lock()[slot] {
             handler(raw_value as u64, context);
         } else {
             GicV3::end_interrupt(int_id, InterruptGroup::Group1);
@@ -414,36 +426,51 @@ impl InterruptHandler for HwInterruptProtocolHandler {
 }
 
 impl HwInterruptProtocolHandler {
-    pub fn new(handlers: Vec<Option<HwInterruptHandler>>, aarch64_int: AArch64InterruptInitializer<'static>) -> Self {
+    pub fn new(
+        handlers: Vec<Option<HwInterruptHandler>>,
+        lpi_slot: usize,
+        aarch64_int: AArch64InterruptInitializer<'static>,
+    ) -> Self {
         Self {
             handlers: TplMutex::new(efi::TPL_HIGH_LEVEL, handlers, "Hardware Interrupt Lock"),
+            lpi_slot,
             aarch64_int: TplMutex::new(efi::TPL_HIGH_LEVEL, aarch64_int, "AArch64 GIC Lock"),
         }
     }
 
+    /// Returns the index of the handler for an interrupt source, if the source has one.
+    fn handler_slot(&self, interrupt_source: usize) -> Option<usize> {
+        let slot = match interrupt_source.checked_sub(LPI_START as usize) {
+            Some(lpi) => self.lpi_slot + lpi,
+            None if interrupt_source < self.lpi_slot => interrupt_source,
+            None => return None,
+        };
+        (slot < self.handlers.lock().len()).then_some(slot)
+    }
+
     /// Internal implementation of interrupt related functions.
     pub fn register_interrupt_source(&self, interrupt_source: usize, handler: HwInterruptHandler) -> efi::Status {
-        if interrupt_source >= self.handlers.lock().len() {
+        let Some(slot) = self.handler_slot(interrupt_source) else {
             return efi::Status::INVALID_PARAMETER;
-        }
+        };
 
         let m_handler = handler as *const c_void;
 
         // If the handler is a null pointer, return invalid parameter
-        if m_handler.is_null() & self.handlers.lock()[interrupt_source].is_none() {
+        if m_handler.is_null() & self.handlers.lock()[slot].is_none() {
             return efi::Status::INVALID_PARAMETER;
         }
 
-        if !m_handler.is_null() & self.handlers.lock()[interrupt_source].is_some() {
+        if !m_handler.is_null() & self.handlers.lock()[slot].is_some() {
             return efi::Status::ALREADY_STARTED;
         }
 
         // If the interrupt handler is unregistered then disable the interrupt
         let result = if m_handler.is_null() {
-            self.handlers.lock()[interrupt_source as usize] = None;
+            self.handlers.lock()[slot] = None;
             self.aarch64_int.lock().disable_interrupt_source(interrupt_source as u64)
         } else {
-            self.handlers.lock()[interrupt_source as usize] = Some(handler);
+            self.handlers.lock()[slot] = Some(handler);
             self.aarch64_int.lock().enable_interrupt_source(interrupt_source as u64)
         };
 
@@ -451,6 +478,25 @@ impl HwInterruptProtocolHandler {
     }
 }
 
+/// The [MsiController] service, backed by the GIC ITS.
+#[derive(IntoService)]
+#[service(dyn MsiController)]
+struct GicMsiController(&'static HwInterruptProtocolHandler);
+
+impl MsiController for GicMsiController {
+    fn allocate_msi(&self, device_id: u32) -> Result<MsiVector, EfiError> {
+        self.0.aarch64_int.lock().allocate_msi(device_id)
+    }
+
+    fn free_msi(&self, vector: MsiVector) -> Result<(), EfiError> {
+        // The vector can no longer be signaled once it is freed, so drop any handler registered for it.
+        if let Some(slot) = self.0.handler_slot(vector.interrupt_source as usize) {
+            self.0.handlers.lock()[slot] = None;
+        }
+        self.0.aarch64_int.lock().free_msi(vector)
+    }
+}
+
 #[derive(IntoComponent, Default)]
 /// A component to install the two hardware interrupt protocols.
 pub(crate) struct HwInterruptProtocolInstaller;
@@ -461,6 +507,7 @@ impl HwInterruptProtocolInstaller {
         interrupt_manager: Service<dyn InterruptManager>,
         gic_bases: Config<GicBases>,
         boot_services: StandardBootServices,
+        mut commands: Commands,
     ) -> patina::error::Result<()> {
         log::info!("GICv3 initializing {:x?}", (gic_bases.0, gic_bases.1));
         let gic_v3 = unsafe {
@@ -469,12 +516,37 @@ impl HwInterruptProtocolInstaller {
         };
         log::info!("GICv3 initialized");
 
-        let max_int = gic_v3.typer().num_spis();
-        let handlers = vec![None; max_int as usize];
-        let aarch64_int = AArch64InterruptInitializer::new(gic_v3);
+        let max_int = gic_v3.typer().num_spis() as usize;
+        let mut aarch64_int = AArch64InterruptInitializer::new(gic_v3);
+
+        let mut lpi_count = 0;
+        if let Some(gits_base) = gic_bases.2 {
+            log::info!("GIC ITS initializing {gits_base:x?}");
+            // The redistributor keeps using the LPI tables until the OS reprograms it, so they are allocated from
+            // runtime services data, which the OS does not reclaim.
+            let its = unsafe {
+                GicIts::initialize(
+                    gits_base,
+                    gic_bases.1,
+                    aarch64_int.gic_v3.typer().id_bits(),
+                    &EFI_RUNTIME_SERVICES_DATA_ALLOCATOR,
+                )
+            };
+            match its {
+                Ok(its) => {
+                    lpi_count = its.lpi_count();
+                    aarch64_int = aarch64_int.with_its(its);
+                }
+                // Wired interrupts still work without the ITS, so continue without MSI support.
+                Err(err) => log::error!("Failed to initialize GIC ITS, MSIs are unavailable: {err:?}"),
+            }
+        }
+
+        let handlers = vec![None; max_int + lpi_count];
 
         // Prepare context for the v1 interrupt handler
-        let hw_int_protocol_handler = Box::leak(Box::new(HwInterruptProtocolHandler::new(handlers, aarch64_int)));
+        let hw_int_protocol_handler =
+            Box::leak(Box::new(HwInterruptProtocolHandler::new(handlers, max_int, aarch64_int)));
         // Produce Interrupt Protocol with the initialized GIC
         let interrupt_protocol = Box::leak(Box::new(EfiHardwareInterruptProtocol::new(hw_int_protocol_handler)));
 
@@ -498,6 +570,11 @@ impl HwInterruptProtocolInstaller {
             )
             .inspect_err(|_| log::error!("Failed to register exception handler for hardware interrupts"))?;
 
+        if lpi_count != 0 {
+            commands.add_service(GicMsiController(hw_int_protocol_handler));
+            log::info!("installed MSI controller service with {lpi_count} LPIs");
+        }
+
         Ok(())
     }
 }
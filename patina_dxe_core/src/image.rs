@@ -6,18 +6,37 @@
 //!
 //! SPDX-License-Identifier: Apache-2.0
 //!
+use alloc::borrow::Cow;
 use alloc::{boxed::Box, collections::BTreeMap, string::String, vec, vec::Vec};
-use core::{convert::TryInto, ffi::c_void, mem::transmute, slice, slice::from_raw_parts};
+use core::{
+    convert::TryInto,
+    ffi::c_void,
+    mem::{size_of, transmute},
+    slice,
+    slice::from_raw_parts,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use goblin::pe::section_table;
 use patina::base::{DEFAULT_CACHE_ATTR, UEFI_PAGE_SIZE, align_up};
-use patina::error::EfiError;
 use patina::performance::{
     logging::{perf_image_start_begin, perf_image_start_end, perf_load_image_begin, perf_load_image_end},
     measurement::create_performance_measurement,
 };
-use patina::{guids, uefi_pages_to_size, uefi_size_to_pages};
+use patina::{
+    component::service::{
+        IntoService,
+        image_loader::{ImageExit, ImageLoader, ImageSource},
+    },
+    error::EfiError,
+};
+use patina::{guids, hii, uefi_pages_to_size, uefi_size_to_pages};
+use patina_internal_cpu::{
+    cache,
+    control_flow::{self as cpu_control_flow, ControlFlowFeatures},
+};
 use patina_internal_device_path::{DevicePathWalker, copy_device_path_to_boxed_slice, device_path_node_count};
 use patina_pi::{
+    dxe_services::GcdMemoryType,
     fw_fs::FfsSectionRawType::PE32,
     hob::{Hob, HobList},
     protocols::firmware_volume,
@@ -25,15 +44,18 @@ use patina_pi::{
 use r_efi::efi;
 
 use crate::{
-    allocator::{core_allocate_pages, core_free_pages},
+    allocator::{core_allocate_pages, core_free_pages, core_free_pool},
     config_tables::debug_image_info_table::{
         EfiDebugImageInfoNormal, core_new_debug_image_info_entry, core_remove_debug_image_info_entry,
         initialize_debug_image_info_table,
     },
+    control_flow,
     dxe_services::{self, core_set_memory_space_attributes},
-    events::EVENT_DB,
+    events::{self, EVENT_DB},
     filesystems::SimpleFile,
-    pecoff::{self, UefiPeInfo, relocation::RelocationBlock},
+    fv,
+    memory_protection::ImageProtectionPolicy,
+    pecoff::{self, ExecuteInPlaceLayout, InPlaceFixup, UefiPeInfo, relocation::RelocationBlock},
     protocol_db,
     protocols::{
         PROTOCOL_DB, core_install_protocol_interface, core_locate_device_path, core_uninstall_protocol_interface,
@@ -55,6 +77,432 @@ pub const EFI_IMAGE_SUBSYSTEM_EFI_RUNTIME_DRIVER: u16 = 12;
 
 pub const ENTRY_POINT_STACK_SIZE: usize = 0x100000;
 
+/// Executes uncompressed boot service drivers in place from memory-mapped firmware volumes instead of copying them
+/// into RAM.
+///
+/// The sections of an image that are not writable are executed where they are in the FV, and must have a file layout
+/// that matches their memory layout. The writable sections are copied into pages of their own, and the fixups of the
+/// image that point into them are redirected to the copy. If the image is not linked for its address in the FV, or
+/// the code of the image holds fixups that point into its writable sections, those fixups are written into the FV,
+/// which must then be writable system memory; they are restored when the image is unloaded.
+///
+/// Memory is identity mapped, so a reference to a writable section that is relative to the instruction pointer (such
+/// as RIP-relative addressing on x64 or ADRP on AArch64) reaches the original section in the FV rather than the copy.
+/// Only images that reach their writable sections through base relocations, such as images built with the large code
+/// model, may be placed in FVs that are executed in place. Runtime drivers, applications and images that cannot be
+/// laid out this way are still copied. Images are copied unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ExecuteInPlaceImages};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ExecuteInPlaceImages)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecuteInPlaceImages;
+
+static EXECUTE_IN_PLACE: AtomicBool = AtomicBool::new(false);
+
+/// Allows eligible images to be executed in place from the FV that contains them.
+pub(crate) fn enable_execute_in_place(_config: &ExecuteInPlaceImages) {
+    EXECUTE_IN_PLACE.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if the platform allows images to be executed in place.
+pub(crate) fn execute_in_place_enabled() -> bool {
+    EXECUTE_IN_PLACE.load(Ordering::SeqCst)
+}
+
+/// Selects how EFI Byte Code (EBC) images are handled.
+///
+/// The core has no EBC interpreter, so EBC images (such as the EBC drivers carried by some PCI option ROMs) are never
+/// loaded. LoadImage fails with EFI_UNSUPPORTED and logs a diagnostic naming the image, which lets the PCI bus driver
+/// fall back to a native driver in the same option ROM. By default the rejection is logged as a warning.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, EbcImagePolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(EbcImagePolicy::Deny)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EbcImagePolicy {
+    /// EBC images are skipped: LoadImage fails with EFI_UNSUPPORTED and a warning is logged.
+    #[default]
+    Skip,
+    /// EBC images are not expected on the platform: LoadImage fails with EFI_UNSUPPORTED and an error is logged.
+    Deny,
+}
+
+static DENY_EBC_IMAGES: AtomicBool = AtomicBool::new(false);
+
+/// Applies the platform policy for EBC images.
+pub(crate) fn set_ebc_image_policy(policy: &EbcImagePolicy) {
+    DENY_EBC_IMAGES.store(*policy == EbcImagePolicy::Deny, Ordering::SeqCst);
+}
+
+/// Selects how images with an import or TLS directory are handled.
+///
+/// UEFI images are loaded without resolving imports or setting up thread local storage, so an image that imports from
+/// other images or uses TLS would run with unresolved imports or uninitialized TLS data. Such images are usually
+/// built with the wrong toolchain settings. By default LoadImage fails with EFI_LOAD_ERROR and logs a diagnostic
+/// naming the image and the directory.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ImageDirectoryPolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ImageDirectoryPolicy::Warn)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageDirectoryPolicy {
+    /// Images with an import or TLS directory are rejected: LoadImage fails with EFI_LOAD_ERROR and an error is logged.
+    #[default]
+    Reject,
+    /// Images with an import or TLS directory are loaded, and a warning is logged.
+    Warn,
+}
+
+static WARN_ON_IMAGE_DIRECTORIES: AtomicBool = AtomicBool::new(false);
+
+/// Applies the platform policy for images with an import or TLS directory.
+pub(crate) fn set_image_directory_policy(policy: &ImageDirectoryPolicy) {
+    WARN_ON_IMAGE_DIRECTORIES.store(*policy == ImageDirectoryPolicy::Warn, Ordering::SeqCst);
+}
+
+static BLOCK_IMAGES_WITHOUT_NX: AtomicBool = AtomicBool::new(false);
+
+/// Applies the platform policy for images that are not NX compatible.
+pub(crate) fn set_image_protection_policy(policy: &ImageProtectionPolicy) {
+    BLOCK_IMAGES_WITHOUT_NX.store(policy.block_images_without_nx, Ordering::SeqCst);
+}
+
+/// Detects image entry points that do not return, so that a hung driver is reported rather than hanging the boot
+/// silently.
+///
+/// When the platform registers this config, a timer is armed each time an image is started. If the entry point has not
+/// returned or called Exit() when `timeout_ms` milliseconds have elapsed, the image is logged and `action` is taken.
+/// The timer is dispatched at TPL_NOTIFY, so an image that hangs at TPL_NOTIFY or above, or with interrupts disabled,
+/// cannot be detected.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ImageWatchdog, ImageWatchdogAction};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ImageWatchdog { timeout_ms: 30_000, action: ImageWatchdogAction::Skip })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImageWatchdog {
+    /// How long an entry point may run, in milliseconds.
+    pub timeout_ms: u64,
+    /// What to do when an entry point runs for longer than `timeout_ms`.
+    pub action: ImageWatchdogAction,
+}
+
+/// The action taken by the [ImageWatchdog] when an entry point does not return in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageWatchdogAction {
+    /// Panic, naming the image.
+    #[default]
+    Panic,
+    /// Abandon the image as if it had called Exit() with EFI_TIMEOUT, unload it and continue the boot.
+    ///
+    /// This is a best-effort recovery: the stack of the image and the context it was interrupted in are discarded, so
+    /// any resources the image holds are not released.
+    Skip,
+}
+
+static IMAGE_WATCHDOG: spin::RwLock<Option<ImageWatchdog>> = spin::RwLock::new(None);
+
+/// Arms the watchdog around every image entry point that is started from now on.
+pub(crate) fn enable_image_watchdog(config: &ImageWatchdog) {
+    *IMAGE_WATCHDOG.write() = Some(*config);
+}
+
+// arms a watchdog timer for the entry point of the image, if the platform enabled the watchdog.
+fn arm_image_watchdog(image_handle: efi::Handle) -> Option<efi::Event> {
+    let config = (*IMAGE_WATCHDOG.read())?;
+    let event = EVENT_DB
+        .create_event(
+            efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+            efi::TPL_NOTIFY,
+            Some(image_watchdog_expired),
+            Some(image_handle),
+            None,
+        )
+        .inspect_err(|err| log::error!("Failed to create image watchdog event: {err:?}"))
+        .ok()?;
+
+    // the timer period is in 100ns units.
+    let status = events::set_timer(event, efi::TIMER_RELATIVE, config.timeout_ms.saturating_mul(10_000));
+    if status != efi::Status::SUCCESS {
+        log::error!("Failed to arm image watchdog: {status:#x?}");
+        let _ = EVENT_DB.close_event(event);
+        return None;
+    }
+    Some(event)
+}
+
+// returns a name for the image that can be used to attribute it in a diagnostic.
+fn image_name(image_handle: efi::Handle) -> String {
+    PRIVATE_IMAGE_DATA.lock().image_name(image_handle)
+}
+
+extern "efiapi" fn image_watchdog_expired(_event: efi::Event, context: *mut c_void) {
+    let image_handle = context as efi::Handle;
+    let action = IMAGE_WATCHDOG.read().map(|config| config.action).unwrap_or_default();
+    let name = image_name(image_handle);
+
+    let current_image = PRIVATE_IMAGE_DATA.lock().running_images.last().copied();
+    if current_image != Some(image_handle) {
+        // the image started another image that is still running. That image has a watchdog of its own, and this image
+        // cannot be abandoned without also abandoning it.
+        let current_name = current_image.map(image_name).unwrap_or_default();
+        if action == ImageWatchdogAction::Panic {
+            panic!("Image watchdog: entry point of {name} has not returned; {current_name} is running.");
+        }
+        log::error!("Image watchdog: entry point of {name} has not returned; {current_name} is running.");
+        return;
+    }
+
+    match action {
+        ImageWatchdogAction::Panic => panic!("Image watchdog: entry point of {name} has not returned."),
+        ImageWatchdogAction::Skip => {
+            log::error!("Image watchdog: entry point of {name} has not returned. Skipping the image.");
+            // exit() switches back to core_start_image and does not return here.
+            exit(image_handle, efi::Status::TIMEOUT, 0, core::ptr::null_mut());
+        }
+    }
+}
+
+/// Limits nested StartImage() calls, to protect the boot against images that start images recursively.
+///
+/// Each nested entry point runs on a stack of its own, so runaway nesting exhausts memory. StartImage() fails with
+/// EFI_OUT_OF_RESOURCES if it would nest more than `max_depth` entry points and, if `detect_cycles` is set, with
+/// EFI_ACCESS_DENIED if the image is a copy of an image whose entry point is already running, such as an application
+/// that loads and starts itself. Both failures are logged with the chain of running images. Unless the platform
+/// registers this config, entry points may nest 16 deep and cycles are detected.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ImageStartNesting};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ImageStartNesting { max_depth: 8, ..Default::default() })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageStartNesting {
+    /// The most entry points that may run nested inside one another.
+    pub max_depth: usize,
+    /// Whether to refuse to start a copy of an image whose entry point is already running.
+    pub detect_cycles: bool,
+}
+
+impl ImageStartNesting {
+    const DEFAULT: Self = Self { max_depth: 16, detect_cycles: true };
+}
+
+impl Default for ImageStartNesting {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static IMAGE_START_NESTING: spin::RwLock<ImageStartNesting> = spin::RwLock::new(ImageStartNesting::DEFAULT);
+
+/// Applies the platform limits on nested StartImage() calls.
+pub(crate) fn set_image_start_nesting(config: &ImageStartNesting) {
+    *IMAGE_START_NESTING.write() = *config;
+}
+
+// returns an error if starting the image would nest too many entry points, or start a copy of a running image.
+fn check_image_start_nesting(image_handle: efi::Handle) -> Result<(), EfiError> {
+    let nesting = *IMAGE_START_NESTING.read();
+    let private_data = PRIVATE_IMAGE_DATA.lock();
+    let name = private_data.image_name(image_handle);
+
+    if private_data.running_images.len() >= nesting.max_depth {
+        log::error!(
+            "start_image failed: starting {name} would nest more than {} entry points. Running images: {}",
+            nesting.max_depth,
+            private_data.running_image_chain()
+        );
+        return Err(EfiError::OutOfResources);
+    }
+
+    if nesting.detect_cycles {
+        let pe_info = &private_data.private_image_data.get(&image_handle).ok_or(EfiError::InvalidParameter)?.pe_info;
+        let is_running = private_data.running_images.iter().any(|handle| {
+            private_data.private_image_data.get(handle).is_some_and(|image_data| image_data.pe_info == *pe_info)
+        });
+        if is_running {
+            log::error!(
+                "start_image failed: a copy of {name} is already running: {} -> {name}",
+                private_data.running_image_chain()
+            );
+            return Err(EfiError::AccessDenied);
+        }
+    }
+    Ok(())
+}
+
+/// Sets the size of the stack that image entry points run on.
+///
+/// Each image that is started runs on a stack of its own. Some images, such as the UEFI shell or OS loaders, need a
+/// larger stack than the default of 1MiB, while small drivers need much less; when images start one another, each
+/// nested image holds its stack until it returns. Images loaded from a firmware volume can be given a stack size of
+/// their own by the GUID of the firmware file they were loaded from. Sizes are rounded up to the minimum stack size.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ImageStackConfig};
+/// use r_efi::efi;
+/// # let physical_hob_list = core::ptr::null();
+///
+/// const SHELL_FILE_GUID: efi::Guid =
+///     efi::Guid::from_fields(0x7c04a583, 0x9e3e, 0x4f1c, 0xad, 0x65, &[0xe0, 0x52, 0x68, 0xd0, 0xb4, 0xd1]);
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ImageStackConfig { default_size: 0x10000, overrides: vec![(SHELL_FILE_GUID, 0x400000)] })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageStackConfig {
+    /// The stack size in bytes for images without an override.
+    pub default_size: usize,
+    /// Stack sizes in bytes for images, by the GUID of the firmware file the image was loaded from.
+    pub overrides: Vec<(efi::Guid, usize)>,
+}
+
+impl ImageStackConfig {
+    const DEFAULT: Self = Self { default_size: ENTRY_POINT_STACK_SIZE, overrides: Vec::new() };
+}
+
+impl Default for ImageStackConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static IMAGE_STACK_CONFIG: spin::RwLock<ImageStackConfig> = spin::RwLock::new(ImageStackConfig::DEFAULT);
+
+/// Applies the platform entry point stack sizes.
+pub(crate) fn set_image_stack_config(config: &ImageStackConfig) {
+    *IMAGE_STACK_CONFIG.write() = config.clone();
+}
+
+// returns the size of the stack that the entry point of the image runs on.
+fn entry_point_stack_size(image_handle: efi::Handle) -> usize {
+    let config = IMAGE_STACK_CONFIG.read();
+    if !config.overrides.is_empty() {
+        let file_path = PRIVATE_IMAGE_DATA
+            .lock()
+            .private_image_data
+            .get(&image_handle)
+            .map_or(core::ptr::null_mut(), |image_data| image_data.image_info.file_path);
+        if !file_path.is_null()
+            && let Ok(file_guid) = get_file_guid_from_device_path(file_path)
+            && let Some(&(_, size)) = config.overrides.iter().find(|(guid, _)| *guid == file_guid)
+        {
+            return size;
+        }
+    }
+    config.default_size
+}
+
+// returns an error if the image cannot be executed on this processor.
+fn check_image_machine(pe_info: &UefiPeInfo) -> Result<(), EfiError> {
+    let filename = pe_info.filename.as_deref().unwrap_or("<unknown>");
+    match pe_info.machine {
+        pecoff::COFF_MACHINE_EBC => {
+            if DENY_EBC_IMAGES.load(Ordering::SeqCst) {
+                log::error!(
+                    "core_load_pe_image failed: image {filename} is an EFI Byte Code (EBC) image. EBC images are not supported."
+                );
+            } else {
+                log::warn!(
+                    "core_load_pe_image: skipping image {filename}: EFI Byte Code (EBC) images are not supported, a native image is required."
+                );
+            }
+            Err(EfiError::Unsupported)
+        }
+        // 32-bit images cannot be relocated as PE32+ images, and calling them would require switching the processor
+        // to compatibility mode and thunking every call across bitness, which the core does not support.
+        pecoff::COFF_MACHINE_X86 => {
+            log::warn!(
+                "core_load_pe_image: skipping image {filename}: IA32 images are not supported, a native image is required."
+            );
+            Err(EfiError::Unsupported)
+        }
+        _ => Ok(()),
+    }
+}
+
+// returns the layout with which the image in `image` can be executed where it is, instead of being loaded into a new
+// buffer, or None if it must be loaded.
+fn execute_in_place_layout(pe_info: &UefiPeInfo, image: &[u8]) -> Option<ExecuteInPlaceLayout> {
+    if !execute_in_place_enabled() || pe_info.image_type != EFI_IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER {
+        return None;
+    }
+    let layout = pecoff::execute_in_place_layout(pe_info, image, image.as_ptr() as usize)?;
+    let image = &image[..pe_info.size_of_image as usize];
+    if !fv::is_fv_resident(image) || (layout.fixes_up_in_place && !is_writable_system_memory(image)) {
+        return None;
+    }
+    Some(layout)
+}
+
+// returns true if `data` lies in writable system memory, so that fixups can be written into it.
+fn is_writable_system_memory(data: &[u8]) -> bool {
+    let start = data.as_ptr() as efi::PhysicalAddress;
+    dxe_services::core_get_memory_space_descriptor(start).is_ok_and(|desc| {
+        desc.memory_type == GcdMemoryType::SystemMemory
+            && desc.attributes & (efi::MEMORY_RO | efi::MEMORY_RP) == 0
+            && start + data.len() as u64 <= desc.base_address + desc.length
+    })
+}
+
+// writes `value` to each fixup of the image at `image_base` that is executed in place.
+//
+// Safety: the fixups must lie within the sections of the image at `image_base`, which must be writable.
+unsafe fn write_in_place_fixups(image_base: usize, fixups: &[InPlaceFixup], value: impl Fn(&InPlaceFixup) -> u64) {
+    for fixup in fixups {
+        unsafe { ((image_base + fixup.offset) as *mut u64).write_unaligned(value(fixup)) };
+    }
+}
+
 // dummy function used to initialize PrivateImageData.entry_point.
 #[coverage(off)]
 extern "efiapi" fn unimplemented_entry_point(
@@ -64,6 +512,12 @@ extern "efiapi" fn unimplemented_entry_point(
     unimplemented!()
 }
 
+// the byte that entry point stacks are filled with, to find how much of the stack an image used.
+const STACK_FILL_PATTERN: u8 = 0xA5;
+
+// the share of an entry point stack, in percent, above which the usage of an image is logged as a warning.
+const STACK_USAGE_WARNING_PERCENT: usize = 90;
+
 // define a stack structure for coroutine support.
 struct ImageStack {
     stack: *const [u8],
@@ -98,6 +552,14 @@ impl ImageStack {
             // debug_assert!(false);
         }
 
+        // the stack pages may have held the code of an image that was since unloaded. Images started in compatibility
+        // mode may execute from memory they write, so make sure no stale instructions for the stack remain cached.
+        cache::synchronize_instruction_cache((stack + UEFI_PAGE_SIZE as u64) as usize, len);
+
+        // fill the stack with a pattern so that high_water_mark() can find how much of it was used.
+        // SAFETY: the stack was allocated above and is len bytes above the guard page.
+        unsafe { core::ptr::write_bytes((stack + UEFI_PAGE_SIZE as u64) as *mut u8, STACK_FILL_PATTERN, len) };
+
         // we have the guard page at the bottom, so we need to add a page to the stack pointer for the limit
         Ok(ImageStack {
             stack: core::ptr::slice_from_raw_parts_mut((stack + (UEFI_PAGE_SIZE as u64)) as *mut u8, len),
@@ -105,6 +567,27 @@ impl ImageStack {
             allocated_pages,
         })
     }
+
+    // returns the most bytes of the stack that were in use at once. The stack grows downward, so this is the distance
+    // from the base to the lowest byte that no longer holds the fill pattern.
+    fn high_water_mark(&self) -> usize {
+        // SAFETY: the stack is allocated for the lifetime of self.
+        let stack = unsafe { &*self.stack };
+        let unused = stack.iter().position(|&byte| byte != STACK_FILL_PATTERN).unwrap_or(self.len);
+        self.len - unused
+    }
+}
+
+// logs how much of its entry point stack an image used, so that the stack sizes in the ImageStackConfig can be tuned.
+fn report_stack_usage(image_handle: efi::Handle, stack: &ImageStack) {
+    let used = stack.high_water_mark();
+    let percent = used * 100 / stack.len;
+    let name = image_name(image_handle);
+    if percent >= STACK_USAGE_WARNING_PERCENT {
+        log::warn!("{name} used {used:#x} of {:#x} bytes ({percent}%) of its entry point stack.", stack.len);
+    } else {
+        log::info!("{name} used {used:#x} of {:#x} bytes ({percent}%) of its entry point stack.", stack.len);
+    }
 }
 
 impl Drop for ImageStack {
@@ -155,6 +638,44 @@ unsafe impl Stack for ImageStack {
     }
 }
 
+// Exit data that an image passed to exit(): a null-terminated UCS-2 string, optionally followed by binary data, in a
+// buffer the image allocated from pool. The buffer is freed when this is dropped, unless ownership of it is handed to
+// the caller of StartImage() with into_raw().
+struct ExitData {
+    size: usize,
+    data: *mut efi::Char16,
+}
+
+impl ExitData {
+    // returns the exit data as UCS-2 characters. A trailing odd byte is ignored.
+    fn as_slice(&self) -> &[efi::Char16] {
+        // Safety: exit() only records exit data that the image provided as a buffer of `size` bytes, and the buffer is
+        // owned by this struct until it is dropped.
+        unsafe { slice::from_raw_parts(self.data, self.size / size_of::<efi::Char16>()) }
+    }
+
+    // returns the null-terminated string at the start of the exit data.
+    fn to_string_lossy(&self) -> String {
+        let exit_data = self.as_slice();
+        let end = exit_data.iter().position(|&c| c == 0).unwrap_or(exit_data.len());
+        String::from_utf16_lossy(&exit_data[..end])
+    }
+
+    // releases ownership of the buffer to the caller, who becomes responsible for freeing it.
+    fn into_raw(self) -> (usize, *mut efi::Char16) {
+        let exit_data = core::mem::ManuallyDrop::new(self);
+        (exit_data.size, exit_data.data)
+    }
+}
+
+impl Drop for ExitData {
+    fn drop(&mut self) {
+        if let Err(err) = core_free_pool(self.data as *mut c_void) {
+            log::error!("Failed to free image exit data at {:p}: {err:?}", self.data);
+        }
+    }
+}
+
 // This struct tracks private data associated with a particular image handle.
 struct PrivateImageData {
     image_buffer: *mut [u8],
@@ -164,13 +685,18 @@ struct PrivateImageData {
     hii_resource_section_num_pages: Option<usize>,
     entry_point: efi::ImageEntryPoint,
     started: bool,
-    exit_data: Option<(usize, *mut efi::Char16)>,
+    exit_data: Option<ExitData>,
     image_info_ptr: *mut c_void,
     image_device_path_ptr: *mut c_void,
     pe_info: UefiPeInfo,
     relocation_data: Vec<RelocationBlock>,
     image_base_page: efi::PhysicalAddress,
     image_num_pages: usize,
+    // the pages holding the copied writable sections of an image that is executed in place.
+    writable_copy: Option<(efi::PhysicalAddress, usize)>,
+    writable_range: core::ops::Range<usize>,
+    // the fixups that were written into the FV for an image that is executed in place.
+    in_place_fixups: Vec<InPlaceFixup>,
 }
 
 impl PrivateImageData {
@@ -229,6 +755,9 @@ impl PrivateImageData {
             relocation_data: Vec::new(),
             image_base_page,
             image_num_pages: num_pages,
+            writable_copy: None,
+            writable_range: 0..0,
+            in_place_fixups: Vec::new(),
         };
 
         image_data.image_info.image_base = image_data.image_buffer as *mut c_void;
@@ -258,6 +787,65 @@ impl PrivateImageData {
             relocation_data: Vec::new(),
             image_base_page,
             image_num_pages,
+            writable_copy: None,
+            writable_range: 0..0,
+            in_place_fixups: Vec::new(),
+        }
+    }
+
+    // builds the private image data for an image that is executed in place from `image`, copying its writable sections
+    // into pages of their own and writing the fixups that the layout requires.
+    fn new_in_place(
+        image_info: efi::protocols::loaded_image::Protocol,
+        image: &[u8],
+        pe_info: &UefiPeInfo,
+        layout: &ExecuteInPlaceLayout,
+    ) -> Result<Self, EfiError> {
+        let image_buffer =
+            core::ptr::slice_from_raw_parts_mut(image.as_ptr() as *mut u8, image_info.image_size as usize);
+        let code_type = image_info.image_code_type;
+        let mut image_data = Self::new_with_existing_allocation(
+            image_info,
+            image_buffer,
+            unimplemented_entry_point,
+            pe_info,
+            image.as_ptr() as efi::PhysicalAddress,
+            0,
+        );
+        image_data.started = false;
+        image_data.image_info.image_base = image_buffer as *mut c_void;
+        image_data.writable_range = layout.writable.clone();
+
+        let mut copy: &mut [u8] = &mut [];
+        if !layout.writable.is_empty() {
+            let num_pages = uefi_size_to_pages!(layout.writable.len());
+            let mut copy_base: efi::PhysicalAddress = 0;
+            core_allocate_pages(efi::ALLOCATE_ANY_PAGES, code_type, num_pages, &mut copy_base, None)?;
+            image_data.writable_copy = Some((copy_base, num_pages));
+            // Safety: the pages were just allocated for the copy.
+            copy = unsafe { core::slice::from_raw_parts_mut(copy_base as *mut u8, layout.writable.len()) };
+        }
+
+        let fixups = pecoff::execute_in_place(pe_info, layout, image, image.as_ptr() as usize, copy)
+            .inspect_err(|err| log::error!("core_load_pe_image_failed: execute_in_place returned status: {err:?}"))
+            .map_err(|_| EfiError::LoadError)?;
+        // the fixups are recorded before they are written, so that they are restored when the image is unloaded.
+        image_data.in_place_fixups = fixups;
+        // Safety: the fixups lie in the sections of the image, and the layout only requires them when the FV is in
+        // writable system memory.
+        unsafe { write_in_place_fixups(image.as_ptr() as usize, &image_data.in_place_fixups, |fixup| fixup.relocated) };
+        Ok(image_data)
+    }
+
+    // returns the address of `section` once the image is loaded, which is in the copy for the writable sections of an
+    // image that is executed in place.
+    fn section_address(&self, section: &section_table::SectionTable) -> u64 {
+        let rva = section.virtual_address as usize;
+        match self.writable_copy {
+            Some((copy_base, _)) if self.writable_range.contains(&rva) => {
+                copy_base + (rva - self.writable_range.start) as u64
+            }
+            _ => self.image_info.image_base as u64 + rva as u64,
         }
     }
 
@@ -288,7 +876,33 @@ impl PrivateImageData {
 
 impl Drop for PrivateImageData {
     fn drop(&mut self) {
+        // images executed in place leave the FV as it was, so that they can be loaded from it again.
+        if !self.in_place_fixups.is_empty() {
+            let image_base = self.image_info.image_base as efi::PhysicalAddress;
+            let cache_attrs = dxe_services::core_get_memory_space_descriptor(image_base)
+                .map(|desc| desc.attributes & efi::CACHE_ATTRIBUTE_MASK)
+                .unwrap_or(DEFAULT_CACHE_ATTR);
+            match core_set_memory_space_attributes(image_base, self.image_info.image_size, cache_attrs | efi::MEMORY_XP)
+            {
+                // Safety: the fixups were written into the image, whose pages were just made writable.
+                Ok(()) => unsafe {
+                    write_in_place_fixups(image_base as usize, &self.in_place_fixups, |fixup| fixup.original)
+                },
+                Err(status) => log::error!(
+                    "Failed to make image at {image_base:#x} writable to restore its fixups with status {status:#x?}"
+                ),
+            }
+        }
+
+        if let Some((copy_base, num_pages)) = self.writable_copy
+            && let Err(status) = core_free_pages(copy_base, num_pages)
+        {
+            log::error!("core_free_pages returned error {status:#x?} for writable sections at {copy_base:#x}");
+        }
+
+        // images executed in place have no pages of their own to free.
         if !self.image_buffer.is_null()
+            && self.image_num_pages != 0
             && let Err(status) = core_free_pages(self.image_base_page, self.image_num_pages)
         {
             log::error!(
@@ -315,17 +929,32 @@ struct DxeCoreGlobalImageData {
     dxe_core_image_handle: efi::Handle,
     system_table: *mut efi::SystemTable,
     private_image_data: BTreeMap<efi::Handle, PrivateImageData>,
-    current_running_image: Option<efi::Handle>,
-    image_start_contexts: Vec<*const Yielder<efi::Handle, efi::Status>>,
+    // the images whose entry points are running, outermost first. The last one is the currently running image.
+    running_images: Vec<efi::Handle>,
+    image_start_contexts: Vec<(*const Yielder<efi::Handle, efi::Status>, ControlFlowFeatures)>,
 }
 
 impl DxeCoreGlobalImageData {
+    // returns a name for the image that can be used to attribute it in a diagnostic.
+    fn image_name(&self, image_handle: efi::Handle) -> String {
+        self.private_image_data
+            .get(&image_handle)
+            .and_then(|image_data| image_data.pe_info.filename.clone())
+            .unwrap_or_else(|| alloc::format!("{image_handle:?}"))
+    }
+
+    // returns the names of the running images, outermost first, for a diagnostic.
+    fn running_image_chain(&self) -> String {
+        let names: Vec<String> = self.running_images.iter().map(|&handle| self.image_name(handle)).collect();
+        names.join(" -> ")
+    }
+
     const fn new() -> Self {
         DxeCoreGlobalImageData {
             dxe_core_image_handle: core::ptr::null_mut(),
             system_table: core::ptr::null_mut(),
             private_image_data: BTreeMap::new(),
-            current_running_image: None,
+            running_images: Vec::new(),
             image_start_contexts: Vec::new(),
         }
     }
@@ -335,7 +964,7 @@ impl DxeCoreGlobalImageData {
         self.dxe_core_image_handle = core::ptr::null_mut();
         self.system_table = core::ptr::null_mut();
         self.private_image_data = BTreeMap::new();
-        self.current_running_image = None;
+        self.running_images = Vec::new();
         self.image_start_contexts = Vec::new();
     }
 }
@@ -368,6 +997,8 @@ fn empty_image_info() -> efi::protocols::loaded_image::Protocol {
 }
 
 fn apply_image_memory_protections(pe_info: &UefiPeInfo, private_info: &PrivateImageData) {
+    // the attributes of all sections are applied together, so that the GCD and page table are only updated once.
+    let mut section_attributes = Vec::with_capacity(pe_info.sections.len());
     for section in &pe_info.sections {
         let mut attributes = efi::MEMORY_XP;
         if section.characteristics & pecoff::IMAGE_SCN_CNT_CODE == pecoff::IMAGE_SCN_CNT_CODE {
@@ -380,8 +1011,8 @@ fn apply_image_memory_protections(pe_info: &UefiPeInfo, private_info: &PrivateIm
             attributes |= efi::MEMORY_RO;
         }
 
-        // each section starts at image_base + virtual_address, per PE/COFF spec.
-        let section_base_addr = (private_info.image_info.image_base as u64) + (section.virtual_address as u64);
+        // each section starts at image_base + virtual_address, per PE/COFF spec, unless it was copied.
+        let section_base_addr = private_info.section_address(section);
 
         let mut capabilities = attributes;
 
@@ -437,19 +1068,21 @@ fn apply_image_memory_protections(pe_info: &UefiPeInfo, private_info: &PrivateIm
             "Applying image memory protections on {section_base_addr:#X} for len {aligned_virtual_size:#X} with attributes {attributes:#X}",
         );
 
-        match dxe_services::core_set_memory_space_attributes(section_base_addr, aligned_virtual_size, attributes) {
-            Ok(_) => continue,
-            Err(status) => log::error!(
-                "Failed to set GCD attributes for image section {section_base_addr:#X} with Status {status:#X?}",
-            ),
-        }
+        section_attributes.push((section_base_addr, aligned_virtual_size, attributes));
+    }
+
+    if let Err(status) = dxe_services::core_set_memory_space_attributes_multi(&section_attributes) {
+        log::error!(
+            "Failed to set GCD attributes for the sections of image {} with Status {status:#X?}",
+            pe_info.filename.as_deref().unwrap_or("Unknown")
+        );
     }
 }
 
 fn remove_image_memory_protections(pe_info: &UefiPeInfo, private_info: &PrivateImageData) {
     for section in &pe_info.sections {
-        // each section starts at image_base + virtual_address, per PE/COFF spec.
-        let section_base_addr = (private_info.image_info.image_base as u64) + (section.virtual_address as u64);
+        // each section starts at image_base + virtual_address, per PE/COFF spec, unless it was copied.
+        let section_base_addr = private_info.section_address(section);
 
         // we need to get the current attributes for this region and remove our attributes
         // we need to reset this to efi::MEMORY_XP so that we can merge all of the pages allocated for this image
@@ -575,6 +1208,33 @@ fn install_dxe_core_image(hob_list: &HobList, system_table: &mut EfiSystemTable)
 }
 
 // loads and relocates the image in the specified slice and returns the
+// returns an error if the image has an import or TLS directory, unless the platform policy only warns about them.
+fn check_image_directories(pe_info: &UefiPeInfo) -> Result<(), EfiError> {
+    let filename = pe_info.filename.as_deref().unwrap_or("<unknown>");
+    let warn_only = WARN_ON_IMAGE_DIRECTORIES.load(Ordering::SeqCst);
+    let mut result = Ok(());
+    for (name, dir) in [("import", pe_info.import_dir), ("TLS", pe_info.tls_dir)] {
+        let Some(dir) = dir else {
+            continue;
+        };
+        if warn_only {
+            log::warn!(
+                "core_load_pe_image: image {filename} has a {name} directory (rva: {:#x}, size: {:#x}). UEFI images must not have one.",
+                dir.virtual_address,
+                dir.size
+            );
+        } else {
+            log::error!(
+                "core_load_pe_image failed: image {filename} has a {name} directory (rva: {:#x}, size: {:#x}). UEFI images must not have one.",
+                dir.virtual_address,
+                dir.size
+            );
+            result = Err(EfiError::LoadError);
+        }
+    }
+    result
+}
+
 // associated PrivateImageData structures.
 fn core_load_pe_image(
     image: &[u8],
@@ -585,6 +1245,9 @@ fn core_load_pe_image(
         .inspect_err(|err| log::error!("core_load_pe_image failed: UefiPeInfo::parse returned {err:?}"))
         .map_err(|_| EfiError::Unsupported)?;
 
+    check_image_machine(&pe_info)?;
+    check_image_directories(&pe_info)?;
+
     // based on the image type, determine the correct allocator and code/data types.
     let (code_type, data_type) = match pe_info.image_type {
         EFI_IMAGE_SUBSYSTEM_EFI_APPLICATION => (efi::LOADER_CODE, efi::LOADER_DATA),
@@ -619,20 +1282,32 @@ fn core_load_pe_image(
     image_info.image_code_type = code_type;
     image_info.image_data_type = data_type;
 
-    //allocate a buffer to hold the image (also updates private_info.image_info.image_base)
-    let mut private_info = PrivateImageData::new(image_info, &pe_info)?;
-    let loaded_image = unsafe { &mut *private_info.image_buffer };
-
-    //load the image into the new loaded image buffer
-    pecoff::load_image(&pe_info, image, loaded_image)
-        .inspect_err(|err| log::error!("core_load_pe_image_failed: load_image returned status: {err:?}"))
-        .map_err(|_| EfiError::LoadError)?;
-
-    //relocate the image to the address at which it was loaded.
+    let mut private_info = if let Some(layout) = execute_in_place_layout(&pe_info, image) {
+        // the sections that are not writable are already laid out in the FV, so they are used where they are.
+        log::info!("Executing {} in place.", pe_info.filename.as_deref().unwrap_or("Unknown"));
+        PrivateImageData::new_in_place(image_info, image, &pe_info, &layout)?
+    } else {
+        //allocate a buffer to hold the image (also updates private_info.image_info.image_base)
+        let mut private_info = PrivateImageData::new(image_info, &pe_info)?;
+        let loaded_image = unsafe { &mut *private_info.image_buffer };
+
+        //load the image into the new loaded image buffer
+        pecoff::load_image(&pe_info, image, loaded_image)
+            .inspect_err(|err| log::error!("core_load_pe_image_failed: load_image returned status: {err:?}"))
+            .map_err(|_| EfiError::LoadError)?;
+
+        //relocate the image to the address at which it was loaded.
+        let loaded_image_addr = private_info.image_info.image_base as usize;
+        private_info.relocation_data = pecoff::relocate_image(&pe_info, loaded_image_addr, loaded_image, &Vec::new())
+            .inspect_err(|err| log::error!("core_load_pe_image_failed: relocate_image returned status: {err:?}"))
+            .map_err(|_| EfiError::LoadError)?;
+        private_info
+    };
     let loaded_image_addr = private_info.image_info.image_base as usize;
-    private_info.relocation_data = pecoff::relocate_image(&pe_info, loaded_image_addr, loaded_image, &Vec::new())
-        .inspect_err(|err| log::error!("core_load_pe_image_failed: relocate_image returned status: {err:?}"))
-        .map_err(|_| EfiError::LoadError)?;
+
+    // the image was written through the data cache (by the load and relocation above, or by decompression of the FV
+    // that holds an image executed in place), so make it visible to instruction fetches before it is started.
+    cache::synchronize_instruction_cache(loaded_image_addr, private_info.image_info.image_size as usize);
 
     // update the entry point. Transmute is required here to cast the raw function address to the ImageEntryPoint function pointer type.
     private_info.entry_point = unsafe {
@@ -656,7 +1331,15 @@ fn core_load_pe_image(
                         &image_buf_ref[resource_section_offset..resource_section_offset + resource_section_size],
                     );
 
-                    log::info!("HII Resource Section found for {}.", pe_info.filename.as_deref().unwrap_or("Unknown"));
+                    let filename = pe_info.filename.as_deref().unwrap_or("Unknown");
+                    match hii::PackageList::parse(resource_slice) {
+                        Ok(package_list) => log::info!(
+                            "HII Resource Section found for {filename}: package list {:?} with {} packages.",
+                            package_list.guid(),
+                            package_list.packages().count()
+                        ),
+                        Err(_) => log::warn!("HII Resource Section of {filename} is not a valid HII package list."),
+                    }
                 } else {
                     log::error!(
                         "HII Resource Section offset {:#X} and size {:#X} are out of bounds for image {:?}.",
@@ -670,6 +1353,14 @@ fn core_load_pe_image(
         }
     }
 
+    if !pe_info.nx_compat && BLOCK_IMAGES_WITHOUT_NX.load(Ordering::SeqCst) {
+        log::error!(
+            "{} is not NX compatible, and the image protection policy blocks such images. Not loading image.",
+            pe_info.filename.as_deref().unwrap_or("Unknown")
+        );
+        return Err(EfiError::LoadError);
+    }
+
     match pe_info.image_type {
         EFI_IMAGE_SUBSYSTEM_EFI_APPLICATION if !pe_info.nx_compat => {
             // we are trying to load an application image that is not NX compatible, likely a bootloader
@@ -692,6 +1383,7 @@ fn core_load_pe_image(
 /// This function will map the image as RWX in the GCD and initiate compatibility mode in the GCD
 fn activate_compatibility_mode(private_info: &PrivateImageData) -> Result<(), EfiError> {
     log::error!("Attempting to load an application image that is not NX compatible. Activating compatibility mode.");
+    record_compatibility_mode(private_info, true);
     crate::gcd::activate_compatibility_mode();
     // for this image map all mem RWX preserving cache attributes if we find them
     let stripped_attrs = dxe_services::core_get_memory_space_descriptor(private_info.image_base_page)
@@ -722,9 +1414,20 @@ fn activate_compatibility_mode(private_info: &PrivateImageData) -> Result<(), Ef
         "Attempting to load {} that is not NX compatible. Compatibility mode is not allowed in this build, not loading image.",
         private_info.pe_info.filename.clone().unwrap_or(String::from("Unknown"))
     );
+    record_compatibility_mode(private_info, false);
     Err(EfiError::LoadError)
 }
 
+// records the image that needed compatibility mode, and whether it was allowed to load.
+fn record_compatibility_mode(private_info: &PrivateImageData, allowed: bool) {
+    crate::compatibility_mode::record_activation(
+        private_info.pe_info.filename.clone().unwrap_or(String::from("Unknown")),
+        private_info.image_base_page,
+        uefi_pages_to_size!(private_info.image_num_pages) as u64,
+        allowed,
+    );
+}
+
 extern "efiapi" fn runtime_image_protection_fixup_ebs(event: efi::Event, _context: *mut c_void) {
     let mut private_data = PRIVATE_IMAGE_DATA.lock();
 
@@ -978,6 +1681,9 @@ fn authenticate_image(
             authentication_status,
             device_path,
         );
+    } else {
+        // images are not verified until the Security Architectural Protocols are installed.
+        crate::security_posture::record_unverified_image();
     }
 
     EfiError::status_to_result(security_status)
@@ -1020,13 +1726,17 @@ pub fn core_load_image(
             if let Ok((_device_path, device_handle)) =
                 core_locate_device_path(efi::protocols::device_path::PROTOCOL_GUID, file_path)
             {
-                (image.to_vec(), false, device_handle, 0)
+                (Cow::Borrowed(image), false, device_handle, 0)
             } else {
                 // (i.e. it doesn't correspond to anything that actually exists in the system)
-                (image.to_vec(), false, protocol_db::INVALID_HANDLE, 0)
+                (Cow::Borrowed(image), false, protocol_db::INVALID_HANDLE, 0)
             }
         }
-        None => get_buffer_by_file_path(boot_policy, file_path)?,
+        None => {
+            let (buffer, from_fv, device_handle, authentication_status) =
+                get_buffer_by_file_path(boot_policy, file_path)?;
+            (Cow::Owned(buffer), from_fv, device_handle, authentication_status)
+        }
     };
 
     // authenticate the image
@@ -1094,11 +1804,18 @@ pub fn core_load_image(
         handle,
     );
 
-    // Notify the debugger of the image load.
+    // Notify the debugger of the image load, with the PDB signature so that symbol servers can resolve its symbols.
+    let symbol_server_key = private_info.pe_info.codeview.as_ref().map(|codeview| codeview.symbol_server_key());
     patina_debugger::notify_module_load(
         private_info.pe_info.filename.as_ref().unwrap_or(&String::from("")),
         private_info.image_info.image_base as usize,
         private_info.image_info.image_size as usize,
+        private_info.pe_info.codeview.as_ref().zip(symbol_server_key.as_deref()).map(
+            |(codeview, symbol_server_key)| patina_debugger::ModuleSymbols {
+                pdb_path: &codeview.pdb_path,
+                symbol_server_key,
+            },
+        ),
     );
 
     // install the loaded_image device path protocol for the new image. If input device path is not null, then make a
@@ -1144,6 +1861,11 @@ pub fn core_load_image(
     private_info.image_info_ptr = image_info_ptr;
     private_info.image_device_path_ptr = file_path as *mut c_void;
 
+    // an image that failed authentication is still loaded, so that the caller can apply its own policy to it.
+    if let Err(err) = security_status {
+        crate::security_posture::record_unsigned_image(private_info.pe_info.filename.as_deref(), err);
+    }
+
     // save the private image data for this image in the private image data map.
     PRIVATE_IMAGE_DATA.lock().private_image_data.insert(handle, private_info);
 
@@ -1215,31 +1937,112 @@ extern "efiapi" fn start_image(
 ) -> efi::Status {
     let status = core_start_image(image_handle);
 
-    // retrieve any exit data that was provided by the entry point.
-    if !exit_data_size.is_null() && !exit_data.is_null() {
-        let private_data = PRIVATE_IMAGE_DATA.lock();
-        if let Some(image_data) = private_data.private_image_data.get(&image_handle)
-            && let Some(image_exit_data) = image_data.exit_data
-            && !exit_data_size.is_null()
-            && !exit_data.is_null()
-        {
+    // retrieve any exit data that was provided by the entry point. The caller is responsible for freeing it; if the
+    // caller did not ask for it, it is freed here.
+    if let Some(image_exit_data) = take_exit_data(image_handle) {
+        if !exit_data_size.is_null() && !exit_data.is_null() {
+            let (size, data) = image_exit_data.into_raw();
             // Safety: Caller must ensure that exit_data_size and exit_data are valid pointers if they are non-null.
             unsafe {
-                exit_data_size.write_unaligned(image_exit_data.0);
-                exit_data.write_unaligned(image_exit_data.1);
+                exit_data_size.write_unaligned(size);
+                exit_data.write_unaligned(data);
             }
+        } else {
+            log::info!("start_image: discarding exit data: {}", image_exit_data.to_string_lossy());
         }
     }
 
+    unload_after_start(image_handle, status);
+
+    match status {
+        Ok(()) => efi::Status::SUCCESS,
+        Err(err) => err,
+    }
+}
+
+// takes ownership of the exit data that the image passed to exit(), if any.
+fn take_exit_data(image_handle: efi::Handle) -> Option<ExitData> {
+    PRIVATE_IMAGE_DATA
+        .lock()
+        .private_image_data
+        .get_mut(&image_handle)
+        .and_then(|image_data| image_data.exit_data.take())
+}
+
+// unloads the image after it has been started if it returned an error or is an application.
+fn unload_after_start(image_handle: efi::Handle, status: Result<(), efi::Status>) {
     let image_type = PRIVATE_IMAGE_DATA.lock().private_image_data.get(&image_handle).map(|x| x.pe_info.image_type);
 
     if status.is_err() || image_type == Some(EFI_IMAGE_SUBSYSTEM_EFI_APPLICATION) {
         let _result = core_unload_image(image_handle, true);
     }
+}
 
-    match status {
-        Ok(()) => efi::Status::SUCCESS,
-        Err(err) => err,
+// returns true if `device_path` holds a sequence of device path nodes that ends with an End of Entire Device Path node.
+fn is_terminated_device_path(device_path: &[u8]) -> bool {
+    let header_size = size_of::<efi::protocols::device_path::Protocol>();
+    let mut offset = 0;
+    while let Some(header) = device_path.get(offset..offset + header_size) {
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        if header[0] == efi::protocols::device_path::TYPE_END
+            && header[1] == efi::protocols::device_path::End::SUBTYPE_ENTIRE
+        {
+            return true;
+        }
+        if length < header_size {
+            return false;
+        }
+        offset += length;
+    }
+    false
+}
+
+/// The core implementation of the [ImageLoader] service.
+#[derive(IntoService)]
+#[service(dyn ImageLoader)]
+pub(crate) struct CoreImageLoader;
+
+impl ImageLoader for CoreImageLoader {
+    fn load_and_start(&self, source: ImageSource) -> Result<ImageExit, EfiError> {
+        let (image, device_path) = match &source {
+            ImageSource::Buffer { image, device_path } => (Some(image.as_slice()), device_path.as_ref()),
+            ImageSource::DevicePath(device_path) => (None, Some(device_path)),
+        };
+        let file_path = match device_path {
+            Some(device_path) if is_terminated_device_path(device_path) => {
+                device_path.as_ptr() as *mut efi::protocols::device_path::Protocol
+            }
+            Some(_) => {
+                log::error!("load_and_start: device path is not terminated within its buffer.");
+                return Err(EfiError::InvalidParameter);
+            }
+            None => core::ptr::null_mut(),
+        };
+
+        let (image_handle, security_status) = core_load_image(false, protocol_db::DXE_CORE_HANDLE, file_path, image)?;
+        if let Err(err) = security_status {
+            log::error!("load_and_start: image failed authentication: {err:?}");
+            let _result = core_unload_image(image_handle, true);
+            return Err(err);
+        }
+
+        let status = core_start_image(image_handle);
+        let started = PRIVATE_IMAGE_DATA
+            .lock()
+            .private_image_data
+            .get(&image_handle)
+            .is_some_and(|image_data| image_data.started);
+
+        // the exit data buffer is freed when the taken exit data is dropped.
+        let exit_data = take_exit_data(image_handle).map(|exit_data| exit_data.as_slice().to_vec());
+
+        unload_after_start(image_handle, status);
+
+        match status {
+            Ok(()) => Ok(ImageExit { status: efi::Status::SUCCESS, exit_data }),
+            Err(status) if started => Ok(ImageExit { status, exit_data }),
+            Err(status) => Err(status.into()),
+        }
     }
 }
 
@@ -1254,8 +2057,13 @@ pub fn core_start_image(image_handle: efi::Handle) -> Result<(), efi::Status> {
         Err(EfiError::InvalidParameter)?;
     }
 
+    check_image_start_nesting(image_handle)?;
+
     // allocate a buffer for the entry point stack.
-    let stack = ImageStack::new(ENTRY_POINT_STACK_SIZE)?;
+    let stack = ImageStack::new(entry_point_stack_size(image_handle))?;
+
+    // the watchdog may abandon the image from a timer notification, which leaves the TPL raised.
+    let entry_tpl = events::current_tpl();
 
     perf_image_start_begin(image_handle, create_performance_measurement);
 
@@ -1269,15 +2077,20 @@ pub fn core_start_image(image_handle: efi::Handle) -> Result<(), efi::Status> {
         if let Some(private_info) = private_data.private_image_data.get_mut(&image_handle) {
             private_info.started = true;
             let entry_point = private_info.entry_point;
+            let features = control_flow::image_features(&private_info.pe_info);
 
-            // save a pointer to the yielder so that exit() can use it.
-            private_data.image_start_contexts.push(yielder as *const Yielder<_, _>);
+            // save a pointer to the yielder and the control flow protections in effect so that exit() can use them.
+            private_data.image_start_contexts.push((yielder as *const Yielder<_, _>, cpu_control_flow::enabled()));
 
             // get a copy of the system table pointer to pass to the entry point.
             let system_table = private_data.system_table;
             // drop our reference to the private data (i.e. release the lock).
             drop(private_data);
 
+            // safety note: this frame never returns; exit() restores the previous protections before switching back
+            // to start_image, so no frame is returned through with a different pointer authentication state.
+            unsafe { cpu_control_flow::set_enabled(features) };
+
             // invoke the entry point. Code on the other side of this pointer is
             // FFI, which is inherently unsafe, but it's not  "technically" unsafe
             // from a rust standpoint since r_efi doesn't define the ImageEntryPoint
@@ -1295,14 +2108,11 @@ pub fn core_start_image(image_handle: efi::Handle) -> Result<(), efi::Status> {
         status
     });
 
-    // Save the handle of the previously running image and update the currently
-    // running image to the one we are about to invoke. In the event of nested
-    // calls to StartImage(), the chain of previously running images will
-    // be preserved on the stack of the various StartImage() instances.
-    let mut private_data = PRIVATE_IMAGE_DATA.lock();
-    let previous_image = private_data.current_running_image;
-    private_data.current_running_image = Some(image_handle);
-    drop(private_data);
+    let watchdog = arm_image_watchdog(image_handle);
+
+    // make the image we are about to invoke the currently running image. In the event of nested calls to
+    // StartImage(), the chain of previously running images is preserved below it.
+    PRIVATE_IMAGE_DATA.lock().running_images.push(image_handle);
 
     // switch stacks and execute the above defined coroutine to start the image.
     let status = match coroutine.resume(image_handle) {
@@ -1312,6 +2122,15 @@ pub fn core_start_image(image_handle: efi::Handle) -> Result<(), efi::Status> {
         CoroutineResult::Return(status) => status,
     };
 
+    if let Some(watchdog) = watchdog
+        && let Err(err) = EVENT_DB.close_event(watchdog)
+    {
+        log::error!("Failed to close image watchdog event: {err:?}");
+    }
+    if events::current_tpl() > entry_tpl {
+        events::restore_tpl(entry_tpl);
+    }
+
     log::info!("start_image entrypoint exit with status: {status:x?}");
 
     // because we used exit() to return from the coroutine (as opposed to
@@ -1322,7 +2141,9 @@ pub fn core_start_image(image_handle: efi::Handle) -> Result<(), efi::Status> {
     // executed.
     unsafe { coroutine.force_reset() };
 
-    PRIVATE_IMAGE_DATA.lock().current_running_image = previous_image;
+    report_stack_usage(image_handle, &coroutine.into_stack());
+
+    PRIVATE_IMAGE_DATA.lock().running_images.pop();
 
     perf_image_start_end(image_handle, create_performance_measurement);
 
@@ -1457,7 +2278,7 @@ extern "efiapi" fn exit(
 
     // image has been started - check the currently running image.
     let mut private_data = PRIVATE_IMAGE_DATA.lock();
-    if Some(image_handle) != private_data.current_running_image {
+    if private_data.running_images.last() != Some(&image_handle) {
         return efi::Status::INVALID_PARAMETER;
     }
 
@@ -1467,17 +2288,21 @@ extern "efiapi" fn exit(
         && !exit_data.is_null()
         && let Some(image_data) = private_data.private_image_data.get_mut(&image_handle)
     {
-        image_data.exit_data = Some((exit_data_size, exit_data));
+        image_data.exit_data = Some(ExitData { size: exit_data_size, data: exit_data });
     }
 
     // retrieve the yielder that was saved in the start_image entry point
     // coroutine wrapper.
     // safety note: this assumes that the top of the image_start_contexts stack
     // is the currently running image.
-    if let Some(yielder) = private_data.image_start_contexts.pop() {
+    if let Some((yielder, previous_features)) = private_data.image_start_contexts.pop() {
         let yielder = unsafe { &*yielder };
         drop(private_data);
 
+        // restore the control flow protections that were in effect when start_image was called, as control
+        // returns to it below.
+        unsafe { control_flow::restore(previous_features) };
+
         // safety note: any variables with "Drop" routines that need to run
         // need to be explicitly dropped before calling suspend(). Since suspend()
         // effectively "longjmp"s back to StartImage(), rust automatic
@@ -1493,6 +2318,73 @@ extern "efiapi" fn exit(
     efi::Status::ACCESS_DENIED
 }
 
+/// Returns the file name of the image that is currently executing, if any.
+///
+/// Returns `None` when no image is running (i.e. the DXE core itself is executing), when the running image has no
+/// file name, or when the image data is locked further up the call stack.
+pub(crate) fn core_current_image_name() -> Option<String> {
+    let private_data = PRIVATE_IMAGE_DATA.try_lock()?;
+    let handle = *private_data.running_images.last()?;
+    private_data.private_image_data.get(&handle)?.pe_info.filename.clone()
+}
+
+/// Calls `f` with the handle and file name of the running image, without allocating.
+///
+/// Returns `None` if the DXE core is running, or when the image data is locked further up the call stack.
+pub(crate) fn try_with_current_image<R>(f: impl FnOnce(efi::Handle, Option<&str>) -> R) -> Option<R> {
+    let private_data = PRIVATE_IMAGE_DATA.try_lock()?;
+    let handle = *private_data.running_images.last()?;
+    let name =
+        private_data.private_image_data.get(&handle).and_then(|image_data| image_data.pe_info.filename.as_deref());
+    Some(f(handle, name))
+}
+
+/// Returns the handle of the running image, or `Some(None)` if the DXE core is running.
+///
+/// Returns `None` when the image data is locked further up the call stack.
+pub(crate) fn try_current_image_handle() -> Option<Option<efi::Handle>> {
+    Some(PRIVATE_IMAGE_DATA.try_lock()?.running_images.last().copied())
+}
+
+/// Returns a name for the image with `image_handle` that can be used to attribute it in a diagnostic.
+///
+/// Returns `None` when the image data is locked further up the call stack.
+pub(crate) fn try_image_name(image_handle: efi::Handle) -> Option<String> {
+    Some(PRIVATE_IMAGE_DATA.try_lock()?.image_name(image_handle))
+}
+
+/// Calls `f` with the file name of the loaded image containing `address` and the offset of `address` in that image.
+///
+/// Returns `None` when no loaded image contains the address, or when the image data is locked further up the call
+/// stack.
+pub(crate) fn with_image_at_address<R>(address: u64, f: impl FnOnce(&str, u64) -> R) -> Option<R> {
+    let private_data = PRIVATE_IMAGE_DATA.try_lock()?;
+    let image = private_data.private_image_data.values().find(|image| {
+        let base = image.image_info.image_base as u64;
+        (base..base + image.image_info.image_size).contains(&address)
+    })?;
+    Some(f(image.pe_info.filename.as_deref().unwrap_or("Unknown"), address - image.image_info.image_base as u64))
+}
+
+/// Calls `f` with the PE information of the DXE core image.
+///
+/// Returns `None` if the DXE core image has not been installed.
+pub(crate) fn with_dxe_core_pe_info<R>(f: impl FnOnce(&UefiPeInfo) -> R) -> Option<R> {
+    let private_data = PRIVATE_IMAGE_DATA.lock();
+    private_data.private_image_data.get(&private_data.dxe_core_image_handle).map(|image| f(&image.pe_info))
+}
+
+/// Returns the base address and size of the DXE core image.
+///
+/// Returns `None` if the DXE core image has not been installed.
+pub(crate) fn dxe_core_image_range() -> Option<(u64, u64)> {
+    let private_data = PRIVATE_IMAGE_DATA.lock();
+    private_data
+        .private_image_data
+        .get(&private_data.dxe_core_image_handle)
+        .map(|image| (image.image_info.image_base as u64, image.image_info.image_size))
+}
+
 /// Initializes image services for the DXE core.
 pub fn init_image_support(hob_list: &HobList, system_table: &mut EfiSystemTable) {
     // initialize system table entry in private global.
@@ -1525,16 +2417,29 @@ pub fn init_image_support(hob_list: &HobList, system_table: &mut EfiSystemTable)
 #[coverage(off)]
 mod tests {
     extern crate std;
-    use super::{empty_image_info, get_buffer_by_file_path, load_image};
+    use super::{
+        CoreImageLoader, ENTRY_POINT_STACK_SIZE, EbcImagePolicy, ExitData, IMAGE_WATCHDOG, ImageStack,
+        ImageStackConfig, ImageStartNesting, ImageWatchdog, ImageWatchdogAction, empty_image_info,
+        enable_image_watchdog, entry_point_stack_size, get_buffer_by_file_path, image_watchdog_expired,
+        is_terminated_device_path, load_image, set_ebc_image_policy, set_image_stack_config, set_image_start_nesting,
+    };
     use crate::{
+        allocator::core_allocate_pool,
+        events,
         image::{PRIVATE_IMAGE_DATA, exit, start_image, unload_image},
         protocol_db,
         protocols::{PROTOCOL_DB, core_install_protocol_interface},
         systemtables::{SYSTEM_TABLE, init_system_table},
         test_collateral, test_support,
     };
-    use core::{ffi::c_void, sync::atomic::AtomicBool};
-    use patina::error::EfiError;
+    use core::{
+        ffi::c_void,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+    use patina::{
+        component::service::image_loader::{ImageLoader, ImageSource},
+        error::EfiError,
+    };
     use r_efi::efi;
     use std::{fs::File, io::Read};
 
@@ -1616,6 +2521,78 @@ mod tests {
         });
     }
 
+    #[test]
+    fn load_image_should_skip_ebc_and_ia32_images() {
+        with_locked_state(|| {
+            let mut test_file =
+                File::open(test_collateral!("test_image_msvc_hii.pe32")).expect("failed to open test file.");
+            let mut image: Vec<u8> = Vec::new();
+            test_file.read_to_end(&mut image).expect("failed to read test file");
+
+            for machine in [crate::pecoff::COFF_MACHINE_EBC, crate::pecoff::COFF_MACHINE_X86] {
+                // rewrite the COFF header machine type, which follows the PE signature.
+                let pe_offset = u32::from_le_bytes(image[0x3c..0x40].try_into().unwrap()) as usize;
+                image[pe_offset + 4..pe_offset + 6].copy_from_slice(&machine.to_le_bytes());
+
+                let mut image_handle: efi::Handle = core::ptr::null_mut();
+                let status = load_image(
+                    false.into(),
+                    protocol_db::DXE_CORE_HANDLE,
+                    core::ptr::null_mut(),
+                    image.as_mut_ptr() as *mut c_void,
+                    image.len(),
+                    core::ptr::addr_of_mut!(image_handle),
+                );
+                assert_eq!(status, efi::Status::UNSUPPORTED);
+                assert!(image_handle.is_null());
+            }
+
+            // denied EBC images are rejected the same way, without asserting on the contents of the image.
+            set_ebc_image_policy(&EbcImagePolicy::Deny);
+            let mut image_handle: efi::Handle = core::ptr::null_mut();
+            let pe_offset = u32::from_le_bytes(image[0x3c..0x40].try_into().unwrap()) as usize;
+            image[pe_offset + 4..pe_offset + 6].copy_from_slice(&crate::pecoff::COFF_MACHINE_EBC.to_le_bytes());
+            let status = load_image(
+                false.into(),
+                protocol_db::DXE_CORE_HANDLE,
+                core::ptr::null_mut(),
+                image.as_mut_ptr() as *mut c_void,
+                image.len(),
+                core::ptr::addr_of_mut!(image_handle),
+            );
+            set_ebc_image_policy(&EbcImagePolicy::Skip);
+            assert_eq!(status, efi::Status::UNSUPPORTED);
+            assert!(image_handle.is_null());
+        });
+    }
+
+    #[test]
+    fn load_image_should_reject_images_with_an_import_or_tls_directory() {
+        with_locked_state(|| {
+            let mut test_file =
+                File::open(test_collateral!("test_image_msvc_hii.pe32")).expect("failed to open test file.");
+            let mut image: Vec<u8> = Vec::new();
+            test_file.read_to_end(&mut image).expect("failed to read test file");
+
+            // give the image an import directory, the second data directory of the PE32+ optional header.
+            let pe_offset = u32::from_le_bytes(image[0x3c..0x40].try_into().unwrap()) as usize;
+            let import_dir = pe_offset + 4 + 20 + 112 + 8;
+            image[import_dir..import_dir + 8].copy_from_slice(&[0x00, 0x10, 0, 0, 0x14, 0, 0, 0]);
+
+            let mut image_handle: efi::Handle = core::ptr::null_mut();
+            let status = load_image(
+                false.into(),
+                protocol_db::DXE_CORE_HANDLE,
+                core::ptr::null_mut(),
+                image.as_mut_ptr() as *mut c_void,
+                image.len(),
+                core::ptr::addr_of_mut!(image_handle),
+            );
+            assert_eq!(status, efi::Status::LOAD_ERROR);
+            assert!(image_handle.is_null());
+        });
+    }
+
     #[test]
     fn load_image_should_authenticate_the_image_with_security_arch() {
         with_locked_state(|| {
@@ -1811,6 +2788,275 @@ mod tests {
         });
     }
 
+    #[test]
+    fn load_and_start_should_reject_images_that_cannot_be_loaded() {
+        with_locked_state(|| {
+            // the device path is not terminated by an End of Entire Device Path node.
+            let unterminated = vec![0x04, 0x04, 0x08, 0x00, 0xaa, 0xbb, 0xcc, 0xdd];
+            let result = CoreImageLoader.load_and_start(ImageSource::DevicePath(unterminated));
+            assert_eq!(result, Err(EfiError::InvalidParameter));
+
+            let image_count = PRIVATE_IMAGE_DATA.lock().private_image_data.len();
+            let result =
+                CoreImageLoader.load_and_start(ImageSource::Buffer { image: vec![0; 0x100], device_path: None });
+            assert_eq!(result, Err(EfiError::Unsupported));
+            assert_eq!(PRIVATE_IMAGE_DATA.lock().private_image_data.len(), image_count);
+        });
+    }
+
+    #[test]
+    fn is_terminated_device_path_should_require_an_end_node_within_the_buffer() {
+        let end = [efi::protocols::device_path::TYPE_END, efi::protocols::device_path::End::SUBTYPE_ENTIRE, 0x04, 0x00];
+        assert!(is_terminated_device_path(&end));
+
+        let mut path = vec![0x04, 0x04, 0x08, 0x00, 0xaa, 0xbb, 0xcc, 0xdd];
+        assert!(!is_terminated_device_path(&path));
+        path.extend_from_slice(&end);
+        assert!(is_terminated_device_path(&path));
+
+        // a node that claims to extend past the end of the buffer, or has a length shorter than its header.
+        assert!(!is_terminated_device_path(&[0x04, 0x04, 0x40, 0x00]));
+        assert!(!is_terminated_device_path(&[0x04, 0x04, 0x00, 0x00, 0x7f, 0xff, 0x04, 0x00]));
+        assert!(!is_terminated_device_path(&[]));
+    }
+
+    #[test]
+    fn start_image_should_hand_exit_data_to_the_caller() {
+        with_locked_state(|| {
+            let mut test_file =
+                File::open(test_collateral!("RustImageTestDxe.efi")).expect("failed to open test file.");
+            let mut image: Vec<u8> = Vec::new();
+            test_file.read_to_end(&mut image).expect("failed to read test file");
+
+            let mut image_handle: efi::Handle = core::ptr::null_mut();
+            let status = load_image(
+                false.into(),
+                protocol_db::DXE_CORE_HANDLE,
+                core::ptr::null_mut(),
+                image.as_mut_ptr() as *mut c_void,
+                image.len(),
+                core::ptr::addr_of_mut!(image_handle),
+            );
+            assert_eq!(status, efi::Status::SUCCESS);
+
+            // as in start_image_should_start_image, the entry point is replaced with a stub in the test executable.
+            // The stub exits with exit data allocated from pool, as the UEFI spec requires.
+            extern "efiapi" fn test_entry_point(
+                image_handle: *mut core::ffi::c_void,
+                _system_table: *mut r_efi::system::SystemTable,
+            ) -> efi::Status {
+                let message: Vec<u16> = "Update failed\0".encode_utf16().collect();
+                let size = message.len() * size_of::<u16>();
+                let data = core_allocate_pool(efi::BOOT_SERVICES_DATA, size).unwrap() as *mut u16;
+                unsafe { core::ptr::copy_nonoverlapping(message.as_ptr(), data, message.len()) };
+                exit(image_handle, efi::Status::ABORTED, size, data)
+            }
+            let mut private_data = PRIVATE_IMAGE_DATA.lock();
+            let image_data = private_data.private_image_data.get_mut(&image_handle).unwrap();
+            image_data.entry_point = test_entry_point;
+            drop(private_data);
+
+            let mut exit_data_size = 0;
+            let mut exit_data: *mut u16 = core::ptr::null_mut();
+            let status =
+                start_image(image_handle, core::ptr::addr_of_mut!(exit_data_size), core::ptr::addr_of_mut!(exit_data));
+            assert_eq!(status, efi::Status::ABORTED);
+
+            // the caller owns the exit data, and the image was unloaded because it returned an error.
+            assert!(!exit_data.is_null());
+            let exit_data = ExitData { size: exit_data_size, data: exit_data };
+            assert_eq!(exit_data.to_string_lossy(), "Update failed");
+            assert_eq!(exit_data.as_slice().len(), 14);
+            assert!(!PRIVATE_IMAGE_DATA.lock().private_image_data.contains_key(&image_handle));
+        });
+    }
+
+    #[test]
+    fn image_watchdog_should_skip_a_hung_image() {
+        with_locked_state(|| {
+            let mut test_file =
+                File::open(test_collateral!("RustImageTestDxe.efi")).expect("failed to open test file.");
+            let mut image: Vec<u8> = Vec::new();
+            test_file.read_to_end(&mut image).expect("failed to read test file");
+
+            let mut image_handle: efi::Handle = core::ptr::null_mut();
+            let status = load_image(
+                false.into(),
+                protocol_db::DXE_CORE_HANDLE,
+                core::ptr::null_mut(),
+                image.as_mut_ptr() as *mut c_void,
+                image.len(),
+                core::ptr::addr_of_mut!(image_handle),
+            );
+            assert_eq!(status, efi::Status::SUCCESS);
+
+            // as in start_image_should_start_image, the entry point is replaced with a stub in the test executable.
+            // The stub stands in for a hung entry point: it raises the TPL as the timer notification would and
+            // expires the watchdog, which must not return to it.
+            extern "efiapi" fn test_entry_point(
+                image_handle: *mut core::ffi::c_void,
+                _system_table: *mut r_efi::system::SystemTable,
+            ) -> efi::Status {
+                events::raise_tpl(efi::TPL_NOTIFY);
+                image_watchdog_expired(core::ptr::null_mut(), image_handle);
+                unreachable!("the watchdog should have abandoned the image");
+            }
+            let mut private_data = PRIVATE_IMAGE_DATA.lock();
+            let image_data = private_data.private_image_data.get_mut(&image_handle).unwrap();
+            image_data.entry_point = test_entry_point;
+            drop(private_data);
+
+            enable_image_watchdog(&ImageWatchdog { timeout_ms: 1000, action: ImageWatchdogAction::Skip });
+            let tpl = events::current_tpl();
+            let status = start_image(image_handle, core::ptr::null_mut(), core::ptr::null_mut());
+            *IMAGE_WATCHDOG.write() = None;
+
+            assert_eq!(status, efi::Status::TIMEOUT);
+            assert_eq!(events::current_tpl(), tpl);
+            assert!(!PRIVATE_IMAGE_DATA.lock().private_image_data.contains_key(&image_handle));
+        });
+    }
+
+    #[test]
+    fn start_image_should_limit_nesting_and_refuse_cycles() {
+        with_locked_state(|| {
+            fn load_test_image(name: &str) -> efi::Handle {
+                let mut test_file = File::open(name).expect("failed to open test file.");
+                let mut image: Vec<u8> = Vec::new();
+                test_file.read_to_end(&mut image).expect("failed to read test file");
+
+                let mut image_handle: efi::Handle = core::ptr::null_mut();
+                let status = load_image(
+                    false.into(),
+                    protocol_db::DXE_CORE_HANDLE,
+                    core::ptr::null_mut(),
+                    image.as_mut_ptr() as *mut c_void,
+                    image.len(),
+                    core::ptr::addr_of_mut!(image_handle),
+                );
+                assert_eq!(status, efi::Status::SUCCESS);
+                image_handle
+            }
+
+            // as in start_image_should_start_image, the entry point is replaced with a stub in the test executable.
+            // The stub starts a copy of its own image, and then a different image with nesting limited to one entry
+            // point; neither may run.
+            static CYCLE_STATUS: AtomicUsize = AtomicUsize::new(0);
+            static NESTED_STATUS: AtomicUsize = AtomicUsize::new(0);
+            extern "efiapi" fn test_entry_point(
+                _image_handle: *mut core::ffi::c_void,
+                _system_table: *mut r_efi::system::SystemTable,
+            ) -> efi::Status {
+                let copy = load_test_image(test_collateral!("RustImageTestDxe.efi"));
+                let status = start_image(copy, core::ptr::null_mut(), core::ptr::null_mut());
+                CYCLE_STATUS.store(status.as_usize(), Ordering::SeqCst);
+
+                set_image_start_nesting(&ImageStartNesting { max_depth: 1, ..Default::default() });
+                let other = load_test_image(test_collateral!("test_image_msvc_hii.pe32"));
+                let status = start_image(other, core::ptr::null_mut(), core::ptr::null_mut());
+                NESTED_STATUS.store(status.as_usize(), Ordering::SeqCst);
+                set_image_start_nesting(&ImageStartNesting::default());
+
+                // images that fail to start are unloaded.
+                let private_data = PRIVATE_IMAGE_DATA.lock();
+                assert!(!private_data.private_image_data.contains_key(&copy));
+                assert!(!private_data.private_image_data.contains_key(&other));
+
+                efi::Status::SUCCESS
+            }
+
+            let image_handle = load_test_image(test_collateral!("RustImageTestDxe.efi"));
+            let mut private_data = PRIVATE_IMAGE_DATA.lock();
+            let image_data = private_data.private_image_data.get_mut(&image_handle).unwrap();
+            image_data.entry_point = test_entry_point;
+            drop(private_data);
+
+            let status = start_image(image_handle, core::ptr::null_mut(), core::ptr::null_mut());
+            assert_eq!(status, efi::Status::SUCCESS);
+            assert_eq!(CYCLE_STATUS.load(Ordering::SeqCst), efi::Status::ACCESS_DENIED.as_usize());
+            assert_eq!(NESTED_STATUS.load(Ordering::SeqCst), efi::Status::OUT_OF_RESOURCES.as_usize());
+            assert!(PRIVATE_IMAGE_DATA.lock().running_images.is_empty());
+        });
+    }
+
+    #[test]
+    fn image_stack_high_water_mark_should_measure_stack_usage() {
+        with_locked_state(|| {
+            let stack = ImageStack::new(0x10000).unwrap();
+            assert_eq!(stack.high_water_mark(), 0);
+
+            // the stack grows downward from the base, so usage is measured from the end of the allocation.
+            // SAFETY: the stack is allocated and 0x10000 bytes long.
+            let bytes = unsafe { &mut *(stack.stack as *mut [u8]) };
+            bytes[0x10000 - 0x100..].fill(0);
+            assert_eq!(stack.high_water_mark(), 0x100);
+
+            // bytes that happen to match the fill pattern below the deepest write are still counted as used.
+            bytes[0x10000 - 0x2000] = 0;
+            assert_eq!(stack.high_water_mark(), 0x2000);
+
+            bytes[0] = 0;
+            assert_eq!(stack.high_water_mark(), 0x10000);
+        });
+    }
+
+    #[test]
+    fn entry_point_stack_size_should_apply_file_guid_overrides() {
+        with_locked_state(|| {
+            const FILE_GUID: efi::Guid =
+                efi::Guid::from_fields(0x7c04a583, 0x9e3e, 0x4f1c, 0xad, 0x65, &[0xe0, 0x52, 0x68, 0xd0, 0xb4, 0xd1]);
+            const OTHER_GUID: efi::Guid =
+                efi::Guid::from_fields(0x462caa21, 0x7614, 0x4503, 0x83, 0x6e, &[0x8a, 0xb6, 0xf4, 0x66, 0x23, 0x31]);
+
+            let mut test_file =
+                File::open(test_collateral!("RustImageTestDxe.efi")).expect("failed to open test file.");
+            let mut image: Vec<u8> = Vec::new();
+            test_file.read_to_end(&mut image).expect("failed to read test file");
+
+            let mut image_handle: efi::Handle = core::ptr::null_mut();
+            let status = load_image(
+                false.into(),
+                protocol_db::DXE_CORE_HANDLE,
+                core::ptr::null_mut(),
+                image.as_mut_ptr() as *mut c_void,
+                image.len(),
+                core::ptr::addr_of_mut!(image_handle),
+            );
+            assert_eq!(status, efi::Status::SUCCESS);
+
+            // an image without a file path uses the default size.
+            set_image_stack_config(&ImageStackConfig { default_size: 0x10000, overrides: vec![(FILE_GUID, 0x400000)] });
+            assert_eq!(entry_point_stack_size(image_handle), 0x10000);
+
+            // an image loaded from a firmware file uses the override for the file, if there is one.
+            let mut file_path = vec![
+                efi::protocols::device_path::TYPE_MEDIA,
+                efi::protocols::device_path::Media::SUBTYPE_PIWG_FIRMWARE_FILE,
+                20,
+                0,
+            ];
+            file_path.extend_from_slice(FILE_GUID.as_bytes());
+            file_path.extend_from_slice(&[0x7f, 0xff, 4, 0]);
+            let file_path = Box::leak(file_path.into_boxed_slice());
+            PRIVATE_IMAGE_DATA.lock().private_image_data.get_mut(&image_handle).unwrap().image_info.file_path =
+                file_path.as_mut_ptr() as *mut efi::protocols::device_path::Protocol;
+            assert_eq!(entry_point_stack_size(image_handle), 0x400000);
+
+            set_image_stack_config(&ImageStackConfig {
+                default_size: 0x10000,
+                overrides: vec![(OTHER_GUID, 0x400000)],
+            });
+            assert_eq!(entry_point_stack_size(image_handle), 0x10000);
+
+            set_image_stack_config(&ImageStackConfig::default());
+            assert_eq!(entry_point_stack_size(image_handle), ENTRY_POINT_STACK_SIZE);
+
+            PRIVATE_IMAGE_DATA.lock().private_image_data.get_mut(&image_handle).unwrap().image_info.file_path =
+                core::ptr::null_mut();
+            assert_eq!(unload_image(image_handle), efi::Status::SUCCESS);
+        });
+    }
+
     #[test]
     fn start_image_error_status_should_unload_image() {
         with_locked_state(|| {
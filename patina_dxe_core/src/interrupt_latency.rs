@@ -0,0 +1,256 @@
+//! Interrupt Latency Measurement
+//!
+//! Interrupts are disabled whenever the TPL is raised to TPL_HIGH_LEVEL, for example while a [TplMutex] guarding the
+//! event database or the GCD is held. Long lockouts delay timer ticks and break drivers that depend on timely
+//! interrupts, such as USB and network drivers. Platforms can measure how long interrupts stay disabled with
+//! [InterruptLatencyReporting]; each lockout is attributed to the TPL_HIGH_LEVEL lock that started it, or to the
+//! RaiseTPL caller if there is none, and the worst offenders are reported at ReadyToBoot.
+//!
+//! [TplMutex]: crate::tpl_lock::TplMutex
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::{
+    ffi::c_void,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use mu_rust_helpers::perf_timer::{Arch, ArchFunctionality};
+use r_efi::efi;
+use spin::Mutex;
+
+use crate::events::EVENT_DB;
+
+/// Measures how long interrupts stay disabled at TPL_HIGH_LEVEL and reports the worst offenders at ReadyToBoot.
+///
+/// Nothing is measured unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, InterruptLatencyReporting};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(InterruptLatencyReporting { report_threshold_us: 100 })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterruptLatencyReporting {
+    /// Offenders whose longest lockout is shorter than this many microseconds are left out of the report.
+    pub report_threshold_us: u64,
+}
+
+// The most lock owners that are tracked. The table is fixed size, as it is updated while allocator locks are held.
+const MAX_OFFENDERS: usize = 32;
+
+// The owner recorded for lockouts that were not started by a TPL_HIGH_LEVEL lock.
+const RAISE_TPL_OWNER: &str = "RaiseTPL";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Offender {
+    name: &'static str,
+    count: u64,
+    total_ticks: u64,
+    worst_ticks: u64,
+}
+
+struct LatencyState {
+    start: Option<u64>,
+    owner: Option<&'static str>,
+    offenders: [Option<Offender>; MAX_OFFENDERS],
+}
+
+impl LatencyState {
+    const fn new() -> Self {
+        Self { start: None, owner: None, offenders: [None; MAX_OFFENDERS] }
+    }
+
+    fn record(&mut self, name: &'static str, ticks: u64) {
+        if let Some(offender) = self.offenders.iter_mut().flatten().find(|offender| offender.name == name) {
+            offender.count += 1;
+            offender.total_ticks += ticks;
+            offender.worst_ticks = offender.worst_ticks.max(ticks);
+            return;
+        }
+
+        let new = Offender { name, count: 1, total_ticks: ticks, worst_ticks: ticks };
+        if let Some(slot) = self.offenders.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(new);
+        } else if let Some(least) = self.offenders.iter_mut().flatten().min_by_key(|offender| offender.worst_ticks)
+            && least.worst_ticks < ticks
+        {
+            // the table is full; keep the worst offenders.
+            *least = new;
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REPORT_THRESHOLD_US: AtomicU64 = AtomicU64::new(0);
+static STATE: Mutex<LatencyState> = Mutex::new(LatencyState::new());
+
+/// Starts measuring interrupt lockouts and registers a report of the worst offenders for ReadyToBoot.
+pub(crate) fn enable_interrupt_latency_reporting(config: &InterruptLatencyReporting) {
+    REPORT_THRESHOLD_US.store(config.report_threshold_us, Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(report_interrupt_latency_event),
+        None,
+        Some(efi::EVENT_GROUP_READY_TO_BOOT),
+    ) {
+        log::error!("Failed to register an event at Ready to Boot to report interrupt latency! Status {status:#X?}");
+    }
+}
+
+// The hooks below run with interrupts disabled, possibly while allocator or logger locks are held, so they never
+// block, allocate or log. A measurement is dropped if the state is busy.
+
+/// Called when interrupts are disabled on raising the TPL to TPL_HIGH_LEVEL.
+pub(crate) fn interrupts_disabled() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(mut state) = STATE.try_lock()
+        && state.start.is_none()
+    {
+        state.start = Some(Arch::cpu_count());
+        state.owner = None;
+    }
+}
+
+/// Attributes the current lockout to `name`, unless it is already attributed to an outer lock.
+pub(crate) fn set_owner(name: &'static str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(mut state) = STATE.try_lock()
+        && state.start.is_some()
+        && state.owner.is_none()
+    {
+        state.owner = Some(name);
+    }
+}
+
+/// Called when interrupts are enabled on restoring the TPL below TPL_HIGH_LEVEL.
+pub(crate) fn interrupts_enabled() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(mut state) = STATE.try_lock()
+        && let Some(start) = state.start.take()
+    {
+        let owner = state.owner.take().unwrap_or(RAISE_TPL_OWNER);
+        state.record(owner, Arch::cpu_count().saturating_sub(start));
+    }
+}
+
+fn ticks_to_us(ticks: u64, frequency: u64) -> u64 {
+    if frequency == 0 { 0 } else { (ticks as u128 * 1_000_000 / frequency as u128) as u64 }
+}
+
+extern "efiapi" fn report_interrupt_latency_event(event: efi::Event, _context: *mut c_void) {
+    // copy the table out, as logging may raise the TPL to TPL_HIGH_LEVEL and would otherwise drop measurements.
+    let offenders = STATE.lock().offenders;
+    let frequency = Arch::perf_frequency();
+    let threshold_us = REPORT_THRESHOLD_US.load(Ordering::SeqCst);
+
+    let mut offenders = offenders
+        .into_iter()
+        .flatten()
+        .filter(|offender| ticks_to_us(offender.worst_ticks, frequency) >= threshold_us)
+        .collect::<alloc::vec::Vec<_>>();
+    offenders.sort_unstable_by_key(|offender| core::cmp::Reverse(offender.worst_ticks));
+
+    if offenders.is_empty() {
+        log::info!("No interrupt lockouts of {threshold_us}us or more were measured.");
+    } else {
+        log::warn!("Interrupt lockouts at TPL_HIGH_LEVEL of {threshold_us}us or more, worst first:");
+        for offender in offenders {
+            log::warn!(
+                "  {}: worst {}us, average {}us over {} lockout(s)",
+                offender.name,
+                ticks_to_us(offender.worst_ticks, frequency),
+                ticks_to_us(offender.total_ticks / offender.count, frequency),
+                offender.count
+            );
+        }
+    }
+
+    if let Err(status) = EVENT_DB.close_event(event) {
+        log::error!("Failed to close the interrupt latency report event: {status:?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use crate::test_support;
+
+    #[test]
+    fn test_lockouts_are_attributed_to_the_outermost_owner() {
+        test_support::with_global_lock(|| {
+            *STATE.lock() = LatencyState::new();
+            ENABLED.store(true, Ordering::SeqCst);
+
+            interrupts_disabled();
+            set_owner("OuterLock");
+            set_owner("InnerLock");
+            interrupts_enabled();
+
+            interrupts_disabled();
+            interrupts_enabled();
+
+            // enabling interrupts without a lockout in progress records nothing.
+            interrupts_enabled();
+
+            ENABLED.store(false, Ordering::SeqCst);
+            let state = STATE.lock();
+            let offenders = state.offenders.iter().flatten().collect::<alloc::vec::Vec<_>>();
+            assert_eq!(offenders.len(), 2);
+            assert_eq!((offenders[0].name, offenders[0].count), ("OuterLock", 1));
+            assert_eq!((offenders[1].name, offenders[1].count), (RAISE_TPL_OWNER, 1));
+            assert!(state.start.is_none());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_full_table_keeps_the_worst_offenders() {
+        let mut state = LatencyState::new();
+        let names = (0..MAX_OFFENDERS)
+            .map(|i| &*alloc::boxed::Box::leak(alloc::format!("Lock{i}").into_boxed_str()))
+            .collect::<alloc::vec::Vec<&'static str>>();
+        for (i, name) in names.iter().enumerate() {
+            state.record(name, 10 + i as u64);
+        }
+
+        state.record("short", 1);
+        assert!(state.offenders.iter().flatten().all(|offender| offender.name != "short"));
+
+        state.record("long", 1000);
+        assert!(state.offenders.iter().flatten().any(|offender| offender.name == "long"));
+        assert!(state.offenders.iter().flatten().all(|offender| offender.name != names[0]));
+
+        state.record("long", 10);
+        let long = state.offenders.iter().flatten().find(|offender| offender.name == "long").unwrap();
+        assert_eq!((long.count, long.total_ticks, long.worst_ticks), (2, 1010, 1000));
+    }
+
+    #[test]
+    fn test_ticks_to_us() {
+        assert_eq!(ticks_to_us(3_000, 1_000_000_000), 3);
+        assert_eq!(ticks_to_us(100, 0), 0);
+    }
+}
@@ -36,44 +36,91 @@
 extern crate alloc;
 
 mod allocator;
+mod benign_faults;
+mod boot_checkpoint;
+mod boot_config;
+mod compatibility_mode;
+mod component_dispatch;
+mod component_timeout;
 mod config_tables;
+mod control_flow;
+mod core_info;
+mod core_stack;
 mod cpu_arch_protocol;
+mod crc32;
 mod decompress;
 mod dispatcher;
 mod driver_services;
 mod dxe_services;
 mod event_db;
 mod events;
+mod fast_boot;
+mod file_prehash;
 mod filesystems;
 mod fv;
+mod fv_loader;
 mod gcd;
+mod guid_names;
+mod handoff_validation;
 #[cfg(all(target_os = "uefi", target_arch = "aarch64"))]
 mod hw_interrupt_protocol;
 mod image;
+mod interrupt_latency;
+mod memory_attribute_journal;
 mod memory_attributes_protocol;
+mod memory_bins;
 mod memory_manager;
+mod memory_protection;
 mod misc_boot_services;
+mod mmio_placement;
+mod panic_policy;
 mod pecoff;
+mod persisted_blob;
+mod progress_code;
 mod protocol_db;
+mod protocol_db_snapshot;
 mod protocols;
+mod proximity_domains;
+#[cfg(any(test, all(target_os = "uefi", target_arch = "aarch64")))]
+mod psci;
+mod reserved_regions;
 mod runtime;
+mod security_posture;
+mod stack_protector;
+mod stray_boot_services;
+#[cfg(feature = "symbol_map")]
+mod symbol_map;
 mod systemtables;
+mod tlb_shootdown;
 mod tpl_lock;
+mod wx_enforcement;
 
 #[cfg(test)]
 #[macro_use]
 #[coverage(off)]
 pub mod test_support;
 
-use core::{ffi::c_void, ptr, str::FromStr};
+use core::{ffi::c_void, ptr};
 
 use alloc::{boxed::Box, vec::Vec};
+use boot_config::{BootConfigBlob, BootConfigEntry};
 use gcd::SpinLockedGcd;
 use memory_manager::CoreMemoryManager;
 use mu_rust_helpers::{function, guid::CALLER_ID};
 use patina::{
     boot_services::StandardBootServices,
-    component::{Component, IntoComponent, Storage, service::IntoService},
+    component::{
+        Component, IntoComponent, Storage,
+        boot_config::BootConfig,
+        lifecycle::LifecycleStage,
+        service::{
+            IntoService,
+            boot_checkpoint::{BootCheckpoint, CheckpointSink},
+            entropy::Entropy,
+            file_digest::FileDigest,
+            persisted_blob::PersistedBlob,
+        },
+    },
     error::{self, Result},
     performance::{
         logging::{perf_function_begin, perf_function_end},
@@ -93,6 +140,39 @@ use r_efi::efi;
 
 use crate::config_tables::memory_attributes_table;
 
+pub use allocator::{MemoryWriteChecks, MemoryZeroPolicy};
+pub use benign_faults::BenignFaultRanges;
+pub use boot_config::BOOT_CONFIG_HOB_GUID;
+pub use component_dispatch::ComponentDispatch;
+pub use component_timeout::{ComponentTimeoutAction, ComponentTimeouts};
+pub use config_tables::LockedConfigurationTables;
+pub use control_flow::ControlFlowProtection;
+pub use core_info::{BuildInfo, CORE_INFO_TABLE_GUID, CoreInfoTableHeader};
+pub use dispatcher::{DISPATCH_POLICY_HOB_GUID, DispatchPolicy, OverrideFirmwareVolumes};
+pub use events::TimerPeriod;
+pub use fast_boot::{FAST_BOOT_CACHE_GUID, FastBootPolicy};
+pub use fv_loader::install_fv_from_buffer;
+pub use guid_names::GuidNames;
+pub use handoff_validation::HandoffValidationConfig;
+pub use image::{
+    EbcImagePolicy, ExecuteInPlaceImages, ImageDirectoryPolicy, ImageStackConfig, ImageStartNesting, ImageWatchdog,
+    ImageWatchdogAction,
+};
+pub use interrupt_latency::InterruptLatencyReporting;
+pub use memory_attribute_journal::MemoryAttributeAudit;
+pub use memory_bins::MemoryBinFeedback;
+pub use memory_protection::{ImageProtectionPolicy, MEMORY_PROTECTION_SETTINGS_HOB_GUID, MemoryProtectionPolicy};
+pub use mmio_placement::MmioPlacement;
+pub use panic_policy::{PanicPolicy, handle_panic};
+pub use progress_code::{ProgressCheckpoint, ProgressCodes, ProgressSink};
+pub use protocol_db_snapshot::{PROTOCOL_DB_SNAPSHOT_TABLE_GUID, ProtocolDbSnapshotHeader, ProtocolDbSnapshotPolicy};
+pub use proximity_domains::ProximityDomains;
+pub use reserved_regions::{ReservedRegion, ReservedRegions};
+pub use runtime::RuntimeAddressingChecks;
+pub use security_posture::{SECURITY_POSTURE_TABLE_GUID, SecurityPostureTableHeader};
+pub use stray_boot_services::StrayBootServicesLogging;
+pub use wx_enforcement::WxEnforcement;
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! ensure {
@@ -113,7 +193,11 @@ macro_rules! error {
 
 pub(crate) static GCD: SpinLockedGcd = SpinLockedGcd::new(Some(events::gcd_map_change));
 
-/// A configuration struct containing the GIC bases (gic_d, gic_r) for AARCH64 systems.
+/// A configuration struct containing the GIC bases (gic_d, gic_r, and optionally gic_its) for AARCH64 systems.
+///
+/// When an Interrupt Translation Service (ITS) base is provided, LPIs are enabled and the core produces the
+/// [MsiController](patina::component::service::msi::MsiController) service, so that PCIe MSI interrupts can be used
+/// during boot.
 ///
 /// ## Example
 ///
@@ -121,7 +205,7 @@ pub(crate) static GCD: SpinLockedGcd = SpinLockedGcd::new(Some(events::gcd_map_c
 /// use patina_dxe_core::{Core, GicBases};
 /// # let physical_hob_list = core::ptr::null();
 ///
-/// let gic_bases = GicBases::new(0x1E000000, 0x1E010000);
+/// let gic_bases = GicBases::new(0x1E000000, 0x1E010000).with_its(0x1E020000);
 /// let core = Core::default()
 ///    .init_memory(physical_hob_list)
 ///    .with_config(gic_bases)
@@ -129,12 +213,18 @@ pub(crate) static GCD: SpinLockedGcd = SpinLockedGcd::new(Some(events::gcd_map_c
 ///    .unwrap();
 /// ```
 #[derive(Debug, PartialEq)]
-pub struct GicBases(pub u64, pub u64);
+pub struct GicBases(pub u64, pub u64, pub Option<u64>);
 
 impl GicBases {
     /// Creates a new instance of the GicBases struct with the provided GIC Distributor and Redistributor base addresses.
     pub fn new(gicd_base: u64, gicr_base: u64) -> Self {
-        GicBases(gicd_base, gicr_base)
+        GicBases(gicd_base, gicr_base, None)
+    }
+
+    /// Sets the base address of the GIC Interrupt Translation Service.
+    pub fn with_its(mut self, gits_base: u64) -> Self {
+        self.2 = Some(gits_base);
+        self
     }
 }
 
@@ -174,9 +264,17 @@ pub struct NoAlloc;
 /// be directly registered with the [Core::with_service] method. If not, there is no guarantee that the service will
 /// be available before the core needs it.
 ///
-/// | Service Trait                           | Description                                      |
-/// |-----------------------------------------|--------------------------------------------------|
-/// | [patina_ffs::section::SectionExtractor] | FW volume section extraction w/ decompression    |
+/// If no [PersistedBlob](patina::component::service::persisted_blob::PersistedBlob) service is registered, the core
+/// registers a [VariableBlobStore](patina::component::service::persisted_blob::VariableBlobStore) that stores blobs in
+/// UEFI variables once the variable drivers are dispatched.
+///
+/// | Service Trait                                                  | Description                                      |
+/// |----------------------------------------------------------------|--------------------------------------------------|
+/// | [patina_ffs::section::SectionExtractor]                        | FW volume section extraction w/ decompression    |
+/// | [patina::component::service::file_digest::FileDigest]          | Pre-hashing of driver images while idle          |
+/// | [patina::component::service::boot_checkpoint::CheckpointSink]  | Boot checkpoints for watchdog cause analysis     |
+/// | [patina::component::service::persisted_blob::PersistedBlob]    | Blobs persisted across boots, such as MRC data   |
+/// | [patina::component::service::entropy::Entropy]                 | Random stack protector guard                     |
 ///
 /// ## Examples
 ///
@@ -200,6 +298,7 @@ pub struct Core<MemoryState> {
     hob_list: HobList<'static>,
     components: Vec<Box<dyn Component>>,
     storage: Storage,
+    boot_configs: Vec<BootConfigEntry>,
     _memory_state: core::marker::PhantomData<MemoryState>,
 }
 
@@ -210,6 +309,7 @@ impl Default for Core<NoAlloc> {
             hob_list: HobList::default(),
             components: Vec::new(),
             storage: Storage::new(),
+            boot_configs: Vec::new(),
             _memory_state: core::marker::PhantomData,
         }
     }
@@ -218,6 +318,7 @@ impl Default for Core<NoAlloc> {
 impl Core<NoAlloc> {
     /// Initializes the core with the given configuration, including GCD initialization, enabling allocations.
     pub fn init_memory(mut self, physical_hob_list: *const c_void) -> Core<Alloc> {
+        progress_code::report(ProgressCheckpoint::InitMemoryEntry);
         log::info!("DXE Core Crate v{}", env!("CARGO_PKG_VERSION"));
 
         let mut cpu = EfiCpu::default();
@@ -233,12 +334,14 @@ impl Core<NoAlloc> {
         }
 
         gcd::init_gcd(physical_hob_list);
+        progress_code::report(ProgressCheckpoint::GcdInitialized);
 
         log::trace!("Initial GCD:\n{GCD}");
 
         // After this point Rust Heap usage is permitted (since GCD is initialized with a single known-free region).
         // Relocate the hobs from the input list pointer into a Vec.
         self.hob_list.discover_hobs(physical_hob_list);
+        stack_protector::record_handoff_stack(&self.hob_list);
 
         log::trace!("HOB list discovered is:");
         log::trace!("{:#x?}", self.hob_list);
@@ -247,16 +350,46 @@ impl Core<NoAlloc> {
         PROTOCOL_DB.init_protocol_db();
         // Initialize full allocation support.
         allocator::init_memory_support(&self.hob_list);
+
+        // Move off of the unguarded handoff stack as soon as pages can be allocated.
+        core_stack::allocate_core_stack();
+        core_stack::run_on_core_stack(move || self.complete_init_memory(physical_hob_list, cpu, interrupt_manager))
+    }
+
+    // Completes memory initialization on the core stack.
+    fn complete_init_memory(
+        mut self,
+        physical_hob_list: *const c_void,
+        cpu: EfiCpu,
+        mut interrupt_manager: Interrupts,
+    ) -> Core<Alloc> {
         // we have to relocate HOBs after memory services are initialized as we are going to allocate memory and
         // the initial free memory may not be enough to contain the HOB list. We need to relocate the HOBs because
         // the initial HOB list is not in mapped memory as passed from pre-DXE.
         self.hob_list.relocate_hobs();
+        progress_code::report(ProgressCheckpoint::MemoryServicesInitialized);
 
         // Add custom monitor commands to the debugger before initializing so that
         // they are available in the initial breakpoint.
         patina_debugger::add_monitor_command("gcd", "Prints the GCD", |_, out| {
             let _ = write!(out, "GCD -\n{GCD}");
         });
+        patina_debugger::add_monitor_command("build_info", "Prints the DXE Core build information", |_, out| {
+            let _ = write!(out, "{}", core_info::build_info());
+        });
+        patina_debugger::add_monitor_command("memmap", "Prints the UEFI memory map", |_, out| {
+            let _ = allocator::write_memory_map(out);
+        });
+        patina_debugger::add_monitor_command("alloc", "Prints the owner of an address: alloc <addr>", |args, out| {
+            let address = args.next().map(|arg| u64::from_str_radix(arg.trim_start_matches("0x"), 16));
+            let _ = match address {
+                Some(Ok(address)) => allocator::write_allocation_info(out, address),
+                _ => write!(out, "Usage: alloc <hex address>"),
+            };
+        });
+
+        #[cfg(feature = "symbol_map")]
+        symbol_map::init();
 
         // Initialize the debugger if it is enabled.
         patina_debugger::initialize(&mut interrupt_manager);
@@ -266,12 +399,24 @@ impl Core<NoAlloc> {
         self.storage.add_service(cpu);
         self.storage.add_service(interrupt_manager);
         self.storage.add_service(CoreMemoryManager);
-
+        self.storage.add_service(allocator::CoreMemoryMap);
+        self.storage.add_service(memory_attributes_protocol::CoreMemoryAttributes);
+        self.storage.add_service(memory_attribute_journal::CoreMemoryAttributeJournal);
+        self.storage.add_service(config_tables::hob_list_table::CoreHobProducer);
+        self.storage.add_service(dxe_services::CoreIoSpace);
+        self.storage.add_service(image::CoreImageLoader);
+        self.storage.add_service(driver_services::CoreDriverDiagnostics);
+        self.storage.add_service(compatibility_mode::CoreCompatibilityModeTelemetry);
+        self.storage.add_service(file_prehash::CorePrecomputedFileDigests);
+        self.storage.add_service(fast_boot::CoreBootCache);
+
+        progress_code::report(ProgressCheckpoint::InitMemoryComplete);
         Core {
             physical_hob_list,
             hob_list: self.hob_list,
             components: self.components,
             storage: self.storage,
+            boot_configs: self.boot_configs,
             _memory_state: core::marker::PhantomData,
         }
     }
@@ -303,6 +448,84 @@ impl Core<NoAlloc> {
         GCD.prioritize_32_bit_memory(true);
         self
     }
+
+    /// Informs the core that page faults should be handled on a dedicated exception stack.
+    ///
+    /// The core stack has a guard page, but a fault on the guard page cannot be reported on the overflowed stack. With
+    /// a dedicated exception stack, core stack overflows are reported like any other page fault. Currently only
+    /// supported on x64.
+    ///
+    /// Must be called prior to [`Core::init_memory`].
+    ///
+    /// ## Example
+    ///
+    /// ``` rust,no_run
+    /// # let physical_hob_list = core::ptr::null();
+    /// patina_dxe_core::Core::default()
+    ///   .use_dedicated_exception_stack()
+    ///   .init_memory(physical_hob_list)
+    ///   .start()
+    ///   .unwrap();
+    /// ```
+    pub fn use_dedicated_exception_stack(self) -> Self {
+        // The exception stack is set up when interrupts are initialized, so only the request is recorded here.
+        patina_internal_cpu::interrupts::use_dedicated_exception_stack();
+        self
+    }
+
+    /// Informs the core that it should write progress codes to `sink` as it initializes, for bring-up of systems that
+    /// fail before logging works.
+    ///
+    /// A code from `codes` is written at each [ProgressCheckpoint] through [`Core::init_memory`] and [`Core::start`].
+    ///
+    /// Must be called prior to [`Core::init_memory`].
+    ///
+    /// ## Example
+    ///
+    /// ``` rust,no_run
+    /// # let physical_hob_list = core::ptr::null();
+    /// patina_dxe_core::Core::default()
+    ///   .with_progress_sink(patina_dxe_core::ProgressSink::IoPort(0x80), Default::default())
+    ///   .init_memory(physical_hob_list)
+    ///   .start()
+    ///   .unwrap();
+    /// ```
+    pub fn with_progress_sink(self, sink: ProgressSink, codes: ProgressCodes) -> Self {
+        // The sink is stored globally so that checkpoints can be written before the core has any state of its own.
+        progress_code::set_progress_sink(sink, codes);
+        self
+    }
+
+    /// Informs the core that it should reserve `regions` of memory, such as a TPM event log, a framebuffer or memory
+    /// owned by the secure world, and exclude them from allocation.
+    ///
+    /// The regions are reserved while memory services are initialized, before any allocation can be made from them.
+    ///
+    /// Must be called prior to [`Core::init_memory`].
+    ///
+    /// ## Example
+    ///
+    /// ``` rust,no_run
+    /// # let physical_hob_list = core::ptr::null();
+    /// static REGIONS: &[patina_dxe_core::ReservedRegion] = &[patina_dxe_core::ReservedRegion {
+    ///     name: "Framebuffer",
+    ///     base_address: 0x8000_0000,
+    ///     length: 0x80_0000,
+    ///     memory_type: r_efi::efi::RESERVED_MEMORY_TYPE,
+    ///     attributes: r_efi::efi::MEMORY_WC | r_efi::efi::MEMORY_XP,
+    /// }];
+    ///
+    /// patina_dxe_core::Core::default()
+    ///   .with_reserved_regions(patina_dxe_core::ReservedRegions(REGIONS))
+    ///   .init_memory(physical_hob_list)
+    ///   .start()
+    ///   .unwrap();
+    /// ```
+    pub fn with_reserved_regions(self, regions: ReservedRegions) -> Self {
+        // The regions are held until init_memory, where they are carved out of the GCD before allocation begins.
+        reserved_regions::set_reserved_regions(regions);
+        self
+    }
 }
 
 impl Core<Alloc> {
@@ -333,20 +556,65 @@ impl Core<Alloc> {
         self
     }
 
+    /// Registers a hook that runs once when the core enters the given [LifecycleStage].
+    ///
+    /// The core enters [PreDispatch](LifecycleStage::PreDispatch) before the first dispatch pass,
+    /// [PostFirstPass](LifecycleStage::PostFirstPass) after it, and [Locked](LifecycleStage::Locked) once all
+    /// configuration has been locked, before the second dispatch pass.
+    pub fn with_lifecycle_hook<F: FnOnce(&mut Storage) + 'static>(mut self, stage: LifecycleStage, hook: F) -> Self {
+        self.storage.add_lifecycle_hook(stage, hook);
+        self
+    }
+
+    /// Adds a configuration value to the Core's storage, like [with_config](Core::with_config), and registers it as
+    /// boot configuration.
+    ///
+    /// Boot configuration is included in the blob returned by [boot_config_blob](Core::boot_config_blob), and is
+    /// replaced at boot by the value in a blob passed to the core in a HOB with [BOOT_CONFIG_HOB_GUID]. This allows a
+    /// platform to tune its configuration without rebuilding the platform binary.
+    pub fn with_boot_config<C: BootConfig>(mut self, config: C) -> Self {
+        self.storage.add_config(config);
+        if !self.boot_configs.iter().any(|entry| entry.guid() == &C::CONFIG_GUID) {
+            self.boot_configs.push(BootConfigEntry::new::<C>());
+        }
+        self
+    }
+
+    /// Serializes the boot configuration and the names of the registered components into a blob, which can be passed
+    /// back to the core in a HOB with [BOOT_CONFIG_HOB_GUID].
+    ///
+    /// Only configuration registered with [with_boot_config](Core::with_boot_config) is serialized.
+    pub fn boot_config_blob(&self) -> Vec<u8> {
+        BootConfigBlob::encode(&self.boot_configs, &self.storage, self.components.iter().map(|c| c.metadata().name()))
+    }
+
+    /// Replaces the boot configuration with the values in the boot configuration HOB, if present.
+    fn apply_boot_config_hob(&mut self) {
+        let Some(data) = self.hob_list.iter().find_map(|hob| match hob {
+            patina_pi::hob::Hob::GuidHob(guid, data) if guid.name == BOOT_CONFIG_HOB_GUID => Some(*data),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        match BootConfigBlob::decode(data) {
+            Some(blob) => {
+                blob.apply(&self.boot_configs, &mut self.storage, self.components.iter().map(|c| c.metadata().name()))
+            }
+            None => log::error!("Ignoring malformed boot configuration HOB."),
+        }
+    }
+
     /// Parses the HOB list producing a `Hob\<T\>` struct for each guided HOB found with a registered parser.
     fn parse_hobs(&mut self) {
         for hob in self.hob_list.iter() {
             if let patina_pi::hob::Hob::GuidHob(guid, data) = hob {
                 let parser_funcs = self.storage.get_hob_parsers(&patina::OwnedGuid::from(guid.name));
                 if parser_funcs.is_empty() {
-                    let (f0, f1, f2, f3, f4, &[f5, f6, f7, f8, f9, f10]) = guid.name.as_fields();
-                    let name = alloc::format!(
-                        "{f0:08x}-{f1:04x}-{f2:04x}-{f3:02x}{f4:02x}-{f5:02x}{f6:02x}{f7:02x}{f8:02x}{f9:02x}{f10:02x}"
-                    );
                     log::warn!(
-                        "No parser registered for HOB: GuidHob {{ {:?}, name: Guid {{ {} }} }}",
+                        "No parser registered for HOB: GuidHob {{ {:?}, name: {} }}",
                         guid.header,
-                        name
+                        guid_names::named(&guid.name)
                     );
                 } else {
                     for parser_func in parser_funcs {
@@ -357,18 +625,68 @@ impl Core<Alloc> {
         }
     }
 
+    /// Applies the driver dispatch policy, preferring a policy HOB over the platform configured policy, and registers
+    /// the platform configured override firmware volumes.
+    fn apply_dispatch_policy(&self) {
+        let hob_policy = self.hob_list.iter().find_map(|hob| match hob {
+            patina_pi::hob::Hob::GuidHob(guid, data) if guid.name == DISPATCH_POLICY_HOB_GUID => {
+                let policy = DispatchPolicy::from_hob_data(data);
+                if policy.is_none() {
+                    log::error!("Ignoring malformed dispatch policy HOB.");
+                }
+                policy
+            }
+            _ => None,
+        });
+
+        let policy = match hob_policy {
+            Some(policy) => policy,
+            None => self.storage.get_config::<DispatchPolicy>().map(|c| (*c).clone()).unwrap_or_default(),
+        };
+        if policy != DispatchPolicy::AllowAll {
+            log::info!("Driver dispatch policy: {policy:?}");
+        }
+        dispatcher::set_dispatch_policy(policy);
+
+        if let Some(overrides) = self.storage.get_config::<OverrideFirmwareVolumes>() {
+            overrides.0.iter().for_each(|&base_address| dispatcher::register_override_fv(base_address));
+        }
+    }
+
+    /// Replaces the memory and image protection policies with those in the memory protection settings HOB, if present.
+    fn apply_memory_protection_hob(&mut self) {
+        let Some(data) = self.hob_list.iter().find_map(|hob| match hob {
+            patina_pi::hob::Hob::GuidHob(guid, data) if guid.name == MEMORY_PROTECTION_SETTINGS_HOB_GUID => Some(*data),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        match memory_protection::from_hob_data(data) {
+            Some((memory, image)) => {
+                self.storage.add_config(memory);
+                self.storage.add_config(image);
+            }
+            None => log::error!("Ignoring malformed memory protection settings HOB."),
+        }
+    }
+
     /// Attempts to dispatch all components.
     ///
     /// This method will exit once no components remain or no components were dispatched during a full iteration.
     fn dispatch_components(&mut self) -> bool {
         let len = self.components.len();
+        if self.storage.get_config::<ComponentDispatch>().is_some_and(|c| *c == ComponentDispatch::Parallel) {
+            self.dispatch_components_on_aps();
+        }
+        let timeouts = self.storage.get_config::<ComponentTimeouts>().map(|c| (*c).clone());
         self.components.retain_mut(|component| {
             // Ok(true): Dispatchable and dispatched returning success
             // Ok(false): Not dispatchable at this time.
             // Err(e): Dispatchable and dispatched returning failure
             let name = component.metadata().name();
             log::trace!("Dispatch Start: Id = [{name:?}]");
-            !match component.run(&mut self.storage) {
+            !match component_timeout::watch(timeouts.as_ref(), name, || component.run(&mut self.storage)) {
                 Ok(true) => {
                     log::info!("Dispatched: Id = [{name:?}] Status = [Success]");
                     true
@@ -384,6 +702,32 @@ impl Core<Alloc> {
         len != self.components.len()
     }
 
+    /// Dispatches a batch of independent components on the application processors, and removes those that ran.
+    fn dispatch_components_on_aps(&mut self) {
+        let mut results = component_dispatch::dispatch_on_aps(&mut self.components, &mut self.storage);
+        if results.is_empty() {
+            return;
+        }
+        let mut index = 0;
+        self.components.retain(|component| {
+            let name = component.metadata().name();
+            let result = results.iter().position(|(i, _)| *i == index).map(|i| results.swap_remove(i).1);
+            index += 1;
+            match result {
+                Some(Ok(true)) => {
+                    log::info!("Dispatched: Id = [{name:?}] Status = [Success]");
+                    false
+                }
+                Some(Err(err)) => {
+                    log::error!("Dispatched: Id = [{name:?}] Status = [Failed] Error = [{err:?}]");
+                    debug_assert!(false);
+                    false
+                }
+                Some(Ok(false)) | None => true,
+            }
+        });
+    }
+
     /// Performs a combined dispatch of Patina components and UEFI drivers.
     ///
     /// This function will continue to loop and perform dispatching until no components have been dispatched in a full
@@ -452,7 +796,6 @@ impl Core<Alloc> {
                 Self::get_hob_list_len(self.physical_hob_list),
             )
         };
-        let relocated_c_hob_list = hob_list_slice.to_vec().into_boxed_slice();
 
         // Instantiate system table.
         systemtables::init_system_table();
@@ -462,11 +805,14 @@ impl Core<Alloc> {
 
             allocator::install_memory_services(st.boot_services_mut());
             gcd::init_paging(&self.hob_list);
+            core_stack::protect_guard_page();
             events::init_events_support(st.boot_services_mut());
             protocols::init_protocol_support(st.boot_services_mut());
             misc_boot_services::init_misc_boot_services_support(st.boot_services_mut());
+            misc_boot_services::calibrate_stall_counter();
             config_tables::init_config_tables_support(st.boot_services_mut());
             runtime::init_runtime_support(st.runtime_services_mut());
+            tlb_shootdown::init_tlb_shootdown_support();
             image::init_image_support(&self.hob_list, st);
             dispatcher::init_dispatcher();
             dxe_services::init_dxe_services(st);
@@ -478,16 +824,8 @@ impl Core<Alloc> {
             st.checksum_all();
 
             // Install HobList configuration table
-            let (a, b, c, &[d0, d1, d2, d3, d4, d5, d6, d7]) =
-                uuid::Uuid::from_str("7739F24C-93D7-11D4-9A3A-0090273FC14D").expect("Invalid UUID format.").as_fields();
-            let hob_list_guid: efi::Guid = efi::Guid::from_fields(a, b, c, d0, d1, &[d2, d3, d4, d5, d6, d7]);
-
-            config_tables::core_install_configuration_table(
-                hob_list_guid,
-                Box::leak(relocated_c_hob_list).as_mut_ptr() as *mut c_void,
-                st,
-            )
-            .expect("Unable to create configuration table due to invalid table entry.");
+            config_tables::hob_list_table::install_hob_list_table(hob_list_slice, st)
+                .expect("Unable to create configuration table due to invalid table entry.");
 
             // Install Memory Type Info configuration table.
             allocator::install_memory_type_info_table(st).expect("Unable to create Memory Type Info Table");
@@ -512,6 +850,11 @@ impl Core<Alloc> {
         unsafe {
             self.storage.set_boot_services(StandardBootServices::new(&*boot_services_ptr));
             self.storage.set_runtime_services(StandardRuntimeServices::new(&*runtime_services_ptr));
+            if self.storage.get_service::<dyn PersistedBlob>().is_none() {
+                self.storage.add_service(persisted_blob::CorePersistedBlob::new(StandardRuntimeServices::new(
+                    &*runtime_services_ptr,
+                )));
+            }
         }
 
         Ok(())
@@ -521,20 +864,62 @@ impl Core<Alloc> {
     #[allow(clippy::default_constructed_unit_structs)]
     fn add_core_components(&mut self) {
         self.insert_component(0, decompress::DecompressProtocolInstaller::default().into_component());
+        self.insert_component(0, fv_loader::FvLoaderProtocolInstaller::default().into_component());
         self.insert_component(0, systemtables::SystemTableChecksumInstaller::default().into_component());
         self.insert_component(0, cpu_arch_protocol::CpuArchProtocolInstaller::default().into_component());
+        self.insert_component(0, handoff_validation::HandoffValidator::default().into_component());
         #[cfg(all(target_os = "uefi", target_arch = "aarch64"))]
         self.insert_component(0, hw_interrupt_protocol::HwInterruptProtocolInstaller::default().into_component());
     }
 
+    /// Registers the PSCI service, using the [PsciConduit](patina::component::service::psci::PsciConduit) registered
+    /// as configuration, or the SMC conduit if none is registered, unless the platform registered its own.
+    #[cfg(all(target_os = "uefi", target_arch = "aarch64"))]
+    fn add_psci_service(&mut self) {
+        use patina::component::service::psci::{Psci, PsciConduit};
+        if self.storage.get_service::<dyn Psci>().is_some() {
+            log::info!("Using the platform PSCI service.");
+            return;
+        }
+        let conduit = self.storage.get_config::<PsciConduit>().map(|conduit| *conduit).unwrap_or_default();
+        log::info!("PSCI conduit: {conduit:?}");
+        self.storage.add_service(psci::CorePsci::new(conduit));
+    }
+
     /// Starts the core, dispatching all drivers.
-    pub fn start(mut self) -> Result<()> {
+    pub fn start(self) -> Result<()> {
+        // the guard must be seeded before the core moves to its own stack, see stack_protector.
+        if let Some(entropy) = self.storage.get_service::<dyn Entropy>() {
+            stack_protector::seed_stack_guard(*entropy);
+        }
+        core_stack::run_on_core_stack(move || self.run())
+    }
+
+    // Dispatches all drivers on the core stack.
+    fn run(mut self) -> Result<()> {
+        progress_code::report(ProgressCheckpoint::StartEntry);
+        if let Some(names) = self.storage.get_config::<GuidNames>() {
+            guid_names::register_guid_names(&names);
+        }
+
+        if let Some(sink) = self.storage.get_service::<dyn CheckpointSink>() {
+            boot_checkpoint::register_checkpoint_sink(sink);
+        }
+
         log::info!("Registering default components");
         self.add_core_components();
+        #[cfg(all(target_os = "uefi", target_arch = "aarch64"))]
+        self.add_psci_service();
         log::info!("Finished.");
 
         log::info!("Initializing System Table");
         self.initialize_system_table()?;
+        #[cfg(all(target_os = "uefi", target_arch = "aarch64"))]
+        if let Some(psci) = self.storage.get_service::<dyn patina::component::service::psci::Psci>() {
+            psci::install_reset_system(psci);
+        }
+        progress_code::report(ProgressCheckpoint::SystemTableInitialized);
+        boot_checkpoint::report(BootCheckpoint::SystemTableInitialized);
         log::info!("Finished.");
 
         log::info!("Parsing HOB list for Guided HOBs.");
@@ -547,15 +932,128 @@ impl Core<Alloc> {
             fv::register_section_extractor(extractor);
         }
 
+        if let Some(file_digest) = self.storage.get_service::<dyn FileDigest>() {
+            log::debug!("File Digest service found, pre-hashing dispatched files.");
+            file_prehash::register_file_digest(file_digest);
+        }
+
+        self.apply_boot_config_hob();
+        self.apply_dispatch_policy();
+        self.apply_memory_protection_hob();
+
+        if let Some(policy) = self.storage.get_config::<MemoryZeroPolicy>() {
+            allocator::set_memory_zero_policy(&policy);
+        }
+
+        if let Some(checks) = self.storage.get_config::<MemoryWriteChecks>() {
+            allocator::set_memory_write_checks(&checks);
+        }
+
+        if let Some(locked) = self.storage.get_config::<LockedConfigurationTables>() {
+            config_tables::set_locked_configuration_tables(&locked);
+        }
+
+        if let Some(ranges) = self.storage.get_config::<BenignFaultRanges>() {
+            benign_faults::enable_benign_faults(&ranges);
+        }
+
+        if let Some(protection) = self.storage.get_config::<ControlFlowProtection>() {
+            control_flow::enable_control_flow_protection(&protection);
+        }
+
+        if let Some(reporting) = self.storage.get_config::<InterruptLatencyReporting>() {
+            interrupt_latency::enable_interrupt_latency_reporting(&reporting);
+        }
+
+        if let Some(audit) = self.storage.get_config::<MemoryAttributeAudit>() {
+            memory_attribute_journal::enable_memory_attribute_audit(&audit);
+        }
+
+        if let Some(enforcement) = self.storage.get_config::<WxEnforcement>() {
+            wx_enforcement::enable_wx_enforcement(&enforcement);
+        }
+
+        if let Some(logging) = self.storage.get_config::<StrayBootServicesLogging>() {
+            stray_boot_services::set_logging(&logging);
+        }
+
+        if self.storage.get_config::<RuntimeAddressingChecks>().is_some() {
+            runtime::enable_addressing_checks();
+        }
+
+        if let Some(policy) = self.storage.get_config::<PanicPolicy>() {
+            panic_policy::set_panic_policy(&policy);
+        }
+
+        if let Some(placement) = self.storage.get_config::<MmioPlacement>() {
+            mmio_placement::set_mmio_placement(&placement);
+        }
+
+        if let Some(domains) = self.storage.get_config::<ProximityDomains>() {
+            proximity_domains::set_proximity_domains(&domains);
+        }
+
+        if let Some(policy) = self.storage.get_config::<FastBootPolicy>()
+            && let Some(blobs) = self.storage.get_service::<dyn PersistedBlob>()
+        {
+            fast_boot::enable(&policy, &self.hob_list, blobs);
+        }
+
+        if self.storage.get_config::<MemoryBinFeedback>().is_some() {
+            memory_bins::enable(&self.hob_list, self.storage.runtime_services().clone());
+        }
+
+        let snapshot_policy =
+            self.storage.get_config::<ProtocolDbSnapshotPolicy>().map(|policy| *policy).unwrap_or_default();
+        protocol_db_snapshot::init_protocol_db_snapshot(&snapshot_policy);
+
+        if let Some(execute_in_place) = self.storage.get_config::<ExecuteInPlaceImages>() {
+            image::enable_execute_in_place(&execute_in_place);
+        }
+
+        if let Some(policy) = self.storage.get_config::<ImageProtectionPolicy>() {
+            image::set_image_protection_policy(&policy);
+        }
+
+        if let Some(policy) = self.storage.get_config::<EbcImagePolicy>() {
+            image::set_ebc_image_policy(&policy);
+        }
+
+        if let Some(policy) = self.storage.get_config::<ImageDirectoryPolicy>() {
+            image::set_image_directory_policy(&policy);
+        }
+
+        if let Some(watchdog) = self.storage.get_config::<ImageWatchdog>() {
+            image::enable_image_watchdog(&watchdog);
+        }
+
+        if let Some(nesting) = self.storage.get_config::<ImageStartNesting>() {
+            image::set_image_start_nesting(&nesting);
+        }
+
+        if let Some(stack) = self.storage.get_config::<ImageStackConfig>() {
+            image::set_image_stack_config(&stack);
+        }
+
+        if let Some(period) = self.storage.get_config::<TimerPeriod>() {
+            events::set_platform_timer_period(&period);
+        }
+
         log::info!("Parsing FVs from FV HOBs");
         fv::parse_hob_fvs(&self.hob_list)?;
         log::info!("Finished.");
 
         log::info!("Dispatching Drivers");
+        progress_code::report(ProgressCheckpoint::DispatchStart);
+        boot_checkpoint::report(BootCheckpoint::DispatchStart);
+        self.storage.enter_lifecycle_stage(LifecycleStage::PreDispatch);
         self.core_dispatcher()?;
+        self.storage.enter_lifecycle_stage(LifecycleStage::PostFirstPass);
         self.storage.lock_configs();
+        self.storage.enter_lifecycle_stage(LifecycleStage::Locked);
         self.core_dispatcher()?;
         log::info!("Finished Dispatching Drivers");
+        boot_checkpoint::report(BootCheckpoint::DispatchComplete);
 
         self.display_components_not_dispatched();
 
@@ -563,6 +1061,18 @@ impl Core<Alloc> {
 
         dispatcher::display_discovered_not_dispatched();
 
+        let config_digest = crc32::hash(&self.boot_config_blob());
+        if let Err(err) = core_info::CoreInfo::collect(config_digest, self.storage.service_names()).publish() {
+            log::error!("Failed to publish DXE Core information: {err:?}");
+        }
+
+        security_posture::enable(
+            self.storage.runtime_services().clone(),
+            self.storage.get_config::<MemoryProtectionPolicy>().map(|policy| *policy),
+        );
+
+        progress_code::report(ProgressCheckpoint::HandoffToBds);
+        boot_checkpoint::report(BootCheckpoint::BdsHandoff);
         call_bds();
 
         log::info!("Finished");
@@ -570,27 +1080,26 @@ impl Core<Alloc> {
     }
 }
 
-const ARCH_PROTOCOLS: &[(uuid::Uuid, &str)] = &[
-    (uuid::uuid!("a46423e3-4617-49f1-b9ff-d1bfa9115839"), "Security"),
-    (uuid::uuid!("26baccb1-6f42-11d4-bce7-0080c73c8881"), "Cpu"),
-    (uuid::uuid!("26baccb2-6f42-11d4-bce7-0080c73c8881"), "Metronome"),
-    (uuid::uuid!("26baccb3-6f42-11d4-bce7-0080c73c8881"), "Timer"),
-    (uuid::uuid!("665e3ff6-46cc-11d4-9a38-0090273fc14d"), "Bds"),
-    (uuid::uuid!("665e3ff5-46cc-11d4-9a38-0090273fc14d"), "Watchdog"),
-    (uuid::uuid!("b7dfb4e1-052f-449f-87be-9818fc91b733"), "Runtime"),
-    (uuid::uuid!("1e5668e2-8481-11d4-bcf1-0080c73c8881"), "Variable"),
-    (uuid::uuid!("6441f818-6362-4e44-b570-7dba31dd2453"), "Variable Write"),
-    (uuid::uuid!("5053697e-2cbc-4819-90d9-0580deee5754"), "Capsule"),
-    (uuid::uuid!("1da97072-bddc-4b30-99f1-72a0b56fff2a"), "Monotonic Counter"),
-    (uuid::uuid!("27cfac88-46cc-11d4-9a38-0090273fc14d"), "Reset"),
-    (uuid::uuid!("27cfac87-46cc-11d4-9a38-0090273fc14d"), "Real Time Clock"),
+const ARCH_PROTOCOLS: &[efi::Guid] = &[
+    patina_pi::protocols::security::PROTOCOL_GUID,
+    patina_pi::protocols::cpu_arch::PROTOCOL_GUID,
+    patina_pi::protocols::metronome::PROTOCOL_GUID,
+    patina_pi::protocols::timer::PROTOCOL_GUID,
+    bds::PROTOCOL_GUID,
+    patina_pi::protocols::watchdog::PROTOCOL_GUID,
+    patina_pi::protocols::runtime::PROTOCOL_GUID,
+    guid_names::VARIABLE_ARCH_PROTOCOL,
+    guid_names::VARIABLE_WRITE_ARCH_PROTOCOL,
+    guid_names::CAPSULE_ARCH_PROTOCOL,
+    patina_pi::protocols::monotonic_counter::PROTOCOL_GUID,
+    guid_names::RESET_ARCH_PROTOCOL,
+    guid_names::REAL_TIME_CLOCK_ARCH_PROTOCOL,
 ];
 
 fn core_display_missing_arch_protocols() {
-    for (uuid, name) in ARCH_PROTOCOLS {
-        let guid = efi::Guid::from_bytes(&uuid.to_bytes_le());
-        if protocols::PROTOCOL_DB.locate_protocol(guid).is_err() {
-            log::warn!("Missing architectural protocol: {uuid:?}, {name:?}");
+    for guid in ARCH_PROTOCOLS {
+        if protocols::PROTOCOL_DB.locate_protocol(*guid).is_err() {
+            log::warn!("Missing architectural protocol: {}", guid_names::named(guid));
         }
     }
 }
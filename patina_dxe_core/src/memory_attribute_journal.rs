@@ -0,0 +1,305 @@
+//! Memory Attribute Journal
+//!
+//! Security reviews need to know who changed the attributes of a range, for example which image made a data buffer
+//! executable. Platforms can enable [MemoryAttributeAudit] to record every change the GCD makes to the attributes of a
+//! memory range: the running image, a performance counter timestamp, the range, and the attributes before and after.
+//! The journal holds the most recent changes and can be read through the
+//! [MemoryAttributeJournal](patina::component::service::memory_attribute_journal::MemoryAttributeJournal) service or
+//! the `attr_journal` monitor command.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use mu_rust_helpers::perf_timer::{Arch, ArchFunctionality};
+use patina::component::service::{
+    IntoService,
+    memory_attribute_journal::{AttributeChange, MemoryAttributeJournal},
+};
+use spin::Mutex;
+
+use crate::image;
+
+/// Records every change to the attributes of a GCD memory range, keeping the most recent `capacity` changes.
+///
+/// Nothing is recorded unless the platform registers this config. Changes are recorded from the time the core starts
+/// dispatching components.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, MemoryAttributeAudit};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(MemoryAttributeAudit { capacity: 4096 })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryAttributeAudit {
+    /// The most changes that are kept. Older changes are overwritten once the journal is full.
+    pub capacity: usize,
+}
+
+// The image recorded for changes made while the image data was locked, so that the running image is not known.
+const UNKNOWN_IMAGE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    sequence: u64,
+    timestamp: u64,
+    // the handle of the running image, 0 for the DXE Core, or UNKNOWN_IMAGE.
+    image: usize,
+    base_address: u64,
+    length: u64,
+    old_attributes: u64,
+    new_attributes: u64,
+}
+
+// A ring of the most recent entries. The entries are allocated up front, as changes are recorded while the GCD is
+// servicing an allocation.
+struct Journal {
+    entries: Vec<Entry>,
+    capacity: usize,
+    recorded: u64,
+}
+
+impl Journal {
+    const fn new() -> Self {
+        Self { entries: Vec::new(), capacity: 0, recorded: 0 }
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self { entries: Vec::with_capacity(capacity), capacity, recorded: 0 }
+    }
+
+    // Records `entry` as the next change, overwriting the oldest entry once the journal is full.
+    fn record(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = Entry { sequence: self.recorded, ..entry };
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries[(self.recorded % self.capacity as u64) as usize] = entry;
+        }
+        self.recorded += 1;
+    }
+
+    // Copies the entries to `out`, oldest first. `out` must have room for the entries, so that nothing is allocated
+    // while the journal is locked.
+    fn copy_to(&self, out: &mut Vec<Entry>) {
+        let oldest = match self.overwritten() {
+            0 => 0,
+            _ => (self.recorded % self.capacity as u64) as usize,
+        };
+        out.extend_from_slice(&self.entries[oldest..]);
+        out.extend_from_slice(&self.entries[..oldest]);
+    }
+
+    // Returns the number of changes that were overwritten.
+    fn overwritten(&self) -> u64 {
+        self.recorded - self.entries.len() as u64
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUSY_DROPS: AtomicU64 = AtomicU64::new(0);
+static JOURNAL: Mutex<Journal> = Mutex::new(Journal::new());
+
+/// Allocates the journal, registers the `attr_journal` monitor command and starts recording changes.
+pub(crate) fn enable_memory_attribute_audit(audit: &MemoryAttributeAudit) {
+    if audit.capacity == 0 {
+        log::warn!("Memory attribute audit has a capacity of 0, no changes will be recorded.");
+        return;
+    }
+    // allocate before locking, as allocating changes attributes and would find the journal busy.
+    let journal = Journal::with_capacity(audit.capacity);
+    *JOURNAL.lock() = journal;
+
+    patina_debugger::add_monitor_command(
+        "attr_journal",
+        "Prints the memory attribute changes: attr_journal [<addr> <len>]",
+        |args, out| {
+            let mut parse = || args.next().map(|arg| u64::from_str_radix(arg.trim_start_matches("0x"), 16));
+            let (base_address, length) = match (parse(), parse()) {
+                (None, _) => (0, u64::MAX),
+                (Some(Ok(base_address)), Some(Ok(length))) => (base_address, length),
+                _ => {
+                    let _ = write!(out, "Usage: attr_journal [<hex address> <hex length>]");
+                    return;
+                }
+            };
+            for change in attribute_changes().iter().filter(|change| change.overlaps(base_address, length)) {
+                let _ = writeln!(
+                    out,
+                    "#{} @{}: {:#x}-{:#x} {:#x} -> {:#x} by {}",
+                    change.sequence,
+                    change.timestamp,
+                    change.base_address,
+                    change.base_address + change.length,
+                    change.old_attributes,
+                    change.new_attributes,
+                    change.image.as_deref().unwrap_or("DXE Core")
+                );
+            }
+            let _ = write!(out, "{} change(s) dropped", dropped_changes());
+        },
+    );
+
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Records a change of the attributes of the `length` bytes at `base_address`.
+///
+/// Called by the GCD, possibly while it services an allocation, so this never blocks, allocates or logs. A change is
+/// dropped if the journal is busy.
+pub(crate) fn record(base_address: u64, length: u64, old_attributes: u64, new_attributes: u64) {
+    if !ENABLED.load(Ordering::Relaxed) || old_attributes == new_attributes {
+        return;
+    }
+    let image = match image::try_current_image_handle() {
+        Some(handle) => handle.map_or(0, |handle| handle as usize),
+        None => UNKNOWN_IMAGE,
+    };
+    let entry = Entry {
+        sequence: 0,
+        timestamp: Arch::cpu_count(),
+        image,
+        base_address,
+        length,
+        old_attributes,
+        new_attributes,
+    };
+    match JOURNAL.try_lock() {
+        Some(mut journal) => journal.record(entry),
+        None => {
+            BUSY_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn attribute_changes() -> Vec<AttributeChange> {
+    let capacity = JOURNAL.lock().capacity;
+    let mut entries = Vec::with_capacity(capacity);
+    JOURNAL.lock().copy_to(&mut entries);
+
+    entries
+        .into_iter()
+        .map(|entry| AttributeChange {
+            sequence: entry.sequence,
+            timestamp: entry.timestamp,
+            image: match entry.image {
+                0 => None,
+                UNKNOWN_IMAGE => Some(String::from("Unknown")),
+                handle => Some(
+                    image::try_image_name(handle as r_efi::efi::Handle)
+                        .unwrap_or_else(|| alloc::format!("{handle:#x}")),
+                ),
+            },
+            base_address: entry.base_address,
+            length: entry.length,
+            old_attributes: entry.old_attributes,
+            new_attributes: entry.new_attributes,
+        })
+        .collect()
+}
+
+fn dropped_changes() -> u64 {
+    JOURNAL.lock().overwritten() + BUSY_DROPS.load(Ordering::Relaxed)
+}
+
+/// The core's [MemoryAttributeJournal] service, which reports the changes recorded by the journal.
+#[derive(IntoService)]
+#[service(dyn MemoryAttributeJournal)]
+pub(crate) struct CoreMemoryAttributeJournal;
+
+impl MemoryAttributeJournal for CoreMemoryAttributeJournal {
+    fn attribute_changes(&self) -> Vec<AttributeChange> {
+        attribute_changes()
+    }
+
+    fn dropped_changes(&self) -> u64 {
+        dropped_changes()
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use crate::test_support;
+    use r_efi::efi;
+
+    fn entry(base_address: u64) -> Entry {
+        Entry {
+            sequence: 0,
+            timestamp: 0,
+            image: 0,
+            base_address,
+            length: 0x1000,
+            old_attributes: efi::MEMORY_XP,
+            new_attributes: efi::MEMORY_RO,
+        }
+    }
+
+    fn recorded(journal: &Journal) -> Vec<(u64, u64)> {
+        let mut entries = Vec::with_capacity(journal.capacity);
+        journal.copy_to(&mut entries);
+        entries.iter().map(|entry| (entry.sequence, entry.base_address)).collect()
+    }
+
+    #[test]
+    fn test_full_journal_keeps_the_most_recent_changes() {
+        let mut journal = Journal::with_capacity(3);
+        journal.record(entry(0x1000));
+        journal.record(entry(0x2000));
+        assert_eq!(recorded(&journal), [(0, 0x1000), (1, 0x2000)]);
+        assert_eq!(journal.overwritten(), 0);
+
+        journal.record(entry(0x3000));
+        journal.record(entry(0x4000));
+        journal.record(entry(0x5000));
+        assert_eq!(recorded(&journal), [(2, 0x3000), (3, 0x4000), (4, 0x5000)]);
+        assert_eq!(journal.overwritten(), 2);
+
+        let mut disabled = Journal::new();
+        disabled.record(entry(0x1000));
+        assert!(recorded(&disabled).is_empty());
+    }
+
+    #[test]
+    fn test_changes_are_recorded_while_enabled() {
+        test_support::with_global_lock(|| {
+            *JOURNAL.lock() = Journal::with_capacity(8);
+            record(0x1000, 0x1000, efi::MEMORY_XP, efi::MEMORY_RO);
+
+            ENABLED.store(true, Ordering::SeqCst);
+            record(0x2000, 0x1000, efi::MEMORY_XP, efi::MEMORY_RO);
+            // changes that leave the attributes as they were are not recorded.
+            record(0x3000, 0x1000, efi::MEMORY_XP, efi::MEMORY_XP);
+            let busy = JOURNAL.lock();
+            record(0x4000, 0x1000, efi::MEMORY_RO, efi::MEMORY_XP);
+            drop(busy);
+            ENABLED.store(false, Ordering::SeqCst);
+
+            let changes = CoreMemoryAttributeJournal.attribute_changes();
+            assert_eq!(changes.len(), 1);
+            assert_eq!((changes[0].base_address, changes[0].image.as_deref()), (0x2000, None));
+            assert_eq!(CoreMemoryAttributeJournal.dropped_changes(), BUSY_DROPS.load(Ordering::SeqCst));
+            assert!(CoreMemoryAttributeJournal.dropped_changes() >= 1);
+
+            *JOURNAL.lock() = Journal::new();
+        })
+        .unwrap();
+    }
+}
@@ -1,5 +1,10 @@
 //! DXE Core Memory Attributes Protocol
 //!
+//! Produces the UEFI Memory Attributes Protocol and the Patina
+//! [MemoryAttributesExtProtocol](patina::uefi_protocol::memory_attributes_ext::MemoryAttributesExtProtocol), which
+//! updates several ranges at once and reports attribute runs. The extended protocol is also available to components as
+//! the [MemoryAttributes](patina::component::service::memory_attributes::MemoryAttributes) service.
+//!
 //! ## License
 //!
 //! Copyright (c) Microsoft Corporation.
@@ -9,13 +14,23 @@
 #![allow(unused)]
 /// Architecture independent public C EFI Memory Attributes Protocol definition.
 use crate::{dxe_services, protocol_db, protocols::PROTOCOL_DB};
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::{
     ffi::c_void,
+    slice,
     sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
 };
 use mu_rust_helpers::function;
-use patina::{base::UEFI_PAGE_MASK, error::EfiError};
+use patina::{
+    base::UEFI_PAGE_MASK,
+    component::service::{
+        IntoService,
+        memory_attributes::{AttributeRun, MemoryAttributes, MemoryRange},
+    },
+    error::EfiError,
+    uefi_protocol::{ProtocolInterface, memory_attributes_ext::MemoryAttributesExtProtocol},
+};
+use patina_pi::dxe_services::MemorySpaceDescriptor;
 use r_efi::efi;
 
 #[repr(C)]
@@ -189,6 +204,180 @@ extern "efiapi" fn clear_memory_attributes(
     efi::Status::SUCCESS
 }
 
+/// Returns the `(base_address, length, attributes)` updates that apply `update` to the attributes of each memory space
+/// descriptor covered by `ranges`. All ranges are validated before `descriptor_for` is used to split them at
+/// descriptor boundaries.
+fn attribute_updates(
+    ranges: &[MemoryRange],
+    attributes: u64,
+    update: impl Fn(u64) -> u64,
+    descriptor_for: impl Fn(efi::PhysicalAddress) -> Result<MemorySpaceDescriptor, EfiError>,
+) -> Result<Vec<(efi::PhysicalAddress, u64, u64)>, EfiError> {
+    // as with the UEFI protocol, only MEMORY_RO, MEMORY_RP, and MEMORY_XP can be set or cleared
+    if attributes == 0 || (attributes & efi::MEMORY_ACCESS_MASK) != attributes {
+        log::error!("Invalid attributes {:x?} in {}", attributes, function!());
+        return Err(EfiError::InvalidParameter);
+    }
+
+    for range in ranges {
+        if range.length == 0
+            || ((range.base_address | range.length) & UEFI_PAGE_MASK as u64) != 0
+            || range.base_address.checked_add(range.length).is_none()
+        {
+            log::error!("Invalid range {:#x?} in {}", range, function!());
+            return Err(EfiError::InvalidParameter);
+        }
+    }
+
+    let mut updates = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let range_end = range.base_address + range.length;
+        let mut current_base = range.base_address;
+        while current_base < range_end {
+            let descriptor = descriptor_for(current_base).map_err(|e| {
+                log::error!(
+                    "Memory descriptor fetching failed with error {:#x?} for {:#x} in {}",
+                    e,
+                    current_base,
+                    function!()
+                );
+                EfiError::NoMapping
+            })?;
+
+            // it is still legal to split a descriptor and only update the attributes on part of it
+            let next_base = u64::min(descriptor.base_address + descriptor.length, range_end);
+            updates.push((current_base, next_base - current_base, update(descriptor.attributes)));
+            current_base = next_base;
+        }
+    }
+    Ok(updates)
+}
+
+/// Applies `update` to the attributes of every descriptor covered by `ranges`, with a single update of the GCD and the
+/// page table.
+fn update_memory_attributes(
+    ranges: &[MemoryRange],
+    attributes: u64,
+    update: impl Fn(u64) -> u64,
+) -> Result<(), EfiError> {
+    let updates = attribute_updates(ranges, attributes, update, dxe_services::core_get_memory_space_descriptor)?;
+    dxe_services::core_set_memory_space_attributes_multi(&updates).map_err(|status| {
+        log::error!("Failed to update memory attributes: {:?} in {}", status, function!());
+        // as with the UEFI protocol, earlier updates are not rolled back and only a few status codes are allowed.
+        EfiError::Unsupported
+    })
+}
+
+/// Returns the run of memory with the same access attributes that starts at `base_address`, ending no later than
+/// `end_address`, using `descriptor_for` to look up the memory space descriptors.
+fn attribute_run(
+    base_address: efi::PhysicalAddress,
+    end_address: efi::PhysicalAddress,
+    descriptor_for: impl Fn(efi::PhysicalAddress) -> Result<MemorySpaceDescriptor, EfiError>,
+) -> Result<AttributeRun, EfiError> {
+    if ((base_address | end_address) & UEFI_PAGE_MASK as u64) != 0 || base_address >= end_address {
+        log::error!("Invalid range {:#x}-{:#x} in {}", base_address, end_address, function!());
+        return Err(EfiError::InvalidParameter);
+    }
+
+    let descriptor = descriptor_for(base_address).map_err(|_| EfiError::NoMapping)?;
+    let attributes = descriptor.attributes & efi::MEMORY_ACCESS_MASK;
+
+    // adjacent descriptors can have the same access attributes when other attributes differ, so merge them.
+    let mut run_end = descriptor.base_address + descriptor.length;
+    while run_end < end_address {
+        match descriptor_for(run_end) {
+            Ok(next) if next.length != 0 && next.attributes & efi::MEMORY_ACCESS_MASK == attributes => {
+                run_end = next.base_address + next.length
+            }
+            _ => break,
+        }
+    }
+
+    let run_end = u64::min(run_end, end_address);
+    Ok(AttributeRun { base_address, length: run_end - base_address, attributes })
+}
+
+/// Provides the extended memory attributes operations to components.
+#[derive(IntoService)]
+#[service(dyn MemoryAttributes)]
+pub(crate) struct CoreMemoryAttributes;
+
+impl MemoryAttributes for CoreMemoryAttributes {
+    fn set_memory_attributes(&self, ranges: &[MemoryRange], attributes: u64) -> Result<(), EfiError> {
+        // this API only adds new attributes that are set, it ignores all 0 attributes.
+        update_memory_attributes(ranges, attributes, |current| current | attributes)
+    }
+
+    fn clear_memory_attributes(&self, ranges: &[MemoryRange], attributes: u64) -> Result<(), EfiError> {
+        // this API only clears attributes that are set to 1, it ignores all 0 attributes.
+        update_memory_attributes(ranges, attributes, |current| current & !attributes)
+    }
+
+    fn attribute_run(&self, base_address: u64, end_address: u64) -> Result<AttributeRun, EfiError> {
+        attribute_run(base_address, end_address, dxe_services::core_get_memory_space_descriptor)
+    }
+}
+
+extern "efiapi" fn set_memory_attributes_ext(
+    _this: *mut MemoryAttributesExtProtocol,
+    ranges: *const MemoryRange,
+    range_count: usize,
+    attributes: u64,
+) -> efi::Status {
+    if ranges.is_null() {
+        log::error!("Ranges is null, failing {}", function!());
+        return efi::Status::INVALID_PARAMETER;
+    }
+
+    // Safety: caller must provide `range_count` valid ranges. The pointer is null-checked above.
+    let ranges = unsafe { slice::from_raw_parts(ranges, range_count) };
+    match CoreMemoryAttributes.set_memory_attributes(ranges, attributes) {
+        Ok(()) => efi::Status::SUCCESS,
+        Err(err) => err.into(),
+    }
+}
+
+extern "efiapi" fn clear_memory_attributes_ext(
+    _this: *mut MemoryAttributesExtProtocol,
+    ranges: *const MemoryRange,
+    range_count: usize,
+    attributes: u64,
+) -> efi::Status {
+    if ranges.is_null() {
+        log::error!("Ranges is null, failing {}", function!());
+        return efi::Status::INVALID_PARAMETER;
+    }
+
+    // Safety: caller must provide `range_count` valid ranges. The pointer is null-checked above.
+    let ranges = unsafe { slice::from_raw_parts(ranges, range_count) };
+    match CoreMemoryAttributes.clear_memory_attributes(ranges, attributes) {
+        Ok(()) => efi::Status::SUCCESS,
+        Err(err) => err.into(),
+    }
+}
+
+extern "efiapi" fn get_attribute_run(
+    _this: *mut MemoryAttributesExtProtocol,
+    base_address: efi::PhysicalAddress,
+    end_address: efi::PhysicalAddress,
+    run: *mut AttributeRun,
+) -> efi::Status {
+    if run.is_null() {
+        log::error!("Run is null, failing {}", function!());
+        return efi::Status::INVALID_PARAMETER;
+    }
+
+    match CoreMemoryAttributes.attribute_run(base_address, end_address) {
+        Ok(attribute_run) => {
+            // Safety: caller must provide a valid pointer to receive the run. It is null-checked above.
+            unsafe { run.write_unaligned(attribute_run) };
+            efi::Status::SUCCESS
+        }
+        Err(err) => err.into(),
+    }
+}
+
 impl EfiMemoryAttributesProtocolImpl {
     fn new() -> Self {
         Self {
@@ -203,6 +392,7 @@ impl EfiMemoryAttributesProtocolImpl {
 
 static MEMORY_ATTRIBUTES_PROTOCOL_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(core::ptr::null_mut());
 static MEMORY_ATTRIBUTES_PROTOCOL_INTERFACE: AtomicPtr<c_void> = AtomicPtr::new(core::ptr::null_mut());
+static MEMORY_ATTRIBUTES_EXT_PROTOCOL_INTERFACE: AtomicPtr<c_void> = AtomicPtr::new(core::ptr::null_mut());
 
 /// This function is called by the DXE Core to install the protocol.
 pub(crate) fn install_memory_attributes_protocol() {
@@ -219,8 +409,22 @@ pub(crate) fn install_memory_attributes_protocol() {
         },
         Err(e) => {
             log::error!("Failed to install MEMORY_ATTRIBUTES_PROTOCOL_GUID: {e:?}");
+            return;
         }
     }
+
+    // the extended protocol is installed on the same handle, so that it is uninstalled along with the UEFI protocol.
+    let protocol =
+        MemoryAttributesExtProtocol::new(set_memory_attributes_ext, clear_memory_attributes_ext, get_attribute_run);
+    let interface = Box::into_raw(Box::new(protocol)) as *mut c_void;
+    MEMORY_ATTRIBUTES_EXT_PROTOCOL_INTERFACE.store(interface, Ordering::SeqCst);
+
+    let handle = MEMORY_ATTRIBUTES_PROTOCOL_HANDLE.load(Ordering::SeqCst);
+    if let Err(e) =
+        PROTOCOL_DB.install_protocol_interface(Some(handle), MemoryAttributesExtProtocol::PROTOCOL_GUID, interface)
+    {
+        log::error!("Failed to install the extended memory attributes protocol: {e:?}");
+    }
 }
 
 #[cfg(feature = "compatibility_mode_allowed")]
@@ -249,5 +453,114 @@ pub(crate) fn uninstall_memory_attributes_protocol() {
                 log::error!("MEMORY_ATTRIBUTES_PROTOCOL_GUID was not installed");
             }
         }
+
+        match (
+            MEMORY_ATTRIBUTES_PROTOCOL_HANDLE.load(Ordering::SeqCst),
+            MEMORY_ATTRIBUTES_EXT_PROTOCOL_INTERFACE.load(Ordering::SeqCst),
+        ) {
+            (handle, interface) if handle != protocol_db::INVALID_HANDLE && !interface.is_null() => {
+                match PROTOCOL_DB.uninstall_protocol_interface(
+                    handle,
+                    MemoryAttributesExtProtocol::PROTOCOL_GUID,
+                    interface,
+                ) {
+                    Ok(_) => log::info!("uninstalled the extended memory attributes protocol"),
+                    Err(e) => log::error!("Failed to uninstall the extended memory attributes protocol: {e:?}"),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    // 0x0-0x4000 RO|XP, 0x4000-0x6000 XP (RO|XP with other attributes), 0x6000-0x8000 XP, 0x8000-0x10000 no access
+    // attributes, unmapped above.
+    fn descriptor_for(address: efi::PhysicalAddress) -> Result<MemorySpaceDescriptor, EfiError> {
+        let (base_address, length, attributes) = match address {
+            0x0..0x4000 => (0x0, 0x4000, efi::MEMORY_RO | efi::MEMORY_XP),
+            0x4000..0x6000 => (0x4000, 0x2000, efi::MEMORY_RO | efi::MEMORY_XP | efi::MEMORY_WB),
+            0x6000..0x8000 => (0x6000, 0x2000, efi::MEMORY_XP),
+            0x8000..0x10000 => (0x8000, 0x8000, efi::MEMORY_WB),
+            _ => return Err(EfiError::NotFound),
+        };
+        Ok(MemorySpaceDescriptor { base_address, length, attributes, ..Default::default() })
+    }
+
+    #[test]
+    fn attribute_run_should_merge_descriptors_with_the_same_access_attributes() {
+        assert_eq!(
+            attribute_run(0x1000, 0x10000, descriptor_for),
+            Ok(AttributeRun { base_address: 0x1000, length: 0x5000, attributes: efi::MEMORY_RO | efi::MEMORY_XP })
+        );
+        assert_eq!(
+            attribute_run(0x6000, 0x10000, descriptor_for),
+            Ok(AttributeRun { base_address: 0x6000, length: 0x2000, attributes: efi::MEMORY_XP })
+        );
+        // the run is clipped to the end address, and ends at unmapped memory.
+        assert_eq!(
+            attribute_run(0x0, 0x2000, descriptor_for),
+            Ok(AttributeRun { base_address: 0x0, length: 0x2000, attributes: efi::MEMORY_RO | efi::MEMORY_XP })
+        );
+        assert_eq!(
+            attribute_run(0x8000, 0x20000, descriptor_for),
+            Ok(AttributeRun { base_address: 0x8000, length: 0x8000, attributes: 0 })
+        );
+
+        assert_eq!(attribute_run(0x10000, 0x20000, descriptor_for), Err(EfiError::NoMapping));
+        assert_eq!(attribute_run(0x1001, 0x2000, descriptor_for), Err(EfiError::InvalidParameter));
+        assert_eq!(attribute_run(0x2000, 0x2000, descriptor_for), Err(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn attribute_updates_should_split_ranges_at_descriptor_boundaries() {
+        let ranges = [
+            MemoryRange { base_address: 0x3000, length: 0x4000 },
+            MemoryRange { base_address: 0x9000, length: 0x1000 },
+        ];
+        let updates = attribute_updates(&ranges, efi::MEMORY_RP, |current| current | efi::MEMORY_RP, descriptor_for);
+        assert_eq!(
+            updates,
+            Ok(alloc::vec![
+                (0x3000, 0x1000, efi::MEMORY_RP | efi::MEMORY_RO | efi::MEMORY_XP),
+                (0x4000, 0x2000, efi::MEMORY_RP | efi::MEMORY_RO | efi::MEMORY_XP | efi::MEMORY_WB),
+                (0x6000, 0x1000, efi::MEMORY_RP | efi::MEMORY_XP),
+                (0x9000, 0x1000, efi::MEMORY_RP | efi::MEMORY_WB),
+            ])
+        );
+    }
+
+    #[test]
+    fn attribute_updates_should_validate_every_range_first() {
+        let update = |current| current & !efi::MEMORY_RO;
+        let mapped = MemoryRange { base_address: 0x0, length: 0x1000 };
+        let unmapped = MemoryRange { base_address: 0x10000, length: 0x1000 };
+
+        for invalid in [
+            MemoryRange { base_address: 0x1000, length: 0 },
+            MemoryRange { base_address: 0x1800, length: 0x1000 },
+            MemoryRange { base_address: 0x1000, length: 0x800 },
+            MemoryRange { base_address: u64::MAX & !(UEFI_PAGE_MASK as u64), length: 0x2000 },
+        ] {
+            // an invalid range is reported even after an unmapped one.
+            assert_eq!(
+                attribute_updates(&[mapped, unmapped, invalid], efi::MEMORY_RO, update, descriptor_for),
+                Err(EfiError::InvalidParameter)
+            );
+        }
+
+        assert_eq!(attribute_updates(&[mapped], 0, update, descriptor_for), Err(EfiError::InvalidParameter));
+        assert_eq!(
+            attribute_updates(&[mapped], efi::MEMORY_WB, update, descriptor_for),
+            Err(EfiError::InvalidParameter)
+        );
+        assert_eq!(
+            attribute_updates(&[mapped, unmapped], efi::MEMORY_RO, update, descriptor_for),
+            Err(EfiError::NoMapping)
+        );
     }
 }
@@ -0,0 +1,204 @@
+//! Memory Bin Feedback
+//!
+//! The core pre-allocates a bin of pages for each memory type listed in the Memory Type Information HOB, so that
+//! runtime and ACPI memory stay at the same addresses from one boot to the next, which is needed for S4 resume. Bins
+//! that are too small are of no use, so EDK II closes the loop in BDS: the memory actually used is recorded at boot,
+//! and written to the `MemoryTypeInformation` variable that the platform's PEI code turns into the HOB for the next
+//! boot.
+//!
+//! Platforms that register [MemoryBinFeedback] get the same loop from the core: at ReadyToBoot, the core compares the
+//! pages in use for each memory type against the bins of this boot, grows the bins that overflowed with some headroom,
+//! and writes the variable if any bin changed. Bins never shrink, so that the memory map stays stable.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+use patina::{
+    guids,
+    runtime_services::{RuntimeServices, StandardRuntimeServices},
+};
+use patina_pi::hob::{Hob, HobList, MEMORY_TYPE_INFO_HOB_GUID};
+use r_efi::efi;
+use spin::Mutex;
+
+use crate::{GCD, events::EVENT_DB};
+
+/// Publishes the memory used at ReadyToBoot as the memory bins for the next boot.
+///
+/// Nothing is published unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, MemoryBinFeedback};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(MemoryBinFeedback)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryBinFeedback;
+
+/// The memory types that are binned when the platform did not produce a Memory Type Information HOB.
+const DEFAULT_BINNED_TYPES: [efi::MemoryType; 7] = [
+    efi::RESERVED_MEMORY_TYPE,
+    efi::ACPI_RECLAIM_MEMORY,
+    efi::ACPI_MEMORY_NVS,
+    efi::RUNTIME_SERVICES_CODE,
+    efi::RUNTIME_SERVICES_DATA,
+    efi::BOOT_SERVICES_CODE,
+    efi::BOOT_SERVICES_DATA,
+];
+
+/// The memory type that terminates the list of bins, `EfiMaxMemoryType`.
+const MAX_MEMORY_TYPE: efi::MemoryType = 16;
+
+const VARIABLE_NAME: &str = "MemoryTypeInformation";
+
+/// The number of pages of each binned memory type.
+type Bins = Vec<(efi::MemoryType, u32)>;
+
+struct Feedback {
+    previous: Bins,
+    runtime_services: StandardRuntimeServices,
+}
+
+static FEEDBACK: Mutex<Option<Feedback>> = Mutex::new(None);
+
+/// Records the bins of this boot, and publishes the bins for the next boot at ReadyToBoot.
+pub(crate) fn enable(hob_list: &HobList, runtime_services: StandardRuntimeServices) {
+    let previous = hob_list
+        .iter()
+        .find_map(|hob| match hob {
+            Hob::GuidHob(guid, data) if guid.name == MEMORY_TYPE_INFO_HOB_GUID => Some(from_bytes(data)),
+            _ => None,
+        })
+        .unwrap_or_else(|| DEFAULT_BINNED_TYPES.iter().map(|&memory_type| (memory_type, 0)).collect());
+    *FEEDBACK.lock() = Some(Feedback { previous, runtime_services });
+
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(publish_event),
+        None,
+        Some(efi::EVENT_GROUP_READY_TO_BOOT),
+    ) {
+        log::error!("Failed to register an event to publish the memory bins! Status {status:#X?}");
+    }
+}
+
+// Returns the bins in a Memory Type Information HOB or variable, which ends at the EfiMaxMemoryType entry.
+fn from_bytes(data: &[u8]) -> Bins {
+    data.chunks_exact(8)
+        .map(|entry| {
+            let field = |offset: usize| u32::from_le_bytes(entry[offset..offset + 4].try_into().unwrap());
+            (field(0), field(4))
+        })
+        .take_while(|&(memory_type, _)| memory_type < MAX_MEMORY_TYPE)
+        .collect()
+}
+
+fn to_bytes(bins: &Bins) -> Vec<u8> {
+    bins.iter()
+        .chain([(MAX_MEMORY_TYPE, 0)].iter())
+        .flat_map(|(memory_type, pages)| memory_type.to_le_bytes().into_iter().chain(pages.to_le_bytes()))
+        .collect()
+}
+
+/// Returns the bins for the next boot, given the bins of this boot and the pages in use by each memory type.
+///
+/// A bin that overflowed grows to the pages in use plus a quarter for headroom, like EDK II.
+fn next_bins(previous: &Bins, used: impl Fn(efi::MemoryType) -> u32) -> Bins {
+    previous
+        .iter()
+        .map(|&(memory_type, pages)| {
+            let used = used(memory_type);
+            (memory_type, if used > pages { used.saturating_add(used / 4) } else { pages })
+        })
+        .collect()
+}
+
+extern "efiapi" fn publish_event(event: efi::Event, _context: *mut c_void) {
+    let _ = EVENT_DB.close_event(event);
+    if let Some(feedback) = FEEDBACK.lock().as_ref() {
+        let table = GCD.memory_type_info_table();
+        let used =
+            |memory_type: efi::MemoryType| table.get(memory_type as usize).map_or(0, |info| info.number_of_pages);
+        publish(&feedback.runtime_services, &feedback.previous, used);
+    }
+}
+
+/// Writes the bins for the next boot to the `MemoryTypeInformation` variable, if they differ from this boot's.
+fn publish(runtime_services: &impl RuntimeServices, previous: &Bins, used: impl Fn(efi::MemoryType) -> u32) {
+    let next = next_bins(previous, used);
+    if next == *previous {
+        log::info!("Memory bins are large enough, not updating them.");
+        return;
+    }
+    for (&(memory_type, pages), &(_, next_pages)) in previous.iter().zip(next.iter()) {
+        if pages != next_pages {
+            log::info!("Growing the memory bin for type {memory_type:#x} from {pages:#x} to {next_pages:#x} pages.");
+        }
+    }
+
+    let name: Vec<u16> = VARIABLE_NAME.encode_utf16().chain([0]).collect();
+    let attributes = efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+    if let Err(status) =
+        runtime_services.set_variable(&name, &guids::MEMORY_TYPE_INFORMATION, attributes, &to_bytes(&next))
+    {
+        log::error!("Failed to publish the memory bins for the next boot: {status:#x?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use patina::runtime_services::MockRuntimeServices;
+
+    #[test]
+    fn test_overflowed_bins_grow_and_others_are_kept() {
+        let previous = Vec::from([(efi::RUNTIME_SERVICES_DATA, 0x100), (efi::ACPI_MEMORY_NVS, 0x40)]);
+        let used = |memory_type| if memory_type == efi::RUNTIME_SERVICES_DATA { 0x200 } else { 0x10 };
+
+        assert_eq!(next_bins(&previous, used), [(efi::RUNTIME_SERVICES_DATA, 0x280), (efi::ACPI_MEMORY_NVS, 0x40)]);
+    }
+
+    #[test]
+    fn test_bins_round_trip_through_the_variable_format() {
+        let bins = Vec::from([(efi::BOOT_SERVICES_CODE, 0x800), (efi::RESERVED_MEMORY_TYPE, 0)]);
+        let bytes = to_bytes(&bins);
+        assert_eq!(bytes.len(), 3 * 8);
+        assert_eq!(from_bytes(&bytes), bins);
+    }
+
+    #[test]
+    fn test_the_variable_is_only_written_when_a_bin_changes() {
+        let previous = Vec::from([(efi::RUNTIME_SERVICES_CODE, 0x20)]);
+
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services.expect_set_variable::<Vec<u8>>().never();
+        publish(&runtime_services, &previous, |_| 0x20);
+
+        let mut runtime_services = MockRuntimeServices::new();
+        runtime_services
+            .expect_set_variable::<Vec<u8>>()
+            .withf(|name, namespace, _, data| {
+                name.len() == VARIABLE_NAME.len() + 1
+                    && *namespace == guids::MEMORY_TYPE_INFORMATION
+                    && from_bytes(data) == [(efi::RUNTIME_SERVICES_CODE, 0x50)]
+            })
+            .once()
+            .returning(|_, _, _, _| Ok(()));
+        publish(&runtime_services, &previous, |_| 0x40);
+    }
+}
@@ -26,7 +26,7 @@ use r_efi::efi;
 
 use crate::{
     allocator::{core_allocate_pages, core_free_pages},
-    dxe_services,
+    dxe_services, proximity_domains,
 };
 
 /// Structure for wrapper rust allocator APIs.
@@ -45,7 +45,19 @@ impl MemoryManager for CoreMemoryManager {
         }
 
         let alloc_type = match options.strategy() {
-            PageAllocationStrategy::Any => efi::ALLOCATE_ANY_PAGES,
+            PageAllocationStrategy::Any => {
+                // the proximity domain is a hint, so fall back to any pages if the domain has no free memory.
+                match options
+                    .proximity_domain()
+                    .and_then(|domain| proximity_domains::find_free_pages(domain, page_count, alignment))
+                {
+                    Some(near_address) => {
+                        address = near_address;
+                        efi::ALLOCATE_ADDRESS
+                    }
+                    None => efi::ALLOCATE_ANY_PAGES,
+                }
+            }
             PageAllocationStrategy::Address(requested_address) => {
                 if requested_address % alignment != 0 {
                     return Err(MemoryError::UnalignedAddress);
@@ -190,6 +202,10 @@ impl MemoryManager for CoreMemoryManager {
             CachingType::from_efi_attributes(attributes).unwrap_or(CachingType::WriteBack),
         ))
     }
+
+    fn get_proximity_domain(&self, address: usize) -> Option<u32> {
+        proximity_domains::proximity_domain(address as u64)
+    }
 }
 
 fn allow_allocations_for_type(memory_type: EfiMemoryType) -> Result<(), MemoryError> {
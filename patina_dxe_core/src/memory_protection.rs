@@ -0,0 +1,166 @@
+//! Memory Protection Settings HOB
+//!
+//! Platforms built on Project Mu describe their DXE memory protection policy in PEI, by producing a
+//! `DXE_MEMORY_PROTECTION_SETTINGS` structure in a guided HOB. The core parses this HOB into the
+//! [MemoryProtectionPolicy] and [ImageProtectionPolicy] configs, so that existing platform PEI code keeps controlling
+//! the policy without a platform specific HOB parser. A policy found in the HOB replaces a policy registered with
+//! [with_config](crate::Core::with_config).
+//!
+//! The core always applies its own baseline protections (null pointer detection, stack guard, non-executable data), so
+//! a HOB cannot turn them off. Of the [ImageProtectionPolicy], the core enforces
+//! [block_images_without_nx](ImageProtectionPolicy::block_images_without_nx); both configs are available to components
+//! through [Config](patina::component::params::Config).
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use r_efi::efi;
+
+/// The GUID of the HOB holding the Project Mu `DXE_MEMORY_PROTECTION_SETTINGS`.
+///
+/// The HOB data is laid out as follows, with multi-byte values in little endian:
+///
+/// | Offset | Size | Field                                                                                           |
+/// |--------|------|-------------------------------------------------------------------------------------------------|
+/// | 0      | 1    | Structure version                                                                               |
+/// | 1      | 1    | CPU stack guard (boolean)                                                                       |
+/// | 2      | 1    | Stack execution protection (boolean)                                                            |
+/// | 3      | 1    | Null pointer detection: bit 0 enabled                                                           |
+/// | 4      | 1    | Heap guard: bit 0 page guard, bit 1 pool guard, bit 2 freed memory guard                        |
+/// | 5      | 1    | Image protection: bit 0 unknown source, bit 1 FV, bit 2 raise error, bit 3 block images w/o NX  |
+/// | 8      | 4    | Page guard memory types, one bit per EFI memory type                                            |
+/// | 12     | 4    | Pool guard memory types, one bit per EFI memory type                                            |
+/// | 16     | 4    | Non-executable memory types, one bit per EFI memory type                                        |
+pub const MEMORY_PROTECTION_SETTINGS_HOB_GUID: efi::Guid =
+    efi::Guid::from_fields(0x9abfd639, 0xd1d0, 0x4eff, 0xbd, 0xb6, &[0x7e, 0xc4, 0x19, 0x0d, 0x17, 0xd5]);
+
+const HOB_DATA_SIZE: usize = 20;
+
+/// The DXE memory protection policy of the platform.
+///
+/// Memory types are bitmasks with one bit per EFI memory type, e.g. `1 << efi::BOOT_SERVICES_DATA`.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, MemoryProtectionPolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(MemoryProtectionPolicy { freed_memory_guard: true, ..Default::default() })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryProtectionPolicy {
+    /// Guard pages are placed below the stacks of the processors.
+    pub cpu_stack_guard: bool,
+    /// The stacks are not executable.
+    pub stack_execution_protection: bool,
+    /// Page 0 is unmapped to catch null pointer dereferences.
+    pub null_pointer_detection: bool,
+    /// The memory types whose page allocations are surrounded by guard pages.
+    pub page_guard_memory_types: u32,
+    /// The memory types whose pool allocations are surrounded by guard pages.
+    pub pool_guard_memory_types: u32,
+    /// Freed pages are unmapped to catch use after free.
+    pub freed_memory_guard: bool,
+    /// The memory types that are not executable.
+    pub nx_memory_types: u32,
+}
+
+/// The protection policy for images loaded by the core.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ImageProtectionPolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ImageProtectionPolicy { block_images_without_nx: true, ..Default::default() })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImageProtectionPolicy {
+    /// Images loaded from an unknown source have their sections protected.
+    pub protect_images_from_unknown: bool,
+    /// Images loaded from a firmware volume have their sections protected.
+    pub protect_images_from_fv: bool,
+    /// Failing to protect an image fails the load.
+    pub raise_error_if_protection_fails: bool,
+    /// Images whose PE header does not advertise NX compatibility are not loaded.
+    pub block_images_without_nx: bool,
+}
+
+/// Parses the data of a memory protection settings HOB, returning `None` if it is too short.
+pub(crate) fn from_hob_data(data: &[u8]) -> Option<(MemoryProtectionPolicy, ImageProtectionPolicy)> {
+    let data = data.get(..HOB_DATA_SIZE)?;
+    let bit = |byte: u8, bit: u32| byte & (1 << bit) != 0;
+    let types = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let (heap_guard, image_protection) = (data[4], data[5]);
+
+    let memory = MemoryProtectionPolicy {
+        cpu_stack_guard: data[1] != 0,
+        stack_execution_protection: data[2] != 0,
+        null_pointer_detection: bit(data[3], 0),
+        page_guard_memory_types: if bit(heap_guard, 0) { types(8) } else { 0 },
+        pool_guard_memory_types: if bit(heap_guard, 1) { types(12) } else { 0 },
+        freed_memory_guard: bit(heap_guard, 2),
+        nx_memory_types: types(16),
+    };
+    let image = ImageProtectionPolicy {
+        protect_images_from_unknown: bit(image_protection, 0),
+        protect_images_from_fv: bit(image_protection, 1),
+        raise_error_if_protection_fails: bit(image_protection, 2),
+        block_images_without_nx: bit(image_protection, 3),
+    };
+    log::info!("Memory protection settings HOB version {}: {memory:x?} {image:?}", data[0]);
+    Some((memory, image))
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hob_data_is_parsed_into_policies() {
+        let mut data = [0u8; HOB_DATA_SIZE];
+        data[..6].copy_from_slice(&[7, 1, 0, 0b01, 0b110, 0b1010]);
+        data[8..12].copy_from_slice(&(1u32 << efi::BOOT_SERVICES_DATA).to_le_bytes());
+        data[12..16].copy_from_slice(&(1u32 << efi::RUNTIME_SERVICES_DATA).to_le_bytes());
+        data[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let (memory, image) = from_hob_data(&data).unwrap();
+        assert_eq!(
+            memory,
+            MemoryProtectionPolicy {
+                cpu_stack_guard: true,
+                stack_execution_protection: false,
+                null_pointer_detection: true,
+                // The page guard is disabled, so its memory types are ignored.
+                page_guard_memory_types: 0,
+                pool_guard_memory_types: 1 << efi::RUNTIME_SERVICES_DATA,
+                freed_memory_guard: true,
+                nx_memory_types: u32::MAX,
+            }
+        );
+        assert_eq!(
+            image,
+            ImageProtectionPolicy {
+                protect_images_from_unknown: false,
+                protect_images_from_fv: true,
+                raise_error_if_protection_fails: false,
+                block_images_without_nx: true,
+            }
+        );
+        assert_eq!(from_hob_data(&data[..HOB_DATA_SIZE - 1]), None);
+    }
+}
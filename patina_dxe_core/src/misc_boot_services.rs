@@ -9,20 +9,42 @@
 use core::{
     ffi::c_void,
     slice::from_raw_parts,
-    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
 };
+use mu_rust_helpers::perf_timer::{Arch, ArchFunctionality};
 use patina::guids;
 use patina_internal_cpu::interrupts;
 use patina_pi::{protocols, status_code};
 use r_efi::efi;
 
 use crate::{
-    GCD, allocator::terminate_memory_map, events::EVENT_DB, protocols::PROTOCOL_DB, systemtables::SYSTEM_TABLE,
+    GCD, allocator::terminate_memory_map, events::EVENT_DB, protocols::PROTOCOL_DB, stray_boot_services,
+    systemtables::SYSTEM_TABLE, tpl_lock,
 };
 
 static METRONOME_ARCH_PTR: AtomicPtr<protocols::metronome::Protocol> = AtomicPtr::new(core::ptr::null_mut());
 static WATCHDOG_ARCH_PTR: AtomicPtr<protocols::watchdog::Protocol> = AtomicPtr::new(core::ptr::null_mut());
 
+// Whether the performance counter runs at a constant rate, so that Stall() can wait on it once its frequency is known.
+static STALL_COUNTER_INVARIANT: AtomicBool = AtomicBool::new(false);
+// The frequency, in Hz, of the performance counter that Stall() waits on, or zero if Stall() waits on the Metronome.
+static STALL_COUNTER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+// The platform monotonic count. The high 32 bits are persisted by the Monotonic Counter Architectural Protocol driver
+// once it is installed; the low 32 bits start at zero on every boot.
+static MONOTONIC_COUNT: AtomicU64 = AtomicU64::new(0);
+// Whether GetNextHighMonotonicCount() is available to persist the high 32 bits of the monotonic count.
+static MONOTONIC_COUNTER_ARCH_AVAILABLE: AtomicBool = AtomicBool::new(false);
+
+// Counters slower than this cannot time microsecond stalls.
+const MIN_STALL_COUNTER_FREQUENCY: u64 = 1_000_000;
+// The frequency perf_timer reports on x64 when CPUID does not describe the TSC; it is not the TSC frequency.
+#[cfg(target_arch = "x86_64")]
+const ACPI_TIMER_FREQUENCY: u64 = 3_579_545;
+// The deviation from the Metronome, in percent, above which the counter frequency is replaced by the measured one.
+const STALL_CALIBRATION_TOLERANCE_PERCENT: u64 = 5;
+// The shortest time, in 100ns units, that the counter is measured against the Metronome for.
+const STALL_CALIBRATION_PERIOD: u64 = 10_000;
+
 // TODO [BEGIN]: LOCAL (TEMP) GUID DEFINITIONS (MOVE LATER)
 
 // These will likely get moved to different places. DXE Core GUID is the GUID of this DXE Core instance.
@@ -41,15 +63,183 @@ extern "efiapi" fn calculate_crc32(data: *mut c_void, data_size: usize, crc_32:
     // Safety: caller must ensure that data and crc_32 are valid pointers. They are null-checked above.
     unsafe {
         let buffer = from_raw_parts(data as *mut u8, data_size);
-        crc_32.write_unaligned(crc32fast::hash(buffer));
+        crc_32.write_unaligned(crate::crc32::hash(buffer));
+    }
+
+    efi::Status::SUCCESS
+}
+
+// Advances `counter` and returns its previous value, along with whether the low 32 bits wrapped into the high 32
+// bits. Once the counter is exhausted it stays at its maximum and DEVICE_ERROR is returned.
+fn advance_monotonic_count(counter: &AtomicU64) -> Result<(u64, bool), efi::Status> {
+    let count = counter
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| count.checked_add(1))
+        .map_err(|_| efi::Status::DEVICE_ERROR)?;
+    Ok((count, count as u32 == u32::MAX))
+}
+
+// Raises the high 32 bits of `counter` to `high_count`, keeping the low 32 bits. The counter never moves backwards.
+fn sync_monotonic_high_count(counter: &AtomicU64, high_count: u32) {
+    _ = counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+        let synced = ((high_count as u64) << 32) | (count & u32::MAX as u64);
+        (synced > count).then_some(synced)
+    });
+}
+
+// Returns the next high 32 bits of the monotonic count from the Runtime Service, which persists them, or None if the
+// Monotonic Counter Architectural Protocol has not been installed yet.
+fn next_persisted_high_count() -> Option<u32> {
+    if !MONOTONIC_COUNTER_ARCH_AVAILABLE.load(Ordering::SeqCst) {
+        return None;
+    }
+    // the lock is released before calling into the runtime service.
+    let get_next_high_mono_count = SYSTEM_TABLE.lock().as_ref()?.runtime_services().get_next_high_mono_count;
+    let mut high_count = 0;
+    match get_next_high_mono_count(&mut high_count) {
+        efi::Status::SUCCESS => Some(high_count),
+        status => {
+            log::warn!("GetNextHighMonotonicCount() failed with status {status:?}");
+            None
+        }
+    }
+}
+
+// Returns a monotonically increasing count for the platform.
+extern "efiapi" fn get_next_monotonic_count(count: *mut u64) -> efi::Status {
+    if count.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+
+    let (next_count, wrapped) = match advance_monotonic_count(&MONOTONIC_COUNT) {
+        Ok(result) => result,
+        Err(status) => return status,
+    };
+    if wrapped {
+        // the low 32 bits wrapped, so the persisted high 32 bits must advance as well.
+        if let Some(high_count) = next_persisted_high_count() {
+            sync_monotonic_high_count(&MONOTONIC_COUNT, high_count);
+        }
     }
 
+    // Safety: count is non-null and the caller guarantees it is valid for writes.
+    unsafe { count.write_unaligned(next_count) };
     efi::Status::SUCCESS
 }
 
+// Returns whether the performance counter runs at a constant rate regardless of processor power states.
+fn stall_counter_is_invariant() -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            use core::arch::x86_64::__cpuid;
+            // Safety: CPUID is available on all x64 processors. Leaf 0x80000007 is only read if it is implemented.
+            let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+            // Invariant TSC is reported in bit 8 of EDX.
+            max_extended_leaf >= 0x8000_0007 && (unsafe { __cpuid(0x8000_0007) }.edx & (1 << 8)) != 0
+        } else {
+            // the generic timer counts at a constant frequency by definition.
+            true
+        }
+    }
+}
+
+/// Selects the time source for Stall().
+///
+/// Stall() waits on the invariant performance counter (the TSC on x64, the generic timer on AArch64) when its frequency
+/// is known, and on the Metronome Architectural Protocol otherwise. The frequency is checked against the Metronome when
+/// it is installed.
+pub(crate) fn calibrate_stall_counter() {
+    if !stall_counter_is_invariant() {
+        log::info!("Stall() uses the Metronome; the performance counter is not invariant.");
+        return;
+    }
+    STALL_COUNTER_INVARIANT.store(true, Ordering::SeqCst);
+
+    let frequency = Arch::perf_frequency();
+    #[cfg(target_arch = "x86_64")]
+    let frequency = if frequency == ACPI_TIMER_FREQUENCY { 0 } else { frequency };
+    if frequency < MIN_STALL_COUNTER_FREQUENCY {
+        log::warn!("Performance counter frequency is unknown; Stall() is calibrated when the Metronome is installed.");
+        return;
+    }
+    STALL_COUNTER_FREQUENCY.store(frequency, Ordering::SeqCst);
+    log::info!("Stall() uses the performance counter at {frequency} Hz.");
+}
+
+// Returns the counter frequency to use, given that the counter advanced `counts` while the Metronome measured
+// `elapsed` in 100ns units, or None if the current frequency is within tolerance of the measured one.
+fn calibrated_frequency(current_frequency: u64, counts: u64, elapsed: u64) -> Option<u64> {
+    let measured_frequency = ((counts as u128 * 10_000_000) / elapsed.max(1) as u128) as u64;
+    if measured_frequency < MIN_STALL_COUNTER_FREQUENCY {
+        log::warn!("Performance counter measured at {measured_frequency} Hz; it is too slow to use for Stall().");
+        return None;
+    }
+    if current_frequency == 0 {
+        log::info!("Stall() uses the performance counter at {measured_frequency} Hz, measured against the Metronome.");
+        return Some(measured_frequency);
+    }
+    let deviation = measured_frequency.abs_diff(current_frequency) * 100 / current_frequency;
+    if deviation > STALL_CALIBRATION_TOLERANCE_PERCENT {
+        log::warn!(
+            "Performance counter frequency {current_frequency} Hz is {deviation}% off the {measured_frequency} Hz measured against the Metronome. Using the measured frequency for Stall()."
+        );
+        return Some(measured_frequency);
+    }
+    log::info!("Performance counter frequency {current_frequency} Hz is within {deviation}% of the Metronome.");
+    None
+}
+
+// Measures the performance counter against the Metronome and corrects the frequency that Stall() uses if needed.
+fn check_stall_calibration(metronome_ptr: *mut protocols::metronome::Protocol) {
+    if !STALL_COUNTER_INVARIANT.load(Ordering::SeqCst) {
+        return;
+    }
+    // Safety: the caller passes the installed Metronome Architectural Protocol interface.
+    let Some(metronome) = (unsafe { metronome_ptr.as_ref() }) else {
+        return;
+    };
+    if metronome.tick_period == 0 {
+        return;
+    }
+
+    // wait for at least one tick first, so that the measurement starts on a tick boundary.
+    let ticks = STALL_CALIBRATION_PERIOD.div_ceil(metronome.tick_period as u64).max(10);
+    if (metronome.wait_for_tick)(metronome_ptr, 1).is_error() {
+        return;
+    }
+    let start = Arch::cpu_count();
+    if (metronome.wait_for_tick)(metronome_ptr, ticks as u32).is_error() {
+        return;
+    }
+    let counts = Arch::cpu_count().wrapping_sub(start);
+
+    let current_frequency = STALL_COUNTER_FREQUENCY.load(Ordering::SeqCst);
+    if let Some(frequency) = calibrated_frequency(current_frequency, counts, ticks * metronome.tick_period as u64) {
+        STALL_COUNTER_FREQUENCY.store(frequency, Ordering::SeqCst);
+    }
+}
+
+// Returns the number of counts of a counter running at `frequency` Hz in `microseconds`, rounded up.
+fn stall_counts(microseconds: usize, frequency: u64) -> u64 {
+    (microseconds as u128 * frequency as u128).div_ceil(1_000_000).min(u64::MAX as u128) as u64
+}
+
+// Spins until `counter` has advanced by at least `counts`, allowing for it to wrap.
+fn wait_for_counts(counts: u64, counter: impl Fn() -> u64) {
+    let start = counter();
+    while counter().wrapping_sub(start) < counts {
+        core::hint::spin_loop();
+    }
+}
+
 // Induces a fine-grained stall. Stalls execution on the processor for at least the requested number of microseconds.
 // Execution of the processor is not yielded for the duration of the stall.
 extern "efiapi" fn stall(microseconds: usize) -> efi::Status {
+    let frequency = STALL_COUNTER_FREQUENCY.load(Ordering::SeqCst);
+    if frequency != 0 {
+        wait_for_counts(stall_counts(microseconds, frequency), Arch::cpu_count);
+        return efi::Status::SUCCESS;
+    }
+
     let metronome_ptr = METRONOME_ARCH_PTR.load(Ordering::SeqCst);
     if let Some(metronome) = unsafe { metronome_ptr.as_mut() } {
         let ticks_100ns: u128 = (microseconds as u128) * 10;
@@ -109,6 +299,7 @@ extern "efiapi" fn metronome_arch_available(event: efi::Event, _context: *mut c_
     match PROTOCOL_DB.locate_protocol(protocols::metronome::PROTOCOL_GUID) {
         Ok(metronome_arch_ptr) => {
             METRONOME_ARCH_PTR.store(metronome_arch_ptr as *mut protocols::metronome::Protocol, Ordering::SeqCst);
+            check_stall_calibration(metronome_arch_ptr as *mut protocols::metronome::Protocol);
             if let Err(status_err) = EVENT_DB.close_event(event) {
                 log::warn!("Could not close event for metronome_arch_available due to error {status_err:?}");
             }
@@ -118,6 +309,22 @@ extern "efiapi" fn metronome_arch_available(event: efi::Event, _context: *mut c_
 }
 // Requires excessive Mocking for the OK case.
 #[coverage(off)]
+// This callback is invoked when the Monotonic Counter Architectural protocol is installed. It raises the high 32 bits
+// of the monotonic count to the persisted value, so that the count keeps increasing across boots.
+extern "efiapi" fn monotonic_counter_arch_available(event: efi::Event, _context: *mut c_void) {
+    if PROTOCOL_DB.locate_protocol(protocols::monotonic_counter::PROTOCOL_GUID).is_err() {
+        return;
+    }
+    MONOTONIC_COUNTER_ARCH_AVAILABLE.store(true, Ordering::SeqCst);
+    if let Some(high_count) = next_persisted_high_count() {
+        sync_monotonic_high_count(&MONOTONIC_COUNT, high_count);
+    }
+    if let Err(status_err) = EVENT_DB.close_event(event) {
+        log::warn!("Could not close event for monotonic_counter_arch_available due to error {status_err:?}");
+    }
+}
+// Requires excessive Mocking for the OK case.
+#[coverage(off)]
 // This callback is invoked when the Watchdog Timer Architectural protocol is installed. It initializes the
 // WATCHDOG_ARCH_PTR to point to the Watchdog Timer Architectural protocol interface.
 extern "efiapi" fn watchdog_arch_available(event: efi::Event, _context: *mut c_void) {
@@ -193,12 +400,22 @@ pub extern "efiapi" fn exit_boot_services(_handle: efi::Handle, map_key: usize)
     // Disable CPU interrupts
     interrupts::disable_interrupts();
 
-    // Clear non-runtime services from the EFI System Table
-    SYSTEM_TABLE
-        .lock()
-        .as_mut()
-        .expect("The System Table pointer is null. This is invalid.")
-        .clear_boot_time_services();
+    // Record the running image for stray boot services calls while the image data, in boot services memory, is intact.
+    stray_boot_services::record_exit_boot_services(crate::allocator::zeroes_boot_services_at_exit());
+
+    // TPL locks stop calling RaiseTPL, as the boot services table is invalidated below.
+    tpl_lock::init_boot_services(core::ptr::null_mut());
+
+    // Invalidate the boot services, and clear non-runtime services from the EFI System Table
+    let mut st_guard = SYSTEM_TABLE.lock();
+    let system_table = st_guard.as_mut().expect("The System Table pointer is null. This is invalid.");
+    let boot_services_table = system_table.boot_services() as *const efi::BootServices as efi::PhysicalAddress;
+    let configuration_tables =
+        (system_table.system_table().configuration_table, system_table.system_table().number_of_table_entries);
+    stray_boot_services::invalidate_boot_services(system_table.boot_services_mut());
+    system_table.checksum_boot_services();
+    system_table.clear_boot_time_services();
+    drop(st_guard);
 
     match PROTOCOL_DB.locate_protocol(protocols::runtime::PROTOCOL_GUID) {
         Ok(rt_arch_ptr) => {
@@ -212,6 +429,18 @@ pub extern "efiapi" fn exit_boot_services(_handle: efi::Handle, map_key: usize)
     crate::runtime::finalize_runtime_support();
     log::info!("EBS completed successfully.");
 
+    // Zeroing must come last, as the core no longer uses boot services memory other than what is kept here.
+    let (tables, table_count) = configuration_tables;
+    let tables = match tables.is_null() {
+        true => &[][..],
+        // Safety: the configuration table pointer and entry count are maintained by core_install_configuration_table.
+        false => unsafe { core::slice::from_raw_parts(tables, table_count) },
+    };
+    crate::allocator::zero_boot_services_memory(
+        core::iter::once(boot_services_table)
+            .chain(tables.iter().map(|table| table.vendor_table as efi::PhysicalAddress)),
+    );
+
     efi::Status::SUCCESS
 }
 
@@ -220,6 +449,7 @@ pub fn init_misc_boot_services_support(bs: &mut efi::BootServices) {
     bs.exit_boot_services = exit_boot_services;
     bs.stall = stall;
     bs.set_watchdog_timer = set_watchdog_timer;
+    bs.get_next_monotonic_count = get_next_monotonic_count;
 
     //set up call back for metronome arch protocol installation.
     let event = EVENT_DB
@@ -230,6 +460,15 @@ pub fn init_misc_boot_services_support(bs: &mut efi::BootServices) {
         .register_protocol_notify(protocols::metronome::PROTOCOL_GUID, event)
         .expect("Failed to register protocol notify on metronome available.");
 
+    //set up call back for monotonic counter arch protocol installation.
+    let event = EVENT_DB
+        .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(monotonic_counter_arch_available), None, None)
+        .expect("Failed to create monotonic counter available callback.");
+
+    PROTOCOL_DB
+        .register_protocol_notify(protocols::monotonic_counter::PROTOCOL_GUID, event)
+        .expect("Failed to register protocol notify on monotonic counter available.");
+
     //set up call back for watchdog arch protocol installation.
     let event = EVENT_DB
         .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(watchdog_arch_available), None, None)
@@ -436,6 +675,136 @@ mod tests {
         .expect("Unexpected Error in test_misc_stall");
     }
 
+    #[test]
+    fn test_stall_counts_round_up() {
+        assert_eq!(stall_counts(0, 24_000_000), 0);
+        assert_eq!(stall_counts(1, 24_000_000), 24);
+        assert_eq!(stall_counts(1, 1_500_000), 2);
+        assert_eq!(stall_counts(1000, 3_000_000_000), 3_000_000);
+        assert_eq!(stall_counts(usize::MAX, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_wait_for_counts_handles_counter_wrap() {
+        use core::cell::Cell;
+
+        let counter = Cell::new(u64::MAX - 20);
+        let read_counter = || {
+            counter.set(counter.get().wrapping_add(7));
+            counter.get()
+        };
+        wait_for_counts(50, read_counter);
+        // the counter wrapped and advanced at least 50 counts past the first read.
+        let advanced = counter.get().wrapping_sub(u64::MAX - 13);
+        assert!((50..57).contains(&advanced), "advanced {advanced}");
+    }
+
+    #[test]
+    fn test_calibrated_frequency() {
+        // without a frequency, the measured frequency is used.
+        assert_eq!(calibrated_frequency(0, 24_000, 10_000), Some(24_000_000));
+        // a frequency within tolerance of the measurement is kept.
+        assert_eq!(calibrated_frequency(24_000_000, 24_500, 10_000), None);
+        // a frequency that is off by more than the tolerance is replaced.
+        assert_eq!(calibrated_frequency(3_579_545, 2_000_000, 10_000), Some(2_000_000_000));
+        // a measurement that is too slow to time stalls is ignored.
+        assert_eq!(calibrated_frequency(0, 500, 10_000), None);
+        assert_eq!(calibrated_frequency(24_000_000, 0, 10_000), None);
+    }
+
+    #[test]
+    fn test_stall_uses_calibrated_counter() {
+        test_support::with_global_lock(|| {
+            METRONOME_ARCH_PTR.store(ptr::null_mut(), Ordering::SeqCst);
+
+            // without a calibrated counter or a metronome, stall is not available.
+            STALL_COUNTER_FREQUENCY.store(0, Ordering::SeqCst);
+            assert_eq!(stall(10), efi::Status::NOT_READY);
+
+            // with a calibrated counter, stall waits on the counter.
+            STALL_COUNTER_FREQUENCY.store(MIN_STALL_COUNTER_FREQUENCY, Ordering::SeqCst);
+            assert_eq!(stall(0), efi::Status::SUCCESS);
+            assert_eq!(stall(10), efi::Status::SUCCESS);
+
+            STALL_COUNTER_FREQUENCY.store(0, Ordering::SeqCst);
+        })
+        .expect("Unexpected Error in test_stall_uses_calibrated_counter");
+    }
+
+    #[test]
+    fn test_advance_monotonic_count() {
+        let counter = AtomicU64::new(0);
+        assert_eq!(advance_monotonic_count(&counter), Ok((0, false)));
+        assert_eq!(advance_monotonic_count(&counter), Ok((1, false)));
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+        // the low 32 bits wrap into the high 32 bits.
+        let counter = AtomicU64::new(u32::MAX as u64 - 1);
+        assert_eq!(advance_monotonic_count(&counter), Ok((u32::MAX as u64 - 1, false)));
+        assert_eq!(advance_monotonic_count(&counter), Ok((u32::MAX as u64, true)));
+        assert_eq!(advance_monotonic_count(&counter), Ok((1 << 32, false)));
+
+        // wrapping in a later high word is reported as well.
+        let counter = AtomicU64::new((7 << 32) | u32::MAX as u64);
+        assert_eq!(advance_monotonic_count(&counter), Ok(((7 << 32) | u32::MAX as u64, true)));
+        assert_eq!(counter.load(Ordering::SeqCst), 8 << 32);
+    }
+
+    #[test]
+    fn test_advance_monotonic_count_exhausted() {
+        let counter = AtomicU64::new(u64::MAX - 1);
+        assert_eq!(advance_monotonic_count(&counter), Ok((u64::MAX - 1, false)));
+        assert_eq!(advance_monotonic_count(&counter), Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(advance_monotonic_count(&counter), Err(efi::Status::DEVICE_ERROR));
+        assert_eq!(counter.load(Ordering::SeqCst), u64::MAX);
+    }
+
+    #[test]
+    fn test_sync_monotonic_high_count() {
+        // the persisted high count is applied, keeping the low 32 bits.
+        let counter = AtomicU64::new(0x1234);
+        sync_monotonic_high_count(&counter, 5);
+        assert_eq!(counter.load(Ordering::SeqCst), (5 << 32) | 0x1234);
+
+        // the same high count leaves the counter as is.
+        sync_monotonic_high_count(&counter, 5);
+        assert_eq!(counter.load(Ordering::SeqCst), (5 << 32) | 0x1234);
+
+        // a lower high count, e.g. after the persisted value was reset, never moves the counter backwards.
+        sync_monotonic_high_count(&counter, 2);
+        assert_eq!(counter.load(Ordering::SeqCst), (5 << 32) | 0x1234);
+    }
+
+    #[test]
+    fn test_get_next_monotonic_count() {
+        test_support::with_global_lock(|| {
+            assert_eq!(get_next_monotonic_count(ptr::null_mut()), efi::Status::INVALID_PARAMETER);
+
+            let mut first = 0;
+            let mut second = 0;
+            assert_eq!(get_next_monotonic_count(&mut first), efi::Status::SUCCESS);
+            assert_eq!(get_next_monotonic_count(&mut second), efi::Status::SUCCESS);
+            assert!(second > first);
+
+            // without the monotonic counter arch protocol the high 32 bits advance in memory on wrap.
+            let saved = MONOTONIC_COUNT.swap(u32::MAX as u64, Ordering::SeqCst);
+            let mut count = 0;
+            assert_eq!(get_next_monotonic_count(&mut count), efi::Status::SUCCESS);
+            assert_eq!(count, u32::MAX as u64);
+            assert_eq!(get_next_monotonic_count(&mut count), efi::Status::SUCCESS);
+            assert_eq!(count, 1 << 32);
+
+            // an exhausted counter reports an error and leaves the output untouched.
+            MONOTONIC_COUNT.store(u64::MAX, Ordering::SeqCst);
+            let mut count = 0;
+            assert_eq!(get_next_monotonic_count(&mut count), efi::Status::DEVICE_ERROR);
+            assert_eq!(count, 0);
+
+            MONOTONIC_COUNT.store(saved, Ordering::SeqCst);
+        })
+        .expect("Unexpected Error in test_get_next_monotonic_count");
+    }
+
     #[test]
     fn test_misc_exit_boot_services() {
         test_support::with_global_lock(|| {
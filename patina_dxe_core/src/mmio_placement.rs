@@ -0,0 +1,151 @@
+//! MMIO Placement
+//!
+//! PCI host bridge drivers allocate BARs from the MMIO apertures of their root bridges with AllocateMemorySpace(). A
+//! bottom-up search places each BAR in the lowest aperture that fits, which fills the scarce space below 4GB with
+//! 64-bit BARs, and leaves no room for the large (and, with resizable BARs, very large) BARs of modern GPUs and
+//! accelerators. Platforms can register [MmioPlacement::PreferAbove4Gb] so that bottom-up searches without a maximum
+//! address are placed above 4GB whenever an aperture there fits, keeping the space below 4GB for devices that need
+//! it.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+use patina::{
+    base::{SIZE_4GB, align_up},
+    error::EfiError,
+};
+use patina_pi::dxe_services::{self, GcdMemoryType};
+use r_efi::efi;
+
+use crate::{GCD, gcd::AllocateType};
+
+/// Where the core places MMIO allocations that do not request an address or a maximum address.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, MmioPlacement};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(MmioPlacement::PreferAbove4Gb)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MmioPlacement {
+    /// Allocations are placed as requested. This is the default.
+    #[default]
+    AsRequested,
+    /// Bottom-up searches are placed in the lowest MMIO above 4GB that fits, and only fall back to MMIO below 4GB if
+    /// none does.
+    PreferAbove4Gb,
+}
+
+static PREFER_ABOVE_4GB: AtomicBool = AtomicBool::new(false);
+
+/// Sets where later MMIO allocations are placed.
+pub(crate) fn set_mmio_placement(placement: &MmioPlacement) {
+    log::info!("MMIO placement: {placement:?}");
+    PREFER_ABOVE_4GB.store(*placement == MmioPlacement::PreferAbove4Gb, Ordering::SeqCst);
+}
+
+/// Allocates memory space from the GCD as [SpinLockedGcd::allocate_memory_space](crate::gcd::SpinLockedGcd) does,
+/// applying the [MmioPlacement] to MMIO allocations.
+pub(crate) fn allocate_memory_space(
+    allocate_type: AllocateType,
+    memory_type: GcdMemoryType,
+    align_shift: usize,
+    len: usize,
+    image_handle: efi::Handle,
+    device_handle: Option<efi::Handle>,
+) -> Result<usize, EfiError> {
+    if memory_type == GcdMemoryType::MemoryMappedIo
+        && matches!(allocate_type, AllocateType::BottomUp(None))
+        && PREFER_ABOVE_4GB.load(Ordering::SeqCst)
+        && let Some(address) = find_above_4gb(align_shift, len)
+    {
+        match GCD.allocate_memory_space(
+            AllocateType::Address(address),
+            memory_type,
+            align_shift,
+            len,
+            image_handle,
+            device_handle,
+        ) {
+            Ok(address) => return Ok(address),
+            Err(err) => log::warn!("Failed to allocate MMIO at {address:#x} above 4GB, searching below: {err:?}"),
+        }
+    }
+    GCD.allocate_memory_space(allocate_type, memory_type, align_shift, len, image_handle, device_handle)
+}
+
+// Returns the lowest address above 4GB at which `len` bytes of unallocated MMIO aligned to `2^align_shift` fit.
+fn find_above_4gb(align_shift: usize, len: usize) -> Option<usize> {
+    if len == 0 || align_shift >= usize::BITS as usize {
+        return None;
+    }
+    let mut descriptors: Vec<dxe_services::MemorySpaceDescriptor> = Vec::with_capacity(GCD.memory_descriptor_count());
+    GCD.get_memory_descriptors(&mut descriptors).ok()?;
+    descriptors
+        .iter()
+        .filter(|descriptor| {
+            descriptor.memory_type == GcdMemoryType::MemoryMappedIo && descriptor.image_handle.is_null()
+        })
+        .find_map(|descriptor| {
+            let end = descriptor.base_address.checked_add(descriptor.length)? as usize;
+            let start = align_up((descriptor.base_address as usize).max(SIZE_4GB), 1 << align_shift).ok()?;
+            (start.checked_add(len)? <= end).then_some(start)
+        })
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::{protocol_db, test_support};
+
+    #[test]
+    fn test_mmio_is_placed_above_4gb() {
+        test_support::with_global_lock(|| {
+            unsafe { test_support::init_test_gcd(None) };
+            let capabilities = efi::MEMORY_UC | efi::MEMORY_XP;
+            unsafe {
+                GCD.add_memory_space(GcdMemoryType::MemoryMappedIo, 0xC000_0000, 0x1000_0000, capabilities).unwrap();
+                GCD.add_memory_space(GcdMemoryType::MemoryMappedIo, 0x1_0000_0000, 0x1000_0000, capabilities).unwrap();
+            }
+            let allocate = |allocate_type, align_shift, len| {
+                allocate_memory_space(
+                    allocate_type,
+                    GcdMemoryType::MemoryMappedIo,
+                    align_shift,
+                    len,
+                    protocol_db::DXE_CORE_HANDLE,
+                    None,
+                )
+            };
+
+            // by default, bottom-up searches take the lowest aperture.
+            set_mmio_placement(&MmioPlacement::AsRequested);
+            assert_eq!(allocate(AllocateType::BottomUp(None), 12, 0x1000), Ok(0xC000_0000));
+
+            set_mmio_placement(&MmioPlacement::PreferAbove4Gb);
+            assert_eq!(allocate(AllocateType::BottomUp(None), 12, 0x1000), Ok(0x1_0000_0000));
+            // large alignments, as needed for resized BARs, are honored.
+            assert_eq!(allocate(AllocateType::BottomUp(None), 27, 0x800_0000), Ok(0x1_0800_0000));
+            // a maximum address is still honored.
+            assert_eq!(allocate(AllocateType::BottomUp(Some(0xFFFF_FFFF)), 12, 0x1000), Ok(0xC000_1000));
+            // allocations that do not fit above 4GB fall back to MMIO below 4GB.
+            assert_eq!(allocate(AllocateType::BottomUp(None), 12, 0x800_0000), Ok(0xC000_2000));
+            set_mmio_placement(&MmioPlacement::AsRequested);
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Panic Policy
+//!
+//! The panic handler is provided by the platform binary, so what happens when the core panics, or hits an
+//! unrecoverable exception (which the exception handlers report as a panic), has depended on whatever that handler
+//! happens to do. Platforms can call [handle_panic] from their `#[panic_handler]` and register a [PanicPolicy] to
+//! choose the outcome instead:
+//!
+//! - [PanicPolicy::DeadLoop] stops in place so that a debugger can be attached. This is the default.
+//! - [PanicPolicy::WarmReset] resets the system through the Reset architectural protocol.
+//! - [PanicPolicy::DumpAndColdReset] logs a crash dump of the running image and the protocol database, then cold
+//!   resets the system.
+//!
+//! If the debugger is enabled, it is broken into before the policy is applied. If the Reset architectural protocol is
+//! not yet installed, the system is reset through PSCI on AArch64, and the core dead loops elsewhere. If the panic
+//! handler is re-entered, the core dead loops regardless of the policy.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::{
+    ffi::c_void,
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use r_efi::efi;
+
+use crate::{
+    events::EVENT_DB, guid_names, image::core_current_image_name, protocols::PROTOCOL_DB, systemtables::SYSTEM_TABLE,
+};
+
+/// What the core does when it panics or hits an unrecoverable exception.
+///
+/// The policy is applied by [handle_panic], which the platform calls from its `#[panic_handler]`.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, PanicPolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(PanicPolicy::DumpAndColdReset)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PanicPolicy {
+    /// Stop in place, so that a debugger can be attached.
+    #[default]
+    DeadLoop,
+    /// Warm reset the system.
+    WarmReset,
+    /// Log a crash dump, then cold reset the system.
+    DumpAndColdReset,
+}
+
+impl PanicPolicy {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => PanicPolicy::WarmReset,
+            2 => PanicPolicy::DumpAndColdReset,
+            _ => PanicPolicy::DeadLoop,
+        }
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(PanicPolicy::DeadLoop as u8);
+static RESET_ARCH_AVAILABLE: AtomicBool = AtomicBool::new(false);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+fn panic_policy() -> PanicPolicy {
+    PanicPolicy::from_u8(POLICY.load(Ordering::SeqCst))
+}
+
+/// Sets the policy applied by [handle_panic], and tracks the Reset architectural protocol if the policy resets.
+pub(crate) fn set_panic_policy(policy: &PanicPolicy) {
+    POLICY.store(*policy as u8, Ordering::SeqCst);
+    if *policy == PanicPolicy::DeadLoop {
+        return;
+    }
+
+    log::info!("Panic policy: {policy:?}");
+    let event = match EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(reset_arch_available),
+        None,
+        None,
+    ) {
+        Ok(event) => event,
+        Err(status) => {
+            log::error!("Failed to create the reset arch available callback for the panic policy: {status:?}");
+            return;
+        }
+    };
+    if let Err(status) = PROTOCOL_DB.register_protocol_notify(guid_names::RESET_ARCH_PROTOCOL, event) {
+        log::error!("Failed to register protocol notify on reset arch for the panic policy: {status:?}");
+    }
+}
+
+// Requires excessive Mocking for the OK case.
+#[coverage(off)]
+// This callback is invoked when the Reset Architectural protocol is installed, after which ResetSystem() in the
+// runtime services table can be used to reset the system on panic.
+extern "efiapi" fn reset_arch_available(event: efi::Event, _context: *mut c_void) {
+    if PROTOCOL_DB.locate_protocol(guid_names::RESET_ARCH_PROTOCOL).is_err() {
+        return;
+    }
+    RESET_ARCH_AVAILABLE.store(true, Ordering::SeqCst);
+    if let Err(status_err) = EVENT_DB.close_event(event) {
+        log::warn!("Could not close event for reset_arch_available due to error {status_err:?}");
+    }
+}
+
+/// Handles a panic according to the registered [PanicPolicy].
+///
+/// Platforms should call this from their `#[panic_handler]`:
+///
+/// ```rust,ignore
+/// #[panic_handler]
+/// fn panic(info: &core::panic::PanicInfo) -> ! {
+///     patina_dxe_core::handle_panic(info)
+/// }
+/// ```
+#[coverage(off)]
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    log::error!("{info}");
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        log::error!("Panicked while handling a panic.");
+        dead_loop();
+    }
+
+    if patina_debugger::enabled() {
+        patina_debugger::breakpoint();
+    }
+
+    match panic_policy() {
+        PanicPolicy::DeadLoop => dead_loop(),
+        PanicPolicy::WarmReset => reset(efi::RESET_WARM),
+        PanicPolicy::DumpAndColdReset => {
+            dump();
+            reset(efi::RESET_COLD)
+        }
+    }
+}
+
+// Logs the state that is most useful to analyze the failure. Locks are only tried, as the panic may have happened
+// while they were held.
+#[coverage(off)]
+fn dump() {
+    log::error!("==== Crash dump ====");
+    match core_current_image_name() {
+        Some(name) => log::error!("Running image: {name}"),
+        None => log::error!("Running image: DXE Core"),
+    }
+
+    log::error!("Protocol database:");
+    let result = PROTOCOL_DB.try_for_each_protocol(|handle, protocol, interface, usage| {
+        log::error!("{handle:p}: {} @ {interface:p}", guid_names::named(&protocol));
+        for entry in usage {
+            log::error!(
+                "    opened by {:?} for {:?} with attributes {:#x}, count {}",
+                entry.agent_handle,
+                entry.controller_handle,
+                entry.attributes,
+                entry.open_count
+            );
+        }
+    });
+    if let Err(err) = result {
+        log::error!("Protocol database is locked: {err:?}");
+    }
+    log::error!("==== End of crash dump ====");
+}
+
+#[coverage(off)]
+fn reset(reset_type: efi::ResetType) -> ! {
+    if !RESET_ARCH_AVAILABLE.load(Ordering::SeqCst) {
+        #[cfg(all(target_os = "uefi", target_arch = "aarch64"))]
+        {
+            log::error!("Reset architectural protocol is not available, resetting through PSCI.");
+            let err = crate::psci::reset_system(reset_type);
+            log::error!("PSCI reset failed: {err:?}");
+        }
+        log::error!("Reset architectural protocol is not available, unable to reset.");
+        dead_loop();
+    }
+    let Some(reset_system) =
+        SYSTEM_TABLE.try_lock().and_then(|table| table.as_ref().map(|table| table.runtime_services().reset_system))
+    else {
+        log::error!("System table is not available, unable to reset.");
+        dead_loop();
+    };
+
+    log::error!("Resetting the system.");
+    reset_system(reset_type, efi::Status::ABORTED, 0, ptr::null_mut());
+    log::error!("ResetSystem() returned.");
+    dead_loop();
+}
+
+#[coverage(off)]
+fn dead_loop() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_panic_policy_round_trip() {
+        test_support::with_global_lock(|| {
+            for policy in [PanicPolicy::WarmReset, PanicPolicy::DumpAndColdReset, PanicPolicy::DeadLoop] {
+                POLICY.store(policy as u8, Ordering::SeqCst);
+                assert_eq!(panic_policy(), policy);
+            }
+            assert_eq!(PanicPolicy::from_u8(0xFF), PanicPolicy::DeadLoop);
+        })
+        .unwrap();
+    }
+}
@@ -13,6 +13,7 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
+use r_efi::efi;
 use scroll::{LE, Pread, Pwrite};
 
 pub mod error;
@@ -20,7 +21,10 @@ pub mod relocation;
 mod resource_directory;
 
 #[allow(unused_imports)]
-pub use goblin::pe::section_table::IMAGE_SCN_CNT_CODE;
+pub use goblin::pe::{
+    header::{COFF_MACHINE_EBC, COFF_MACHINE_X86},
+    section_table::IMAGE_SCN_CNT_CODE,
+};
 
 use relocation::{RelocationBlock, parse_relocation_blocks};
 use resource_directory::{DataEntry, Directory, DirectoryEntry, DirectoryString};
@@ -40,12 +44,18 @@ const SIZEOF_STANDARD_FIELDS_64: usize = 24;
 
 // Relocation type that does not require any action.
 const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+// Relocation type that requires the high 16 bits of the adjustment be
+// applied to the 16-bit value.
+pub(crate) const IMAGE_REL_BASED_HIGH: u16 = 1;
+// Relocation type that requires the low 16 bits of the adjustment be
+// applied to the 16-bit value.
+pub(crate) const IMAGE_REL_BASED_LOW: u16 = 2;
 // Relocation type that requires the adjustment be applied to the entire
 // 32-bit value.
-const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+pub(crate) const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
 // Relocation type that requires the adjustment be applied to the entire
 // 64-bit value.
-const IMAGE_REL_BASED_DIR64: u16 = 10;
+pub(crate) const IMAGE_REL_BASED_DIR64: u16 = 10;
 
 /// Enum representing the type of header in a PE32 image.
 #[derive(Debug, Default, Clone, PartialEq)]
@@ -55,6 +65,38 @@ pub enum HeaderType {
     Pe,
 }
 
+/// The CodeView RSDS (PDB 7.0) signature of an image, which identifies the PDB built with the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeViewSignature {
+    /// The GUID of the PDB.
+    pub guid: efi::Guid,
+    /// The number of times the PDB was written.
+    pub age: u32,
+    /// The path of the PDB when the image was linked.
+    pub pdb_path: String,
+}
+
+impl CodeViewSignature {
+    fn from_pdb70(codeview_data: &goblin::pe::debug::CodeviewPDB70DebugInfo) -> Self {
+        let path_end = codeview_data.filename.iter().position(|&c| c == b'\0').unwrap_or(codeview_data.filename.len());
+        Self {
+            guid: efi::Guid::from_bytes(&codeview_data.signature),
+            age: codeview_data.age,
+            pdb_path: String::from_utf8_lossy(&codeview_data.filename[..path_end]).into_owned(),
+        }
+    }
+
+    /// Returns the key that symbol servers file the PDB under: the GUID as 32 hex digits followed by the age in hex.
+    pub fn symbol_server_key(&self) -> String {
+        let bytes = self.guid.as_bytes();
+        let data1 = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        let data2 = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let data3 = u16::from_le_bytes([bytes[6], bytes[7]]);
+        let data4: String = bytes[8..].iter().map(|byte| format!("{byte:02X}")).collect();
+        format!("{data1:08X}{data2:04X}{data3:04X}{data4}{:X}", self.age)
+    }
+}
+
 /// Type containing information about a PE32 image.
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct UefiPeInfo {
@@ -67,6 +109,8 @@ pub struct UefiPeInfo {
     pub entry_point_offset: usize,
     /// The subsystem type (IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER \[0xB\], etc.).
     pub image_type: u16,
+    /// The machine type the image was built for (IMAGE_FILE_MACHINE_X64 \[0x8664\], etc.).
+    pub machine: u16,
     /// The total length of the image.
     pub size_of_image: u32,
     /// The size of an individual section in a power of 2 (4K \[0x1000\], etc.).
@@ -77,10 +121,19 @@ pub struct UefiPeInfo {
     pub sections: Vec<goblin::pe::section_table::SectionTable>,
     /// The filename, if present, from debug_data
     pub filename: Option<String>,
+    /// The CodeView RSDS signature, if present, from debug_data
+    pub codeview: Option<CodeViewSignature>,
     /// The relocation directory, if present.
     pub reloc_dir: Option<goblin::pe::data_directories::DataDirectory>,
+    /// The import directory, if present. UEFI images must not import from other images.
+    pub import_dir: Option<goblin::pe::data_directories::DataDirectory>,
+    /// The TLS directory, if present. UEFI images must not use thread local storage.
+    pub tls_dir: Option<goblin::pe::data_directories::DataDirectory>,
     /// Whether the NX_COMPAT DLL Characteristic flag is set
     pub nx_compat: bool,
+    /// Whether the CET_COMPAT extended DLL Characteristic flag is set, i.e. the image is compatible with return
+    /// address protection
+    pub cet_compat: bool,
 }
 
 impl UefiPeInfo {
@@ -102,11 +155,14 @@ impl UefiPeInfo {
         pe.header_type = HeaderType::Te(parsed_te.rva_offset);
         pe.entry_point_offset = parsed_te.header.entry_point as usize;
         pe.image_type = parsed_te.header.subsystem as u16;
+        pe.machine = parsed_te.header.machine;
         pe.section_alignment = 0;
         pe.size_of_headers = parsed_te.header.base_of_code as usize;
         pe.sections = parsed_te.sections;
         // TE doesn't have the optional header with DLL Characteristics, so we have to assume the image is NX_COMPAT
         pe.nx_compat = true;
+        // TE images carry no extended DLL Characteristics, so they are not assumed to be compatible with control flow
+        // protections
 
         // TE headers always have a reloc dir, even if it's empty
         // unlike PE32 headers.
@@ -122,6 +178,7 @@ impl UefiPeInfo {
             // Parse the filename from the debug data if it exists.
             if let Some(codeview_data) = &parsed_te.debug_data.codeview_pdb70_debug_info {
                 pe.filename = UefiPeInfo::read_filename(codeview_data.filename)?;
+                pe.codeview = Some(CodeViewSignature::from_pdb70(codeview_data));
             };
 
             Ok(pe)
@@ -134,14 +191,17 @@ impl UefiPeInfo {
     fn from_pe(bytes: &[u8]) -> error::Result<Self> {
         let mut pe = UefiPeInfo::default();
 
-        // Parse the PE header and verify the optional header exists
-        let parsed_pe = goblin::pe::PE::parse(bytes)?;
+        // Parse the PE header and verify the optional header exists. The import directory is recorded but not parsed,
+        // as UEFI images must not import from other images and the loader rejects such images with a diagnostic.
+        let options = goblin::pe::options::ParseOptions::default().with_parse_imports(false);
+        let parsed_pe = goblin::pe::PE::parse_with_opts(bytes, &options)?;
         let optional_header = parsed_pe.header.optional_header.ok_or(error::Error::NoOptionalHeader)?;
 
         // Set the simple fields
         pe.header_type = HeaderType::Pe;
         pe.entry_point_offset = optional_header.standard_fields.address_of_entry_point as usize;
         pe.image_type = optional_header.windows_fields.subsystem;
+        pe.machine = parsed_pe.header.coff_header.machine;
         pe.section_alignment = optional_header.windows_fields.section_alignment;
         pe.size_of_image = optional_header.windows_fields.size_of_image;
         pe.sections = parsed_pe.sections.into_iter().collect();
@@ -155,6 +215,10 @@ impl UefiPeInfo {
             pe.reloc_dir = Some(*reloc_section);
         }
 
+        // Set the import and TLS directories if they are not empty
+        pe.import_dir = optional_header.data_directories.get_import_table().filter(|dir| dir.size != 0).copied();
+        pe.tls_dir = optional_header.data_directories.get_tls_table().filter(|dir| dir.size != 0).copied();
+
         // Calculate the image base offset by finding the offset of the windows fields
         // image_base is the first entry in the windows_fields
         let mut windows_fields_offset = parsed_pe.header.dos_header.pe_pointer;
@@ -165,8 +229,15 @@ impl UefiPeInfo {
 
         // Get the filename if the data exists
         if let Some(debug_data) = parsed_pe.debug_data {
+            // The extended DLL characteristics are stored in a debug directory entry rather than the optional header
+            if let Some(ex_dll_characteristics) = debug_data.ex_dll_characteristics_info {
+                let characteristics_ex = ex_dll_characteristics.characteristics_ex;
+                pe.cet_compat = characteristics_ex & goblin::pe::debug::IMAGE_DLLCHARACTERISTICS_EX_CET_COMPAT != 0;
+            }
+
             if let Some(codeview_data) = debug_data.codeview_pdb70_debug_info {
                 pe.filename = UefiPeInfo::read_filename(codeview_data.filename)?;
+                pe.codeview = Some(CodeViewSignature::from_pdb70(&codeview_data));
             } else if let Some(codeview_data) = debug_data.codeview_pdb20_debug_info {
                 pe.filename = UefiPeInfo::read_filename(codeview_data.filename)?;
             }
@@ -236,11 +307,187 @@ pub fn load_image(pe_info: &UefiPeInfo, image: &[u8], loaded_image: &mut [u8]) -
     Ok(())
 }
 
+/// The layout of an image that is executed in place, as returned by [execute_in_place_layout].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecuteInPlaceLayout {
+    /// The section aligned range of the image, as offsets from its base, that holds all of its writable sections.
+    /// This range is copied into pages of its own, and is empty if the image has no writable sections.
+    pub writable: core::ops::Range<usize>,
+    /// Whether fixups must be written into the sections that are executed in place.
+    pub fixes_up_in_place: bool,
+}
+
+/// A fixup that must be written into the sections of an image that is executed in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InPlaceFixup {
+    /// The offset of the 64-bit fixup from the base of the image.
+    pub offset: usize,
+    /// The value of the fixup in the image.
+    pub original: u64,
+    /// The value to write for the image to run with its writable sections copied.
+    pub relocated: u64,
+}
+
+// returns the offsets of the DIR64 fixups of an image, or an error if it has a fixup of any other type.
+fn dir64_fixups(pe_info: &UefiPeInfo, image: &[u8]) -> error::Result<Vec<usize>> {
+    let Some(dir) = pe_info.reloc_dir else {
+        return Ok(Vec::new());
+    };
+    let relocation_data = image
+        .get((dir.virtual_address as usize)..(dir.virtual_address as usize + dir.size as usize))
+        .ok_or(error::Error::BufferTooShort(dir.size as usize, "image"))?;
+
+    let mut fixups = Vec::new();
+    for block in parse_relocation_blocks(relocation_data)? {
+        for reloc in block.relocations {
+            let fixup_type = reloc.type_and_offset >> 12;
+            let fixup_rva = block.block_header.page_rva as usize + (reloc.type_and_offset & 0xFFF) as usize;
+            match fixup_type {
+                IMAGE_REL_BASED_ABSOLUTE => {}
+                IMAGE_REL_BASED_DIR64 => fixups.push(fixup_rva),
+                _ => return Err(error::Error::UnsupportedRelocation(fixup_type, fixup_rva)),
+            }
+        }
+    }
+    Ok(fixups)
+}
+
+/// Returns the layout with which the image at `address` can be executed in place, or None if it cannot be.
+///
+/// The sections that are not writable are executed where they are, so this requires a PE32 header, a section aligned
+/// `address`, and a file layout that matches the memory layout of each of those sections. The writable sections are
+/// copied, so their file layout does not matter, but they must not be interleaved with the other sections. Only DIR64
+/// fixups, the type used by x64 and AArch64 images, can be redirected to the copy.
+pub fn execute_in_place_layout(pe_info: &UefiPeInfo, image: &[u8], address: usize) -> Option<ExecuteInPlaceLayout> {
+    let alignment = pe_info.section_alignment as usize;
+    if pe_info.header_type != HeaderType::Pe
+        || alignment == 0
+        || !address.is_multiple_of(alignment)
+        || image.len() < pe_info.size_of_image as usize
+    {
+        return None;
+    }
+
+    let is_writable = |section: &goblin::pe::section_table::SectionTable| {
+        section.characteristics & goblin::pe::section_table::IMAGE_SCN_MEM_WRITE != 0
+    };
+    let writable = pe_info
+        .sections
+        .iter()
+        .filter(|section| is_writable(section))
+        .map(|section| {
+            let start = section.virtual_address as usize;
+            start..start + (section.virtual_size as usize).next_multiple_of(alignment)
+        })
+        .reduce(|span, section| span.start.min(section.start)..span.end.max(section.end))
+        .unwrap_or(0..0);
+    if writable.end > pe_info.size_of_image as usize {
+        return None;
+    }
+
+    let in_place_matches = pe_info.sections.iter().filter(|section| !is_writable(section)).all(|section| {
+        let start = section.virtual_address as usize;
+        section.pointer_to_raw_data == section.virtual_address
+            && section.size_of_raw_data >= section.virtual_size
+            && (start + section.virtual_size as usize <= writable.start || start >= writable.end)
+    });
+    if !in_place_matches {
+        return None;
+    }
+
+    // fixups in the executed sections must be written if the image is not linked for `address`, or if they point into
+    // the writable sections, which are moved.
+    let base = image.pread_with::<u64>(pe_info.image_base_header_field_offset, LE).ok()?;
+    let adjustment = (address as u64).wrapping_sub(base);
+    let writable_addresses = (address + writable.start) as u64..(address + writable.end) as u64;
+    let mut fixes_up_in_place = false;
+    for offset in dir64_fixups(pe_info, image).ok()? {
+        if !writable.contains(&offset) {
+            let relocated = image.pread_with::<u64>(offset, LE).ok()?.wrapping_add(adjustment);
+            fixes_up_in_place |= adjustment != 0 || writable_addresses.contains(&relocated);
+        }
+    }
+
+    Some(ExecuteInPlaceLayout { writable, fixes_up_in_place })
+}
+
+/// Copies the writable sections of the image at `address` into `copy` and applies the fixups of the image, so that
+/// it runs from `address` with its writable sections at `copy`.
+///
+/// Fixups that point into the writable sections are redirected to `copy`. The fixups in `copy` are applied there, and
+/// the fixups that must be written into the sections executed in place are returned for the caller to write.
+///
+/// ## Errors
+///
+/// Returns [`BufferTooShort`](error::Error::BufferTooShort) error if `copy` cannot hold the writable sections, or a
+/// writable section or fixup lies outside of `image`.
+///
+/// Returns [`UnsupportedRelocation`](error::Error::UnsupportedRelocation) error if the image has a fixup of a type
+/// other than DIR64.
+pub fn execute_in_place(
+    pe_info: &UefiPeInfo,
+    layout: &ExecuteInPlaceLayout,
+    image: &[u8],
+    address: usize,
+    copy: &mut [u8],
+) -> error::Result<Vec<InPlaceFixup>> {
+    let writable = layout.writable.clone();
+    if copy.len() < writable.len() {
+        return Err(error::Error::BufferTooShort(writable.len(), "copy"));
+    }
+
+    copy.fill(0);
+    for section in &pe_info.sections {
+        if section.characteristics & goblin::pe::section_table::IMAGE_SCN_MEM_WRITE == 0 {
+            continue;
+        }
+        let size = section.size_of_raw_data.min(section.virtual_size) as usize;
+        let src = image
+            .get((section.pointer_to_raw_data as usize)..(section.pointer_to_raw_data as usize + size))
+            .ok_or(error::Error::BufferTooShort(size, "image"))?;
+        let dst_start = section.virtual_address as usize - writable.start;
+        copy[dst_start..dst_start + size].copy_from_slice(src);
+    }
+
+    let base = image.pread_with::<u64>(pe_info.image_base_header_field_offset, LE)?;
+    let adjustment = (address as u64).wrapping_sub(base);
+    let writable_addresses = (address + writable.start) as u64..(address + writable.end) as u64;
+    let redirect = (copy.as_ptr() as u64).wrapping_sub(writable_addresses.start);
+
+    let mut in_place_fixups = Vec::new();
+    for offset in dir64_fixups(pe_info, image)? {
+        let in_copy = writable.contains(&offset);
+        let original = if in_copy {
+            copy.pread_with::<u64>(offset - writable.start, LE)?
+        } else {
+            image.pread_with::<u64>(offset, LE)?
+        };
+
+        let mut relocated = original.wrapping_add(adjustment);
+        if writable_addresses.contains(&relocated) {
+            relocated = relocated.wrapping_add(redirect);
+        }
+
+        if in_copy {
+            copy.pwrite_with(relocated, offset - writable.start, LE)?;
+        } else if relocated != original {
+            in_place_fixups.push(InPlaceFixup { offset, original, relocated });
+        }
+    }
+    Ok(in_place_fixups)
+}
+
 /// Attempts to relocate the image to the specified destination.
 ///
 /// Relocates the already loaded image to the destination address, applying
 /// all relocation fixups, returning an error if it failed.
 ///
+/// The ABSOLUTE, HIGH, LOW, HIGHLOW and DIR64 base relocation types, which are
+/// the types valid in x64 and AArch64 UEFI images, are supported. ARM64X images
+/// are loaded as their native AArch64 view: the base relocations are applied,
+/// but the ARM64X dynamic value relocations that switch the image to its x64
+/// view are not.
+///
 /// ## Errors
 ///
 /// Returns [`Parse`](error::Error::Parse) error if parsing a image containing a TE header
@@ -251,6 +498,9 @@ pub fn load_image(pe_info: &UefiPeInfo, image: &[u8], loaded_image: &mut [u8]) -
 ///
 /// Returns [`BufferTooShort`](error::Error::BufferTooShort) error if either of the buffers provided are
 /// not large enough to contain the image as specified by the image header.
+///
+/// Returns [`UnsupportedRelocation`](error::Error::UnsupportedRelocation) error if the image contains a
+/// relocation of any other type, such as HIGHADJ or a type specific to another architecture.
 pub fn relocate_image(
     pe_info: &UefiPeInfo,
     destination: usize,
@@ -282,71 +532,73 @@ pub fn relocate_image(
     for (block_idx, reloc_block) in relocation_block.iter_mut().enumerate() {
         for (reloc_idx, reloc) in reloc_block.relocations.iter_mut().enumerate() {
             let fixup_type = reloc.type_and_offset >> 12;
-            let fixup =
-                reloc_block.block_header.page_rva as usize + (reloc.type_and_offset & 0xFFF) as usize - rva_offset;
-
-            match fixup_type {
-                IMAGE_REL_BASED_ABSOLUTE => {}
+            let fixup_rva = reloc_block.block_header.page_rva as usize + (reloc.type_and_offset & 0xFFF) as usize;
+            let fixup = fixup_rva - rva_offset;
+
+            // Read the value at the fixup and compute its relocated value.
+            let (value, relocated) = match fixup_type {
+                IMAGE_REL_BASED_ABSOLUTE => continue,
+                IMAGE_REL_BASED_HIGH | IMAGE_REL_BASED_LOW => {
+                    let value = image.pread_with::<u16>(fixup, LE)?;
+                    let delta = if fixup_type == IMAGE_REL_BASED_HIGH { adjustment >> 16 } else { adjustment };
+                    (value as u64, value.wrapping_add(delta as u16) as u64)
+                }
                 IMAGE_REL_BASED_HIGHLOW => {
                     let value = image.pread_with::<u32>(fixup, LE)?;
-                    image.pwrite_with(value.wrapping_add(adjustment as u32), fixup, LE)?;
+                    (value as u64, value.wrapping_add(adjustment as u32) as u64)
                 }
                 IMAGE_REL_BASED_DIR64 => {
-                    let mut value = image.pread_with::<u64>(fixup, LE)?;
-                    image.pwrite_with(value.wrapping_add(adjustment), fixup, LE)?;
-
-                    if !prev_reloc_blocks.is_empty()
-                        && prev_reloc_blocks[block_idx].relocations[reloc_idx].value != value
-                    {
-                        continue;
-                    }
+                    let value = image.pread_with::<u64>(fixup, LE)?;
+                    (value, value.wrapping_add(adjustment))
+                }
+                // HIGHADJ is not valid in UEFI images, and the remaining types are specific to other architectures.
+                _ => return Err(error::Error::UnsupportedRelocation(fixup_type, fixup_rva)),
+            };
 
-                    value = value.wrapping_add(adjustment);
-                    reloc.value = value;
+            match fixup_type {
+                IMAGE_REL_BASED_HIGH | IMAGE_REL_BASED_LOW => image.pwrite_with(relocated as u16, fixup, LE)?,
+                IMAGE_REL_BASED_HIGHLOW => image.pwrite_with(relocated as u32, fixup, LE)?,
+                _ => image.pwrite_with(relocated, fixup, LE)?,
+            };
 
-                    let subslice = image.get_mut(fixup..fixup + 8).ok_or(error::Error::BufferTooShort(8, "image"))?;
-                    subslice.copy_from_slice(&value.to_le_bytes()[..]);
-                }
-                _ => todo!(), // Other fixups not implemented at this time
+            // Only record the relocated value of fixups that still held the value recorded by the previous relocation.
+            if !prev_reloc_blocks.is_empty() && prev_reloc_blocks[block_idx].relocations[reloc_idx].value != value {
+                continue;
             }
+            reloc.value = relocated;
         }
     }
     Ok(relocation_block)
 }
 
+/// Returns the size and alignment of the value recorded for a fixup of `fixup_type` in the runtime relocation data.
+fn runtime_fixup_layout(fixup_type: u16) -> (usize, usize) {
+    match fixup_type {
+        IMAGE_REL_BASED_HIGH | IMAGE_REL_BASED_LOW => (size_of::<u16>(), 1),
+        IMAGE_REL_BASED_HIGHLOW => (size_of::<u32>(), size_of::<u32>()),
+        IMAGE_REL_BASED_DIR64 => (size_of::<u64>(), size_of::<u64>()),
+        _ => (0, 1),
+    }
+}
+
 /// Converts a vector of relocation blocks into a flat buffer suitable for use in the runtime protocol.
+///
+/// The buffer holds the relocated value of each fixup, in order, as the runtime driver expects: 16-bit values for
+/// HIGH and LOW fixups, and naturally aligned 32-bit and 64-bit values for HIGHLOW and DIR64 fixups.
 pub fn flatten_runtime_relocation_data(relocation_data: &[RelocationBlock]) -> &'static mut [u8] {
     // The runtime protocol expects linearly appended values, determine how much space
     // is needed to store this.
-
-    let mut size = 0;
-    for block in relocation_data {
-        for reloc in &block.relocations {
-            let fixup_type = reloc.type_and_offset >> 12;
-            size += match fixup_type {
-                IMAGE_REL_BASED_ABSOLUTE => 0,
-                IMAGE_REL_BASED_HIGHLOW => core::mem::size_of::<u32>(),
-                IMAGE_REL_BASED_DIR64 => core::mem::size_of::<u64>(),
-                _ => todo!(), // Other fixups not implemented at this time
-            }
-        }
-    }
+    let relocations = || relocation_data.iter().flat_map(|block| block.relocations.iter());
+    let size = relocations().fold(0usize, |size, reloc| {
+        let (value_size, align) = runtime_fixup_layout(reloc.type_and_offset >> 12);
+        size.next_multiple_of(align) + value_size
+    });
 
     let mut flat_data = Vec::with_capacity_in(size, &crate::allocator::EFI_RUNTIME_SERVICES_DATA_ALLOCATOR);
-    for block in relocation_data {
-        for reloc in &block.relocations {
-            let fixup_type = reloc.type_and_offset >> 12;
-            match fixup_type {
-                IMAGE_REL_BASED_ABSOLUTE => {}
-                IMAGE_REL_BASED_HIGHLOW => {
-                    flat_data.extend_from_slice(&(reloc.value as u32).to_le_bytes());
-                }
-                IMAGE_REL_BASED_DIR64 => {
-                    flat_data.extend_from_slice(&reloc.value.to_le_bytes());
-                }
-                _ => todo!(), // Other fixups not implemented at this time
-            }
-        }
+    for reloc in relocations() {
+        let (value_size, align) = runtime_fixup_layout(reloc.type_and_offset >> 12);
+        flat_data.resize(flat_data.len().next_multiple_of(align), 0);
+        flat_data.extend_from_slice(&reloc.value.to_le_bytes()[..value_size]);
     }
 
     // Double check that the capacity calculation was correct.
@@ -509,6 +761,7 @@ mod tests {
         let image_info = UefiPeInfo::parse(image).unwrap();
 
         assert_eq!(image_info.image_type, 11);
+        assert_eq!(image_info.machine, goblin::pe::header::COFF_MACHINE_X86_64);
         assert_eq!(image_info.section_alignment, 0x0);
         assert_eq!(image_info.filename, Some(String::from("RustTerseImageTestDxe.efi")));
         assert_eq!(image_info.size_of_image, 0x5ef8);
@@ -523,6 +776,7 @@ mod tests {
         assert_eq!(image_info.image_type, 0x0B);
         assert_eq!(image_info.section_alignment, 0x1000);
         assert_eq!(image_info.filename, Some(String::from("RustFfiTestDxe.efi")));
+        assert_eq!(image_info.machine, goblin::pe::header::COFF_MACHINE_X86_64);
         assert_eq!(image_info.size_of_image, 0x14000);
         assert_eq!(image_info.entry_point_offset, 0x11B8);
     }
@@ -539,6 +793,11 @@ mod tests {
         assert_eq!(image_info.filename, Some(String::from("DisplayEngine.efi")));
         assert_eq!(image_info.size_of_image, 0x19000);
         assert_eq!(image_info.entry_point_offset, 0x11EC);
+
+        let codeview = image_info.codeview.unwrap();
+        assert!(codeview.pdb_path.ends_with(r"DisplayEngineDxe\DEBUG\DisplayEngine.pdb"));
+        assert_eq!(codeview.age, 7);
+        assert_eq!(codeview.symbol_server_key(), "5E8FD4B6DEE54DC99C7520FF0314A4547");
     }
 
     #[test]
@@ -614,6 +873,7 @@ mod tests {
 
         //debug information is not included when loading an image in the present implementation, so filename will not be present.
         image_info.filename = None;
+        image_info.codeview = None;
         assert_eq!(image_info, loaded_image_info);
     }
 
@@ -688,6 +948,75 @@ mod tests {
         assert!(first_mismatch.is_none(), "relocated image mismatch at idx: {:#x?}", first_mismatch.unwrap());
     }
 
+    // returns the header of the test image, with the file layout of each section matching its memory layout.
+    fn execute_in_place_info() -> UefiPeInfo {
+        let mut image_info = UefiPeInfo::parse(include_bytes!("../resources/test/pe32/test_image.pe32")).unwrap();
+        for section in &mut image_info.sections {
+            section.pointer_to_raw_data = section.virtual_address;
+            section.size_of_raw_data = section.virtual_size;
+        }
+        image_info
+    }
+
+    #[test]
+    fn execute_in_place_requires_a_matching_layout() {
+        let image = include_bytes!("../resources/test/pe32/test_image.pe32");
+        let image_info = UefiPeInfo::parse(image).unwrap();
+        assert!(execute_in_place_layout(&image_info, image, 0x04158000).is_none());
+
+        // an image linked for 0x04158000 with a file layout matching its memory layout copies only its .data section.
+        let relocated_image = include_bytes!("../resources/test/pe32/test_image_relocated.bin");
+        let xip_info = execute_in_place_info();
+        let layout = execute_in_place_layout(&xip_info, relocated_image, 0x04158000).unwrap();
+        assert_eq!(layout.writable, 0x10000..0x11000);
+        assert!(execute_in_place_layout(&xip_info, relocated_image, 0x04158800).is_none());
+
+        // writable sections must not be interleaved with the sections that are executed in place.
+        let mut interleaved_info = xip_info.clone();
+        interleaved_info.sections[0].characteristics |= goblin::pe::section_table::IMAGE_SCN_MEM_WRITE;
+        assert!(execute_in_place_layout(&interleaved_info, relocated_image, 0x04158000).is_none());
+    }
+
+    #[test]
+    fn execute_in_place_should_copy_writable_sections_and_redirect_fixups_into_them() {
+        // the loaded test image has a file layout matching its memory layout and a writable .data section, whose
+        // fixups point into .rdata. .rdata is made writable as well, so that those fixups must be redirected.
+        let loaded_image = include_bytes!("../resources/test/pe32/test_image_loaded.bin");
+        let mut xip_info = execute_in_place_info();
+        xip_info.sections[1].characteristics |= goblin::pe::section_table::IMAGE_SCN_MEM_WRITE;
+        let layout = execute_in_place_layout(&xip_info, loaded_image, 0x04158000).unwrap();
+        assert_eq!(layout.writable, 0xc000..0x11000);
+
+        let mut copy = vec![0xA5u8; layout.writable.len()];
+        let fixups = execute_in_place(&xip_info, &layout, loaded_image, 0x04158000, &mut copy).unwrap();
+        assert_eq!(fixups.is_empty(), !layout.fixes_up_in_place);
+
+        // moving the copy back over the writable sections and undoing the redirection of the fixups that point into
+        // it must give the image relocated to 0x04158000.
+        let copy_range = copy.as_ptr() as u64..copy.as_ptr() as u64 + copy.len() as u64;
+        let redirect = copy_range.start.wrapping_sub(0x04158000 + layout.writable.start as u64);
+        let mut image = loaded_image.to_vec();
+        for fixup in &fixups {
+            assert_eq!(image.pread_with::<u64>(fixup.offset, LE).unwrap(), fixup.original);
+            image.pwrite_with(fixup.relocated, fixup.offset, LE).unwrap();
+        }
+        image[layout.writable.clone()].copy_from_slice(&copy);
+
+        let mut redirected = 0;
+        for offset in dir64_fixups(&xip_info, loaded_image).unwrap() {
+            let value = image.pread_with::<u64>(offset, LE).unwrap();
+            if copy_range.contains(&value) {
+                image.pwrite_with(value.wrapping_sub(redirect), offset, LE).unwrap();
+                redirected += 1;
+            }
+        }
+        assert!(redirected > 0);
+
+        // the header of an image executed in place is left as it is in the FV.
+        image.pwrite_with(0x04158000u64, xip_info.image_base_header_field_offset, LE).unwrap();
+        assert_eq!(image, include_bytes!("../resources/test/pe32/test_image_relocated.bin"));
+    }
+
     #[test]
     fn pe_relocate_image_should_work_multiple_times() {
         let image = include_bytes!("../resources/test/pe32/test_image.pe32");
@@ -726,6 +1055,82 @@ mod tests {
         }
     }
 
+    /// Builds a loaded image with the image base at offset 0, one relocation block at 0x100 with a relocation of each
+    /// of `fixup_types`, at 0x200, 0x210, ..., and a value at each of those offsets.
+    fn crafted_image(fixup_types: &[u16]) -> (UefiPeInfo, Vec<u8>) {
+        let mut image = vec![0u8; 0x400];
+        image.pwrite_with(0x1000_0000u64, 0, LE).unwrap();
+
+        let block_size = 8 + 2 * fixup_types.len();
+        image.pwrite_with(0u32, 0x100, LE).unwrap();
+        image.pwrite_with(block_size as u32, 0x104, LE).unwrap();
+        for (index, fixup_type) in fixup_types.iter().enumerate() {
+            let offset = 0x200 + 0x10 * index;
+            image.pwrite_with((fixup_type << 12) | offset as u16, 0x108 + 2 * index, LE).unwrap();
+            image.pwrite_with(0x1000_8000_1000_8000u64, offset, LE).unwrap();
+        }
+
+        let pe_info = UefiPeInfo {
+            reloc_dir: Some(goblin::pe::data_directories::DataDirectory {
+                virtual_address: 0x100,
+                size: block_size.next_multiple_of(4) as u32,
+            }),
+            ..Default::default()
+        };
+        (pe_info, image)
+    }
+
+    #[test]
+    fn relocate_image_should_apply_each_relocation_type() {
+        let fixup_types = [
+            IMAGE_REL_BASED_ABSOLUTE,
+            IMAGE_REL_BASED_HIGH,
+            IMAGE_REL_BASED_LOW,
+            IMAGE_REL_BASED_HIGHLOW,
+            IMAGE_REL_BASED_DIR64,
+        ];
+        let (pe_info, mut image) = crafted_image(&fixup_types);
+
+        let blocks = relocate_image(&pe_info, 0x1001_2345, &mut image, &Vec::new()).unwrap();
+
+        assert_eq!(image.pread_with::<u64>(0, LE).unwrap(), 0x1001_2345);
+        assert_eq!(image.pread_with::<u64>(0x200, LE).unwrap(), 0x1000_8000_1000_8000);
+        assert_eq!(image.pread_with::<u64>(0x210, LE).unwrap(), 0x1000_8000_1000_8001);
+        assert_eq!(image.pread_with::<u64>(0x220, LE).unwrap(), 0x1000_8000_1000_A345);
+        assert_eq!(image.pread_with::<u64>(0x230, LE).unwrap(), 0x1000_8000_1001_A345);
+        assert_eq!(image.pread_with::<u64>(0x240, LE).unwrap(), 0x1000_8000_1001_A345);
+
+        let values: Vec<u64> = blocks[0].relocations.iter().map(|reloc| reloc.value).collect();
+        assert_eq!(values, [0, 0x8001, 0xA345, 0x1001_A345, 0x1000_8000_1001_A345]);
+
+        // relocating again only records the values of the fixups that still held their recorded value.
+        image.pwrite_with(0x5555u16, 0x210, LE).unwrap();
+        let blocks = relocate_image(&pe_info, 0x1000_0000, &mut image, &blocks).unwrap();
+        assert_eq!(image.pread_with::<u16>(0x220, LE).unwrap(), 0x8000);
+        assert_eq!(image.pread_with::<u64>(0x240, LE).unwrap(), 0x1000_8000_1000_8000);
+        let values: Vec<u64> = blocks[0].relocations.iter().map(|reloc| reloc.value).collect();
+        assert_eq!(values, [0, 0, 0x8000, 0x1000_8000, 0x1000_8000_1000_8000]);
+    }
+
+    #[test]
+    fn relocate_image_should_reject_unsupported_relocation_types() {
+        // HIGHADJ, the ARM and RISC-V MOV/HI20/LO12 types, and undefined types.
+        for fixup_type in [4, 5, 7, 8, 9, 11, 15] {
+            let (pe_info, mut image) = crafted_image(&[IMAGE_REL_BASED_DIR64, fixup_type]);
+            match relocate_image(&pe_info, 0x2000_0000, &mut image, &Vec::new()) {
+                Err(error::Error::UnsupportedRelocation(reported_type, 0x210)) => assert_eq!(reported_type, fixup_type),
+                other => panic!("Expected UnsupportedRelocation error for type {fixup_type}, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn runtime_fixup_layout_should_match_the_runtime_driver() {
+        let fixup_types =
+            [IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_HIGH, IMAGE_REL_BASED_HIGHLOW, IMAGE_REL_BASED_DIR64];
+        assert_eq!(fixup_types.map(runtime_fixup_layout), [(0, 1), (2, 1), (4, 4), (8, 8)]);
+    }
+
     #[test]
     fn pe_load_resource_section_should_succeed() {
         // test_image_<toolchain>_hii.pe32 file is just a copy of TftpDynamicCommand.efi module copied and renamed.
@@ -21,6 +21,9 @@ pub enum Error {
     BadSignature(u16),
     /// The parsed PeCoff image does not contain an Optional Header.
     NoOptionalHeader,
+    /// The image contains a base relocation of a type (first field) that is not valid for a UEFI image on this
+    /// architecture, at an RVA (second field).
+    UnsupportedRelocation(u16, usize),
 }
 
 impl From<scroll::Error> for Error {
@@ -0,0 +1,81 @@
+//! Default Persisted Blob Service
+//!
+//! If the platform does not register a [PersistedBlob] service, the core registers a [VariableBlobStore]. The UEFI
+//! variable services are only available once the variable drivers install the Variable and Variable Write
+//! Architectural Protocols, so [CorePersistedBlob] returns [EfiError::NotReady] until they are installed, rather than
+//! calling into runtime services that are not yet implemented.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+
+use patina::{
+    component::service::{
+        IntoService,
+        persisted_blob::{PersistedBlob, VariableBlobStore},
+    },
+    error::EfiError,
+    runtime_services::StandardRuntimeServices,
+};
+use r_efi::efi;
+
+use crate::{guid_names, protocols::PROTOCOL_DB};
+
+/// The core's [PersistedBlob] service, which stores blobs in UEFI variables.
+#[derive(IntoService)]
+#[service(dyn PersistedBlob)]
+pub(crate) struct CorePersistedBlob(VariableBlobStore<StandardRuntimeServices>);
+
+impl CorePersistedBlob {
+    pub(crate) fn new(runtime_services: StandardRuntimeServices) -> Self {
+        Self(VariableBlobStore::new(runtime_services))
+    }
+}
+
+// Returns NotReady unless each of the `protocols` is installed.
+fn require(protocols: &[efi::Guid]) -> Result<(), EfiError> {
+    match protocols.iter().all(|protocol| PROTOCOL_DB.locate_protocol(*protocol).is_ok()) {
+        true => Ok(()),
+        false => Err(EfiError::NotReady),
+    }
+}
+
+impl PersistedBlob for CorePersistedBlob {
+    fn load(&self, id: &efi::Guid) -> Result<Vec<u8>, EfiError> {
+        require(&[guid_names::VARIABLE_ARCH_PROTOCOL])?;
+        self.0.load(id)
+    }
+
+    fn save(&self, id: &efi::Guid, data: &[u8]) -> Result<(), EfiError> {
+        require(&[guid_names::VARIABLE_ARCH_PROTOCOL, guid_names::VARIABLE_WRITE_ARCH_PROTOCOL])?;
+        self.0.save(id, data)
+    }
+
+    fn delete(&self, id: &efi::Guid) -> Result<(), EfiError> {
+        require(&[guid_names::VARIABLE_ARCH_PROTOCOL, guid_names::VARIABLE_WRITE_ARCH_PROTOCOL])?;
+        self.0.delete(id)
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_blobs_are_not_ready_before_the_variable_services() {
+        test_support::with_global_lock(|| {
+            unsafe { test_support::init_test_protocol_db() };
+            let blobs = CorePersistedBlob::new(StandardRuntimeServices::new_uninit());
+            assert_eq!(blobs.load(&patina::guids::ZERO), Err(EfiError::NotReady));
+            assert_eq!(blobs.save(&patina::guids::ZERO, &[]), Err(EfiError::NotReady));
+            assert_eq!(blobs.delete(&patina::guids::ZERO), Err(EfiError::NotReady));
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Early Progress Codes
+//!
+//! When a system dies before the serial port or logging works, the last progress code written to a POST code display,
+//! a scratch register or a semihosting console is often the only indication of how far the core got. Platforms can
+//! register a [ProgressSink] with [Core::with_progress_sink](crate::Core::with_progress_sink) before
+//! [init_memory](crate::Core::init_memory), and the core writes a code from [ProgressCodes] to it at each
+//! [ProgressCheckpoint] through [init_memory](crate::Core::init_memory) and [start](crate::Core::start).
+//!
+//! Writing to the sink does not allocate or log, so it can be used from the first instruction of the core.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::fmt::Write;
+
+use spin::RwLock;
+
+/// Where the core writes early progress codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressSink {
+    /// Writes the low byte of each code to an x86 I/O port, usually the port 0x80 POST code display. Ignored on other
+    /// architectures.
+    IoPort(u16),
+    /// Writes each code to a 32-bit memory mapped scratch register at the given address.
+    Mmio(usize),
+    /// Prints each code to the debug console through an Arm semihosting `SYS_WRITE0` call. Ignored on other
+    /// architectures.
+    ///
+    /// Semihosting traps if no debugger or simulator services the call, so it must only be used when one is present.
+    Semihosting,
+}
+
+/// The points during core initialization at which a progress code is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressCheckpoint {
+    /// [init_memory](crate::Core::init_memory) was entered.
+    InitMemoryEntry,
+    /// The GCD was initialized from the HOB list, and heap allocations are possible.
+    GcdInitialized,
+    /// The memory services were initialized and the core moved to its own stack.
+    MemoryServicesInitialized,
+    /// The debugger was initialized and [init_memory](crate::Core::init_memory) is about to return.
+    InitMemoryComplete,
+    /// [start](crate::Core::start) was entered.
+    StartEntry,
+    /// The system table was initialized.
+    SystemTableInitialized,
+    /// Driver dispatch is starting.
+    DispatchStart,
+    /// Driver dispatch finished and the core is about to hand off to BDS.
+    HandoffToBds,
+}
+
+/// The code written to the [ProgressSink] at each [ProgressCheckpoint].
+///
+/// The default codes count up from 0x60, the start of the DXE range in common POST code conventions.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ProgressCodes, ProgressSink};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .with_progress_sink(ProgressSink::IoPort(0x80), ProgressCodes { handoff_to_bds: 0xAA, ..Default::default() })
+///   .init_memory(physical_hob_list)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressCodes {
+    /// Code for [ProgressCheckpoint::InitMemoryEntry].
+    pub init_memory_entry: u32,
+    /// Code for [ProgressCheckpoint::GcdInitialized].
+    pub gcd_initialized: u32,
+    /// Code for [ProgressCheckpoint::MemoryServicesInitialized].
+    pub memory_services_initialized: u32,
+    /// Code for [ProgressCheckpoint::InitMemoryComplete].
+    pub init_memory_complete: u32,
+    /// Code for [ProgressCheckpoint::StartEntry].
+    pub start_entry: u32,
+    /// Code for [ProgressCheckpoint::SystemTableInitialized].
+    pub system_table_initialized: u32,
+    /// Code for [ProgressCheckpoint::DispatchStart].
+    pub dispatch_start: u32,
+    /// Code for [ProgressCheckpoint::HandoffToBds].
+    pub handoff_to_bds: u32,
+}
+
+impl ProgressCodes {
+    const DEFAULT: ProgressCodes = ProgressCodes {
+        init_memory_entry: 0x60,
+        gcd_initialized: 0x61,
+        memory_services_initialized: 0x62,
+        init_memory_complete: 0x63,
+        start_entry: 0x64,
+        system_table_initialized: 0x65,
+        dispatch_start: 0x66,
+        handoff_to_bds: 0x67,
+    };
+
+    /// Returns the code for `checkpoint`.
+    pub fn code(&self, checkpoint: ProgressCheckpoint) -> u32 {
+        match checkpoint {
+            ProgressCheckpoint::InitMemoryEntry => self.init_memory_entry,
+            ProgressCheckpoint::GcdInitialized => self.gcd_initialized,
+            ProgressCheckpoint::MemoryServicesInitialized => self.memory_services_initialized,
+            ProgressCheckpoint::InitMemoryComplete => self.init_memory_complete,
+            ProgressCheckpoint::StartEntry => self.start_entry,
+            ProgressCheckpoint::SystemTableInitialized => self.system_table_initialized,
+            ProgressCheckpoint::DispatchStart => self.dispatch_start,
+            ProgressCheckpoint::HandoffToBds => self.handoff_to_bds,
+        }
+    }
+}
+
+impl Default for ProgressCodes {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static PROGRESS: RwLock<Option<(ProgressSink, ProgressCodes)>> = RwLock::new(None);
+
+/// Writes the code for each later checkpoint to `sink`.
+pub(crate) fn set_progress_sink(sink: ProgressSink, codes: ProgressCodes) {
+    *PROGRESS.write() = Some((sink, codes));
+}
+
+/// Writes the code for `checkpoint` to the registered sink, if any.
+pub(crate) fn report(checkpoint: ProgressCheckpoint) {
+    // the lock is only written during configuration, so this does not spin in practice.
+    let Some((sink, codes)) = *PROGRESS.read() else {
+        return;
+    };
+    let code = codes.code(checkpoint);
+    match sink {
+        ProgressSink::IoPort(port) => io_port_write(port, code as u8),
+        // Safety: the platform registered the address as a writable scratch register.
+        ProgressSink::Mmio(address) => unsafe { (address as *mut u32).write_volatile(code) },
+        ProgressSink::Semihosting => semihosting_write(code),
+    }
+}
+
+// Formats the message for a semihosting console into a NUL terminated buffer, returning the used length.
+#[cfg_attr(not(all(target_os = "uefi", target_arch = "aarch64")), allow(dead_code))]
+fn format_semihosting_message(buffer: &mut [u8], code: u32) -> usize {
+    struct Message<'a> {
+        buffer: &'a mut [u8],
+        len: usize,
+    }
+
+    impl Write for Message<'_> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            // always leave room for the NUL terminator.
+            let end = self.len + s.len();
+            if end >= self.buffer.len() {
+                return Err(core::fmt::Error);
+            }
+            self.buffer[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    let mut message = Message { buffer, len: 0 };
+    let _ = writeln!(message, "Patina progress: {code:#06x}");
+    let len = message.len;
+    buffer[len] = 0;
+    len + 1
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "uefi", target_arch = "x86_64"))] {
+        fn io_port_write(port: u16, value: u8) {
+            // Safety: the platform registered the port for progress codes.
+            unsafe { core::arch::asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags)) };
+        }
+    } else {
+        fn io_port_write(_port: u16, _value: u8) {}
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
+        fn semihosting_write(code: u32) {
+            const SYS_WRITE0: u64 = 0x04;
+            let mut buffer = [0u8; 32];
+            format_semihosting_message(&mut buffer, code);
+            // Safety: SYS_WRITE0 only reads the NUL terminated string, and the platform selected semihosting knowing
+            // that a debugger or simulator services the call.
+            unsafe {
+                core::arch::asm!(
+                    "hlt #0xf000",
+                    inout("x0") SYS_WRITE0 => _,
+                    in("x1") buffer.as_ptr(),
+                    options(nostack, preserves_flags),
+                )
+            };
+        }
+    } else {
+        fn semihosting_write(_code: u32) {}
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_progress_codes() {
+        let codes = ProgressCodes { dispatch_start: 0xD0, ..Default::default() };
+        assert_eq!(codes.code(ProgressCheckpoint::InitMemoryEntry), 0x60);
+        assert_eq!(codes.code(ProgressCheckpoint::DispatchStart), 0xD0);
+        assert_eq!(codes.code(ProgressCheckpoint::HandoffToBds), 0x67);
+    }
+
+    #[test]
+    fn test_report_to_mmio() {
+        test_support::with_global_lock(|| {
+            let mut register = 0u32;
+            set_progress_sink(ProgressSink::Mmio(&raw mut register as usize), ProgressCodes::default());
+            report(ProgressCheckpoint::StartEntry);
+            assert_eq!(unsafe { (&raw const register).read_volatile() }, 0x64);
+            *PROGRESS.write() = None;
+
+            // nothing is written once the sink is removed.
+            report(ProgressCheckpoint::DispatchStart);
+            assert_eq!(unsafe { (&raw const register).read_volatile() }, 0x64);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_semihosting_message() {
+        let mut buffer = [0xFFu8; 32];
+        let len = format_semihosting_message(&mut buffer, 0x63);
+        assert_eq!(&buffer[..len], b"Patina progress: 0x0063\n\0");
+
+        // a message that does not fit is truncated, but is still terminated.
+        let mut buffer = [0xFFu8; 8];
+        let len = format_semihosting_message(&mut buffer, 0x63);
+        assert_eq!(buffer[len - 1], 0);
+    }
+}
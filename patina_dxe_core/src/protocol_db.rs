@@ -16,7 +16,7 @@ use alloc::{
     vec::Vec,
 };
 use core::{cmp::Ordering, ffi::c_void, hash::Hasher};
-use patina::error::EfiError;
+use patina::{error::EfiError, uefi_protocol::ProtocolInterface};
 use r_efi::efi;
 
 use crate::tpl_lock;
@@ -116,7 +116,7 @@ struct ProtocolInstance {
     usage: Vec<OpenProtocolInformation>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 struct OrdGuid(efi::Guid);
 
 impl PartialOrd for OrdGuid {
@@ -191,7 +191,13 @@ impl Handle {
 // interaction with the database should be via [`SpinLockedProtocolDb`] below.
 struct ProtocolDb {
     handles: BTreeMap<usize, Handle>,
+    // Index of the handles each protocol is installed on, keyed by handle creation order, so that protocol lookups do
+    // not scan every handle.
+    protocol_index: BTreeMap<OrdGuid, BTreeMap<usize, usize>>,
     notifications: BTreeMap<OrdGuid, Vec<ProtocolNotify>>,
+    // Index of the protocol each registration key was registered for, so that registration lookups only search the
+    // notify list of that protocol.
+    registrations: BTreeMap<usize, OrdGuid>,
     hash_new_handles: bool,
     next_handle: usize,
     next_registration: usize,
@@ -201,7 +207,9 @@ impl ProtocolDb {
     const fn new() -> Self {
         ProtocolDb {
             handles: BTreeMap::new(),
+            protocol_index: BTreeMap::new(),
             notifications: BTreeMap::new(),
+            registrations: BTreeMap::new(),
             hash_new_handles: false,
             next_handle: 1,
             next_registration: 1,
@@ -213,9 +221,7 @@ impl ProtocolDb {
     }
 
     fn registered_protocols(&self) -> Vec<efi::Guid> {
-        let protocols: BTreeSet<efi::Guid> =
-            self.handles.iter().flat_map(|(_, handle)| handle.keys().map(|&OrdGuid(guid)| guid)).collect();
-        protocols.into_iter().collect()
+        self.protocol_index.keys().map(|&OrdGuid(guid)| guid).collect()
     }
 
     fn install_protocol_interface(
@@ -271,6 +277,8 @@ impl ProtocolDb {
         //attempt to add the protocol to the set of protocols on this handle.
         let exists = handle_instance.insert(OrdGuid(protocol), protocol_instance);
         assert!(exists.is_none()); //should be guaranteed by the `contains_key` check above.
+        let order = handle_instance.order;
+        self.protocol_index.entry(OrdGuid(protocol)).or_default().insert(order, key);
 
         //determine if there are any events to be notified.
         if let Some(events) = self.notifications.get_mut(&OrdGuid(protocol)) {
@@ -311,42 +319,49 @@ impl ProtocolDb {
             return Err(EfiError::AccessDenied);
         }
         handle_instance.remove(&OrdGuid(protocol));
+        let order = handle_instance.order;
 
         //if the last protocol instance on a handle is removed, delete the structures associated with the handles.
         if handle_instance.is_empty() {
             self.handles.remove(&key);
         }
 
+        if let Some(index) = self.protocol_index.get_mut(&OrdGuid(protocol)) {
+            index.remove(&order);
+            if index.is_empty() {
+                self.protocol_index.remove(&OrdGuid(protocol));
+            }
+        }
+
         Ok(())
     }
 
-    fn locate_handles(&mut self, protocol: Option<efi::Guid>) -> Result<Vec<efi::Handle>, EfiError> {
-        let mut handles: Vec<_> = self
-            .handles
-            .iter()
-            .filter_map(|(key, handle_data)| {
-                match protocol {
-                    None => Some((*key as efi::Handle, handle_data.order)), //"None" means return all handles.
-                    Some(protocol) if handle_data.contains_key(&OrdGuid(protocol)) => {
-                        Some((*key as efi::Handle, handle_data.order))
-                    }
-                    _ => None,
-                }
-            })
-            .collect();
-
-        if handles.is_empty() {
-            return Err(EfiError::NotFound);
-        }
+    fn locate_handles(&self, protocol: Option<efi::Guid>) -> Result<Vec<efi::Handle>, EfiError> {
+        let Some(protocol) = protocol else {
+            //"None" means return all handles.
+            let mut handles: Vec<_> =
+                self.handles.iter().map(|(key, handle_data)| (*key as efi::Handle, handle_data.order)).collect();
+            if handles.is_empty() {
+                return Err(EfiError::NotFound);
+            }
 
-        //sort by order of creation.
-        handles.sort_by(|a, b| a.1.cmp(&b.1));
+            //sort by order of creation.
+            handles.sort_by(|a, b| a.1.cmp(&b.1));
+            return Ok(handles.iter().map(|(handle, _)| *handle).collect());
+        };
 
-        Ok(handles.iter().map(|(handle, _)| *handle).collect())
+        //the index is already sorted by order of creation.
+        let index = self.protocol_index.get(&OrdGuid(protocol)).ok_or(EfiError::NotFound)?;
+        Ok(index.values().map(|key| *key as efi::Handle).collect())
     }
 
-    fn locate_protocol(&mut self, protocol: efi::Guid) -> Result<*mut c_void, EfiError> {
-        let interface = self.handles.values().find_map(|x| x.get(&OrdGuid(protocol)));
+    fn locate_protocol(&self, protocol: efi::Guid) -> Result<*mut c_void, EfiError> {
+        let interface = self
+            .protocol_index
+            .get(&OrdGuid(protocol))
+            .and_then(|index| index.values().next())
+            .and_then(|key| self.handles.get(key))
+            .and_then(|handle| handle.get(&OrdGuid(protocol)));
 
         match interface {
             Some(interface) => Ok(interface.interface),
@@ -395,17 +410,16 @@ impl ProtocolDb {
         let instance = handle_instance.get_mut(&OrdGuid(protocol)).ok_or(EfiError::Unsupported)?;
 
         let new_using_agent = OpenProtocolInformation::new(handle, agent_handle, controller_handle, attributes)?;
-        let exact_match = instance.usage.iter_mut().find(|user| user == &&new_using_agent);
-
-        if instance.opened_by_driver && exact_match.is_some() {
-            return Err(EfiError::AlreadyStarted);
-        }
-
-        if !instance.opened_by_exclusive
-            && let Some(exact_match) = exact_match
-        {
-            exact_match.open_count += 1;
-            return Ok(());
+        if let Some(exact_match) = instance.usage.iter_mut().find(|user| user == &&new_using_agent) {
+            //the agent already has the protocol open BY_DRIVER for this controller.
+            if (exact_match.attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0 {
+                return Err(EfiError::AlreadyStarted);
+            }
+            //repeated non-exclusive opens are counted in the existing record; a repeated exclusive open is denied below.
+            if (exact_match.attributes & efi::OPEN_PROTOCOL_EXCLUSIVE) == 0 {
+                exact_match.open_count += 1;
+                return Ok(());
+            }
         }
 
         const BY_DRIVER_EXCLUSIVE: u32 = efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE;
@@ -521,23 +535,32 @@ impl ProtocolDb {
     }
 
     fn register_protocol_notify(&mut self, protocol: efi::Guid, event: efi::Event) -> Result<*mut c_void, EfiError> {
-        let registration = self.next_registration as *mut c_void;
-        self.next_registration += 1;
-        let protocol_notify = ProtocolNotify { event, registration, fresh_handles: BTreeSet::new() };
+        let notify_list = self.notifications.entry(OrdGuid(protocol)).or_default();
 
-        if let Some(existing_key) = self.notifications.get_mut(&OrdGuid(protocol)) {
-            existing_key.push(protocol_notify);
-        } else {
-            let events: Vec<ProtocolNotify> = vec![protocol_notify];
-            self.notifications.insert(OrdGuid(protocol), events);
+        //registering the same event for the same protocol again would signal it twice for each install, so the
+        //existing registration is returned instead.
+        if let Some(existing) = notify_list.iter().find(|notify| notify.event == event) {
+            return Ok(existing.registration);
         }
+
+        let registration = self.next_registration as *mut c_void;
+        self.next_registration += 1;
+        notify_list.push(ProtocolNotify { event, registration, fresh_handles: BTreeSet::new() });
+        self.registrations.insert(registration as usize, OrdGuid(protocol));
         Ok(registration)
     }
 
     fn unregister_protocol_notify_event(&mut self, event: efi::Event) {
-        for (_, v) in self.notifications.iter_mut() {
-            v.retain(|x| x.event != event);
-        }
+        let registrations = &mut self.registrations;
+        self.notifications.retain(|_, v| {
+            v.retain(|x| {
+                if x.event == event {
+                    registrations.remove(&(x.registration as usize));
+                }
+                x.event != event
+            });
+            !v.is_empty()
+        });
     }
 
     fn unregister_protocol_notify_events(&mut self, events: Vec<efi::Event>) {
@@ -546,15 +569,17 @@ impl ProtocolDb {
         }
     }
 
+    fn registration_notify(&mut self, registration: *mut c_void) -> Option<&mut ProtocolNotify> {
+        let protocol = self.registrations.get(&(registration as usize))?;
+        self.notifications.get_mut(protocol)?.iter_mut().find(|notify| notify.registration == registration)
+    }
+
     fn next_handle_for_registration(&mut self, registration: *mut c_void) -> Option<efi::Handle> {
-        for (_, v) in self.notifications.iter_mut() {
-            if let Some(index) = v.iter().position(|notify| notify.registration == registration)
-                && let Some(handle) = v[index].fresh_handles.pop_first()
-            {
-                return Some(handle);
-            }
-        }
-        None
+        self.registration_notify(registration)?.fresh_handles.pop_first()
+    }
+
+    fn peek_handle_for_registration(&mut self, registration: *mut c_void) -> Option<efi::Handle> {
+        self.registration_notify(registration)?.fresh_handles.first().copied()
     }
 
     fn get_child_handles(&mut self, parent_handle: efi::Handle) -> Vec<efi::Handle> {
@@ -620,7 +645,9 @@ impl SpinLockedProtocolDb {
     pub unsafe fn reset(&self) {
         let mut inner = self.inner.lock();
         inner.handles.clear();
+        inner.protocol_index.clear();
         inner.notifications.clear();
+        inner.registrations.clear();
         inner.hash_new_handles = false;
         inner.next_handle = 1;
         inner.next_registration = 1;
@@ -714,11 +741,39 @@ impl SpinLockedProtocolDb {
         self.lock().locate_handles(protocol)
     }
 
+    /// Returns an iterator over the handles that have the protocol `P` installed on them.
+    ///
+    /// The handles are collected when this is called, so the iterator does not hold the database lock and is not
+    /// affected by protocols installed or uninstalled while it is in use. The iterator is empty if no handles have the
+    /// protocol installed.
+    pub fn handles_with<P: ProtocolInterface>(&self) -> impl Iterator<Item = efi::Handle> + use<P> {
+        self.locate_handles(Some(P::PROTOCOL_GUID)).unwrap_or_default().into_iter()
+    }
+
+    /// Returns an iterator over the handles that have the protocol `P` installed on them along with the interface of
+    /// `P` on each handle.
+    ///
+    /// As with [`handles_with`](Self::handles_with), the handles and interfaces are collected when this is called.
+    /// Dereferencing an interface is unsafe; the interface is only valid while it remains installed on the handle.
+    pub fn interfaces_with<P: ProtocolInterface>(&self) -> impl Iterator<Item = (efi::Handle, *mut P)> + use<P> {
+        let db = self.lock();
+        let interfaces: Vec<_> = db
+            .locate_handles(Some(P::PROTOCOL_GUID))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|handle| {
+                let interface = db.get_interface_for_handle(handle, P::PROTOCOL_GUID).ok()?;
+                Some((handle, interface as *mut P))
+            })
+            .collect();
+        interfaces.into_iter()
+    }
+
     /// Returns an instance of the specified protocol interface from any handle.
     ///
     /// On success, this function returns the protocol interface pointer for the given protocol from any handle. If
-    /// multiple handles exist with this protocol installed on them, no guarantees are made about which handle the
-    /// interface will come from.
+    /// multiple handles exist with this protocol installed on them, the interface on the earliest created handle is
+    /// returned.
     ///
     /// ## Errors
     ///
@@ -757,8 +812,8 @@ impl SpinLockedProtocolDb {
     ///
     /// Returns [`INVALID_PARAMETER`](r_efi::efi::Status::INVALID_PARAMETER) if incorrect parameters are given.
     /// Returns [`NOT_FOUND`](r_efi::efi::Status::NOT_FOUND) if no matching interfaces are found.
-    /// Returns [`ALREADY_STARTED`](r_efi::efi::Status::ALREADY_STARTED) if attributes is BY_DRIVER and there is an
-    ///     existing usage by the agent handle.
+    /// Returns [`ALREADY_STARTED`](r_efi::efi::Status::ALREADY_STARTED) if attributes is BY_DRIVER and the agent handle
+    ///     already has the protocol open with the same attributes for the same controller.
     /// Returns [`ACCESS_DENIED`](r_efi::efi::Status::ACCESS_DENIED) if attributes is efi::OPEN_PROTOCOL_BY_DRIVER |
     ///     efi::OPEN_PROTOCOL_EXCLUSIVE | BY_DRIVER_EXCLUSIVE and there is an existing usage that conflicts with those
     ///     attributes.
@@ -843,14 +898,16 @@ impl SpinLockedProtocolDb {
     /// so that the caller can fire the events.
     ///
     /// Returns a registration token that can be used with [next_handle_for_registration](SpinLockedProtocolDb::next_handle_for_registration)
-    /// to iterate over handles that have fresh installations of the specified protocol.
+    /// to iterate over handles that have fresh installations of the specified protocol. If the event is already
+    /// registered for the protocol, the existing registration token is returned.
     pub fn register_protocol_notify(&self, protocol: efi::Guid, event: efi::Event) -> Result<*mut c_void, EfiError> {
         self.lock().register_protocol_notify(protocol, event)
     }
 
     /// De-registers a list of previously installed protocol notifies.
     ///
-    /// This can be used by the caller to remove previously registered event notifications.
+    /// This can be used by the caller to remove previously registered event notifications, such as when the events are
+    /// closed. The registration tokens of the events are no longer valid afterwards.
     pub fn unregister_protocol_notify_events(&self, events: Vec<efi::Event>) {
         self.lock().unregister_protocol_notify_events(events);
     }
@@ -860,6 +917,30 @@ impl SpinLockedProtocolDb {
         self.lock().next_handle_for_registration(registration)
     }
 
+    /// Returns the handle that [next_handle_for_registration](SpinLockedProtocolDb::next_handle_for_registration)
+    /// would return next for the registration, without consuming it.
+    pub fn peek_handle_for_registration(&self, registration: *mut c_void) -> Option<efi::Handle> {
+        self.lock().peek_handle_for_registration(registration)
+    }
+
+    /// Calls `f` with the handle, protocol, interface and open protocol information of every protocol instance in the
+    /// database, grouped by handle.
+    ///
+    /// Returns [`EfiError::AccessDenied`] without calling `f` if the database is locked, as it is when a crash
+    /// interrupts an update.
+    pub fn try_for_each_protocol(
+        &self,
+        mut f: impl FnMut(efi::Handle, efi::Guid, *mut c_void, &[OpenProtocolInformation]),
+    ) -> Result<(), EfiError> {
+        let db = self.inner.try_lock().ok_or(EfiError::AccessDenied)?;
+        for (&key, handle) in db.handles.iter() {
+            for (guid, instance) in handle.iter() {
+                f(key as efi::Handle, guid.0, instance.interface, &instance.usage);
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a vector of controller handles that have parent_handle open BY_CHILD_CONTROLLER.
     pub fn get_child_handles(&self, parent_handle: efi::Handle) -> Vec<efi::Handle> {
         self.lock().get_child_handles(parent_handle)
@@ -1111,6 +1192,82 @@ mod tests {
         });
     }
 
+    #[test]
+    fn handles_with_should_iterate_handles_and_interfaces_of_a_protocol() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_PROTOCOL_DB: SpinLockedProtocolDb = SpinLockedProtocolDb::new();
+
+            SPIN_LOCKED_PROTOCOL_DB.lock().enable_handle_hashing();
+
+            use efi::protocols::{block_io, driver_binding};
+
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.handles_with::<block_io::Protocol>().count(), 0);
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.interfaces_with::<block_io::Protocol>().count(), 0);
+
+            let (handle1, _) =
+                SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, block_io::PROTOCOL_GUID, 0x10 as _).unwrap();
+            let (handle2, _) = SPIN_LOCKED_PROTOCOL_DB
+                .install_protocol_interface(None, driver_binding::PROTOCOL_GUID, 0x20 as _)
+                .unwrap();
+            let (handle3, _) =
+                SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, block_io::PROTOCOL_GUID, 0x30 as _).unwrap();
+
+            assert_eq!(
+                SPIN_LOCKED_PROTOCOL_DB.handles_with::<block_io::Protocol>().collect::<Vec<_>>(),
+                vec![handle1, handle3]
+            );
+            assert_eq!(
+                SPIN_LOCKED_PROTOCOL_DB.handles_with::<driver_binding::Protocol>().collect::<Vec<_>>(),
+                vec![handle2]
+            );
+            assert_eq!(
+                SPIN_LOCKED_PROTOCOL_DB.interfaces_with::<block_io::Protocol>().collect::<Vec<_>>(),
+                vec![(handle1, 0x10 as *mut block_io::Protocol), (handle3, 0x30 as *mut block_io::Protocol)]
+            );
+
+            // the iterator is a snapshot, so the database can be changed while iterating.
+            for handle in SPIN_LOCKED_PROTOCOL_DB.handles_with::<block_io::Protocol>() {
+                let interface =
+                    SPIN_LOCKED_PROTOCOL_DB.get_interface_for_handle(handle, block_io::PROTOCOL_GUID).unwrap();
+                SPIN_LOCKED_PROTOCOL_DB
+                    .uninstall_protocol_interface(handle, block_io::PROTOCOL_GUID, interface)
+                    .unwrap();
+            }
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.handles_with::<block_io::Protocol>().count(), 0);
+        });
+    }
+
+    #[test]
+    fn protocol_lookups_should_reflect_uninstalls() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_PROTOCOL_DB: SpinLockedProtocolDb = SpinLockedProtocolDb::new();
+
+            SPIN_LOCKED_PROTOCOL_DB.lock().enable_handle_hashing();
+
+            let uuid1 = Uuid::from_str("0e896c7a-57dc-4987-bc22-abc3a8263210").unwrap();
+            let guid1 = efi::Guid::from_bytes(uuid1.as_bytes());
+            let uuid2 = Uuid::from_str("98d32ea1-e980-46b5-bb2c-564934c8cce6").unwrap();
+            let guid2 = efi::Guid::from_bytes(uuid2.as_bytes());
+
+            let (handle1, _) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, 0x1 as _).unwrap();
+            let (handle2, _) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, 0x2 as _).unwrap();
+            SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(Some(handle1), guid2, 0x3 as _).unwrap();
+
+            // the interface on the earliest created handle is located.
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.locate_protocol(guid1), Ok(0x1 as _));
+
+            SPIN_LOCKED_PROTOCOL_DB.uninstall_protocol_interface(handle1, guid1, 0x1 as _).unwrap();
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.locate_protocol(guid1), Ok(0x2 as _));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.locate_handles(Some(guid1)), Ok(vec![handle2]));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.locate_handles(Some(guid2)), Ok(vec![handle1]));
+
+            SPIN_LOCKED_PROTOCOL_DB.uninstall_protocol_interface(handle2, guid1, 0x2 as _).unwrap();
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.locate_protocol(guid1), Err(EfiError::NotFound));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.locate_handles(Some(guid1)), Err(EfiError::NotFound));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.registered_protocols(), vec![guid2]);
+        });
+    }
+
     #[test]
     fn validate_handle_should_validate_good_handles_and_reject_bad_ones() {
         with_locked_state(|| {
@@ -1463,6 +1620,59 @@ mod tests {
         drop(protocol_db);
     }
 
+    #[test]
+    fn add_protocol_usage_should_count_repeated_opens_alongside_driver_and_exclusive_usages() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_PROTOCOL_DB: SpinLockedProtocolDb = SpinLockedProtocolDb::new();
+
+            let uuid1 = Uuid::from_str("0e896c7a-57dc-4987-bc22-abc3a8263210").unwrap();
+            let guid1 = efi::Guid::from_bytes(uuid1.as_bytes());
+            let interface1: *mut c_void = 0x1234 as *mut c_void;
+
+            let (handle1, _) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+            let (handle2, _) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+            let (handle3, _) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+            let (handle4, _) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+
+            for attributes in [efi::OPEN_PROTOCOL_BY_DRIVER, efi::OPEN_PROTOCOL_EXCLUSIVE] {
+                SPIN_LOCKED_PROTOCOL_DB
+                    .add_protocol_usage(handle1, guid1, Some(handle2), Some(handle3), attributes)
+                    .unwrap();
+
+                //Repeated GET_PROTOCOL opens by another agent are counted in a single record, not ALREADY_STARTED.
+                for _ in 0..2 {
+                    SPIN_LOCKED_PROTOCOL_DB
+                        .add_protocol_usage(handle1, guid1, Some(handle4), None, efi::OPEN_PROTOCOL_GET_PROTOCOL)
+                        .unwrap();
+                }
+                let protocol_db = SPIN_LOCKED_PROTOCOL_DB.lock();
+                let protocol_user_list =
+                    &protocol_db.handles.get(&(handle1 as usize)).unwrap().get(&OrdGuid(guid1)).unwrap().usage;
+                assert_eq!(2, protocol_user_list.len());
+                assert_eq!(efi::OPEN_PROTOCOL_GET_PROTOCOL, protocol_user_list[1].attributes);
+                assert_eq!(2, protocol_user_list[1].open_count);
+                drop(protocol_db);
+
+                //A repeated exclusive open is denied rather than counted.
+                if attributes == efi::OPEN_PROTOCOL_EXCLUSIVE {
+                    let result = SPIN_LOCKED_PROTOCOL_DB.add_protocol_usage(
+                        handle1,
+                        guid1,
+                        Some(handle2),
+                        Some(handle3),
+                        attributes,
+                    );
+                    assert_eq!(result, Err(EfiError::AccessDenied));
+                }
+
+                SPIN_LOCKED_PROTOCOL_DB
+                    .remove_protocol_usage(handle1, guid1, Some(handle2), Some(handle3), None)
+                    .unwrap();
+                SPIN_LOCKED_PROTOCOL_DB.remove_protocol_usage(handle1, guid1, Some(handle4), None, None).unwrap();
+            }
+        });
+    }
+
     #[test]
     fn add_protocol_usage_by_handle_get_or_test() {
         with_locked_state(|| {
@@ -1979,6 +2189,76 @@ mod tests {
         });
     }
 
+    #[test]
+    fn register_protocol_notify_should_coalesce_duplicate_registrations() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_PROTOCOL_DB: SpinLockedProtocolDb = SpinLockedProtocolDb::new();
+
+            let uuid1 = Uuid::from_str("0e896c7a-57dc-4987-bc22-abc3a8263210").unwrap();
+            let guid1 = efi::Guid::from_bytes(uuid1.as_bytes());
+            let uuid2 = Uuid::from_str("98d32ea1-e980-46b5-bb2a-a3b5c8e4d1f0").unwrap();
+            let guid2 = efi::Guid::from_bytes(uuid2.as_bytes());
+            let interface1: *mut c_void = 0x1234 as *mut c_void;
+
+            let event = 0x8765 as *mut c_void;
+            let reg1 = SPIN_LOCKED_PROTOCOL_DB.register_protocol_notify(guid1, event).unwrap();
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.register_protocol_notify(guid1, event).unwrap(), reg1);
+
+            //the same event registered for another protocol is a separate registration.
+            let reg2 = SPIN_LOCKED_PROTOCOL_DB.register_protocol_notify(guid2, event).unwrap();
+            assert_ne!(reg1, reg2);
+
+            let (_, notifies) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+            assert_eq!(notifies.len(), 1);
+        });
+    }
+
+    #[test]
+    fn peek_handle_for_registration_should_not_consume_the_handle() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_PROTOCOL_DB: SpinLockedProtocolDb = SpinLockedProtocolDb::new();
+
+            let uuid1 = Uuid::from_str("0e896c7a-57dc-4987-bc22-abc3a8263210").unwrap();
+            let guid1 = efi::Guid::from_bytes(uuid1.as_bytes());
+            let interface1: *mut c_void = 0x1234 as *mut c_void;
+
+            let event = 0x8765 as *mut c_void;
+            let reg1 = SPIN_LOCKED_PROTOCOL_DB.register_protocol_notify(guid1, event).unwrap();
+            let hnd1 = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap().0;
+
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.peek_handle_for_registration(reg1), Some(hnd1));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.peek_handle_for_registration(reg1), Some(hnd1));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.next_handle_for_registration(reg1), Some(hnd1));
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.peek_handle_for_registration(reg1), None);
+
+            //unknown registration keys have no handles.
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.peek_handle_for_registration(0x5555 as *mut c_void), None);
+        });
+    }
+
+    #[test]
+    fn unregister_protocol_notify_events_should_invalidate_registrations() {
+        with_locked_state(|| {
+            static SPIN_LOCKED_PROTOCOL_DB: SpinLockedProtocolDb = SpinLockedProtocolDb::new();
+
+            let uuid1 = Uuid::from_str("0e896c7a-57dc-4987-bc22-abc3a8263210").unwrap();
+            let guid1 = efi::Guid::from_bytes(uuid1.as_bytes());
+            let interface1: *mut c_void = 0x1234 as *mut c_void;
+
+            let event = 0x8765 as *mut c_void;
+            let reg1 = SPIN_LOCKED_PROTOCOL_DB.register_protocol_notify(guid1, event).unwrap();
+            SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+
+            SPIN_LOCKED_PROTOCOL_DB.unregister_protocol_notify_events(vec![event]);
+            assert_eq!(SPIN_LOCKED_PROTOCOL_DB.next_handle_for_registration(reg1), None);
+            assert!(SPIN_LOCKED_PROTOCOL_DB.lock().notifications.is_empty());
+            assert!(SPIN_LOCKED_PROTOCOL_DB.lock().registrations.is_empty());
+
+            let (_, notifies) = SPIN_LOCKED_PROTOCOL_DB.install_protocol_interface(None, guid1, interface1).unwrap();
+            assert!(notifies.is_empty());
+        });
+    }
+
     #[test]
     fn next_handle_for_registration_should_return_next_handle_for_registration() {
         with_locked_state(|| {
@@ -0,0 +1,311 @@
+//! Protocol Database Snapshot
+//!
+//! Captures the handles, installed protocols, interface pointers and open protocol information of the protocol
+//! database as a compact binary blob, so that offline tooling can reconstruct the state of the system when analyzing
+//! a failure.
+//!
+//! The snapshot is available at any time through the `protocol_db` debugger monitor command, which prints it as hex,
+//! including while the debugger is stopped on an exception. The `protocols` monitor command prints the same information
+//! as text, with protocol names, and `handles <guid>` lists the handles a single protocol is installed on. Platforms can
+//! also register [ProtocolDbSnapshotPolicy] to publish a snapshot taken at ReadyToBoot as a configuration table with
+//! [PROTOCOL_DB_SNAPSHOT_TABLE_GUID].
+//!
+//! The snapshot is a [ProtocolDbSnapshotHeader] followed by one record per installed protocol, grouped by handle. All
+//! fields are little endian:
+//!
+//! | Field             | Size | Description                                            |
+//! |-------------------|------|--------------------------------------------------------|
+//! | handle            | 8    | The handle the protocol is installed on.               |
+//! | protocol          | 16   | The protocol GUID.                                     |
+//! | interface         | 8    | The interface pointer.                                 |
+//! | open_entry_count  | 4    | The number of open protocol information entries.       |
+//!
+//! Each record is followed by its open protocol information entries:
+//!
+//! | Field             | Size | Description                                            |
+//! |-------------------|------|--------------------------------------------------------|
+//! | agent_handle      | 8    | The agent that opened the protocol, or zero.           |
+//! | controller_handle | 8    | The controller the protocol was opened for, or zero.   |
+//! | attributes        | 4    | The `EFI_OPEN_PROTOCOL_*` attributes.                  |
+//! | open_count        | 4    | The number of times the protocol was opened this way.  |
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::{ffi::c_void, mem::size_of};
+
+use patina::{OwnedGuid, error::EfiError};
+use r_efi::efi;
+
+use crate::{
+    config_tables, events::EVENT_DB, guid_names, protocol_db::OpenProtocolInformation, protocols::PROTOCOL_DB,
+    systemtables,
+};
+
+/// The GUID of the configuration table containing the protocol database snapshot taken at ReadyToBoot.
+pub const PROTOCOL_DB_SNAPSHOT_TABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0x6c1d0f52, 0x93a8, 0x4e2b, 0x8d, 0x47, &[0x2f, 0x61, 0xc3, 0x0b, 0x5e, 0x94]);
+
+/// Whether the core publishes a snapshot of the protocol database at ReadyToBoot.
+///
+/// No snapshot is published unless the platform registers this config. The `protocol_db` debugger monitor command is
+/// available either way.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ProtocolDbSnapshotPolicy};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ProtocolDbSnapshotPolicy { publish_at_ready_to_boot: true })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtocolDbSnapshotPolicy {
+    /// Publish a snapshot taken at ReadyToBoot as a configuration table with [PROTOCOL_DB_SNAPSHOT_TABLE_GUID].
+    pub publish_at_ready_to_boot: bool,
+}
+
+/// The header of a protocol database snapshot.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProtocolDbSnapshotHeader {
+    /// `PDBS`
+    pub signature: u32,
+    /// The version of the snapshot format.
+    pub version: u32,
+    /// The length of the snapshot, including the header.
+    pub length: u32,
+    /// The number of protocol records following the header.
+    pub record_count: u32,
+}
+
+impl ProtocolDbSnapshotHeader {
+    /// The signature of the snapshot.
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"PDBS");
+    /// The current version of the snapshot format.
+    pub const VERSION: u32 = 1;
+}
+
+// Appends the record for one protocol instance to `snapshot`.
+fn push_record(
+    snapshot: &mut Vec<u8>,
+    handle: efi::Handle,
+    protocol: efi::Guid,
+    interface: *mut c_void,
+    usage: &[OpenProtocolInformation],
+) {
+    snapshot.extend_from_slice(&(handle as u64).to_le_bytes());
+    snapshot.extend_from_slice(protocol.as_bytes());
+    snapshot.extend_from_slice(&(interface as u64).to_le_bytes());
+    snapshot.extend_from_slice(&(usage.len() as u32).to_le_bytes());
+    for entry in usage {
+        snapshot.extend_from_slice(&(entry.agent_handle.unwrap_or(core::ptr::null_mut()) as u64).to_le_bytes());
+        snapshot.extend_from_slice(&(entry.controller_handle.unwrap_or(core::ptr::null_mut()) as u64).to_le_bytes());
+        snapshot.extend_from_slice(&entry.attributes.to_le_bytes());
+        snapshot.extend_from_slice(&entry.open_count.to_le_bytes());
+    }
+}
+
+/// Takes a snapshot of the protocol database.
+///
+/// ## Errors
+///
+/// Returns [`EfiError::AccessDenied`] if the protocol database is locked.
+pub(crate) fn snapshot() -> Result<Vec<u8>, EfiError> {
+    let mut snapshot = alloc::vec![0; size_of::<ProtocolDbSnapshotHeader>()];
+    let mut record_count = 0u32;
+    PROTOCOL_DB.try_for_each_protocol(|handle, protocol, interface, usage| {
+        push_record(&mut snapshot, handle, protocol, interface, usage);
+        record_count += 1;
+    })?;
+
+    let header = ProtocolDbSnapshotHeader {
+        signature: ProtocolDbSnapshotHeader::SIGNATURE,
+        version: ProtocolDbSnapshotHeader::VERSION,
+        length: snapshot.len() as u32,
+        record_count,
+    };
+    for (i, field) in [header.signature, header.version, header.length, header.record_count].into_iter().enumerate() {
+        snapshot[i * 4..i * 4 + 4].copy_from_slice(&field.to_le_bytes());
+    }
+    Ok(snapshot)
+}
+
+extern "efiapi" fn publish_snapshot_event(event: efi::Event, _context: *mut c_void) {
+    match snapshot() {
+        Ok(snapshot) => {
+            let table = snapshot.leak();
+            let mut st = systemtables::SYSTEM_TABLE.lock();
+            let st = st.as_mut().expect("System Table not initialized!");
+            match config_tables::core_install_configuration_table(
+                PROTOCOL_DB_SNAPSHOT_TABLE_GUID,
+                table.as_mut_ptr() as *mut c_void,
+                st,
+            ) {
+                Ok(()) => log::info!("Published a {} byte protocol database snapshot.", table.len()),
+                Err(err) => log::error!("Failed to publish the protocol database snapshot: {err:?}"),
+            }
+        }
+        Err(err) => log::error!("Failed to take a protocol database snapshot: {err:?}"),
+    }
+
+    if let Err(status) = EVENT_DB.close_event(event) {
+        log::error!("Failed to close the protocol database snapshot event: {status:?}");
+    }
+}
+
+/// Registers the `protocol_db` monitor command and, if the policy asks for it, the ReadyToBoot snapshot.
+pub(crate) fn init_protocol_db_snapshot(policy: &ProtocolDbSnapshotPolicy) {
+    patina_debugger::add_monitor_command("protocol_db", "Prints a protocol database snapshot as hex", |_, out| {
+        match snapshot() {
+            Ok(snapshot) => {
+                for line in snapshot.chunks(32) {
+                    line.iter().for_each(|byte| {
+                        let _ = write!(out, "{byte:02x}");
+                    });
+                    let _ = out.write_str("\n");
+                }
+            }
+            Err(err) => {
+                let _ = write!(out, "Protocol database is locked: {err:?}");
+            }
+        }
+    });
+
+    patina_debugger::add_monitor_command("protocols", "Prints the installed protocols by handle", |_, out| {
+        let result = PROTOCOL_DB.try_for_each_protocol(|handle, protocol, interface, usage| {
+            let _ = writeln!(out, "{handle:p}: {} @ {interface:p}", guid_names::named(&protocol));
+            for entry in usage {
+                let _ = writeln!(
+                    out,
+                    "    opened by {:?} for {:?} with attributes {:#x}, count {}",
+                    entry.agent_handle, entry.controller_handle, entry.attributes, entry.open_count
+                );
+            }
+        });
+        if let Err(err) = result {
+            let _ = write!(out, "Protocol database is locked: {err:?}");
+        }
+    });
+
+    patina_debugger::add_monitor_command(
+        "handles",
+        "Prints the handles with a protocol: handles <guid>",
+        |args, out| {
+            let Some(Ok(protocol)) = args.next().map(OwnedGuid::try_from_string) else {
+                let _ = write!(out, "Usage: handles <guid>");
+                return;
+            };
+            let protocol = protocol.to_efi_guid();
+            let _ = writeln!(out, "{}", guid_names::named(&protocol));
+            let result = PROTOCOL_DB.try_for_each_protocol(|handle, installed, interface, _| {
+                if installed == protocol {
+                    let _ = writeln!(out, "{handle:p}: {interface:p}");
+                }
+            });
+            if let Err(err) = result {
+                let _ = write!(out, "Protocol database is locked: {err:?}");
+            }
+        },
+    );
+
+    if !policy.publish_at_ready_to_boot {
+        return;
+    }
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(publish_snapshot_event),
+        None,
+        Some(efi::EVENT_GROUP_READY_TO_BOOT),
+    ) {
+        log::error!(
+            "Failed to register an event at Ready to Boot to publish the protocol database snapshot! Status {status:#X?}"
+        );
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::{protocols::core_install_protocol_interface, test_support};
+
+    const PROTOCOL_A: efi::Guid =
+        efi::Guid::from_fields(0x2b8e_41c7, 0x5a0d, 0x4f63, 0xb1, 0x9e, &[0x70, 0x15, 0xd2, 0x4c, 0x88, 0x01]);
+    const PROTOCOL_B: efi::Guid =
+        efi::Guid::from_fields(0x2b8e_41c7, 0x5a0d, 0x4f63, 0xb1, 0x9e, &[0x70, 0x15, 0xd2, 0x4c, 0x88, 0x02]);
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_snapshot_records_protocols_and_usage() {
+        test_support::with_global_lock(|| {
+            unsafe { test_support::init_test_protocol_db() };
+            let well_known = snapshot().unwrap();
+            let well_known_records = read_u32(&well_known, 12);
+
+            let handle = core_install_protocol_interface(None, PROTOCOL_A, 0x1000 as *mut c_void).unwrap();
+            core_install_protocol_interface(Some(handle), PROTOCOL_B, 0x2000 as *mut c_void).unwrap();
+            let agent = core_install_protocol_interface(None, PROTOCOL_A, 0x3000 as *mut c_void).unwrap();
+            PROTOCOL_DB
+                .add_protocol_usage(handle, PROTOCOL_B, Some(agent), Some(handle), efi::OPEN_PROTOCOL_BY_DRIVER)
+                .unwrap();
+
+            let snapshot = snapshot().unwrap();
+            assert_eq!(&snapshot[0..4], b"PDBS");
+            assert_eq!(read_u32(&snapshot, 4), ProtocolDbSnapshotHeader::VERSION);
+            assert_eq!(read_u32(&snapshot, 8) as usize, snapshot.len());
+            assert_eq!(read_u32(&snapshot, 12), well_known_records + 3);
+
+            // walk the records, collecting those on the new handle.
+            let mut offset = size_of::<ProtocolDbSnapshotHeader>();
+            let mut records = Vec::new();
+            while offset < snapshot.len() {
+                let record_handle = read_u64(&snapshot, offset);
+                let protocol = efi::Guid::from_bytes(snapshot[offset + 8..offset + 24].try_into().unwrap());
+                let interface = read_u64(&snapshot, offset + 24);
+                let entry_count = read_u32(&snapshot, offset + 32) as usize;
+                let entries = (0..entry_count)
+                    .map(|i| {
+                        let entry = offset + 36 + i * 24;
+                        (
+                            read_u64(&snapshot, entry),
+                            read_u64(&snapshot, entry + 8),
+                            read_u32(&snapshot, entry + 16),
+                            read_u32(&snapshot, entry + 20),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                if record_handle == handle as u64 {
+                    records.push((protocol, interface, entries));
+                }
+                offset += 36 + entry_count * 24;
+            }
+            assert_eq!(offset, snapshot.len());
+
+            records.sort_by_key(|(_, interface, _)| *interface);
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0], (PROTOCOL_A, 0x1000, Vec::new()));
+            assert_eq!(
+                records[1],
+                (PROTOCOL_B, 0x2000, alloc::vec![(agent as u64, handle as u64, efi::OPEN_PROTOCOL_BY_DRIVER, 1)])
+            );
+        })
+        .unwrap();
+    }
+}
@@ -9,7 +9,6 @@
 use core::{ffi::c_void, mem::size_of};
 
 use alloc::{slice, vec, vec::Vec};
-use mu_rust_helpers::guid::guid_fmt;
 use patina::error::EfiError;
 use patina_internal_device_path::{is_device_path_end, remaining_device_path};
 use r_efi::efi;
@@ -19,6 +18,7 @@ use crate::{
     allocator::core_allocate_pool,
     driver_services::{core_connect_controller, core_disconnect_controller},
     events::{EVENT_DB, signal_event},
+    guid_names,
     protocol_db::{DXE_CORE_HANDLE, SpinLockedProtocolDb},
     tpl_lock,
 };
@@ -30,7 +30,7 @@ pub fn core_install_protocol_interface(
     protocol: efi::Guid,
     interface: *mut c_void,
 ) -> Result<efi::Handle, EfiError> {
-    log::info!("InstallProtocolInterface: {:?} @ {:#x?}", guid_fmt!(protocol), interface);
+    log::info!("InstallProtocolInterface: {:?} @ {:#x?}", guid_names::named(&protocol), interface);
     let (handle, notifies) = PROTOCOL_DB.install_protocol_interface(handle, protocol, interface)?;
 
     let mut closed_events = Vec::new();
@@ -77,7 +77,7 @@ pub fn core_uninstall_protocol_interface(
     protocol: efi::Guid,
     interface: *mut c_void,
 ) -> Result<(), EfiError> {
-    log::info!("UninstallProtocolInterface: {:?} @ {:#x?}", guid_fmt!(protocol), interface);
+    log::info!("UninstallProtocolInterface: {:?} @ {:#x?}", guid_names::named(&protocol), interface);
 
     // Check if the handle/protocol/interface triple is legitimate
     match PROTOCOL_DB.get_interface_for_handle(handle, protocol) {
@@ -273,7 +273,8 @@ extern "efiapi" fn locate_handle(
             if search_key.is_null() {
                 return efi::Status::INVALID_PARAMETER;
             }
-            if let Some(handle) = PROTOCOL_DB.next_handle_for_registration(search_key) {
+            // the handle is only consumed once it is returned, so that a call to get the buffer size does not lose it.
+            if let Some(handle) = PROTOCOL_DB.peek_handle_for_registration(search_key) {
                 Ok(vec![handle])
             } else {
                 Err(EfiError::NotFound)
@@ -322,6 +323,10 @@ extern "efiapi" fn locate_handle(
                 );
             }
 
+            if search_type == efi::BY_REGISTER_NOTIFY {
+                PROTOCOL_DB.next_handle_for_registration(search_key);
+            }
+
             efi::Status::SUCCESS
         }
     }
@@ -357,8 +362,13 @@ extern "efiapi" fn open_protocol(
     // Safety: Caller must ensure that protocol is a valid pointer. It is null-checked above.
     let protocol = unsafe { protocol.read_unaligned() };
 
-    if interface.is_null() && attributes != efi::OPEN_PROTOCOL_TEST_PROTOCOL {
-        return efi::Status::INVALID_PARAMETER;
+    if attributes != efi::OPEN_PROTOCOL_TEST_PROTOCOL {
+        if interface.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        // the interface is returned as null on every error except ALREADY_STARTED.
+        // Safety: Caller must ensure that interface is a valid pointer. It is null-checked above.
+        unsafe { interface.write_unaligned(core::ptr::null_mut()) };
     }
 
     let agent_handle = PROTOCOL_DB.validate_handle(agent_handle).map_or_else(|_err| None, |_ok| Some(agent_handle));
@@ -366,36 +376,23 @@ extern "efiapi" fn open_protocol(
     let controller_handle =
         PROTOCOL_DB.validate_handle(controller_handle).map_or_else(|_err| None, |_ok| Some(controller_handle));
 
-    // if attributes has exclusive flag set, then attempt to disconnect any other drivers that have the requested protocol
+    // if attributes has exclusive flag set, then attempt to disconnect the drivers that have the requested protocol
     // open on this handle BY_DRIVER.
-    if (attributes & efi::OPEN_PROTOCOL_EXCLUSIVE) != 0 {
-        let usages = match PROTOCOL_DB.get_open_protocol_information_by_protocol(handle, protocol) {
-            Err(EfiError::NotFound) => Vec::new(),
-            Err(err) => return err.into(),
-            Ok(usages) => usages,
-        };
-        if let Some(usage) = usages.iter().find(|x| {
-            (x.attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0
-                && (x.attributes & efi::OPEN_PROTOCOL_EXCLUSIVE) == 0
-                && x.agent_handle != agent_handle
-        }) {
-            // Safety: handles are validated above.
-            unsafe {
-                if core_disconnect_controller(handle, usage.agent_handle, None).is_err() {
-                    return efi::Status::ACCESS_DENIED;
-                }
-            }
-        }
+    const BY_DRIVER_EXCLUSIVE: u32 = efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE;
+    let exclusive_request = match attributes {
+        efi::OPEN_PROTOCOL_EXCLUSIVE => agent_handle.is_some(),
+        BY_DRIVER_EXCLUSIVE => agent_handle.is_some() && controller_handle.is_some(),
+        _ => false,
+    };
+    if exclusive_request
+        && let Err(err) =
+            disconnect_drivers_for_exclusive_open(handle, protocol, agent_handle, controller_handle, attributes)
+    {
+        return err.into();
     }
 
     match PROTOCOL_DB.add_protocol_usage(handle, protocol, agent_handle, controller_handle, attributes) {
-        Err(EfiError::Unsupported) => {
-            if !interface.is_null() {
-                // Safety: Caller must ensure that interface is a valid pointer if it is non-null.
-                unsafe { interface.write_unaligned(core::ptr::null_mut()) };
-            }
-            return efi::Status::UNSUPPORTED;
-        }
+        Err(EfiError::Unsupported) => return efi::Status::UNSUPPORTED,
         Err(EfiError::AlreadyStarted) if (attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0 => {
             //For already started interface is still returned.
             let desired_interface = PROTOCOL_DB
@@ -424,6 +421,53 @@ extern "efiapi" fn open_protocol(
     efi::Status::SUCCESS
 }
 
+// disconnects the drivers that have the protocol on the handle open BY_DRIVER, so that it can be opened EXCLUSIVE.
+// Fails with ACCESS_DENIED if the protocol is already open EXCLUSIVE, or a driver cannot be disconnected.
+fn disconnect_drivers_for_exclusive_open(
+    handle: efi::Handle,
+    protocol: efi::Guid,
+    agent_handle: Option<efi::Handle>,
+    controller_handle: Option<efi::Handle>,
+    attributes: u32,
+) -> Result<(), EfiError> {
+    let mut disconnected_agents = Vec::new();
+    loop {
+        // each disconnect may close other usages, so the usages are read again after every disconnect.
+        let usages = match PROTOCOL_DB.get_open_protocol_information_by_protocol(handle, protocol) {
+            Err(EfiError::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+            Ok(usages) => usages,
+        };
+        // the agent already has the protocol open BY_DRIVER with these attributes; ALREADY_STARTED is returned for it.
+        if (attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0
+            && usages.iter().any(|x| {
+                x.agent_handle == agent_handle && x.controller_handle == controller_handle && x.attributes == attributes
+            })
+        {
+            return Ok(());
+        }
+        if usages.iter().any(|x| (x.attributes & efi::OPEN_PROTOCOL_EXCLUSIVE) != 0) {
+            return Err(EfiError::AccessDenied);
+        }
+        let Some(agent_handle) =
+            usages.iter().find(|x| (x.attributes & efi::OPEN_PROTOCOL_BY_DRIVER) != 0).map(|x| x.agent_handle)
+        else {
+            return Ok(());
+        };
+
+        // a driver that stops successfully but keeps the protocol open would otherwise be disconnected forever.
+        if disconnected_agents.contains(&agent_handle) {
+            return Err(EfiError::AccessDenied);
+        }
+        disconnected_agents.push(agent_handle);
+
+        // Safety: the handles are taken from the protocol database.
+        if unsafe { core_disconnect_controller(handle, agent_handle, None) }.is_err() {
+            return Err(EfiError::AccessDenied);
+        }
+    }
+}
+
 extern "efiapi" fn close_protocol(
     handle: efi::Handle,
     protocol: *mut efi::Guid,
@@ -499,7 +543,41 @@ extern "efiapi" fn open_protocol_information(
     }
 }
 
-unsafe extern "C" fn install_multiple_protocol_interfaces(handle: *mut efi::Handle, mut args: ...) -> efi::Status {
+// Returns true if `device_path` is already installed on some handle, which makes a second install a duplicate.
+fn device_path_installed(device_path: *mut c_void) -> bool {
+    matches!(
+        core_locate_device_path(
+            efi::protocols::device_path::PROTOCOL_GUID,
+            device_path as *const efi::protocols::device_path::Protocol,
+        ),
+        Ok((remaining_path, handle)) if PROTOCOL_DB.validate_handle(handle).is_ok() && is_device_path_end(remaining_path)
+    )
+}
+
+// Returns true if any protocol appears more than once in `interfaces`.
+fn has_duplicate_protocols(interfaces: &[(efi::Guid, *mut c_void)]) -> bool {
+    interfaces
+        .iter()
+        .enumerate()
+        .any(|(idx, (protocol, _))| interfaces[..idx].iter().any(|(other, _)| other == protocol))
+}
+
+/// Installs `interfaces` on `handle`, or on a new handle if `handle` is `None`, as a single transaction.
+///
+/// Either every interface is installed, or none are and the handle is left as it was. The list is checked before
+/// anything is installed: a protocol may appear only once, may not already be on `handle`, and a device path may not
+/// already be installed on another handle. Protocol notifies are deferred until all interfaces are installed.
+///
+/// ## Errors
+///
+/// Returns [`EfiError::InvalidParameter`] if `interfaces` is empty, `handle` is invalid, or a protocol is repeated or
+/// already installed on `handle`.
+/// Returns [`EfiError::AlreadyStarted`] if a device path in `interfaces` is already installed.
+/// Returns the error from installing an interface if it fails, after the installed interfaces are removed again.
+pub fn core_install_multiple_protocol_interfaces(
+    handle: Option<efi::Handle>,
+    interfaces: &[(efi::Guid, *mut c_void)],
+) -> Result<efi::Handle, EfiError> {
     // The UEFI spec does not indicate whether the protocols installed here are atomic with respect to notify  - i.e.
     // whether any registered notifies should be invoked between the installation of the multiple protocols, or only
     // after all protocols are installed. Despite the spec ambiguity, the reference EDK2 C implementation does raise to
@@ -509,6 +587,94 @@ unsafe extern "C" fn install_multiple_protocol_interfaces(handle: *mut efi::Hand
     let tpl_mutex = TplMutex::new(efi::TPL_NOTIFY, (), "atomic_protocol_install");
     let _tpl_guard = tpl_mutex.lock();
 
+    if interfaces.is_empty() || has_duplicate_protocols(interfaces) {
+        return Err(EfiError::InvalidParameter);
+    }
+    if let Some(handle) = handle {
+        PROTOCOL_DB.validate_handle(handle)?;
+        if interfaces.iter().any(|(protocol, _)| PROTOCOL_DB.get_interface_for_handle(handle, *protocol).is_ok()) {
+            return Err(EfiError::InvalidParameter);
+        }
+    }
+    if interfaces.iter().any(|(protocol, interface)| {
+        *protocol == efi::protocols::device_path::PROTOCOL_GUID && device_path_installed(*interface)
+    }) {
+        return Err(EfiError::AlreadyStarted);
+    }
+
+    let mut installed_handle = handle;
+    for (idx, (protocol, interface)) in interfaces.iter().enumerate() {
+        match core_install_protocol_interface(installed_handle, *protocol, *interface) {
+            Ok(new_handle) => installed_handle = Some(new_handle),
+            Err(err) => {
+                // remove the interfaces installed so far, newest first. A new handle is freed with its last interface.
+                if let Some(installed_handle) = installed_handle {
+                    for (protocol, interface) in interfaces[..idx].iter().rev() {
+                        let result = core_uninstall_protocol_interface(installed_handle, *protocol, *interface);
+                        debug_assert!(result.is_ok());
+                    }
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(installed_handle.expect("at least one interface was installed."))
+}
+
+/// Removes `interfaces` from `handle` as a single transaction.
+///
+/// Either every interface is removed, or those that were removed are installed again and the handle is left as it
+/// was. Every interface must be installed on `handle` before anything is removed. The handle is freed if no
+/// interfaces remain on it.
+///
+/// ## Errors
+///
+/// Returns [`EfiError::InvalidParameter`] if `handle` is invalid, a protocol is repeated or not installed on `handle`
+/// with the given interface, or an interface could not be removed (for instance, because an agent that opened it
+/// could not be stopped).
+pub fn core_uninstall_multiple_protocol_interfaces(
+    handle: efi::Handle,
+    interfaces: &[(efi::Guid, *mut c_void)],
+) -> Result<(), EfiError> {
+    PROTOCOL_DB.validate_handle(handle).map_err(|_| EfiError::InvalidParameter)?;
+    if has_duplicate_protocols(interfaces)
+        || interfaces
+            .iter()
+            .any(|(protocol, interface)| PROTOCOL_DB.get_interface_for_handle(handle, *protocol) != Ok(*interface))
+    {
+        return Err(EfiError::InvalidParameter);
+    }
+
+    // Keep the handle alive while its interfaces are removed, so that a failure part way through can install them
+    // again on the same handle. This fails if an outer reinstall is already keeping the handle alive.
+    let keep_alive = install_dummy_interface(handle).is_ok();
+
+    let mut result = Ok(());
+    for (idx, (protocol, interface)) in interfaces.iter().enumerate() {
+        if let Err(err) = core_uninstall_protocol_interface(handle, *protocol, *interface) {
+            log::warn!(
+                "UninstallMultipleProtocolInterfaces: failed to remove {:?} with {err:?}; restoring the handle.",
+                guid_names::named(protocol)
+            );
+            // install the interfaces removed so far again, newest first.
+            for (protocol, interface) in interfaces[..idx].iter().rev() {
+                let reinstall = core_install_protocol_interface(Some(handle), *protocol, *interface);
+                debug_assert!(reinstall.is_ok());
+            }
+            result = Err(EfiError::InvalidParameter);
+            break;
+        }
+    }
+
+    if keep_alive {
+        let removed = uninstall_dummy_interface(handle);
+        debug_assert!(removed.is_ok());
+    }
+    result
+}
+
+unsafe extern "C" fn install_multiple_protocol_interfaces(handle: *mut efi::Handle, mut args: ...) -> efi::Status {
     if handle.is_null() {
         return efi::Status::INVALID_PARAMETER;
     }
@@ -521,35 +687,24 @@ unsafe extern "C" fn install_multiple_protocol_interfaces(handle: *mut efi::Hand
             break;
         }
         let interface: *mut c_void = unsafe { args.arg() };
-        if unsafe { *protocol } == efi::protocols::device_path::PROTOCOL_GUID
-            && let Ok((remaining_path, handle)) = core_locate_device_path(
-                efi::protocols::device_path::PROTOCOL_GUID,
-                interface as *const efi::protocols::device_path::Protocol,
-            )
-            && PROTOCOL_DB.validate_handle(handle).is_ok()
-            && is_device_path_end(remaining_path)
-        {
-            return efi::Status::ALREADY_STARTED;
-        }
-
-        interfaces_to_install.push((protocol, interface));
+        interfaces_to_install.push((unsafe { protocol.read_unaligned() }, interface));
+    }
+    if interfaces_to_install.is_empty() {
+        return efi::Status::SUCCESS;
     }
 
-    let mut interfaces_to_uninstall_on_error = Vec::new();
-    for (protocol, interface) in interfaces_to_install {
-        match install_protocol_interface(handle, protocol, efi::NATIVE_INTERFACE, interface) {
-            efi::Status::SUCCESS => interfaces_to_uninstall_on_error.push((protocol, interface)),
-            err => {
-                //on error, attempt to uninstall all the previously installed interfaces. best-effort, errors are ignored.
-                for (protocol, interface) in interfaces_to_uninstall_on_error {
-                    let _ = uninstall_protocol_interface(unsafe { *handle }, protocol, interface);
-                }
-                return err;
-            }
+    // Safety: Caller must ensure that handle is a valid pointer. It is null-checked above.
+    let caller_handle = unsafe { handle.read_unaligned() };
+    let caller_handle = if caller_handle.is_null() { None } else { Some(caller_handle) };
+
+    // the caller's handle is only updated once every interface is installed.
+    match core_install_multiple_protocol_interfaces(caller_handle, &interfaces_to_install) {
+        Ok(installed_handle) => {
+            unsafe { handle.write_unaligned(installed_handle) };
+            efi::Status::SUCCESS
         }
+        Err(err) => err.into(),
     }
-
-    efi::Status::SUCCESS
 }
 
 unsafe extern "C" fn uninstall_multiple_protocol_interfaces(handle: efi::Handle, mut args: ...) -> efi::Status {
@@ -564,25 +719,12 @@ unsafe extern "C" fn uninstall_multiple_protocol_interfaces(handle: efi::Handle,
             break;
         }
         let interface: *mut c_void = unsafe { args.arg() };
-        interfaces_to_uninstall.push((protocol, interface));
-    }
-
-    let mut interfaces_to_reinstall_on_error = Vec::new();
-    for (protocol, interface) in interfaces_to_uninstall {
-        match uninstall_protocol_interface(handle, protocol, interface) {
-            efi::Status::SUCCESS => interfaces_to_reinstall_on_error.push((protocol, interface)),
-            _err => {
-                //on error, attempt to re-install all the previously uninstall interfaces. best-effort, errors are ignored.
-                for (protocol, interface) in interfaces_to_reinstall_on_error {
-                    let protocol = *(unsafe { protocol.as_mut().expect("previously null-checked pointer is null.") });
-                    let _ = core_install_protocol_interface(Some(handle), protocol, interface);
-                }
-                return efi::Status::INVALID_PARAMETER;
-            }
-        }
+        interfaces_to_uninstall.push((unsafe { protocol.read_unaligned() }, interface));
     }
 
-    efi::Status::SUCCESS
+    core_uninstall_multiple_protocol_interfaces(handle, &interfaces_to_uninstall)
+        .map(|_| efi::Status::SUCCESS)
+        .unwrap_or_else(|err| err.into())
 }
 
 extern "efiapi" fn protocols_per_handle(
@@ -830,3 +972,209 @@ pub fn init_protocol_support(bs: &mut efi::BootServices) {
     bs.locate_protocol = locate_protocol;
     bs.locate_device_path = locate_device_path;
 }
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use core::ptr;
+
+    const PROTOCOL_A: efi::Guid =
+        efi::Guid::from_fields(0x0a3f_5c11, 0x2d4e, 0x4b7a, 0x9c, 0x01, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x01]);
+    const PROTOCOL_B: efi::Guid =
+        efi::Guid::from_fields(0x0a3f_5c11, 0x2d4e, 0x4b7a, 0x9c, 0x01, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x02]);
+    const PROTOCOL_C: efi::Guid =
+        efi::Guid::from_fields(0x0a3f_5c11, 0x2d4e, 0x4b7a, 0x9c, 0x01, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x03]);
+
+    // An end of entire device path node, which is a complete (empty) device path on its own.
+    static END_DEVICE_PATH: [u8; 4] = [0x7f, 0xff, 0x04, 0x00];
+
+    fn with_locked_state<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
+        test_support::with_global_lock(|| {
+            unsafe {
+                test_support::init_test_protocol_db();
+            }
+            f();
+        })
+        .unwrap();
+    }
+
+    fn interface(value: usize) -> *mut c_void {
+        value as *mut c_void
+    }
+
+    #[test]
+    fn install_multiple_should_install_every_interface_on_a_new_handle() {
+        with_locked_state(|| {
+            let handle = core_install_multiple_protocol_interfaces(
+                None,
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x20))],
+            )
+            .unwrap();
+            assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_A), Ok(interface(0x10)));
+            assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_B), Ok(interface(0x20)));
+        });
+    }
+
+    #[test]
+    fn install_multiple_should_install_nothing_when_a_protocol_is_repeated() {
+        with_locked_state(|| {
+            let result = core_install_multiple_protocol_interfaces(
+                None,
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x20)), (PROTOCOL_A, interface(0x30))],
+            );
+            assert_eq!(result, Err(EfiError::InvalidParameter));
+            assert_eq!(PROTOCOL_DB.locate_handles(Some(PROTOCOL_A)), Err(EfiError::NotFound));
+            assert_eq!(PROTOCOL_DB.locate_handles(Some(PROTOCOL_B)), Err(EfiError::NotFound));
+
+            assert_eq!(core_install_multiple_protocol_interfaces(None, &[]), Err(EfiError::InvalidParameter));
+        });
+    }
+
+    #[test]
+    fn install_multiple_should_leave_an_existing_handle_unchanged_on_failure() {
+        with_locked_state(|| {
+            let handle = core_install_protocol_interface(None, PROTOCOL_B, interface(0x20)).unwrap();
+
+            let result = core_install_multiple_protocol_interfaces(
+                Some(handle),
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x30)), (PROTOCOL_C, interface(0x40))],
+            );
+            assert_eq!(result, Err(EfiError::InvalidParameter));
+            assert_eq!(PROTOCOL_DB.get_protocols_on_handle(handle), Ok(vec![PROTOCOL_B]));
+            assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_B), Ok(interface(0x20)));
+
+            // an invalid handle installs nothing.
+            let result = core_install_multiple_protocol_interfaces(
+                Some(0x1234 as efi::Handle),
+                &[(PROTOCOL_A, interface(0x10))],
+            );
+            assert!(result.is_err());
+            assert_eq!(PROTOCOL_DB.locate_handles(Some(PROTOCOL_A)), Err(EfiError::NotFound));
+        });
+    }
+
+    #[test]
+    fn install_multiple_should_reject_a_duplicate_device_path() {
+        with_locked_state(|| {
+            let device_path = END_DEVICE_PATH.as_ptr() as *mut c_void;
+            let first = core_install_multiple_protocol_interfaces(
+                None,
+                &[(efi::protocols::device_path::PROTOCOL_GUID, device_path)],
+            )
+            .unwrap();
+
+            let duplicate = [0x7fu8, 0xff, 0x04, 0x00];
+            let result = core_install_multiple_protocol_interfaces(
+                None,
+                &[
+                    (PROTOCOL_A, interface(0x10)),
+                    (efi::protocols::device_path::PROTOCOL_GUID, duplicate.as_ptr() as *mut c_void),
+                ],
+            );
+            assert_eq!(result, Err(EfiError::AlreadyStarted));
+            assert_eq!(PROTOCOL_DB.locate_handles(Some(PROTOCOL_A)), Err(EfiError::NotFound));
+            assert_eq!(PROTOCOL_DB.locate_handles(Some(efi::protocols::device_path::PROTOCOL_GUID)), Ok(vec![first]));
+        });
+    }
+
+    #[test]
+    fn install_multiple_boot_service_should_only_update_the_handle_on_success() {
+        with_locked_state(|| {
+            let mut protocol_a = PROTOCOL_A;
+            let mut protocol_b = PROTOCOL_B;
+
+            let mut handle: efi::Handle = ptr::null_mut();
+            let status = unsafe {
+                install_multiple_protocol_interfaces(
+                    &mut handle,
+                    &mut protocol_a as *mut efi::Guid,
+                    interface(0x10),
+                    &mut protocol_a as *mut efi::Guid,
+                    interface(0x20),
+                    ptr::null_mut::<efi::Guid>(),
+                )
+            };
+            assert_eq!(status, efi::Status::INVALID_PARAMETER);
+            assert!(handle.is_null());
+
+            let status = unsafe {
+                install_multiple_protocol_interfaces(
+                    &mut handle,
+                    &mut protocol_a as *mut efi::Guid,
+                    interface(0x10),
+                    &mut protocol_b as *mut efi::Guid,
+                    interface(0x20),
+                    ptr::null_mut::<efi::Guid>(),
+                )
+            };
+            assert_eq!(status, efi::Status::SUCCESS);
+            assert_eq!(PROTOCOL_DB.get_protocols_on_handle(handle).map(|p| p.len()), Ok(2));
+
+            let status = unsafe {
+                uninstall_multiple_protocol_interfaces(
+                    handle,
+                    &mut protocol_a as *mut efi::Guid,
+                    interface(0x10),
+                    &mut protocol_b as *mut efi::Guid,
+                    interface(0x20),
+                    ptr::null_mut::<efi::Guid>(),
+                )
+            };
+            assert_eq!(status, efi::Status::SUCCESS);
+            assert!(PROTOCOL_DB.validate_handle(handle).is_err());
+        });
+    }
+
+    #[test]
+    fn uninstall_multiple_should_remove_nothing_when_an_interface_does_not_match() {
+        with_locked_state(|| {
+            let handle = core_install_multiple_protocol_interfaces(
+                None,
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x20))],
+            )
+            .unwrap();
+
+            for interfaces in [
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x99))][..],
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_C, interface(0x30))][..],
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_A, interface(0x10))][..],
+            ] {
+                assert_eq!(
+                    core_uninstall_multiple_protocol_interfaces(handle, interfaces),
+                    Err(EfiError::InvalidParameter)
+                );
+                assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_A), Ok(interface(0x10)));
+                assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_B), Ok(interface(0x20)));
+            }
+        });
+    }
+
+    #[test]
+    fn uninstall_multiple_should_restore_removed_interfaces_when_one_cannot_be_removed() {
+        with_locked_state(|| {
+            let handle = core_install_multiple_protocol_interfaces(
+                None,
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x20))],
+            )
+            .unwrap();
+
+            // an agent without a driver binding holds PROTOCOL_B open by driver, so it cannot be stopped.
+            let agent = core_install_protocol_interface(None, PROTOCOL_C, interface(0x30)).unwrap();
+            PROTOCOL_DB
+                .add_protocol_usage(handle, PROTOCOL_B, Some(agent), Some(handle), efi::OPEN_PROTOCOL_BY_DRIVER)
+                .unwrap();
+
+            let result = core_uninstall_multiple_protocol_interfaces(
+                handle,
+                &[(PROTOCOL_A, interface(0x10)), (PROTOCOL_B, interface(0x20))],
+            );
+            assert_eq!(result, Err(EfiError::InvalidParameter));
+            assert!(PROTOCOL_DB.validate_handle(handle).is_ok());
+            assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_A), Ok(interface(0x10)));
+            assert_eq!(PROTOCOL_DB.get_interface_for_handle(handle, PROTOCOL_B), Ok(interface(0x20)));
+            assert_eq!(PROTOCOL_DB.get_protocols_on_handle(handle).map(|p| p.len()), Ok(2));
+        });
+    }
+}
@@ -0,0 +1,139 @@
+//! Proximity Domains
+//!
+//! On NUMA systems, buffers that are used by one processor or device, such as AP stacks or device rings, perform best
+//! when they are allocated from memory attached to the same node. Platforms describe which memory belongs to which
+//! proximity domain with [ProximityDomains], using the same domains that they report in the ACPI SRAT, and components
+//! request memory near a node with
+//! [AllocationOptions::with_proximity_domain](patina::component::service::memory::AllocationOptions::with_proximity_domain).
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::ops::Range;
+
+use alloc::vec::Vec;
+use patina::base::{UEFI_PAGE_SIZE, align_down};
+use patina_pi::dxe_services::{self, GcdMemoryType};
+use spin::RwLock;
+
+use crate::GCD;
+
+/// The proximity domain of each range of system memory.
+///
+/// Each entry is a range of memory and the proximity domain it belongs to, which must match the memory affinity
+/// structures of the ACPI SRAT that the platform produces. Ranges must not overlap.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ProximityDomains};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(ProximityDomains(vec![(0x0..0x8000_0000, 0), (0x1_0000_0000..0x2_0000_0000, 1)]))
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProximityDomains(pub Vec<(Range<u64>, u32)>);
+
+static DOMAINS: RwLock<Vec<(Range<u64>, u32)>> = RwLock::new(Vec::new());
+
+/// Sets the proximity domain of each range of memory, ignoring ranges that are empty or overlap an earlier range.
+pub(crate) fn set_proximity_domains(domains: &ProximityDomains) {
+    let mut ranges: Vec<(Range<u64>, u32)> = Vec::with_capacity(domains.0.len());
+    for (range, domain) in &domains.0 {
+        if range.is_empty() || ranges.iter().any(|(other, _)| range.start < other.end && other.start < range.end) {
+            log::error!("Ignoring invalid or overlapping range {range:#x?} of proximity domain {domain}.");
+            continue;
+        }
+        log::info!("Proximity domain {domain}: {range:#x?}");
+        ranges.push((range.clone(), *domain));
+    }
+    *DOMAINS.write() = ranges;
+}
+
+/// Returns the proximity domain of the memory at `address`, if the platform assigned it to one.
+pub(crate) fn proximity_domain(address: u64) -> Option<u32> {
+    DOMAINS.read().iter().find(|(range, _)| range.contains(&address)).map(|(_, domain)| *domain)
+}
+
+/// Returns the highest address at which `pages` free pages of system memory aligned to `alignment` are available in
+/// `domain`, if any.
+pub(crate) fn find_free_pages(domain: u32, pages: usize, alignment: usize) -> Option<u64> {
+    let size = pages.checked_mul(UEFI_PAGE_SIZE)? as u64;
+    let ranges: Vec<Range<u64>> =
+        DOMAINS.read().iter().filter(|(_, other)| *other == domain).map(|(range, _)| range.clone()).collect();
+    if ranges.is_empty() || size == 0 {
+        return None;
+    }
+
+    let mut descriptors: Vec<dxe_services::MemorySpaceDescriptor> = Vec::with_capacity(GCD.memory_descriptor_count());
+    GCD.get_memory_descriptors(&mut descriptors).ok()?;
+    // search top down, as the allocators do.
+    descriptors
+        .iter()
+        .rev()
+        .filter(|descriptor| descriptor.memory_type == GcdMemoryType::SystemMemory && descriptor.image_handle.is_null())
+        .find_map(|descriptor| {
+            ranges.iter().find_map(|range| {
+                let start = descriptor.base_address.max(range.start);
+                let end = (descriptor.base_address + descriptor.length).min(range.end);
+                let address = align_down(end.checked_sub(size)?, alignment as u64).ok()?;
+                (address >= start).then_some(address)
+            })
+        })
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::{memory_manager::CoreMemoryManager, test_support};
+    use patina::component::service::memory::{AllocationOptions, MemoryManager};
+
+    #[test]
+    fn test_allocations_are_placed_in_the_requested_domain() {
+        test_support::with_global_lock(|| {
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::reset_allocators();
+            }
+            // split the test memory between two domains.
+            let mut descriptors = Vec::with_capacity(GCD.memory_descriptor_count());
+            GCD.get_memory_descriptors(&mut descriptors).unwrap();
+            let memory = descriptors.iter().filter(|d| d.memory_type == GcdMemoryType::SystemMemory);
+            let start = memory.clone().map(|d| d.base_address).min().unwrap();
+            let end = memory.map(|d| d.base_address + d.length).max().unwrap();
+            let middle = start + (end - start) / 2;
+            set_proximity_domains(&ProximityDomains(Vec::from([
+                (start..middle, 0),
+                (middle..end, 1),
+                (middle..middle + 0x1000, 2),
+            ])));
+            assert_eq!(proximity_domain(start), Some(0));
+            assert_eq!(proximity_domain(middle), Some(1));
+
+            for domain in [0, 1] {
+                let allocation = CoreMemoryManager
+                    .allocate_pages(4, AllocationOptions::new().with_proximity_domain(domain))
+                    .unwrap();
+                let address = allocation.into_raw_ptr::<u8>().unwrap() as usize;
+                assert_eq!(CoreMemoryManager.get_proximity_domain(address), Some(domain));
+                assert_eq!(CoreMemoryManager.get_proximity_domain(address + 4 * UEFI_PAGE_SIZE - 1), Some(domain));
+            }
+
+            // domains without memory fall back to any memory.
+            let allocation =
+                CoreMemoryManager.allocate_pages(1, AllocationOptions::new().with_proximity_domain(2)).unwrap();
+            assert!(allocation.into_raw_ptr::<u8>().is_some());
+
+            set_proximity_domains(&ProximityDomains::default());
+        })
+        .unwrap();
+    }
+}
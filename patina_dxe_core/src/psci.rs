@@ -0,0 +1,220 @@
+//! DXE Core PSCI Service
+//!
+//! Produces the [Psci] service on AArch64 platforms that do not register their own, making PSCI calls through the
+//! [PsciConduit] registered with the core as configuration. The SMC conduit is used if no conduit is registered.
+//!
+//! Whichever [Psci] service is registered backs the `ResetSystem()` runtime service until a Reset Architectural
+//! Protocol driver replaces it, and the panic policy resets the system through it when no such driver is installed.
+//! The core resides in boot services memory, so the PSCI backed `ResetSystem()` is only valid while boot services are
+//! available; platforms still need a runtime reset driver for the OS.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::ffi::c_void;
+
+use patina::component::service::{
+    IntoService, Service,
+    psci::{self, Psci, PsciConduit, PsciError, function_id},
+};
+use r_efi::efi;
+
+use crate::systemtables::SYSTEM_TABLE;
+
+struct ResetPsci(Service<dyn Psci>);
+
+// Safety: the service is set once and only used to reset the system, so it is safe to mark it send and sync.
+unsafe impl Send for ResetPsci {}
+unsafe impl Sync for ResetPsci {}
+
+static RESET_PSCI: spin::Once<ResetPsci> = spin::Once::new();
+
+/// Services `ResetSystem()` with `psci` until a Reset Architectural Protocol driver replaces it.
+///
+/// Must be called once the system table is initialized, and before any driver is dispatched.
+pub(crate) fn install_reset_system(psci: Service<dyn Psci>) {
+    RESET_PSCI.call_once(|| ResetPsci(psci));
+    if let Some(table) = SYSTEM_TABLE.lock().as_mut() {
+        table.runtime_services_mut().reset_system = psci_reset_system;
+        table.checksum_all();
+    }
+}
+
+/// Resets the system through the PSCI service installed with [install_reset_system], returning the error if the reset
+/// failed or no service is installed.
+pub(crate) fn reset_system(reset_type: efi::ResetType) -> PsciError {
+    match RESET_PSCI.get() {
+        Some(reset) => psci::reset_system(*reset.0, reset_type),
+        None => PsciError::NotSupported,
+    }
+}
+
+#[coverage(off)]
+extern "efiapi" fn psci_reset_system(
+    reset_type: efi::ResetType,
+    _status: efi::Status,
+    _size: usize,
+    _data: *mut c_void,
+) {
+    let err = reset_system(reset_type);
+    log::error!("PSCI reset failed: {err:?}");
+    // ResetSystem() does not return.
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Makes an SMC Calling Convention call with the given function identifier and arguments, returning `x0`.
+type SmcccCall = fn(PsciConduit, u32, [u64; 3]) -> i64;
+
+/// The core implementation of the [Psci] service.
+#[derive(IntoService)]
+#[service(dyn Psci)]
+pub(crate) struct CorePsci {
+    conduit: PsciConduit,
+    call: SmcccCall,
+}
+
+impl CorePsci {
+    pub(crate) fn new(conduit: PsciConduit) -> Self {
+        Self { conduit, call: smccc_call }
+    }
+
+    fn call(&self, function_id: u32, args: [u64; 3]) -> i64 {
+        (self.call)(self.conduit, function_id, args)
+    }
+
+    fn call_no_return(&self, function_id: u32) -> PsciError {
+        match PsciError::from_return_value(self.call(function_id, [0; 3]) as i32) {
+            Err(err) => err,
+            Ok(()) => PsciError::InternalFailure,
+        }
+    }
+}
+
+impl Psci for CorePsci {
+    fn version(&self) -> (u16, u16) {
+        let version = self.call(function_id::PSCI_VERSION, [0; 3]) as u32;
+        ((version >> 16) as u16, version as u16)
+    }
+
+    fn is_supported(&self, function_id: u32) -> bool {
+        self.call(function_id::PSCI_FEATURES, [function_id as u64, 0, 0]) >= 0
+    }
+
+    fn cpu_on(&self, target_mpidr: u64, entry_point: u64, context_id: u64) -> Result<(), PsciError> {
+        PsciError::from_return_value(self.call(function_id::CPU_ON, [target_mpidr, entry_point, context_id]) as i32)
+    }
+
+    fn cpu_off(&self) -> PsciError {
+        self.call_no_return(function_id::CPU_OFF)
+    }
+
+    fn system_off(&self) -> PsciError {
+        self.call_no_return(function_id::SYSTEM_OFF)
+    }
+
+    fn system_reset(&self) -> PsciError {
+        self.call_no_return(function_id::SYSTEM_RESET)
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "uefi", target_arch = "aarch64"))] {
+        fn smccc_call(conduit: PsciConduit, function_id: u32, args: [u64; 3]) -> i64 {
+            let mut x0 = function_id as u64;
+            // SAFETY: PSCI calls follow the SMC Calling Convention, which only modifies x0-x17.
+            unsafe {
+                match conduit {
+                    PsciConduit::Smc => core::arch::asm!(
+                        "smc #0",
+                        inout("x0") x0,
+                        inout("x1") args[0] => _,
+                        inout("x2") args[1] => _,
+                        inout("x3") args[2] => _,
+                        out("x4") _, out("x5") _, out("x6") _, out("x7") _, out("x8") _, out("x9") _,
+                        out("x10") _, out("x11") _, out("x12") _, out("x13") _, out("x14") _, out("x15") _,
+                        out("x16") _, out("x17") _,
+                    ),
+                    PsciConduit::Hvc => core::arch::asm!(
+                        "hvc #0",
+                        inout("x0") x0,
+                        inout("x1") args[0] => _,
+                        inout("x2") args[1] => _,
+                        inout("x3") args[2] => _,
+                        out("x4") _, out("x5") _, out("x6") _, out("x7") _, out("x8") _, out("x9") _,
+                        out("x10") _, out("x11") _, out("x12") _, out("x13") _, out("x14") _, out("x15") _,
+                        out("x16") _, out("x17") _,
+                    ),
+                }
+            }
+            x0 as i64
+        }
+    } else {
+        fn smccc_call(_conduit: PsciConduit, _function_id: u32, _args: [u64; 3]) -> i64 {
+            // PSCI_RET_NOT_SUPPORTED
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    fn psci(call: SmcccCall) -> CorePsci {
+        CorePsci { conduit: PsciConduit::Hvc, call }
+    }
+
+    #[test]
+    fn test_psci_calls() {
+        let psci = psci(|conduit, function, args| {
+            assert_eq!(conduit, PsciConduit::Hvc);
+            match function {
+                function_id::PSCI_VERSION => 0x0001_0002,
+                function_id::PSCI_FEATURES if args[0] == function_id::CPU_ON as u64 => 0,
+                function_id::CPU_ON if args == [1, 0x8000, 7] => 0,
+                function_id::CPU_ON => -4,
+                function_id::SYSTEM_RESET => -3,
+                _ => -1,
+            }
+        });
+
+        assert_eq!(psci.version(), (1, 2));
+        assert!(psci.is_supported(function_id::CPU_ON));
+        assert!(!psci.is_supported(function_id::SYSTEM_OFF));
+        assert_eq!(psci.cpu_on(1, 0x8000, 7), Ok(()));
+        assert_eq!(psci.cpu_on(2, 0x8000, 7), Err(PsciError::AlreadyOn));
+        assert_eq!(psci.system_reset(), PsciError::Denied);
+        assert_eq!(psci.system_off(), PsciError::NotSupported);
+    }
+
+    #[test]
+    fn test_returning_from_no_return_call_is_a_failure() {
+        let psci = psci(|_, _, _| 0);
+        assert_eq!(psci.cpu_off(), PsciError::InternalFailure);
+    }
+
+    #[test]
+    fn test_reset_system_uses_installed_service() {
+        crate::test_support::with_global_lock(|| {
+            assert_eq!(reset_system(efi::RESET_COLD), PsciError::NotSupported);
+
+            let mut psci = patina::component::service::psci::MockPsci::new();
+            psci.expect_system_off().once().return_const(PsciError::Denied);
+            install_reset_system(Service::mock(alloc::boxed::Box::new(psci)));
+            assert_eq!(reset_system(efi::RESET_SHUTDOWN), PsciError::Denied);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_non_aarch64_calls_are_not_supported() {
+        let psci = CorePsci::new(PsciConduit::Smc);
+        assert_eq!(psci.system_reset(), PsciError::NotSupported);
+    }
+}
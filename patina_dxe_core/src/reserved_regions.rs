@@ -0,0 +1,234 @@
+//! Reserved Memory Regions
+//!
+//! Platforms often need memory carved out of the system memory map before any driver runs: a TPM event log, a
+//! framebuffer, or memory owned by the secure world. Rather than crafting memory allocation HOBs in an earlier phase,
+//! platforms can declare these regions with [Core::with_reserved_regions](crate::Core::with_reserved_regions). The
+//! core allocates each region as soon as the pre-DXE memory allocations are processed, before any other allocation can
+//! land in it, and applies the attributes requested for it.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina::{base::UEFI_PAGE_MASK, error::EfiError, uefi_size_to_pages};
+use patina_pi::dxe_services::GcdMemoryType;
+use r_efi::efi;
+use spin::RwLock;
+
+use crate::{GCD, allocator::core_allocate_pages, gcd::AllocateType as AllocationStrategy, protocol_db};
+
+/// A region of memory that the core reserves during memory initialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReservedRegion {
+    /// A name for the region, used in log messages.
+    pub name: &'static str,
+    /// The page aligned base address of the region.
+    pub base_address: u64,
+    /// The length of the region in bytes, which must be a multiple of the page size.
+    pub length: u64,
+    /// The memory type the region is reported as in the memory map, if it is in system memory. Regions outside of
+    /// system memory, such as a framebuffer, are reserved in the GCD and keep their GCD memory type.
+    pub memory_type: efi::MemoryType,
+    /// The attributes applied to the region, such as `efi::MEMORY_WC` for a framebuffer, or `efi::MEMORY_RUNTIME`
+    /// for a region the OS must map for runtime services. These replace the existing attributes of the region, so
+    /// they should include a cacheability attribute. No attributes are changed if this is 0.
+    pub attributes: u64,
+}
+
+/// Memory regions that the core reserves and excludes from allocation.
+///
+/// Regions must not overlap the free memory region of the PHIT HOB, which the core allocates from before the regions
+/// are reserved.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, ReservedRegion, ReservedRegions};
+/// use r_efi::efi;
+/// # let physical_hob_list = core::ptr::null();
+///
+/// static REGIONS: &[ReservedRegion] = &[ReservedRegion {
+///     name: "TPM Event Log",
+///     base_address: 0x7F00_0000,
+///     length: 0x10000,
+///     memory_type: efi::ACPI_RECLAIM_MEMORY,
+///     attributes: 0,
+/// }];
+///
+/// Core::default()
+///   .with_reserved_regions(ReservedRegions(REGIONS))
+///   .init_memory(physical_hob_list)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReservedRegions(pub &'static [ReservedRegion]);
+
+static REGIONS: RwLock<&'static [ReservedRegion]> = RwLock::new(&[]);
+
+/// Sets the regions that are reserved when memory is initialized.
+pub(crate) fn set_reserved_regions(regions: ReservedRegions) {
+    *REGIONS.write() = regions.0;
+}
+
+/// Reserves each of the registered regions, logging and skipping any that cannot be reserved.
+///
+/// Must be called once the pre-DXE memory allocations are processed, and before any other allocations are made.
+pub(crate) fn reserve_regions() {
+    let regions = *REGIONS.read();
+    for region in regions {
+        match reserve_region(region) {
+            Ok(()) => log::info!(
+                "Reserved {} at {:#x} of length {:#x} as {:#x?}.",
+                region.name,
+                region.base_address,
+                region.length,
+                region.memory_type
+            ),
+            Err(err) => log::error!(
+                "Failed to reserve {} at {:#x} of length {:#x}: {err:?}",
+                region.name,
+                region.base_address,
+                region.length
+            ),
+        }
+    }
+}
+
+fn reserve_region(region: &ReservedRegion) -> Result<(), EfiError> {
+    if region.base_address == 0
+        || region.length == 0
+        || (region.base_address & UEFI_PAGE_MASK as u64) != 0
+        || (region.length & UEFI_PAGE_MASK as u64) != 0
+    {
+        return Err(EfiError::InvalidParameter);
+    }
+    // these describe free memory, which would defeat the purpose of the reservation.
+    if matches!(region.memory_type, efi::CONVENTIONAL_MEMORY | efi::PERSISTENT_MEMORY | efi::UNACCEPTED_MEMORY_TYPE) {
+        return Err(EfiError::InvalidParameter);
+    }
+
+    let base = region.base_address as usize;
+    let length = region.length as usize;
+    let descriptor = GCD.get_memory_descriptor_for_address(region.base_address)?;
+    match descriptor.memory_type {
+        GcdMemoryType::SystemMemory => {
+            // use the allocators, so that the region is reported with its memory type in the memory map.
+            let mut address = region.base_address;
+            core_allocate_pages(
+                efi::ALLOCATE_ADDRESS,
+                region.memory_type,
+                uefi_size_to_pages!(length),
+                &mut address as *mut efi::PhysicalAddress,
+                None,
+            )?;
+        }
+        GcdMemoryType::Unaccepted => return Err(EfiError::Unsupported),
+        memory_type => {
+            // regions that were not described by a resource descriptor HOB are added to the GCD as reserved memory.
+            let memory_type = if memory_type == GcdMemoryType::NonExistent {
+                // Safety: the platform declared that the region exists and is reserved for its use.
+                unsafe { GCD.add_memory_space(GcdMemoryType::Reserved, base, length, region.attributes)? };
+                GcdMemoryType::Reserved
+            } else {
+                memory_type
+            };
+            GCD.allocate_memory_space(
+                AllocationStrategy::Address(base),
+                memory_type,
+                0,
+                length,
+                protocol_db::DXE_CORE_HANDLE,
+                None,
+            )?;
+        }
+    }
+
+    if region.attributes != 0 {
+        let descriptor = GCD.get_memory_descriptor_for_address(region.base_address)?;
+        if descriptor.capabilities & region.attributes != region.attributes {
+            GCD.set_memory_space_capabilities(base, length, descriptor.capabilities | region.attributes)?;
+        }
+        GCD.set_memory_space_attributes(base, length, region.attributes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::{allocator::core_free_pages, test_support};
+
+    #[test]
+    fn test_reserve_regions() {
+        test_support::with_global_lock(|| {
+            unsafe {
+                test_support::init_test_gcd(None);
+                test_support::reset_allocators();
+            }
+
+            // find free system memory for the region.
+            let mut free = 0;
+            core_allocate_pages(efi::ALLOCATE_ANY_PAGES, efi::BOOT_SERVICES_DATA, 2, &mut free, None).unwrap();
+            core_free_pages(free, 2).unwrap();
+
+            let regions = std::vec![
+                ReservedRegion {
+                    name: "System Memory",
+                    base_address: free,
+                    length: 0x2000,
+                    memory_type: efi::ACPI_MEMORY_NVS,
+                    attributes: efi::MEMORY_WB | efi::MEMORY_RUNTIME,
+                },
+                ReservedRegion {
+                    name: "Conventional",
+                    base_address: 0x30000000,
+                    length: 0x1000,
+                    memory_type: efi::CONVENTIONAL_MEMORY,
+                    attributes: 0,
+                },
+                ReservedRegion {
+                    name: "Non-existent",
+                    base_address: 0x20000000,
+                    length: 0x1000,
+                    memory_type: efi::RESERVED_MEMORY_TYPE,
+                    attributes: 0,
+                },
+                ReservedRegion {
+                    name: "Unaligned",
+                    base_address: 0x20000800,
+                    length: 0x1000,
+                    memory_type: efi::RESERVED_MEMORY_TYPE,
+                    attributes: 0,
+                },
+            ];
+            set_reserved_regions(ReservedRegions(regions.leak()));
+            reserve_regions();
+            set_reserved_regions(ReservedRegions::default());
+
+            let descriptor = GCD.get_memory_descriptor_for_address(free).unwrap();
+            assert_eq!(descriptor.image_handle, protocol_db::EFI_ACPI_MEMORY_NVS_ALLOCATOR_HANDLE);
+            assert_eq!(descriptor.attributes, efi::MEMORY_WB | efi::MEMORY_RUNTIME);
+            // the region can no longer be allocated.
+            let mut address = free;
+            assert!(
+                core_allocate_pages(efi::ALLOCATE_ADDRESS, efi::BOOT_SERVICES_DATA, 1, &mut address, None).is_err()
+            );
+
+            // memory that was not in the GCD is added as reserved memory.
+            let descriptor = GCD.get_memory_descriptor_for_address(0x20000000).unwrap();
+            assert_eq!(descriptor.memory_type, GcdMemoryType::Reserved);
+            assert_eq!(descriptor.image_handle, protocol_db::DXE_CORE_HANDLE);
+
+            // invalid regions are skipped.
+            for address in [0x20001000, 0x30000000] {
+                let descriptor = GCD.get_memory_descriptor_for_address(address).unwrap();
+                assert_eq!(descriptor.memory_type, GcdMemoryType::NonExistent);
+            }
+        })
+        .unwrap();
+    }
+}
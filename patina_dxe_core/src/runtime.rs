@@ -1,5 +1,29 @@
 //! DXE Core Runtime Support
 //!
+//! ## Addressing Checks
+//!
+//! Runtime services must be called with physical addressing until SetVirtualAddressMap, and with virtual addressing
+//! after it. OS loaders that get this wrong cause crashes that look random. With [RuntimeAddressingChecks]
+//! registered, the core replaces the runtime services at ExitBootServices with shims that flag such calls before
+//! forwarding them:
+//!
+//! - Before SetVirtualAddressMap, a pointer argument that is not a physical address is flagged.
+//! - During SetVirtualAddressMap, the shim records the virtual map and puts the converted runtime services back in
+//!   the table. After that, a call only reaches a shim through a function pointer the caller kept from before
+//!   SetVirtualAddressMap, so every such call is flagged. Any pointer argument that is the physical address of a
+//!   relocated runtime region is flagged too.
+//!
+//! The shims are part of the DXE core, which resides in boot services memory, so the checks are only meant for
+//! debugging, with an OS that keeps boot services memory mapped while it is validated.
+//!
+//! ## Relocation Checks
+//!
+//! With the addressing checks, the SetVirtualAddressMap shim also verifies that the runtime driver relocated the
+//! runtime images for virtual addressing. When a runtime image is loaded, a sample of its fixups is recorded with the
+//! values they were relocated to. The sampled fixups are read before SetVirtualAddressMap, and afterwards each fixup
+//! that still held its recorded value must hold that value adjusted to the virtual address of the image, while the
+//! others must be unchanged. A mismatch points at a bug in the relocation data kept for the image, or in the runtime driver.
+//!
 //! ## License
 //!
 //! Copyright (c) Microsoft Corporation.
@@ -7,15 +31,28 @@
 //! SPDX-License-Identifier: Apache-2.0
 //!
 
-use core::{ffi::c_void, ptr};
+use core::{
+    ffi::c_void,
+    mem::size_of,
+    ptr,
+    slice::from_raw_parts,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
 
 use alloc::collections::LinkedList;
 use patina::error::EfiError;
 use r_efi::efi;
 use spin::Mutex;
 
-use crate::{events::EVENT_DB, pecoff::relocation::RelocationBlock, protocols::PROTOCOL_DB};
-use patina_pi::{list_entry, protocols::runtime};
+use crate::{
+    GCD,
+    events::EVENT_DB,
+    image::{core_current_image_name, with_image_at_address},
+    pecoff::{self, relocation::RelocationBlock},
+    protocols::PROTOCOL_DB,
+    systemtables::SYSTEM_TABLE,
+};
+use patina_pi::{dxe_services::GcdMemoryType, list_entry, protocols::runtime};
 
 struct RuntimeData {
     runtime_arch_ptr: *mut runtime::Protocol,
@@ -84,6 +121,13 @@ pub fn finalize_runtime_support() {
     if !data.runtime_arch_ptr.is_null() {
         unsafe { (*data.runtime_arch_ptr).at_runtime.store(true, core::sync::atomic::Ordering::Relaxed) };
     }
+    drop(data);
+
+    if ADDRESSING_CHECKS.load(Ordering::SeqCst) {
+        let mut st_guard = SYSTEM_TABLE.lock();
+        let system_table = st_guard.as_mut().expect("The System Table pointer is null. This is invalid.");
+        install_addressing_checks(system_table.runtime_services_mut());
+    }
 }
 
 extern "efiapi" fn runtime_protocol_notify(_event: efi::Event, _context: *mut c_void) {
@@ -140,6 +184,14 @@ pub fn add_runtime_image(
 ) -> Result<(), EfiError> {
     let mut data = RUNTIME_DATA.lock();
 
+    if ADDRESSING_CHECKS.load(Ordering::SeqCst) {
+        // SAFETY: the image was just loaded and relocated at `image_base`.
+        let samples = sample_fixups(image_base as u64, relocation_data, |address, fixup_type| unsafe {
+            read_fixup(address, fixup_type)
+        });
+        FIXUP_SAMPLES.lock().push(ImageFixups { handle: handle as usize, image_base: image_base as u64, samples });
+    }
+
     let relocation_data = crate::pecoff::flatten_runtime_relocation_data(relocation_data);
     data.runtime_images.push_back(runtime::ImageEntry {
         image_base,
@@ -157,9 +209,394 @@ pub fn remove_runtime_image(image_handle: efi::Handle) -> Result<(), EfiError> {
     let mut data = RUNTIME_DATA.lock();
     for _ in data.runtime_images.extract_if(|entry| entry.handle == image_handle) {}
     data.update_protocol_lists();
+    FIXUP_SAMPLES.lock().retain(|image| image.handle != image_handle as usize);
     Ok(())
 }
 
+/// Flags runtime services calls made with physical addressing after SetVirtualAddressMap, or with virtual addressing
+/// before it, and runtime images that SetVirtualAddressMap did not relocate as expected.
+///
+/// This is debug instrumentation; runtime services are not checked unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, RuntimeAddressingChecks};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(RuntimeAddressingChecks)
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuntimeAddressingChecks;
+
+static ADDRESSING_CHECKS: AtomicBool = AtomicBool::new(false);
+static VIRTUAL_MODE: AtomicBool = AtomicBool::new(false);
+static CHECKED_TABLE: AtomicPtr<efi::RuntimeServices> = AtomicPtr::new(ptr::null_mut());
+
+/// Enables the runtime services addressing checks, which are installed at ExitBootServices.
+pub(crate) fn enable_addressing_checks() {
+    ADDRESSING_CHECKS.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if the runtime services addressing checks are enabled.
+pub(crate) fn addressing_checks_enabled() -> bool {
+    ADDRESSING_CHECKS.load(Ordering::SeqCst)
+}
+
+// The most runtime regions that are recorded from the virtual map.
+const MAX_RUNTIME_REGIONS: usize = 64;
+
+// A runtime region, and the virtual address it was relocated to by SetVirtualAddressMap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RuntimeRegion {
+    physical: u64,
+    virtual_address: u64,
+    size: u64,
+}
+
+impl RuntimeRegion {
+    fn contains_physical(&self, address: u64) -> bool {
+        (self.physical..self.physical.saturating_add(self.size)).contains(&address)
+    }
+
+    fn contains_virtual(&self, address: u64) -> bool {
+        (self.virtual_address..self.virtual_address.saturating_add(self.size)).contains(&address)
+    }
+}
+
+struct VirtualMap {
+    regions: [RuntimeRegion; MAX_RUNTIME_REGIONS],
+    count: usize,
+}
+
+impl VirtualMap {
+    fn regions(&self) -> &[RuntimeRegion] {
+        &self.regions[..self.count]
+    }
+
+    // Returns the virtual address of the physical `address`, or `address` if it is not in a runtime region.
+    fn convert(&self, address: u64) -> u64 {
+        self.regions()
+            .iter()
+            .find(|region| region.contains_physical(address))
+            .map_or(address, |region| address - region.physical + region.virtual_address)
+    }
+}
+
+static VIRTUAL_MAP: Mutex<VirtualMap> = Mutex::new(VirtualMap {
+    regions: [RuntimeRegion { physical: 0, virtual_address: 0, size: 0 }; MAX_RUNTIME_REGIONS],
+    count: 0,
+});
+
+// Returns whether a pointer argument uses the wrong addressing for the current mode. Before SetVirtualAddressMap, that
+// is any address that is not physical memory; after it, the physical address of a relocated runtime region.
+fn is_misaddressed(
+    address: u64,
+    virtual_mode: bool,
+    regions: &[RuntimeRegion],
+    is_physical: impl Fn(u64) -> bool,
+) -> bool {
+    if !virtual_mode {
+        return !is_physical(address);
+    }
+    !regions.iter().any(|region| region.contains_virtual(address))
+        && regions.iter().any(|region| region.physical != region.virtual_address && region.contains_physical(address))
+}
+
+fn is_physical_memory(address: u64) -> bool {
+    GCD.get_memory_descriptor_for_address(address)
+        .is_ok_and(|descriptor| descriptor.memory_type != GcdMemoryType::NonExistent)
+}
+
+fn report(service: &str, problem: core::fmt::Arguments) {
+    let image = if VIRTUAL_MODE.load(Ordering::SeqCst) { None } else { core_current_image_name() };
+    log::error!("Runtime service {service} {problem}. Running image: {}", image.as_deref().unwrap_or("Unknown"));
+}
+
+fn check_call(service: &str, pointers: &[(&str, u64)]) {
+    let virtual_mode = VIRTUAL_MODE.load(Ordering::SeqCst);
+    if virtual_mode {
+        report(service, format_args!("was called through its physical address after SetVirtualAddressMap"));
+    }
+    let map = VIRTUAL_MAP.lock();
+    for &(argument, address) in pointers {
+        if address == 0 || !is_misaddressed(address, virtual_mode, map.regions(), is_physical_memory) {
+            continue;
+        }
+        let addressing = if virtual_mode { "a physical" } else { "a virtual" };
+        let owner = with_image_at_address(address, |name, offset| alloc::format!("{name} + {offset:#x}"));
+        report(
+            service,
+            format_args!(
+                "was passed {addressing} address in {argument} ({address:#x}, {})",
+                owner.as_deref().unwrap_or("not in a loaded image")
+            ),
+        );
+    }
+}
+
+// The most fixups of each runtime image that are checked after SetVirtualAddressMap.
+const FIXUP_SAMPLES_PER_IMAGE: usize = 16;
+
+// A fixup of a runtime image, with the value it was relocated to when the image was loaded, and the value it held
+// before SetVirtualAddressMap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FixupSample {
+    address: u64,
+    fixup_type: u16,
+    recorded: u64,
+    before: u64,
+}
+
+impl FixupSample {
+    // Returns the value the fixup must hold after the image at `image_base` was relocated to `virtual_base`.
+    fn expected(&self, image_base: u64, virtual_base: u64) -> u64 {
+        if self.before != self.recorded {
+            // The image changed the fixup since it was loaded, so the runtime driver must leave it alone.
+            return self.before;
+        }
+        let adjustment = virtual_base.wrapping_sub(image_base);
+        match self.fixup_type {
+            pecoff::IMAGE_REL_BASED_HIGH => (self.recorded as u16).wrapping_add((adjustment >> 16) as u16) as u64,
+            pecoff::IMAGE_REL_BASED_LOW => (self.recorded as u16).wrapping_add(adjustment as u16) as u64,
+            pecoff::IMAGE_REL_BASED_HIGHLOW => (self.recorded as u32).wrapping_add(adjustment as u32) as u64,
+            _ => self.recorded.wrapping_add(adjustment),
+        }
+    }
+}
+
+struct ImageFixups {
+    handle: usize,
+    image_base: u64,
+    samples: alloc::vec::Vec<FixupSample>,
+}
+
+static FIXUP_SAMPLES: Mutex<alloc::vec::Vec<ImageFixups>> = Mutex::new(alloc::vec::Vec::new());
+
+// Reads the value of a fixup of `fixup_type` at `address`.
+//
+// SAFETY: `address` must be readable for the size of the fixup.
+unsafe fn read_fixup(address: u64, fixup_type: u16) -> u64 {
+    unsafe {
+        match fixup_type {
+            pecoff::IMAGE_REL_BASED_HIGH | pecoff::IMAGE_REL_BASED_LOW => {
+                ptr::read_unaligned(address as *const u16) as u64
+            }
+            pecoff::IMAGE_REL_BASED_HIGHLOW => ptr::read_unaligned(address as *const u32) as u64,
+            _ => ptr::read_unaligned(address as *const u64),
+        }
+    }
+}
+
+// Picks up to FIXUP_SAMPLES_PER_IMAGE fixups spread across the relocation data of the image at `image_base`. Fixups
+// that do not hold their recorded value, which `read` returns, are not sampled.
+fn sample_fixups(
+    image_base: u64,
+    relocation_data: &[RelocationBlock],
+    read: impl Fn(u64, u16) -> u64,
+) -> alloc::vec::Vec<FixupSample> {
+    let fixups = || {
+        relocation_data.iter().flat_map(|block| {
+            block.relocations.iter().filter_map(move |reloc| {
+                let fixup_type = reloc.type_and_offset >> 12;
+                let rva = block.block_header.page_rva as u64 + (reloc.type_and_offset & 0xFFF) as u64;
+                (fixup_type != 0).then_some((image_base + rva, fixup_type, reloc.value))
+            })
+        })
+    };
+    let step = fixups().count().div_ceil(FIXUP_SAMPLES_PER_IMAGE).max(1);
+    fixups()
+        .step_by(step)
+        .filter(|&(address, fixup_type, recorded)| read(address, fixup_type) == recorded)
+        .map(|(address, fixup_type, recorded)| FixupSample { address, fixup_type, recorded, before: recorded })
+        .collect()
+}
+
+// Records the value of each sampled fixup before SetVirtualAddressMap.
+fn read_fixups_before(images: &mut [ImageFixups], read: impl Fn(u64, u16) -> u64) {
+    for sample in images.iter_mut().flat_map(|image| image.samples.iter_mut()) {
+        sample.before = read(sample.address, sample.fixup_type);
+    }
+}
+
+// Reports the sampled fixups that were not relocated as expected by SetVirtualAddressMap, returning their count.
+fn check_fixups_after(images: &[ImageFixups], map: &VirtualMap, read: impl Fn(u64, u16) -> u64) -> usize {
+    let mut mismatches = 0;
+    for image in images {
+        // Images outside of the recorded runtime regions cannot be checked.
+        if !map.regions().iter().any(|region| region.contains_physical(image.image_base)) {
+            continue;
+        }
+        let virtual_base = map.convert(image.image_base);
+        for sample in &image.samples {
+            let expected = sample.expected(image.image_base, virtual_base);
+            let actual = read(sample.address, sample.fixup_type);
+            if actual != expected {
+                log::error!(
+                    "Runtime image at {:#x} was not relocated as expected by SetVirtualAddressMap: the fixup at rva {:#x} holds {actual:#x}, expected {expected:#x}.",
+                    image.image_base,
+                    sample.address - image.image_base
+                );
+                mismatches += 1;
+            }
+        }
+    }
+    mismatches
+}
+
+// Recalculates the checksum of the runtime services table after its functions were replaced.
+fn checksum(rt: &mut efi::RuntimeServices) {
+    rt.hdr.crc32 = 0;
+    let rs_slice =
+        unsafe { from_raw_parts(rt as *const efi::RuntimeServices as *const u8, size_of::<efi::RuntimeServices>()) };
+    rt.hdr.crc32 = crate::crc32::table_hash(rs_slice);
+}
+
+macro_rules! checked_runtime_services {
+    ($($service:ident: $type:ty = fn($($arg:ident: $arg_type:ty),*) -> $ret:ty, pointers [$($pointer:ident),*];)*) => {
+        // The runtime services the shims forward to, at their physical addresses.
+        #[derive(Clone, Copy)]
+        struct Originals {
+            $($service: $type,)*
+            set_virtual_address_map: efi::RuntimeSetVirtualAddressMap,
+        }
+
+        $(
+            extern "efiapi" fn $service($($arg: $arg_type),*) -> $ret {
+                check_call(stringify!($service), &[$((stringify!($pointer), $pointer as u64)),*]);
+                (originals().$service)($($arg),*)
+            }
+        )*
+
+        /// Replaces the runtime services in `rt` with shims that check the addressing of each call.
+        fn install_addressing_checks(rt: &mut efi::RuntimeServices) {
+            *ORIGINALS.lock() = Some(Originals {
+                $($service: rt.$service,)*
+                set_virtual_address_map: rt.set_virtual_address_map,
+            });
+            $(rt.$service = $service;)*
+            rt.set_virtual_address_map = set_virtual_address_map;
+            checksum(rt);
+            CHECKED_TABLE.store(rt, Ordering::SeqCst);
+        }
+
+        // Puts the original runtime services back in `rt`, converted to their virtual addresses.
+        fn restore_converted(rt: &mut efi::RuntimeServices, originals: &Originals, map: &VirtualMap) {
+            // SAFETY: a function pointer is the size of a usize, and the virtual address maps to the same function.
+            $(
+                rt.$service = unsafe {
+                    core::mem::transmute::<usize, $type>(map.convert(originals.$service as usize as u64) as usize)
+                };
+            )*
+            rt.set_virtual_address_map = unsafe {
+                core::mem::transmute::<usize, efi::RuntimeSetVirtualAddressMap>(
+                    map.convert(originals.set_virtual_address_map as usize as u64) as usize,
+                )
+            };
+            checksum(rt);
+        }
+    };
+}
+
+checked_runtime_services! {
+    get_time: efi::RuntimeGetTime = fn(time: *mut efi::Time, capabilities: *mut efi::TimeCapabilities)
+        -> efi::Status, pointers [time, capabilities];
+    set_time: efi::RuntimeSetTime = fn(time: *mut efi::Time) -> efi::Status, pointers [time];
+    get_wakeup_time: efi::RuntimeGetWakeupTime = fn(enabled: *mut efi::Boolean, pending: *mut efi::Boolean,
+        time: *mut efi::Time) -> efi::Status, pointers [enabled, pending, time];
+    set_wakeup_time: efi::RuntimeSetWakeupTime = fn(enable: efi::Boolean, time: *mut efi::Time) -> efi::Status,
+        pointers [time];
+    convert_pointer: efi::RuntimeConvertPointer = fn(debug_disposition: usize, address: *mut *mut c_void)
+        -> efi::Status, pointers [address];
+    get_variable: efi::RuntimeGetVariable = fn(name: *mut efi::Char16, guid: *mut efi::Guid, attributes: *mut u32,
+        data_size: *mut usize, data: *mut c_void) -> efi::Status, pointers [name, guid, attributes, data_size, data];
+    get_next_variable_name: efi::RuntimeGetNextVariableName = fn(name_size: *mut usize, name: *mut efi::Char16,
+        guid: *mut efi::Guid) -> efi::Status, pointers [name_size, name, guid];
+    set_variable: efi::RuntimeSetVariable = fn(name: *mut efi::Char16, guid: *mut efi::Guid, attributes: u32,
+        data_size: usize, data: *mut c_void) -> efi::Status, pointers [name, guid, data];
+    get_next_high_mono_count: efi::RuntimeGetNextHighMonoCount = fn(count: *mut u32) -> efi::Status, pointers [count];
+    reset_system: efi::RuntimeResetSystem = fn(reset_type: efi::ResetType, status: efi::Status, data_size: usize,
+        data: *mut c_void) -> (), pointers [data];
+    update_capsule: efi::RuntimeUpdateCapsule = fn(capsules: *mut *mut efi::CapsuleHeader, count: usize,
+        scatter_gather_list: efi::PhysicalAddress) -> efi::Status, pointers [capsules];
+    query_capsule_capabilities: efi::RuntimeQueryCapsuleCapabilities = fn(capsules: *mut *mut efi::CapsuleHeader,
+        count: usize, maximum_size: *mut u64, reset_type: *mut efi::ResetType) -> efi::Status,
+        pointers [capsules, maximum_size, reset_type];
+    query_variable_info: efi::RuntimeQueryVariableInfo = fn(attributes: u32, maximum_storage_size: *mut u64,
+        remaining_storage_size: *mut u64, maximum_variable_size: *mut u64) -> efi::Status,
+        pointers [maximum_storage_size, remaining_storage_size, maximum_variable_size];
+}
+
+static ORIGINALS: Mutex<Option<Originals>> = Mutex::new(None);
+
+fn originals() -> Originals {
+    ORIGINALS.lock().expect("Runtime services addressing checks called before they were installed.")
+}
+
+extern "efiapi" fn set_virtual_address_map(
+    map_size: usize,
+    descriptor_size: usize,
+    descriptor_version: u32,
+    virtual_map: *mut efi::MemoryDescriptor,
+) -> efi::Status {
+    let originals = originals();
+    if VIRTUAL_MODE.load(Ordering::SeqCst) {
+        report(
+            "set_virtual_address_map",
+            format_args!("was called through its physical address after SetVirtualAddressMap"),
+        );
+        return (originals.set_virtual_address_map)(map_size, descriptor_size, descriptor_version, virtual_map);
+    }
+
+    let mut fixup_samples = FIXUP_SAMPLES.lock();
+    // SAFETY: the runtime images are still identity mapped until SetVirtualAddressMap returns.
+    read_fixups_before(&mut fixup_samples, |address, fixup_type| unsafe { read_fixup(address, fixup_type) });
+
+    let status = (originals.set_virtual_address_map)(map_size, descriptor_size, descriptor_version, virtual_map);
+    if status != efi::Status::SUCCESS || descriptor_size == 0 {
+        return status;
+    }
+
+    let mut map = VIRTUAL_MAP.lock();
+    map.count = 0;
+    for index in 0..map_size / descriptor_size {
+        // SAFETY: SetVirtualAddressMap succeeded, so the map holds `map_size` bytes of valid descriptors.
+        let descriptor = unsafe {
+            ptr::read_unaligned((virtual_map as *const u8).add(index * descriptor_size) as *const efi::MemoryDescriptor)
+        };
+        if descriptor.attribute & efi::MEMORY_RUNTIME == 0 {
+            continue;
+        }
+        if map.count == MAX_RUNTIME_REGIONS {
+            log::warn!("Runtime addressing checks only cover the first {MAX_RUNTIME_REGIONS} runtime regions.");
+            break;
+        }
+        let count = map.count;
+        map.regions[count] = RuntimeRegion {
+            physical: descriptor.physical_start,
+            virtual_address: descriptor.virtual_start,
+            size: descriptor.number_of_pages * 0x1000,
+        };
+        map.count += 1;
+    }
+    VIRTUAL_MODE.store(true, Ordering::SeqCst);
+
+    // SAFETY: as above, the physical addresses of the runtime images are still mapped during the call.
+    let mismatches =
+        check_fixups_after(&fixup_samples, &map, |address, fixup_type| unsafe { read_fixup(address, fixup_type) });
+    debug_assert_eq!(mismatches, 0, "Runtime images were not relocated as expected by SetVirtualAddressMap.");
+
+    // SAFETY: the table was checked by install_addressing_checks, and is still identity mapped during the call.
+    if let Some(rt) = unsafe { CHECKED_TABLE.load(Ordering::SeqCst).as_mut() } {
+        restore_converted(rt, &originals, &map);
+    }
+    status
+}
+
 #[cfg(test)]
 #[coverage(off)]
 mod tests {
@@ -338,4 +775,153 @@ mod tests {
         })
         .unwrap_or_else(|e| panic!("Test failed with runtime allocator conflict: {:?}", e));
     }
+
+    const REGION: RuntimeRegion =
+        RuntimeRegion { physical: 0x8000_0000, virtual_address: 0xffff_8000_0000_0000, size: 0x2000 };
+
+    #[test]
+    fn test_virtual_addresses_are_flagged_before_set_virtual_address_map() {
+        let is_physical = |address: u64| address < 0x1_0000_0000;
+        assert!(!is_misaddressed(0x8000_1000, false, &[], is_physical));
+        assert!(is_misaddressed(0xffff_8000_0000_1000, false, &[], is_physical));
+    }
+
+    #[test]
+    fn test_physical_runtime_addresses_are_flagged_after_set_virtual_address_map() {
+        let is_physical = |_| panic!("physical memory is not checked in virtual mode");
+        assert!(is_misaddressed(0x8000_1000, true, &[REGION], is_physical));
+        assert!(!is_misaddressed(0xffff_8000_0000_1000, true, &[REGION], is_physical));
+        // OS buffers outside of the runtime regions cannot be checked.
+        assert!(!is_misaddressed(0x4000_0000, true, &[REGION], is_physical));
+
+        let identity = RuntimeRegion { virtual_address: REGION.physical, ..REGION };
+        assert!(!is_misaddressed(0x8000_1000, true, &[identity], is_physical));
+    }
+
+    fn relocation_block(page_rva: u32, fixups: &[(u16, u16, u64)]) -> RelocationBlock {
+        RelocationBlock {
+            block_header: crate::pecoff::relocation::BaseRelocationBlockHeader { page_rva, block_size: 0 },
+            relocations: fixups
+                .iter()
+                .map(|&(fixup_type, offset, value)| crate::pecoff::relocation::Relocation {
+                    type_and_offset: (fixup_type << 12) | offset,
+                    value,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_fixups_are_sampled_across_the_relocation_data() {
+        let fixups: Vec<(u16, u16, u64)> = (0..40).map(|i| (pecoff::IMAGE_REL_BASED_DIR64, i * 8, i as u64)).collect();
+        let blocks = [relocation_block(0x1000, &fixups[..20]), relocation_block(0x2000, &fixups[20..])];
+
+        let read = |address: u64, _| (address & 0xFFF) / 8;
+        let samples = sample_fixups(0x8000_0000, &blocks, read);
+        assert_eq!(samples.len(), 14);
+        assert_eq!(samples[0], FixupSample { address: 0x8000_1000, fixup_type: 10, recorded: 0, before: 0 });
+        assert_eq!(samples[13].address, 0x8000_2000 + 39 * 8);
+
+        // fixups that do not hold their recorded value, and ABSOLUTE padding, are not sampled.
+        let blocks = [relocation_block(0x1000, &[(0, 0, 0), (pecoff::IMAGE_REL_BASED_HIGHLOW, 8, 7)])];
+        assert!(sample_fixups(0x8000_0000, &blocks, |_, _| 0).is_empty());
+    }
+
+    #[test]
+    fn test_fixups_are_checked_after_set_virtual_address_map() {
+        let mut memory = [0u64; 4];
+        let base = memory.as_mut_ptr() as u64;
+        let region = RuntimeRegion { physical: base, virtual_address: 0xffff_0000_0000_0000, size: 0x1000 };
+        let mut map = VirtualMap { regions: [RuntimeRegion::default(); MAX_RUNTIME_REGIONS], count: 1 };
+        map.regions[0] = region;
+        let adjustment = region.virtual_address.wrapping_sub(base);
+
+        memory = [base + 0x10, base + 0x20, base + 0x30, 0x1234];
+        let blocks = [relocation_block(
+            0,
+            &[
+                (pecoff::IMAGE_REL_BASED_DIR64, 0, base + 0x10),
+                (pecoff::IMAGE_REL_BASED_DIR64, 8, base + 0x20),
+                (pecoff::IMAGE_REL_BASED_DIR64, 16, base + 0x30),
+                (pecoff::IMAGE_REL_BASED_LOW, 24, 0x1234),
+            ],
+        )];
+        let read = |address, fixup_type| unsafe { read_fixup(address, fixup_type) };
+        let mut images = [ImageFixups { handle: 1, image_base: base, samples: sample_fixups(base, &blocks, read) }];
+        assert_eq!(images[0].samples.len(), 4);
+
+        // the image changed its third fixup since it was loaded.
+        memory[2] = 0x42;
+        read_fixups_before(&mut images, read);
+
+        // the runtime driver relocates the fixups that hold their recorded values, but misses the first one.
+        memory[1] = memory[1].wrapping_add(adjustment);
+        memory[3] = 0x1234u16.wrapping_add(adjustment as u16) as u64;
+        assert_eq!(check_fixups_after(&images, &map, read), 1);
+
+        memory[0] = memory[0].wrapping_add(adjustment);
+        assert_eq!(check_fixups_after(&images, &map, read), 0);
+
+        // images outside of the runtime regions are not checked.
+        map.count = 0;
+        memory[0] = 0;
+        assert_eq!(check_fixups_after(&images, &map, read), 0);
+    }
+
+    extern "efiapi" fn mock_get_next_high_mono_count(count: *mut u32) -> efi::Status {
+        unsafe { count.write(7) };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn mock_set_virtual_address_map(
+        _map_size: usize,
+        _descriptor_size: usize,
+        _descriptor_version: u32,
+        _virtual_map: *mut efi::MemoryDescriptor,
+    ) -> efi::Status {
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_checks_forward_calls_and_are_removed_by_set_virtual_address_map() {
+        with_global_lock(|| {
+            unsafe { crate::test_support::init_test_gcd(None) };
+            crate::systemtables::init_system_table();
+            let mut st_guard = SYSTEM_TABLE.lock();
+            let rt = st_guard.as_mut().expect("System Table not initialized!").runtime_services_mut();
+            rt.get_next_high_mono_count = mock_get_next_high_mono_count;
+            rt.set_virtual_address_map = mock_set_virtual_address_map;
+            install_addressing_checks(rt);
+            assert_ne!(rt.get_next_high_mono_count as usize, mock_get_next_high_mono_count as usize);
+
+            let mut count = 0;
+            assert_eq!((rt.get_next_high_mono_count)(&mut count), efi::Status::SUCCESS);
+            assert_eq!(count, 7);
+
+            let mut descriptors = [efi::MemoryDescriptor {
+                r#type: efi::RUNTIME_SERVICES_DATA,
+                physical_start: REGION.physical,
+                virtual_start: REGION.virtual_address,
+                number_of_pages: 2,
+                attribute: efi::MEMORY_RUNTIME,
+            }];
+            let size = size_of::<efi::MemoryDescriptor>();
+            assert_eq!((rt.set_virtual_address_map)(size, size, 1, descriptors.as_mut_ptr()), efi::Status::SUCCESS);
+            assert!(VIRTUAL_MODE.load(Ordering::SeqCst));
+            assert_eq!(VIRTUAL_MAP.lock().regions(), [REGION]);
+            // The mocks are not in a runtime region, so they are restored unconverted.
+            assert_eq!(rt.get_next_high_mono_count as usize, mock_get_next_high_mono_count as usize);
+
+            // A caller that kept the physical pointer to the shim still reaches the runtime service.
+            let mut count = 0;
+            assert_eq!(get_next_high_mono_count(&mut count), efi::Status::SUCCESS);
+            assert_eq!(count, 7);
+
+            VIRTUAL_MODE.store(false, Ordering::SeqCst);
+            VIRTUAL_MAP.lock().count = 0;
+            *ORIGINALS.lock() = None;
+            CHECKED_TABLE.store(ptr::null_mut(), Ordering::SeqCst);
+        })
+        .unwrap();
+    }
 }
@@ -0,0 +1,330 @@
+//! Security Posture Report
+//!
+//! Platform owners otherwise have to piece together the security health of a boot from many log lines. At ReadyToBoot,
+//! the core collects one report of the state that matters most:
+//!
+//! - whether UEFI Secure Boot is enabled, from the `SecureBoot` variable.
+//! - the images that activated, or were refused, compatibility mode.
+//! - the memory protections in effect: a non-executable handoff stack, the null pointer guard on page 0, and heap
+//!   guard pages, which the core does not place even if the [MemoryProtectionPolicy] requests them.
+//! - the images that were loaded although their authentication failed, and the number of images loaded before the
+//!   Security Architectural Protocols were installed, which are not verified.
+//! - the architectural protocols that are missing.
+//!
+//! The report is logged, published as a configuration table with [SECURITY_POSTURE_TABLE_GUID], and available through
+//! the `security_posture` debugger monitor command. The configuration table is a [SecurityPostureTableHeader], whose
+//! flags summarize the report, followed by the report as NUL terminated UTF-8 text.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    ffi::c_void,
+    fmt::{self, Display},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use patina::{
+    base::UEFI_PAGE_SIZE,
+    component::service::compatibility_mode::CompatibilityModeActivation,
+    error::EfiError,
+    runtime_services::{
+        RuntimeServices, StandardRuntimeServices, variable_services::authenticated::GLOBAL_VARIABLE_GUID,
+    },
+};
+use patina_pi::dxe_services::GcdMemoryType;
+use r_efi::efi;
+use spin::Mutex;
+
+use crate::{
+    ARCH_PROTOCOLS, GCD, MemoryProtectionPolicy, compatibility_mode, config_tables, events::EVENT_DB, guid_names,
+    protocols::PROTOCOL_DB, stack_protector, systemtables,
+};
+
+/// The GUID of the configuration table containing the security posture report.
+pub const SECURITY_POSTURE_TABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0x1b4a7c3e, 0x52d6, 0x4f0b, 0x8e, 0x91, &[0x3a, 0x6c, 0x2f, 0xd4, 0x07, 0xb8]);
+
+/// The header of the security posture configuration table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SecurityPostureTableHeader {
+    /// `PSEC`
+    pub signature: u32,
+    /// The version of the table format.
+    pub version: u32,
+    /// The length of the table, including the header and report.
+    pub length: u32,
+    /// A summary of the report, as a bitmask of the `FLAG_*` constants.
+    pub flags: u32,
+}
+
+impl SecurityPostureTableHeader {
+    /// The signature of the table.
+    pub const SIGNATURE: u32 = u32::from_le_bytes(*b"PSEC");
+    /// The current version of the table format.
+    pub const VERSION: u32 = 1;
+    /// Secure Boot is enabled.
+    pub const FLAG_SECURE_BOOT: u32 = 1 << 0;
+    /// An image activated compatibility mode.
+    pub const FLAG_COMPATIBILITY_MODE: u32 = 1 << 1;
+    /// The handoff stack is not executable.
+    pub const FLAG_NX_STACK: u32 = 1 << 2;
+    /// Page 0 is not mapped.
+    pub const FLAG_NULL_GUARD: u32 = 1 << 3;
+    /// Heap allocations are surrounded by guard pages. Never set, as the core does not place heap guard pages.
+    pub const FLAG_HEAP_GUARD: u32 = 1 << 4;
+    /// An image was loaded although its authentication failed.
+    pub const FLAG_UNSIGNED_IMAGES: u32 = 1 << 5;
+    /// An architectural protocol is missing.
+    pub const FLAG_MISSING_ARCH_PROTOCOLS: u32 = 1 << 6;
+}
+
+static REPORT: spin::Once<String> = spin::Once::new();
+static UNVERIFIED_IMAGES: AtomicUsize = AtomicUsize::new(0);
+static UNSIGNED_IMAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static POSTURE_INPUTS: Mutex<Option<(StandardRuntimeServices, Option<MemoryProtectionPolicy>)>> = Mutex::new(None);
+
+/// Records that an image was loaded without being verified, as no Security Architectural Protocol was installed.
+pub(crate) fn record_unverified_image() {
+    UNVERIFIED_IMAGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records that the image with `filename` was loaded although its authentication failed with `err`.
+pub(crate) fn record_unsigned_image(filename: Option<&str>, err: EfiError) {
+    UNSIGNED_IMAGES.lock().push(alloc::format!("{} ({err:?})", filename.unwrap_or("Unknown")));
+}
+
+/// Publishes the security posture report at ReadyToBoot.
+pub(crate) fn enable(runtime_services: StandardRuntimeServices, policy: Option<MemoryProtectionPolicy>) {
+    *POSTURE_INPUTS.lock() = Some((runtime_services, policy));
+
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(publish_event),
+        None,
+        Some(efi::EVENT_GROUP_READY_TO_BOOT),
+    ) {
+        log::error!("Failed to register an event to publish the security posture report! Status {status:#X?}");
+    }
+}
+
+/// A summary of the security state of a boot.
+#[derive(Debug)]
+struct SecurityPosture {
+    // None if the SecureBoot variable could not be read.
+    secure_boot: Option<bool>,
+    compatibility_mode: Vec<CompatibilityModeActivation>,
+    nx_stack: bool,
+    null_guard: bool,
+    // the memory types whose page or pool allocations the policy requests guard pages for.
+    heap_guard_requested: u32,
+    unsigned_images: Vec<String>,
+    unverified_images: usize,
+    missing_arch_protocols: Vec<String>,
+}
+
+impl SecurityPosture {
+    fn collect(runtime_services: &impl RuntimeServices, policy: Option<&MemoryProtectionPolicy>) -> Self {
+        let name: Vec<u16> = "SecureBoot".encode_utf16().chain([0]).collect();
+        let secure_boot = match runtime_services.get_variable::<Vec<u8>>(&name, &GLOBAL_VARIABLE_GUID, Some(1)) {
+            Ok((value, _)) => Some(value.first() == Some(&1)),
+            Err(efi::Status::NOT_FOUND) => Some(false),
+            Err(status) => {
+                log::warn!("Failed to read the SecureBoot variable: {status:#x?}");
+                None
+            }
+        };
+
+        // the page above the guard page, as the guard page is read protected rather than non-executable.
+        let stack = stack_protector::handoff_stack();
+        let nx_stack = !stack.is_empty()
+            && GCD
+                .get_memory_descriptor_for_address((stack.start + UEFI_PAGE_SIZE) as efi::PhysicalAddress)
+                .is_ok_and(|descriptor| descriptor.attributes & efi::MEMORY_XP != 0);
+        let null_guard = GCD.get_memory_descriptor_for_address(0).is_ok_and(|descriptor| {
+            descriptor.memory_type == GcdMemoryType::NonExistent || descriptor.attributes & efi::MEMORY_RP != 0
+        });
+
+        Self {
+            secure_boot,
+            compatibility_mode: compatibility_mode::activations(),
+            nx_stack,
+            null_guard,
+            heap_guard_requested: policy
+                .map_or(0, |policy| policy.page_guard_memory_types | policy.pool_guard_memory_types),
+            unsigned_images: UNSIGNED_IMAGES.lock().clone(),
+            unverified_images: UNVERIFIED_IMAGES.load(Ordering::Relaxed),
+            missing_arch_protocols: ARCH_PROTOCOLS
+                .iter()
+                .filter(|guid| PROTOCOL_DB.locate_protocol(**guid).is_err())
+                .map(|guid| guid_names::named(guid).to_string())
+                .collect(),
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        [
+            (self.secure_boot == Some(true), SecurityPostureTableHeader::FLAG_SECURE_BOOT),
+            (
+                self.compatibility_mode.iter().any(|activation| activation.allowed),
+                SecurityPostureTableHeader::FLAG_COMPATIBILITY_MODE,
+            ),
+            (self.nx_stack, SecurityPostureTableHeader::FLAG_NX_STACK),
+            (self.null_guard, SecurityPostureTableHeader::FLAG_NULL_GUARD),
+            (!self.unsigned_images.is_empty(), SecurityPostureTableHeader::FLAG_UNSIGNED_IMAGES),
+            (!self.missing_arch_protocols.is_empty(), SecurityPostureTableHeader::FLAG_MISSING_ARCH_PROTOCOLS),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag)
+    }
+
+    /// Builds the configuration table for the report.
+    fn to_table(&self, report: &str) -> Vec<u8> {
+        let length = size_of::<SecurityPostureTableHeader>() + report.len() + 1;
+        let header = SecurityPostureTableHeader {
+            signature: SecurityPostureTableHeader::SIGNATURE,
+            version: SecurityPostureTableHeader::VERSION,
+            length: length as u32,
+            flags: self.flags(),
+        };
+
+        let mut table = Vec::with_capacity(length);
+        for field in [header.signature, header.version, header.length, header.flags] {
+            table.extend_from_slice(&field.to_le_bytes());
+        }
+        table.extend_from_slice(report.as_bytes());
+        table.push(0);
+        table
+    }
+
+    /// Publishes the report as a configuration table and through the `security_posture` monitor command.
+    fn publish(self) -> Result<(), EfiError> {
+        let report = REPORT.call_once(|| alloc::format!("{self}"));
+        log::info!("Security Posture:\n{report}");
+
+        let table = self.to_table(report).leak();
+        let mut st = systemtables::SYSTEM_TABLE.lock();
+        let st = st.as_mut().expect("System Table not initialized!");
+        config_tables::core_install_configuration_table(
+            SECURITY_POSTURE_TABLE_GUID,
+            table.as_mut_ptr() as *mut c_void,
+            st,
+        )?;
+
+        patina_debugger::add_monitor_command("security_posture", "Prints the security posture report", |_, out| {
+            let _ = out.write_str(REPORT.get().map_or("Security posture not yet collected.", |r| r.as_str()));
+        });
+        Ok(())
+    }
+}
+
+impl Display for SecurityPosture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.secure_boot {
+            Some(enabled) => writeln!(f, "secure_boot: {enabled}")?,
+            None => writeln!(f, "secure_boot: unknown")?,
+        }
+        writeln!(f, "compatibility_mode:")?;
+        self.compatibility_mode.iter().try_for_each(|activation| {
+            let outcome = if activation.allowed { "activated" } else { "refused" };
+            writeln!(f, "  {outcome}: {}", activation.image)
+        })?;
+        writeln!(f, "protections:")?;
+        writeln!(f, "  nx_stack: {}", self.nx_stack)?;
+        writeln!(f, "  null_guard: {}", self.null_guard)?;
+        match self.heap_guard_requested {
+            0 => writeln!(f, "  heap_guard: false")?,
+            types => writeln!(f, "  heap_guard: false (requested for memory types {types:#x}, not supported)")?,
+        }
+        writeln!(f, "unsigned_images:")?;
+        self.unsigned_images.iter().try_for_each(|image| writeln!(f, "  {image}"))?;
+        writeln!(f, "unverified_images: {}", self.unverified_images)?;
+        writeln!(f, "missing_arch_protocols:")?;
+        self.missing_arch_protocols.iter().try_for_each(|protocol| writeln!(f, "  {protocol}"))
+    }
+}
+
+extern "efiapi" fn publish_event(event: efi::Event, _context: *mut c_void) {
+    let _ = EVENT_DB.close_event(event);
+    let Some((runtime_services, policy)) = POSTURE_INPUTS.lock().take() else {
+        return;
+    };
+    if let Err(err) = SecurityPosture::collect(&runtime_services, policy.as_ref()).publish() {
+        log::error!("Failed to publish the security posture report: {err:?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use alloc::vec;
+    use patina::{component::service::compatibility_mode::RelaxedProtections, runtime_services::MockRuntimeServices};
+
+    #[test]
+    fn test_security_posture_table() {
+        let posture = SecurityPosture {
+            secure_boot: Some(true),
+            compatibility_mode: vec![CompatibilityModeActivation {
+                image: String::from("grubx64.efi"),
+                image_base: 0x1000_0000,
+                image_size: 0x10_0000,
+                allowed: true,
+                relaxed: RelaxedProtections::ALL,
+            }],
+            nx_stack: true,
+            null_guard: false,
+            heap_guard_requested: 1 << efi::BOOT_SERVICES_DATA,
+            unsigned_images: vec![String::from("shell.efi (SecurityViolation)")],
+            unverified_images: 12,
+            missing_arch_protocols: vec![],
+        };
+        let report = alloc::format!("{posture}");
+        assert_eq!(
+            report,
+            "secure_boot: true\ncompatibility_mode:\n  activated: grubx64.efi\nprotections:\n  nx_stack: true\n  \
+             null_guard: false\n  heap_guard: false (requested for memory types 0x10, not supported)\n\
+             unsigned_images:\n  shell.efi (SecurityViolation)\nunverified_images: 12\nmissing_arch_protocols:\n"
+        );
+
+        let table = posture.to_table(&report);
+        let field = |offset: usize| u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap());
+        assert_eq!(field(0), SecurityPostureTableHeader::SIGNATURE);
+        assert_eq!(field(8) as usize, table.len());
+        assert_eq!(
+            field(12),
+            SecurityPostureTableHeader::FLAG_SECURE_BOOT
+                | SecurityPostureTableHeader::FLAG_COMPATIBILITY_MODE
+                | SecurityPostureTableHeader::FLAG_NX_STACK
+                | SecurityPostureTableHeader::FLAG_UNSIGNED_IMAGES
+        );
+        assert_eq!(table.last(), Some(&0));
+    }
+
+    #[test]
+    fn test_secure_boot_state_is_read_from_the_variable() {
+        test_support::with_global_lock(|| {
+            let secure_boot = |result: Result<(Vec<u8>, u32), efi::Status>| {
+                let mut runtime_services = MockRuntimeServices::new();
+                runtime_services.expect_get_variable::<Vec<u8>>().returning(move |_, _, _| result.clone());
+                SecurityPosture::collect(&runtime_services, None).secure_boot
+            };
+            assert_eq!(secure_boot(Ok((vec![1], 0))), Some(true));
+            assert_eq!(secure_boot(Ok((vec![0], 0))), Some(false));
+            assert_eq!(secure_boot(Err(efi::Status::NOT_FOUND)), Some(false));
+            assert_eq!(secure_boot(Err(efi::Status::DEVICE_ERROR)), None);
+        })
+        .unwrap();
+    }
+}
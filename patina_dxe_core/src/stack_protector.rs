@@ -0,0 +1,206 @@
+//! Stack Protector
+//!
+//! When the core is built with the compiler's stack protector (`RUSTFLAGS=-Zstack-protector=strong`), functions that
+//! keep buffers on the stack save a guard value below their return address and check that it is unchanged before
+//! returning, so that an overrun of the buffer is caught before the corrupted return address is used. This module
+//! provides the guard and the check that the UEFI targets call: they follow the MSVC convention, where the guard is
+//! `__security_cookie` and the check is `__security_check_cookie`. A failed check calls `__stack_chk_fail`, which
+//! reports the running image and panics, so that the platform's [PanicPolicy](crate::PanicPolicy) applies.
+//!
+//! The guard starts as a built-in value, which an attacker can know. If the platform registers an
+//! [Entropy](patina::component::service::entropy::Entropy) service with the core, the guard is replaced with a random
+//! value from it when the core starts. At that point the only live frames are on the handoff stack, the stack the core
+//! was entered on, as the core has not yet moved to its own stack. Those frames saved the built-in guard, so checks on
+//! the handoff stack also accept it; every frame on any other stack must hold the random guard.
+//!
+//! Without the compiler flag nothing calls into this module, and the guard is never used.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use patina::{component::service::entropy::Entropy, guids::HOB_MEMORY_ALLOC_STACK};
+use patina_pi::hob::{self, Hob, HobList};
+
+// The guard until it is seeded, the same default the MSVC runtime uses.
+const BOOT_GUARD: usize = 0x2B99_2DDF_A232;
+
+#[cfg_attr(target_os = "uefi", unsafe(export_name = "__security_cookie"))]
+static GUARD: AtomicUsize = AtomicUsize::new(BOOT_GUARD);
+
+// The handoff stack, from the stack memory allocation HOB. Empty if there is no stack HOB.
+static HANDOFF_STACK_BASE: AtomicUsize = AtomicUsize::new(0);
+static HANDOFF_STACK_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the handoff stack from the stack memory allocation HOB in `hob_list`.
+pub(crate) fn record_handoff_stack(hob_list: &HobList) {
+    let stack = hob_list.iter().find_map(|hob| match hob {
+        Hob::MemoryAllocation(hob::MemoryAllocation { alloc_descriptor: desc, .. })
+            if desc.name == HOB_MEMORY_ALLOC_STACK =>
+        {
+            Some(desc.memory_base_address as usize..(desc.memory_base_address + desc.memory_length) as usize)
+        }
+        _ => None,
+    });
+    if let Some(stack) = stack {
+        HANDOFF_STACK_BASE.store(stack.start, Ordering::SeqCst);
+        HANDOFF_STACK_END.store(stack.end, Ordering::SeqCst);
+    }
+}
+
+/// Returns the handoff stack, or an empty range if there is no stack HOB.
+pub(crate) fn handoff_stack() -> Range<usize> {
+    HANDOFF_STACK_BASE.load(Ordering::Relaxed)..HANDOFF_STACK_END.load(Ordering::Relaxed)
+}
+
+/// Replaces the built-in guard with a random value from `entropy`.
+///
+/// Must be called on the handoff stack before the core moves to its own stack, as frames elsewhere that saved the
+/// built-in guard would fail their check.
+pub(crate) fn seed_stack_guard(entropy: &dyn Entropy) {
+    if GUARD.load(Ordering::SeqCst) != BOOT_GUARD {
+        return;
+    }
+    if !handoff_stack().contains(&stack_pointer()) {
+        log::warn!("Not running on the handoff stack from the stack HOB, keeping the built-in stack protector guard.");
+        return;
+    }
+
+    let mut bytes = [0u8; size_of::<usize>()];
+    if let Err(err) = entropy.fill_bytes(&mut bytes) {
+        log::error!("Failed to get entropy for the stack protector guard, keeping the built-in guard: {err:?}");
+        return;
+    }
+    match usize::from_ne_bytes(bytes) {
+        0 | BOOT_GUARD => {
+            log::warn!("Entropy service returned a weak stack protector guard, keeping the built-in guard.")
+        }
+        guard => {
+            GUARD.store(guard, Ordering::SeqCst);
+            log::info!("Stack protector guard seeded from the entropy service.");
+        }
+    }
+}
+
+// Returns true if `cookie`, the guard saved by the frame being checked, is intact. Frames on the handoff stack may
+// have saved the built-in guard before the guard was seeded.
+#[cfg(any(test, target_os = "uefi"))]
+fn cookie_is_intact(cookie: usize, guard: usize, stack_pointer: usize, handoff_stack: Range<usize>) -> bool {
+    cookie == guard || (cookie == BOOT_GUARD && handoff_stack.contains(&stack_pointer))
+}
+
+// Returns the current stack pointer. This must not take the address of a local, which would make the caller a
+// function that the stack protector checks.
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let stack_pointer: usize;
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            // Safety: reads the stack pointer into a register.
+            unsafe { core::arch::asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags)) };
+        } else if #[cfg(target_arch = "aarch64")] {
+            // Safety: reads the stack pointer into a register.
+            unsafe { core::arch::asm!("mov {}, sp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags)) };
+        } else {
+            stack_pointer = core::hint::black_box(0);
+        }
+    }
+    stack_pointer
+}
+
+/// Checks the guard saved by a frame before it returns. Called by functions instrumented by the stack protector.
+#[cfg(target_os = "uefi")]
+#[unsafe(no_mangle)]
+extern "C" fn __security_check_cookie(cookie: usize) {
+    if !cookie_is_intact(cookie, GUARD.load(Ordering::Relaxed), stack_pointer(), handoff_stack()) {
+        __stack_chk_fail();
+    }
+}
+
+/// Reports a stack buffer overrun and panics.
+#[cfg(target_os = "uefi")]
+#[unsafe(no_mangle)]
+extern "C" fn __stack_chk_fail() -> ! {
+    use crate::image;
+
+    // the image data may be locked by the function whose stack was overrun.
+    let image: alloc::string::String = match image::try_current_image_handle() {
+        Some(None) => "the DXE Core".into(),
+        Some(Some(handle)) => image::try_image_name(handle).unwrap_or_else(|| alloc::format!("image {handle:?}")),
+        None => "an unknown image".into(),
+    };
+    panic!("Stack buffer overrun detected while running {image}: the stack protector guard was overwritten.");
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    use crate::test_support;
+    use patina::{component::service::entropy::MockEntropy, error::EfiError};
+
+    #[test]
+    fn test_built_in_guard_is_only_accepted_on_the_handoff_stack() {
+        let handoff = 0x10000..0x20000;
+        assert!(cookie_is_intact(0x1234, 0x1234, 0x50000, handoff.clone()));
+        assert!(cookie_is_intact(BOOT_GUARD, BOOT_GUARD, 0x50000, handoff.clone()));
+        assert!(cookie_is_intact(BOOT_GUARD, 0x1234, 0x18000, handoff.clone()));
+        assert!(!cookie_is_intact(BOOT_GUARD, 0x1234, 0x50000, handoff.clone()));
+        assert!(!cookie_is_intact(0x4321, 0x1234, 0x18000, handoff));
+    }
+
+    #[test]
+    fn test_guard_is_seeded_from_entropy() {
+        test_support::with_global_lock(|| {
+            let reset = || {
+                GUARD.store(BOOT_GUARD, Ordering::SeqCst);
+                HANDOFF_STACK_BASE.store(0, Ordering::SeqCst);
+                HANDOFF_STACK_END.store(usize::MAX, Ordering::SeqCst);
+            };
+            let entropy = |result: fn(&mut [u8]) -> patina::error::Result<()>| {
+                let mut entropy = MockEntropy::new();
+                entropy.expect_fill_bytes().returning(result);
+                entropy
+            };
+
+            reset();
+            seed_stack_guard(&entropy(|_| Err(EfiError::DeviceError)));
+            assert_eq!(GUARD.load(Ordering::SeqCst), BOOT_GUARD);
+            seed_stack_guard(&entropy(|buffer| {
+                buffer.fill(0);
+                Ok(())
+            }));
+            assert_eq!(GUARD.load(Ordering::SeqCst), BOOT_GUARD);
+
+            seed_stack_guard(&entropy(|buffer| {
+                buffer.fill(0xA5);
+                Ok(())
+            }));
+            assert_eq!(GUARD.load(Ordering::SeqCst), usize::from_ne_bytes([0xA5; size_of::<usize>()]));
+            // the guard is only seeded once.
+            seed_stack_guard(&entropy(|buffer| {
+                buffer.fill(0x5A);
+                Ok(())
+            }));
+            assert_eq!(GUARD.load(Ordering::SeqCst), usize::from_ne_bytes([0xA5; size_of::<usize>()]));
+
+            // the guard is not seeded off the handoff stack.
+            reset();
+            HANDOFF_STACK_END.store(0, Ordering::SeqCst);
+            seed_stack_guard(&entropy(|buffer| {
+                buffer.fill(0xA5);
+                Ok(())
+            }));
+            assert_eq!(GUARD.load(Ordering::SeqCst), BOOT_GUARD);
+        })
+        .unwrap();
+    }
+}
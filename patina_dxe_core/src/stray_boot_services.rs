@@ -0,0 +1,284 @@
+//! Boot Services Invalidation after ExitBootServices
+//!
+//! Once ExitBootServices succeeds, the boot services no longer exist, but a driver that kept a pointer to the boot
+//! services table can still call through it, for example from a runtime event. Such a call would run core code
+//! against structures that were freed or are owned by the OS, and corrupt them in ways that are hard to trace back to
+//! the caller. The core instead replaces every function in the boot services table with a stub that returns
+//! EFI_UNSUPPORTED, so that stray calls fail the same way every time.
+//!
+//! For the first calls after ExitBootServices, the stubs also log the boot service that was called and the image that
+//! was running when ExitBootServices was called. The image is recorded in a fixed size static during ExitBootServices,
+//! as the image data lives in boot services memory and nothing may be allocated afterwards. The length of this debug
+//! window is set with [StrayBootServicesLogging]; the window is kept short, as the OS takes over the hardware used for
+//! logging soon after ExitBootServices. The logging is disabled when boot services memory is zeroed at
+//! ExitBootServices, as the logger may live in that memory.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::{
+    ffi::c_void,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use r_efi::efi;
+
+use crate::image;
+
+/// The number of stray boot services calls that are logged after ExitBootServices.
+///
+/// Sixteen calls are logged unless the platform registers this config. Zero disables the logging, for platforms whose
+/// logging cannot be used after ExitBootServices.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, StrayBootServicesLogging};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(StrayBootServicesLogging { logged_calls: 0 })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrayBootServicesLogging {
+    /// The number of calls that are logged before the stubs stop logging.
+    pub logged_calls: u32,
+}
+
+impl Default for StrayBootServicesLogging {
+    fn default() -> Self {
+        Self { logged_calls: DEFAULT_LOGGED_CALLS }
+    }
+}
+
+const DEFAULT_LOGGED_CALLS: u32 = 16;
+
+// The number of calls that are still logged.
+static CALLS_TO_LOG: AtomicU32 = AtomicU32::new(DEFAULT_LOGGED_CALLS);
+// Set while a call is logged, so that a logger calling a boot service does not recurse.
+static LOGGING: AtomicBool = AtomicBool::new(false);
+
+// The longest image name that is recorded at ExitBootServices; longer names are truncated.
+const MAX_IMAGE_NAME: usize = 64;
+
+// The image that was running when ExitBootServices was called.
+struct ExitingImage {
+    handle: usize,
+    name: [u8; MAX_IMAGE_NAME],
+    name_len: usize,
+}
+
+impl ExitingImage {
+    const NONE: Self = Self { handle: 0, name: [0; MAX_IMAGE_NAME], name_len: 0 };
+
+    fn record(&mut self, handle: efi::Handle, name: Option<&str>) {
+        self.handle = handle as usize;
+        let name = name.unwrap_or_default();
+        let mut len = name.len().min(MAX_IMAGE_NAME);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.name_len = len;
+    }
+
+    fn name(&self) -> &str {
+        // the name was truncated at a character boundary when it was recorded.
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or_default()
+    }
+}
+
+static EXITING_IMAGE: spin::Mutex<ExitingImage> = spin::Mutex::new(ExitingImage::NONE);
+
+/// Applies the platform's debug window for stray boot services calls.
+pub(crate) fn set_logging(config: &StrayBootServicesLogging) {
+    CALLS_TO_LOG.store(config.logged_calls, Ordering::SeqCst);
+}
+
+/// Records the image that is calling ExitBootServices, for the attribution of stray calls.
+///
+/// Must be called during ExitBootServices, before the boot services are invalidated. The debug window is closed if
+/// `zeroing_boot_services` is set, as the logger may live in the memory that is zeroed.
+pub(crate) fn record_exit_boot_services(zeroing_boot_services: bool) {
+    if zeroing_boot_services {
+        CALLS_TO_LOG.store(0, Ordering::SeqCst);
+    }
+
+    let mut exiting = EXITING_IMAGE.lock();
+    *exiting = ExitingImage::NONE;
+    image::try_with_current_image(|handle, name| exiting.record(handle, name));
+}
+
+fn stray_call(service: &str) {
+    if CALLS_TO_LOG.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |calls| calls.checked_sub(1)).is_err()
+        || LOGGING.swap(true, Ordering::SeqCst)
+    {
+        return;
+    }
+    if let Some(exiting) = EXITING_IMAGE.try_lock() {
+        match (exiting.handle, exiting.name()) {
+            (0, _) => log::error!("Boot service {service} called after the DXE core exited boot services."),
+            (handle, "") => {
+                log::error!("Boot service {service} called after image {handle:#x} exited boot services.")
+            }
+            (_, name) => log::error!("Boot service {service} called after {name} exited boot services."),
+        }
+    }
+    LOGGING.store(false, Ordering::SeqCst);
+}
+
+macro_rules! stray_boot_services {
+    ($($service:ident($($arg:ty),*) -> $ret:ty = $value:expr;)*) => {
+        $(
+            extern "efiapi" fn $service($(_: $arg),*) -> $ret {
+                stray_call(stringify!($service));
+                $value
+            }
+        )*
+
+        /// Replaces every function in `bs` with a stub that returns EFI_UNSUPPORTED. The caller recalculates the
+        /// table checksum.
+        pub(crate) fn invalidate_boot_services(bs: &mut efi::BootServices) {
+            $(bs.$service = $service;)*
+        }
+    };
+}
+
+stray_boot_services! {
+    // Interrupts are disabled after ExitBootServices, which is what TPL_HIGH_LEVEL means.
+    raise_tpl(efi::Tpl) -> efi::Tpl = efi::TPL_HIGH_LEVEL;
+    restore_tpl(efi::Tpl) -> () = ();
+    allocate_pages(efi::AllocateType, efi::MemoryType, usize, *mut efi::PhysicalAddress) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    free_pages(efi::PhysicalAddress, usize) -> efi::Status = efi::Status::UNSUPPORTED;
+    get_memory_map(*mut usize, *mut efi::MemoryDescriptor, *mut usize, *mut usize, *mut u32) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    allocate_pool(efi::MemoryType, usize, *mut *mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    free_pool(*mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    create_event(u32, efi::Tpl, Option<efi::EventNotify>, *mut c_void, *mut efi::Event) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    set_timer(efi::Event, efi::TimerDelay, u64) -> efi::Status = efi::Status::UNSUPPORTED;
+    wait_for_event(usize, *mut efi::Event, *mut usize) -> efi::Status = efi::Status::UNSUPPORTED;
+    signal_event(efi::Event) -> efi::Status = efi::Status::UNSUPPORTED;
+    close_event(efi::Event) -> efi::Status = efi::Status::UNSUPPORTED;
+    check_event(efi::Event) -> efi::Status = efi::Status::UNSUPPORTED;
+    install_protocol_interface(*mut efi::Handle, *mut efi::Guid, efi::InterfaceType, *mut c_void) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    reinstall_protocol_interface(efi::Handle, *mut efi::Guid, *mut c_void, *mut c_void) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    uninstall_protocol_interface(efi::Handle, *mut efi::Guid, *mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    handle_protocol(efi::Handle, *mut efi::Guid, *mut *mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    register_protocol_notify(*mut efi::Guid, efi::Event, *mut *mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    locate_handle(efi::LocateSearchType, *mut efi::Guid, *mut c_void, *mut usize, *mut efi::Handle) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    locate_device_path(*mut efi::Guid, *mut *mut efi::protocols::device_path::Protocol, *mut efi::Handle)
+        -> efi::Status = efi::Status::UNSUPPORTED;
+    install_configuration_table(*mut efi::Guid, *mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    load_image(efi::Boolean, efi::Handle, *mut efi::protocols::device_path::Protocol, *mut c_void, usize,
+        *mut efi::Handle) -> efi::Status = efi::Status::UNSUPPORTED;
+    start_image(efi::Handle, *mut usize, *mut *mut efi::Char16) -> efi::Status = efi::Status::UNSUPPORTED;
+    exit(efi::Handle, efi::Status, usize, *mut efi::Char16) -> efi::Status = efi::Status::UNSUPPORTED;
+    unload_image(efi::Handle) -> efi::Status = efi::Status::UNSUPPORTED;
+    exit_boot_services(efi::Handle, usize) -> efi::Status = efi::Status::UNSUPPORTED;
+    get_next_monotonic_count(*mut u64) -> efi::Status = efi::Status::UNSUPPORTED;
+    stall(usize) -> efi::Status = efi::Status::UNSUPPORTED;
+    set_watchdog_timer(usize, u64, usize, *mut efi::Char16) -> efi::Status = efi::Status::UNSUPPORTED;
+    connect_controller(efi::Handle, *mut efi::Handle, *mut efi::protocols::device_path::Protocol, efi::Boolean)
+        -> efi::Status = efi::Status::UNSUPPORTED;
+    disconnect_controller(efi::Handle, efi::Handle, efi::Handle) -> efi::Status = efi::Status::UNSUPPORTED;
+    open_protocol(efi::Handle, *mut efi::Guid, *mut *mut c_void, efi::Handle, efi::Handle, u32) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    close_protocol(efi::Handle, *mut efi::Guid, efi::Handle, efi::Handle) -> efi::Status = efi::Status::UNSUPPORTED;
+    open_protocol_information(efi::Handle, *mut efi::Guid, *mut *mut efi::OpenProtocolInformationEntry, *mut usize)
+        -> efi::Status = efi::Status::UNSUPPORTED;
+    protocols_per_handle(efi::Handle, *mut *mut *mut efi::Guid, *mut usize) -> efi::Status = efi::Status::UNSUPPORTED;
+    locate_handle_buffer(efi::LocateSearchType, *mut efi::Guid, *mut c_void, *mut usize, *mut *mut efi::Handle)
+        -> efi::Status = efi::Status::UNSUPPORTED;
+    locate_protocol(*mut efi::Guid, *mut c_void, *mut *mut c_void) -> efi::Status = efi::Status::UNSUPPORTED;
+    install_multiple_protocol_interfaces(*mut efi::Handle, *mut c_void, *mut c_void) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    uninstall_multiple_protocol_interfaces(efi::Handle, *mut c_void, *mut c_void) -> efi::Status =
+        efi::Status::UNSUPPORTED;
+    calculate_crc32(*mut c_void, usize, *mut u32) -> efi::Status = efi::Status::UNSUPPORTED;
+    copy_mem(*mut c_void, *mut c_void, usize) -> () = ();
+    set_mem(*mut c_void, usize, u8) -> () = ();
+    create_event_ex(u32, efi::Tpl, Option<efi::EventNotify>, *const c_void, *const efi::Guid, *mut efi::Event)
+        -> efi::Status = efi::Status::UNSUPPORTED;
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::test_support;
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn test_stray_calls_return_unsupported() {
+        test_support::with_global_lock(|| {
+            let bs = MaybeUninit::zeroed();
+            let mut bs: efi::BootServices = unsafe { bs.assume_init() };
+            invalidate_boot_services(&mut bs);
+
+            let mut buffer = core::ptr::null_mut();
+            assert_eq!((bs.allocate_pool)(efi::BOOT_SERVICES_DATA, 8, &mut buffer), efi::Status::UNSUPPORTED);
+            assert!(buffer.is_null());
+            assert_eq!((bs.exit_boot_services)(core::ptr::null_mut(), 0), efi::Status::UNSUPPORTED);
+            assert_eq!((bs.raise_tpl)(efi::TPL_NOTIFY), efi::TPL_HIGH_LEVEL);
+
+            let mut value = 0u8;
+            (bs.set_mem)(&mut value as *mut u8 as *mut c_void, 1, 0xff);
+            assert_eq!(value, 0);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_stray_calls_are_logged_during_the_debug_window() {
+        test_support::with_global_lock(|| {
+            set_logging(&StrayBootServicesLogging { logged_calls: 2 });
+            for _ in 0..3 {
+                stray_call("stall");
+            }
+            assert_eq!(CALLS_TO_LOG.load(Ordering::SeqCst), 0);
+            assert!(!LOGGING.load(Ordering::SeqCst));
+            set_logging(&StrayBootServicesLogging::default());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_logging_is_disabled_when_boot_services_memory_is_zeroed() {
+        test_support::with_global_lock(|| {
+            record_exit_boot_services(false);
+            assert_eq!(CALLS_TO_LOG.load(Ordering::SeqCst), DEFAULT_LOGGED_CALLS);
+
+            record_exit_boot_services(true);
+            assert_eq!(CALLS_TO_LOG.load(Ordering::SeqCst), 0);
+            set_logging(&StrayBootServicesLogging::default());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_exiting_image_name_is_truncated_at_a_character_boundary() {
+        let mut exiting = ExitingImage::NONE;
+        exiting.record(0x1000 as efi::Handle, Some("Loader.efi"));
+        assert_eq!((exiting.handle, exiting.name()), (0x1000, "Loader.efi"));
+
+        // a two byte character straddles the limit, so it is dropped rather than split.
+        let long = std::format!("{}\u{e9}", "a".repeat(MAX_IMAGE_NAME - 1));
+        exiting.record(0x1000 as efi::Handle, Some(&long));
+        assert_eq!(exiting.name(), &long[..MAX_IMAGE_NAME - 1]);
+
+        exiting.record(0x2000 as efi::Handle, None);
+        assert_eq!(exiting.name(), "");
+    }
+}
@@ -0,0 +1,68 @@
+//! DXE Core Symbol Map
+//!
+//! With the `symbol_map` feature, a symbol map of the core is embedded in the core image, so that stack traces of
+//! faults inside the core show `module!function+offset` without external symbol files, and the `sym` debugger monitor
+//! command resolves core addresses to functions.
+//!
+//! The map is generated from the linker map of the core, so the core is built twice:
+//!
+//! 1. Build with the `symbol_map` feature and `-Clink-arg=/lldmap:<path>` to produce the linker map. An empty symbol map
+//!    is embedded.
+//! 2. Run `core/patina_stacktrace/resolve_stacktrace/generate_symbol_map.py <linker map> <symbol map>`, then build again
+//!    with the `PATINA_SYMBOL_MAP` environment variable set to the symbol map.
+//!
+//! A map from a different build is detected by resolving [patina_symbol_map_anchor] at initialization, and is not used.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use patina_stacktrace::symbol_map::{self, SymbolMap};
+
+/// The symbol map of the core, empty if `PATINA_SYMBOL_MAP` was not set at build time.
+static SYMBOL_MAP: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/symbol_map.bin"));
+
+/// A function with a known name, used to check that the embedded symbol map is for this build of the core.
+#[unsafe(no_mangle)]
+#[inline(never)]
+pub extern "efiapi" fn patina_symbol_map_anchor() {}
+
+/// Registers the embedded symbol map for stack traces and adds the `sym` monitor command.
+pub(crate) fn init() {
+    if SYMBOL_MAP.is_empty() {
+        log::warn!("The symbol_map feature is enabled, but no symbol map was embedded. Set PATINA_SYMBOL_MAP.");
+        return;
+    }
+    let map = match SymbolMap::parse(SYMBOL_MAP) {
+        Ok(map) => map,
+        Err(err) => {
+            log::error!("Failed to parse the embedded symbol map: {err}");
+            return;
+        }
+    };
+    // SAFETY: The anchor is a function of the core image, which is loaded.
+    if let Err(err) =
+        unsafe { symbol_map::register(map, patina_symbol_map_anchor as usize as u64, "patina_symbol_map_anchor") }
+    {
+        log::error!("Failed to register the embedded symbol map: {err}");
+        return;
+    }
+    log::info!("Registered the embedded symbol map with {} symbols.", map.len());
+
+    patina_debugger::add_monitor_command(
+        "sym",
+        "Resolves a DXE Core address to a function: sym <addr>",
+        |args, out| {
+            let address = args.next().map(|arg| u64::from_str_radix(arg.trim_start_matches("0x"), 16));
+            let _ = match address {
+                Some(Ok(address)) => match symbol_map::resolve(address) {
+                    Some((function, offset)) => write!(out, "{address:#x}: {function}+{offset:#x}"),
+                    None => write!(out, "{address:#x} is not in the DXE Core"),
+                },
+                _ => write!(out, "Usage: sym <hex address>"),
+            };
+        },
+    );
+}
@@ -163,10 +163,9 @@ impl EfiRuntimeServicesTable {
     }
 
     pub fn checksum(&mut self) {
-        self.runtime_services.hdr.crc32 = 0;
         let rs_ptr = self.runtime_services.as_ref() as *const efi::RuntimeServices as *const u8;
         let rs_slice = unsafe { from_raw_parts(rs_ptr, size_of::<efi::RuntimeServices>()) };
-        self.runtime_services.hdr.crc32 = crc32fast::hash(rs_slice);
+        self.runtime_services.hdr.crc32 = crate::crc32::table_hash(rs_slice);
     }
 }
 
@@ -578,10 +577,9 @@ impl EfiBootServicesTable {
     }
 
     pub fn checksum(&mut self) {
-        self.boot_services.hdr.crc32 = 0;
         let bs_ptr = self.boot_services.as_ref() as *const efi::BootServices as *const u8;
         let bs_slice = unsafe { from_raw_parts(bs_ptr, size_of::<efi::BootServices>()) };
-        self.boot_services.hdr.crc32 = crc32fast::hash(bs_slice);
+        self.boot_services.hdr.crc32 = crate::crc32::table_hash(bs_slice);
     }
 }
 
@@ -662,10 +660,9 @@ impl EfiSystemTable {
     }
 
     pub fn checksum(&mut self) {
-        self.system_table.hdr.crc32 = 0;
         let st_ptr = self.system_table.as_ref() as *const efi::SystemTable as *const u8;
         let st_slice = unsafe { from_raw_parts(st_ptr, size_of::<efi::SystemTable>()) };
-        self.system_table.hdr.crc32 = crc32fast::hash(st_slice);
+        self.system_table.hdr.crc32 = crate::crc32::table_hash(st_slice);
     }
 
     pub fn checksum_runtime_services(&mut self) {
@@ -0,0 +1,341 @@
+//! DXE Core TLB Shootdown Support
+//!
+//! Changing or removing an existing mapping in the page table only invalidates the TLB of the processor that makes
+//! the change. Once the MP Services protocol is installed, application processors (APs) may be running with the
+//! shared page table, so every such change must also invalidate their TLBs before they can use the stale translation.
+//!
+//! The GCD calls [request_tlb_shootdown] after it changes or removes an existing mapping, and holds a
+//! [TlbShootdownScope] across the operation so that the APs are flushed once its locks are released and before it
+//! returns to its caller. The request is a no-op until the MP Services protocol is installed. If the APs cannot be
+//! flushed (e.g. because they are busy with a non-blocking StartupAllAPs call), the shootdown stays pending and is
+//! retried on a timer and by the next change to the page table.
+//!
+//! While the core dispatches a batch of components on the APs, the BSP is blocked in StartupAllAPs and cannot start
+//! a shootdown. Changes made by those components are instead flushed by each AP before it takes its next component,
+//! and by the BSP and all APs once the batch completes.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+use core::{
+    ffi::c_void,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use patina_internal_cpu::tlb;
+use r_efi::{efi, protocols::mp_services};
+
+use crate::{
+    events::{self, EVENT_DB},
+    protocols::PROTOCOL_DB,
+    tpl_lock,
+};
+
+// Delay before a failed shootdown is retried, in 100ns units.
+const RETRY_DELAY: u64 = 100_000;
+
+static MP_SERVICES: AtomicPtr<mp_services::Protocol> = AtomicPtr::new(ptr::null_mut());
+static RETRY_EVENT: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static SHOOTDOWN_PENDING: AtomicBool = AtomicBool::new(false);
+// Incremented by each request, so processors that cannot be flushed right away can tell whether their TLB is stale.
+static SHOOTDOWN_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init_tlb_shootdown_support() {
+    let event = EVENT_DB
+        .create_event(efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(tlb_shootdown_retry), None, None)
+        .expect("Failed to create TLB shootdown retry event.");
+    RETRY_EVENT.store(event, Ordering::SeqCst);
+
+    // Setup a event callback for the MP Services protocol.
+    let event = EVENT_DB
+        .create_event(efi::EVT_NOTIFY_SIGNAL, efi::TPL_CALLBACK, Some(mp_services_protocol_notify), None, None)
+        .expect("Failed to create MP services protocol installation callback.");
+
+    PROTOCOL_DB
+        .register_protocol_notify(mp_services::PROTOCOL_GUID, event)
+        .expect("Failed to register protocol notify on MP services protocol.");
+}
+
+/// Records that the TLBs of all running APs must be invalidated.
+///
+/// Must be called after an existing mapping in the page table is changed or removed, while a [TlbShootdownScope] is
+/// held. The calling processor is expected to have invalidated its own TLB already.
+pub(crate) fn request_tlb_shootdown() {
+    if MP_SERVICES.load(Ordering::SeqCst).is_null() {
+        return;
+    }
+
+    SHOOTDOWN_GENERATION.fetch_add(1, Ordering::SeqCst);
+    SHOOTDOWN_PENDING.store(true, Ordering::SeqCst);
+}
+
+/// Completes any pending TLB shootdown when dropped.
+///
+/// Must be created before the locks guarding the page table are taken, so that it is dropped after they are released.
+pub(crate) struct TlbShootdownScope;
+
+impl Drop for TlbShootdownScope {
+    fn drop(&mut self) {
+        complete_tlb_shootdown();
+    }
+}
+
+/// Returns the current shootdown generation, to be passed to [complete_ap_batch] once a batch of work on the APs
+/// completes.
+pub(crate) fn generation() -> usize {
+    SHOOTDOWN_GENERATION.load(Ordering::SeqCst)
+}
+
+/// Invalidates the TLB of the calling processor if a shootdown was requested since it last observed `generation`.
+///
+/// Called by APs between the components of a batch, as the BSP cannot flush them while the batch runs.
+pub(crate) fn flush_local_tlb_if_stale(generation: &mut usize) {
+    let current = SHOOTDOWN_GENERATION.load(Ordering::SeqCst);
+    if current != *generation {
+        tlb::flush_local_tlb();
+        *generation = current;
+    }
+}
+
+/// Completes the shootdowns requested while a batch of work ran on the APs, which started at `generation`.
+///
+/// Must be called from the BSP once the batch completes.
+pub(crate) fn complete_ap_batch(generation: usize) {
+    // the mappings may have been changed by an AP, so the BSP's own TLB may be stale as well.
+    let mut generation = generation;
+    flush_local_tlb_if_stale(&mut generation);
+    complete_tlb_shootdown();
+}
+
+fn complete_tlb_shootdown() {
+    // while the core runs code on the APs, the BSP is blocked in StartupAllAPs; the batch completes the shootdown.
+    if tpl_lock::on_application_processor().is_some() || !SHOOTDOWN_PENDING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    let mp_services = MP_SERVICES.load(Ordering::SeqCst);
+    if mp_services.is_null() {
+        return;
+    }
+
+    // SAFETY: the protocol is identified by its GUID and should be a valid pointer to an EFI_MP_SERVICES_PROTOCOL
+    //         structure. A null wait event runs the procedure in blocking mode, so all APs have flushed their TLBs
+    //         when the call returns.
+    let status = unsafe {
+        ((*mp_services).startup_all_aps)(
+            mp_services,
+            flush_ap_tlb,
+            efi::Boolean::FALSE,
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+
+    match status {
+        // NOT_STARTED is returned when there are no enabled APs, so there are no TLBs to flush.
+        efi::Status::SUCCESS | efi::Status::NOT_STARTED => {}
+        status => {
+            log::warn!("Failed to flush the TLBs of the APs, retrying: {status:#x?}");
+            SHOOTDOWN_PENDING.store(true, Ordering::SeqCst);
+
+            let event = RETRY_EVENT.load(Ordering::SeqCst);
+            if !event.is_null() {
+                let status = events::set_timer(event, efi::TIMER_RELATIVE, RETRY_DELAY);
+                if status.is_error() {
+                    log::error!("Failed to schedule a TLB shootdown retry: {status:#x?}");
+                }
+            }
+        }
+    }
+}
+
+extern "efiapi" fn mp_services_protocol_notify(_event: efi::Event, _context: *mut c_void) {
+    let Ok(ptr) = PROTOCOL_DB.locate_protocol(mp_services::PROTOCOL_GUID) else {
+        return;
+    };
+    log::info!("MP services protocol installed. TLB shootdown enabled.");
+    MP_SERVICES.store(ptr as *mut mp_services::Protocol, Ordering::SeqCst);
+}
+
+extern "efiapi" fn tlb_shootdown_retry(_event: efi::Event, _context: *mut c_void) {
+    complete_tlb_shootdown();
+}
+
+extern "efiapi" fn flush_ap_tlb(_context: *mut c_void) {
+    tlb::flush_local_tlb();
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::test_support::with_global_lock;
+
+    static STARTUP_ALL_APS_CALLS: AtomicUsize = AtomicUsize::new(0);
+    // The number of upcoming StartupAllAPs calls that fail because the APs are busy.
+    static STARTUP_ALL_APS_BUSY: AtomicUsize = AtomicUsize::new(0);
+
+    extern "efiapi" fn mock_startup_all_aps(
+        _this: *mut mp_services::Protocol,
+        procedure: mp_services::ApProcedure,
+        _single_thread: efi::Boolean,
+        _wait_event: efi::Event,
+        _timeout: usize,
+        argument: *mut c_void,
+        _failed_cpu_list: *mut *mut usize,
+    ) -> efi::Status {
+        STARTUP_ALL_APS_CALLS.fetch_add(1, Ordering::SeqCst);
+        if STARTUP_ALL_APS_BUSY.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |busy| busy.checked_sub(1)).is_ok() {
+            return efi::Status::NOT_READY;
+        }
+        procedure(argument);
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn unused_get_number_of_processors(
+        _this: *mut mp_services::Protocol,
+        _processors: *mut usize,
+        _enabled_processors: *mut usize,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn unused_get_processor_info(
+        _this: *mut mp_services::Protocol,
+        _processor: usize,
+        _info: *mut mp_services::ProcessorInformation,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn unused_startup_this_ap(
+        _this: *mut mp_services::Protocol,
+        _procedure: mp_services::ApProcedure,
+        _processor: usize,
+        _wait_event: efi::Event,
+        _timeout: usize,
+        _argument: *mut c_void,
+        _finished: *mut efi::Boolean,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn unused_switch_bsp(
+        _this: *mut mp_services::Protocol,
+        _processor: usize,
+        _enable_old_bsp: efi::Boolean,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn unused_enable_disable_ap(
+        _this: *mut mp_services::Protocol,
+        _processor: usize,
+        _enable: efi::Boolean,
+        _health_flag: *mut u32,
+    ) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    extern "efiapi" fn unused_who_am_i(_this: *mut mp_services::Protocol, _processor: *mut usize) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    fn mock_mp_services() -> *mut mp_services::Protocol {
+        Box::leak(Box::new(mp_services::Protocol {
+            get_number_of_processors: unused_get_number_of_processors,
+            get_processor_info: unused_get_processor_info,
+            startup_all_aps: mock_startup_all_aps,
+            startup_this_ap: unused_startup_this_ap,
+            switch_bsp: unused_switch_bsp,
+            enable_disable_ap: unused_enable_disable_ap,
+            who_am_i: unused_who_am_i,
+        }))
+    }
+
+    #[test]
+    fn test_request_is_ignored_without_mp_services() {
+        with_global_lock(|| {
+            MP_SERVICES.store(ptr::null_mut(), Ordering::SeqCst);
+            SHOOTDOWN_PENDING.store(false, Ordering::SeqCst);
+
+            request_tlb_shootdown();
+            assert!(!SHOOTDOWN_PENDING.load(Ordering::SeqCst));
+        })
+        .unwrap();
+    }
+
+    fn with_mock_mp_services<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
+        with_global_lock(|| {
+            STARTUP_ALL_APS_CALLS.store(0, Ordering::SeqCst);
+            STARTUP_ALL_APS_BUSY.store(0, Ordering::SeqCst);
+            SHOOTDOWN_PENDING.store(false, Ordering::SeqCst);
+            MP_SERVICES.store(mock_mp_services(), Ordering::SeqCst);
+            f();
+            MP_SERVICES.store(ptr::null_mut(), Ordering::SeqCst);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_shootdown_completes_when_the_scope_ends() {
+        with_mock_mp_services(|| {
+            // no shootdown was requested.
+            drop(TlbShootdownScope);
+            assert_eq!(STARTUP_ALL_APS_CALLS.load(Ordering::SeqCst), 0);
+
+            // requests made within a scope result in a single shootdown before it ends.
+            {
+                let _shootdown = TlbShootdownScope;
+                request_tlb_shootdown();
+                request_tlb_shootdown();
+                assert_eq!(STARTUP_ALL_APS_CALLS.load(Ordering::SeqCst), 0);
+            }
+            assert_eq!(STARTUP_ALL_APS_CALLS.load(Ordering::SeqCst), 1);
+            assert!(!SHOOTDOWN_PENDING.load(Ordering::SeqCst));
+        });
+    }
+
+    #[test]
+    fn test_failed_shootdown_stays_pending() {
+        with_mock_mp_services(|| {
+            STARTUP_ALL_APS_BUSY.store(1, Ordering::SeqCst);
+            request_tlb_shootdown();
+            complete_tlb_shootdown();
+            assert!(SHOOTDOWN_PENDING.load(Ordering::SeqCst));
+
+            tlb_shootdown_retry(ptr::null_mut(), ptr::null_mut());
+            assert!(!SHOOTDOWN_PENDING.load(Ordering::SeqCst));
+            assert_eq!(STARTUP_ALL_APS_CALLS.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn test_shootdown_is_deferred_until_an_ap_batch_completes() {
+        with_mock_mp_services(|| {
+            let generation = generation();
+            tpl_lock::set_mp_services(MP_SERVICES.load(Ordering::SeqCst));
+
+            // an AP that did not observe the request flushes its own TLB before taking more work.
+            let mut ap_generation = generation;
+            request_tlb_shootdown();
+            complete_tlb_shootdown();
+            flush_local_tlb_if_stale(&mut ap_generation);
+            assert_eq!(ap_generation, generation + 1);
+            assert!(SHOOTDOWN_PENDING.load(Ordering::SeqCst));
+            assert_eq!(STARTUP_ALL_APS_CALLS.load(Ordering::SeqCst), 0);
+
+            tpl_lock::set_mp_services(ptr::null_mut());
+            complete_ap_batch(generation);
+            assert!(!SHOOTDOWN_PENDING.load(Ordering::SeqCst));
+            assert_eq!(STARTUP_ALL_APS_CALLS.load(Ordering::SeqCst), 1);
+        });
+    }
+}
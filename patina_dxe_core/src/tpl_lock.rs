@@ -10,11 +10,14 @@
 use core::{
     cell::UnsafeCell,
     fmt,
+    hint::spin_loop,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
 };
 
-use r_efi::efi;
+use r_efi::{efi, protocols::mp_services};
+
+use crate::interrupt_latency;
 
 static BOOT_SERVICES_PTR: AtomicPtr<efi::BootServices> = AtomicPtr::new(core::ptr::null_mut());
 
@@ -37,10 +40,56 @@ fn boot_services() -> Option<&'static mut efi::BootServices> {
     unsafe { boot_services_ptr.as_mut() }
 }
 
+static MP_SERVICES_PTR: AtomicPtr<mp_services::Protocol> = AtomicPtr::new(core::ptr::null_mut());
+static BSP_NUMBER: AtomicUsize = AtomicUsize::new(0);
+
+const NO_OWNER: usize = usize::MAX;
+
+/// Called with the MP Services protocol before the core runs code on application processors (APs), and with null once
+/// they finish. While set, [TplMutex::lock] waits for a lock that is held by another processor instead of panicking,
+/// and locks taken on an AP do not adjust the TPL, as boot services may only be called from the boot strap processor
+/// (BSP).
+///
+/// Must be called from the BSP.
+pub(crate) fn set_mp_services(mp_services: *mut mp_services::Protocol) {
+    if let Some(processor) = who_am_i(mp_services) {
+        BSP_NUMBER.store(processor, Ordering::SeqCst);
+    }
+    MP_SERVICES_PTR.store(mp_services, Ordering::SeqCst);
+}
+
+fn who_am_i(mp_services: *mut mp_services::Protocol) -> Option<usize> {
+    if mp_services.is_null() {
+        return None;
+    }
+    let mut processor = 0;
+    // SAFETY: the pointer was provided by set_mp_services, and WhoAmI may be called from any processor.
+    match unsafe { ((*mp_services).who_am_i)(mp_services, &mut processor) } {
+        efi::Status::SUCCESS => Some(processor),
+        _ => None,
+    }
+}
+
+// Returns None if the core is not running code on the APs, otherwise the number of the calling processor.
+fn current_processor() -> Option<usize> {
+    who_am_i(MP_SERVICES_PTR.load(Ordering::SeqCst))
+}
+
+// Returns None if the core is not running code on the APs, otherwise whether the caller is an AP.
+pub(crate) fn on_application_processor() -> Option<bool> {
+    let mp_services = MP_SERVICES_PTR.load(Ordering::SeqCst);
+    if mp_services.is_null() {
+        return None;
+    }
+    Some(who_am_i(mp_services).is_some_and(|processor| processor != BSP_NUMBER.load(Ordering::SeqCst)))
+}
+
 /// Used to guard data with a locked MUTEX and TPL level.
 pub struct TplMutex<T: ?Sized> {
     tpl_lock_level: efi::Tpl,
     lock: AtomicBool,
+    // The processor that holds the lock, recorded while the core runs code on the APs.
+    owner: AtomicUsize,
     name: &'static str,
     data: UnsafeCell<T>,
 }
@@ -48,6 +97,7 @@ pub struct TplMutex<T: ?Sized> {
 pub struct TplGuard<'a, T: ?Sized + 'a> {
     release_tpl: Option<efi::Tpl>,
     lock: &'a AtomicBool,
+    owner: &'a AtomicUsize,
     name: &'static str,
     data: *mut T,
 }
@@ -61,7 +111,13 @@ unsafe impl<T: ?Sized + Send> Send for TplGuard<'_, T> {}
 impl<T> TplMutex<T> {
     /// Instantiates a new TplMutex with the given TPL level, data object, and name string.
     pub const fn new(tpl_lock_level: efi::Tpl, data: T, name: &'static str) -> Self {
-        Self { tpl_lock_level, lock: AtomicBool::new(false), data: UnsafeCell::new(data), name }
+        Self {
+            tpl_lock_level,
+            lock: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            data: UnsafeCell::new(data),
+            name,
+        }
     }
 }
 
@@ -69,17 +125,38 @@ impl<T: ?Sized> TplMutex<T> {
     /// Lock the TplMutex and return a TplGuard object used to access the data. This will raise the system TPL level
     /// to the level specified at TplMutex creation.
     ///
-    /// Safety: Lock reentrance is not supported; attempt to re-lock something already locked will panic.
+    /// Safety: Lock reentrance is not supported; attempt to re-lock something already locked will panic. While the
+    /// core runs code on application processors, a lock held by another processor is waited for instead, but a lock
+    /// re-entered by the processor that holds it still panics rather than spinning forever.
     pub fn lock(&self) -> TplGuard<'_, T> {
-        self.try_lock().unwrap_or_else(|| panic!("Re-entrant locks for {:?} not permitted.", self.name))
+        let processor = current_processor();
+        loop {
+            match self.try_lock() {
+                Some(guard) => return guard,
+                None if processor.is_some_and(|processor| self.owner.load(Ordering::SeqCst) != processor) => {
+                    spin_loop()
+                }
+                None => panic!("Re-entrant locks for {:?} not permitted.", self.name),
+            }
+        }
     }
 
     /// Attempts to lock the TplMutex, and if successful, returns a guard object that can be used to access the data.
     pub fn try_lock(&self) -> Option<TplGuard<'_, T>> {
-        let boot_services = boot_services();
+        let boot_services = boot_services().filter(|_| on_application_processor() != Some(true));
         let release_tpl = boot_services.as_ref().map(|bs| (bs.raise_tpl)(self.tpl_lock_level));
         if self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
-            Some(TplGuard { release_tpl, lock: &self.lock, name: self.name, data: unsafe { &mut *self.data.get() } })
+            if self.tpl_lock_level == efi::TPL_HIGH_LEVEL && release_tpl.is_some_and(|tpl| tpl < efi::TPL_HIGH_LEVEL) {
+                interrupt_latency::set_owner(self.name);
+            }
+            self.owner.store(current_processor().unwrap_or(NO_OWNER), Ordering::SeqCst);
+            Some(TplGuard {
+                release_tpl,
+                lock: &self.lock,
+                owner: &self.owner,
+                name: self.name,
+                data: unsafe { &mut *self.data.get() },
+            })
         } else {
             if let Some(release_tpl) = release_tpl
                 && let Some(bs) = boot_services
@@ -129,6 +206,8 @@ impl<'a, T: ?Sized> DerefMut for TplGuard<'a, T> {
 
 impl<T: ?Sized> Drop for TplGuard<'_, T> {
     fn drop(&mut self) {
+        // the owner is cleared before the lock is released, so a waiting processor never sees itself as the owner.
+        self.owner.store(NO_OWNER, Ordering::SeqCst);
         self.lock.store(false, Ordering::Release);
         if let Some(tpl) = self.release_tpl {
             let bs = boot_services()
@@ -146,13 +225,15 @@ mod tests {
 
     use crate::test_support;
 
-    use super::{TplMutex, init_boot_services};
+    use super::{TplMutex, init_boot_services, set_mp_services};
     use core::{
         mem::MaybeUninit,
+        panic::AssertUnwindSafe,
         sync::atomic::{AtomicUsize, Ordering},
     };
-    use r_efi::efi;
+    use r_efi::{efi, protocols::mp_services};
 
+    static PROCESSOR: AtomicUsize = AtomicUsize::new(0);
     static TPL: AtomicUsize = AtomicUsize::new(efi::TPL_APPLICATION);
 
     fn with_locked_state<F: Fn() + std::panic::RefUnwindSafe>(f: F) {
@@ -162,6 +243,7 @@ mod tests {
             //mock boot services - otherwise tests for unrelated implementations that
             //use TplMutex might end up calling the mocks unexpectedly.
             init_boot_services(core::ptr::null_mut());
+            set_mp_services(core::ptr::null_mut());
         })
         .unwrap();
     }
@@ -190,6 +272,18 @@ mod tests {
         Box::into_raw(Box::new(boot_services))
     }
 
+    extern "efiapi" fn mock_who_am_i(_this: *mut mp_services::Protocol, processor: *mut usize) -> efi::Status {
+        unsafe { processor.write(PROCESSOR.load(Ordering::SeqCst)) };
+        efi::Status::SUCCESS
+    }
+
+    fn mock_mp_services() -> *mut mp_services::Protocol {
+        let mp_services = MaybeUninit::zeroed();
+        let mut mp_services: mp_services::Protocol = unsafe { mp_services.assume_init() };
+        mp_services.who_am_i = mock_who_am_i;
+        Box::into_raw(Box::new(mp_services))
+    }
+
     #[test]
     fn tpl_mutex_can_be_created() {
         with_locked_state(|| {
@@ -224,4 +318,41 @@ mod tests {
             println!("{guard:}");
         });
     }
+
+    #[test]
+    fn tpl_mutex_should_not_change_tpl_on_application_processors() {
+        with_locked_state(|| {
+            init_boot_services(mock_boot_services());
+            PROCESSOR.store(0, Ordering::SeqCst);
+            set_mp_services(mock_mp_services());
+            let tpl_mutex = TplMutex::new(efi::TPL_NOTIFY, 1_usize, "test_lock");
+
+            PROCESSOR.store(1, Ordering::SeqCst);
+            let guard = tpl_mutex.lock();
+            assert_eq!(TPL.load(Ordering::SeqCst), efi::TPL_APPLICATION);
+            assert!(tpl_mutex.try_lock().is_none());
+            drop(guard);
+
+            PROCESSOR.store(0, Ordering::SeqCst);
+            let guard = tpl_mutex.lock();
+            assert_eq!(TPL.load(Ordering::SeqCst), efi::TPL_NOTIFY);
+            drop(guard);
+            assert_eq!(TPL.load(Ordering::SeqCst), efi::TPL_APPLICATION);
+        });
+    }
+
+    #[test]
+    fn tpl_mutex_should_panic_when_reentered_on_application_processors() {
+        with_locked_state(|| {
+            PROCESSOR.store(0, Ordering::SeqCst);
+            set_mp_services(mock_mp_services());
+            let tpl_mutex = TplMutex::new(efi::TPL_NOTIFY, 1_usize, "test_lock");
+
+            PROCESSOR.store(1, Ordering::SeqCst);
+            let guard = tpl_mutex.lock();
+            let relock = std::panic::catch_unwind(AssertUnwindSafe(|| drop(tpl_mutex.lock())));
+            assert!(relock.is_err());
+            drop(guard);
+        });
+    }
 }
@@ -0,0 +1,182 @@
+//! Write XOR Execute Enforcement
+//!
+//! Memory that is both writable and executable lets an attacker who can write to it run their own code. Platforms can
+//! register [WxEnforcement] to have the core scan the GCD, which the page table mirrors, once the EndOfDxe event group
+//! is signaled. Every allocated range that is both writable and executable is logged as an error with its owner: the
+//! loaded image that contains it, or else the allocator that manages it. Debug builds assert if any violation is
+//! found, so that new violations are caught while the platform is developed.
+//!
+//! Ranges that are known to need write and execute access, such as a region a legacy driver patches in place, can be
+//! exempted.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{format, string::String, vec::Vec};
+use core::{ffi::c_void, ops::Range};
+
+use patina::guids;
+use patina_pi::dxe_services::{GcdMemoryType, MemorySpaceDescriptor};
+use r_efi::efi;
+use spin::RwLock;
+
+use crate::{
+    GCD,
+    allocator::{allocator_memory_type_name, for_each_unkept_range},
+    events::EVENT_DB,
+    image::with_image_at_address,
+    protocol_db,
+};
+
+/// Scans memory for ranges that are both writable and executable when EndOfDxe is signaled.
+///
+/// Nothing is scanned unless the platform registers this config.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina_dxe_core::{Core, WxEnforcement};
+/// # let physical_hob_list = core::ptr::null();
+///
+/// Core::default()
+///   .init_memory(physical_hob_list)
+///   .with_config(WxEnforcement { exemptions: vec![0xE0000..0x100000] })
+///   .start()
+///   .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WxEnforcement {
+    /// Ranges that may be both writable and executable.
+    pub exemptions: Vec<Range<u64>>,
+}
+
+static EXEMPTIONS: RwLock<Vec<(u64, u64)>> = RwLock::new(Vec::new());
+
+/// Registers the scan for writable and executable memory at EndOfDxe.
+pub(crate) fn enable_wx_enforcement(enforcement: &WxEnforcement) {
+    *EXEMPTIONS.write() = enforcement.exemptions.iter().map(|range| (range.start, range.end)).collect();
+
+    if let Err(status) = EVENT_DB.create_event(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        Some(check_wx_event),
+        None,
+        Some(guids::EVENT_GROUP_END_OF_DXE),
+    ) {
+        log::error!("Failed to register an event at EndOfDxe to check W^X! Status {status:#X?}");
+    }
+}
+
+fn is_writable_and_executable(attributes: u64) -> bool {
+    attributes & (efi::MEMORY_RO | efi::MEMORY_XP | efi::MEMORY_RP) == 0
+}
+
+// Returns the parts of the allocated descriptors that are writable and executable and are not exempt, with the
+// attributes of the descriptor and the handle that owns it. Unallocated memory is not mapped, whatever its attributes.
+fn find_violations(
+    descriptors: &[MemorySpaceDescriptor],
+    exemptions: &[(u64, u64)],
+) -> Vec<(Range<u64>, u64, efi::Handle)> {
+    let mut violations = Vec::new();
+    for descriptor in descriptors {
+        if descriptor.image_handle == protocol_db::INVALID_HANDLE
+            || descriptor.memory_type == GcdMemoryType::NonExistent
+            || !is_writable_and_executable(descriptor.attributes)
+        {
+            continue;
+        }
+        let end = descriptor.base_address + descriptor.length;
+        for_each_unkept_range(descriptor.base_address, end, exemptions, |base, end| {
+            violations.push((base..end, descriptor.attributes, descriptor.image_handle));
+        });
+    }
+    violations
+}
+
+fn owner_name(address: u64, handle: efi::Handle) -> String {
+    if let Some(name) = with_image_at_address(address, |name, offset| format!("{name} + {offset:#x}")) {
+        return name;
+    }
+    match allocator_memory_type_name(handle) {
+        Some(memory_type) => format!("{memory_type} allocator"),
+        None => format!("{handle:?}"),
+    }
+}
+
+extern "efiapi" fn check_wx_event(event: efi::Event, _context: *mut c_void) {
+    let mut descriptors = Vec::with_capacity(GCD.memory_descriptor_count() + 10);
+    match GCD.get_memory_descriptors(&mut descriptors) {
+        Ok(()) => {
+            let violations = find_violations(&descriptors, &EXEMPTIONS.read());
+            for (range, attributes, handle) in &violations {
+                log::error!(
+                    "W^X violation: {:#x}-{:#x} is writable and executable (attributes {attributes:#x}), owned by {}.",
+                    range.start,
+                    range.end,
+                    owner_name(range.start, *handle)
+                );
+            }
+            if violations.is_empty() {
+                log::info!("W^X check at EndOfDxe found no writable and executable memory.");
+            } else {
+                log::error!("W^X check at EndOfDxe found {} writable and executable range(s).", violations.len());
+                debug_assert!(false);
+            }
+        }
+        Err(err) => log::error!("Failed to get the GCD memory descriptors for the W^X check: {err:?}"),
+    }
+
+    if let Err(status) = EVENT_DB.close_event(event) {
+        log::error!("Failed to close the W^X check event: {status:?}");
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    fn descriptor(
+        memory_type: GcdMemoryType,
+        base_address: u64,
+        length: u64,
+        attributes: u64,
+    ) -> MemorySpaceDescriptor {
+        MemorySpaceDescriptor {
+            memory_type,
+            base_address,
+            length,
+            attributes,
+            image_handle: protocol_db::EFI_BOOT_SERVICES_DATA_ALLOCATOR_HANDLE,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_writable_and_executable_ranges_are_violations() {
+        let descriptors = [
+            descriptor(GcdMemoryType::SystemMemory, 0x1000, 0x1000, efi::MEMORY_WB),
+            descriptor(GcdMemoryType::SystemMemory, 0x2000, 0x1000, efi::MEMORY_WB | efi::MEMORY_XP),
+            descriptor(GcdMemoryType::SystemMemory, 0x3000, 0x1000, efi::MEMORY_WB | efi::MEMORY_RO),
+            descriptor(GcdMemoryType::SystemMemory, 0x4000, 0x1000, efi::MEMORY_RP),
+            descriptor(GcdMemoryType::NonExistent, 0x5000, 0x1000, 0),
+            descriptor(GcdMemoryType::MemoryMappedIo, 0x6000, 0x4000, efi::MEMORY_UC),
+            MemorySpaceDescriptor {
+                image_handle: protocol_db::INVALID_HANDLE,
+                ..descriptor(GcdMemoryType::SystemMemory, 0xA000, 0x1000, 0)
+            },
+        ];
+
+        let violations = find_violations(&descriptors, &[]);
+        let ranges = violations.iter().map(|(range, _, _)| range.clone()).collect::<Vec<_>>();
+        assert_eq!(ranges, [0x1000..0x2000, 0x6000..0xA000]);
+
+        // exemptions remove the exempt part of a range.
+        let violations = find_violations(&descriptors, &[(0x0, 0x2000), (0x7000, 0x8000)]);
+        let ranges = violations.iter().map(|(range, _, _)| range.clone()).collect::<Vec<_>>();
+        assert_eq!(ranges, [0x6000..0x7000, 0x8000..0xA000]);
+    }
+}
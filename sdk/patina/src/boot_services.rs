@@ -17,6 +17,7 @@ pub mod boxed;
 pub mod c_ptr;
 pub mod event;
 pub mod protocol_handler;
+pub mod protocol_notify;
 pub mod tpl;
 
 #[cfg(any(test, feature = "mockall"))]
@@ -1204,7 +1205,10 @@ impl BootServices for StandardBootServices {
 
         // Use to get the buffer_size
         let mut buffer_size = 0;
-        locate_handle(search_type.into(), protocol, search_key, ptr::addr_of_mut!(buffer_size), ptr::null_mut());
+        match locate_handle(search_type.into(), protocol, search_key, ptr::addr_of_mut!(buffer_size), ptr::null_mut()) {
+            s if s.is_error() && s != efi::Status::BUFFER_TOO_SMALL => return Err(s),
+            _ => (),
+        }
 
         let buffer = self.allocate_pool(MemoryType::BOOT_SERVICES_DATA, buffer_size)?;
 
@@ -2432,6 +2436,49 @@ mod tests {
         assert_eq!(10, handles[0] as usize);
     }
 
+    #[test]
+    fn test_handle_buffer_iter_with_protocol() {
+        let boot_services =
+            boot_services!(locate_handle_buffer = efi_locate_handle_buffer, free_pool = efi_free_pool_use_box);
+
+        extern "efiapi" fn efi_locate_handle_buffer(
+            search_type: efi::LocateSearchType,
+            protocol: *mut efi::Guid,
+            search_key: *mut c_void,
+            no_handles: *mut usize,
+            buffer: *mut *mut efi::Handle,
+        ) -> efi::Status {
+            assert_eq!(efi::BY_PROTOCOL, search_type);
+            assert_eq!(ptr::null_mut(), search_key);
+            if unsafe { ptr::read(protocol) } == efi::protocols::block_io::PROTOCOL_GUID {
+                return efi::Status::NOT_FOUND;
+            }
+            assert_eq!(TestProtocol::PROTOCOL_GUID, unsafe { ptr::read(protocol) });
+
+            let mut allocation = ptr::null_mut();
+            efi_allocate_pool_use_box(efi::BOOT_SERVICES_DATA, 3 * mem::size_of::<efi::Handle>(), &mut allocation);
+            let handles = allocation as *mut efi::Handle;
+            for (index, handle) in [10_usize, 20, 30].into_iter().enumerate() {
+                unsafe { ptr::write(handles.add(index), handle as _) };
+            }
+            unsafe {
+                ptr::write(no_handles, 3);
+                ptr::write(buffer, handles);
+            }
+            efi::Status::SUCCESS
+        }
+
+        let handles = protocol_handler::HandleBufferIter::with_protocol::<TestProtocol>(&boot_services).unwrap();
+        assert_eq!(3, handles.len());
+        assert_eq!(vec![10, 20, 30], handles.map(|handle| handle as usize).collect::<Vec<_>>());
+
+        let mut handles =
+            protocol_handler::HandleBufferIter::with_protocol::<efi::protocols::block_io::Protocol>(&boot_services)
+                .unwrap();
+        assert_eq!(0, handles.len());
+        assert_eq!(None, handles.next());
+    }
+
     #[test]
     #[should_panic = "Boot services function handle_protocol is not initialized."]
     fn test_handle_protocol_not_init() {
@@ -10,6 +10,9 @@ use core::{ffi::c_void, ptr::NonNull};
 
 use r_efi::efi;
 
+use super::{BootServices, boxed::BootServicesBox};
+use crate::uefi_protocol::ProtocolInterface;
+
 /// Represents a registration handle for protocol notifications in the UEFI system.
 pub type Registration = NonNull<c_void>;
 
@@ -33,3 +36,61 @@ impl From<HandleSearchType> for efi::LocateSearchType {
         }
     }
 }
+
+/// An iterator over the handles in a buffer returned by [`BootServices::locate_handle_buffer`].
+///
+/// The buffer is freed when the iterator is dropped.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina::boot_services::{StandardBootServices, protocol_handler::HandleBufferIter};
+/// use r_efi::efi;
+///
+/// fn count_block_io_devices(boot_services: &StandardBootServices) -> Result<usize, efi::Status> {
+///     Ok(HandleBufferIter::with_protocol::<efi::protocols::block_io::Protocol>(boot_services)?.count())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct HandleBufferIter<'a, B: BootServices> {
+    buffer: Option<BootServicesBox<'a, [efi::Handle], B>>,
+    index: usize,
+}
+
+impl<'a, B: BootServices> HandleBufferIter<'a, B> {
+    /// Creates an iterator over the handles in `buffer`, or an empty iterator if there is no buffer.
+    pub fn new(buffer: Option<BootServicesBox<'a, [efi::Handle], B>>) -> Self {
+        Self { buffer, index: 0 }
+    }
+
+    /// Returns an iterator over the handles that have the protocol `T` installed on them.
+    ///
+    /// The iterator is empty if no handles have the protocol installed.
+    pub fn with_protocol<T: ProtocolInterface + 'static>(boot_services: &'a B) -> Result<Self, efi::Status> {
+        match boot_services.locate_handle_buffer(HandleSearchType::ByProtocol(&T::PROTOCOL_GUID)) {
+            Ok(buffer) => Ok(Self::new(Some(buffer))),
+            Err(efi::Status::NOT_FOUND) => Ok(Self::new(None)),
+            Err(status) => Err(status),
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buffer.as_ref().map_or(0, |buffer| buffer.len() - self.index)
+    }
+}
+
+impl<B: BootServices> Iterator for HandleBufferIter<'_, B> {
+    type Item = efi::Handle;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let handle = *self.buffer.as_ref()?.get(self.index)?;
+        self.index += 1;
+        Some(handle)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining(), Some(self.remaining()))
+    }
+}
+
+impl<B: BootServices> ExactSizeIterator for HandleBufferIter<'_, B> {}
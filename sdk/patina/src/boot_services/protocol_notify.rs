@@ -0,0 +1,248 @@
+//! This module provides protocol install notifications that call a closure.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::ptr;
+
+use r_efi::efi;
+
+use super::{
+    BootServices,
+    event::EventType,
+    protocol_handler::{HandleSearchType, Registration},
+    tpl::Tpl,
+};
+
+struct NotifyContext<B: BootServices> {
+    boot_services: B,
+    registration: Option<Registration>,
+    callback: Box<dyn FnMut(efi::Handle)>,
+}
+
+/// Calls a closure with each handle on which a protocol is installed.
+///
+/// This wraps the event, notify function and registration key that
+/// [`BootServices::register_protocol_notify`] requires. The closure runs at the given TPL, once for every handle that
+/// the protocol is installed on after the notification is created. The notification is cancelled, and its event
+/// closed, when this is dropped.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use patina::boot_services::{StandardBootServices, protocol_notify::ProtocolNotification, tpl::Tpl};
+/// use r_efi::efi;
+///
+/// fn watch_block_io(boot_services: &StandardBootServices) -> Result<ProtocolNotification<StandardBootServices>, efi::Status> {
+///     ProtocolNotification::new(
+///         boot_services.clone(),
+///         &efi::protocols::block_io::PROTOCOL_GUID,
+///         Tpl::CALLBACK,
+///         |handle| log::info!("Block I/O installed on {handle:?}"),
+///     )
+/// }
+/// ```
+#[must_use = "if unused the notification is immediately cancelled"]
+pub struct ProtocolNotification<B: BootServices + 'static> {
+    event: efi::Event,
+    context: *mut NotifyContext<B>,
+}
+
+impl<B: BootServices + 'static> ProtocolNotification<B> {
+    /// Registers `callback` to be called with each handle that `protocol` is installed on.
+    pub fn new<F>(
+        boot_services: B,
+        protocol: &'static efi::Guid,
+        notify_tpl: Tpl,
+        callback: F,
+    ) -> Result<Self, efi::Status>
+    where
+        F: FnMut(efi::Handle) + 'static,
+    {
+        let context =
+            Box::into_raw(Box::new(NotifyContext { boot_services, registration: None, callback: Box::new(callback) }));
+
+        // SAFETY: the context is owned by the notification and outlives the event, which is closed before the context is
+        //         freed.
+        let boot_services = unsafe { &(*context).boot_services };
+        let event = match unsafe {
+            boot_services.create_event_unchecked(
+                EventType::NOTIFY_SIGNAL,
+                notify_tpl,
+                Some(protocol_notify::<B>),
+                context,
+            )
+        } {
+            Ok(event) => event,
+            Err(status) => {
+                // SAFETY: the context was created above and is not referenced by an event.
+                drop(unsafe { Box::from_raw(context) });
+                return Err(status);
+            }
+        };
+
+        // the notification owns the event and context from here on, and releases both if registration fails.
+        let notification = Self { event, context };
+        let registration = boot_services.register_protocol_notify(protocol, event)?;
+        // SAFETY: the notify function only reads the registration while the event is signaled, which cannot happen before
+        //         the registration exists.
+        unsafe { (*context).registration = Some(registration) };
+        Ok(notification)
+    }
+
+    /// Returns the event that is signaled when the protocol is installed.
+    pub fn event(&self) -> efi::Event {
+        self.event
+    }
+}
+
+impl<B: BootServices + 'static> Drop for ProtocolNotification<B> {
+    fn drop(&mut self) {
+        // SAFETY: the context is valid until it is freed below, after the event is closed.
+        let context = unsafe { Box::from_raw(self.context) };
+        let _ = context.boot_services.close_event(self.event);
+        self.context = ptr::null_mut();
+    }
+}
+
+extern "efiapi" fn protocol_notify<B: BootServices>(_event: efi::Event, context: *mut NotifyContext<B>) {
+    // SAFETY: the context is valid for as long as the event exists.
+    let Some(context) = (unsafe { context.as_mut() }) else {
+        return;
+    };
+    let Some(registration) = context.registration else {
+        return;
+    };
+
+    while let Ok(handles) = context.boot_services.locate_handle(HandleSearchType::ByRegisterNotify(registration)) {
+        if handles.is_empty() {
+            break;
+        }
+        for &handle in handles.iter() {
+            (context.callback)(handle);
+        }
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::boot_services::StandardBootServices;
+    use alloc::{rc::Rc, vec, vec::Vec};
+    use core::{cell::RefCell, ffi::c_void, mem::MaybeUninit};
+    use std::sync::Mutex;
+
+    static NOTIFY: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+    static INSTALLED_HANDLES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    static CLOSED_EVENTS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    extern "efiapi" fn efi_create_event(
+        event_type: u32,
+        notify_tpl: efi::Tpl,
+        notify_function: Option<efi::EventNotify>,
+        notify_context: *mut c_void,
+        event: *mut efi::Event,
+    ) -> efi::Status {
+        assert_eq!(event_type, efi::EVT_NOTIFY_SIGNAL);
+        assert_eq!(notify_tpl, efi::TPL_CALLBACK);
+        *NOTIFY.lock().unwrap() = Some((notify_function.unwrap() as usize, notify_context as usize));
+        unsafe { event.write(1_usize as efi::Event) };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn efi_register_protocol_notify(
+        _protocol: *mut efi::Guid,
+        event: efi::Event,
+        registration: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(event as usize, 1);
+        unsafe { registration.write(10_usize as *mut c_void) };
+        efi::Status::SUCCESS
+    }
+
+    // returns the installed handles one at a time, as LocateHandle() does for a registration.
+    extern "efiapi" fn efi_locate_handle(
+        search_type: efi::LocateSearchType,
+        _protocol: *mut efi::Guid,
+        search_key: *mut c_void,
+        buffer_size: *mut usize,
+        buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        assert_eq!(search_type, efi::BY_REGISTER_NOTIFY);
+        assert_eq!(search_key as usize, 10);
+        let mut installed = INSTALLED_HANDLES.lock().unwrap();
+        let Some(&handle) = installed.first() else {
+            return efi::Status::NOT_FOUND;
+        };
+        if unsafe { buffer_size.read() } < size_of::<efi::Handle>() {
+            unsafe { buffer_size.write(size_of::<efi::Handle>()) };
+            return efi::Status::BUFFER_TOO_SMALL;
+        }
+        installed.remove(0);
+        unsafe { buffer.write(handle as efi::Handle) };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn efi_allocate_pool(
+        _mem_type: efi::MemoryType,
+        size: usize,
+        buffer: *mut *mut c_void,
+    ) -> efi::Status {
+        let allocation = vec![0_u64; size.div_ceil(size_of::<u64>()).max(1)].into_boxed_slice();
+        unsafe { buffer.write(Box::into_raw(allocation) as *mut c_void) };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn efi_free_pool(_buffer: *mut c_void) -> efi::Status {
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn efi_close_event(event: efi::Event) -> efi::Status {
+        CLOSED_EVENTS.lock().unwrap().push(event as usize);
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_protocol_notification_calls_closure_for_each_installed_handle() {
+        let efi_boot_services = unsafe {
+            let mut bs = MaybeUninit::<efi::BootServices>::zeroed();
+            bs.assume_init_mut().create_event = efi_create_event;
+            bs.assume_init_mut().register_protocol_notify = efi_register_protocol_notify;
+            bs.assume_init_mut().locate_handle = efi_locate_handle;
+            bs.assume_init_mut().allocate_pool = efi_allocate_pool;
+            bs.assume_init_mut().free_pool = efi_free_pool;
+            bs.assume_init_mut().close_event = efi_close_event;
+            Box::leak(Box::new(bs.assume_init()))
+        };
+        let boot_services = StandardBootServices::new(efi_boot_services);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_by_closure = seen.clone();
+        let notification = ProtocolNotification::new(
+            boot_services,
+            &efi::protocols::block_io::PROTOCOL_GUID,
+            Tpl::CALLBACK,
+            move |handle| seen_by_closure.borrow_mut().push(handle as usize),
+        )
+        .unwrap();
+        assert_eq!(notification.event() as usize, 1);
+
+        // the protocol is installed on two handles before the event is dispatched.
+        INSTALLED_HANDLES.lock().unwrap().extend([0x100, 0x200]);
+        let (notify_function, notify_context) = NOTIFY.lock().unwrap().unwrap();
+        let notify_function: efi::EventNotify = unsafe { core::mem::transmute(notify_function) };
+        notify_function(1_usize as efi::Event, notify_context as *mut c_void);
+        assert_eq!(*seen.borrow(), vec![0x100, 0x200]);
+        assert!(INSTALLED_HANDLES.lock().unwrap().is_empty());
+
+        drop(notification);
+        assert_eq!(*CLOSED_EVENTS.lock().unwrap(), vec![1]);
+    }
+}
@@ -123,7 +123,10 @@
 //!
 extern crate alloc;
 
+pub mod boot_config;
 pub mod hob;
+pub mod lifecycle;
+pub mod message_bus;
 mod metadata;
 pub mod params;
 pub mod service;
@@ -132,7 +135,7 @@ mod struct_component;
 
 use crate::error::Result;
 
-pub use metadata::MetaData;
+pub use metadata::{Access, MetaData};
 pub use storage::Storage;
 pub use storage::UnsafeStorageCell;
 
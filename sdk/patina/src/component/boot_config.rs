@@ -0,0 +1,55 @@
+//! Serializable boot configuration.
+//!
+//! A [Config](crate::component::params::Config) datum that implements [BootConfig] can be serialized into a boot
+//! configuration blob by the core, and replaced at boot by the value in a blob passed to the core in a HOB. This allows
+//! a platform to tune behavior, such as policies and budgets, without rebuilding the platform binary.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::{OwnedGuid, component::boot_config::BootConfig};
+//!
+//! #[derive(Default, Debug, PartialEq)]
+//! struct TimeoutConfig {
+//!     timeout_ms: u32,
+//! }
+//!
+//! impl BootConfig for TimeoutConfig {
+//!     const CONFIG_GUID: OwnedGuid =
+//!         OwnedGuid::from_fields(0x3f7bc1d2, 0x64a9, 0x4c0e, 0x9b, 0x15, [0x27, 0xd8, 0x6e, 0x41, 0xa3, 0x0c]);
+//!
+//!     fn to_bytes(&self) -> Vec<u8> {
+//!         self.timeout_ms.to_le_bytes().to_vec()
+//!     }
+//!
+//!     fn from_bytes(bytes: &[u8]) -> Option<Self> {
+//!         Some(TimeoutConfig { timeout_ms: u32::from_le_bytes(bytes.try_into().ok()?) })
+//!     }
+//! }
+//!
+//! let config = TimeoutConfig { timeout_ms: 500 };
+//! assert_eq!(TimeoutConfig::from_bytes(&config.to_bytes()), Some(config));
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+
+use crate::OwnedGuid;
+
+/// A configuration datum that can be serialized into, and restored from, a boot configuration blob.
+pub trait BootConfig: Default + Sized + 'static {
+    /// The guid identifying the configuration in a boot configuration blob.
+    const CONFIG_GUID: OwnedGuid;
+
+    /// Serializes the configuration into bytes.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Deserializes the configuration from bytes produced by [to_bytes](BootConfig::to_bytes), returning `None` if the
+    /// bytes are malformed.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
@@ -0,0 +1,95 @@
+//! Component lifecycle stages and hooks.
+//!
+//! The core executes components in two dispatch passes. Configuration is mutable during the first pass, and is locked
+//! between the two passes. A lifecycle hook allows a component or the platform to run code exactly once when the core
+//! reaches a [LifecycleStage], such as finalizing configuration derived from other configuration before it is locked,
+//! or reacting to configuration being locked.
+//!
+//! Hooks are registered with [Commands::add_lifecycle_hook](crate::component::params::Commands::add_lifecycle_hook)
+//! from a component, or with [Storage::add_lifecycle_hook] directly. A hook registered for a stage the core has already
+//! entered runs immediately.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::{
+//!     lifecycle::LifecycleStage,
+//!     params::{Commands, ConfigMut},
+//! };
+//!
+//! #[derive(Default)]
+//! struct Budget(u32);
+//!
+//! fn my_component(_budget: ConfigMut<Budget>, mut commands: Commands) -> patina::error::Result<()> {
+//!     commands.add_lifecycle_hook(LifecycleStage::PostFirstPass, |storage| {
+//!         if let Some(mut budget) = storage.get_config_mut::<Budget>() {
+//!             budget.0 = budget.0.max(16);
+//!         }
+//!     });
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::Debug;
+
+use super::Storage;
+
+/// A stage of the component lifecycle, in the order the core enters them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LifecycleStage {
+    /// The core is about to execute the first dispatch pass. Configuration is mutable.
+    PreDispatch,
+    /// The first dispatch pass has completed and configuration is about to be locked. This is the last opportunity to
+    /// modify configuration.
+    PostFirstPass,
+    /// Configuration has been locked, and the core is about to execute the second dispatch pass.
+    Locked,
+}
+
+/// A hook that runs when the core enters a [LifecycleStage].
+pub type LifecycleHook = Box<dyn FnOnce(&mut Storage)>;
+
+/// The registered lifecycle hooks and the current stage.
+#[derive(Default)]
+pub(crate) struct LifecycleHooks {
+    stage: Option<LifecycleStage>,
+    hooks: Vec<(LifecycleStage, LifecycleHook)>,
+}
+
+impl LifecycleHooks {
+    pub(crate) const fn new() -> Self {
+        Self { stage: None, hooks: Vec::new() }
+    }
+
+    /// Returns the most recent stage the core entered.
+    pub(crate) fn stage(&self) -> Option<LifecycleStage> {
+        self.stage
+    }
+
+    /// Registers a hook to run when the core enters `stage`.
+    pub(crate) fn add(&mut self, stage: LifecycleStage, hook: LifecycleHook) {
+        self.hooks.push((stage, hook));
+    }
+
+    /// Enters `stage`, returning the hooks registered for it, or for an earlier stage, in registration order.
+    pub(crate) fn enter(&mut self, stage: LifecycleStage) -> Vec<LifecycleHook> {
+        let current = self.stage.map_or(stage, |current| current.max(stage));
+        self.stage = Some(current);
+        self.hooks.extract_if(.., |(stage, _)| *stage <= current).map(|(_, hook)| hook).collect()
+    }
+}
+
+impl Debug for LifecycleHooks {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LifecycleHooks").field("stage", &self.stage).field("hooks", &self.hooks.len()).finish()
+    }
+}
@@ -0,0 +1,100 @@
+//! A typed publish/subscribe message bus for components.
+//!
+//! The message bus allows loosely coupled components to exchange events, such as "thermal trip" or "console ready",
+//! without defining a bespoke service or protocol notify for every interaction. Each event type is its own channel:
+//! subscribers to an event type are called, in subscription order, with every event of that type that is published.
+//!
+//! The bus retains the most recent event published on each channel, and a new subscriber is immediately called with
+//! it. This allows a component to observe a one-time event, such as "console ready", even if it was published before
+//! the component executed.
+//!
+//! Components publish and subscribe with [Commands::publish](crate::component::params::Commands::publish) and
+//! [Commands::subscribe](crate::component::params::Commands::subscribe), which take effect after the component has
+//! executed. Subscribers are called with a shared reference to the event and must use interior mutability to record
+//! state.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::params::Commands;
+//!
+//! #[derive(Debug)]
+//! struct ThermalTrip {
+//!     sensor: u32,
+//! }
+//!
+//! fn thermal_monitor(mut commands: Commands) -> patina::error::Result<()> {
+//!     commands.publish(ThermalTrip { sensor: 3 });
+//!     Ok(())
+//! }
+//!
+//! fn thermal_logger(mut commands: Commands) -> patina::error::Result<()> {
+//!     commands.subscribe(|trip: &ThermalTrip| log::warn!("Thermal trip on sensor {}", trip.sensor));
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{
+    any::{Any, TypeId},
+    fmt::Debug,
+};
+
+type Subscriber = Box<dyn Fn(&dyn Any)>;
+
+/// A channel for a single event type.
+#[derive(Default)]
+struct Channel {
+    subscribers: Vec<Subscriber>,
+    last: Option<Box<dyn Any>>,
+}
+
+/// A typed publish/subscribe message bus.
+#[derive(Default)]
+pub(crate) struct MessageBus {
+    channels: BTreeMap<TypeId, Channel>,
+}
+
+impl MessageBus {
+    pub(crate) const fn new() -> Self {
+        Self { channels: BTreeMap::new() }
+    }
+
+    /// Subscribes to events of type `E`, immediately calling the subscriber with the most recent event, if any.
+    pub(crate) fn subscribe<E: 'static, F: Fn(&E) + 'static>(&mut self, subscriber: F) {
+        let subscriber: Subscriber = Box::new(move |event| {
+            if let Some(event) = event.downcast_ref::<E>() {
+                subscriber(event)
+            }
+        });
+
+        let channel = self.channels.entry(TypeId::of::<E>()).or_default();
+        if let Some(last) = &channel.last {
+            subscriber(last.as_ref());
+        }
+        channel.subscribers.push(subscriber);
+    }
+
+    /// Publishes an event to all subscribers of type `E`, returning the number of subscribers called.
+    pub(crate) fn publish<E: 'static>(&mut self, event: E) -> usize {
+        let channel = self.channels.entry(TypeId::of::<E>()).or_default();
+        let event: Box<dyn Any> = Box::new(event);
+        channel.subscribers.iter().for_each(|subscriber| subscriber(event.as_ref()));
+        channel.last = Some(event);
+        channel.subscribers.len()
+    }
+}
+
+impl Debug for MessageBus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MessageBus").field("channels", &self.channels.len()).finish()
+    }
+}
@@ -55,7 +55,7 @@ impl MetaData {
 
     /// Returns immutable access to the param usage metadata for the component.
     #[inline(always)]
-    pub(crate) fn access(&self) -> &Access {
+    pub fn access(&self) -> &Access {
         &self.access
     }
 }
@@ -73,6 +73,10 @@ pub struct Access {
     writes_all_configs: bool,
     /// is `true` if the component accesses the deferred queue.
     has_deferred: bool,
+    /// Accesses to a service.
+    services: FixedBitSet,
+    /// is `true` if the component has access to the UEFI boot or runtime services tables.
+    has_boot_services: bool,
 }
 
 impl Access {
@@ -84,6 +88,8 @@ impl Access {
             reads_all_configs: false,
             writes_all_configs: false,
             has_deferred: false,
+            services: FixedBitSet::new(),
+            has_boot_services: false,
         }
     }
 }
@@ -150,6 +156,52 @@ impl Access {
     pub fn deferred(&mut self) {
         self.has_deferred = true;
     }
+
+    /// Registers an access to the specified service.
+    pub fn add_service(&mut self, id: usize) {
+        self.services.grow_and_insert(id);
+    }
+
+    /// Returns whether the component accesses the service denoted by `id`.
+    pub fn has_service(&self, id: usize) -> bool {
+        self.services.contains(id)
+    }
+
+    /// Returns the ids of the services the component accesses.
+    pub fn services(&self) -> impl Iterator<Item = usize> + '_ {
+        self.services.ones()
+    }
+
+    /// Marks the component as having access to the UEFI boot or runtime services tables.
+    pub fn boot_services(&mut self) {
+        self.has_boot_services = true;
+    }
+
+    /// Returns whether or not the component has access to the UEFI boot or runtime services tables.
+    pub fn has_boot_services(&self) -> bool {
+        self.has_boot_services
+    }
+
+    /// Returns whether the component must run on the boot strap processor.
+    ///
+    /// UEFI boot and runtime services may only be called from the boot strap processor, and the deferred queue and
+    /// access to all config resources reach into storage that is shared with every other component.
+    /// Services are not considered here, as whether a service may be called from an application processor is known
+    /// by the storage rather than the component; see [ApSafe](crate::component::service::ApSafe).
+    pub fn requires_bsp(&self) -> bool {
+        self.has_boot_services || self.has_deferred || self.reads_all_configs
+    }
+
+    /// Returns whether the component can run at the same time as a component with the `other` access requirements.
+    ///
+    /// Neither component may require the boot strap processor, and the components may not share any config resource
+    /// or service, as neither are synchronized for access from multiple processors.
+    pub fn is_compatible(&self, other: &Access) -> bool {
+        !self.requires_bsp()
+            && !other.requires_bsp()
+            && self.config_read_and_writes.is_disjoint(&other.config_read_and_writes)
+            && self.services.is_disjoint(&other.services)
+    }
 }
 
 impl fmt::Debug for Access {
@@ -254,4 +306,37 @@ mod tests {
         assert!(access.has_any_config_read());
         assert!(access.has_any_config_write());
     }
+
+    #[test]
+    fn test_components_sharing_a_config_or_service_are_not_compatible() {
+        let mut a = Access::new();
+        a.add_config_read(0);
+        a.add_service(0);
+        let mut b = Access::new();
+        b.add_config_read(1);
+        b.add_service(1);
+        assert!(a.is_compatible(&b));
+
+        b.add_config_read(0);
+        assert!(!a.is_compatible(&b));
+
+        let mut c = Access::new();
+        c.add_service(0);
+        assert!(!a.is_compatible(&c));
+        assert!(b.is_compatible(&c));
+    }
+
+    #[test]
+    fn test_components_requiring_the_bsp_are_not_compatible() {
+        let a = Access::new();
+        assert!(a.is_compatible(&Access::new()));
+
+        for mark in [Access::boot_services, Access::deferred, Access::reads_all_configs] {
+            let mut b = Access::new();
+            mark(&mut b);
+            assert!(b.requires_bsp());
+            assert!(!a.is_compatible(&b));
+            assert!(!b.is_compatible(&a));
+        }
+    }
 }
@@ -88,6 +88,9 @@
 //! Once a config datum is locked, it cannot be unlocked, and no further components that have a [ConfigMut] parameter
 //! will be executed.
 //!
+//! Configuration derived from other configuration can be finalized exactly once before the core locks all config datums
+//! by registering a hook for [LifecycleStage::PostFirstPass] with [Commands::add_lifecycle_hook].
+//!
 //! ## License
 //!
 //! Copyright (c) Microsoft Corporation.
@@ -107,6 +110,7 @@ use alloc::boxed::Box;
 use crate::{
     boot_services::StandardBootServices,
     component::{
+        lifecycle::LifecycleStage,
         metadata::MetaData,
         service::IntoService,
         storage::{Deferred, Storage, UnsafeStorageCell},
@@ -577,6 +581,43 @@ impl Commands<'_> {
         });
     }
 
+    /// Adds a versioned service to storage sometime after the component has been executed, replacing an existing
+    /// service of a lesser or equal version.
+    ///
+    /// See [Storage::add_versioned_service] for more information.
+    pub fn add_versioned_service<S: IntoService + 'static>(&mut self, service: S, version: u32) {
+        self.queue.add_command(move |storage| {
+            storage.add_versioned_service(service, version);
+        });
+    }
+
+    /// Registers a hook to run once when the core enters `stage`, sometime after the component has been executed.
+    ///
+    /// See [lifecycle](crate::component::lifecycle) for more information.
+    pub fn add_lifecycle_hook<F: FnOnce(&mut Storage) + 'static>(&mut self, stage: LifecycleStage, hook: F) {
+        self.queue.add_command(move |storage| {
+            storage.add_lifecycle_hook(stage, hook);
+        });
+    }
+
+    /// Publishes an event on the message bus sometime after the component has been executed.
+    ///
+    /// See [message_bus](crate::component::message_bus) for more information.
+    pub fn publish<E: 'static>(&mut self, event: E) {
+        self.queue.add_command(move |storage| {
+            storage.publish(event);
+        });
+    }
+
+    /// Subscribes to events on the message bus sometime after the component has been executed.
+    ///
+    /// See [message_bus](crate::component::message_bus) for more information.
+    pub fn subscribe<E: 'static, F: Fn(&E) + 'static>(&mut self, subscriber: F) {
+        self.queue.add_command(move |storage| {
+            storage.subscribe(subscriber);
+        });
+    }
+
     /// Creates an instance of Commands that will never apply any commands to the storage.
     ///
     /// This function is intended for testing purposes only. Dropping the returned value will cause a memory leak as
@@ -648,7 +689,9 @@ unsafe impl Param for StandardBootServices {
         unsafe { storage.storage() }.boot_services().is_init()
     }
 
-    fn init_state(_storage: &mut Storage, _meta: &mut MetaData) -> Self::State {}
+    fn init_state(_storage: &mut Storage, meta: &mut MetaData) -> Self::State {
+        meta.access_mut().boot_services();
+    }
 }
 
 unsafe impl Param for StandardRuntimeServices {
@@ -666,7 +709,9 @@ unsafe impl Param for StandardRuntimeServices {
         unsafe { storage.storage() }.runtime_services().is_init()
     }
 
-    fn init_state(_storage: &mut Storage, _meta: &mut MetaData) -> Self::State {}
+    fn init_state(_storage: &mut Storage, meta: &mut MetaData) -> Self::State {
+        meta.access_mut().boot_services();
+    }
 }
 
 macro_rules! impl_component_param_tuple {
@@ -126,7 +126,22 @@ use crate::component::{
     storage::{Storage, UnsafeStorageCell},
 };
 
+pub mod boot_cache;
+pub mod boot_checkpoint;
+pub mod compatibility_mode;
+pub mod driver_diagnostics;
+pub mod entropy;
+pub mod file_digest;
+pub mod hob_producer;
+pub mod image_loader;
+pub mod io_space;
 pub mod memory;
+pub mod memory_attribute_journal;
+pub mod memory_attributes;
+pub mod memory_map;
+pub mod msi;
+pub mod persisted_blob;
+pub mod psci;
 
 pub use patina_macro::IntoService;
 
@@ -166,10 +181,53 @@ pub trait IntoService {
     /// ```
     fn register_service<S: ?Sized + 'static>(storage: &mut Storage, service: &'static dyn Any) {
         let id = storage.register_service::<S>();
-        storage.insert_service(id, service);
+        storage.insert_service::<S>(id, service);
     }
 }
 
+/// Marks a service as safe to call from an application processor (AP).
+///
+/// A component is only dispatched on an AP when every [Service] it takes was added with
+/// [Storage::add_ap_safe_service]; all other services are assumed to be usable from the boot strap processor only.
+///
+/// ## Safety
+///
+/// Every method of every service the type is registered as must be safe to call from any processor, at the same time
+/// as the boot strap processor and other APs are running. In particular, the methods must not call UEFI boot or
+/// runtime services, or any other protocol that is not documented as AP safe.
+pub unsafe trait ApSafe {}
+
+/// An event published on the [message bus](crate::component::message_bus) when a registered service is replaced.
+///
+/// A [Service] held by a consumer continues to reference the implementation it was created with. Consumers that need
+/// to switch to the replacement, such as one substituting a hardware backed RNG for a software fallback, subscribe to
+/// this event for the service trait and update the service they hold.
+///
+/// ## Example
+///
+/// ```rust
+/// use core::cell::RefCell;
+/// use patina::component::{params::Commands, service::{Service, ServiceReplaced}};
+///
+/// trait Rng {
+///     fn next(&self) -> u64;
+/// }
+///
+/// fn rng_consumer(rng: Service<dyn Rng>, mut commands: Commands) -> patina::error::Result<()> {
+///     let current = RefCell::new(rng);
+///     commands.subscribe(move |event: &ServiceReplaced<dyn Rng>| {
+///         *current.borrow_mut() = event.service.clone();
+///     });
+///     Ok(())
+/// }
+/// ```
+pub struct ServiceReplaced<T: ?Sized + 'static> {
+    /// The replacement service.
+    pub service: Service<T>,
+    /// The version of the replacement service.
+    pub version: u32,
+}
+
 /// A service with a static lifetime that can be used as a parameter to a [Component](super::Component).
 ///
 /// The underlying service that this object wraps can be either a concrete type such as a struct or enum, or a dyn
@@ -335,8 +393,10 @@ unsafe impl<T: ?Sized + 'static> Param for Service<T> {
         unsafe { storage.storage() }.get_raw_service(*state).is_some()
     }
 
-    fn init_state(storage: &mut Storage, _meta: &mut MetaData) -> Self::State {
-        storage.register_service::<T>()
+    fn init_state(storage: &mut Storage, meta: &mut MetaData) -> Self::State {
+        let id = storage.register_service::<T>();
+        meta.access_mut().add_service(id);
+        id
     }
 }
 
@@ -403,6 +463,52 @@ mod tests {
         assert_eq!(1, s3.x)
     }
 
+    #[test]
+    fn test_versioned_service_replacement() {
+        use crate as patina;
+        use core::cell::Cell;
+        use std::rc::Rc;
+
+        trait Rng {
+            fn next(&self) -> u32;
+        }
+
+        #[derive(IntoService)]
+        #[service(dyn Rng)]
+        struct FixedRng(u32);
+
+        impl Rng for FixedRng {
+            fn next(&self) -> u32 {
+                self.0
+            }
+        }
+
+        let mut storage = Storage::new();
+        assert_eq!(storage.service_version::<dyn Rng>(), None);
+        storage.add_service(FixedRng(1));
+        let original = storage.get_service::<dyn Rng>().unwrap();
+
+        let notified = Rc::new(Cell::new(0));
+        let observer = notified.clone();
+        storage.subscribe(move |event: &ServiceReplaced<dyn Rng>| observer.set(event.service.next()));
+
+        // A greater version replaces the service and notifies subscribers.
+        storage.add_versioned_service(FixedRng(2), 2);
+        assert_eq!(storage.service_version::<dyn Rng>(), Some(2));
+        assert_eq!(storage.get_service::<dyn Rng>().unwrap().next(), 2);
+        assert_eq!(notified.get(), 2);
+        assert_eq!(original.next(), 1);
+
+        // A lesser version does not.
+        storage.add_service(FixedRng(3));
+        assert_eq!(storage.get_service::<dyn Rng>().unwrap().next(), 2);
+        assert_eq!(notified.get(), 2);
+
+        storage.add_versioned_service(FixedRng(4), 2);
+        assert_eq!(storage.get_service::<dyn Rng>().unwrap().next(), 4);
+        assert_eq!(notified.get(), 4);
+    }
+
     #[test]
     fn test_available_service_validates_true() {
         use crate as patina;
@@ -0,0 +1,70 @@
+//! Boot Cache Service Definitions.
+//!
+//! Much of the time spent in a warm boot repeats work whose result rarely changes from one boot to the next: finding
+//! the devices that are present, connecting every controller to find the consoles, and training links. The core
+//! produces the [BootCache] service, which keeps such results from the previous boot so that components can skip the
+//! slow path when nothing changed, for example by connecting only the cached console devices instead of connecting
+//! all controllers.
+//!
+//! The results of the previous boot are only used if the platform enabled fast boot, and the core detected no
+//! hardware change since the previous boot. Components should record their results with [BootCache::set] on every
+//! boot, fast or not, and call [BootCache::invalidate] if they find that a cached result no longer matches the
+//! hardware, so that the next boot takes the full path. A `mockall` mock is available for testing (`MockBootCache`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, boot_cache::{BootCache, CONNECTED_CONSOLES}};
+//!
+//! fn connect_consoles(cache: Service<dyn BootCache>) -> patina::error::Result<()> {
+//!     let consoles = match cache.get(&CONNECTED_CONSOLES) {
+//!         Some(consoles) => consoles, // connect the cached console device paths.
+//!         None => Vec::new(),         // connect all controllers, and find the consoles.
+//!     };
+//!     cache.set(&CONNECTED_CONSOLES, &consoles);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+use r_efi::efi;
+
+/// The class of the device paths of the consoles that were connected.
+pub const CONNECTED_CONSOLES: efi::Guid =
+    efi::Guid::from_fields(0x5f3b2a71, 0x0c4d, 0x4e89, 0xa1, 0x6b, &[0x2d, 0x7e, 0x93, 0x40, 0xc8, 0x15]);
+
+/// The class of the devices that were found present during enumeration.
+pub const DEVICE_PRESENCE: efi::Guid =
+    efi::Guid::from_fields(0x9a0e6c42, 0x7b1f, 0x4d3a, 0x85, 0xe2, &[0x4f, 0x19, 0x6a, 0xd0, 0x3b, 0x77]);
+
+/// The class of the values that were found when training links or buses.
+pub const TRAINED_VALUES: efi::Guid =
+    efi::Guid::from_fields(0x3ec8d5b6, 0x1a92, 0x4f07, 0x9c, 0x34, &[0xb8, 0x5d, 0x02, 0xe6, 0x71, 0xaf]);
+
+/// Results of the previous boot that components use to skip slow work.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait BootCache {
+    /// Returns true if fast boot is enabled, and the results of the previous boot are valid for this boot.
+    fn is_valid(&self) -> bool;
+
+    /// Returns the result of the previous boot for `class`, if the results are valid and one was recorded.
+    fn get(&self, class: &efi::Guid) -> Option<Vec<u8>>;
+
+    /// Records the result of this boot for `class`, for use by the next boot.
+    fn set(&self, class: &efi::Guid, data: &[u8]);
+
+    /// Marks the results of the previous boot as invalid, such as when a component detects a hardware change. No
+    /// results are returned for the remainder of this boot, and the next boot takes the full path.
+    fn invalidate(&self);
+}
@@ -0,0 +1,65 @@
+//! Boot Checkpoint Service Definitions.
+//!
+//! When a hardware watchdog resets a system that stalled during boot, the only clue to where it stalled is what was
+//! persisted before the reset. A platform that produces the [CheckpointSink] service is told by the core as boot
+//! reaches each [BootCheckpoint], and can record it in a scratch register or a memory region that survives the
+//! watchdog reset. The last recorded checkpoint then identifies the phase in which boot stalled. A `mockall` mock is
+//! available for testing (`MockCheckpointSink`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{IntoService, boot_checkpoint::{BootCheckpoint, CheckpointSink}};
+//!
+//! #[derive(IntoService)]
+//! #[service(dyn CheckpointSink)]
+//! struct ScratchRegister(usize);
+//!
+//! impl CheckpointSink for ScratchRegister {
+//!     fn checkpoint(&self, checkpoint: BootCheckpoint) {
+//!         // Safety: the platform reserves the scratch register for boot checkpoints.
+//!         unsafe { (self.0 as *mut u32).write_volatile(checkpoint as u32) };
+//!     }
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The phases of boot reported to the [CheckpointSink], in the order they are reached.
+///
+/// The values are stable, so that they can be decoded from a persisted checkpoint after a reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum BootCheckpoint {
+    /// Memory services are initialized and the core is starting.
+    MemoryInitialized = 1,
+    /// The system table and boot services are initialized.
+    SystemTableInitialized = 2,
+    /// Driver dispatch is starting.
+    DispatchStart = 3,
+    /// Driver dispatch finished.
+    DispatchComplete = 4,
+    /// The core is handing off to BDS.
+    BdsHandoff = 5,
+    /// The ReadyToBoot event group was signaled.
+    ReadyToBoot = 6,
+    /// The OS loader called ExitBootServices().
+    ExitBootServices = 7,
+}
+
+/// Records the boot checkpoints reached, so that the cause of a watchdog reset can be determined.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait CheckpointSink {
+    /// Records that boot reached `checkpoint`.
+    ///
+    /// The core may call this from event notification functions at TPL_CALLBACK, including during ExitBootServices(),
+    /// so implementations must not allocate memory or depend on protocols that may already be uninstalled.
+    fn checkpoint(&self, checkpoint: BootCheckpoint);
+}
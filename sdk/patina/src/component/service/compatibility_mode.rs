@@ -0,0 +1,172 @@
+//! Compatibility Mode Telemetry Service Definitions.
+//!
+//! Legacy OS loaders that are not NX compatible cannot run with the core's memory protections, so a core built with
+//! the `compatibility_mode_allowed` feature relaxes them when such a loader is loaded, and a core built without it
+//! refuses to load the loader. OEMs need to know how often this happens in the field. The core records each attempt
+//! as a [CompatibilityModeActivation]: the image, whether it was allowed to load, and the protections that were
+//! relaxed for it. The [CompatibilityModeTelemetry] service produced by the core returns the activations, and each
+//! activation is also reported as a status code with [CompatibilityModeStatusCodeData] as the extended data, so that
+//! telemetry drivers that listen for status codes can forward it. A `mockall` mock is available for testing
+//! (`MockCompatibilityModeTelemetry`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, compatibility_mode::CompatibilityModeTelemetry};
+//!
+//! fn report_degraded_boot(telemetry: Service<dyn CompatibilityModeTelemetry>) {
+//!     for activation in telemetry.activations().iter().filter(|activation| activation.allowed) {
+//!         log::warn!("{} relaxed {:?}", activation.image, activation.relaxed);
+//!     }
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{string::String, vec::Vec};
+use patina_pi::protocols::status_code::EfiStatusCodeData;
+use r_efi::efi;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The GUID of the [CompatibilityModeStatusCodeData] extended data of the compatibility mode status code.
+pub const COMPATIBILITY_MODE_STATUS_CODE_DATA_GUID: efi::Guid =
+    efi::Guid::from_fields(0x6c1f0b52, 0x3e8a, 0x4d4f, 0x9b, 0x27, &[0x51, 0xa4, 0xc0, 0x8e, 0x3d, 0x76]);
+
+/// The memory protections relaxed when an image activated compatibility mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelaxedProtections {
+    /// The image was mapped writable and executable.
+    pub image_writable_and_executable: bool,
+    /// Page 0 and the system memory below 0xA0000 were mapped writable and executable.
+    pub legacy_region_executable: bool,
+    /// Memory allocated from then on is executable.
+    pub allocations_executable: bool,
+    /// The existing loader code and data allocations were mapped writable and executable.
+    pub loader_memory_executable: bool,
+    /// The Memory Attributes protocol was uninstalled, so the loader could not restore the protections.
+    pub memory_attributes_protocol_removed: bool,
+}
+
+impl RelaxedProtections {
+    /// Every protection, as relaxed by the image that first activates compatibility mode.
+    pub const ALL: Self = Self {
+        image_writable_and_executable: true,
+        legacy_region_executable: true,
+        allocations_executable: true,
+        loader_memory_executable: true,
+        memory_attributes_protocol_removed: true,
+    };
+
+    /// Returns the protections as a bitmask, in the order of the fields starting at bit 0, as in
+    /// [CompatibilityModeStatusCodeData::relaxed].
+    pub const fn bits(&self) -> u32 {
+        self.image_writable_and_executable as u32
+            | (self.legacy_region_executable as u32) << 1
+            | (self.allocations_executable as u32) << 2
+            | (self.loader_memory_executable as u32) << 3
+            | (self.memory_attributes_protocol_removed as u32) << 4
+    }
+
+    /// Returns true if no protection was relaxed.
+    pub const fn is_empty(&self) -> bool {
+        self.bits() == 0
+    }
+}
+
+/// An image that is not NX compatible, loaded while the core's memory protections were active.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatibilityModeActivation {
+    /// The file name of the image, or "Unknown" if the image has no debug information.
+    pub image: String,
+    /// The base address the image was loaded at.
+    pub image_base: u64,
+    /// The size of the image in bytes.
+    pub image_size: u64,
+    /// True if the image was loaded with compatibility mode, false if the core refused to load it.
+    pub allowed: bool,
+    /// The protections that were relaxed for the image. Only the first image that is allowed relaxes the protections
+    /// for the whole platform, and none are relaxed for an image that is refused.
+    pub relaxed: RelaxedProtections,
+}
+
+/// The extended data of the status code reported for each [CompatibilityModeActivation].
+///
+/// The status code is an `EFI_ERROR_CODE | EFI_ERROR_MINOR` with the value
+/// `EFI_SOFTWARE_DXE_CORE | EFI_SW_EC_ILLEGAL_SOFTWARE_STATE`, reported by the DXE Core.
+#[repr(C)]
+pub struct CompatibilityModeStatusCodeData {
+    /// The header, with [COMPATIBILITY_MODE_STATUS_CODE_DATA_GUID] as the type.
+    pub header: EfiStatusCodeData,
+    /// [CompatibilityModeActivation::image_base].
+    pub image_base: u64,
+    /// [CompatibilityModeActivation::image_size].
+    pub image_size: u64,
+    /// [CompatibilityModeActivation::relaxed], as returned by [RelaxedProtections::bits].
+    pub relaxed: u32,
+    /// 1 if [CompatibilityModeActivation::allowed], 0 otherwise.
+    pub allowed: u32,
+    /// [CompatibilityModeActivation::image] as NUL padded UTF-8, truncated to fit.
+    pub image: [u8; 64],
+}
+
+impl From<&CompatibilityModeActivation> for CompatibilityModeStatusCodeData {
+    fn from(activation: &CompatibilityModeActivation) -> Self {
+        let mut image = [0u8; 64];
+        // leave room for a terminating NUL.
+        let len = activation.image.len().min(image.len() - 1);
+        image[..len].copy_from_slice(&activation.image.as_bytes()[..len]);
+        Self {
+            header: EfiStatusCodeData {
+                header_size: size_of::<EfiStatusCodeData>() as u16,
+                size: (size_of::<Self>() - size_of::<EfiStatusCodeData>()) as u16,
+                r#type: COMPATIBILITY_MODE_STATUS_CODE_DATA_GUID,
+            },
+            image_base: activation.image_base,
+            image_size: activation.image_size,
+            relaxed: activation.relaxed.bits(),
+            allowed: activation.allowed as u32,
+            image,
+        }
+    }
+}
+
+/// Reports the images that activated, or were refused, compatibility mode.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait CompatibilityModeTelemetry {
+    /// Returns the activations in the order the images were loaded.
+    fn activations(&self) -> Vec<CompatibilityModeActivation>;
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_code_data() {
+        let activation = CompatibilityModeActivation {
+            image: String::from("grubx64.efi"),
+            image_base: 0x1000_0000,
+            image_size: 0x20_0000,
+            allowed: true,
+            relaxed: RelaxedProtections { image_writable_and_executable: true, ..Default::default() },
+        };
+        let data = CompatibilityModeStatusCodeData::from(&activation);
+        assert_eq!(data.header.header_size as usize + data.header.size as usize, size_of_val(&data));
+        assert_eq!((data.image_base, data.image_size, data.relaxed, data.allowed), (0x1000_0000, 0x20_0000, 1, 1));
+        assert_eq!(&data.image[..12], b"grubx64.efi\0");
+
+        let long_name = CompatibilityModeActivation { image: "x".repeat(100), ..activation };
+        let data = CompatibilityModeStatusCodeData::from(&long_name);
+        assert_eq!(data.image[62..], [b'x', 0]);
+
+        assert_eq!(RelaxedProtections::ALL.bits(), 0x1F);
+        assert!(RelaxedProtections::default().is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+//! Driver Diagnostics Service Definitions.
+//!
+//! Drivers that follow the UEFI driver model may produce the Driver Diagnostics 2 protocol to run diagnostics on the
+//! controllers they manage. Boot managers and manageability tools would otherwise need to find the drivers managing a
+//! controller, locate the protocol on each of them and free the result buffers themselves. The [DriverDiagnostics]
+//! service is produced by the core and runs the diagnostics of every driver managing a controller in one call,
+//! returning the outcome for each driver as a [DiagnosticResult]. A `mockall` mock is available for testing
+//! (`MockDriverDiagnostics`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, driver_diagnostics::{DiagnosticType, DriverDiagnostics}};
+//! use r_efi::efi;
+//!
+//! fn check_controller(diagnostics: Service<dyn DriverDiagnostics>, controller: efi::Handle) -> patina::error::Result<()> {
+//!     for result in diagnostics.run_diagnostics(controller, None, DiagnosticType::Standard, "en-US")? {
+//!         if result.status.is_error() {
+//!             log::error!("Diagnostics failed: {:?} {:?}", result.status, result.message);
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The kind of diagnostics to run, as defined for EFI_DRIVER_DIAGNOSTICS2_PROTOCOL.RunDiagnostics().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticType {
+    /// A quick diagnostic that is suitable for every boot.
+    Standard,
+    /// A thorough diagnostic that may take a long time to run.
+    Extended,
+    /// A diagnostic intended for the manufacturing environment.
+    Manufacturing,
+    /// Cancels a diagnostic that is running.
+    Cancel,
+}
+
+impl From<DiagnosticType> for efi::protocols::driver_diagnostics2::Type {
+    fn from(diagnostic_type: DiagnosticType) -> Self {
+        match diagnostic_type {
+            DiagnosticType::Standard => efi::protocols::driver_diagnostics2::TYPE_STANDARD,
+            DiagnosticType::Extended => efi::protocols::driver_diagnostics2::TYPE_EXTENDED,
+            DiagnosticType::Manufacturing => efi::protocols::driver_diagnostics2::TYPE_MANUFACTURING,
+            DiagnosticType::Cancel => efi::protocols::driver_diagnostics2::TYPE_CANCEL,
+        }
+    }
+}
+
+/// The outcome of the diagnostics of one driver managing a controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticResult {
+    /// The driver binding handle of the driver that ran the diagnostics.
+    pub driver_handle: efi::Handle,
+    /// The status returned by the driver: success if the controller passed, [efi::Status::DEVICE_ERROR] if it failed.
+    pub status: efi::Status,
+    /// The GUID that identifies the kind of error the driver found, if it returned one.
+    pub error_type: Option<efi::Guid>,
+    /// The message that the driver returned with the result, if any.
+    pub message: Option<String>,
+}
+
+/// Runs the diagnostics of drivers on the controllers they manage.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait DriverDiagnostics {
+    /// Runs `diagnostic_type` diagnostics on `controller_handle`, or on its child `child_handle` if one is given,
+    /// with every driver managing the controller that produces the Driver Diagnostics 2 protocol.
+    ///
+    /// `language` is an RFC 4646 language code, such as "en-US", for the messages in the results. Returns
+    /// [EfiError::InvalidParameter] if a handle is not valid and [EfiError::NotFound] if no driver managing the
+    /// controller supports diagnostics. The status of each driver is returned in its [DiagnosticResult], even if it
+    /// is an error.
+    fn run_diagnostics(
+        &self,
+        controller_handle: efi::Handle,
+        child_handle: Option<efi::Handle>,
+        diagnostic_type: DiagnosticType,
+        language: &str,
+    ) -> Result<Vec<DiagnosticResult>, EfiError>;
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_type_maps_to_protocol_type() {
+        use efi::protocols::driver_diagnostics2;
+
+        assert_eq!(driver_diagnostics2::Type::from(DiagnosticType::Standard), driver_diagnostics2::TYPE_STANDARD);
+        assert_eq!(driver_diagnostics2::Type::from(DiagnosticType::Extended), driver_diagnostics2::TYPE_EXTENDED);
+        assert_eq!(
+            driver_diagnostics2::Type::from(DiagnosticType::Manufacturing),
+            driver_diagnostics2::TYPE_MANUFACTURING
+        );
+        assert_eq!(driver_diagnostics2::Type::from(DiagnosticType::Cancel), driver_diagnostics2::TYPE_CANCEL);
+    }
+}
@@ -0,0 +1,57 @@
+//! Entropy Service Definitions.
+//!
+//! Security mitigations in the core, such as the stack protector guard, need values that an attacker cannot predict.
+//! A platform that produces the [Entropy] service supplies them from a source it trusts, such as a hardware random
+//! number generator or a TPM. The core uses the service if the platform registers it with the core before
+//! [start](https://docs.rs/patina_dxe_core). A `mockall` mock is available for testing (`MockEntropy`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::{
+//!     component::service::{IntoService, entropy::Entropy},
+//!     error::{EfiError, Result},
+//! };
+//!
+//! #[derive(IntoService)]
+//! #[service(dyn Entropy)]
+//! struct HardwareRng;
+//!
+//! impl HardwareRng {
+//!     fn read(&self) -> Option<u64> {
+//!         // read the platform's random number generator.
+//!         Some(0x5A5A_A5A5_5A5A_A5A5)
+//!     }
+//! }
+//!
+//! impl Entropy for HardwareRng {
+//!     fn fill_bytes(&self, buffer: &mut [u8]) -> Result<()> {
+//!         for chunk in buffer.chunks_mut(8) {
+//!             let value = self.read().ok_or(EfiError::DeviceError)?;
+//!             chunk.copy_from_slice(&value.to_ne_bytes()[..chunk.len()]);
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+use crate::error::Result;
+
+/// Supplies random bytes that are suitable for seeding security mitigations.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait Entropy {
+    /// Fills `buffer` with random bytes, or returns an error if the source cannot supply them.
+    ///
+    /// The core may call this before driver dispatch starts, so implementations must not depend on protocols or on
+    /// other components.
+    fn fill_bytes(&self, buffer: &mut [u8]) -> Result<()>;
+}
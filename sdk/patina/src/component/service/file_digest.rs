@@ -0,0 +1,61 @@
+//! FFS File Digest Service Definitions.
+//!
+//! Measured boot and UEFI Secure Boot hash every driver image before it is started, which serializes hashing with
+//! driver dispatch. A platform that produces the [FileDigest] service allows the core to compute these digests ahead
+//! of time: FFS files are queued for hashing as they are discovered, and the queue is worked off while the core is
+//! otherwise idle, such as while a driver waits for an event during dispatch.
+//!
+//! The digests computed ahead of time are available through the [PrecomputedFileDigests] service, which is produced
+//! by the core. Measurement and verification handlers look up a digest there before computing it themselves. `mockall`
+//! mocks are available for testing (`MockFileDigest` and `MockPrecomputedFileDigests`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, file_digest::{FileDigest, PrecomputedFileDigests}};
+//!
+//! fn image_digest(
+//!     digest: Service<dyn FileDigest>,
+//!     precomputed: Service<dyn PrecomputedFileDigests>,
+//!     image: &[u8],
+//! ) -> patina::error::Result<Vec<u8>> {
+//!     match precomputed.precomputed_digest(image) {
+//!         Some(digest) => Ok(digest),
+//!         None => digest.digest(image),
+//!     }
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Computes the digest of FFS file contents that measurement and verification handlers require.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait FileDigest {
+    /// Returns the digest of `data`.
+    ///
+    /// The core calls this with interrupts enabled at TPL_APPLICATION, so implementations may use boot services.
+    fn digest(&self, data: &[u8]) -> Result<Vec<u8>, EfiError>;
+}
+
+/// Digests of FFS file contents that were computed ahead of time with the [FileDigest] service.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait PrecomputedFileDigests {
+    /// Returns the digest of `data`, if it was computed ahead of time.
+    ///
+    /// `data` must be the same buffer that was hashed, not a copy of it. Returns `None` if the digest has not been
+    /// computed yet, in which case the caller computes it itself.
+    fn precomputed_digest(&self, data: &[u8]) -> Option<Vec<u8>>;
+}
@@ -0,0 +1,159 @@
+//! HOB Producer Service Definitions.
+//!
+//! The HOB list handed off by the HOB producer phase is published to DXE drivers as the HOB list configuration table.
+//! Some information that consumers expect to find in the HOB list is only learned during DXE, such as the location of
+//! a graphics framebuffer or a resource descriptor for a device that was discovered at runtime. The [HobProducer]
+//! service appends such HOBs to the HOB list, so that consumers do not need to look for this information through
+//! other channels.
+//!
+//! The service is produced by the core. Appended HOBs are visible to consumers that locate the HOB list configuration
+//! table after the HOB is produced; they are not parsed into [Hob](crate::component::hob::Hob) params. A `mockall`
+//! mock is available for testing (`MockHobProducer`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, hob_producer::{self, HobProducer}};
+//! use r_efi::efi;
+//!
+//! const FRAMEBUFFER_INFO_GUID: efi::Guid =
+//!     efi::Guid::from_fields(0x39f62cce, 0x6825, 0x4669, 0xbb, 0x56, &[0x54, 0x1a, 0xba, 0x75, 0x3a, 0x07]);
+//!
+//! fn publish_framebuffer(producer: Service<dyn HobProducer>, info: &[u8]) -> patina::error::Result<()> {
+//!     hob_producer::produce_guid_hob(&**producer, &FRAMEBUFFER_INFO_GUID, info)?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use patina_pi::hob::{self, ResourceDescriptor, header};
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Appends HOBs to the HOB list published to DXE drivers.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait HobProducer {
+    /// Appends a HOB of type `hob_type`, where `body` is the HOB contents following the generic HOB header.
+    ///
+    /// The producer fills in the generic header and pads the HOB to a multiple of 8 bytes, as described by
+    /// [encode_hob]. Returns [EfiError::InvalidParameter] if the HOB cannot be encoded, and [EfiError::AccessDenied] if
+    /// the HOB list can no longer be changed.
+    fn produce_hob(&self, hob_type: u16, body: &[u8]) -> Result<(), EfiError>;
+}
+
+/// Encodes a HOB of type `hob_type` with the contents `body`, prefixed by a generic HOB header.
+///
+/// The HOB is zero padded to a multiple of 8 bytes, as required by the PI specification. Returns
+/// [EfiError::InvalidParameter] for the Handoff and End of HOB List types, which only the HOB producer phase may
+/// create, or if the HOB does not fit in the 16-bit header length.
+pub fn encode_hob(hob_type: u16, body: &[u8]) -> Result<Vec<u8>, EfiError> {
+    if hob_type == hob::HANDOFF || hob_type == hob::END_OF_HOB_LIST {
+        return Err(EfiError::InvalidParameter);
+    }
+
+    let length = (size_of::<header::Hob>() + body.len()).next_multiple_of(8);
+    let header = header::Hob {
+        r#type: hob_type,
+        length: u16::try_from(length).map_err(|_| EfiError::InvalidParameter)?,
+        reserved: 0,
+    };
+
+    let mut bytes = Vec::with_capacity(length);
+    bytes.extend_from_slice(&header.r#type.to_le_bytes());
+    bytes.extend_from_slice(&header.length.to_le_bytes());
+    bytes.extend_from_slice(&header.reserved.to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes.resize(length, 0);
+    Ok(bytes)
+}
+
+/// Appends a GUID extension HOB named `name` that contains `data`.
+pub fn produce_guid_hob(producer: &dyn HobProducer, name: &efi::Guid, data: &[u8]) -> Result<(), EfiError> {
+    let mut body = Vec::with_capacity(size_of::<efi::Guid>() + data.len());
+    body.extend_from_slice(name.as_bytes());
+    body.extend_from_slice(data);
+    producer.produce_hob(hob::GUID_EXTENSION, &body)
+}
+
+/// Appends a resource descriptor HOB. The header of `descriptor` is ignored.
+pub fn produce_resource_descriptor(
+    producer: &dyn HobProducer,
+    descriptor: &ResourceDescriptor,
+) -> Result<(), EfiError> {
+    let mut body = Vec::with_capacity(size_of::<ResourceDescriptor>() - size_of::<header::Hob>());
+    body.extend_from_slice(descriptor.owner.as_bytes());
+    body.extend_from_slice(&descriptor.resource_type.to_le_bytes());
+    body.extend_from_slice(&descriptor.resource_attribute.to_le_bytes());
+    body.extend_from_slice(&descriptor.physical_start.to_le_bytes());
+    body.extend_from_slice(&descriptor.resource_length.to_le_bytes());
+    producer.produce_hob(hob::RESOURCE_DESCRIPTOR, &body)
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_hob_pads_and_fills_header() {
+        let bytes = encode_hob(hob::GUID_EXTENSION, &[0xaa; 19]).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(&bytes[..8], &[0x04, 0x00, 0x20, 0x00, 0, 0, 0, 0]);
+        assert_eq!(&bytes[8..27], &[0xaa; 19]);
+        assert_eq!(&bytes[27..], &[0; 5]);
+
+        assert_eq!(encode_hob(hob::CPU, &[]).unwrap().len(), 8);
+        assert_eq!(encode_hob(hob::GUID_EXTENSION, &[0; 0xfff0]).unwrap().len(), 0xfff8);
+        assert_eq!(encode_hob(hob::GUID_EXTENSION, &[0; 0xfff1]), Err(EfiError::InvalidParameter));
+        assert_eq!(encode_hob(hob::HANDOFF, &[]), Err(EfiError::InvalidParameter));
+        assert_eq!(encode_hob(hob::END_OF_HOB_LIST, &[]), Err(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn test_typed_hobs_match_pi_layout() {
+        let descriptor = ResourceDescriptor {
+            header: header::Hob { r#type: 0, length: 0, reserved: 0 },
+            owner: efi::Guid::from_fields(1, 2, 3, 4, 5, &[6, 7, 8, 9, 10, 11]),
+            resource_type: hob::EFI_RESOURCE_MEMORY_MAPPED_IO,
+            resource_attribute: hob::EFI_RESOURCE_ATTRIBUTE_PRESENT,
+            physical_start: 0xfe00_0000,
+            resource_length: 0x1000,
+        };
+
+        let mut producer = MockHobProducer::new();
+        producer
+            .expect_produce_hob()
+            .withf(move |hob_type, body| {
+                let encoded = encode_hob(*hob_type, body).unwrap();
+                *hob_type == hob::RESOURCE_DESCRIPTOR
+                    && encoded.len() == size_of::<ResourceDescriptor>()
+                    // SAFETY: the encoded HOB is the size of a resource descriptor.
+                    && unsafe { encoded.as_ptr().cast::<ResourceDescriptor>().read_unaligned() }.physical_start
+                        == 0xfe00_0000
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        producer
+            .expect_produce_hob()
+            .withf(|hob_type, body| *hob_type == hob::GUID_EXTENSION && body.len() == 16 + 3 && body[16..] == [1, 2, 3])
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        produce_resource_descriptor(&producer, &descriptor).unwrap();
+        produce_guid_hob(&producer, &efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]), &[1, 2, 3]).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+//! Image Loader Service Definitions.
+//!
+//! Components that launch an image, such as a diagnostics application or a firmware update tool, would otherwise
+//! need to call LoadImage and StartImage through the raw Boot Services table and manage the exit data buffer
+//! themselves. The [ImageLoader] service is produced by the core and loads, authenticates and starts an image in one
+//! call, returning the status and exit data of the image as an [ImageExit]. A `mockall` mock is available for testing
+//! (`MockImageLoader`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, image_loader::{ImageLoader, ImageSource}};
+//!
+//! fn run_diagnostics(loader: Service<dyn ImageLoader>, image: Vec<u8>) -> patina::error::Result<()> {
+//!     let exit = loader.load_and_start(ImageSource::Buffer { image, device_path: None })?;
+//!     if exit.status.is_error() {
+//!         log::error!("Diagnostics failed: {:?} {:?}", exit.status, exit.description());
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The image to load with [ImageLoader::load_and_start].
+///
+/// Device paths are given as the bytes of a device path, terminated by an End of Entire Device Path node.
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    /// An image that is already in memory.
+    Buffer {
+        /// The contents of the image file.
+        image: Vec<u8>,
+        /// The device path the image was read from, if known. It is used to authenticate the image and is recorded in
+        /// the Loaded Image protocol.
+        device_path: Option<Vec<u8>>,
+    },
+    /// An image that the core reads from the file at the given device path.
+    DevicePath(Vec<u8>),
+}
+
+/// The result of running an image that was started by [ImageLoader::load_and_start].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageExit {
+    /// The status that the image returned from its entry point or passed to Exit().
+    pub status: efi::Status,
+    /// The exit data that the image passed to Exit(): a null-terminated string, optionally followed by binary data.
+    pub exit_data: Option<Vec<u16>>,
+}
+
+impl ImageExit {
+    /// Returns the null-terminated string at the start of the exit data, if the image provided exit data.
+    pub fn description(&self) -> Option<String> {
+        let exit_data = self.exit_data.as_ref()?;
+        let end = exit_data.iter().position(|&c| c == 0).unwrap_or(exit_data.len());
+        Some(String::from_utf16_lossy(&exit_data[..end]))
+    }
+}
+
+/// Loads and starts images.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait ImageLoader {
+    /// Loads the image from `source` and transfers control to its entry point.
+    ///
+    /// Returns [EfiError::InvalidParameter] if a device path is not terminated within its buffer, and an error if the
+    /// image cannot be loaded or started, including [EfiError::SecurityViolation] and [EfiError::AccessDenied] if it
+    /// fails authentication. Once the image has run, its exit status is returned in the [ImageExit], even if it is an
+    /// error. Applications, and drivers that return an error, are unloaded after they exit, as they are by
+    /// StartImage().
+    fn load_and_start(&self, source: ImageSource) -> Result<ImageExit, EfiError>;
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_description_stops_at_the_null_terminator() {
+        let mut exit_data: Vec<u16> = "Update failed".encode_utf16().collect();
+        exit_data.extend_from_slice(&[0, 0x1234, 0x5678]);
+        let exit = ImageExit { status: efi::Status::ABORTED, exit_data: Some(exit_data) };
+        assert_eq!(exit.description(), Some(String::from("Update failed")));
+
+        let exit = ImageExit { status: efi::Status::ABORTED, exit_data: Some(vec![0x41, 0x42]) };
+        assert_eq!(exit.description(), Some(String::from("AB")));
+
+        let exit = ImageExit { status: efi::Status::SUCCESS, exit_data: None };
+        assert_eq!(exit.description(), None);
+    }
+}
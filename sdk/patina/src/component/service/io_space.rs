@@ -0,0 +1,87 @@
+//! I/O Space Service Definitions.
+//!
+//! The Global Coherency Domain (GCD) tracks the I/O port space of the processor alongside its memory space. The
+//! [IoSpace] service is produced by the core and provides typed access to the I/O space services of the DXE Services
+//! Table (AddIoSpace, AllocateIoSpace, FreeIoSpace, RemoveIoSpace, GetIoSpaceDescriptor and GetIoSpaceMap), so that
+//! components such as PCI host bridge drivers can add the I/O windows they decode and allocate I/O BARs from them. A
+//! `mockall` mock is available for testing (`MockIoSpace`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, io_space::{IoAllocateType, IoSpace}};
+//! use patina_pi::dxe_services::GcdIoType;
+//! use r_efi::efi;
+//!
+//! fn add_root_bridge_window(io: Service<dyn IoSpace>, image_handle: efi::Handle) -> patina::error::Result<u64> {
+//!     io.add_io_space(GcdIoType::Io, 0x1000, 0xF000)?;
+//!     // allocate a 256 byte, 256 byte aligned BAR from the window.
+//!     io.allocate_io_space(IoAllocateType::BottomUp(None), GcdIoType::Io, 8, 0x100, image_handle, None)
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use patina_pi::dxe_services::{GcdIoType, IoSpaceDescriptor};
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Selects the range of I/O space that [IoSpace::allocate_io_space] allocates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoAllocateType {
+    /// Allocates the range starting at the given address.
+    Address(u64),
+    /// Allocates the lowest suitable range, optionally ending at or below the given address.
+    BottomUp(Option<u64>),
+    /// Allocates the highest suitable range, optionally at or below the given address.
+    TopDown(Option<u64>),
+}
+
+/// Manages the I/O space of the Global Coherency Domain.
+///
+/// Errors are reported as described for the corresponding DXE Services in the UEFI Platform Initialization
+/// Specification, Section II-7.2.4.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait IoSpace {
+    /// Adds `length` bytes of I/O space of type `io_type` at `base_address`. The range must not have been added before.
+    fn add_io_space(&self, io_type: GcdIoType, base_address: u64, length: u64) -> Result<(), EfiError>;
+
+    /// Allocates `length` bytes of I/O space of type `io_type` aligned to `2^align_shift` bytes, and returns its base
+    /// address.
+    ///
+    /// The allocation is owned by `image_handle`, and by `device_handle` if one is given.
+    fn allocate_io_space(
+        &self,
+        allocate_type: IoAllocateType,
+        io_type: GcdIoType,
+        align_shift: usize,
+        length: u64,
+        image_handle: efi::Handle,
+        device_handle: Option<efi::Handle>,
+    ) -> Result<u64, EfiError>;
+
+    /// Frees `length` bytes of I/O space at `base_address` that were allocated with [IoSpace::allocate_io_space].
+    fn free_io_space(&self, base_address: u64, length: u64) -> Result<(), EfiError>;
+
+    /// Removes `length` bytes of I/O space at `base_address` that were added with [IoSpace::add_io_space]. The range
+    /// must not be allocated.
+    fn remove_io_space(&self, base_address: u64, length: u64) -> Result<(), EfiError>;
+
+    /// Returns the descriptor of the I/O space range that contains `address`.
+    fn get_io_space_descriptor(&self, address: u64) -> Result<IoSpaceDescriptor, EfiError>;
+
+    /// Returns the descriptors of the whole I/O space, in increasing address order.
+    fn get_io_space_map(&self) -> Result<Vec<IoSpaceDescriptor>, EfiError>;
+}
@@ -282,6 +282,22 @@ pub trait MemoryManager {
     /// - `Err(MemoryError)` if the request failed for other reasons.
     ///
     fn get_page_attributes(&self, address: usize, page_count: usize) -> Result<(AccessType, CachingType), MemoryError>;
+
+    /// Gets the proximity domain of a page.
+    ///
+    /// Returns the proximity domain (NUMA node) that the platform assigned to
+    /// the memory at `address`, which matches the domain reported for it in
+    /// the ACPI SRAT.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(domain)` if the platform assigned the memory to a domain.
+    /// - `None` if the memory is not assigned to a domain, or the platform does
+    ///   not describe proximity domains.
+    ///
+    fn get_proximity_domain(&self, _address: usize) -> Option<u32> {
+        None
+    }
 }
 
 /// The `AllocationOptions` structure allows for the caller to  specify
@@ -296,6 +312,7 @@ pub struct AllocationOptions {
     allocation_strategy: PageAllocationStrategy,
     alignment: usize,
     memory_type: EfiMemoryType,
+    proximity_domain: Option<u32>,
 }
 
 impl Default for AllocationOptions {
@@ -314,6 +331,7 @@ impl AllocationOptions {
     /// - `allocation_strategy`: [`PageAllocationStrategy::Any`]
     /// - `alignment`: [`UEFI_PAGE_SIZE`]
     /// - `memory_type`: [`EfiMemoryType::BootServicesData`]
+    /// - `proximity_domain`: `None`
     ///
     #[inline(always)]
     pub const fn new() -> Self {
@@ -321,6 +339,7 @@ impl AllocationOptions {
             allocation_strategy: PageAllocationStrategy::Any,
             alignment: UEFI_PAGE_SIZE,
             memory_type: EfiMemoryType::BootServicesData,
+            proximity_domain: None,
         }
     }
 
@@ -350,6 +369,18 @@ impl AllocationOptions {
         self
     }
 
+    /// Requests that the allocation be placed in memory of the given proximity
+    /// domain (NUMA node), as reported in the ACPI SRAT. This is a hint: if no
+    /// memory of the domain is available, the allocation is made from any memory.
+    ///
+    /// The proximity domain will be ignored unless the allocation strategy is
+    /// [`PageAllocationStrategy::Any`].
+    #[inline(always)]
+    pub const fn with_proximity_domain(mut self, proximity_domain: u32) -> Self {
+        self.proximity_domain = Some(proximity_domain);
+        self
+    }
+
     /// Gets the strategy for the allocation.
     #[inline(always)]
     pub fn strategy(&self) -> PageAllocationStrategy {
@@ -367,6 +398,12 @@ impl AllocationOptions {
     pub fn memory_type(&self) -> EfiMemoryType {
         self.memory_type
     }
+
+    /// Gets the proximity domain requested for the allocation, if any.
+    #[inline(always)]
+    pub fn proximity_domain(&self) -> Option<u32> {
+        self.proximity_domain
+    }
 }
 
 #[repr(align(4096))]
@@ -0,0 +1,144 @@
+//! Memory Attribute Journal Service Definitions.
+//!
+//! The [MemoryAttributeJournal] service is produced by the core. When the platform enables the journal, the core
+//! records every change to the attributes of a GCD memory range: which image was running, when, the range, and the
+//! attributes before and after. The journal answers questions such as "who made this range executable?" during
+//! security reviews. A `mockall` mock is available for testing (`MockMemoryAttributeJournal`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, memory_attribute_journal::MemoryAttributeJournal};
+//!
+//! fn report_executable(journal: Service<dyn MemoryAttributeJournal>, base_address: u64, length: u64) {
+//!     for change in journal.changes_for(base_address, length).iter().filter(|change| change.made_executable()) {
+//!         log::info!(
+//!             "{:#x}-{:#x} made executable by {}",
+//!             change.base_address,
+//!             change.base_address + change.length,
+//!             change.image.as_deref().unwrap_or("the DXE Core")
+//!         );
+//!     }
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{string::String, vec::Vec};
+use r_efi::efi;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// A change to the attributes of a memory range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeChange {
+    /// The position of the change among all changes recorded since the journal was enabled, starting at 0.
+    pub sequence: u64,
+    /// The performance counter value when the change was made.
+    pub timestamp: u64,
+    /// The file name of the image that was running when the change was made, or `None` if the DXE Core made it.
+    pub image: Option<String>,
+    /// The base address of the range.
+    pub base_address: u64,
+    /// The length of the range in bytes.
+    pub length: u64,
+    /// The attributes of the range before the change.
+    pub old_attributes: u64,
+    /// The attributes of the range after the change.
+    pub new_attributes: u64,
+}
+
+impl AttributeChange {
+    /// Returns true if the change made a mapped range executable.
+    pub fn made_executable(&self) -> bool {
+        let not_executable = efi::MEMORY_XP | efi::MEMORY_RP;
+        self.old_attributes & not_executable != 0 && self.new_attributes & not_executable == 0
+    }
+
+    /// Returns true if the change made a mapped range writable.
+    pub fn made_writable(&self) -> bool {
+        let not_writable = efi::MEMORY_RO | efi::MEMORY_RP;
+        self.old_attributes & not_writable != 0 && self.new_attributes & not_writable == 0
+    }
+
+    /// Returns true if the changed range overlaps the `length` bytes at `base_address`.
+    pub fn overlaps(&self, base_address: u64, length: u64) -> bool {
+        self.base_address < base_address.saturating_add(length)
+            && base_address < self.base_address.saturating_add(self.length)
+    }
+}
+
+/// Reports the memory attribute changes recorded by the core.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MemoryAttributeJournal {
+    /// Returns the recorded changes, oldest first. Only the most recent changes are kept once the journal is full.
+    /// Empty if the platform has not enabled the journal.
+    fn attribute_changes(&self) -> Vec<AttributeChange>;
+
+    /// Returns the number of changes that were made but are no longer, or were never, in the journal.
+    fn dropped_changes(&self) -> u64;
+}
+
+impl dyn MemoryAttributeJournal + '_ {
+    /// Returns the recorded changes to ranges that overlap the `length` bytes at `base_address`, oldest first.
+    pub fn changes_for(&self, base_address: u64, length: u64) -> Vec<AttributeChange> {
+        let mut changes = self.attribute_changes();
+        changes.retain(|change| change.overlaps(base_address, length));
+        changes
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+
+    fn change(sequence: u64, base_address: u64, old_attributes: u64, new_attributes: u64) -> AttributeChange {
+        AttributeChange {
+            sequence,
+            timestamp: sequence * 100,
+            image: None,
+            base_address,
+            length: 0x2000,
+            old_attributes,
+            new_attributes,
+        }
+    }
+
+    #[test]
+    fn test_changes_for_filters_by_range() {
+        let mut journal = MockMemoryAttributeJournal::new();
+        journal.expect_attribute_changes().returning(|| {
+            vec![
+                change(0, 0x1000, efi::MEMORY_XP, efi::MEMORY_RO),
+                change(1, 0x4000, efi::MEMORY_RP, efi::MEMORY_XP),
+                change(2, 0x2000, efi::MEMORY_RO, efi::MEMORY_XP),
+            ]
+        });
+        let journal: &dyn MemoryAttributeJournal = &journal;
+
+        let changes = journal.changes_for(0x2800, 0x100);
+        assert_eq!(changes.iter().map(|change| change.sequence).collect::<std::vec::Vec<_>>(), [0, 2]);
+        assert!(journal.changes_for(0x3000, 0x1000).iter().all(|change| change.sequence == 2));
+        assert!(journal.changes_for(0x6000, 0x1000).is_empty());
+    }
+
+    #[test]
+    fn test_change_classification() {
+        assert!(change(0, 0, efi::MEMORY_XP, efi::MEMORY_RO).made_executable());
+        assert!(change(0, 0, efi::MEMORY_RP, efi::MEMORY_RO).made_executable());
+        assert!(!change(0, 0, efi::MEMORY_XP, efi::MEMORY_XP | efi::MEMORY_RO).made_executable());
+        assert!(!change(0, 0, 0, efi::MEMORY_XP).made_executable());
+
+        assert!(change(0, 0, efi::MEMORY_RO, efi::MEMORY_XP).made_writable());
+        assert!(!change(0, 0, efi::MEMORY_RO, efi::MEMORY_RO | efi::MEMORY_XP).made_writable());
+    }
+}
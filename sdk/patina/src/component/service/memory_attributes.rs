@@ -0,0 +1,119 @@
+//! Memory Attributes Service Definitions.
+//!
+//! The [MemoryAttributes] service is produced by the core and is the Rust counterpart of the
+//! [MemoryAttributesExtProtocol](crate::uefi_protocol::memory_attributes_ext::MemoryAttributesExtProtocol): it sets
+//! or clears access attributes (`EFI_MEMORY_RP`, `EFI_MEMORY_RO` and `EFI_MEMORY_XP`) on several ranges at once, and
+//! iterates over the attribute runs of a range. A `mockall` mock is available for testing (`MockMemoryAttributes`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, memory_attributes::{MemoryAttributes, MemoryRange}};
+//! use r_efi::efi;
+//!
+//! fn protect_and_report(attributes: Service<dyn MemoryAttributes>) -> patina::error::Result<()> {
+//!     let code = MemoryRange { base_address: 0x10_0000, length: 0x4000 };
+//!     let rodata = MemoryRange { base_address: 0x10_8000, length: 0x2000 };
+//!     attributes.set_memory_attributes(&[code, rodata], efi::MEMORY_RO)?;
+//!
+//!     for run in attributes.attribute_runs(0x10_0000, 0x10_000) {
+//!         let run = run?;
+//!         log::info!("{:#x}-{:#x}: {:#x}", run.base_address, run.base_address + run.length, run.attributes);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use crate::error::EfiError;
+
+pub use crate::uefi_protocol::memory_attributes_ext::{AttributeRun, MemoryRange};
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// Sets, clears and reports the access attributes of memory.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MemoryAttributes {
+    /// Sets `attributes` on each of the page aligned `ranges`. All ranges are validated before any attributes are
+    /// changed.
+    fn set_memory_attributes(&self, ranges: &[MemoryRange], attributes: u64) -> Result<(), EfiError>;
+
+    /// Clears `attributes` on each of the page aligned `ranges`. All ranges are validated before any attributes are
+    /// changed.
+    fn clear_memory_attributes(&self, ranges: &[MemoryRange], attributes: u64) -> Result<(), EfiError>;
+
+    /// Returns the run of memory with the same access attributes that starts at `base_address`, ending no later than
+    /// `end_address`.
+    fn attribute_run(&self, base_address: u64, end_address: u64) -> Result<AttributeRun, EfiError>;
+}
+
+impl dyn MemoryAttributes + '_ {
+    /// Returns an iterator over the attribute runs of the `length` bytes at `base_address`. The iterator ends after
+    /// the first error.
+    pub fn attribute_runs(&self, base_address: u64, length: u64) -> AttributeRuns<'_> {
+        AttributeRuns { service: self, next: base_address, end: base_address.saturating_add(length) }
+    }
+}
+
+/// An iterator over the attribute runs of a range, created by
+/// [attribute_runs](trait.MemoryAttributes.html#method.attribute_runs).
+pub struct AttributeRuns<'a> {
+    service: &'a dyn MemoryAttributes,
+    next: u64,
+    end: u64,
+}
+
+impl Iterator for AttributeRuns<'_> {
+    type Item = Result<AttributeRun, EfiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let run = self.service.attribute_run(self.next, self.end);
+        self.next = match run {
+            Ok(run) if run.length > 0 => run.base_address + run.length,
+            _ => self.end,
+        };
+        Some(run)
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_attribute_runs_walk_the_range() {
+        let mut attributes = MockMemoryAttributes::new();
+        attributes.expect_attribute_run().returning(|base_address, end_address| match base_address {
+            0x1000 => Ok(AttributeRun { base_address, length: 0x2000, attributes: r_efi::efi::MEMORY_XP }),
+            0x3000 => Ok(AttributeRun { base_address, length: end_address - base_address, attributes: 0 }),
+            _ => Err(EfiError::NoMapping),
+        });
+        let attributes: &dyn MemoryAttributes = &attributes;
+
+        let runs: Vec<_> = attributes.attribute_runs(0x1000, 0x4000).collect();
+        assert_eq!(
+            runs,
+            [
+                Ok(AttributeRun { base_address: 0x1000, length: 0x2000, attributes: r_efi::efi::MEMORY_XP }),
+                Ok(AttributeRun { base_address: 0x3000, length: 0x2000, attributes: 0 }),
+            ]
+        );
+
+        // the iterator ends after the first error.
+        let runs: Vec<_> = attributes.attribute_runs(0x8000, 0x4000).collect();
+        assert_eq!(runs, [Err(EfiError::NoMapping)]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Memory Map Service Definitions.
+//!
+//! The [MemoryMap] service is produced by the core and returns the UEFI memory map, as GetMemoryMap() would, as an
+//! owned [MemoryMapSnapshot]. Components no longer need to size a buffer, retry GetMemoryMap() when the map grew in
+//! between, and reinterpret the buffer as descriptors with unsafe code. The map key of the snapshot detects when the
+//! memory map changed since the snapshot was taken. A `mockall` mock is available for testing (`MockMemoryMap`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, memory_map::MemoryMap};
+//! use r_efi::efi;
+//!
+//! fn free_pages(memory_map: Service<dyn MemoryMap>) -> patina::error::Result<u64> {
+//!     let snapshot = memory_map.snapshot()?;
+//!     let free = snapshot.of_type(efi::CONVENTIONAL_MEMORY).map(|descriptor| descriptor.number_of_pages).sum();
+//!     // a later snapshot is only needed once the memory map changed.
+//!     assert!(!memory_map.has_changed_since(&snapshot));
+//!     Ok(free)
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::slice;
+
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// The descriptors of the UEFI memory map at one point in time, in increasing address order.
+#[derive(Debug, Clone)]
+pub struct MemoryMapSnapshot {
+    descriptors: Vec<efi::MemoryDescriptor>,
+    map_key: usize,
+}
+
+impl MemoryMapSnapshot {
+    /// Creates a snapshot of the memory map described by `descriptors`, identified by `map_key`.
+    pub fn new(descriptors: Vec<efi::MemoryDescriptor>, map_key: usize) -> Self {
+        Self { descriptors, map_key }
+    }
+
+    /// Returns the key of the memory map, as GetMemoryMap() returns it.
+    pub fn map_key(&self) -> usize {
+        self.map_key
+    }
+
+    /// Returns the descriptors of the memory map.
+    pub fn descriptors(&self) -> &[efi::MemoryDescriptor] {
+        &self.descriptors
+    }
+
+    /// Returns an iterator over the descriptors of the memory map.
+    pub fn iter(&self) -> slice::Iter<'_, efi::MemoryDescriptor> {
+        self.descriptors.iter()
+    }
+
+    /// Returns an iterator over the descriptors of the memory map of type `memory_type`.
+    pub fn of_type(&self, memory_type: efi::MemoryType) -> impl Iterator<Item = &efi::MemoryDescriptor> {
+        self.iter().filter(move |descriptor| descriptor.r#type == memory_type)
+    }
+
+    /// Returns the descriptor that contains `address`, if any.
+    pub fn descriptor_for_address(&self, address: efi::PhysicalAddress) -> Option<&efi::MemoryDescriptor> {
+        self.iter().find(|descriptor| {
+            let length = descriptor.number_of_pages.saturating_mul(crate::base::UEFI_PAGE_SIZE as u64);
+            (descriptor.physical_start..descriptor.physical_start.saturating_add(length)).contains(&address)
+        })
+    }
+}
+
+impl<'a> IntoIterator for &'a MemoryMapSnapshot {
+    type Item = &'a efi::MemoryDescriptor;
+    type IntoIter = slice::Iter<'a, efi::MemoryDescriptor>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Provides the UEFI memory map.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MemoryMap {
+    /// Returns the current memory map.
+    fn snapshot(&self) -> Result<MemoryMapSnapshot, EfiError>;
+
+    /// Returns the key of the current memory map, which changes whenever the memory map changes.
+    fn map_key(&self) -> Result<usize, EfiError>;
+
+    /// Returns true if the memory map changed since `snapshot` was taken, or if the current map key is unavailable.
+    fn has_changed_since(&self, snapshot: &MemoryMapSnapshot) -> bool {
+        self.map_key() != Ok(snapshot.map_key())
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn descriptor(r#type: efi::MemoryType, physical_start: u64, number_of_pages: u64) -> efi::MemoryDescriptor {
+        efi::MemoryDescriptor { r#type, physical_start, virtual_start: 0, number_of_pages, attribute: 0 }
+    }
+
+    #[test]
+    fn test_snapshot_lookups() {
+        let snapshot = MemoryMapSnapshot::new(
+            vec![
+                descriptor(efi::CONVENTIONAL_MEMORY, 0x0, 0x10),
+                descriptor(efi::BOOT_SERVICES_DATA, 0x10000, 0x4),
+                descriptor(efi::CONVENTIONAL_MEMORY, 0x20000, 0x20),
+            ],
+            0x1234,
+        );
+
+        assert_eq!(snapshot.map_key(), 0x1234);
+        assert_eq!(snapshot.of_type(efi::CONVENTIONAL_MEMORY).map(|d| d.number_of_pages).sum::<u64>(), 0x30);
+        assert_eq!((&snapshot).into_iter().count(), 3);
+        assert_eq!(snapshot.descriptor_for_address(0x13FFF).map(|d| d.physical_start), Some(0x10000));
+        assert!(snapshot.descriptor_for_address(0x14000).is_none());
+    }
+
+    #[test]
+    fn test_has_changed_since_compares_map_keys() {
+        let snapshot = MemoryMapSnapshot::new(vec![], 7);
+
+        struct Fixed(Result<usize, EfiError>);
+        impl MemoryMap for Fixed {
+            fn snapshot(&self) -> Result<MemoryMapSnapshot, EfiError> {
+                unimplemented!()
+            }
+            fn map_key(&self) -> Result<usize, EfiError> {
+                self.0
+            }
+        }
+
+        assert!(!Fixed(Ok(7)).has_changed_since(&snapshot));
+        assert!(Fixed(Ok(8)).has_changed_since(&snapshot));
+        assert!(Fixed(Err(EfiError::OutOfResources)).has_changed_since(&snapshot));
+    }
+}
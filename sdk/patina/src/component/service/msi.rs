@@ -0,0 +1,61 @@
+//! Message Signaled Interrupt (MSI) Service Definitions.
+//!
+//! PCIe devices signal MSI and MSI-X interrupts by writing a data value to a doorbell address. The [MsiController]
+//! service allocates these doorbells, routing each one to an interrupt source that can be registered and enabled with
+//! the Hardware Interrupt protocol, like any other interrupt.
+//!
+//! On AArch64 platforms that describe a GICv3/v4 Interrupt Translation Service (ITS), the service is produced by the
+//! core, with each vector backed by a Locality-specific Peripheral Interrupt (LPI). A `mockall` mock is available for
+//! testing (`MockMsiController`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, msi::MsiController};
+//!
+//! fn program_device(msi: Service<dyn MsiController>, requester_id: u32) -> patina::error::Result<()> {
+//!     let vector = msi.allocate_msi(requester_id)?;
+//!     // Write `vector.address` and `vector.data` to the device's MSI capability, then register a handler for
+//!     // `vector.interrupt_source` with the Hardware Interrupt protocol.
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// A doorbell allocated to a device for signaling an MSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiVector {
+    /// The identifier of the device the vector was allocated to, such as a PCIe requester ID.
+    pub device_id: u32,
+    /// The address the device writes to signal the interrupt.
+    pub address: u64,
+    /// The value the device writes to signal the interrupt.
+    pub data: u32,
+    /// The interrupt source that is raised when the device signals the interrupt.
+    pub interrupt_source: u64,
+}
+
+/// Allocates MSI doorbells for devices.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait MsiController {
+    /// Allocates a new vector for the device identified by `device_id`.
+    ///
+    /// Returns [EfiError::InvalidParameter] if the device identifier cannot be routed, and [EfiError::OutOfResources]
+    /// if no vectors remain for the device.
+    fn allocate_msi(&self, device_id: u32) -> Result<MsiVector, EfiError>;
+
+    /// Frees a vector previously returned by [MsiController::allocate_msi].
+    ///
+    /// The interrupt source must not be used after the vector is freed.
+    fn free_msi(&self, vector: MsiVector) -> Result<(), EfiError>;
+}
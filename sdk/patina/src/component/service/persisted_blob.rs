@@ -0,0 +1,388 @@
+//! Persisted Blob Service Definitions.
+//!
+//! Some hardware initialization is slow enough that platforms save its results and reapply them on the next boot:
+//! memory training data (the MRC cache), PCIe link equalization settings, and the like. The [PersistedBlob] service
+//! saves and loads such opaque blobs, each identified by a GUID, so that components do not each reinvent where and
+//! how they are stored. Two backends are provided:
+//!
+//! - [VariableBlobStore] stores each blob in non-volatile UEFI variables, split across several variables if the blob
+//!   is larger than the variable store allows. The core registers this backend if the platform does not register its
+//!   own [PersistedBlob] service.
+//! - [FlashBlobStore] stores each blob in a dedicated [FlashRegion], for blobs that are needed before the variable
+//!   services are available or that are too large for the variable store. Each region holds a header with the length
+//!   and CRC32 of the blob, which is written last, so that a blob that was not completely written is not loaded.
+//!
+//! A `mockall` mock is available for testing (`MockPersistedBlob`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::{component::service::{Service, persisted_blob::PersistedBlob}, error::Result};
+//! use r_efi::efi;
+//!
+//! const TRAINING_DATA: efi::Guid = efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]);
+//!
+//! fn restore_training_data(blobs: Service<dyn PersistedBlob>) -> Result<()> {
+//!     match blobs.load(&TRAINING_DATA) {
+//!         Ok(_data) => { /* reapply the saved training data. */ }
+//!         Err(_) => {
+//!             let data = [0u8; 16]; // train, and save the results.
+//!             blobs.save(&TRAINING_DATA, &data)?;
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+use r_efi::efi;
+
+use crate::{
+    component::{Storage, service::IntoService},
+    error::{EfiError, Result},
+    runtime_services::RuntimeServices,
+};
+
+/// Saves and loads opaque blobs that persist across boots.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait PersistedBlob {
+    /// Returns the blob saved as `id`, or [EfiError::NotFound] if there is none.
+    fn load(&self, id: &efi::Guid) -> Result<Vec<u8>>;
+
+    /// Saves `data` as `id`, replacing any blob previously saved as `id`.
+    fn save(&self, id: &efi::Guid, data: &[u8]) -> Result<()>;
+
+    /// Deletes the blob saved as `id`. Deleting a blob that does not exist succeeds.
+    fn delete(&self, id: &efi::Guid) -> Result<()>;
+}
+
+/// A [PersistedBlob] backend that stores blobs in non-volatile UEFI variables.
+///
+/// Each blob is stored in the namespace of its GUID, in variables named `Blob0000`, `Blob0001` and so on, each holding
+/// at most `chunk_size` bytes of the blob.
+pub struct VariableBlobStore<R: RuntimeServices + 'static> {
+    runtime_services: R,
+    chunk_size: usize,
+}
+
+impl<R: RuntimeServices + 'static> VariableBlobStore<R> {
+    /// The default largest number of bytes stored in a single variable, which fits within the maximum variable size of
+    /// common variable stores.
+    pub const DEFAULT_CHUNK_SIZE: usize = 0x4000;
+
+    const ATTRIBUTES: u32 = efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS;
+
+    /// Creates a backend that stores blobs through `runtime_services`.
+    pub const fn new(runtime_services: R) -> Self {
+        Self { runtime_services, chunk_size: Self::DEFAULT_CHUNK_SIZE }
+    }
+
+    /// Sets the largest number of bytes stored in a single variable.
+    pub const fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = if chunk_size == 0 { 1 } else { chunk_size };
+        self
+    }
+
+    // Returns the NUL terminated name of the variable holding chunk `index` of a blob.
+    fn chunk_name(index: usize) -> [u16; 9] {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+        let mut name = [0u16; 9];
+        for (dst, src) in name.iter_mut().zip(b"Blob") {
+            *dst = *src as u16;
+        }
+        for digit in 0..4 {
+            name[4 + digit] = HEX[(index >> (4 * (3 - digit))) & 0xF] as u16;
+        }
+        name
+    }
+
+    // Deletes chunks from `index` until one is not found.
+    fn delete_from(&self, id: &efi::Guid, mut index: usize) -> Result<()> {
+        loop {
+            match self.runtime_services.set_variable::<[u8; 0]>(&Self::chunk_name(index), id, Self::ATTRIBUTES, &[]) {
+                Ok(()) => index += 1,
+                Err(efi::Status::NOT_FOUND) => return Ok(()),
+                Err(status) => return Err(status.into()),
+            }
+        }
+    }
+}
+
+impl<R: RuntimeServices + 'static> PersistedBlob for VariableBlobStore<R> {
+    fn load(&self, id: &efi::Guid) -> Result<Vec<u8>> {
+        let mut blob = Vec::new();
+        for index in 0.. {
+            match self.runtime_services.get_variable::<Vec<u8>>(&Self::chunk_name(index), id, Some(self.chunk_size)) {
+                // a short chunk is the last one.
+                Ok((chunk, _)) if chunk.len() < self.chunk_size => {
+                    blob.extend_from_slice(&chunk);
+                    break;
+                }
+                Ok((chunk, _)) => blob.extend_from_slice(&chunk),
+                Err(efi::Status::NOT_FOUND) if index > 0 => break,
+                Err(status) => return Err(status.into()),
+            }
+        }
+        Ok(blob)
+    }
+
+    fn save(&self, id: &efi::Guid, data: &[u8]) -> Result<()> {
+        // an empty chunk marks the end of a blob whose length is a multiple of the chunk size, or an empty blob.
+        let chunks = data.len() / self.chunk_size + 1;
+        for index in 0..chunks {
+            let chunk = data[(index * self.chunk_size)..data.len().min((index + 1) * self.chunk_size)].to_vec();
+            self.runtime_services
+                .set_variable(&Self::chunk_name(index), id, Self::ATTRIBUTES, &chunk)
+                .map_err(EfiError::from)?;
+        }
+        // chunks of a previous, longer blob.
+        self.delete_from(id, chunks)
+    }
+
+    fn delete(&self, id: &efi::Guid) -> Result<()> {
+        self.delete_from(id, 0)
+    }
+}
+
+impl<R: RuntimeServices + 'static> IntoService for VariableBlobStore<R> {
+    fn register(self, storage: &mut Storage) {
+        let boxed: Box<dyn PersistedBlob> = Box::new(self);
+        Self::register_service::<dyn PersistedBlob>(storage, Box::leak(Box::new(boxed)));
+    }
+}
+
+/// A region of flash dedicated to storing a single blob.
+pub trait FlashRegion {
+    /// Returns the size of the region in bytes.
+    fn size(&self) -> usize;
+
+    /// Reads `buffer.len()` bytes from `offset` in the region into `buffer`.
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()>;
+
+    /// Erases the whole region.
+    fn erase(&self) -> Result<()>;
+
+    /// Writes `data` to `offset` in the erased region.
+    fn write(&self, offset: usize, data: &[u8]) -> Result<()>;
+}
+
+const FLASH_BLOB_SIGNATURE: u32 = u32::from_le_bytes(*b"PBLB");
+const FLASH_BLOB_HEADER_SIZE: usize = 16;
+
+/// A [PersistedBlob] backend that stores each blob in a dedicated [FlashRegion].
+///
+/// Blobs can only be saved as the GUIDs that a region was added for, and must fit in the region after a 16 byte
+/// header.
+pub struct FlashBlobStore<F: FlashRegion + 'static> {
+    regions: Vec<(efi::Guid, F)>,
+}
+
+impl<F: FlashRegion + 'static> FlashBlobStore<F> {
+    /// Creates a backend without any regions.
+    pub const fn new() -> Self {
+        Self { regions: Vec::new() }
+    }
+
+    /// Adds the region that the blob saved as `id` is stored in.
+    pub fn with_region(mut self, id: efi::Guid, region: F) -> Self {
+        self.regions.push((id, region));
+        self
+    }
+
+    fn region(&self, id: &efi::Guid) -> Result<&F> {
+        self.regions.iter().find(|(other, _)| other == id).map(|(_, region)| region).ok_or(EfiError::NotFound)
+    }
+
+    // Returns the header of a blob of `data`: the signature, length and CRC32 of the data, and a reserved field.
+    fn header(data: &[u8]) -> [u8; FLASH_BLOB_HEADER_SIZE] {
+        let mut header = [0u8; FLASH_BLOB_HEADER_SIZE];
+        header[0..4].copy_from_slice(&FLASH_BLOB_SIGNATURE.to_le_bytes());
+        header[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        header[8..12].copy_from_slice(&crc32(data).to_le_bytes());
+        header
+    }
+}
+
+impl<F: FlashRegion + 'static> Default for FlashBlobStore<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: FlashRegion + 'static> PersistedBlob for FlashBlobStore<F> {
+    fn load(&self, id: &efi::Guid) -> Result<Vec<u8>> {
+        let region = self.region(id)?;
+        let mut header = [0u8; FLASH_BLOB_HEADER_SIZE];
+        region.read(0, &mut header)?;
+        let field = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        // an erased or partially written region does not have a valid signature.
+        if field(0) != FLASH_BLOB_SIGNATURE {
+            return Err(EfiError::NotFound);
+        }
+        let length = field(4) as usize;
+        if length > region.size().saturating_sub(FLASH_BLOB_HEADER_SIZE) {
+            return Err(EfiError::VolumeCorrupted);
+        }
+        let mut data = alloc::vec![0u8; length];
+        region.read(FLASH_BLOB_HEADER_SIZE, &mut data)?;
+        if crc32(&data) != field(8) {
+            return Err(EfiError::CrcError);
+        }
+        Ok(data)
+    }
+
+    fn save(&self, id: &efi::Guid, data: &[u8]) -> Result<()> {
+        let region = self.region(id)?;
+        if data.len() > region.size().saturating_sub(FLASH_BLOB_HEADER_SIZE) || data.len() > u32::MAX as usize {
+            return Err(EfiError::BadBufferSize);
+        }
+        region.erase()?;
+        region.write(FLASH_BLOB_HEADER_SIZE, data)?;
+        // the header is written last, so that the blob is only found once it is completely written.
+        region.write(0, &Self::header(data))
+    }
+
+    fn delete(&self, id: &efi::Guid) -> Result<()> {
+        match self.region(id) {
+            Ok(region) => region.erase(),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl<F: FlashRegion + 'static> IntoService for FlashBlobStore<F> {
+    fn register(self, storage: &mut Storage) {
+        let boxed: Box<dyn PersistedBlob> = Box::new(self);
+        Self::register_service::<dyn PersistedBlob>(storage, Box::leak(Box::new(boxed)));
+    }
+}
+
+// The CRC32 (IEEE 802.3) of `data`, as used throughout UEFI.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 })
+    })
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use crate::runtime_services::MockRuntimeServices;
+    use core::cell::RefCell;
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    const ID: efi::Guid = efi::Guid::from_fields(0x1, 0x2, 0x3, 0x4, 0x5, &[0x6; 6]);
+
+    type Variables = Arc<Mutex<BTreeMap<std::vec::Vec<u16>, std::vec::Vec<u8>>>>;
+
+    fn variable_store(variables: &Variables) -> VariableBlobStore<MockRuntimeServices> {
+        let mut runtime_services = MockRuntimeServices::new();
+        let store = variables.clone();
+        runtime_services.expect_get_variable::<Vec<u8>>().returning(move |name, namespace, _| {
+            assert_eq!(*namespace, ID);
+            store.lock().unwrap().get(name).map(|data| (data.clone(), 0)).ok_or(efi::Status::NOT_FOUND)
+        });
+        let store = variables.clone();
+        runtime_services.expect_set_variable::<Vec<u8>>().returning(move |name, _, attributes, data| {
+            assert_eq!(attributes, efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS);
+            store.lock().unwrap().insert(name.to_vec(), data.clone());
+            Ok(())
+        });
+        let store = variables.clone();
+        runtime_services.expect_set_variable::<[u8; 0]>().returning(move |name, _, _, _| {
+            store.lock().unwrap().remove(name).map(|_| ()).ok_or(efi::Status::NOT_FOUND)
+        });
+        VariableBlobStore::new(runtime_services).with_chunk_size(4)
+    }
+
+    #[test]
+    fn test_chunk_names() {
+        let name: std::vec::Vec<u16> = "Blob00A1\0".encode_utf16().collect();
+        assert_eq!(VariableBlobStore::<MockRuntimeServices>::chunk_name(0xA1).as_slice(), name.as_slice());
+    }
+
+    #[test]
+    fn test_variable_blob_store() {
+        let variables = Variables::default();
+        let store = variable_store(&variables);
+        assert_eq!(store.load(&ID), Err(EfiError::NotFound));
+
+        store.save(&ID, b"0123456789").unwrap();
+        assert_eq!(variables.lock().unwrap().len(), 3);
+        assert_eq!(store.load(&ID).unwrap(), b"0123456789");
+
+        // a blob that fills its last chunk is terminated by an empty chunk.
+        store.save(&ID, b"0123").unwrap();
+        assert_eq!(variables.lock().unwrap().len(), 2);
+        assert_eq!(store.load(&ID).unwrap(), b"0123");
+
+        store.save(&ID, b"").unwrap();
+        assert_eq!(store.load(&ID).unwrap(), b"");
+
+        store.delete(&ID).unwrap();
+        assert!(variables.lock().unwrap().is_empty());
+        assert_eq!(store.load(&ID), Err(EfiError::NotFound));
+    }
+
+    struct TestRegion(RefCell<std::vec::Vec<u8>>);
+
+    impl FlashRegion for TestRegion {
+        fn size(&self) -> usize {
+            self.0.borrow().len()
+        }
+
+        fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+            buffer.copy_from_slice(&self.0.borrow()[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn erase(&self) -> Result<()> {
+            self.0.borrow_mut().fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&self, offset: usize, data: &[u8]) -> Result<()> {
+            self.0.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flash_blob_store() {
+        let store = FlashBlobStore::new().with_region(ID, TestRegion(RefCell::new(std::vec![0xFF; 32])));
+        assert_eq!(store.load(&ID), Err(EfiError::NotFound));
+        assert_eq!(store.load(&crate::guids::ZERO), Err(EfiError::NotFound));
+        assert_eq!(store.save(&crate::guids::ZERO, b"data"), Err(EfiError::NotFound));
+
+        store.save(&ID, b"training data").unwrap();
+        assert_eq!(store.load(&ID).unwrap(), b"training data");
+        assert_eq!(store.save(&ID, &[0u8; 17]), Err(EfiError::BadBufferSize));
+
+        // corrupted data is not loaded.
+        store.regions[0].1.0.borrow_mut()[20] ^= 1;
+        assert_eq!(store.load(&ID), Err(EfiError::CrcError));
+
+        store.delete(&ID).unwrap();
+        assert_eq!(store.load(&ID), Err(EfiError::NotFound));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,201 @@
+//! Arm Power State Coordination Interface (PSCI) Service Definitions.
+//!
+//! PSCI is the firmware interface used on AArch64 platforms to power CPUs on and off, and to shut down or reset the
+//! system. Calls are made through either the Secure Monitor Call (SMC) or Hypervisor Call (HVC) conduit, as described
+//! by the `method` property of the `/psci` node of the platform's device tree.
+//!
+//! The core produces the [Psci] service on AArch64 platforms that do not register their own, using the [PsciConduit]
+//! registered as configuration, so that reset, shutdown and multi-processor bring-up code does not need to issue PSCI
+//! calls directly. The core services `ResetSystem()` with it until a Reset Architectural Protocol driver is installed.
+//! MP Services are not part of the core, so [Psci::cpu_on] and [Psci::cpu_off] are for the platform component that
+//! brings up the other processors. A `mockall` mock is available for testing (`MockPsci`).
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::component::service::{Service, psci::Psci};
+//! use r_efi::efi;
+//!
+//! fn reset_component(psci: Service<dyn Psci>) -> patina::error::Result<()> {
+//!     // Only returns if the reset failed.
+//!     let err = patina::component::service::psci::reset_system(&**psci, efi::RESET_COLD);
+//!     Err(err.into())
+//! }
+//! ```
+//!
+//! See <https://developer.arm.com/documentation/den0022/latest/>
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// PSCI function identifiers, using the SMC64 calling convention where one exists.
+pub mod function_id {
+    /// Returns the version of PSCI implemented.
+    pub const PSCI_VERSION: u32 = 0x8400_0000;
+    /// Powers down the calling CPU.
+    pub const CPU_OFF: u32 = 0x8400_0002;
+    /// Powers up a CPU.
+    pub const CPU_ON: u32 = 0xC400_0003;
+    /// Shuts down the system.
+    pub const SYSTEM_OFF: u32 = 0x8400_0008;
+    /// Resets the system.
+    pub const SYSTEM_RESET: u32 = 0x8400_0009;
+    /// Queries whether a PSCI function is implemented.
+    pub const PSCI_FEATURES: u32 = 0x8400_000A;
+}
+
+/// The conduit used to make PSCI calls.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PsciConduit {
+    /// Secure Monitor Call, handled by the secure monitor at EL3.
+    #[default]
+    Smc,
+    /// Hypervisor Call, handled by the hypervisor at EL2.
+    Hvc,
+}
+
+impl PsciConduit {
+    /// Returns the conduit described by the `method` property of a device tree `/psci` node, if it is valid.
+    pub fn from_fdt_method(method: &str) -> Option<Self> {
+        match method.trim_end_matches('\0') {
+            "smc" => Some(Self::Smc),
+            "hvc" => Some(Self::Hvc),
+            _ => None,
+        }
+    }
+}
+
+/// An error returned by a PSCI call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsciError {
+    /// The function is not implemented.
+    NotSupported,
+    /// A parameter was invalid.
+    InvalidParameters,
+    /// The call was denied.
+    Denied,
+    /// The target CPU is already on.
+    AlreadyOn,
+    /// A CPU_ON call for the target CPU is already pending.
+    OnPending,
+    /// The firmware failed to complete the call.
+    InternalFailure,
+    /// The target CPU is not present.
+    NotPresent,
+    /// The target CPU is disabled.
+    Disabled,
+    /// The entry point address is invalid.
+    InvalidAddress,
+    /// The call returned a value not defined by the specification.
+    Unknown(i32),
+}
+
+impl PsciError {
+    /// Converts the return value of a PSCI call into a result.
+    pub fn from_return_value(value: i32) -> Result<(), PsciError> {
+        match value {
+            0 => Ok(()),
+            -1 => Err(Self::NotSupported),
+            -2 => Err(Self::InvalidParameters),
+            -3 => Err(Self::Denied),
+            -4 => Err(Self::AlreadyOn),
+            -5 => Err(Self::OnPending),
+            -6 => Err(Self::InternalFailure),
+            -7 => Err(Self::NotPresent),
+            -8 => Err(Self::Disabled),
+            -9 => Err(Self::InvalidAddress),
+            other => Err(Self::Unknown(other)),
+        }
+    }
+}
+
+impl From<PsciError> for EfiError {
+    fn from(err: PsciError) -> Self {
+        match err {
+            PsciError::NotSupported => EfiError::Unsupported,
+            PsciError::InvalidParameters | PsciError::InvalidAddress => EfiError::InvalidParameter,
+            PsciError::Denied => EfiError::AccessDenied,
+            PsciError::AlreadyOn => EfiError::AlreadyStarted,
+            PsciError::OnPending => EfiError::NotReady,
+            PsciError::NotPresent | PsciError::Disabled => EfiError::NotFound,
+            PsciError::InternalFailure | PsciError::Unknown(_) => EfiError::DeviceError,
+        }
+    }
+}
+
+/// The PSCI operations used by firmware.
+///
+/// Functions that power down the calling CPU or the system only return if the call failed.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+pub trait Psci {
+    /// Returns the PSCI version as `(major, minor)`.
+    fn version(&self) -> (u16, u16);
+
+    /// Returns whether the PSCI function identified by `function_id` is implemented.
+    fn is_supported(&self, function_id: u32) -> bool;
+
+    /// Powers up the CPU identified by `target_mpidr`, which starts executing at `entry_point` with `context_id` in
+    /// `x0`.
+    fn cpu_on(&self, target_mpidr: u64, entry_point: u64, context_id: u64) -> Result<(), PsciError>;
+
+    /// Powers down the calling CPU.
+    fn cpu_off(&self) -> PsciError;
+
+    /// Shuts down the system.
+    fn system_off(&self) -> PsciError;
+
+    /// Resets the system.
+    fn system_reset(&self) -> PsciError;
+}
+
+/// Services a UEFI `ResetSystem()` request with PSCI, only returning if the reset failed.
+///
+/// `EfiResetShutdown` is serviced with `SYSTEM_OFF`, and all other reset types with `SYSTEM_RESET`.
+pub fn reset_system(psci: &dyn Psci, reset_type: efi::ResetType) -> PsciError {
+    match reset_type {
+        efi::RESET_SHUTDOWN => psci.system_off(),
+        _ => psci.system_reset(),
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conduit_from_fdt_method() {
+        assert_eq!(PsciConduit::from_fdt_method("smc"), Some(PsciConduit::Smc));
+        assert_eq!(PsciConduit::from_fdt_method("hvc\0"), Some(PsciConduit::Hvc));
+        assert_eq!(PsciConduit::from_fdt_method("svc"), None);
+    }
+
+    #[test]
+    fn test_return_values_map_to_errors() {
+        assert_eq!(PsciError::from_return_value(0), Ok(()));
+        assert_eq!(PsciError::from_return_value(-4), Err(PsciError::AlreadyOn));
+        assert_eq!(PsciError::from_return_value(-42), Err(PsciError::Unknown(-42)));
+        assert_eq!(EfiError::from(PsciError::Denied), EfiError::AccessDenied);
+    }
+
+    #[test]
+    fn test_reset_system_selects_function() {
+        let mut psci = MockPsci::new();
+        psci.expect_system_off().once().return_const(PsciError::Denied);
+        psci.expect_system_reset().times(2).return_const(PsciError::InternalFailure);
+
+        assert_eq!(reset_system(&psci, efi::RESET_SHUTDOWN), PsciError::Denied);
+        assert_eq!(reset_system(&psci, efi::RESET_COLD), PsciError::InternalFailure);
+        assert_eq!(reset_system(&psci, efi::RESET_WARM), PsciError::InternalFailure);
+    }
+}
@@ -15,7 +15,11 @@ use crate::{
 
 use crate::OwnedGuid;
 use crate::boot_services::StandardBootServices;
-use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
 use core::{
     any::{Any, TypeId},
     cell::{Ref, RefCell, RefMut, UnsafeCell},
@@ -27,7 +31,9 @@ use core::{
 
 use super::{
     hob::{FromHob, Hob},
-    service::{IntoService, Service},
+    lifecycle::{LifecycleHooks, LifecycleStage},
+    message_bus::MessageBus,
+    service::{ApSafe, IntoService, Service, ServiceReplaced},
 };
 
 type HobParsers = BTreeMap<OwnedGuid, BTreeMap<TypeId, fn(&[u8], &mut Storage)>>;
@@ -205,12 +211,26 @@ pub struct Storage {
     services: SparseVec<&'static dyn Any>,
     /// A map to convert a Service type to a concrete service index.
     service_indices: BTreeMap<TypeId, usize>,
+    /// A map to convert a service index to the name of its Service type.
+    service_names: BTreeMap<usize, &'static str>,
+    /// A map to convert a service index to the version of the registered service.
+    service_versions: BTreeMap<usize, u32>,
+    /// The version of the service currently being registered.
+    registering_version: u32,
+    /// The indices of the registered services that are safe to call from an application processor.
+    ap_safe_services: BTreeSet<usize>,
+    /// Whether the service currently being registered is safe to call from an application processor.
+    registering_ap_safe: bool,
     /// HOB parsers for converting guided HOBs into `Hob<T>` datums.
     hob_parsers: HobParsers,
     /// A container for all [Hob](super::hob::Hob) datums.
     hobs: SparseVec<Vec<Box<dyn Any>>>,
     /// a map to convert from TypeId to a hob index.
     hob_indices: BTreeMap<TypeId, usize>,
+    /// Hooks to run when the core enters a lifecycle stage.
+    lifecycle: LifecycleHooks,
+    /// Publish/subscribe channels for component events.
+    message_bus: MessageBus,
     // Standard Boot Services.
     boot_services: StandardBootServices,
     // Standard Runtime Services.
@@ -232,9 +252,16 @@ impl Storage {
             config_indices: BTreeMap::new(),
             services: SparseVec::new(),
             service_indices: BTreeMap::new(),
+            service_names: BTreeMap::new(),
+            service_versions: BTreeMap::new(),
+            registering_version: 0,
+            ap_safe_services: BTreeSet::new(),
+            registering_ap_safe: false,
             hob_parsers: BTreeMap::new(),
             hobs: SparseVec::new(),
             hob_indices: BTreeMap::new(),
+            lifecycle: LifecycleHooks::new(),
+            message_bus: MessageBus::new(),
             boot_services: StandardBootServices::new_uninit(),
             runtime_services: StandardRuntimeServices::new_uninit(),
         }
@@ -341,9 +368,51 @@ impl Storage {
         (&self.configs).into_iter().flatten().for_each(|config| config.borrow_mut().lock());
     }
 
+    /// Registers a hook to run once when the core enters `stage`.
+    ///
+    /// If the core has already entered `stage`, the hook runs immediately.
+    pub fn add_lifecycle_hook<F: FnOnce(&mut Storage) + 'static>(&mut self, stage: LifecycleStage, hook: F) {
+        self.lifecycle.add(stage, Box::new(hook));
+        if self.lifecycle.stage().is_some_and(|current| current >= stage) {
+            self.enter_lifecycle_stage(stage);
+        }
+    }
+
+    /// Enters `stage`, running any hooks registered for it, or for an earlier stage, in registration order.
+    ///
+    /// Deferred changes are applied first, so that hooks registered by a component through
+    /// [Commands](super::params::Commands) are run.
+    pub fn enter_lifecycle_stage(&mut self, stage: LifecycleStage) {
+        self.apply_deferred();
+        for hook in self.lifecycle.enter(stage) {
+            hook(self);
+        }
+    }
+
+    /// Returns the most recent lifecycle stage entered by the core, if any.
+    pub fn lifecycle_stage(&self) -> Option<LifecycleStage> {
+        self.lifecycle.stage()
+    }
+
+    /// Subscribes to events of type `E` on the message bus.
+    ///
+    /// If an event of type `E` has already been published, the subscriber is immediately called with the most recent
+    /// one. See [message_bus](super::message_bus) for more information.
+    pub fn subscribe<E: 'static, F: Fn(&E) + 'static>(&mut self, subscriber: F) {
+        self.message_bus.subscribe(subscriber);
+    }
+
+    /// Publishes an event to all subscribers of type `E` on the message bus, returning the number of subscribers
+    /// called.
+    pub fn publish<E: 'static>(&mut self, event: E) -> usize {
+        self.message_bus.publish(event)
+    }
+
     /// Registers a service type with the storage and returns its global id.
     pub(crate) fn register_service<C: ?Sized + 'static>(&mut self) -> usize {
-        self.get_or_register_service(TypeId::of::<C>())
+        let id = self.get_or_register_service(TypeId::of::<C>());
+        self.service_names.entry(id).or_insert(core::any::type_name::<C>());
+        id
     }
 
     /// Gets the global id of a service, registering it if it does not exist.
@@ -352,14 +421,72 @@ impl Storage {
         *self.service_indices.entry(id).or_insert(idx)
     }
 
-    /// Inserts a service into the storage.
-    pub(crate) fn insert_service(&mut self, id: usize, service: &'static dyn Any) {
+    /// Inserts a service into the storage, unless a service with a greater version is already registered.
+    ///
+    /// If an existing service is replaced, a [ServiceReplaced] event is published on the message bus.
+    pub(crate) fn insert_service<S: ?Sized + 'static>(&mut self, id: usize, service: &'static dyn Any) {
+        let version = self.registering_version;
+        let replacing = self.services.contains(id);
+        if replacing && self.service_versions.get(&id).is_some_and(|current| *current > version) {
+            log::info!(
+                "Service {} version {version} not registered, version {} is already registered.",
+                core::any::type_name::<S>(),
+                self.service_versions[&id]
+            );
+            return;
+        }
+
         self.services.insert(id, service);
+        self.service_versions.insert(id, version);
+        if self.registering_ap_safe {
+            self.ap_safe_services.insert(id);
+        } else {
+            self.ap_safe_services.remove(&id);
+        }
+        if replacing {
+            log::info!("Service {} replaced with version {version}.", core::any::type_name::<S>());
+            self.publish(ServiceReplaced::<S> { service: Service::from(service), version });
+        }
     }
 
     /// Adds a new service to the storage.
+    ///
+    /// The service is registered with version `0`, replacing any existing service of version `0`.
     pub fn add_service<S: IntoService + 'static>(&mut self, service: S) {
+        self.add_versioned_service(service, 0);
+    }
+
+    /// Adds a new service to the storage with the given version.
+    ///
+    /// If a service is already registered, it is only replaced if `version` is greater than or equal to the version of
+    /// the registered service. Consumers that already hold the replaced service are notified with a [ServiceReplaced]
+    /// event on the message bus.
+    pub fn add_versioned_service<S: IntoService + 'static>(&mut self, service: S, version: u32) {
+        self.registering_version = version;
         service.register(self);
+        self.registering_version = 0;
+    }
+
+    /// Adds a new service to the storage, marking each service it registers as safe to call from an application
+    /// processor.
+    ///
+    /// Components whose services are all AP safe may be dispatched on an application processor. The service is
+    /// registered with version `0`, as with [Storage::add_service].
+    pub fn add_ap_safe_service<S: IntoService + ApSafe + 'static>(&mut self, service: S) {
+        self.registering_ap_safe = true;
+        self.add_service(service);
+        self.registering_ap_safe = false;
+    }
+
+    /// Returns whether the registered service denoted by `id` is safe to call from an application processor.
+    pub fn is_ap_safe_service(&self, id: usize) -> bool {
+        self.ap_safe_services.contains(&id)
+    }
+
+    /// Returns the version of the registered service, if any.
+    pub fn service_version<S: ?Sized + 'static>(&self) -> Option<u32> {
+        let idx = *self.service_indices.get(&TypeId::of::<S>())?;
+        self.services.contains(idx).then(|| self.service_versions.get(&idx).copied().unwrap_or_default())
     }
 
     /// Retrieves a service from the underlying storage in its untyped form.
@@ -368,6 +495,11 @@ impl Storage {
         self.services.get(id).copied()
     }
 
+    /// Returns the type names of all services that have been added to the storage.
+    pub fn service_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.service_names.iter().filter(|(id, _)| self.services.contains(**id)).map(|(_, name)| *name)
+    }
+
     /// Attempts to retrieve a service from the storage.
     pub fn get_service<S: ?Sized + 'static>(&self) -> Option<Service<S>> {
         let idx = *self.service_indices.get(&TypeId::of::<S>())?;
@@ -659,6 +791,110 @@ mod tests {
         assert_eq!(service.test(), 42);
     }
 
+    #[test]
+    fn test_service_names_only_lists_added_services() {
+        use crate as patina;
+        trait TestService {}
+        trait MissingService {}
+
+        #[derive(IntoService)]
+        #[service(dyn TestService)]
+        struct TestServiceImpl;
+
+        impl TestService for TestServiceImpl {}
+
+        let mut storage = Storage::new();
+        storage.register_service::<dyn MissingService>();
+        storage.add_service(TestServiceImpl);
+
+        let names: Vec<_> = storage.service_names().collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].ends_with("TestService"));
+    }
+
+    #[test]
+    fn test_ap_safe_services_are_tracked_by_registration() {
+        use crate as patina;
+        trait TestService {}
+
+        #[derive(IntoService)]
+        #[service(dyn TestService)]
+        struct TestServiceImpl;
+
+        impl TestService for TestServiceImpl {}
+
+        // SAFETY: the service has no methods.
+        unsafe impl ApSafe for TestServiceImpl {}
+
+        let mut storage = Storage::new();
+        let id = storage.register_service::<dyn TestService>();
+        assert!(!storage.is_ap_safe_service(id));
+
+        storage.add_ap_safe_service(TestServiceImpl);
+        assert!(storage.is_ap_safe_service(id));
+
+        // a replacement that is not AP safe clears the mark.
+        storage.add_service(TestServiceImpl);
+        assert!(!storage.is_ap_safe_service(id));
+    }
+
+    #[test]
+    fn test_lifecycle_hooks_run_once_in_stage_order() {
+        use core::cell::RefCell;
+        use std::rc::Rc;
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut storage = Storage::new();
+        for (stage, name) in [
+            (LifecycleStage::Locked, "locked"),
+            (LifecycleStage::PreDispatch, "pre"),
+            (LifecycleStage::PostFirstPass, "post"),
+        ] {
+            let order = order.clone();
+            storage.add_lifecycle_hook(stage, move |_| order.borrow_mut().push(name));
+        }
+        assert!(order.borrow().is_empty());
+        assert_eq!(storage.lifecycle_stage(), None);
+
+        storage.enter_lifecycle_stage(LifecycleStage::PreDispatch);
+        assert_eq!(*order.borrow(), ["pre"]);
+
+        // Entering a later stage runs the hooks for it, and a hook for an entered stage runs immediately.
+        storage.enter_lifecycle_stage(LifecycleStage::PostFirstPass);
+        storage.enter_lifecycle_stage(LifecycleStage::PostFirstPass);
+        let late = order.clone();
+        storage.add_lifecycle_hook(LifecycleStage::PreDispatch, move |_| late.borrow_mut().push("late"));
+        assert_eq!(*order.borrow(), ["pre", "post", "late"]);
+
+        storage.enter_lifecycle_stage(LifecycleStage::Locked);
+        assert_eq!(*order.borrow(), ["pre", "post", "late", "locked"]);
+        assert_eq!(storage.lifecycle_stage(), Some(LifecycleStage::Locked));
+    }
+
+    #[test]
+    fn test_message_bus_delivers_events_by_type() {
+        use core::cell::RefCell;
+        use std::rc::Rc;
+
+        struct ConsoleReady;
+        struct ThermalTrip(u32);
+
+        let trips = Rc::new(RefCell::new(Vec::new()));
+        let mut storage = Storage::new();
+        assert_eq!(storage.publish(ThermalTrip(0)), 0);
+
+        // Subscribers receive the most recent event when they subscribe.
+        let early = trips.clone();
+        storage.subscribe(move |trip: &ThermalTrip| early.borrow_mut().push(trip.0));
+        assert_eq!(*trips.borrow(), [0]);
+
+        let late = trips.clone();
+        storage.subscribe(move |trip: &ThermalTrip| late.borrow_mut().push(trip.0 + 100));
+        assert_eq!(storage.publish(ThermalTrip(1)), 2);
+        assert_eq!(storage.publish(ConsoleReady), 0);
+        assert_eq!(*trips.borrow(), [0, 100, 1, 101]);
+    }
+
     #[test]
     fn test_apply_deferred_storage() {
         use crate as patina;
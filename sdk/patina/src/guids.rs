@@ -98,6 +98,16 @@ pub const HARDWARE_INTERRUPT_PROTOCOL: efi::Guid =
 pub const HARDWARE_INTERRUPT_PROTOCOL_V2: efi::Guid =
     efi::Guid::from_fields(0x32898322, 0x2da1, 0x474a, 0xba, 0xaa, &[0xf3, 0xf7, 0xcf, 0x56, 0x94, 0x70]);
 
+/// HOB List configuration table GUID.
+///
+/// (`7739F24C-93D7-11D4-9A3A-0090273FC14D`)
+/// ```
+/// # use patina::{Guid, guids::HOB_LIST};
+/// # assert_eq!("7739F24C-93D7-11D4-9A3A-0090273FC14D", format!("{:?}", Guid::from_ref(&HOB_LIST)));
+/// ```
+pub const HOB_LIST: efi::Guid =
+    efi::Guid::from_fields(0x7739F24C, 0x93D7, 0x11D4, 0x9A, 0x3A, &[0x00, 0x90, 0x27, 0x3F, 0xC1, 0x4D]);
+
 /// Memory Type Info GUID
 ///
 /// The memory type information HOB and variable can be used to store information
@@ -0,0 +1,452 @@
+//! HII Package Lists
+//!
+//! Images carry their forms, strings, fonts and images as an HII package list in the `HII` resource of their PE
+//! resource section. The core installs the package list on the image handle as the HII Package List protocol; this
+//! module provides a typed, bounds checked view of it, so that the package list can be inspected without unsafe pointer
+//! arithmetic (UEFI Specification, Section 33.3.1).
+//!
+//! [PackageList::parse] validates the package list header and the header of every package once, after which
+//! [PackageList::packages] enumerates the packages. String packages can be looked up by language, and their strings
+//! by string ID.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::hii::{PackageList, PackageType};
+//!
+//! fn print_strings(package_list: &[u8]) -> patina::error::Result<()> {
+//!     let package_list = PackageList::parse(package_list)?;
+//!     for package in package_list.packages().filter(|package| package.package_type() == PackageType::Strings) {
+//!         log::info!("string package of {} bytes", package.data().len());
+//!     }
+//!     if let Some(title) = package_list.string("en-US", 1) {
+//!         log::info!("title: {title}");
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{string::String, vec::Vec};
+
+use r_efi::efi;
+
+use crate::error::EfiError;
+
+/// The size of `EFI_HII_PACKAGE_LIST_HEADER`.
+const PACKAGE_LIST_HEADER_SIZE: usize = 20;
+/// The size of `EFI_HII_PACKAGE_HEADER`.
+const PACKAGE_HEADER_SIZE: usize = 4;
+/// `EFI_HII_PACKAGE_END`, the type of the package that terminates a package list.
+const PACKAGE_END: u8 = 0xDF;
+/// The offset of the language in `EFI_HII_STRING_PACKAGE_HDR`.
+const STRING_PACKAGE_LANGUAGE_OFFSET: usize = 46;
+
+// String information block types (UEFI Specification, Section 33.3.6.2).
+const SIBT_END: u8 = 0x00;
+const SIBT_STRING_SCSU: u8 = 0x10;
+const SIBT_STRING_UCS2: u8 = 0x14;
+const SIBT_STRINGS_UCS2_FONT: u8 = 0x17;
+const SIBT_DUPLICATE: u8 = 0x20;
+const SIBT_SKIP2: u8 = 0x21;
+const SIBT_SKIP1: u8 = 0x22;
+const SIBT_EXT1: u8 = 0x30;
+const SIBT_EXT2: u8 = 0x31;
+const SIBT_EXT4: u8 = 0x32;
+
+/// The type of an HII package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageType {
+    /// A package whose format is defined by a GUID.
+    Guid,
+    /// Forms (IFR).
+    Forms,
+    /// Strings of a single language.
+    Strings,
+    /// Fonts.
+    Fonts,
+    /// Images.
+    Images,
+    /// Simple fonts.
+    SimpleFonts,
+    /// The device path of the driver that produced the package list.
+    DevicePath,
+    /// Keyboard layouts.
+    KeyboardLayout,
+    /// Animations.
+    Animations,
+    /// A system specific package type, from 0xE0 to 0xFF.
+    System(u8),
+    /// A package type that is not defined by the UEFI Specification.
+    Unknown(u8),
+}
+
+impl From<u8> for PackageType {
+    fn from(package_type: u8) -> Self {
+        match package_type {
+            efi::hii::PACKAGE_TYPE_GUID => PackageType::Guid,
+            efi::hii::PACKAGE_FORMS => PackageType::Forms,
+            efi::hii::PACKAGE_STRINGS => PackageType::Strings,
+            efi::hii::PACKAGE_FONTS => PackageType::Fonts,
+            efi::hii::PACKAGE_IMAGES => PackageType::Images,
+            efi::hii::PACKAGE_SIMPLE_FONTS => PackageType::SimpleFonts,
+            efi::hii::PACKAGE_DEVICE_PATH => PackageType::DevicePath,
+            efi::hii::PACKAGE_KEYBOARD_LAYOUT => PackageType::KeyboardLayout,
+            efi::hii::PACKAGE_ANIMATIONS => PackageType::Animations,
+            efi::hii::PACKAGE_TYPE_SYSTEM_BEGIN..=efi::hii::PACKAGE_TYPE_SYSTEM_END => {
+                PackageType::System(package_type)
+            }
+            _ => PackageType::Unknown(package_type),
+        }
+    }
+}
+
+/// A validated HII package list.
+#[derive(Debug, Clone, Copy)]
+pub struct PackageList<'a> {
+    guid: efi::Guid,
+    /// The packages, without the package list header and the end package.
+    packages: &'a [u8],
+}
+
+impl<'a> PackageList<'a> {
+    /// Parses the package list at the start of `data`.
+    ///
+    /// Returns [EfiError::InvalidParameter] if the package list header, or the header of any of its packages, is out of
+    /// bounds, or if the package list does not end with an end package.
+    pub fn parse(data: &'a [u8]) -> Result<Self, EfiError> {
+        let header = data.get(..PACKAGE_LIST_HEADER_SIZE).ok_or(EfiError::InvalidParameter)?;
+        let guid = efi::Guid::from_bytes(header[..16].try_into().unwrap());
+        let length = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        let data = data.get(PACKAGE_LIST_HEADER_SIZE..length).ok_or(EfiError::InvalidParameter)?;
+
+        let mut offset = 0;
+        loop {
+            let (package_type, package_length) = package_header(&data[offset..]).ok_or(EfiError::InvalidParameter)?;
+            if package_type == PACKAGE_END {
+                return Ok(Self { guid, packages: &data[..offset] });
+            }
+            offset += package_length;
+        }
+    }
+
+    /// Parses the package list at `header`, such as the interface of the HII Package List protocol of an image.
+    ///
+    /// ## Safety
+    ///
+    /// `header` must point to a package list header that is followed by the rest of the package list, as given by its
+    /// length, and the package list must not be modified for the lifetime `'a`.
+    pub unsafe fn from_raw(header: *const efi::hii::PackageListHeader) -> Result<Self, EfiError> {
+        if header.is_null() {
+            return Err(EfiError::InvalidParameter);
+        }
+        // SAFETY: The caller guarantees that the header is valid, and that it is followed by the package list.
+        let length = unsafe { header.read_unaligned() }.package_length as usize;
+        // SAFETY: As above.
+        Self::parse(unsafe { core::slice::from_raw_parts(header as *const u8, length) })
+    }
+
+    /// Returns the GUID of the package list.
+    pub fn guid(&self) -> efi::Guid {
+        self.guid
+    }
+
+    /// Returns an iterator over the packages of the package list, without the end package.
+    pub fn packages(&self) -> Packages<'a> {
+        Packages { remaining: self.packages }
+    }
+
+    /// Returns an iterator over the string packages of the package list that can be parsed.
+    pub fn string_packages(&self) -> impl Iterator<Item = StringPackage<'a>> + use<'a> {
+        self.packages().filter_map(|package| StringPackage::parse(package).ok())
+    }
+
+    /// Returns the string `id` of the string package for `language`, e.g. `en-US`.
+    pub fn string(&self, language: &str, id: u16) -> Option<String> {
+        self.string_packages().find(|package| package.language().eq_ignore_ascii_case(language))?.string(id)
+    }
+}
+
+/// Returns the type and length of the package at the start of `data`, if it fits in `data`.
+fn package_header(data: &[u8]) -> Option<(u8, usize)> {
+    let header = data.get(..PACKAGE_HEADER_SIZE)?;
+    let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    (PACKAGE_HEADER_SIZE..=data.len()).contains(&length).then_some((header[3], length))
+}
+
+/// An iterator over the packages of a [PackageList].
+#[derive(Debug, Clone)]
+pub struct Packages<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for Packages<'a> {
+    type Item = Package<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The package headers were validated when the package list was parsed.
+        let (package_type, length) = package_header(self.remaining)?;
+        let (package, remaining) = self.remaining.split_at(length);
+        self.remaining = remaining;
+        Some(Package { package_type: package_type.into(), bytes: package })
+    }
+}
+
+/// A package of a [PackageList].
+#[derive(Debug, Clone, Copy)]
+pub struct Package<'a> {
+    package_type: PackageType,
+    /// The package, including its header.
+    bytes: &'a [u8],
+}
+
+impl<'a> Package<'a> {
+    /// Returns the type of the package.
+    pub fn package_type(&self) -> PackageType {
+        self.package_type
+    }
+
+    /// Returns the data of the package that follows the package header.
+    pub fn data(&self) -> &'a [u8] {
+        &self.bytes[PACKAGE_HEADER_SIZE..]
+    }
+}
+
+/// A validated string package.
+#[derive(Debug, Clone, Copy)]
+pub struct StringPackage<'a> {
+    language: &'a str,
+    /// The string information blocks.
+    blocks: &'a [u8],
+}
+
+impl<'a> StringPackage<'a> {
+    /// Parses a package of type [PackageType::Strings].
+    ///
+    /// Returns [EfiError::InvalidParameter] if the package is not a string package, or if its header is malformed.
+    pub fn parse(package: Package<'a>) -> Result<Self, EfiError> {
+        if package.package_type != PackageType::Strings {
+            return Err(EfiError::InvalidParameter);
+        }
+        let bytes = package.bytes;
+        let header_size = read_u32(bytes, 4).ok_or(EfiError::InvalidParameter)? as usize;
+        let blocks_offset = read_u32(bytes, 8).ok_or(EfiError::InvalidParameter)? as usize;
+        let language = bytes.get(STRING_PACKAGE_LANGUAGE_OFFSET..header_size).ok_or(EfiError::InvalidParameter)?;
+        let language = language.split(|&byte| byte == 0).next().unwrap_or_default();
+        Ok(Self {
+            language: core::str::from_utf8(language).map_err(|_| EfiError::InvalidParameter)?,
+            blocks: bytes.get(blocks_offset..).ok_or(EfiError::InvalidParameter)?,
+        })
+    }
+
+    /// Returns the language of the strings, as an RFC 4646 language code such as `en-US`.
+    pub fn language(&self) -> &'a str {
+        self.language
+    }
+
+    /// Returns the string `id`, or `None` if the package does not define it.
+    ///
+    /// String IDs start at 1. SCSU encoded strings are only decoded if they are plain ASCII.
+    pub fn string(&self, id: u16) -> Option<String> {
+        let mut current: u16 = 1;
+        let mut offset = 0;
+        loop {
+            let block_type = *self.blocks.get(offset)?;
+            let block_start = offset;
+            offset += 1;
+            match block_type {
+                SIBT_END => return None,
+                SIBT_STRING_SCSU..=SIBT_STRINGS_UCS2_FONT => {
+                    let ucs2 = block_type >= SIBT_STRING_UCS2;
+                    // odd block types have a font identifier, and the second of each pair holds several strings.
+                    if block_type & 0x1 != 0 {
+                        offset += 1;
+                    }
+                    let count = if block_type & 0x2 != 0 {
+                        offset += 2;
+                        read_u16(self.blocks, offset - 2)?
+                    } else {
+                        1
+                    };
+                    for _ in 0..count {
+                        let string = self.blocks.get(offset..)?;
+                        let length = encoded_length(string, ucs2)?;
+                        if current == id {
+                            return decode(&string[..length], ucs2);
+                        }
+                        current = current.checked_add(1)?;
+                        offset += length;
+                    }
+                }
+                SIBT_DUPLICATE => {
+                    let duplicate = read_u16(self.blocks, offset)?;
+                    // only earlier strings can be duplicated, which also rules out cycles.
+                    if current == id {
+                        return if duplicate < id { self.string(duplicate) } else { None };
+                    }
+                    current = current.checked_add(1)?;
+                    offset += 2;
+                }
+                SIBT_SKIP2 => {
+                    current = current.checked_add(read_u16(self.blocks, offset)?)?;
+                    offset += 2;
+                }
+                SIBT_SKIP1 => {
+                    current = current.checked_add(u16::from(*self.blocks.get(offset)?))?;
+                    offset += 1;
+                }
+                SIBT_EXT1 | SIBT_EXT2 | SIBT_EXT4 => {
+                    // extended blocks hold no strings, and their length includes the block header.
+                    let length = match block_type {
+                        SIBT_EXT1 => usize::from(*self.blocks.get(offset + 1)?),
+                        SIBT_EXT2 => usize::from(read_u16(self.blocks, offset + 1)?),
+                        _ => read_u32(self.blocks, offset + 1)? as usize,
+                    };
+                    offset = block_start.checked_add(length).filter(|&next| next > block_start)?;
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Returns the length in bytes of the null terminated string at the start of `data`, including the terminator.
+fn encoded_length(data: &[u8], ucs2: bool) -> Option<usize> {
+    match ucs2 {
+        true => data.chunks_exact(2).position(|unit| unit == [0, 0]).map(|units| (units + 1) * 2),
+        false => data.iter().position(|&byte| byte == 0).map(|bytes| bytes + 1),
+    }
+}
+
+/// Decodes the null terminated string `data`.
+fn decode(data: &[u8], ucs2: bool) -> Option<String> {
+    match ucs2 {
+        true => {
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            String::from_utf16(&units).ok()
+        }
+        false => {
+            let bytes = &data[..data.len() - 1];
+            bytes.iter().all(|byte| byte.is_ascii_graphic() || matches!(byte, b' ' | b'\t' | b'\n' | b'\r')).then(
+                || {
+                    // SCSU encodes printable ASCII as itself.
+                    bytes.iter().map(|&byte| byte as char).collect()
+                },
+            )
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const LIST_GUID: efi::Guid = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+
+    fn package(package_type: u8, data: &[u8]) -> Vec<u8> {
+        let length = (PACKAGE_HEADER_SIZE + data.len()) as u32;
+        let mut package = length.to_le_bytes()[..3].to_vec();
+        package.push(package_type);
+        package.extend_from_slice(data);
+        package
+    }
+
+    fn package_list(packages: &[Vec<u8>]) -> Vec<u8> {
+        let packages: Vec<u8> = packages.iter().flatten().copied().chain(package(PACKAGE_END, &[])).collect();
+        let mut list = LIST_GUID.as_bytes().to_vec();
+        list.extend_from_slice(&((PACKAGE_LIST_HEADER_SIZE + packages.len()) as u32).to_le_bytes());
+        list.extend(packages);
+        list
+    }
+
+    fn ucs2(string: &str) -> Vec<u8> {
+        string.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn string_package(language: &str, blocks: &[u8]) -> Vec<u8> {
+        let header_size = STRING_PACKAGE_LANGUAGE_OFFSET + language.len() + 1;
+        let mut data = (header_size as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&(header_size as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 34]);
+        data.extend(language.bytes().chain([0]));
+        data.extend_from_slice(blocks);
+        package(efi::hii::PACKAGE_STRINGS, &data)
+    }
+
+    #[test]
+    fn test_packages_are_enumerated_up_to_the_end_package() {
+        let list = package_list(&[package(efi::hii::PACKAGE_FORMS, &[1, 2, 3]), package(0xE5, &[])]);
+        let package_list = PackageList::parse(&list).unwrap();
+
+        assert_eq!(package_list.guid(), LIST_GUID);
+        let packages: Vec<_> = package_list.packages().map(|p| (p.package_type(), p.data().to_vec())).collect();
+        assert_eq!(packages, [(PackageType::Forms, vec![1, 2, 3]), (PackageType::System(0xE5), vec![])]);
+
+        let parsed = unsafe { PackageList::from_raw(list.as_ptr() as *const efi::hii::PackageListHeader) };
+        assert_eq!(parsed.unwrap().packages().count(), 2);
+    }
+
+    #[test]
+    fn test_malformed_package_lists_are_rejected() {
+        let list = package_list(&[package(efi::hii::PACKAGE_FORMS, &[1, 2, 3])]);
+        // the package list is truncated.
+        assert_eq!(PackageList::parse(&list[..list.len() - 1]).err(), Some(EfiError::InvalidParameter));
+
+        // the package overruns the package list.
+        let mut overrun = list.clone();
+        overrun[PACKAGE_LIST_HEADER_SIZE] = 0xFF;
+        assert_eq!(PackageList::parse(&overrun).err(), Some(EfiError::InvalidParameter));
+
+        // the end package is missing.
+        let mut missing_end = list[..list.len() - PACKAGE_HEADER_SIZE].to_vec();
+        let length = missing_end.len() as u32;
+        missing_end[16..20].copy_from_slice(&length.to_le_bytes());
+        assert_eq!(PackageList::parse(&missing_end).err(), Some(EfiError::InvalidParameter));
+    }
+
+    #[test]
+    fn test_strings_are_looked_up_by_language_and_id() {
+        let mut blocks = vec![SIBT_STRING_UCS2];
+        blocks.extend(ucs2("Setup"));
+        blocks.extend([SIBT_SKIP1, 2, SIBT_STRINGS_UCS2_FONT, 0, 2, 0]);
+        blocks.extend(ucs2("Boot"));
+        blocks.extend(ucs2("Exit \u{2192}"));
+        blocks.extend([SIBT_EXT1, 0x40, 4, 0, SIBT_DUPLICATE, 1, 0, SIBT_STRING_SCSU]);
+        blocks.extend(b"Save\0");
+        blocks.push(SIBT_END);
+        let list = package_list(&[string_package("en-US", &blocks), string_package("fr-FR", &[SIBT_END])]);
+        let package_list = PackageList::parse(&list).unwrap();
+
+        let languages: Vec<_> = package_list.string_packages().map(|package| package.language()).collect();
+        assert_eq!(languages, ["en-US", "fr-FR"]);
+        assert_eq!(package_list.string("en-us", 1).as_deref(), Some("Setup"));
+        // strings 2 and 3 are skipped.
+        assert_eq!(package_list.string("en-US", 2), None);
+        assert_eq!(package_list.string("en-US", 4).as_deref(), Some("Boot"));
+        assert_eq!(package_list.string("en-US", 5).as_deref(), Some("Exit \u{2192}"));
+        assert_eq!(package_list.string("en-US", 6).as_deref(), Some("Setup"));
+        assert_eq!(package_list.string("en-US", 7).as_deref(), Some("Save"));
+        assert_eq!(package_list.string("en-US", 8), None);
+        assert_eq!(package_list.string("fr-FR", 1), None);
+        assert_eq!(package_list.string("de-DE", 1), None);
+    }
+}
@@ -40,9 +40,11 @@ pub mod driver_binding;
 pub mod efi_types;
 pub mod error;
 pub mod guids;
+pub mod hii;
 pub mod log;
 pub mod performance;
 pub mod runtime_services;
+pub mod secret;
 pub mod serial;
 #[coverage(off)]
 pub mod test;
@@ -47,6 +47,9 @@ pub enum Format {
     Json,
     /// Verbose JSON blob containing the log level, message, target, and file path and line number.
     VerboseJson,
+    /// EDK II `DEBUG()` compatible text, tagged with the matching EDK II error level and the module that logged it,
+    /// e.g. `[DEBUG_INFO] [patina_dxe_core] message`. Intended for tooling that scrapes EDK II logs.
+    Edk2,
 }
 
 impl Format {
@@ -94,6 +97,52 @@ impl Format {
                 )
                 .expect("Printing to serial failed");
             }
+            Format::Edk2 => {
+                writeln!(target, "[{}] [{}] {}", edk2_error_level(record.level()), edk2_module(record), record.args())
+                    .expect("Printing to serial failed");
+            }
         }
     }
 }
+
+/// Returns the name of the EDK II error level that corresponds to the log level.
+fn edk2_error_level(level: log::Level) -> &'static str {
+    match level {
+        log::Level::Error => "DEBUG_ERROR",
+        log::Level::Warn => "DEBUG_WARN",
+        log::Level::Info => "DEBUG_INFO",
+        log::Level::Debug | log::Level::Trace => "DEBUG_VERBOSE",
+    }
+}
+
+/// Returns the module that logged the record, which is the crate portion of the record's target.
+fn edk2_module<'a>(record: &'a log::Record) -> &'a str {
+    let target = record.target();
+    target.split("::").next().filter(|module| !module.is_empty()).unwrap_or("unknown")
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::string::String;
+
+    fn format(format: Format, level: log::Level, target: &str) -> String {
+        let mut out = String::new();
+        format.write(&mut out, &log::Record::builder().args(format_args!("hello")).level(level).target(target).build());
+        out
+    }
+
+    #[test]
+    fn test_edk2_format() {
+        assert_eq!(
+            format(Format::Edk2, log::Level::Info, "patina_dxe_core::dispatcher"),
+            "[DEBUG_INFO] [patina_dxe_core] hello\n"
+        );
+        assert_eq!(format(Format::Edk2, log::Level::Error, "driver"), "[DEBUG_ERROR] [driver] hello\n");
+        assert_eq!(format(Format::Edk2, log::Level::Warn, "driver"), "[DEBUG_WARN] [driver] hello\n");
+        assert_eq!(format(Format::Edk2, log::Level::Debug, "driver"), "[DEBUG_VERBOSE] [driver] hello\n");
+        assert_eq!(format(Format::Edk2, log::Level::Trace, ""), "[DEBUG_VERBOSE] [unknown] hello\n");
+    }
+}
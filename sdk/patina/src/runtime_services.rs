@@ -60,6 +60,15 @@ impl StandardRuntimeServices {
         !self.efi_runtime_services.load(Ordering::Relaxed).is_null()
     }
 
+    /// Returns a raw pointer to the underlying [efi::RuntimeServices] table.
+    ///
+    /// Intended for components that produce a runtime service (e.g. the variable services) and must install their
+    /// implementation in the table. Writing through the pointer is only sound before ExitBootServices, and must be
+    /// followed by installing the corresponding architectural protocol so that the table checksum is recalculated.
+    pub fn as_mut_ptr(&self) -> *mut efi::RuntimeServices {
+        self.efi_runtime_services.load(Ordering::Relaxed)
+    }
+
     fn efi_runtime_services(&self) -> &efi::RuntimeServices {
         // SAFETY: Runtime services lifetime is expected to live long enough.
         unsafe { self.efi_runtime_services.load(Ordering::Relaxed).as_ref() }
@@ -254,6 +263,17 @@ pub trait RuntimeServices {
     ///
     fn query_variable_info(&self, attributes: u32) -> Result<VariableInfo, efi::Status>;
 
+    /// Converts a pointer from physical to virtual addressing.
+    ///
+    /// UEFI Spec Documentation: [8.4.2. EFI_RUNTIME_SERVICES.ConvertPointer()](https://uefi.org/specs/UEFI/2.10/08_Services_Runtime_Services.html#convertpointer)
+    ///
+    /// # Safety
+    ///
+    /// Must only be called from an `EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE` notify function, and `address` must point
+    /// into memory that is mapped at runtime. The physical address is no longer valid once the conversion is done.
+    ///
+    unsafe fn convert_pointer(&self, address: &mut *mut c_void) -> Result<(), efi::Status>;
+
     /// Set's a UEFI variable
     ///
     /// # Safety
@@ -434,6 +454,18 @@ impl RuntimeServices for StandardRuntimeServices {
 
         if status.is_error() { Err(status) } else { Ok(var_info) }
     }
+
+    unsafe fn convert_pointer(&self, address: &mut *mut c_void) -> Result<(), efi::Status> {
+        let convert_pointer = self.efi_runtime_services().convert_pointer;
+        if convert_pointer as usize == 0 {
+            debug_assert!(false, "ConvertPointer has not initialized in the Runtime Services Table.");
+            return Err(efi::Status::NOT_FOUND);
+        }
+
+        let status = convert_pointer(0, address as *mut *mut c_void);
+
+        if status.is_error() { Err(status) } else { Ok(()) }
+    }
 }
 
 #[cfg(test)]
@@ -677,6 +709,29 @@ pub(crate) mod test {
         efi::Status::SUCCESS
     }
 
+    pub const DUMMY_VIRTUAL_OFFSET: usize = 0xFFFF_8000_0000_0000;
+
+    /// Mocks ConvertPointer() from UEFI spec
+    ///
+    /// Converts the address by adding DUMMY_VIRTUAL_OFFSET. A null address can be passed in to test a failed
+    /// conversion.
+    ///
+    pub extern "efiapi" fn mock_efi_convert_pointer(
+        debug_disposition: usize,
+        address: *mut *mut c_void,
+    ) -> efi::Status {
+        assert_eq!(debug_disposition, 0);
+
+        unsafe {
+            if (*address).is_null() {
+                return efi::Status::NOT_FOUND;
+            }
+            *address = (*address).wrapping_byte_add(DUMMY_VIRTUAL_OFFSET);
+        }
+
+        efi::Status::SUCCESS
+    }
+
     #[test]
     fn test_debug_print_works_before_init() {
         let rs: StandardRuntimeServices = StandardRuntimeServices::new_uninit();
@@ -865,4 +920,16 @@ pub(crate) mod test {
         assert!(status.is_err());
         assert_eq!(status.unwrap_err(), efi::Status::INVALID_PARAMETER);
     }
+
+    #[test]
+    fn test_convert_pointer() {
+        let rs = runtime_services!(convert_pointer = mock_efi_convert_pointer);
+
+        let mut address = 0x1000 as *mut c_void;
+        assert!(unsafe { rs.convert_pointer(&mut address) }.is_ok());
+        assert_eq!(address as usize, 0x1000 + DUMMY_VIRTUAL_OFFSET);
+
+        let mut address = ptr::null_mut();
+        assert_eq!(unsafe { rs.convert_pointer(&mut address) }, Err(efi::Status::NOT_FOUND));
+    }
 }
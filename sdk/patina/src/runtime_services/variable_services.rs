@@ -6,6 +6,8 @@ use r_efi::efi::{self, Guid};
 
 use super::RuntimeServices;
 
+pub mod authenticated;
+
 /// Status information returned by [`RuntimeServices::get_variable_unchecked`]
 #[derive(Debug)]
 pub enum GetVariableStatus {
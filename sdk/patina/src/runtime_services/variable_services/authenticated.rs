@@ -0,0 +1,674 @@
+//! Authenticated UEFI variable support.
+//!
+//! Provides parsing and validation of the authentication descriptors that prefix the data of a SetVariable() request
+//! for authenticated variables:
+//!
+//! - `EFI_VARIABLE_AUTHENTICATION_2` for variables with the
+//!   [`VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS`](efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS)
+//!   attribute.
+//! - `EFI_VARIABLE_AUTHENTICATION_3` for variables with the
+//!   [`VARIABLE_ENHANCED_AUTHENTICATED_ACCESS`](efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS) attribute, using either
+//!   the timestamp or the nonce authentication type.
+//!
+//! Signature verification is not performed in this module. Instead, the signed payload is reconstructed per the
+//! UEFI specification and handed to a platform provided [`AuthVariableVerifier`] together with the trust anchors that
+//! are allowed to authorize the write. This allows a variable store implementation to manage the Secure Boot key
+//! variables (`PK`, `KEK`, `db`, `dbx`) without this crate taking a dependency on a specific crypto library.
+//!
+//! UEFI Spec Documentation: [8.2.1. Using the EFI_VARIABLE_AUTHENTICATION_3 descriptor](https://uefi.org/specs/UEFI/2.10/08_Services_Runtime_Services.html#using-the-efi-variable-authentication-3-descriptor)
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use r_efi::efi;
+
+use crate::error::{EfiError, Result};
+
+#[cfg(any(test, feature = "mockall"))]
+use mockall::automock;
+
+/// `EFI_CERT_TYPE_PKCS7_GUID`, the only certificate type accepted in an authentication descriptor.
+///
+/// (`4AAFD29D-68DF-49EE-8AA9-347D375665A7`)
+pub const CERT_TYPE_PKCS7_GUID: efi::Guid =
+    efi::Guid::from_fields(0x4aafd29d, 0x68df, 0x49ee, 0x8a, 0xa9, &[0x34, 0x7d, 0x37, 0x56, 0x65, 0xa7]);
+
+/// `EFI_CERT_X509_GUID`, the signature type of an `EFI_SIGNATURE_LIST` containing DER encoded X.509 certificates.
+///
+/// (`A5C059A1-94E4-4AA7-87B5-AB155C2BF072`)
+pub const CERT_X509_GUID: efi::Guid =
+    efi::Guid::from_fields(0xa5c059a1, 0x94e4, 0x4aa7, 0x87, 0xb5, &[0xab, 0x15, 0x5c, 0x2b, 0xf0, 0x72]);
+
+/// `EFI_GLOBAL_VARIABLE`, the namespace of the `PK` and `KEK` variables.
+///
+/// (`8BE4DF61-93CA-11D2-AA0D-00E098032B8C`)
+pub const GLOBAL_VARIABLE_GUID: efi::Guid =
+    efi::Guid::from_fields(0x8be4df61, 0x93ca, 0x11d2, 0xaa, 0x0d, &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]);
+
+/// `EFI_IMAGE_SECURITY_DATABASE_GUID`, the namespace of the `db`, `dbx`, `dbt` and `dbr` variables.
+///
+/// (`D719B2CB-3D3A-4596-A3BC-DAD00E67656F`)
+pub const IMAGE_SECURITY_DATABASE_GUID: efi::Guid =
+    efi::Guid::from_fields(0xd719b2cb, 0x3d3a, 0x4596, 0xa3, 0xbc, &[0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f]);
+
+/// `WIN_CERT_TYPE_EFI_GUID`, the certificate type of a `WIN_CERTIFICATE_UEFI_GUID`.
+pub const WIN_CERT_TYPE_EFI_GUID: u16 = 0x0EF1;
+
+/// The `WIN_CERTIFICATE` revision required in an authentication descriptor.
+pub const WIN_CERT_REVISION: u16 = 0x0200;
+
+/// `EFI_VARIABLE_AUTHENTICATION_3_TIMESTAMP_TYPE`
+pub const AUTHENTICATION_3_TIMESTAMP_TYPE: u8 = 1;
+
+/// `EFI_VARIABLE_AUTHENTICATION_3_NONCE_TYPE`
+pub const AUTHENTICATION_3_NONCE_TYPE: u8 = 2;
+
+/// `EFI_VARIABLE_ENHANCED_AUTH_FLAG_UPDATE_CERT`, set when the descriptor carries a new signing certificate.
+pub const ENHANCED_AUTH_FLAG_UPDATE_CERT: u32 = 0x0000_0001;
+
+/// The only `EFI_VARIABLE_AUTHENTICATION_3` version defined by the specification.
+pub const AUTHENTICATION_3_VERSION: u8 = 1;
+
+// EFI_TIME (16) + WIN_CERTIFICATE (8) + CertType (16).
+const AUTHENTICATION_2_HEADER_SIZE: usize = size_of::<efi::Time>() + WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE;
+// Version (1) + Type (1) + MetadataSize (4) + Flags (4).
+const AUTHENTICATION_3_HEADER_SIZE: usize = 10;
+// dwLength (4) + wRevision (2) + wCertificateType (2) + CertType (16).
+const WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE: usize = 24;
+// SignatureType (16) + SignatureListSize (4) + SignatureHeaderSize (4) + SignatureSize (4).
+const SIGNATURE_LIST_HEADER_SIZE: usize = 28;
+
+/// A `WIN_CERTIFICATE_UEFI_GUID` whose certificate type has been validated as PKCS7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pkcs7Certificate<'a> {
+    /// The DER encoded PKCS7 SignedData structure.
+    pub cert_data: &'a [u8],
+}
+
+/// The timestamp of a time based authenticated write.
+///
+/// Only the fields of `EFI_TIME` that are allowed to be non-zero in an authentication descriptor are kept, in an
+/// order such that the derived [`Ord`] implementation is chronological.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    /// The year (1900 - 9999).
+    pub year: u16,
+    /// The month (1 - 12).
+    pub month: u8,
+    /// The day (1 - 31).
+    pub day: u8,
+    /// The hour (0 - 23).
+    pub hour: u8,
+    /// The minute (0 - 59).
+    pub minute: u8,
+    /// The second (0 - 59).
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// Parses an `EFI_TIME` from the start of `data`.
+    ///
+    /// The `Pad1`, `Nanosecond`, `TimeZone`, `Daylight` and `Pad2` fields must be zero in an authentication descriptor,
+    /// otherwise [`EfiError::SecurityViolation`] is returned.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let bytes = data.get(..size_of::<efi::Time>()).ok_or(EfiError::SecurityViolation)?;
+        if bytes[7..].iter().any(|&b| b != 0) {
+            log::error!("Authenticated variable write rejected: timestamp has non-zero reserved fields.");
+            return Err(EfiError::SecurityViolation);
+        }
+        Ok(Self {
+            year: u16::from_le_bytes([bytes[0], bytes[1]]),
+            month: bytes[2],
+            day: bytes[3],
+            hour: bytes[4],
+            minute: bytes[5],
+            second: bytes[6],
+        })
+    }
+
+    /// Returns the `EFI_TIME` byte representation of the timestamp.
+    pub fn to_bytes(&self) -> [u8; size_of::<efi::Time>()] {
+        let mut bytes = [0u8; size_of::<efi::Time>()];
+        bytes[0..2].copy_from_slice(&self.year.to_le_bytes());
+        bytes[2..7].copy_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+        bytes
+    }
+}
+
+/// The freshness mechanism of an authenticated write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthenticationKind<'a> {
+    /// Time based authentication, using the timestamp from the descriptor.
+    TimeBased(Timestamp),
+    /// Nonce based authentication, using the nonce from the descriptor.
+    NonceBased(&'a [u8]),
+}
+
+/// A parsed `EFI_VARIABLE_AUTHENTICATION_2` or `EFI_VARIABLE_AUTHENTICATION_3` descriptor and the variable data that
+/// follows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticatedData<'a> {
+    /// The freshness mechanism of the write.
+    pub kind: AuthenticationKind<'a>,
+    /// The certificate that should replace the signing certificate of the variable, if any.
+    pub new_cert: Option<Pkcs7Certificate<'a>>,
+    /// The PKCS7 signature over the payload returned by [`AuthenticatedData::signed_payload`].
+    pub signing_cert: Pkcs7Certificate<'a>,
+    /// The variable data, without the authentication descriptor.
+    pub data: &'a [u8],
+}
+
+impl<'a> AuthenticatedData<'a> {
+    /// Parses the authentication descriptor from the data of a SetVariable() request.
+    ///
+    /// The descriptor type is selected from the attributes of the request. Returns
+    /// [`EfiError::InvalidParameter`] if the attributes do not describe an authenticated variable.
+    pub fn parse(attributes: u32, data: &'a [u8]) -> Result<Self> {
+        let time_based = attributes & efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0;
+        let enhanced = attributes & efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS != 0;
+        match (time_based, enhanced) {
+            (true, false) => Self::parse_authentication_2(data),
+            (false, true) => Self::parse_authentication_3(data),
+            _ => Err(EfiError::InvalidParameter),
+        }
+    }
+
+    /// Parses an `EFI_VARIABLE_AUTHENTICATION_2` descriptor and the variable data that follows it.
+    pub fn parse_authentication_2(data: &'a [u8]) -> Result<Self> {
+        if data.len() < AUTHENTICATION_2_HEADER_SIZE {
+            return Err(EfiError::SecurityViolation);
+        }
+        let timestamp = Timestamp::parse(data)?;
+        let (signing_cert, rest) = read_win_certificate(&data[size_of::<efi::Time>()..])?;
+        Ok(Self { kind: AuthenticationKind::TimeBased(timestamp), new_cert: None, signing_cert, data: rest })
+    }
+
+    /// Parses an `EFI_VARIABLE_AUTHENTICATION_3` descriptor and the variable data that follows it.
+    pub fn parse_authentication_3(data: &'a [u8]) -> Result<Self> {
+        if data.len() < AUTHENTICATION_3_HEADER_SIZE {
+            return Err(EfiError::SecurityViolation);
+        }
+        let version = data[0];
+        let auth_type = data[1];
+        let metadata_size = read_u32(data, 2)? as usize;
+        let flags = read_u32(data, 6)?;
+
+        if version != AUTHENTICATION_3_VERSION || flags & !ENHANCED_AUTH_FLAG_UPDATE_CERT != 0 {
+            return Err(EfiError::InvalidParameter);
+        }
+        if metadata_size < AUTHENTICATION_3_HEADER_SIZE || metadata_size > data.len() {
+            return Err(EfiError::SecurityViolation);
+        }
+
+        let metadata = &data[AUTHENTICATION_3_HEADER_SIZE..metadata_size];
+        let (kind, metadata) = match auth_type {
+            AUTHENTICATION_3_TIMESTAMP_TYPE => {
+                let timestamp = Timestamp::parse(metadata)?;
+                (AuthenticationKind::TimeBased(timestamp), &metadata[size_of::<efi::Time>()..])
+            }
+            AUTHENTICATION_3_NONCE_TYPE => {
+                let nonce_size = read_u32(metadata, 0)? as usize;
+                let nonce = metadata.get(4..4 + nonce_size).ok_or(EfiError::SecurityViolation)?;
+                if nonce.is_empty() {
+                    return Err(EfiError::InvalidParameter);
+                }
+                (AuthenticationKind::NonceBased(nonce), &metadata[4 + nonce_size..])
+            }
+            _ => return Err(EfiError::InvalidParameter),
+        };
+
+        let (new_cert, metadata) = if flags & ENHANCED_AUTH_FLAG_UPDATE_CERT != 0 {
+            let (cert, rest) = read_win_certificate(metadata)?;
+            (Some(cert), rest)
+        } else {
+            (None, metadata)
+        };
+
+        let (signing_cert, metadata) = read_win_certificate(metadata)?;
+        if !metadata.is_empty() {
+            return Err(EfiError::SecurityViolation);
+        }
+
+        Ok(Self { kind, new_cert, signing_cert, data: &data[metadata_size..] })
+    }
+
+    /// Returns the serialized payload covered by the signature of the descriptor.
+    ///
+    /// The payload is the concatenation of the variable name (without the null terminator), the vendor GUID, the
+    /// attributes, the timestamp or nonce, the new certificate (if present) and the variable data.
+    pub fn signed_payload(&self, name: &[u16], namespace: &efi::Guid, attributes: u32) -> Vec<u8> {
+        let name = name.strip_suffix(&[0]).unwrap_or(name);
+        let mut payload = Vec::with_capacity(name.len() * 2 + size_of::<efi::Guid>() + 4 + self.data.len());
+        name.iter().for_each(|c| payload.extend_from_slice(&c.to_le_bytes()));
+        payload.extend_from_slice(namespace.as_bytes());
+        payload.extend_from_slice(&attributes.to_le_bytes());
+        match self.kind {
+            AuthenticationKind::TimeBased(timestamp) => payload.extend_from_slice(&timestamp.to_bytes()),
+            AuthenticationKind::NonceBased(nonce) => payload.extend_from_slice(nonce),
+        }
+        if let Some(new_cert) = self.new_cert {
+            payload.extend_from_slice(new_cert.cert_data);
+        }
+        payload.extend_from_slice(self.data);
+        payload
+    }
+}
+
+/// The authentication state persisted alongside an authenticated variable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthenticationState {
+    /// The timestamp of the most recent write of a time based authenticated variable.
+    pub timestamp: Option<Timestamp>,
+    /// The nonce of the most recent write of a nonce based authenticated variable.
+    pub nonce: Option<Vec<u8>>,
+    /// The certificate that must sign further writes of an `EFI_VARIABLE_AUTHENTICATION_3` variable.
+    pub signing_cert: Option<Vec<u8>>,
+}
+
+/// Verifies PKCS7 signatures of authenticated variable writes.
+///
+/// A platform produces this service with the crypto library of its choice. The service is expected to validate the
+/// certificate chain of the signer up to one of the provided trust anchors.
+#[cfg_attr(any(test, feature = "mockall"), automock)]
+#[allow(clippy::needless_lifetimes)] //https://github.com/rust-lang/rust-clippy/issues/6622
+pub trait AuthVariableVerifier {
+    /// Verifies that `signature` is a valid PKCS7 SignedData over `payload`, signed by a certificate that chains to
+    /// one of `trust_anchors` (DER encoded X.509 certificates).
+    ///
+    /// Returns [`EfiError::SecurityViolation`] if the signature cannot be verified.
+    fn verify_pkcs7<'a>(&self, signature: &[u8], payload: &[u8], trust_anchors: &[&'a [u8]]) -> Result<()>;
+}
+
+/// Authenticates a write to an authenticated variable.
+///
+/// - `stored` is the authentication state of the existing variable, if any.
+/// - `trust_anchors` are the certificates that may authorize the write. For `EFI_VARIABLE_AUTHENTICATION_3`
+///   variables with an established signing certificate, that certificate is used instead.
+///
+/// On success, returns the variable data without the descriptor and the authentication state to persist.
+pub fn authenticate_write<'a>(
+    verifier: &dyn AuthVariableVerifier,
+    name: &[u16],
+    namespace: &efi::Guid,
+    attributes: u32,
+    data: &'a [u8],
+    stored: Option<&AuthenticationState>,
+    trust_anchors: &[&[u8]],
+) -> Result<(&'a [u8], AuthenticationState)> {
+    let auth = AuthenticatedData::parse(attributes, data)?;
+    let append = attributes & efi::VARIABLE_APPEND_WRITE != 0;
+    let mut state = stored.cloned().unwrap_or_default();
+
+    match auth.kind {
+        AuthenticationKind::TimeBased(timestamp) => {
+            match state.timestamp {
+                // Appends do not need a newer timestamp, but the stored timestamp never moves backwards.
+                Some(previous) if append => state.timestamp = Some(previous.max(timestamp)),
+                Some(previous) if timestamp <= previous => {
+                    log::error!("Authenticated variable write rejected: timestamp is not newer than the stored one.");
+                    return Err(EfiError::SecurityViolation);
+                }
+                _ => state.timestamp = Some(timestamp),
+            }
+        }
+        AuthenticationKind::NonceBased(nonce) => {
+            if state.nonce.as_deref() == Some(nonce) {
+                log::error!("Authenticated variable write rejected: nonce was reused.");
+                return Err(EfiError::SecurityViolation);
+            }
+            state.nonce = Some(nonce.to_vec());
+        }
+    }
+
+    let payload = auth.signed_payload(name, namespace, attributes);
+    match state.signing_cert.as_deref() {
+        Some(cert) if attributes & efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS != 0 => {
+            verifier.verify_pkcs7(auth.signing_cert.cert_data, &payload, &[cert])?
+        }
+        _ => verifier.verify_pkcs7(auth.signing_cert.cert_data, &payload, trust_anchors)?,
+    }
+
+    if let Some(new_cert) = auth.new_cert {
+        state.signing_cert = Some(new_cert.cert_data.to_vec());
+    }
+
+    Ok((auth.data, state))
+}
+
+/// The key database that authorizes writes to a Secure Boot key variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureBootKey {
+    /// The Platform Key (`PK`).
+    Pk,
+    /// The Key Exchange Key database (`KEK`).
+    Kek,
+}
+
+/// Returns the key databases whose certificates may authorize a write to the given variable, or `None` if the
+/// variable is not a Secure Boot key variable.
+///
+/// - `PK` and `KEK` writes must be signed by `PK`.
+/// - `db`, `dbx`, `dbt` and `dbr` writes must be signed by `PK` or `KEK`.
+pub fn secure_boot_authorities(name: &[u16], namespace: &efi::Guid) -> Option<&'static [SecureBootKey]> {
+    let name = name.strip_suffix(&[0]).unwrap_or(name);
+    if *namespace == GLOBAL_VARIABLE_GUID && (utf16_eq(name, "PK") || utf16_eq(name, "KEK")) {
+        return Some(&[SecureBootKey::Pk]);
+    }
+    if *namespace == IMAGE_SECURITY_DATABASE_GUID && ["db", "dbx", "dbt", "dbr"].iter().any(|n| utf16_eq(name, n)) {
+        return Some(&[SecureBootKey::Pk, SecureBootKey::Kek]);
+    }
+    None
+}
+
+/// Returns the DER encoded X.509 certificates contained in a sequence of `EFI_SIGNATURE_LIST` structures, such as the
+/// data of the `PK` or `KEK` variables.
+///
+/// Signature lists of other types (e.g. hashes) are skipped.
+pub fn x509_certificates(mut signature_lists: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut certs = Vec::new();
+    while !signature_lists.is_empty() {
+        if signature_lists.len() < SIGNATURE_LIST_HEADER_SIZE {
+            return Err(EfiError::InvalidParameter);
+        }
+        let signature_type = read_guid(signature_lists, 0)?;
+        let list_size = read_u32(signature_lists, 16)? as usize;
+        let header_size = read_u32(signature_lists, 20)? as usize;
+        let signature_size = read_u32(signature_lists, 24)? as usize;
+
+        let list = signature_lists.get(..list_size).ok_or(EfiError::InvalidParameter)?;
+        let entries = list.get(SIGNATURE_LIST_HEADER_SIZE + header_size..).ok_or(EfiError::InvalidParameter)?;
+        if signature_size <= size_of::<efi::Guid>() || entries.len() % signature_size != 0 {
+            return Err(EfiError::InvalidParameter);
+        }
+
+        if signature_type == CERT_X509_GUID {
+            // Each EFI_SIGNATURE_DATA is the owner GUID followed by the signature data.
+            certs.extend(entries.chunks_exact(signature_size).map(|entry| &entry[size_of::<efi::Guid>()..]));
+        }
+        signature_lists = &signature_lists[list_size..];
+    }
+    Ok(certs)
+}
+
+fn utf16_eq(name: &[u16], s: &str) -> bool {
+    name.iter().copied().eq(s.encode_utf16())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).ok_or(EfiError::SecurityViolation)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_guid(data: &[u8], offset: usize) -> Result<efi::Guid> {
+    let bytes = data.get(offset..offset + size_of::<efi::Guid>()).ok_or(EfiError::SecurityViolation)?;
+    Ok(efi::Guid::from_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a `WIN_CERTIFICATE_UEFI_GUID`, returning the certificate and the remaining data.
+fn read_win_certificate(data: &[u8]) -> Result<(Pkcs7Certificate<'_>, &[u8])> {
+    if data.len() < WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE {
+        return Err(EfiError::SecurityViolation);
+    }
+    let length = read_u32(data, 0)? as usize;
+    let revision = u16::from_le_bytes([data[4], data[5]]);
+    let cert_type = u16::from_le_bytes([data[6], data[7]]);
+    if revision != WIN_CERT_REVISION || cert_type != WIN_CERT_TYPE_EFI_GUID {
+        return Err(EfiError::InvalidParameter);
+    }
+    if read_guid(data, 8)? != CERT_TYPE_PKCS7_GUID {
+        return Err(EfiError::InvalidParameter);
+    }
+    if length < WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE || length > data.len() {
+        return Err(EfiError::SecurityViolation);
+    }
+    Ok((Pkcs7Certificate { cert_data: &data[WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE..length] }, &data[length..]))
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+
+    const NAME: &[u16] = &[b'd' as u16, b'b' as u16, 0];
+
+    fn time(second: u8) -> Timestamp {
+        Timestamp { year: 2025, month: 1, day: 1, hour: 0, minute: 0, second }
+    }
+
+    fn win_certificate(cert_data: &[u8]) -> Vec<u8> {
+        let mut cert = Vec::new();
+        cert.extend_from_slice(&((WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE + cert_data.len()) as u32).to_le_bytes());
+        cert.extend_from_slice(&WIN_CERT_REVISION.to_le_bytes());
+        cert.extend_from_slice(&WIN_CERT_TYPE_EFI_GUID.to_le_bytes());
+        cert.extend_from_slice(CERT_TYPE_PKCS7_GUID.as_bytes());
+        cert.extend_from_slice(cert_data);
+        cert
+    }
+
+    fn authentication_2(timestamp: Timestamp, data: &[u8]) -> Vec<u8> {
+        let mut buffer = timestamp.to_bytes().to_vec();
+        buffer.extend(win_certificate(&[0xAA; 4]));
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    fn authentication_3_nonce(nonce: &[u8], new_cert: Option<&[u8]>, data: &[u8]) -> Vec<u8> {
+        let mut metadata = Vec::new();
+        metadata.extend_from_slice(&(nonce.len() as u32).to_le_bytes());
+        metadata.extend_from_slice(nonce);
+        if let Some(new_cert) = new_cert {
+            metadata.extend(win_certificate(new_cert));
+        }
+        metadata.extend(win_certificate(&[0xBB; 4]));
+
+        let flags = if new_cert.is_some() { ENHANCED_AUTH_FLAG_UPDATE_CERT } else { 0 };
+        let mut buffer = vec![AUTHENTICATION_3_VERSION, AUTHENTICATION_3_NONCE_TYPE];
+        buffer.extend_from_slice(&((AUTHENTICATION_3_HEADER_SIZE + metadata.len()) as u32).to_le_bytes());
+        buffer.extend_from_slice(&flags.to_le_bytes());
+        buffer.extend(metadata);
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    fn accepting_verifier() -> MockAuthVariableVerifier {
+        let mut verifier = MockAuthVariableVerifier::new();
+        verifier.expect_verify_pkcs7().returning(|_, _, _| Ok(()));
+        verifier
+    }
+
+    #[test]
+    fn test_parse_authentication_2() {
+        let buffer = authentication_2(time(1), &[1, 2, 3]);
+        let auth = AuthenticatedData::parse(efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS, &buffer).unwrap();
+        assert_eq!(auth.kind, AuthenticationKind::TimeBased(time(1)));
+        assert_eq!(auth.signing_cert.cert_data, &[0xAA; 4]);
+        assert_eq!(auth.new_cert, None);
+        assert_eq!(auth.data, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_parse_authentication_3_nonce_with_new_cert() {
+        let buffer = authentication_3_nonce(&[9, 9], Some(&[0xCC; 3]), &[4, 5]);
+        let auth = AuthenticatedData::parse(efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS, &buffer).unwrap();
+        assert_eq!(auth.kind, AuthenticationKind::NonceBased(&[9, 9]));
+        assert_eq!(auth.new_cert.unwrap().cert_data, &[0xCC; 3]);
+        assert_eq!(auth.signing_cert.cert_data, &[0xBB; 4]);
+        assert_eq!(auth.data, &[4, 5]);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_descriptors() {
+        assert_eq!(AuthenticatedData::parse(0, &[]), Err(EfiError::InvalidParameter));
+        assert_eq!(AuthenticatedData::parse_authentication_2(&[0; 8]), Err(EfiError::SecurityViolation));
+
+        let mut buffer = authentication_3_nonce(&[1], None, &[]);
+        buffer[0] = 2;
+        assert_eq!(AuthenticatedData::parse_authentication_3(&buffer), Err(EfiError::InvalidParameter));
+
+        let mut buffer = authentication_3_nonce(&[1], None, &[]);
+        buffer[2..6].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(AuthenticatedData::parse_authentication_3(&buffer), Err(EfiError::SecurityViolation));
+
+        assert_eq!(
+            AuthenticatedData::parse_authentication_3(&authentication_3_nonce(&[], None, &[])),
+            Err(EfiError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn test_time_based_write_must_be_monotonic() {
+        let verifier = accepting_verifier();
+        let attributes = efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS;
+
+        let buffer = authentication_2(time(5), &[1]);
+        let (data, state) =
+            authenticate_write(&verifier, NAME, &IMAGE_SECURITY_DATABASE_GUID, attributes, &buffer, None, &[]).unwrap();
+        assert_eq!(data, &[1]);
+        assert_eq!(state.timestamp, Some(time(5)));
+
+        let stale = authentication_2(time(5), &[2]);
+        assert_eq!(
+            authenticate_write(&verifier, NAME, &IMAGE_SECURITY_DATABASE_GUID, attributes, &stale, Some(&state), &[]),
+            Err(EfiError::SecurityViolation)
+        );
+
+        let append = authentication_2(time(3), &[3]);
+        let (_, appended) = authenticate_write(
+            &verifier,
+            NAME,
+            &IMAGE_SECURITY_DATABASE_GUID,
+            attributes | efi::VARIABLE_APPEND_WRITE,
+            &append,
+            Some(&state),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(appended.timestamp, Some(time(5)));
+    }
+
+    #[test]
+    fn test_time_based_write_rejects_reserved_fields() {
+        let verifier = accepting_verifier();
+        let mut buffer = authentication_2(time(1), &[]);
+        // Nanosecond
+        buffer[8] = 1;
+        assert_eq!(
+            authenticate_write(
+                &verifier,
+                NAME,
+                &IMAGE_SECURITY_DATABASE_GUID,
+                efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS,
+                &buffer,
+                None,
+                &[]
+            ),
+            Err(EfiError::SecurityViolation)
+        );
+    }
+
+    #[test]
+    fn test_nonce_based_write_tracks_signing_cert() {
+        let attributes = efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_ENHANCED_AUTHENTICATED_ACCESS;
+
+        let mut verifier = MockAuthVariableVerifier::new();
+        verifier
+            .expect_verify_pkcs7()
+            .withf(|_, _, anchors| anchors == [&[0x11u8][..]])
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let buffer = authentication_3_nonce(&[1], Some(&[0xCC; 3]), &[7]);
+        let (_, state) =
+            authenticate_write(&verifier, NAME, &GLOBAL_VARIABLE_GUID, attributes, &buffer, None, &[&[0x11]]).unwrap();
+        assert_eq!(state.signing_cert.as_deref(), Some(&[0xCC; 3][..]));
+
+        // Subsequent writes are verified against the established signing certificate.
+        let mut verifier = MockAuthVariableVerifier::new();
+        verifier
+            .expect_verify_pkcs7()
+            .withf(|_, _, anchors| anchors == [&[0xCCu8; 3][..]])
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let buffer = authentication_3_nonce(&[2], None, &[8]);
+        authenticate_write(&verifier, NAME, &GLOBAL_VARIABLE_GUID, attributes, &buffer, Some(&state), &[&[0x11]])
+            .unwrap();
+
+        // Reusing the nonce is rejected.
+        let buffer = authentication_3_nonce(&[1], None, &[8]);
+        let verifier = accepting_verifier();
+        let mut state = state;
+        state.nonce = Some(vec![1]);
+        assert_eq!(
+            authenticate_write(&verifier, NAME, &GLOBAL_VARIABLE_GUID, attributes, &buffer, Some(&state), &[]),
+            Err(EfiError::SecurityViolation)
+        );
+    }
+
+    #[test]
+    fn test_signature_failure_is_propagated() {
+        let mut verifier = MockAuthVariableVerifier::new();
+        verifier.expect_verify_pkcs7().returning(|_, _, _| Err(EfiError::SecurityViolation));
+        let buffer = authentication_2(time(1), &[]);
+        assert_eq!(
+            authenticate_write(
+                &verifier,
+                NAME,
+                &IMAGE_SECURITY_DATABASE_GUID,
+                efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS,
+                &buffer,
+                None,
+                &[]
+            ),
+            Err(EfiError::SecurityViolation)
+        );
+    }
+
+    #[test]
+    fn test_signed_payload_layout() {
+        let buffer = authentication_2(time(1), &[0xEE]);
+        let auth = AuthenticatedData::parse_authentication_2(&buffer).unwrap();
+        let payload = auth.signed_payload(NAME, &IMAGE_SECURITY_DATABASE_GUID, 0x27);
+        assert_eq!(&payload[..4], &[b'd', 0, b'b', 0]);
+        assert_eq!(&payload[4..20], IMAGE_SECURITY_DATABASE_GUID.as_bytes());
+        assert_eq!(&payload[20..24], &0x27u32.to_le_bytes());
+        assert_eq!(&payload[24..40], &time(1).to_bytes());
+        assert_eq!(&payload[40..], &[0xEE]);
+    }
+
+    #[test]
+    fn test_secure_boot_authorities() {
+        let pk: Vec<u16> = "PK\0".encode_utf16().collect();
+        let dbx: Vec<u16> = "dbx".encode_utf16().collect();
+        assert_eq!(secure_boot_authorities(&pk, &GLOBAL_VARIABLE_GUID), Some(&[SecureBootKey::Pk][..]));
+        assert_eq!(
+            secure_boot_authorities(&dbx, &IMAGE_SECURITY_DATABASE_GUID),
+            Some(&[SecureBootKey::Pk, SecureBootKey::Kek][..])
+        );
+        assert_eq!(secure_boot_authorities(&dbx, &GLOBAL_VARIABLE_GUID), None);
+    }
+
+    #[test]
+    fn test_x509_certificates() {
+        let mut list = Vec::new();
+        list.extend_from_slice(CERT_X509_GUID.as_bytes());
+        list.extend_from_slice(&((SIGNATURE_LIST_HEADER_SIZE + 2 * 20) as u32).to_le_bytes());
+        list.extend_from_slice(&0u32.to_le_bytes());
+        list.extend_from_slice(&20u32.to_le_bytes());
+        for fill in [0x1, 0x2] {
+            list.extend_from_slice(&[0u8; 16]);
+            list.extend_from_slice(&[fill; 4]);
+        }
+        let certs = x509_certificates(&list).unwrap();
+        assert_eq!(certs, vec![&[0x1u8; 4][..], &[0x2u8; 4][..]]);
+
+        assert_eq!(x509_certificates(&list[..20]), Err(EfiError::InvalidParameter));
+    }
+}
@@ -0,0 +1,133 @@
+//! Buffers for Secrets
+//!
+//! [SecretBuffer] holds sensitive data, such as passwords, keys and TPM authorization values, and zeroes it when it is
+//! dropped. The contents are never formatted, so they stay out of logs, crash dumps and telemetry that capture values
+//! through [Debug](core::fmt::Debug).
+//!
+//! [zeroize] wipes buffers that are not held in a [SecretBuffer], such as a caller's buffer that a secret was read
+//! from.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use patina::secret::SecretBuffer;
+//!
+//! let mut input = *b"password";
+//! let password = SecretBuffer::from_mut_slice(&mut input);
+//!
+//! assert_eq!(input, [0; 8]);
+//! assert_eq!(password.expose(), b"password");
+//! assert_eq!(format!("{password:?}"), "SecretBuffer { len: 8, .. }");
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use alloc::{boxed::Box, vec};
+use core::{
+    fmt,
+    sync::atomic::{Ordering, compiler_fence},
+};
+
+/// Zeroes `buffer` with writes that the compiler cannot remove, even if the buffer is never read again.
+pub fn zeroize(buffer: &mut [u8]) {
+    for byte in buffer.iter_mut() {
+        // Safety: `byte` is a valid, aligned reference to a u8.
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    // keep later accesses, such as freeing the buffer, from being ordered before the writes.
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// A heap buffer for secrets that is zeroed when it is dropped.
+///
+/// The buffer never moves or grows once created, so no copies of the secret are left behind in freed memory. It does
+/// not implement [Clone], and its [Debug](fmt::Debug) output only shows the length.
+pub struct SecretBuffer {
+    data: Box<[u8]>,
+}
+
+impl SecretBuffer {
+    /// Creates a zeroed buffer of `len` bytes.
+    pub fn new(len: usize) -> Self {
+        Self { data: vec![0; len].into_boxed_slice() }
+    }
+
+    /// Moves the secret in `source` into a new buffer and zeroes `source`.
+    pub fn from_mut_slice(source: &mut [u8]) -> Self {
+        let mut buffer = Self::new(source.len());
+        buffer.data.copy_from_slice(source);
+        zeroize(source);
+        buffer
+    }
+
+    /// Returns the length of the secret in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if the secret is empty.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the secret. Callers should not copy it out of the buffer.
+    pub fn expose(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the secret for writing, such as to read a secret directly into the buffer.
+    pub fn expose_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        zeroize(&mut self.data);
+    }
+}
+
+impl fmt::Debug for SecretBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretBuffer").field("len", &self.len()).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+#[coverage(off)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_mut_slice_wipes_source() {
+        let mut source = [0xA5u8; 32];
+        let mut secret = SecretBuffer::from_mut_slice(&mut source);
+
+        assert_eq!(source, [0; 32]);
+        assert_eq!(secret.len(), 32);
+        assert!(secret.expose().iter().all(|&b| b == 0xA5));
+
+        secret.expose_mut()[0] = 0x5A;
+        assert_eq!(secret.expose()[0], 0x5A);
+        assert!(SecretBuffer::new(0).is_empty());
+    }
+
+    #[test]
+    fn test_zeroize() {
+        let mut buffer = [0xA5u8; 16];
+        zeroize(&mut buffer[..8]);
+        assert_eq!(buffer[..8], [0; 8]);
+        assert_eq!(buffer[8..], [0xA5; 8]);
+    }
+
+    #[test]
+    fn test_debug_hides_contents() {
+        let mut input = *b"hunter2";
+        let secret = SecretBuffer::from_mut_slice(&mut input);
+        assert_eq!(format!("{secret:?}"), "SecretBuffer { len: 7, .. }");
+    }
+}
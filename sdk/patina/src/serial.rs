@@ -17,6 +17,8 @@ pub trait SerialIO: Sync {
     fn read(&self) -> u8;
     /// Try to read a byte from the serial port, returning `None` if no byte is available.
     fn try_read(&self) -> Option<u8>;
+    /// Send any buffered writes. Serial ports that write immediately do not need to implement this.
+    fn flush(&self) {}
 }
 
 pub mod uart;
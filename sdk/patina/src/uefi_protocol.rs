@@ -11,6 +11,8 @@
 pub mod device_path;
 
 pub mod decompress;
+pub mod fv_loader;
+pub mod memory_attributes_ext;
 pub mod performance_measurement;
 pub mod status_code;
 
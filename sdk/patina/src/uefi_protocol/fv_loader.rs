@@ -0,0 +1,51 @@
+//! Firmware Volume Loader Protocol
+//!
+//! A Patina defined protocol, produced by the DXE Core, that installs a firmware volume held in a memory buffer and
+//! dispatches the drivers it contains. It is intended for recovery flows and driver development, where the firmware
+//! volume is read from a file system or downloaded rather than being part of the flash image.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use core::ffi::c_void;
+
+use r_efi::efi;
+
+use super::ProtocolInterface;
+
+/// The ffi interface for the install_fv_from_buffer function of the [FvLoaderProtocol].
+///
+/// Copies the firmware volume at `buffer` of `size` bytes into memory owned by the core, authenticates it, installs it
+/// and dispatches any drivers it contains. The caller may free `buffer` once the function returns. On success, the
+/// handle of the installed firmware volume is written to `firmware_volume_handle`.
+///
+/// Returns `INVALID_PARAMETER` if a pointer is null, `VOLUME_CORRUPTED` if the buffer does not hold a valid firmware
+/// volume, and the status of the Security2 Architectural Protocol if the firmware volume does not authenticate.
+pub type InstallFvFromBufferFn = extern "efiapi" fn(
+    this: *mut FvLoaderProtocol,
+    buffer: *const c_void,
+    size: usize,
+    firmware_volume_handle: *mut efi::Handle,
+) -> efi::Status;
+
+/// C struct for the Firmware Volume Loader protocol.
+#[repr(C)]
+pub struct FvLoaderProtocol {
+    /// Installs a firmware volume from a memory buffer and dispatches its drivers.
+    pub install_fv_from_buffer: InstallFvFromBufferFn,
+}
+
+impl FvLoaderProtocol {
+    /// Creates a new instance of the protocol with the given function.
+    pub const fn new(install_fv_from_buffer: InstallFvFromBufferFn) -> Self {
+        Self { install_fv_from_buffer }
+    }
+}
+
+unsafe impl ProtocolInterface for FvLoaderProtocol {
+    const PROTOCOL_GUID: efi::Guid =
+        efi::Guid::from_fields(0x8e6487fe, 0xf7c0, 0x4931, 0xb8, 0x7e, &[0x04, 0xa7, 0x67, 0x1b, 0xd5, 0x69]);
+}
@@ -0,0 +1,99 @@
+//! Extended Memory Attributes Protocol
+//!
+//! A Patina defined protocol, produced by the DXE Core alongside the UEFI Memory Attributes Protocol, that sets or
+//! clears access attributes on several ranges in one call and reports the attribute runs over a range. OS loaders
+//! that protect many image sections, and memory protection test suites that walk large ranges, otherwise need a
+//! protocol call per range or per page.
+//!
+//! The attributes are the access attributes of the UEFI Memory Attributes Protocol: `EFI_MEMORY_RP`, `EFI_MEMORY_RO`
+//! and `EFI_MEMORY_XP`.
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+use r_efi::efi;
+
+use super::ProtocolInterface;
+
+/// A page aligned range of memory.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryRange {
+    /// The base address of the range.
+    pub base_address: efi::PhysicalAddress,
+    /// The length of the range, in bytes.
+    pub length: u64,
+}
+
+/// A range of memory with the same access attributes throughout.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AttributeRun {
+    /// The base address of the run.
+    pub base_address: efi::PhysicalAddress,
+    /// The length of the run, in bytes.
+    pub length: u64,
+    /// The access attributes of the run.
+    pub attributes: u64,
+}
+
+/// The ffi interface for the set_memory_attributes and clear_memory_attributes functions of the
+/// [MemoryAttributesExtProtocol].
+///
+/// Sets or clears `attributes` on each of the `range_count` ranges at `ranges`. All ranges are validated before any
+/// attributes are changed.
+///
+/// Returns `INVALID_PARAMETER` if `ranges` is null, a range is empty or not page aligned, or `attributes` is not a
+/// non-zero combination of access attributes, `NO_MAPPING` if a range is not described by the memory space map, and
+/// `UNSUPPORTED` if the attributes could not be changed.
+pub type UpdateMemoryAttributesFn = extern "efiapi" fn(
+    this: *mut MemoryAttributesExtProtocol,
+    ranges: *const MemoryRange,
+    range_count: usize,
+    attributes: u64,
+) -> efi::Status;
+
+/// The ffi interface for the get_attribute_run function of the [MemoryAttributesExtProtocol].
+///
+/// Writes the run of memory with the same access attributes that starts at the page aligned `base_address`, ending no
+/// later than `end_address`, to `run`. Iterate over the attribute runs of a range by calling it again with the end of
+/// the previous run, until the run ends at `end_address`.
+///
+/// Returns `INVALID_PARAMETER` if `run` is null, an address is not page aligned or the range is empty, and
+/// `NO_MAPPING` if `base_address` is not described by the memory space map.
+pub type GetAttributeRunFn = extern "efiapi" fn(
+    this: *mut MemoryAttributesExtProtocol,
+    base_address: efi::PhysicalAddress,
+    end_address: efi::PhysicalAddress,
+    run: *mut AttributeRun,
+) -> efi::Status;
+
+/// C struct for the Extended Memory Attributes protocol.
+#[repr(C)]
+pub struct MemoryAttributesExtProtocol {
+    /// Sets access attributes on several ranges.
+    pub set_memory_attributes: UpdateMemoryAttributesFn,
+    /// Clears access attributes on several ranges.
+    pub clear_memory_attributes: UpdateMemoryAttributesFn,
+    /// Reports the attribute run at an address.
+    pub get_attribute_run: GetAttributeRunFn,
+}
+
+impl MemoryAttributesExtProtocol {
+    /// Creates a new instance of the protocol with the given functions.
+    pub const fn new(
+        set_memory_attributes: UpdateMemoryAttributesFn,
+        clear_memory_attributes: UpdateMemoryAttributesFn,
+        get_attribute_run: GetAttributeRunFn,
+    ) -> Self {
+        Self { set_memory_attributes, clear_memory_attributes, get_attribute_run }
+    }
+}
+
+unsafe impl ProtocolInterface for MemoryAttributesExtProtocol {
+    const PROTOCOL_GUID: efi::Guid =
+        efi::Guid::from_fields(0x5c3b0a5e, 0x4f0e, 0x4b47, 0x9d, 0x2c, &[0x61, 0x0a, 0x8e, 0x27, 0xf3, 0x94]);
+}
@@ -340,6 +340,22 @@ pub const EFI_RESOURCE_ATTRIBUTE_PERSISTENT: u32 = 0x00800000;
 //
 pub const EFI_RESOURCE_ATTRIBUTE_MORE_RELIABLE: u32 = 0x02000000;
 
+//
+// Physical memory encryption attribute. The memory is encrypted by the platform.
+//
+pub const EFI_RESOURCE_ATTRIBUTE_ENCRYPTED: u32 = 0x04000000;
+
+//
+// Physical memory special-purpose attribute. The memory is earmarked for a specific purpose, such as a particular
+// device or application, and is not used for general allocations.
+//
+pub const EFI_RESOURCE_ATTRIBUTE_SPECIAL_PURPOSE: u32 = 0x08000000;
+
+//
+// Physical memory hot-pluggable attribute. The memory may be removed by the platform while the system is running.
+//
+pub const EFI_RESOURCE_ATTRIBUTE_HOT_PLUGGABLE: u32 = 0x10000000;
+
 //
 // The rest of the attributes are used to describe capabilities
 //
@@ -17,6 +17,7 @@ pub mod cpu_arch;
 pub mod firmware_volume;
 pub mod firmware_volume_block;
 pub mod metronome;
+pub mod monotonic_counter;
 pub mod runtime;
 pub mod security;
 pub mod security2;
@@ -0,0 +1,23 @@
+//! Monotonic Counter Architectural Protocol
+//!
+//! Produced by the driver that persists the high 32 bits of the platform monotonic count. The protocol has no
+//! interface; its installation indicates that the GetNextMonotonicCount() Boot Service and the
+//! GetNextHighMonotonicCount() Runtime Service are available.
+//!
+//! See <https://uefi.org/specs/PI/1.8A/V2_DXE_Architectural_Protocols.html#monotonic-counter-architectural-protocol>
+//!
+//! ## License
+//!
+//! Copyright (c) Microsoft Corporation.
+//!
+//! SPDX-License-Identifier: Apache-2.0
+//!
+
+use r_efi::efi;
+
+/// Monotonic Counter Architectural Protocol GUID
+///
+/// # Documentation
+/// UEFI Platform Initialization Specification, Release 1.8, Section II-12.5.1
+pub const PROTOCOL_GUID: efi::Guid =
+    efi::Guid::from_fields(0x1da97072, 0xbddc, 0x4b30, 0x99, 0xf1, &[0x72, 0xa0, 0xb5, 0x6f, 0xff, 0x2a]);